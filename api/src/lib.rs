@@ -0,0 +1,956 @@
+//! The JSON types used by the HTTP API of the Nucleoid persistence backend, shared by the backend and its clients.
+
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlayerProfileResponse {
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = "uuid"))]
+    pub uuid: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub private: Option<bool>,
+    /// When the player was first seen, by an upload or a profile update, in RFC 3339 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<String>,
+    /// When the player was last seen, by an upload or a profile update, in RFC 3339 format.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<String>,
+    /// The player's rating in each namespace that they have played rated matches in.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub ratings: HashMap<String, PlayerRating>,
+    /// Whether the player was anonymized, so that their stats are only kept under their UUID.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub anonymized: bool,
+}
+
+/// A page of players, listed by `GET /players`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlayerListResponse {
+    /// The players' profiles, without their ratings.
+    pub players: Vec<PlayerProfileResponse>,
+    /// Passed as `after` to get the next page. Not given on the last page.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UpdatePlayerProfileRequest {
+    pub username: String,
+    pub private: Option<bool>,
+}
+
+/// A username that a player has used, returned by `GET /player/{uuid}/names`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UsernameHistoryEntry {
+    pub username: String,
+    /// When the player took the name in RFC 3339 format, or `null` if it isn't known.
+    pub used_from: Option<String>,
+    /// When the player changed to another name in RFC 3339 format, or `null` for their current name.
+    pub used_until: Option<String>,
+}
+
+/// A play session that a game server reports to `POST /player/{uuid}/playtime`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlaytimeReport {
+    /// The namespace of the game that was played.
+    pub namespace: String,
+    /// How long the session lasted, in seconds.
+    pub seconds: u64,
+}
+
+/// How long a player has played, returned by `GET /player/{uuid}/playtime`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlaytimeResponse {
+    /// Playtime across every namespace, in seconds.
+    pub total_seconds: u64,
+    /// Playtime in each namespace.
+    pub namespaces: HashMap<String, NamespacePlaytime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NamespacePlaytime {
+    pub seconds: u64,
+    /// Number of sessions reported.
+    pub sessions: u64,
+    /// When the last session was reported, in RFC 3339 format.
+    pub last_played: String,
+}
+
+/// Stats keyed by namespace and then by stat name.
+pub type PlayerStatsResponse = HashMap<String, HashMap<String, f64>>;
+
+/// A namespace's global stats, keyed by stat name.
+pub type GlobalStatsResponse = HashMap<String, f64>;
+
+/// The change in a namespace's global stats over a window, keyed by stat name.
+pub type GlobalStatsDeltaResponse = HashMap<String, f64>;
+
+/// How a stat is presented, as registered by the developers of its game.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    /// The unit of the stat's values, e.g. `blocks` or `seconds`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// The registered info of a namespace's stats, keyed by stat name.
+pub type StatInfoResponse = HashMap<String, StatInfo>;
+
+/// How an achievement is presented, as registered by the developers of its game.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AchievementInfo {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// The achievements of a namespace and how many players have unlocked them, returned by
+/// `GET /achievements/{namespace}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AchievementsResponse {
+    /// Number of players with stats or achievements in the namespace.
+    pub players: u64,
+    /// Each registered achievement, keyed by its id.
+    pub achievements: HashMap<String, AchievementSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AchievementSummary {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+    /// Number of players who have unlocked the achievement.
+    pub unlocked_by: u64,
+    /// Percentage of the namespace's players who have unlocked the achievement, from 0 to 100.
+    pub unlock_percent: f64,
+}
+
+/// Achievements that a game server grants to a player with `POST /player/{uuid}/achievements`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GrantAchievementsRequest {
+    pub namespace: String,
+    /// Ids of the achievements, which must be registered for the namespace.
+    pub achievements: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GrantAchievementsResponse {
+    /// The achievements that the player didn't have before, so that the game can announce them.
+    pub granted: Vec<String>,
+}
+
+/// An achievement that a player has unlocked, returned by `GET /player/{uuid}/achievements`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UnlockedAchievement {
+    pub namespace: String,
+    pub achievement: String,
+    /// When the achievement was unlocked, in RFC 3339 format.
+    pub unlocked_at: String,
+}
+
+/// A player's Elo rating in a namespace.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct PlayerRating {
+    pub rating: f64,
+    /// Number of rated matches played.
+    pub matches: u64,
+}
+
+/// The result of a match, submitted to `POST /ratings/{namespace}/match`. Either `winner` and `loser` are given for a
+/// match between two players, or `teams` for any other match.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MatchResultRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, format = "uuid"))]
+    pub winner: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, format = "uuid"))]
+    pub loser: Option<Uuid>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub teams: Vec<MatchTeam>,
+}
+
+/// A team, or a single player, and where it placed in a match.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MatchTeam {
+    #[cfg_attr(feature = "openapi", schema(value_type = Vec<String>))]
+    pub players: Vec<Uuid>,
+    /// Where the team placed, starting at 1 for the winner. Teams with the same placement drew.
+    pub placement: u32,
+}
+
+/// How a match changed the ratings of its players, keyed by UUID.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct MatchResultResponse {
+    #[cfg_attr(feature = "openapi", schema(value_type = HashMap<String, RatingChange>))]
+    pub ratings: HashMap<Uuid, RatingChange>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct RatingChange {
+    /// The player's rating after the match.
+    pub rating: f64,
+    /// How much the match changed the rating by.
+    pub change: f64,
+}
+
+/// Which end of a leaderboard is ranked first.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum LeaderboardOrder {
+    /// Highest values first.
+    #[default]
+    #[serde(rename = "desc")]
+    Descending,
+    /// Lowest values first, for stats where less is better such as completion times.
+    #[serde(rename = "asc")]
+    Ascending,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LeaderboardEntry {
+    /// The position of the player, starting at 1.
+    pub rank: u32,
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = "uuid"))]
+    pub uuid: Uuid,
+    pub username: Option<String>,
+    pub value: f64,
+}
+
+pub type LeaderboardResponse = Vec<LeaderboardEntry>;
+
+/// A leaderboard as it stood when a snapshot of it was saved.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LeaderboardSnapshotResponse {
+    /// When the snapshot was saved, in RFC 3339 format.
+    pub taken_at: String,
+    pub order: LeaderboardOrder,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+/// Where a player ranks among every player with a stat.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatRankResponse {
+    pub value: f64,
+    /// The position of the player, starting at 1. Players with the same value share a rank.
+    pub rank: u64,
+    /// The number of players ranked.
+    pub players: u64,
+    /// The percentage of ranked players that are ranked below the player, so that `100 - percentile` is the top
+    /// percentage that the player is in.
+    pub percentile: f64,
+}
+
+/// A stat summarized across every player that has it, counting each player's value the same way as leaderboards.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatSummaryResponse {
+    /// The number of players with the stat.
+    pub players: u64,
+    pub sum: f64,
+    pub mean: f64,
+    pub median: f64,
+    /// The population standard deviation.
+    pub stddev: f64,
+}
+
+pub type PlayerStatsBundle = HashMap<Uuid, HashMap<String, UploadStat>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GameStatsBundle {
+    pub server_name: String,
+    pub namespace: String,
+    pub stats: StatsBundle,
+    /// Identifies the bundle, so that retrying an upload that was already applied doesn't count its stats twice.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    #[cfg_attr(feature = "openapi", schema(value_type = Option<String>, format = "uuid"))]
+    pub bundle_id: Option<Uuid>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatsBundle {
+    pub global: Option<HashMap<String, UploadStat>>,
+    /// Stats keyed by player UUID and then by stat name.
+    #[cfg_attr(feature = "openapi", schema(value_type = HashMap<String, HashMap<String, UploadStat>>))]
+    pub players: PlayerStatsBundle,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case", tag = "type", content = "value")]
+pub enum UploadStat {
+    IntTotal(i32),
+    IntRollingAverage(i32),
+    /// Adds `count` values summing to `total` to an int rolling average at once.
+    IntRollingAverageBatch { total: i32, count: u32 },
+    LongTotal(i64),
+    LongRollingAverage(i64),
+    /// Adds `count` values summing to `total` to a long rolling average at once.
+    LongRollingAverageBatch { total: i64, count: u32 },
+    FloatTotal(f64),
+    FloatRollingAverage(f64),
+    /// Adds `count` values summing to `total` to a float rolling average at once.
+    FloatRollingAverageBatch { total: f64, count: u32 },
+    /// Keeps the lowest value uploaded, e.g. a fastest time in ticks.
+    IntMin(i32),
+    /// Keeps the highest value uploaded, e.g. a highest score in a single game.
+    IntMax(i32),
+    FloatMin(f64),
+    FloatMax(f64),
+    /// Keeps the most recently uploaded value.
+    Latest(f64),
+    /// Counts each value in the bucket of the stat's configured `buckets` that it falls in.
+    Histogram(Vec<f64>),
+}
+
+impl UploadStat {
+    pub fn aggregation(&self) -> StatAggregation {
+        match self {
+            UploadStat::IntTotal(_) | UploadStat::LongTotal(_) | UploadStat::FloatTotal(_) => StatAggregation::Total,
+            UploadStat::IntRollingAverage(_) | UploadStat::IntRollingAverageBatch { .. }
+                | UploadStat::LongRollingAverage(_) | UploadStat::LongRollingAverageBatch { .. }
+                | UploadStat::FloatRollingAverage(_) | UploadStat::FloatRollingAverageBatch { .. } => StatAggregation::Average,
+            UploadStat::IntMin(_) | UploadStat::FloatMin(_) => StatAggregation::Min,
+            UploadStat::IntMax(_) | UploadStat::FloatMax(_) => StatAggregation::Max,
+            UploadStat::Latest(_) => StatAggregation::Latest,
+            UploadStat::Histogram(_) => StatAggregation::Histogram,
+        }
+    }
+
+    pub fn is_average(&self) -> bool {
+        self.aggregation() == StatAggregation::Average
+    }
+
+    /// The value carried by this upload, widened to a float for validation. Batches of averaged values give their
+    /// average, so that they are validated like the individual values they stand for, and histograms give their
+    /// largest value.
+    pub fn value(&self) -> f64 {
+        match self {
+            UploadStat::IntTotal(value) | UploadStat::IntRollingAverage(value)
+                | UploadStat::IntMin(value) | UploadStat::IntMax(value) => *value as f64,
+            UploadStat::LongTotal(value) | UploadStat::LongRollingAverage(value) => *value as f64,
+            UploadStat::FloatTotal(value) | UploadStat::FloatRollingAverage(value)
+                | UploadStat::FloatMin(value) | UploadStat::FloatMax(value) | UploadStat::Latest(value) => *value,
+            UploadStat::IntRollingAverageBatch { .. } | UploadStat::LongRollingAverageBatch { .. }
+                | UploadStat::FloatRollingAverageBatch { .. } => self.total() / self.count() as f64,
+            UploadStat::Histogram(values) => values.iter().copied().fold(0.0, f64::max),
+        }
+    }
+
+    /// Every value carried by this upload: each value counted by a histogram, or the single value of other stats.
+    pub fn values(&self) -> Vec<f64> {
+        match self {
+            UploadStat::Histogram(values) => values.clone(),
+            _ => vec![self.value()],
+        }
+    }
+
+    /// The amount added to the stored total, widened to a float.
+    pub fn total(&self) -> f64 {
+        match self {
+            UploadStat::IntRollingAverageBatch { total, .. } => *total as f64,
+            UploadStat::LongRollingAverageBatch { total, .. } => *total as f64,
+            UploadStat::FloatRollingAverageBatch { total, .. } => *total,
+            _ => self.value(),
+        }
+    }
+
+    /// How many values this upload adds to a rolling average, or 1 for other stats.
+    pub fn count(&self) -> u32 {
+        match self {
+            UploadStat::IntRollingAverageBatch { count, .. }
+                | UploadStat::LongRollingAverageBatch { count, .. }
+                | UploadStat::FloatRollingAverageBatch { count, .. } => *count,
+            _ => 1,
+        }
+    }
+}
+
+/// The stored type of a stat.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum StatType {
+    IntTotal,
+    IntRollingAverage,
+    LongTotal,
+    LongRollingAverage,
+    FloatTotal,
+    FloatRollingAverage,
+    DecimalTotal,
+    DecimalRollingAverage,
+    IntMin,
+    IntMax,
+    FloatMin,
+    FloatMax,
+    Latest,
+    Histogram,
+}
+
+/// How the uploads of a stat are combined into its stored value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatAggregation {
+    /// Uploads are added up.
+    Total,
+    /// Uploads are added up and counted, and the stat's value is their average.
+    Average,
+    /// The lowest upload is kept.
+    Min,
+    /// The highest upload is kept.
+    Max,
+    /// The most recent upload is kept.
+    Latest,
+    /// Uploads are counted in buckets.
+    Histogram,
+}
+
+impl StatType {
+    pub fn aggregation(&self) -> StatAggregation {
+        match self {
+            StatType::IntTotal | StatType::LongTotal | StatType::FloatTotal | StatType::DecimalTotal => StatAggregation::Total,
+            StatType::IntRollingAverage | StatType::LongRollingAverage
+                | StatType::FloatRollingAverage | StatType::DecimalRollingAverage => StatAggregation::Average,
+            StatType::IntMin | StatType::FloatMin => StatAggregation::Min,
+            StatType::IntMax | StatType::FloatMax => StatAggregation::Max,
+            StatType::Latest => StatAggregation::Latest,
+            StatType::Histogram => StatAggregation::Histogram,
+        }
+    }
+
+    pub fn is_average(&self) -> bool {
+        self.aggregation() == StatAggregation::Average
+    }
+
+    /// The name of the type, as it is serialized.
+    pub fn name(&self) -> &'static str {
+        match self {
+            StatType::IntTotal => "int_total",
+            StatType::IntRollingAverage => "int_rolling_average",
+            StatType::LongTotal => "long_total",
+            StatType::LongRollingAverage => "long_rolling_average",
+            StatType::FloatTotal => "float_total",
+            StatType::FloatRollingAverage => "float_rolling_average",
+            StatType::DecimalTotal => "decimal_total",
+            StatType::DecimalRollingAverage => "decimal_rolling_average",
+            StatType::IntMin => "int_min",
+            StatType::IntMax => "int_max",
+            StatType::FloatMin => "float_min",
+            StatType::FloatMax => "float_max",
+            StatType::Latest => "latest",
+            StatType::Histogram => "histogram",
+        }
+    }
+}
+
+/// A bundle that was applied, as sent to clients of the live feed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveBundleEvent {
+    pub namespace: String,
+    pub server_name: String,
+    /// The number of players that the bundle has stats for.
+    pub players: usize,
+}
+
+/// A bucket of a histogram stat, counting the values from `min` up to but not including `max`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct HistogramBucket {
+    /// The lower bound of the bucket, or `None` for the first bucket.
+    pub min: Option<f64>,
+    /// The upper bound of the bucket, or `None` for the last bucket.
+    pub max: Option<f64>,
+    pub count: i64,
+}
+
+/// The value of a player's stat after one of the bundles that changed it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct StatHistoryPoint {
+    /// When the bundle was applied, in RFC 3339 format.
+    pub recorded_at: String,
+    pub value: f64,
+}
+
+/// A season that the stats of seasonal namespaces are added to.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SeasonResponse {
+    pub season: u32,
+    /// When the season was started, in RFC 3339 format, or `None` for the first season.
+    pub started_at: Option<String>,
+}
+
+/// A completed match, as uploaded by a game server.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GameUploadRequest {
+    pub server_name: String,
+    pub namespace: String,
+    /// When the match started, in RFC 3339 format.
+    pub started_at: String,
+    /// When the match ended, in RFC 3339 format.
+    pub ended_at: String,
+    pub participants: Vec<GameParticipant>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GameParticipant {
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = "uuid"))]
+    pub uuid: Uuid,
+    /// Whether the player won, or was on the winning team.
+    #[serde(default)]
+    pub winner: bool,
+    /// The player's final score, for games that keep one.
+    #[serde(default)]
+    pub score: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GameUploadResponse {
+    pub id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct GameResponse {
+    pub id: String,
+    pub server_name: String,
+    pub namespace: String,
+    pub started_at: String,
+    pub ended_at: String,
+    pub participants: Vec<GameParticipant>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ConvertStatRequest {
+    pub stat: String,
+    pub to: StatType,
+    pub count: Option<i32>,
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatConversionReport {
+    pub dry_run: bool,
+    /// Number of documents containing the stat.
+    pub matched: u64,
+    /// Number of documents that were (or, in a dry run, would be) converted.
+    pub converted: u64,
+    pub failed: Vec<DocumentFailure>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RenameStatsRequest {
+    /// Maps the current key of each stat to rename to its new key.
+    pub stats: HashMap<String, String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatRenameReport {
+    /// Number of stats renamed in player and global stats documents, counting each document separately.
+    pub renamed: u64,
+    /// Documents where a stat was left under its old key, as they already had a stat under the new key.
+    pub failed: Vec<DocumentFailure>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MergeNamespaceRequest {
+    pub from: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NamespaceMergeReport {
+    /// Number of documents merged into the target namespace.
+    pub merged: u64,
+    /// Documents that were left in the source namespace.
+    pub failed: Vec<DocumentFailure>,
+}
+
+/// What was deleted by `DELETE /admin/stats/{namespace}/{stat}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct StatDeletionReport {
+    /// Number of player stats documents that the stat was removed from, counting all-time and per-season stats
+    /// separately.
+    pub stats_documents: u64,
+    /// Whether the stat was removed from the namespace's global stats.
+    pub global: bool,
+    /// Number of entries of players' stat history that the stat was removed from.
+    pub history_entries: u64,
+    /// Number of saved leaderboard snapshots of the stat that were deleted.
+    pub leaderboard_snapshots: u64,
+}
+
+/// What was deleted by `DELETE /admin/namespaces/{namespace}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct NamespaceDeletionReport {
+    /// Number of player stats documents deleted, counting all-time and per-season stats separately.
+    pub stats_documents: u64,
+    /// Whether the namespace had global stats.
+    pub global: bool,
+    /// Number of entries deleted from players' stat history.
+    pub history_entries: u64,
+    pub leaderboard_snapshots: u64,
+    pub games: u64,
+    /// Number of achievement unlocks deleted.
+    pub achievements: u64,
+    pub ratings: u64,
+    /// Number of players whose playtime in the namespace was deleted.
+    pub playtime: u64,
+}
+
+/// Where `POST /admin/backup?s3=true` uploaded a backup.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BackupUploadResponse {
+    pub bucket: String,
+    pub key: String,
+    /// Number of documents in the backup.
+    pub documents: u64,
+    /// Size of the backup in bytes.
+    pub bytes: u64,
+}
+
+/// What was restored by `POST /admin/restore`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RestoreReport {
+    /// Number of documents restored into each collection.
+    pub restored: HashMap<String, u64>,
+    /// Number of documents that were left out as they already exist.
+    pub skipped: u64,
+}
+
+/// What was deleted by `DELETE /player/{uuid}`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PlayerDeletionReport {
+    /// Whether the player had a profile.
+    pub profile: bool,
+    /// Namespaces that the player had stats in.
+    pub namespaces: Vec<String>,
+    /// Number of stats documents deleted, counting all-time and per-season stats separately.
+    pub stats_documents: u64,
+    /// Number of recorded values deleted from the player's stat history.
+    pub history_entries: u64,
+    /// Number of the player's quarantined stats documents that were deleted.
+    pub corrupt_documents: u64,
+}
+
+/// What was removed by `POST /player/{uuid}/anonymize`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PlayerAnonymizationReport {
+    /// Whether the player had a profile.
+    pub profile: bool,
+    /// Namespaces that the player has stats in, which are kept.
+    pub namespaces: Vec<String>,
+    /// Number of usernames removed from the player's username history.
+    pub previous_usernames: u64,
+    /// Number of saved leaderboard snapshots that the player's username was removed from.
+    pub leaderboard_snapshots: u64,
+}
+
+/// What a game server has uploaded, returned by `GET /stats/servers/{server}/summary`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ServerStatsSummary {
+    pub server_name: String,
+    /// What the server uploaded to each namespace.
+    pub namespaces: HashMap<String, ServerNamespaceSummary>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ServerNamespaceSummary {
+    /// Number of bundles uploaded.
+    pub bundles: u64,
+    /// Number of players that stats were uploaded for, counting a player again in each bundle.
+    pub player_uploads: u64,
+    /// When the last bundle was uploaded, in RFC 3339 format.
+    pub last_upload_at: String,
+    /// Number of values uploaded for each stat, counting both player and global stats.
+    pub stats: HashMap<String, u64>,
+}
+
+/// The body of the `202 Accepted` response to an upload that was queued to be applied in the background.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadQueuedResponse {
+    /// ID of the upload, for `GET /stats/upload/{id}/status`.
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = "uuid"))]
+    pub id: Uuid,
+}
+
+/// The body of the response to a dry run of an upload, telling what uploading the bundle would have done.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadDryRunResponse {
+    pub outcome: UploadDryRunOutcome,
+    /// The `max_increment`s that the bundle breaks, if it would have been quarantined.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub violations: Vec<Violation>,
+}
+
+/// What uploading a bundle would have done.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum UploadDryRunOutcome {
+    Applied,
+    /// A bundle with the same `bundle_id` has already been applied, so nothing would have been changed.
+    Duplicate,
+    /// The bundle adds more to a stat than its `max_increment`, so it would have been quarantined.
+    Quarantined,
+}
+
+/// How far a queued upload has got.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum UploadState {
+    /// Waiting for a worker.
+    Queued,
+    /// Being written to the database.
+    Processing,
+    Applied,
+    /// A bundle with the same `bundle_id` had already been applied, so nothing was changed.
+    Duplicate,
+    /// A stat was uploaded as a different type than it is stored as, so nothing was changed.
+    Rejected,
+    /// Writing the bundle failed, and it may be uploaded again.
+    Failed,
+}
+
+/// The progress of a queued upload.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct UploadStatusResponse {
+    #[cfg_attr(feature = "openapi", schema(value_type = String, format = "uuid"))]
+    pub id: Uuid,
+    pub state: UploadState,
+    /// Why the upload was rejected or failed.
+    pub error: Option<String>,
+    /// When the upload was queued, in RFC 3339 format.
+    pub received_at: String,
+    /// When the upload finished processing, in RFC 3339 format.
+    pub finished_at: Option<String>,
+}
+
+/// An accepted upload, as listed by `GET /admin/uploads`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadLogResponse {
+    /// When the upload was accepted, in RFC 3339 format.
+    pub received_at: String,
+    /// The name of the token that the upload was made with.
+    pub token: String,
+    pub server_name: String,
+    pub namespace: String,
+    /// Number of players in the bundle.
+    pub players: u64,
+    /// Number of stats in the bundle, counting each player's stats and the global stats.
+    pub stats: u64,
+    /// Whether the bundle was saved to the spool to be applied later, rather than applied straight away.
+    pub spooled: bool,
+}
+
+/// A document that an admin operation could not process, and why.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DocumentFailure {
+    pub document: String,
+    pub error: String,
+}
+
+/// A stats document that was quarantined because it couldn't be read.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CorruptDocumentSummary {
+    pub id: String,
+    /// The collection that the document was taken from, `player-stats` or `global-stats`.
+    pub collection: String,
+    pub namespace: Option<String>,
+    pub player: Option<Uuid>,
+    /// Why the document couldn't be read. Not known for documents quarantined by older versions.
+    pub error: Option<String>,
+    pub quarantined_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CorruptDocumentResponse {
+    #[serde(flatten)]
+    pub summary: CorruptDocumentSummary,
+    /// The document as it was quarantined, in MongoDB canonical extended JSON.
+    pub document: serde_json::Value,
+}
+
+/// A repaired version of a quarantined document, to be merged back into the collection that it was taken from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RestoreCorruptDocumentRequest {
+    /// The repaired document, in MongoDB extended JSON.
+    pub document: serde_json::Value,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RunJobResponse {
+    pub run_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobResponse {
+    pub name: String,
+    pub schedule: String,
+    /// Whether the job is running on the instance that handled the request.
+    pub running: bool,
+    pub last_run: Option<JobRunResponse>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobRunResponse {
+    pub id: String,
+    pub job: String,
+    pub trigger: JobTrigger,
+    /// When the run started, in RFC 3339 format.
+    pub started_at: String,
+    pub finished_at: Option<String>,
+    pub outcome: JobOutcome,
+    pub items_processed: Option<i64>,
+    pub error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobTrigger {
+    Schedule,
+    Manual,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum JobOutcome {
+    Running,
+    Succeeded,
+    Failed,
+}
+
+/// The body of error responses that carry more detail than their status code.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ErrorResponse {
+    pub error: String,
+}
+
+/// The body of a `400 Bad Request` for a rejected upload.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ValidationErrorResponse {
+    pub error: String,
+    /// Every limit on names and sizes that the upload breaks. Empty if a stat was instead rejected by its
+    /// `stat_metadata`, a script or a plugin, as described by `error`.
+    pub violations: Vec<Violation>,
+}
+
+/// A limit broken by an upload, and where.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Violation {
+    /// The part of the upload that breaks the limit: `namespace`, `stats`, `stats.players`, or `stats.{name}` for the
+    /// name of a stat.
+    pub field: String,
+    pub message: String,
+}
+
+/// An upload that was quarantined for adding more to a stat than its `max_increment`, listed by
+/// `GET /admin/suspicious-uploads`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SuspiciousUploadResponse {
+    pub id: String,
+    /// When the upload was received, in RFC 3339 format.
+    pub received_at: String,
+    /// The name of the token that the upload was made with.
+    pub token: String,
+    /// Each increment that was over its limit.
+    pub violations: Vec<Violation>,
+    pub bundle: GameStatsBundle,
+}
+
+/// What is done with aggregates rebuilt from the bundle log.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum RebuildMode {
+    /// Compare the rebuilt aggregates with the live ones.
+    Verify,
+    /// Compare them, and then replace the live aggregates with them.
+    Replace,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RebuildAggregatesRequest {
+    /// Only replay bundles for this namespace. Required when replacing.
+    #[serde(default)]
+    pub namespace: Option<String>,
+    /// Only replay bundles received at or after this time, in RFC 3339 format. Not allowed when replacing.
+    #[serde(default)]
+    pub from: Option<String>,
+    /// Only replay bundles received before this time, in RFC 3339 format. Not allowed when replacing.
+    #[serde(default)]
+    pub until: Option<String>,
+    pub mode: RebuildMode,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AggregateRebuildReport {
+    pub mode: RebuildMode,
+    /// Number of logged bundles that were replayed.
+    pub replayed: u64,
+    /// Number of logged bundles that were skipped because they were never fully applied.
+    pub skipped: u64,
+    /// Logged bundles that could not be replayed.
+    pub failed: Vec<DocumentFailure>,
+    /// Number of stats that differ between the live and rebuilt aggregates.
+    pub mismatched: u64,
+    /// The first of the stats that differ.
+    pub mismatches: Vec<StatMismatch>,
+    /// Number of documents written to the live aggregates.
+    pub replaced: u64,
+}
+
+/// A stat whose live value differs from its value rebuilt from the bundle log.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatMismatch {
+    pub namespace: String,
+    /// The player that the stat belongs to, or `None` for global stats.
+    pub player: Option<Uuid>,
+    pub stat: String,
+    pub live: Option<f64>,
+    pub rebuilt: Option<f64>,
+}
@@ -0,0 +1,35 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    grpc::generate();
+}
+
+/// Generates the server of the gRPC service described in `proto/persistence.proto`. Its messages are written out in
+/// `src/grpc.rs`, so that building doesn't need `protoc`.
+#[cfg(feature = "grpc")]
+mod grpc {
+    use tonic_build::manual::{Builder, Method, Service};
+
+    pub fn generate() {
+        println!("cargo:rerun-if-changed=build.rs");
+        let service = Service::builder()
+            .name("Persistence")
+            .package("nucleoid.persistence.v1")
+            .method(method("UploadStatsBundle", "upload_stats_bundle", "StatsBundle", "UploadStatsResponse"))
+            .method(method("UpdatePlayerProfile", "update_player_profile", "UpdatePlayerProfileRequest", "UpdatePlayerProfileResponse"))
+            .build();
+        Builder::new()
+            .build_client(false)
+            .build_server(true)
+            .compile(&[service]);
+    }
+
+    fn method(route_name: &str, name: &str, input: &str, output: &str) -> Method {
+        Method::builder()
+            .name(name)
+            .route_name(route_name)
+            .input_type(format!("crate::grpc::proto::{}", input))
+            .output_type(format!("crate::grpc::proto::{}", output))
+            .codec_path("tonic::codec::ProstCodec")
+            .build()
+    }
+}
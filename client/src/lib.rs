@@ -0,0 +1,662 @@
+//! A typed async client for the HTTP API of the Nucleoid persistence backend.
+
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use reqwest::{Method, RequestBuilder, Response, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::Sha256;
+use thiserror::Error;
+use uuid::Uuid;
+
+pub use nucleoid_persistence_api as api;
+use nucleoid_persistence_api::{
+    AchievementInfo, AchievementsResponse, AggregateRebuildReport, BackupUploadResponse, ConvertStatRequest, CorruptDocumentResponse, CorruptDocumentSummary, GameResponse,
+    GameStatsBundle, GameUploadRequest, GameUploadResponse, GlobalStatsDeltaResponse, GlobalStatsResponse,
+    GrantAchievementsRequest, GrantAchievementsResponse, HistogramBucket, JobResponse, JobRunResponse, LeaderboardOrder, LeaderboardResponse, LeaderboardSnapshotResponse, MatchResultRequest, MatchResultResponse, MergeNamespaceRequest,
+    NamespaceDeletionReport, NamespaceMergeReport, PlayerAnonymizationReport, PlayerDeletionReport, PlayerListResponse, PlaytimeReport, PlaytimeResponse, PlayerProfileResponse, PlayerStatsResponse, RebuildAggregatesRequest, RenameStatsRequest,
+    RestoreCorruptDocumentRequest, RestoreReport, RunJobResponse, SeasonResponse, ServerStatsSummary, StatConversionReport, StatDeletionReport, StatHistoryPoint, StatRenameReport, StatRankResponse, StatSummaryResponse,
+    StatInfoResponse, SuspiciousUploadResponse, UnlockedAchievement, UpdatePlayerProfileRequest, UploadDryRunResponse, UploadLogResponse, UploadQueuedResponse, UploadStatusResponse, UsernameHistoryEntry,
+};
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("request failed: {0}")]
+    Http(#[from] reqwest::Error),
+    #[error("server responded with {status}: {body}")]
+    Status {
+        status: StatusCode,
+        body: String,
+    },
+}
+
+pub type Result<T> = std::result::Result<T, ClientError>;
+
+/// How uploads are retried.
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Total number of attempts, including the first.
+    pub max_attempts: u32,
+    /// Delay before the first retry, which doubles after each attempt.
+    pub initial_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(500),
+        }
+    }
+}
+
+/// What the server did with an uploaded bundle.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum UploadOutcome {
+    /// The bundle was written to the database.
+    Applied,
+    /// The server was busy and saved the bundle to its spool, to be written later.
+    Spooled,
+    /// The server queued the bundle to be written in the background. Its progress can be checked with
+    /// [PersistenceClient::get_upload_status].
+    Queued(Uuid),
+    /// A bundle with the same `bundle_id` had already been applied, so nothing was changed.
+    Duplicate,
+}
+
+/// A client for one persistence backend.
+#[derive(Clone)]
+pub struct PersistenceClient {
+    http: reqwest::Client,
+    base_url: String,
+    token: Option<String>,
+    signing_key: Option<(String, String)>,
+    retry: RetryPolicy,
+}
+
+impl PersistenceClient {
+    /// Creates a client for the backend at `base_url`, e.g. `http://localhost:8080`, without a token.
+    pub fn new(base_url: impl Into<String>) -> Self {
+        Self::with_http_client(reqwest::Client::new(), base_url)
+    }
+
+    pub fn with_http_client(http: reqwest::Client, base_url: impl Into<String>) -> Self {
+        Self {
+            http,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            token: None,
+            signing_key: None,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// Sends `token` as the `Authorization` header of every request. Uploads, profile updates and admin requests each
+    /// need a token with the matching scope.
+    pub fn with_token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Signs uploads with the token called `name` instead of sending it, so that the token never crosses the network.
+    /// Other requests still send the token set by [Self::with_token], if any.
+    pub fn with_signing_key(mut self, name: impl Into<String>, token: impl Into<String>) -> Self {
+        self.signing_key = Some((name.into(), token.into()));
+        self
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Gets a player's profile, or `None` if the backend has never seen them.
+    pub async fn get_player_profile(&self, uuid: Uuid) -> Result<Option<PlayerProfileResponse>> {
+        let response = self.request(Method::GET, &format!("/player/{}", uuid)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Finds the player who most recently had a username, ignoring case.
+    pub async fn get_player_by_name(&self, username: &str) -> Result<Option<PlayerProfileResponse>> {
+        let response = self.request(Method::GET, &format!("/player/by-name/{}", username)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Lists the usernames that a player has used, newest first, or `None` if the player is unknown. Needs a token
+    /// with the `read_private` scope.
+    pub async fn get_username_history(&self, uuid: Uuid) -> Result<Option<Vec<UsernameHistoryEntry>>> {
+        let response = self.request(Method::GET, &format!("/player/{}/names", uuid)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Gets how long a player has played, or `None` if the player is unknown.
+    pub async fn get_playtime(&self, uuid: Uuid) -> Result<Option<PlaytimeResponse>> {
+        let response = self.request(Method::GET, &format!("/player/{}/playtime", uuid)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Adds a play session onto a player's playtime. Needs a token with the `upload_stats` scope.
+    pub async fn report_playtime(&self, uuid: Uuid, report: &PlaytimeReport) -> Result<()> {
+        let response = self.request(Method::POST, &format!("/player/{}/playtime", uuid)).json(report).send().await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Lists the achievements that a player has unlocked in one or every namespace, most recently unlocked first, or
+    /// `None` if the player is unknown.
+    pub async fn get_player_achievements(&self, uuid: Uuid, namespace: Option<&str>) -> Result<Option<Vec<UnlockedAchievement>>> {
+        let mut request = self.request(Method::GET, &format!("/player/{}/achievements", uuid));
+        if let Some(namespace) = namespace {
+            request = request.query(&[("namespace", namespace)]);
+        }
+        optional_json(request.send().await?).await
+    }
+
+    /// Unlocks registered achievements for a player, returning those they didn't have before. Needs a token with the
+    /// `upload_stats` scope.
+    pub async fn grant_achievements(&self, uuid: Uuid, request: &GrantAchievementsRequest) -> Result<GrantAchievementsResponse> {
+        let response = self.request(Method::POST, &format!("/player/{}/achievements", uuid)).json(request).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    pub async fn update_player_profile(&self, uuid: Uuid, request: &UpdatePlayerProfileRequest) -> Result<()> {
+        let response = self.request(Method::PUT, &format!("/player/{}", uuid)).json(request).send().await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Deletes everything stored about a player, reporting what was deleted.
+    pub async fn delete_player(&self, uuid: Uuid) -> Result<PlayerDeletionReport> {
+        let response = self.request(Method::DELETE, &format!("/player/{}", uuid)).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Removes a player's username and username history while keeping their stats, or returns `None` if the player
+    /// has no profile.
+    pub async fn anonymize_player(&self, uuid: Uuid) -> Result<Option<PlayerAnonymizationReport>> {
+        let response = self.request(Method::POST, &format!("/player/{}/anonymize", uuid)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Finds up to `limit` players whose current username starts with `prefix`, ignoring case.
+    pub async fn search_players(&self, prefix: &str, limit: Option<u32>) -> Result<Vec<PlayerProfileResponse>> {
+        let mut request = self.request(Method::GET, "/players/search").query(&[("q", prefix)]);
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Lists a page of up to `limit` players, ordered by UUID or, if `by_last_seen`, most recently seen first. The
+    /// next page starts `after` the `next` cursor of the previous one.
+    pub async fn list_players(&self, limit: Option<u32>, after: Option<&str>, by_last_seen: bool) -> Result<PlayerListResponse> {
+        let mut request = self.request(Method::GET, "/players");
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        if let Some(after) = after {
+            request = request.query(&[("after", after)]);
+        }
+        if by_last_seen {
+            request = request.query(&[("sort", "last_seen")]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Gets a player's stats in one namespace, or in every namespace if `namespace` is `None`.
+    pub async fn get_player_stats(&self, uuid: Uuid, namespace: Option<&str>) -> Result<Option<PlayerStatsResponse>> {
+        let path = match namespace {
+            Some(namespace) => format!("/player/{}/stats/{}", uuid, namespace),
+            None => format!("/player/{}/stats", uuid),
+        };
+        let response = self.request(Method::GET, &path).send().await?;
+        optional_json(response).await
+    }
+
+    /// Gets a player's stats in several namespaces with one request.
+    pub async fn get_player_stats_in(&self, uuid: Uuid, namespaces: &[&str]) -> Result<Option<PlayerStatsResponse>> {
+        let response = self.request(Method::GET, &format!("/player/{}/stats", uuid))
+            .query(&[("namespaces", namespaces.join(","))])
+            .send().await?;
+        optional_json(response).await
+    }
+
+    /// Gets the recorded values of a player's stat, oldest first. `from` and `until` are RFC 3339 times.
+    pub async fn get_stat_history(&self, uuid: Uuid, namespace: &str, stat: &str, from: Option<&str>, until: Option<&str>) -> Result<Option<Vec<StatHistoryPoint>>> {
+        let mut request = self.request(Method::GET, &format!("/player/{}/stats/{}/history", uuid, namespace))
+            .query(&[("stat", stat)]);
+        if let Some(from) = from {
+            request = request.query(&[("from", from)]);
+        }
+        if let Some(until) = until {
+            request = request.query(&[("until", until)]);
+        }
+        optional_json(request.send().await?).await
+    }
+
+    /// Gets the buckets of a player's histogram stat.
+    pub async fn get_stat_histogram(&self, uuid: Uuid, namespace: &str, stat: &str) -> Result<Option<Vec<HistogramBucket>>> {
+        let request = self.request(Method::GET, &format!("/player/{}/stats/{}/histogram", uuid, namespace))
+            .query(&[("stat", stat)]);
+        optional_json(request.send().await?).await
+    }
+
+    /// Uploads a bundle of stats.
+    ///
+    /// Uploads without a `bundle_id` aren't idempotent, so they are only retried when the server is known not to have
+    /// applied them: when the connection couldn't be made, or the server responded that it was unavailable. Other
+    /// failures are returned straight away, as retrying them could count the bundle twice. Uploads with a `bundle_id`
+    /// are also retried after timeouts and server errors, since the server ignores them if they were applied.
+    pub async fn upload_stats(&self, bundle: &GameStatsBundle) -> Result<UploadOutcome> {
+        let mut backoff = self.retry.initial_backoff;
+        let mut attempt = 1;
+        loop {
+            let result = self.upload_request("/stats/upload", bundle).send().await;
+            let idempotent = bundle.bundle_id.is_some();
+            let retryable = match &result {
+                Ok(response) => response.status() == StatusCode::SERVICE_UNAVAILABLE
+                    || (idempotent && response.status().is_server_error()),
+                Err(e) => e.is_connect() || (idempotent && e.is_timeout()),
+            };
+            if !retryable || attempt >= self.retry.max_attempts {
+                let response = check_status(result?).await?;
+                return Ok(if response.status() == StatusCode::ACCEPTED {
+                    // Only queued uploads have a body, with the ID of the upload.
+                    match response.json::<UploadQueuedResponse>().await {
+                        Ok(queued) => UploadOutcome::Queued(queued.id),
+                        Err(_) => UploadOutcome::Spooled,
+                    }
+                } else if response.headers().contains_key("x-duplicate-bundle") {
+                    UploadOutcome::Duplicate
+                } else {
+                    UploadOutcome::Applied
+                });
+            }
+
+            tokio::time::sleep(backoff).await;
+            backoff *= 2;
+            attempt += 1;
+        }
+    }
+
+    /// Checks a bundle against the server's stored stats without writing anything, telling what uploading it would
+    /// have done. Bundles that would be refused fail with the same [ClientError::Status] as [Self::upload_stats].
+    pub async fn dry_run_upload_stats(&self, bundle: &GameStatsBundle) -> Result<UploadDryRunResponse> {
+        let response = self.upload_request("/stats/upload?dry_run=true", bundle).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Gets the progress of an upload that the server queued, or `None` if it is unknown to the server.
+    pub async fn get_upload_status(&self, id: Uuid) -> Result<Option<UploadStatusResponse>> {
+        let response = self.request(Method::GET, &format!("/stats/upload/{}/status", id)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Records a completed match, returning its id.
+    pub async fn upload_game(&self, game: &GameUploadRequest) -> Result<String> {
+        let response = self.upload_request("/games/upload", game).send().await?;
+        let response: GameUploadResponse = check_status(response).await?.json().await?;
+        Ok(response.id)
+    }
+
+    /// Gets the most recently finished games, optionally only those in one namespace.
+    pub async fn get_recent_games(&self, namespace: Option<&str>, limit: Option<u32>) -> Result<Vec<GameResponse>> {
+        let request = self.games_request("/games/recent", namespace, limit);
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Gets the games that a player most recently took part in.
+    pub async fn get_player_games(&self, uuid: Uuid, namespace: Option<&str>, limit: Option<u32>) -> Result<Option<Vec<GameResponse>>> {
+        let request = self.games_request(&format!("/player/{}/games", uuid), namespace, limit);
+        optional_json(request.send().await?).await
+    }
+
+    fn games_request(&self, path: &str, namespace: Option<&str>, limit: Option<u32>) -> RequestBuilder {
+        let mut request = self.request(Method::GET, path);
+        if let Some(namespace) = namespace {
+            request = request.query(&[("namespace", namespace)]);
+        }
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        request
+    }
+
+    /// Gets a player's stats from a season, which is a season number or `current`.
+    pub async fn get_player_season_stats(&self, uuid: Uuid, namespace: Option<&str>, season: &str) -> Result<Option<PlayerStatsResponse>> {
+        let path = match namespace {
+            Some(namespace) => format!("/player/{}/stats/{}", uuid, namespace),
+            None => format!("/player/{}/stats", uuid),
+        };
+        let response = self.request(Method::GET, &path).query(&[("season", season)]).send().await?;
+        optional_json(response).await
+    }
+
+    /// Ranks players by a stat within a season, which is a season number or `current`.
+    pub async fn get_season_leaderboard(&self, namespace: &str, stat: &str, season: &str, limit: Option<u32>, order: LeaderboardOrder) -> Result<LeaderboardResponse> {
+        let mut request = self.request(Method::GET, &format!("/leaderboard/{}/{}", namespace, stat))
+            .query(&[("order", order)])
+            .query(&[("season", season)]);
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Gets the season that the stats of seasonal namespaces are currently added to.
+    pub async fn get_current_season(&self) -> Result<SeasonResponse> {
+        let response = self.request(Method::GET, "/seasons/current").send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Ends the current season and starts the next one, returning the new season.
+    pub async fn start_season(&self) -> Result<SeasonResponse> {
+        let response = self.request(Method::POST, "/admin/seasons/start").send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Lists the namespaces that have stats.
+    pub async fn get_namespaces(&self) -> Result<Vec<String>> {
+        let response = self.request(Method::GET, "/stats/namespaces").send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Gets the registered display names, units and descriptions of a namespace's stats.
+    pub async fn get_stat_metadata(&self, namespace: &str) -> Result<StatInfoResponse> {
+        let response = self.request(Method::GET, &format!("/stats/{}/metadata", namespace)).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Registers how stats are presented, replacing anything registered for them before.
+    pub async fn update_stat_metadata(&self, namespace: &str, stats: &StatInfoResponse) -> Result<()> {
+        let response = self.request(Method::PUT, &format!("/stats/{}/metadata", namespace)).json(stats).send().await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Gets the registered achievements of a namespace and how many players have unlocked each.
+    pub async fn get_achievements(&self, namespace: &str) -> Result<AchievementsResponse> {
+        let response = self.request(Method::GET, &format!("/achievements/{}", namespace)).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Registers how achievements are presented, replacing anything registered for them before. Needs a token with
+    /// the `update_achievements` scope.
+    pub async fn update_achievements(&self, namespace: &str, achievements: &HashMap<String, AchievementInfo>) -> Result<()> {
+        let response = self.request(Method::PUT, &format!("/achievements/{}", namespace)).json(achievements).send().await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Gets a namespace's global stats, or `None` if nothing has been uploaded to it.
+    pub async fn get_global_stats(&self, namespace: &str) -> Result<Option<GlobalStatsResponse>> {
+        let response = self.request(Method::GET, &format!("/stats/global/{}", namespace)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Summarizes one of the player stats of a namespace across every player, or `None` if no player has it.
+    pub async fn get_stat_summary(&self, namespace: &str, stat: &str) -> Result<Option<StatSummaryResponse>> {
+        let request = self.request(Method::GET, &format!("/stats/{}/summary", namespace))
+            .query(&[("stat", stat)]);
+        optional_json(request.send().await?).await
+    }
+
+    /// Gets the buckets of a global histogram stat.
+    pub async fn get_global_stat_histogram(&self, namespace: &str, stat: &str) -> Result<Option<Vec<HistogramBucket>>> {
+        let request = self.request(Method::GET, &format!("/stats/global/{}/histogram", namespace))
+            .query(&[("stat", stat)]);
+        optional_json(request.send().await?).await
+    }
+
+    /// Gets what a game server has uploaded to each namespace, or `None` if it hasn't uploaded any bundles.
+    pub async fn get_server_stats(&self, server_name: &str) -> Result<Option<ServerStatsSummary>> {
+        let server_name = percent_encoding::utf8_percent_encode(server_name, percent_encoding::NON_ALPHANUMERIC);
+        let response = self.request(Method::GET, &format!("/stats/servers/{}/summary", server_name)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Gets the players with the highest (or lowest) values of a stat.
+    pub async fn get_leaderboard(&self, namespace: &str, stat: &str, limit: Option<u32>, order: LeaderboardOrder) -> Result<LeaderboardResponse> {
+        let mut request = self.request(Method::GET, &format!("/leaderboard/{}/{}", namespace, stat))
+            .query(&[("order", order)]);
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Records the result of a match, returning how it changed the ratings of its players. Needs a token with the
+    /// `upload_games` scope.
+    pub async fn record_match(&self, namespace: &str, request: &MatchResultRequest) -> Result<MatchResultResponse> {
+        let response = self.request(Method::POST, &format!("/ratings/{}/match", namespace)).json(request).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Ranks the players of a namespace by their rating.
+    pub async fn get_rating_leaderboard(&self, namespace: &str, limit: Option<u32>) -> Result<LeaderboardResponse> {
+        let mut request = self.request(Method::GET, &format!("/ratings/{}/leaderboard", namespace));
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Gets the latest saved snapshot of a leaderboard taken at or before `at`, an RFC 3339 time, or now if `at` is
+    /// `None`. Returns `None` if no snapshot was taken by then.
+    pub async fn get_leaderboard_snapshot(&self, namespace: &str, stat: &str, order: LeaderboardOrder, at: Option<&str>) -> Result<Option<LeaderboardSnapshotResponse>> {
+        let mut request = self.request(Method::GET, &format!("/leaderboard/{}/{}/snapshot", namespace, stat))
+            .query(&[("order", order)]);
+        if let Some(at) = at {
+            request = request.query(&[("at", at)]);
+        }
+        optional_json(request.send().await?).await
+    }
+
+    /// Gets where a player ranks by one of their stats, or `None` if they don't have the stat.
+    pub async fn get_stat_rank(&self, uuid: Uuid, namespace: &str, stat: &str, order: LeaderboardOrder) -> Result<Option<StatRankResponse>> {
+        let response = self.request(Method::GET, &format!("/player/{}/stats/{}/{}/rank", uuid, namespace, stat))
+            .query(&[("order", order)])
+            .send().await?;
+        optional_json(response).await
+    }
+
+    /// Gets how much a namespace's global stats changed over `window`, e.g. `24h` or `7d`.
+    pub async fn get_global_stats_delta(&self, namespace: &str, window: &str) -> Result<Option<GlobalStatsDeltaResponse>> {
+        let response = self.request(Method::GET, &format!("/stats/global/{}/delta", namespace))
+            .query(&[("window", window)])
+            .send().await?;
+        optional_json(response).await
+    }
+
+    /// Gets the backend's metrics, in the Prometheus text format.
+    pub async fn metrics(&self) -> Result<String> {
+        let response = self.request(Method::GET, "/metrics").send().await?;
+        Ok(check_status(response).await?.text().await?)
+    }
+
+    pub async fn convert_stat(&self, namespace: &str, request: &ConvertStatRequest) -> Result<StatConversionReport> {
+        let response = self.request(Method::POST, &format!("/admin/stats/{}/convert", namespace)).json(request).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Renames stats in a namespace, given a map of their current keys to their new keys.
+    pub async fn rename_stats(&self, namespace: &str, stats: HashMap<String, String>) -> Result<StatRenameReport> {
+        let request = RenameStatsRequest { stats };
+        let response = self.request(Method::POST, &format!("/admin/stats/{}/rename", namespace)).json(&request).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Merges the stats of the namespace `from` into `namespace`.
+    pub async fn merge_namespace(&self, namespace: &str, from: &str) -> Result<NamespaceMergeReport> {
+        let request = MergeNamespaceRequest { from: from.to_string() };
+        let response = self.request(Method::POST, &format!("/admin/namespaces/{}/merge", namespace)).json(&request).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Removes a stat from every player and the global stats of a namespace.
+    pub async fn delete_stat(&self, namespace: &str, stat: &str) -> Result<StatDeletionReport> {
+        let response = self.request(Method::DELETE, &format!("/admin/stats/{}/{}", namespace, stat)).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Deletes everything stored for a namespace, apart from player profiles and the bundle and upload logs.
+    pub async fn delete_namespace(&self, namespace: &str) -> Result<NamespaceDeletionReport> {
+        let response = self.request(Method::DELETE, &format!("/admin/namespaces/{}", namespace)).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Rebuilds aggregates from the bundle log, verifying or replacing the live aggregates.
+    pub async fn rebuild_aggregates(&self, request: &RebuildAggregatesRequest) -> Result<AggregateRebuildReport> {
+        let response = self.request(Method::POST, "/admin/aggregates/rebuild").json(request).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Downloads a backup of the whole database as newline-delimited JSON.
+    pub async fn backup(&self) -> Result<Vec<u8>> {
+        let response = self.request(Method::POST, "/admin/backup").send().await?;
+        Ok(check_status(response).await?.bytes().await?.to_vec())
+    }
+
+    /// Makes a backup of the whole database and uploads it to the S3 bucket that the backend is configured with.
+    pub async fn backup_to_s3(&self) -> Result<BackupUploadResponse> {
+        let response = self.request(Method::POST, "/admin/backup").query(&[("s3", true)]).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Restores a backup made by [Client::backup], adding the documents that the database doesn't already have.
+    pub async fn restore(&self, backup: Vec<u8>) -> Result<RestoreReport> {
+        let response = self.request(Method::POST, "/admin/restore").body(backup).send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Lists the most recently accepted uploads, newest first, optionally only those from one server or to one
+    /// namespace.
+    pub async fn list_uploads(&self, server_name: Option<&str>, namespace: Option<&str>, limit: Option<u32>) -> Result<Vec<UploadLogResponse>> {
+        let mut request = self.request(Method::GET, "/admin/uploads");
+        if let Some(server_name) = server_name {
+            request = request.query(&[("server_name", server_name)]);
+        }
+        if let Some(namespace) = namespace {
+            request = request.query(&[("namespace", namespace)]);
+        }
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Lists quarantined stats documents, most recently quarantined first.
+    pub async fn list_corrupt_documents(&self, limit: Option<u32>) -> Result<Vec<CorruptDocumentSummary>> {
+        let mut request = self.request(Method::GET, "/admin/corrupt-stats");
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    pub async fn get_corrupt_document(&self, id: &str) -> Result<Option<CorruptDocumentResponse>> {
+        let response = self.request(Method::GET, &format!("/admin/corrupt-stats/{}", id)).send().await?;
+        optional_json(response).await
+    }
+
+    /// Merges a repaired version of a quarantined document back into the collection that it was taken from.
+    pub async fn restore_corrupt_document(&self, id: &str, request: &RestoreCorruptDocumentRequest) -> Result<()> {
+        let response = self.request(Method::POST, &format!("/admin/corrupt-stats/{}/restore", id)).json(request).send().await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Lists uploads quarantined for adding more to a stat than its `max_increment`, most recently received first.
+    pub async fn list_suspicious_uploads(&self, limit: Option<u32>) -> Result<Vec<SuspiciousUploadResponse>> {
+        let mut request = self.request(Method::GET, "/admin/suspicious-uploads");
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    /// Applies a quarantined upload and removes it from quarantine.
+    pub async fn release_suspicious_upload(&self, id: &str) -> Result<()> {
+        let response = self.request(Method::POST, &format!("/admin/suspicious-uploads/{}/release", id)).send().await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Removes a quarantined upload without applying it.
+    pub async fn discard_suspicious_upload(&self, id: &str) -> Result<()> {
+        let response = self.request(Method::DELETE, &format!("/admin/suspicious-uploads/{}", id)).send().await?;
+        check_status(response).await?;
+        Ok(())
+    }
+
+    /// Starts a run of a background job, returning the id of the run.
+    pub async fn run_job(&self, name: &str) -> Result<String> {
+        let response = self.request(Method::POST, &format!("/admin/jobs/{}/run", name)).send().await?;
+        let response: RunJobResponse = check_status(response).await?.json().await?;
+        Ok(response.run_id)
+    }
+
+    pub async fn list_jobs(&self) -> Result<Vec<JobResponse>> {
+        let response = self.request(Method::GET, "/admin/jobs").send().await?;
+        Ok(check_status(response).await?.json().await?)
+    }
+
+    /// Gets the most recent runs of a job, newest first.
+    pub async fn get_job_runs(&self, name: &str, limit: Option<u32>) -> Result<Vec<JobRunResponse>> {
+        let mut request = self.request(Method::GET, &format!("/admin/jobs/{}/runs", name));
+        if let Some(limit) = limit {
+            request = request.query(&[("limit", limit)]);
+        }
+        Ok(check_status(request.send().await?).await?.json().await?)
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        let request = self.http.request(method, format!("{}{}", self.base_url, path));
+        match &self.token {
+            Some(token) => request.header(reqwest::header::AUTHORIZATION, token),
+            None => request,
+        }
+    }
+
+    /// Builds an upload, signing it with a fresh timestamp and nonce if a signing key is set.
+    fn upload_request(&self, path: &str, body: &impl Serialize) -> RequestBuilder {
+        let (name, token) = match &self.signing_key {
+            Some(signing_key) => signing_key,
+            None => return self.request(Method::POST, path).json(body),
+        };
+
+        let body = serde_json::to_vec(body).expect("API types always serialize");
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+        let nonce = format!("{:032x}", rand::random::<u128>());
+        let mut mac = Hmac::<Sha256>::new_from_slice(token.as_bytes()).expect("HMAC accepts keys of any length");
+        mac.update(format!("{}\n{}\n", timestamp, nonce).as_bytes());
+        mac.update(&body);
+
+        self.http.post(format!("{}{}", self.base_url, path))
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .header("X-Signature-Key", name)
+            .header("X-Signature-Timestamp", timestamp)
+            .header("X-Signature-Nonce", nonce)
+            .header("X-Signature", hex::encode(mac.finalize().into_bytes()))
+            .body(body)
+    }
+}
+
+async fn check_status(response: Response) -> Result<Response> {
+    let status = response.status();
+    if status.is_success() {
+        Ok(response)
+    } else {
+        let body = response.text().await.unwrap_or_default();
+        Err(ClientError::Status { status, body })
+    }
+}
+
+/// Reads a JSON response, treating a 404 as `None`.
+async fn optional_json<T: DeserializeOwned>(response: Response) -> Result<Option<T>> {
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(check_status(response).await?.json().await?))
+}
@@ -0,0 +1,345 @@
+//! Backups of the whole database as newline-delimited JSON, which `POST /admin/backup` streams or uploads to S3, and
+//! `POST /admin/restore` reads back. A backup starts with a header line, then has a line for each document, and ends
+//! with a line counting them, so that a backup that was cut off can be told apart from a complete one.
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use futures::{Stream, StreamExt};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::sync::mpsc;
+use warp::hyper::body::{Buf, Bytes};
+use xtra::Address;
+
+use crate::config::{DatabaseType, S3Config};
+use crate::limit::RouteLimits;
+use crate::model::{BackupUploadResponse, RestoreReport};
+use crate::store::{ExportDocuments, GetBackupManifest, ImportDocuments, StoreHandler};
+use crate::web;
+
+/// Version of the format of backups, which is increased whenever older backups can no longer be read.
+const FORMAT: u32 = 1;
+/// How many documents are read from, or written to, the database at a time.
+const BATCH_SIZE: usize = 100;
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum Line {
+    Header { backup: Header },
+    Document { collection: String, document: serde_json::Value },
+    End { end: End },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Header {
+    format: u32,
+    database_type: DatabaseType,
+    /// Backups can only be restored into a database at the same schema version, as documents aren't migrated.
+    schema_version: usize,
+    created_at: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct End {
+    documents: u64,
+}
+
+/// Why a backup couldn't be restored.
+#[derive(Debug)]
+pub enum RestoreError {
+    /// The backup is malformed or can't be restored into this database. Documents before the problem are still
+    /// restored.
+    Invalid(String),
+    Failed(anyhow::Error),
+}
+
+impl From<anyhow::Error> for RestoreError {
+    fn from(e: anyhow::Error) -> Self {
+        RestoreError::Failed(e)
+    }
+}
+
+/// Streams a backup of every collection. The stream ends with an error if the backup fails part of the way through,
+/// leaving it without its end line.
+pub fn stream(database: Address<StoreHandler>, limits: RouteLimits, database_type: DatabaseType) -> impl Stream<Item = Result<Bytes>> {
+    let (sender, receiver) = mpsc::channel(4);
+    tokio::spawn(async move {
+        if let Err(e) = export(&database, &limits, database_type, &sender).await {
+            log::warn!("backup failed: {}", e);
+            let _ = sender.send(Err(e)).await;
+        }
+    });
+    futures::stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|chunk| (chunk, receiver))
+    })
+}
+
+/// Makes a backup and uploads it to the configured bucket. Backups are held in memory until they are uploaded.
+pub async fn upload(config: &S3Config, database: Address<StoreHandler>, limits: RouteLimits, database_type: DatabaseType) -> Result<BackupUploadResponse> {
+    let (sender, mut receiver) = mpsc::channel::<Result<Bytes>>(4);
+    let collect = async move {
+        let mut body = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            body.extend_from_slice(&chunk?);
+        }
+        Ok::<_, anyhow::Error>(body)
+    };
+    // The sender is dropped once the backup has been written, which ends the body.
+    let write = async move { export(&database, &limits, database_type, &sender).await };
+    let (documents, body) = tokio::try_join!(write, collect)?;
+
+    let key = format!("{}nucleoid-backup-{}.ndjson", config.prefix, chrono::Utc::now().format("%Y%m%dT%H%M%SZ"));
+    let bytes = body.len() as u64;
+    put_object(config, &key, body).await?;
+    log::info!("Uploaded backup of {} documents to s3://{}/{}", documents, config.bucket, key);
+    Ok(BackupUploadResponse {
+        bucket: config.bucket.clone(),
+        key,
+        documents,
+        bytes,
+    })
+}
+
+/// Writes the lines of a backup to `sender` a batch at a time, returning the number of documents in it.
+async fn export(database: &Address<StoreHandler>, limits: &RouteLimits, database_type: DatabaseType, sender: &mpsc::Sender<Result<Bytes>>) -> Result<u64> {
+    let manifest = web::send(database, GetBackupManifest, limits.deadline("backup")).await?;
+    let header = Line::Header {
+        backup: Header {
+            format: FORMAT,
+            database_type,
+            schema_version: manifest.schema_version,
+            created_at: chrono::Utc::now().to_rfc3339(),
+        },
+    };
+    write_lines(sender, &[header]).await?;
+
+    let mut documents = 0;
+    for collection in manifest.collections {
+        let mut after = None;
+        loop {
+            // Each batch gets its own deadline, as the whole backup can take much longer than a single request.
+            let batch = web::send(database, ExportDocuments {
+                collection: collection.clone(),
+                after: after.take(),
+                limit: BATCH_SIZE as i64,
+            }, limits.deadline("backup")).await?;
+            documents += batch.documents.len() as u64;
+            let lines: Vec<Line> = batch.documents.into_iter()
+                .map(|document| Line::Document { collection: collection.clone(), document })
+                .collect();
+            write_lines(sender, &lines).await?;
+            match batch.next {
+                Some(next) => after = Some(next),
+                None => break,
+            }
+        }
+    }
+
+    write_lines(sender, &[Line::End { end: End { documents } }]).await?;
+    Ok(documents)
+}
+
+async fn write_lines(sender: &mpsc::Sender<Result<Bytes>>, lines: &[Line]) -> Result<()> {
+    let mut chunk = Vec::new();
+    for line in lines {
+        serde_json::to_writer(&mut chunk, line)?;
+        chunk.push(b'\n');
+    }
+    sender.send(Ok(chunk.into())).await
+        .map_err(|_| anyhow::anyhow!("the backup was no longer being read"))
+}
+
+/// Restores the documents of a backup that was made by [stream] or [upload]. Documents that already exist are left
+/// as they are, so restoring into a database that isn't empty only adds what it is missing.
+pub async fn restore<B: Buf>(
+    database: &Address<StoreHandler>,
+    limits: &RouteLimits,
+    database_type: DatabaseType,
+    max_line_bytes: u64,
+    body: impl Stream<Item = Result<B, warp::Error>> + Unpin,
+) -> Result<RestoreReport, RestoreError> {
+    let manifest = web::send(database, GetBackupManifest, limits.deadline("restore")).await?;
+    let mut lines = split_lines(body, max_line_bytes);
+    let mut report = RestoreReport::default();
+
+    match lines.next().await.transpose()? {
+        Some(Line::Header { backup }) => {
+            if backup.format != FORMAT {
+                return Err(RestoreError::Invalid(format!("backups in format {} can't be read, only format {}", backup.format, FORMAT)));
+            }
+            if backup.database_type != database_type {
+                return Err(RestoreError::Invalid(format!("the backup is of a {:?} database rather than a {:?} one", backup.database_type, database_type)));
+            }
+            if backup.schema_version != manifest.schema_version {
+                return Err(RestoreError::Invalid(format!("the backup is at schema version {} rather than {}", backup.schema_version, manifest.schema_version)));
+            }
+        }
+        _ => return Err(RestoreError::Invalid("the backup doesn't start with a header".to_string())),
+    }
+
+    let mut pending: HashMap<String, Vec<serde_json::Value>> = HashMap::new();
+    let mut documents = 0;
+    let mut ended = false;
+    while let Some(line) = lines.next().await {
+        match line? {
+            _ if ended => return Err(RestoreError::Invalid("the backup continues after its end".to_string())),
+            Line::Document { collection, document } => {
+                if !manifest.collections.contains(&collection) {
+                    flush_all(database, limits, &mut pending, &mut report).await?;
+                    return Err(RestoreError::Invalid(format!("'{}' isn't a collection that is backed up", collection)));
+                }
+                documents += 1;
+                let batch = pending.entry(collection.clone()).or_default();
+                batch.push(document);
+                if batch.len() >= BATCH_SIZE {
+                    let batch = std::mem::take(batch);
+                    flush(database, limits, collection, batch, &mut report).await?;
+                }
+            }
+            Line::End { end } => {
+                if end.documents != documents {
+                    flush_all(database, limits, &mut pending, &mut report).await?;
+                    return Err(RestoreError::Invalid(format!("the backup has {} documents rather than the {} it should have", documents, end.documents)));
+                }
+                ended = true;
+            }
+            Line::Header { .. } => return Err(RestoreError::Invalid("the backup has more than one header".to_string())),
+        }
+    }
+
+    flush_all(database, limits, &mut pending, &mut report).await?;
+    if !ended {
+        return Err(RestoreError::Invalid(format!("the backup was cut off after {} documents, which were restored", documents)));
+    }
+    log::info!("Restored {} documents from a backup, skipping {} that already existed", documents - report.skipped, report.skipped);
+    Ok(report)
+}
+
+async fn flush_all(database: &Address<StoreHandler>, limits: &RouteLimits, pending: &mut HashMap<String, Vec<serde_json::Value>>, report: &mut RestoreReport) -> Result<()> {
+    for (collection, batch) in pending.drain() {
+        flush(database, limits, collection, batch, report).await?;
+    }
+    Ok(())
+}
+
+async fn flush(database: &Address<StoreHandler>, limits: &RouteLimits, collection: String, batch: Vec<serde_json::Value>, report: &mut RestoreReport) -> Result<()> {
+    if batch.is_empty() {
+        return Ok(());
+    }
+    let total = batch.len() as u64;
+    let restored = web::send(database, ImportDocuments {
+        collection: collection.clone(),
+        documents: batch,
+    }, limits.deadline("restore")).await?;
+    *report.restored.entry(collection).or_default() += restored;
+    report.skipped += total - restored;
+    Ok(())
+}
+
+/// Parses a body into the lines of a backup, without holding more than a line of it in memory at a time.
+fn split_lines<B: Buf>(body: impl Stream<Item = Result<B, warp::Error>> + Unpin, max_line_bytes: u64) -> impl Stream<Item = Result<Line, RestoreError>> + Unpin {
+    let state = (body, Vec::new(), false);
+    Box::pin(futures::stream::unfold(state, move |(mut body, mut buffer, mut done)| async move {
+        loop {
+            if let Some(end) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                if line.iter().all(u8::is_ascii_whitespace) {
+                    continue;
+                }
+                return Some((parse_line(&line), (body, buffer, done)));
+            }
+            if done {
+                // The last line doesn't have to end with a newline.
+                if buffer.iter().all(u8::is_ascii_whitespace) {
+                    return None;
+                }
+                let line = std::mem::take(&mut buffer);
+                return Some((parse_line(&line), (body, buffer, done)));
+            }
+            match body.next().await {
+                Some(Ok(mut chunk)) => {
+                    while chunk.has_remaining() {
+                        let bytes = chunk.chunk();
+                        buffer.extend_from_slice(bytes);
+                        let len = bytes.len();
+                        chunk.advance(len);
+                    }
+                    if buffer.len() as u64 > max_line_bytes && !buffer.contains(&b'\n') {
+                        let error = RestoreError::Invalid(format!("a line of the backup is longer than the limit of {} bytes", max_line_bytes));
+                        return Some((Err(error), (body, Vec::new(), true)));
+                    }
+                }
+                Some(Err(e)) => return Some((Err(RestoreError::Failed(e.into())), (body, Vec::new(), true))),
+                None => done = true,
+            }
+        }
+    }))
+}
+
+fn parse_line(line: &[u8]) -> Result<Line, RestoreError> {
+    serde_json::from_slice(line).map_err(|e| RestoreError::Invalid(format!("invalid line in the backup: {}", e)))
+}
+
+/// Uploads an object with a request signed with AWS Signature Version 4.
+async fn put_object(config: &S3Config, key: &str, body: Vec<u8>) -> Result<()> {
+    let path = format!("/{}", key.split('/').map(uri_encode).collect::<Vec<_>>().join("/"));
+    let url = match &config.endpoint {
+        Some(endpoint) => format!("{}/{}{}", endpoint.trim_end_matches('/'), config.bucket, path),
+        None => format!("https://{}.s3.{}.amazonaws.com{}", config.bucket, config.region, path),
+    };
+    let url = reqwest::Url::parse(&url)?;
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => anyhow::bail!("the S3 endpoint '{}' has no host", url),
+    };
+
+    let now = chrono::Utc::now();
+    let (date, time) = (now.format("%Y%m%d").to_string(), now.format("%Y%m%dT%H%M%SZ").to_string());
+    let payload_hash = hex::encode(Sha256::digest(&body));
+    let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+    let canonical_request = format!("PUT\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+        url.path(), host, payload_hash, time, signed_headers, payload_hash);
+    let scope = format!("{}/{}/s3/aws4_request", date, config.region);
+    let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}", time, scope, hex::encode(Sha256::digest(canonical_request.as_bytes())));
+
+    let mut key = hmac(format!("AWS4{}", config.secret_access_key).as_bytes(), date.as_bytes());
+    for part in [config.region.as_str(), "s3", "aws4_request"] {
+        key = hmac(&key, part.as_bytes());
+    }
+    let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+    let authorization = format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        config.access_key_id, scope, signed_headers, signature);
+
+    let response = reqwest::Client::new().put(url)
+        .header("authorization", authorization)
+        .header("x-amz-content-sha256", payload_hash)
+        .header("x-amz-date", time)
+        .header("content-type", "application/x-ndjson")
+        .body(body)
+        .send().await?;
+    if !response.status().is_success() {
+        let status = response.status();
+        let error = response.text().await.unwrap_or_default();
+        anyhow::bail!("S3 responded with {}: {}", status, error);
+    }
+    Ok(())
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Percent-encodes a segment of an object's key as S3 expects in signed requests.
+fn uri_encode(segment: &str) -> String {
+    segment.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
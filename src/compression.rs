@@ -0,0 +1,46 @@
+use std::borrow::Cow;
+use std::io::Read;
+
+use thiserror::Error;
+
+/// Why a request body couldn't be decoded.
+#[derive(Error, Debug)]
+pub enum BodyError {
+    #[error("unsupported content encoding '{0}'")]
+    UnsupportedEncoding(String),
+    #[error("request body could not be decoded: {0}")]
+    Invalid(String),
+    #[error("request body is larger than the limit of {0} bytes")]
+    TooLarge(u64),
+}
+
+impl warp::reject::Reject for BodyError {}
+
+/// Decompresses a request body according to its `Content-Encoding`, which may be `gzip`, `zstd` or `identity`.
+///
+/// Decompressed bodies larger than `limit` are rejected, so that a small compressed body can't expand without bound.
+pub fn decode<'a>(encoding: Option<&str>, body: &'a [u8], limit: u64) -> Result<Cow<'a, [u8]>, BodyError> {
+    let encoding = encoding.map(str::trim).unwrap_or("identity");
+    if encoding.eq_ignore_ascii_case("identity") {
+        return Ok(Cow::Borrowed(body));
+    }
+
+    let decoded = if encoding.eq_ignore_ascii_case("gzip") {
+        read_limited(flate2::read::GzDecoder::new(body), limit)?
+    } else if encoding.eq_ignore_ascii_case("zstd") {
+        let decoder = zstd::stream::read::Decoder::new(body).map_err(|e| BodyError::Invalid(e.to_string()))?;
+        read_limited(decoder, limit)?
+    } else {
+        return Err(BodyError::UnsupportedEncoding(encoding.to_string()));
+    };
+    Ok(Cow::Owned(decoded))
+}
+
+fn read_limited(reader: impl Read, limit: u64) -> Result<Vec<u8>, BodyError> {
+    let mut decoded = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut decoded).map_err(|e| BodyError::Invalid(e.to_string()))?;
+    if decoded.len() as u64 > limit {
+        return Err(BodyError::TooLarge(limit));
+    }
+    Ok(decoded)
+}
@@ -1,16 +1,1214 @@
-use std::fs::File;
-use std::path::Path;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, RwLock};
 
+use anyhow::Context;
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use thiserror::Error;
 use rand::Rng;
 use rand::distributions::Alphanumeric;
 
+use crate::model::LeaderboardOrder;
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Config {
+    /// Which backend stats are stored in. `database_url` must point at a database of this type.
+    #[serde(default)]
+    pub database_type: DatabaseType,
     pub database_url: String,
     pub database_name: String,
+    /// How database operations are retried after transient errors, such as while MongoDB restarts.
+    #[serde(default)]
+    pub database_retry: DatabaseRetryConfig,
+    /// How many database operations are handled at once, each by its own actor, so that slow uploads don't hold up
+    /// reads.
+    #[serde(default = "default_database_actors")]
+    pub database_actors: usize,
     pub api_port: u16,
-    pub server_tokens: Vec<String>,
+    /// Address that the HTTP server listens on.
+    #[serde(default = "default_bind_address")]
+    pub bind_address: IpAddr,
+    /// Whether to serve reads only, rejecting requests that would change the database. Can also be set with the
+    /// `--read-only` flag.
+    #[serde(default)]
+    pub read_only: bool,
+    /// Largest request body accepted, in bytes.
+    #[serde(default = "default_max_body_bytes")]
+    pub max_body_bytes: u64,
+    /// How long to wait for open requests and in-flight uploads to finish when shutting down.
+    #[serde(default = "default_shutdown_timeout_secs")]
+    pub shutdown_timeout_secs: u64,
+    /// Directory that uploads are saved to when they can't be written to the database straight away, or `null` to
+    /// reject them instead.
+    #[serde(default = "default_spool_dir")]
+    pub spool_dir: Option<PathBuf>,
+    /// How long destructive admin operations hold their locks for if they aren't released, e.g. after a crash.
+    #[serde(default = "default_admin_lock_ttl_secs")]
+    pub admin_lock_ttl_secs: u64,
+    /// Discord webhook that alerts are posted to, e.g. when a corrupt stats document is quarantined.
+    #[serde(default)]
+    pub webhook_url: Option<String>,
+    /// Whether errors from handling requests are also posted to the webhook.
+    #[serde(default)]
+    pub webhook_server_errors: bool,
+    /// PEM certificate chain that HTTPS is served with, along with `tls_key_path`. HTTP is served if neither is set.
+    #[serde(default)]
+    pub tls_cert_path: Option<PathBuf>,
+    /// PEM private key of `tls_cert_path`.
+    #[serde(default)]
+    pub tls_key_path: Option<PathBuf>,
+    /// Tuning options for the HTTP server.
+    #[serde(default)]
+    pub http: HttpConfig,
+    /// The gRPC service that game servers can upload through instead of the HTTP API.
+    #[serde(default)]
+    pub grpc: GrpcConfig,
+    /// Which cross-origin requests browsers are allowed to make.
+    #[serde(default)]
+    pub cors: CorsConfig,
+    /// Export of request traces to an OpenTelemetry collector.
+    #[serde(default)]
+    pub telemetry: TelemetryConfig,
+    /// Tokens accepted in the `Authorization` header, each with the scopes that it grants.
+    #[serde(default)]
+    pub tokens: Vec<ApiToken>,
+    /// Checks on uploads that are signed with a token instead of sending it.
+    #[serde(default)]
+    pub signatures: SignatureConfig,
+    /// Namespaces that are hidden from unauthenticated requests.
+    #[serde(default)]
+    pub internal_namespaces: Vec<String>,
+    /// Maps old namespace names to the namespace that their stats are now stored in.
+    #[serde(default)]
+    pub namespace_aliases: HashMap<String, String>,
+    /// Namespaces whose player stats are also kept per season, so that they can be ranked within a season.
+    #[serde(default)]
+    pub seasonal_namespaces: Vec<String>,
+    /// Per-namespace maps of old stat keys to the key that they have been renamed to.
+    #[serde(default)]
+    pub stat_aliases: HashMap<String, HashMap<String, String>>,
+    /// Whether stat aliases are also applied to the keys of stats returned by the API.
+    #[serde(default)]
+    pub stat_aliases_on_read: bool,
+    /// How long hourly rollups of global stats are kept for, limiting the window of global stat deltas.
+    #[serde(default = "default_global_stats_rollup_retention_hours")]
+    pub global_stats_rollup_retention_hours: u32,
+    /// How long the IDs of applied bundles are remembered for, and so how long an upload can be retried for without
+    /// counting it twice.
+    #[serde(default = "default_bundle_id_retention_hours")]
+    pub bundle_id_retention_hours: u32,
+    /// Recording of accepted bundles in the append-only `bundle-log` collection.
+    #[serde(default)]
+    pub bundle_log: BundleLogConfig,
+    /// Recording of who uploaded each accepted bundle in the capped `upload-log` collection.
+    #[serde(default)]
+    pub upload_log: UploadLogConfig,
+    /// Applying uploaded bundles in the background rather than while the upload request waits.
+    #[serde(default)]
+    pub upload_queue: UploadQueueConfig,
+    /// Consuming stats bundles from a message queue, alongside uploads to the API.
+    #[serde(default)]
+    pub message_queue: MessageQueueConfig,
+    /// Recording of the values of player stats over time in the `stat-history` collection.
+    #[serde(default)]
+    pub stat_history: StatHistoryConfig,
+    /// Leaderboards that are periodically saved to the `leaderboard-snapshots` collection.
+    #[serde(default)]
+    pub leaderboard_snapshots: LeaderboardSnapshotConfig,
+    /// Limits on how many requests are handled at once.
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    /// Limits on how many requests each token or client address may make to a route.
+    #[serde(default)]
+    pub rate_limits: RateLimitConfig,
+    /// How long requests may take before they are abandoned.
+    #[serde(default)]
+    pub deadlines: DeadlineConfig,
+    /// How long public responses can be cached by clients and CDNs.
+    #[serde(default)]
+    pub cache: CacheConfig,
+    /// In-memory caching of leaderboards and global stats.
+    #[serde(default)]
+    pub result_cache: ResultCacheConfig,
+    /// Overrides for the schedules of background jobs, keyed by job name.
+    #[serde(default)]
+    pub jobs: HashMap<String, JobConfig>,
+    /// WASM modules that can change or reject uploaded bundles.
+    #[serde(default)]
+    pub wasm_plugins: WasmPluginConfig,
+    /// Paths of Rhai scripts that validate and derive stats, keyed by namespace.
+    #[serde(default)]
+    pub scripts: HashMap<String, PathBuf>,
+    /// Limits on the names and sizes of uploaded bundles.
+    #[serde(default)]
+    pub validation: ValidationConfig,
+    /// How match results change the Elo ratings of players.
+    #[serde(default)]
+    pub ratings: RatingsConfig,
+    /// Looking up the usernames of nameless players from the Mojang session API.
+    #[serde(default)]
+    pub mojang: MojangConfig,
+    /// Where `POST /admin/backup` can upload backups to.
+    #[serde(default)]
+    pub backup: BackupConfig,
+    /// Per-namespace, per-stat rules applied to uploads.
+    #[serde(default)]
+    pub stat_metadata: HashMap<String, HashMap<String, StatMetadata>>,
+    /// The config as it was last [reloaded](Config::reload), which every clone of this config shares.
+    #[serde(skip)]
+    pub reloaded: ReloadedConfig,
+}
+
+/// The problems that [Config::validate] found with a config.
+#[derive(Error, Debug)]
+#[error("the config is invalid:{}", .problems.iter().map(|problem| format!("\n  - {}", problem)).collect::<String>())]
+pub struct InvalidConfig {
+    pub problems: Vec<String>,
+}
+
+/// Checks the parts of a MongoDB connection string that can be checked without connecting: its scheme, and that it
+/// names at least one host.
+fn check_mongodb_url(url: &str) -> Result<(), String> {
+    let (srv, rest) = if let Some(rest) = url.strip_prefix("mongodb+srv://") {
+        (true, rest)
+    } else if let Some(rest) = url.strip_prefix("mongodb://") {
+        (false, rest)
+    } else {
+        return Err("it must start with mongodb:// or mongodb+srv://".to_string());
+    };
+    let hosts = rest.split(['/', '?']).next().unwrap_or_default();
+    let hosts = hosts.rsplit_once('@').map_or(hosts, |(_, hosts)| hosts);
+    if hosts.split(',').any(str::is_empty) {
+        return Err("it must name at least one host, e.g. mongodb://localhost/".to_string());
+    }
+    if srv && (hosts.contains(',') || hosts.contains(':')) {
+        return Err("mongodb+srv:// connection strings must name a single host without a port".to_string());
+    }
+    Ok(())
+}
+
+/// Holds the config as it was last reloaded, of which only the tokens, rate limits and webhook are used.
+#[derive(Clone, Debug, Default)]
+pub struct ReloadedConfig(Arc<RwLock<Option<Arc<Config>>>>);
+
+impl Config {
+    /// Resolves a namespace alias to the namespace that its stats are stored in.
+    pub fn canonical_namespace<'a>(&'a self, namespace: &'a str) -> &'a str {
+        self.namespace_aliases.get(namespace).map(String::as_str).unwrap_or(namespace)
+    }
+
+    /// Resolves a stat key alias to the key that the stat is now stored under.
+    pub fn canonical_stat_name<'a>(&'a self, namespace: &str, stat: &'a str) -> &'a str {
+        self.stat_aliases.get(namespace)
+            .and_then(|aliases| aliases.get(stat))
+            .map(String::as_str)
+            .unwrap_or(stat)
+    }
+
+    pub fn stat_metadata(&self, namespace: &str, stat: &str) -> Option<&StatMetadata> {
+        self.stat_metadata.get(namespace)?.get(stat)
+    }
+
+    /// The upper bounds of the buckets of a histogram stat, in ascending order.
+    pub fn histogram_buckets(&self, namespace: &str, stat: &str) -> Vec<f64> {
+        let mut buckets = self.stat_metadata(namespace, stat)
+            .map(|metadata| metadata.buckets.clone())
+            .unwrap_or_default();
+        buckets.sort_by(f64::total_cmp);
+        buckets.dedup();
+        buckets
+    }
+
+    /// Finds the configured token sent in an `Authorization` header.
+    pub fn token(&self, token: &str) -> Option<ApiToken> {
+        self.reloadable(|config| config.tokens.iter().find(|candidate| candidate.token == token).cloned())
+    }
+
+    /// Finds the configured token that a signed request names in its `X-Signature-Key` header.
+    pub fn token_by_name(&self, name: &str) -> Option<ApiToken> {
+        self.reloadable(|config| config.tokens.iter().find(|candidate| candidate.name == name).cloned())
+    }
+
+    /// The rate limit of a route, if it has one.
+    pub fn rate_limit(&self, route: &str) -> Option<RateLimit> {
+        self.reloadable(|config| config.rate_limits.routes.get(route).cloned())
+    }
+
+    pub fn trust_forwarded_for(&self) -> bool {
+        self.reloadable(|config| config.rate_limits.trust_forwarded_for)
+    }
+
+    pub fn webhook_url(&self) -> Option<String> {
+        self.reloadable(|config| config.webhook_url.clone())
+    }
+
+    pub fn webhook_server_errors(&self) -> bool {
+        self.reloadable(|config| config.webhook_server_errors)
+    }
+
+    /// Checks the options that would otherwise only fail once they are used, or not at all, reporting every problem
+    /// at once.
+    pub fn validate(&self) -> Result<(), InvalidConfig> {
+        let mut problems = Vec::new();
+
+        if self.tokens.is_empty() {
+            problems.push("`tokens` is empty, so no request could upload stats or use the admin API; add a token with the scopes it needs".to_string());
+        }
+        let mut names = HashSet::new();
+        let mut secrets = HashSet::new();
+        for token in &self.tokens {
+            if token.name.is_empty() {
+                problems.push("a token has an empty `name`, which identifies it in logs and signed requests".to_string());
+            } else if !names.insert(&token.name) {
+                problems.push(format!("more than one token is named '{}'; names must be unique", token.name));
+            }
+            if token.token.is_empty() {
+                problems.push(format!("token '{}' has an empty `token`, which requests would authorize with", token.name));
+            } else if !secrets.insert(&token.token) {
+                problems.push(format!("token '{}' has the same `token` as another one, so requests can't tell them apart", token.name));
+            }
+        }
+
+        match self.database_type {
+            DatabaseType::Mongodb => {
+                if let Err(e) = check_mongodb_url(&self.database_url) {
+                    problems.push(format!("`database_url` isn't a valid MongoDB connection string: {}", e));
+                }
+                if self.database_name.is_empty() {
+                    problems.push("`database_name` is empty; set it to the MongoDB database that stats are stored in".to_string());
+                }
+            }
+            DatabaseType::Postgres => {
+                let valid_scheme = ["postgres://", "postgresql://"].iter().any(|scheme| self.database_url.starts_with(scheme));
+                if !valid_scheme {
+                    problems.push("`database_url` must start with postgres:// when `database_type` is postgres".to_string());
+                } else if let Err(e) = sqlx::postgres::PgConnectOptions::from_str(&self.database_url) {
+                    problems.push(format!("`database_url` isn't a valid PostgreSQL connection URL: {}", e));
+                }
+            }
+            DatabaseType::Memory => {}
+        }
+        if self.database_actors == 0 {
+            problems.push("`database_actors` must be at least 1".to_string());
+        }
+
+        if self.api_port == 0 {
+            problems.push("`api_port` must be between 1 and 65535".to_string());
+        }
+        if self.grpc.enabled && self.grpc.port == 0 {
+            problems.push("`grpc.port` must be between 1 and 65535".to_string());
+        }
+        if self.grpc.enabled && self.grpc.port == self.api_port {
+            problems.push(format!("`grpc.port` and `api_port` are both {}; the gRPC service needs a port of its own", self.api_port));
+        }
+        if self.tls_cert_path.is_some() != self.tls_key_path.is_some() {
+            problems.push("`tls_cert_path` and `tls_key_path` must be set together".to_string());
+        }
+        if self.max_body_bytes == 0 {
+            problems.push("`max_body_bytes` must be at least 1".to_string());
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidConfig { problems })
+        }
+    }
+
+    /// Reads the settings that can be changed without restarting from the config that was last reloaded, or from
+    /// this config if it hasn't been.
+    fn reloadable<T>(&self, read: impl FnOnce(&Config) -> T) -> T {
+        match &*self.reloaded.0.read().unwrap() {
+            Some(reloaded) => read(reloaded),
+            None => read(self),
+        }
+    }
+
+    /// Reads the config file at `path` again, and switches every clone of this config over to its tokens, rate limits
+    /// and webhook. The current settings are kept if it can't be read.
+    pub fn reload(&self, path: &Path) -> anyhow::Result<()> {
+        let mut reloaded = read(path)?;
+        apply_env_overrides(&mut reloaded)?;
+        reloaded.validate()?;
+        self.reload_from(reloaded);
+        Ok(())
+    }
+
+    /// Switches every clone of this config over to the tokens, rate limits and webhook of `reloaded`, warning if it
+    /// also changes settings that are only read when the backend starts.
+    pub fn reload_from(&self, reloaded: Config) {
+        let mut unchanged = reloaded.clone();
+        self.reloadable(|current| {
+            unchanged.tokens = current.tokens.clone();
+            unchanged.rate_limits = current.rate_limits.clone();
+            unchanged.webhook_url = current.webhook_url.clone();
+            unchanged.webhook_server_errors = current.webhook_server_errors;
+        });
+        // `--read-only` puts the backend in read-only mode whatever the file says.
+        unchanged.read_only |= self.read_only;
+        if serde_json::to_value(&unchanged).ok() != serde_json::to_value(self).ok() {
+            log::warn!("The reloaded config changes settings other than tokens, rate limits and the webhook, which only take effect after a restart");
+        }
+        *self.reloaded.0.write().unwrap() = Some(Arc::new(reloaded));
+    }
+
+    /// Reloads the config from the file at `path` whenever the process receives `SIGHUP`.
+    pub fn reload_on_sighup(&self, path: PathBuf) -> anyhow::Result<()> {
+        #[cfg(unix)]
+        {
+            let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+            let config = self.clone();
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    match config.reload(&path) {
+                        Ok(()) => log::info!("Reloaded tokens, rate limits and webhook from {}", path.display()),
+                        Err(e) => log::error!("Failed to reload {}, still using the previous settings: {:#}", path.display(), e),
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ApiToken {
+    /// Identifies the token in logs, e.g. by the server that it was given to.
+    pub name: String,
+    pub token: String,
+    pub scopes: Vec<TokenScope>,
+    /// Namespaces that the token may write to, by their canonical names, so that a game server can't write into the
+    /// namespace of another game. Every namespace may be written to if this is empty.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub namespaces: Vec<String>,
+}
+
+impl ApiToken {
+    pub fn has_scope(&self, scope: TokenScope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// Whether the token may write to a namespace, given by its canonical name.
+    pub fn can_write_namespace(&self, namespace: &str) -> bool {
+        self.namespaces.is_empty() || self.namespaces.iter().any(|allowed| allowed == namespace)
+    }
+}
+
+/// A permission granted by a token.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Uploading stats bundles.
+    UploadStats,
+    /// Recording completed games.
+    UploadGames,
+    /// Updating player profiles.
+    UpdateProfiles,
+    /// Registering the display names, units and descriptions of stats.
+    UpdateStatMetadata,
+    /// Registering the display names and descriptions of achievements.
+    UpdateAchievements,
+    /// Seeing private players and internal namespaces in responses from read endpoints.
+    ReadPrivate,
+    /// Using the `/admin` endpoints.
+    Admin,
+}
+
+/// The scopes of a game server's token, given to the default token and to tokens from the `server_tokens` list of
+/// older configs.
+const SERVER_SCOPES: &[TokenScope] = &[TokenScope::UploadStats, TokenScope::UploadGames, TokenScope::UpdateProfiles];
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HttpConfig {
+    /// Whether HTTP/1 connections are kept open between requests.
+    #[serde(default = "default_true")]
+    pub keep_alive: bool,
+    /// Idle time in seconds before TCP keep-alive probes are sent on a connection.
+    #[serde(default)]
+    pub tcp_keepalive_secs: Option<u64>,
+    /// Whether HTTP/2 connections are accepted alongside HTTP/1.
+    #[serde(default = "default_true")]
+    pub http2: bool,
+    /// Interval in seconds between HTTP/2 keep-alive pings. Pings are not sent if unset.
+    #[serde(default)]
+    pub http2_keepalive_interval_secs: Option<u64>,
+    /// How long in seconds to wait for a keep-alive ping to be acknowledged before closing the connection.
+    #[serde(default = "default_http2_keepalive_timeout_secs")]
+    pub http2_keepalive_timeout_secs: u64,
+    /// Maximum number of open connections. Further connections are not accepted until one closes.
+    #[serde(default)]
+    pub max_connections: Option<usize>,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive: true,
+            tcp_keepalive_secs: None,
+            http2: true,
+            http2_keepalive_interval_secs: None,
+            http2_keepalive_timeout_secs: default_http2_keepalive_timeout_secs(),
+            max_connections: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GrpcConfig {
+    /// Whether the gRPC service is served. It is only available if the backend was built with the `grpc` feature.
+    #[serde(default)]
+    pub enabled: bool,
+    /// Port that the gRPC service listens on, on the same `bind_address` as the HTTP API.
+    #[serde(default = "default_grpc_port")]
+    pub port: u16,
+}
+
+impl Default for GrpcConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            port: default_grpc_port(),
+        }
+    }
+}
+
+fn default_grpc_port() -> u16 {
+    3031
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CorsConfig {
+    /// Origins that pages may make requests from, such as `https://nucleoid.xyz`, or `*` to allow any origin.
+    #[serde(default = "default_cors_allowed_origins")]
+    pub allowed_origins: Vec<String>,
+    /// Methods that cross-origin requests may use.
+    #[serde(default = "default_cors_allowed_methods")]
+    pub allowed_methods: Vec<String>,
+    /// Headers that cross-origin requests may send. Tokens can only be sent from other origins if `authorization`
+    /// is included.
+    #[serde(default = "default_cors_allowed_headers")]
+    pub allowed_headers: Vec<String>,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        Self {
+            allowed_origins: default_cors_allowed_origins(),
+            allowed_methods: default_cors_allowed_methods(),
+            allowed_headers: default_cors_allowed_headers(),
+        }
+    }
+}
+
+fn default_cors_allowed_origins() -> Vec<String> {
+    vec!["*".to_string()]
+}
+
+fn default_cors_allowed_methods() -> Vec<String> {
+    vec!["GET".to_string()]
+}
+
+fn default_cors_allowed_headers() -> Vec<String> {
+    vec!["content-type".to_string()]
+}
+
+fn default_bind_address() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
+fn default_max_body_bytes() -> u64 {
+    4 * 1024 * 1024
+}
+
+fn default_shutdown_timeout_secs() -> u64 {
+    30
+}
+
+fn default_database_actors() -> usize {
+    8
+}
+
+fn default_spool_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("spool"))
+}
+
+fn default_admin_lock_ttl_secs() -> u64 {
+    60 * 60
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_http2_keepalive_timeout_secs() -> u64 {
+    20
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    /// Maximum number of requests handled at once across all routes.
+    #[serde(default)]
+    pub global: Option<usize>,
+    /// Maximum number of requests handled at once for individual routes, keyed by route name.
+    #[serde(default)]
+    pub routes: HashMap<String, usize>,
+    /// How long in milliseconds a request waits for a free slot before it is rejected with a 503.
+    #[serde(default)]
+    pub queue_timeout_ms: u64,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Limits for individual routes, keyed by route name.
+    #[serde(default)]
+    pub routes: HashMap<String, RateLimit>,
+    /// Whether client addresses are taken from the `X-Forwarded-For` header. Only enable this behind a proxy that
+    /// sets it, as clients can otherwise pick their own address.
+    #[serde(default)]
+    pub trust_forwarded_for: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    /// What requests are counted by.
+    pub per: RateLimitKey,
+    /// How many requests may be made within each window.
+    pub requests: u32,
+    #[serde(default = "default_rate_limit_window_secs")]
+    pub window_secs: u64,
+}
+
+fn default_rate_limit_window_secs() -> u64 {
+    60
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+    /// The token in the `Authorization` header, falling back to the client's address for requests without a known
+    /// token.
+    Token,
+    /// The client's address.
+    Ip,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct JobConfig {
+    /// Cron expression for when the job runs, including seconds, e.g. `0 0 * * * *` for every hour. Defaults to the
+    /// job's own schedule.
+    #[serde(default)]
+    pub schedule: Option<String>,
+    /// Largest random delay in seconds added to each run, so that jobs scheduled at the same time are spread out.
+    #[serde(default)]
+    pub jitter_secs: u64,
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    /// OTLP/HTTP endpoint of the collector that traces are sent to, e.g. `http://localhost:4318`, or `null` to not
+    /// export traces.
+    #[serde(default)]
+    pub otlp_endpoint: Option<String>,
+    /// Name of the service that traces are reported under.
+    #[serde(default = "default_telemetry_service_name")]
+    pub service_name: String,
+    /// Fraction of requests that are traced, from 0 to 1.
+    #[serde(default = "default_telemetry_sample_ratio")]
+    pub sample_ratio: f64,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: default_telemetry_service_name(),
+            sample_ratio: default_telemetry_sample_ratio(),
+        }
+    }
+}
+
+fn default_telemetry_service_name() -> String {
+    "nucleoid-persistence-backend".to_string()
+}
+
+fn default_telemetry_sample_ratio() -> f64 {
+    1.0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignatureConfig {
+    /// How far in seconds the timestamp of a signed request may be from the backend's clock. Nonces are remembered for
+    /// this long, so a signed request can't be replayed until it would be rejected as too old.
+    #[serde(default = "default_signature_max_skew_secs")]
+    pub max_skew_secs: u64,
+}
+
+impl Default for SignatureConfig {
+    fn default() -> Self {
+        Self {
+            max_skew_secs: default_signature_max_skew_secs(),
+        }
+    }
+}
+
+fn default_signature_max_skew_secs() -> u64 {
+    300
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ValidationConfig {
+    /// Longest namespace or stat name accepted.
+    #[serde(default = "default_max_name_length")]
+    pub max_name_length: usize,
+    #[serde(default = "default_max_players_per_bundle")]
+    pub max_players_per_bundle: usize,
+    /// Most stats accepted in one bundle, counting each player's stats and the global stats.
+    #[serde(default = "default_max_stats_per_bundle")]
+    pub max_stats_per_bundle: usize,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        Self {
+            max_name_length: default_max_name_length(),
+            max_players_per_bundle: default_max_players_per_bundle(),
+            max_stats_per_bundle: default_max_stats_per_bundle(),
+        }
+    }
+}
+
+fn default_max_name_length() -> usize {
+    64
+}
+
+fn default_max_players_per_bundle() -> usize {
+    1000
+}
+
+fn default_max_stats_per_bundle() -> usize {
+    20_000
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BundleLogConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// How many days logged bundles are kept for, or `null` to keep them forever.
+    #[serde(default = "default_bundle_log_retention_days")]
+    pub retention_days: Option<u32>,
+}
+
+impl Default for BundleLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: default_bundle_log_retention_days(),
+        }
+    }
+}
+
+fn default_bundle_log_retention_days() -> Option<u32> {
+    Some(90)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UploadLogConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// How many uploads are kept, after which the oldest are dropped as new ones are recorded.
+    #[serde(default = "default_upload_log_max_entries")]
+    pub max_entries: u64,
+}
+
+impl Default for UploadLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            max_entries: default_upload_log_max_entries(),
+        }
+    }
+}
+
+fn default_upload_log_max_entries() -> u64 {
+    100_000
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct UploadQueueConfig {
+    /// Whether uploads are queued and answered with a `202 Accepted`. Otherwise, uploads are applied before they are
+    /// responded to.
+    #[serde(default)]
+    pub enabled: bool,
+    /// How many bundles can wait in the queue, after which uploads are spooled or rejected as if the backend were busy.
+    #[serde(default = "default_upload_queue_capacity")]
+    pub capacity: usize,
+    /// How many bundles are applied at once.
+    #[serde(default = "default_upload_queue_workers")]
+    pub workers: usize,
+    /// How long the status of a finished upload can be checked for.
+    #[serde(default = "default_upload_status_retention_secs")]
+    pub status_retention_secs: u64,
+}
+
+impl Default for UploadQueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: default_upload_queue_capacity(),
+            workers: default_upload_queue_workers(),
+            status_retention_secs: default_upload_status_retention_secs(),
+        }
+    }
+}
+
+fn default_upload_queue_capacity() -> usize {
+    1000
+}
+
+fn default_upload_queue_workers() -> usize {
+    4
+}
+
+fn default_upload_status_retention_secs() -> u64 {
+    60 * 60
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MessageQueueConfig {
+    /// Whether bundles are consumed from the message queue.
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub kind: MessageQueueKind,
+    /// The URL of the broker, such as `amqp://localhost:5672/%2f` or `nats://localhost:4222`.
+    #[serde(default)]
+    pub url: String,
+    /// The AMQP queue, or the NATS subject, that bundles are published to.
+    #[serde(default = "default_message_queue_subject")]
+    pub subject: String,
+    /// The JetStream stream that holds the subject's messages, which is created if it doesn't exist. Only used by NATS.
+    #[serde(default = "default_message_queue_stream")]
+    pub stream: String,
+    /// The name of the token, in `tokens`, that consumed bundles are uploaded as. It needs the `upload_stats` scope.
+    #[serde(default)]
+    pub token: String,
+    /// How many messages may be delivered before earlier ones are acknowledged.
+    #[serde(default = "default_message_queue_prefetch")]
+    pub prefetch: u16,
+    /// How long to wait before retrying a bundle that couldn't be written, or reconnecting to the broker.
+    #[serde(default = "default_message_queue_retry_delay_secs")]
+    pub retry_delay_secs: u64,
+}
+
+impl Default for MessageQueueConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            kind: MessageQueueKind::default(),
+            url: String::new(),
+            subject: default_message_queue_subject(),
+            stream: default_message_queue_stream(),
+            token: String::new(),
+            prefetch: default_message_queue_prefetch(),
+            retry_delay_secs: default_message_queue_retry_delay_secs(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageQueueKind {
+    /// An AMQP 0.9.1 broker such as RabbitMQ. Needs the `amqp` feature.
+    #[default]
+    Amqp,
+    /// A NATS server with JetStream enabled. Needs the `nats` feature.
+    Nats,
+}
+
+fn default_message_queue_subject() -> String {
+    "nucleoid.stats".to_string()
+}
+
+fn default_message_queue_stream() -> String {
+    "NUCLEOID_STATS".to_string()
+}
+
+fn default_message_queue_prefetch() -> u16 {
+    16
+}
+
+fn default_message_queue_retry_delay_secs() -> u64 {
+    5
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RatingsConfig {
+    /// The rating that players start with in each namespace.
+    #[serde(default = "default_initial_rating")]
+    pub initial_rating: f64,
+    /// The most that a player's rating can change by in one match.
+    #[serde(default = "default_k_factor")]
+    pub k_factor: f64,
+}
+
+impl Default for RatingsConfig {
+    fn default() -> Self {
+        Self {
+            initial_rating: default_initial_rating(),
+            k_factor: default_k_factor(),
+        }
+    }
+}
+
+fn default_initial_rating() -> f64 {
+    1000.0
+}
+
+fn default_k_factor() -> f64 {
+    32.0
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MojangConfig {
+    /// Whether the usernames of players whose profiles have none, such as those only created by stats uploads, are
+    /// looked up when their profile is read.
+    #[serde(default)]
+    pub enabled: bool,
+    /// The profile endpoint of the session API, which the player's UUID is appended to.
+    #[serde(default = "default_mojang_url")]
+    pub url: String,
+    /// How long looked-up usernames, and players that Mojang doesn't know, are remembered for.
+    #[serde(default = "default_mojang_cache_ttl_secs")]
+    pub cache_ttl_secs: u64,
+    /// How many lookups may be made per minute. Players are left nameless while the limit is used up.
+    #[serde(default = "default_mojang_requests_per_minute")]
+    pub requests_per_minute: u32,
+    /// How long to wait for a lookup before leaving the player nameless.
+    #[serde(default = "default_mojang_timeout_ms")]
+    pub timeout_ms: u64,
+}
+
+impl Default for MojangConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: default_mojang_url(),
+            cache_ttl_secs: default_mojang_cache_ttl_secs(),
+            requests_per_minute: default_mojang_requests_per_minute(),
+            timeout_ms: default_mojang_timeout_ms(),
+        }
+    }
+}
+
+fn default_mojang_url() -> String {
+    "https://sessionserver.mojang.com/session/minecraft/profile".to_string()
+}
+
+fn default_mojang_cache_ttl_secs() -> u64 {
+    6 * 60 * 60
+}
+
+fn default_mojang_requests_per_minute() -> u32 {
+    100
+}
+
+fn default_mojang_timeout_ms() -> u64 {
+    2000
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct BackupConfig {
+    /// The S3 bucket that backups are uploaded to when asked to, or `null` to only stream them in the response.
+    #[serde(default)]
+    pub s3: Option<S3Config>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// URL of an S3-compatible service, such as MinIO, that is used instead of AWS. Buckets are addressed by path
+    /// rather than by host name on it.
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Prepended to the key of each backup, e.g. `nucleoid/`.
+    #[serde(default)]
+    pub prefix: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StatHistoryConfig {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// How many days recorded values are kept for, or `null` to keep them forever.
+    #[serde(default = "default_stat_history_retention_days")]
+    pub retention_days: Option<u32>,
+}
+
+impl Default for StatHistoryConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            retention_days: default_stat_history_retention_days(),
+        }
+    }
+}
+
+fn default_stat_history_retention_days() -> Option<u32> {
+    Some(365)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LeaderboardSnapshotConfig {
+    /// The leaderboards to save on each run of the `snapshot_leaderboards` job.
+    #[serde(default)]
+    pub leaderboards: Vec<SnapshotLeaderboard>,
+    /// How many players are saved of each leaderboard.
+    #[serde(default = "default_snapshot_size")]
+    pub size: i64,
+    /// How many days snapshots are kept for, or `null` to keep them forever.
+    #[serde(default = "default_snapshot_retention_days")]
+    pub retention_days: Option<u32>,
+}
+
+impl Default for LeaderboardSnapshotConfig {
+    fn default() -> Self {
+        Self {
+            leaderboards: Vec::new(),
+            size: default_snapshot_size(),
+            retention_days: default_snapshot_retention_days(),
+        }
+    }
+}
+
+/// A leaderboard to save snapshots of.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SnapshotLeaderboard {
+    pub namespace: String,
+    pub stat: String,
+    #[serde(default)]
+    pub order: LeaderboardOrder,
+}
+
+fn default_snapshot_size() -> i64 {
+    100
+}
+
+fn default_snapshot_retention_days() -> Option<u32> {
+    Some(365)
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WasmPluginConfig {
+    /// Paths of the modules to load, run in order on every upload.
+    #[serde(default)]
+    pub paths: Vec<PathBuf>,
+    /// Number of instructions that a plugin may execute for each bundle before it is stopped.
+    #[serde(default = "default_wasm_fuel")]
+    pub fuel: u64,
+    /// Largest amount of memory in bytes that a plugin may use for each bundle.
+    #[serde(default = "default_wasm_memory_bytes")]
+    pub memory_bytes: usize,
+}
+
+impl Default for WasmPluginConfig {
+    fn default() -> Self {
+        Self {
+            paths: Vec::new(),
+            fuel: default_wasm_fuel(),
+            memory_bytes: default_wasm_memory_bytes(),
+        }
+    }
+}
+
+fn default_wasm_fuel() -> u64 {
+    10_000_000
+}
+
+fn default_wasm_memory_bytes() -> usize {
+    16 * 1024 * 1024
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DeadlineConfig {
+    /// How long in milliseconds a request may take, including any database work, before a 504 is returned.
+    #[serde(default = "default_deadline_ms")]
+    pub default_ms: u64,
+    /// Deadlines for individual routes, keyed by route name.
+    #[serde(default)]
+    pub routes: HashMap<String, u64>,
+}
+
+impl Default for DeadlineConfig {
+    fn default() -> Self {
+        Self {
+            default_ms: default_deadline_ms(),
+            routes: HashMap::new(),
+        }
+    }
+}
+
+fn default_deadline_ms() -> u64 {
+    30_000
+}
+
+/// Max age in seconds of cacheable responses for each class of route. Responses are not cacheable if unset.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub profiles: Option<u64>,
+    #[serde(default)]
+    pub stats: Option<u64>,
+    #[serde(default)]
+    pub leaderboards: Option<u64>,
+    #[serde(default)]
+    pub global_stats: Option<u64>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DatabaseRetryConfig {
+    /// Total number of attempts at an operation, including the first.
+    #[serde(default = "default_database_max_attempts")]
+    pub max_attempts: u32,
+    /// Delay in milliseconds before the first retry, which doubles after each attempt.
+    #[serde(default = "default_database_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    /// Longest delay in milliseconds between attempts.
+    #[serde(default = "default_database_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// How long in milliseconds an attempt waits for a reachable server before failing, unless `database_url` sets
+    /// `serverSelectionTimeoutMS`.
+    #[serde(default = "default_server_selection_timeout_ms")]
+    pub server_selection_timeout_ms: u64,
+}
+
+impl Default for DatabaseRetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_database_max_attempts(),
+            initial_backoff_ms: default_database_initial_backoff_ms(),
+            max_backoff_ms: default_database_max_backoff_ms(),
+            server_selection_timeout_ms: default_server_selection_timeout_ms(),
+        }
+    }
+}
+
+fn default_database_max_attempts() -> u32 {
+    5
+}
+
+fn default_database_initial_backoff_ms() -> u64 {
+    100
+}
+
+fn default_database_max_backoff_ms() -> u64 {
+    2000
+}
+
+fn default_server_selection_timeout_ms() -> u64 {
+    3000
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ResultCacheConfig {
+    /// How long in seconds results are cached for if no bundles are applied to their namespace, or `null` to disable
+    /// the cache.
+    #[serde(default)]
+    pub ttl_secs: Option<u64>,
+    /// Most results held at once.
+    #[serde(default = "default_result_cache_max_entries")]
+    pub max_entries: usize,
+    /// How long in seconds summaries of a stat across players are cached for, or `null` to not cache them. Unlike other
+    /// results, they are kept when bundles are applied to their namespace.
+    #[serde(default = "default_stat_summary_ttl_secs")]
+    pub stat_summary_ttl_secs: Option<u64>,
+}
+
+impl Default for ResultCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_secs: None,
+            max_entries: default_result_cache_max_entries(),
+            stat_summary_ttl_secs: default_stat_summary_ttl_secs(),
+        }
+    }
+}
+
+fn default_result_cache_max_entries() -> usize {
+    10_000
+}
+
+fn default_stat_summary_ttl_secs() -> Option<u64> {
+    Some(5 * 60)
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StatMetadata {
+    /// Smallest value accepted in a single upload of this stat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Largest value accepted in a single upload of this stat.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// How values of this stat are stored in the database.
+    #[serde(default)]
+    pub storage: StatStorage,
+    /// Set when the stat is being phased out; uploads to it are logged and counted.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deprecated: Option<StatDeprecation>,
+    /// Uploads larger than this are accepted, but logged and counted as anomalies.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub anomaly_above: Option<f64>,
+    /// Most that a single upload may add to a player's or the global total of this stat, such as wins in one game.
+    /// Uploads that add more are quarantined as suspicious instead of being applied.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_increment: Option<f64>,
+    /// Totals that are logged and counted when a player's total first reaches them.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub milestones: Vec<f64>,
+    /// Upper bounds of the buckets that a histogram stat counts its values in. Values at or above the last bound are
+    /// counted in one more bucket.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub buckets: Vec<f64>,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct StatDeprecation {
+    /// The stat that replaces this one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub successor: Option<String>,
+    /// Whether uploads to this stat should be stored under the successor instead.
+    #[serde(default)]
+    pub redirect: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DatabaseType {
+    #[default]
+    Mongodb,
+    /// PostgreSQL, whose tables are created on startup unless in read-only mode.
+    Postgres,
+    /// Kept in memory and lost on restart, for tests and local development. `database_url` is ignored.
+    Memory,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatStorage {
+    /// Stored as the BSON type matching the uploaded stat type.
+    #[default]
+    Native,
+    /// Stored as a BSON Decimal128, for stats where floating point drift over a long-lived total matters.
+    Decimal128,
 }
 
 impl Default for Config {
@@ -22,25 +1220,243 @@ impl Default for Config {
             .collect();
 
         Self {
+            database_type: DatabaseType::default(),
             database_url: "mongodb://localhost/".to_string(),
             database_name: "nucleoid_players".to_string(),
+            database_retry: DatabaseRetryConfig::default(),
+            database_actors: default_database_actors(),
             api_port: 3030,
-            server_tokens: vec![random_token],
+            bind_address: default_bind_address(),
+            read_only: false,
+            max_body_bytes: default_max_body_bytes(),
+            shutdown_timeout_secs: default_shutdown_timeout_secs(),
+            spool_dir: default_spool_dir(),
+            admin_lock_ttl_secs: default_admin_lock_ttl_secs(),
+            webhook_url: None,
+            webhook_server_errors: false,
+            tls_cert_path: None,
+            tls_key_path: None,
+            http: HttpConfig::default(),
+            grpc: GrpcConfig::default(),
+            cors: CorsConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            tokens: vec![ApiToken {
+                name: "default".to_string(),
+                token: random_token,
+                scopes: SERVER_SCOPES.to_vec(),
+                namespaces: Vec::new(),
+            }],
+            signatures: SignatureConfig::default(),
+            internal_namespaces: Vec::new(),
+            namespace_aliases: HashMap::new(),
+            seasonal_namespaces: Vec::new(),
+            stat_aliases: HashMap::new(),
+            stat_aliases_on_read: false,
+            stat_metadata: HashMap::new(),
+            global_stats_rollup_retention_hours: default_global_stats_rollup_retention_hours(),
+            bundle_id_retention_hours: default_bundle_id_retention_hours(),
+            bundle_log: BundleLogConfig::default(),
+            upload_log: UploadLogConfig::default(),
+            upload_queue: UploadQueueConfig::default(),
+            message_queue: MessageQueueConfig::default(),
+            stat_history: StatHistoryConfig::default(),
+            leaderboard_snapshots: LeaderboardSnapshotConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            rate_limits: RateLimitConfig::default(),
+            deadlines: DeadlineConfig::default(),
+            cache: CacheConfig::default(),
+            result_cache: ResultCacheConfig::default(),
+            jobs: HashMap::new(),
+            wasm_plugins: WasmPluginConfig::default(),
+            scripts: HashMap::new(),
+            validation: ValidationConfig::default(),
+            ratings: RatingsConfig::default(),
+            mojang: MojangConfig::default(),
+            backup: BackupConfig::default(),
+            reloaded: ReloadedConfig::default(),
         }
     }
 }
 
-pub(super) fn load() -> Config {
-    let path = Path::new("config.json");
-    if path.exists() {
-        let mut file = File::open(path).unwrap();
-        serde_json::from_reader(&mut file).unwrap()
+fn default_global_stats_rollup_retention_hours() -> u32 {
+    7 * 24
+}
+
+fn default_bundle_id_retention_hours() -> u32 {
+    7 * 24
+}
+
+/// The files that the config is looked for in, in the working directory, unless another is given with `--config`.
+/// The first is created if none of them exist.
+const CONFIG_PATHS: [&str; 4] = ["config.json", "config.toml", "config.yaml", "config.yml"];
+
+/// The formats that config files can be written in, which are told apart by their extension.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Format {
+    Json,
+    Toml,
+    Yaml,
+}
+
+impl Format {
+    fn of(path: &Path) -> anyhow::Result<Format> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => Ok(Format::Json),
+            Some("toml") => Ok(Format::Toml),
+            Some("yaml") | Some("yml") => Ok(Format::Yaml),
+            _ => anyhow::bail!("{} isn't a .json, .toml, .yaml or .yml file, so its format isn't known", path.display()),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Format::Json => "JSON",
+            Format::Toml => "TOML",
+            Format::Yaml => "YAML",
+        }
+    }
+
+    fn parse<T: DeserializeOwned>(self, text: &str) -> anyhow::Result<T> {
+        Ok(match self {
+            Format::Json => serde_json::from_str(text)?,
+            Format::Toml => toml::from_str(text)?,
+            Format::Yaml => serde_yaml::from_str(text)?,
+        })
+    }
+
+    fn write(self, config: &Config) -> anyhow::Result<String> {
+        Ok(match self {
+            Format::Json => serde_json::to_string_pretty(config)?,
+            Format::Toml => toml::to_string_pretty(config)?,
+            Format::Yaml => serde_yaml::to_string(config)?,
+        })
+    }
+}
+
+/// Finds the config file: the one given with `--config`, or otherwise the one of [CONFIG_PATHS] that exists. Fails if
+/// more than one of them does, so that the one being edited is never silently ignored.
+pub fn locate(path: Option<PathBuf>) -> anyhow::Result<PathBuf> {
+    if let Some(path) = path {
+        return Ok(path);
+    }
+    let existing: Vec<&str> = CONFIG_PATHS.iter().copied().filter(|path| Path::new(path).exists()).collect();
+    match existing.as_slice() {
+        [] => Ok(PathBuf::from(CONFIG_PATHS[0])),
+        [path] => Ok(PathBuf::from(path)),
+        paths => anyhow::bail!("found more than one config file ({}); remove all but one, or pick one with --config", paths.join(", ")),
+    }
+}
+
+/// Loads the config file at `path`, creating it with a random server token if it doesn't exist, and then applies any
+/// overrides from environment variables. Fails with a description of every problem if the result isn't
+/// [valid](Config::validate).
+pub fn load(path: &Path) -> anyhow::Result<Config> {
+    let mut config = if path.exists() {
+        read(path)?
     } else {
         let config = Config::default();
+        let text = Format::of(path)?.write(&config)?;
+        std::fs::write(path, text).with_context(|| format!("failed to create {}", path.display()))?;
+        config
+    };
 
-        let mut file = File::create(path).unwrap();
-        serde_json::to_writer_pretty(&mut file, &config).unwrap();
+    apply_env_overrides(&mut config)?;
+    config.validate()?;
+    Ok(config)
+}
 
-        config
+fn read(path: &Path) -> anyhow::Result<Config> {
+    let format = Format::of(path)?;
+    let text = std::fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    let mut config: serde_json::Value = format.parse(&text)
+        .with_context(|| format!("{} isn't valid {}", path.display(), format.name()))?;
+    // Errors from parsing the file itself say where in it they are, which is only lost if token lists were migrated.
+    let config = if migrate_token_lists(&mut config) {
+        serde_json::from_value(config).map_err(anyhow::Error::from)
+    } else {
+        format.parse(&text)
+    };
+    config.with_context(|| format!("{} has a missing or invalid option", path.display()))
+}
+
+/// Overrides options with `NUCLEOID_*` environment variables, for deployments where writing a config file is awkward.
+/// Overrides are never written back to the file.
+fn apply_env_overrides(config: &mut Config) -> anyhow::Result<()> {
+    if let Ok(database_type) = std::env::var("NUCLEOID_DATABASE_TYPE") {
+        config.database_type = serde_json::from_value(serde_json::Value::String(database_type))
+            .context("invalid value for NUCLEOID_DATABASE_TYPE, expected mongodb, postgres or memory")?;
+    }
+    if let Some(database_url) = env_override("NUCLEOID_DATABASE_URL")? {
+        config.database_url = database_url;
+    }
+    if let Some(database_name) = env_override("NUCLEOID_DATABASE_NAME")? {
+        config.database_name = database_name;
+    }
+    if let Some(api_port) = env_override("NUCLEOID_API_PORT")? {
+        config.api_port = api_port;
+    }
+    if let Some(bind_address) = env_override("NUCLEOID_BIND_ADDRESS")? {
+        config.bind_address = bind_address;
+    }
+    if let Some(read_only) = env_override("NUCLEOID_READ_ONLY")? {
+        config.read_only = read_only;
+    }
+    if let Some(webhook_url) = env_override("NUCLEOID_WEBHOOK_URL")? {
+        config.webhook_url = Some(webhook_url);
+    }
+    if let Ok(tokens) = std::env::var("NUCLEOID_TOKENS") {
+        config.tokens = serde_json::from_str(&tokens).context("invalid value for NUCLEOID_TOKENS, expected a JSON array of tokens")?;
+    }
+    Ok(())
+}
+
+/// Parses an environment variable, failing if it is set to something invalid so that typos aren't ignored.
+fn env_override<T: FromStr>(name: &str) -> anyhow::Result<Option<T>> where T::Err: Display {
+    let value = match std::env::var(name) {
+        Ok(value) => value,
+        Err(_) => return Ok(None),
+    };
+    match value.parse() {
+        Ok(value) => Ok(Some(value)),
+        Err(e) => anyhow::bail!("invalid value for {}: {}", name, e),
+    }
+}
+
+/// Converts the `server_tokens`, `read_tokens` and `admin_tokens` lists of older configs into scoped tokens that grant
+/// the same permissions, returning whether there were any.
+fn migrate_token_lists(config: &mut serde_json::Value) -> bool {
+    let config = match config.as_object_mut() {
+        Some(config) => config,
+        None => return false,
+    };
+
+    let lists: [(&str, &str, &[TokenScope]); 3] = [
+        ("server_tokens", "server", SERVER_SCOPES),
+        ("read_tokens", "read", &[TokenScope::ReadPrivate]),
+        ("admin_tokens", "admin", &[TokenScope::Admin, TokenScope::ReadPrivate]),
+    ];
+    let mut migrated = Vec::new();
+    for (key, name, scopes) in lists {
+        let tokens = match config.remove(key) {
+            Some(serde_json::Value::Array(tokens)) => tokens,
+            _ => continue,
+        };
+        for (i, token) in tokens.into_iter().enumerate() {
+            migrated.push(serde_json::json!({
+                "name": format!("{}-{}", name, i + 1),
+                "token": token,
+                "scopes": scopes,
+            }));
+        }
+    }
+    if migrated.is_empty() {
+        return false;
+    }
+
+    log::warn!("The config has token lists, which are deprecated; treating them as {} scoped tokens", migrated.len());
+    let tokens = config.entry("tokens").or_insert_with(|| serde_json::Value::Array(Vec::new()));
+    if let Some(tokens) = tokens.as_array_mut() {
+        tokens.extend(migrated);
     }
+    true
 }
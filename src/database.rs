@@ -2,38 +2,198 @@ use anyhow::Result;
 use async_trait::async_trait;
 use futures::TryStreamExt;
 use mongodb::{bson::doc, Client, Collection, Database};
-use mongodb::options::FindOptions;
+use serde::de::DeserializeOwned;
+use mongodb::error::{BulkWriteFailure, ErrorKind, WriteFailure};
+use mongodb::options::{ClientOptions, Collation, CollationStrength, FindOneAndUpdateOptions, FindOneOptions, FindOptions, InsertManyOptions, ReturnDocument, UpdateModifications, UpdateOptions};
 use uuid::Uuid;
-use xtra::{Actor, Context, Handler, Message};
+use tracing::Instrument;
 
-use crate::config::Config;
-use crate::model::{PlayerGameStats, PlayerProfile, GameStatsBundle, PlayerStatsResponse, GlobalGameStats};
-use crate::util::uuid_to_bson;
-use std::collections::HashMap;
-use bson::Document;
+use crate::config::{Config, StatStorage};
+use crate::migration::{self, Migrations};
+use crate::reporting::{Alert, Reporter};
+use crate::store::{can_store_as, BackupBatch, BackupManifest, BundleOutcome, BundlePending, check_mergeable, stat_values, stored_type, sum_rollups, ConvertStat, GetGames, GetLeaderboard, GetLeaderboardSnapshot, GetPlayers, GetRatingLeaderboard, GetStatHistogram, GetStatRank, GetStatSummary, GetStatHistory, GetUploadLog, PlayerCursor, PlayerOrder, IncompleteBundleLog, RebuildAggregates, RestoreOutcome, SearchPlayers, StatTypeMismatch, StatsStore};
+use crate::model::{AchievementInfo, AchievementUnlocks, MatchTeam, RatingChange, StoredRating, PlayerGameStats, PlayerProfile, PreviousUsername, GameStatsBundle, PlayerStatsResponse, GlobalGameStats, UploadStat, StatType, StatConversionReport, StatDeletionReport, StatRenameReport, GameStat, DocumentFailure, NamespaceDeletionReport, NamespaceMergeReport, PlayerAnonymizationReport, PlayerDeletionReport, ServerStats, UploadLogEntry, GlobalStatsRollup, JobRun, UploadStatExt, BundleLogEntry, AggregateRebuildReport, RebuildMode, StatMismatch, LeaderboardEntry, LeaderboardOrder, Game, StatInfo, StoredStatInfo, CorruptDocument, StatSnapshot, StoredSeason, StatAggregation, HistogramBucket, StatRank, StatSummaryResponse, StoredAchievement, StoredAchievementInfo, StoredLeaderboardSnapshot, StoredPlaytime, SuspiciousUpload};
+use bson::oid::ObjectId;
+use crate::util::{uuid_from_bson, uuid_to_bson};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::Duration;
+use bson::{Bson, Document};
+
+/// How many documents are read and written at once by admin operations that touch a whole namespace.
+const ADMIN_BATCH_SIZE: u32 = 100;
+
+const HOUR_MILLIS: i64 = 60 * 60 * 1000;
+
+/// How many mismatched stats are listed in the report of an aggregate rebuild.
+const MAX_REPORTED_MISMATCHES: usize = 1000;
+
+/// Room that is made for each entry of the upload log, which is much more than an entry normally takes up.
+const UPLOAD_LOG_ENTRY_BYTES: i64 = 1024;
+
+/// The `_id` of the document in the `meta` collection that holds the current season.
+const SEASON_ID: &str = "season";
+
+/// The `_id` of the document in the `meta` collection that holds the schema version.
+const SCHEMA_ID: &str = "schema";
+
+/// The field of a stats document that lists the documents whose stats have been merged onto it but which haven't been
+/// removed yet, so that a merge that was interrupted in between isn't applied twice when it is run again.
+const MERGED_FROM: &str = "merged_from";
+
+/// The collections that backups are made of. Leases and rebuilt aggregates only matter while the instance that
+/// wrote them is running, and the coverage of the bundle log can't be vouched for once stats are restored.
+const BACKUP_COLLECTIONS: &[&str] = &[
+    "meta", "players", "player-stats", "player-season-stats", "global-stats", "global-stats-rollups", "games",
+    "stat-metadata", "achievement-metadata", "achievements", "ratings", "playtime", "stat-history",
+    "leaderboard-snapshots", "server-stats", "bundle-log", "upload-log", "applied-bundles", "corrupt_stats",
+    "suspicious-uploads", "job-runs",
+];
+
+/// Migrations of the database, in the order that they are applied. See [Migrations].
+const MIGRATIONS: &[&str] = &["widen_int_stats"];
 
 pub struct MongoDatabaseHandler {
     client: Client,
     config: Config,
+    reporter: Reporter,
+    /// Held while a bundle is checked and applied, so that concurrent uploads to a namespace can't store a new stat
+    /// as two different types, and while the aggregates of a namespace are replaced. See [Self::lock_namespace].
+    upload_locks: std::sync::Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    /// Namespaces whose [bundle log coverage](Self::track_bundle_log_coverage) is known to be tracked.
+    covered_namespaces: std::sync::Mutex<HashSet<String>>,
 }
 
 impl MongoDatabaseHandler {
     pub async fn connect(config: &Config) -> Result<Self> {
+        let mut options = ClientOptions::parse(&config.database_url).await?;
+        // Failing quickly while the database is unreachable leaves time in a request's deadline to retry.
+        if options.server_selection_timeout.is_none() {
+            options.server_selection_timeout = Some(Duration::from_millis(config.database_retry.server_selection_timeout_ms));
+        }
+
         let handler = Self {
-            client: Client::with_uri_str(&*config.database_url).await?,
+            client: Client::with_options(options)?,
             config: config.clone(),
+            reporter: Reporter::new(config),
+            upload_locks: Default::default(),
+            covered_namespaces: Default::default(),
         };
 
         // Ping the database to ensure we can connect and so we crash early if we can't
-        handler.client.database("admin")
-            .run_command(doc! {"ping": 1}, None)
-            .await?;
+        handler.ping().await?;
+
+        // A read-only instance may be pointed at a secondary, so it leaves migrations to a writable one.
+        if config.read_only {
+            migration::check(&handler).await?;
+        } else {
+            handler.create_indexes().await?;
+            handler.create_upload_log().await?;
+            migration::migrate(&handler, config).await?;
+            // Bundles uploaded while the log is disabled aren't logged, so no namespace's log covers them.
+            if !config.bundle_log.enabled {
+                handler.bundle_log_coverage().update_many(doc! {}, doc! {"$set": {"complete": false}}, None).await?;
+            }
+        }
 
         Ok(handler)
     }
 
+    /// Creates the indexes that queries rely on. Creating an index that already exists does nothing.
+    async fn create_indexes(&self) -> Result<()> {
+        self.create_index("players", doc! {"key": {"uuid": 1}, "name": "uuid", "unique": true}).await?;
+        self.create_index("players", doc! {"key": {"last_seen": -1, "uuid": 1}, "name": "last_seen_uuid"}).await?;
+        self.create_index("players", doc! {
+            "key": {"username": 1},
+            "name": "username_case_insensitive",
+            "collation": {"locale": "en", "strength": 2},
+        }).await?;
+        self.create_index("player-stats", doc! {"key": {"uuid": 1, "namespace": 1}, "name": "uuid_namespace", "unique": true}).await?;
+        self.create_index("global-stats", doc! {"key": {"namespace": 1}, "name": "namespace", "unique": true}).await?;
+        self.create_index("global-stats-rollups", doc! {"key": {"namespace": 1, "hour": 1}, "name": "namespace_hour"}).await?;
+        self.create_index("games", doc! {"key": {"ended_at": -1}, "name": "ended_at"}).await?;
+        self.create_index("games", doc! {"key": {"namespace": 1, "ended_at": -1}, "name": "namespace_ended_at"}).await?;
+        self.create_index("games", doc! {"key": {"participants.uuid": 1, "ended_at": -1}, "name": "participant_ended_at"}).await?;
+        self.create_index("stat-metadata", doc! {"key": {"namespace": 1, "stat": 1}, "name": "namespace_stat", "unique": true}).await?;
+        self.create_index("player-season-stats", doc! {
+            "key": {"uuid": 1, "namespace": 1, "season": 1},
+            "name": "uuid_namespace_season",
+            "unique": true,
+        }).await?;
+        self.create_index("stat-history", doc! {"key": {"uuid": 1, "namespace": 1, "recorded_at": 1}, "name": "uuid_namespace_recorded_at"}).await?;
+        self.create_index("leaderboard-snapshots", doc! {"key": {"namespace": 1, "stat": 1, "order": 1, "taken_at": -1}, "name": "namespace_stat_order_taken_at"}).await?;
+        self.create_index("leaderboard-snapshots", doc! {"key": {"taken_at": 1}, "name": "taken_at"}).await?;
+        self.create_index("playtime", doc! {"key": {"uuid": 1, "namespace": 1}, "name": "uuid_namespace", "unique": true}).await?;
+        self.create_index("achievement-metadata", doc! {"key": {"namespace": 1, "achievement": 1}, "name": "namespace_achievement", "unique": true}).await?;
+        self.create_index("achievements", doc! {
+            "key": {"uuid": 1, "namespace": 1, "achievement": 1},
+            "name": "uuid_namespace_achievement",
+            "unique": true,
+        }).await?;
+        self.create_index("achievements", doc! {"key": {"namespace": 1, "achievement": 1}, "name": "namespace_achievement"}).await?;
+        self.create_index("ratings", doc! {"key": {"uuid": 1, "namespace": 1}, "name": "uuid_namespace", "unique": true}).await?;
+        self.create_index("ratings", doc! {"key": {"namespace": 1, "rating": -1}, "name": "namespace_rating"}).await?;
+        self.create_index("server-stats", doc! {"key": {"server_name": 1, "namespace": 1}, "name": "server_name_namespace", "unique": true}).await?;
+        self.create_index("applied-bundles", doc! {"key": {"applied_at": 1}, "name": "applied_at"}).await?;
+        Ok(())
+    }
+
+    /// Creates the capped collection that uploads are logged to. A collection that already exists is left as it is,
+    /// even if `max_entries` has changed since it was created.
+    async fn create_upload_log(&self) -> Result<()> {
+        let max_entries = self.config.upload_log.max_entries as i64;
+        let result = self.database().run_command(doc! {
+            "create": "upload-log",
+            "capped": true,
+            // Capped collections are also limited in size, which is set well above what the entries should take up.
+            "size": max_entries.max(1) * UPLOAD_LOG_ENTRY_BYTES,
+            "max": max_entries,
+        }, None).await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if is_namespace_exists_error(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Creates an index on a collection. Unique indexes can't be built while a collection has duplicates, which older
+    /// versions could create, so that is logged instead of stopping the server from starting.
+    async fn create_index(&self, collection: &str, index: Document) -> Result<()> {
+        let name = index.get_str("name").unwrap_or_default().to_string();
+        let result = self.database().run_command(doc! {
+            "createIndexes": collection,
+            "indexes": [index],
+        }, None).await;
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if is_duplicate_key_error(&e) => {
+                log::error!("Could not create unique index {} on {}, as it has duplicate documents that need to be merged: {}", name, collection, e);
+                Ok(())
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Older versions stored int stats as int32, which would overflow. This converts any existing values to int64.
+    async fn widen_int_stats(&self) -> Result<()> {
+        let mut widened = 0;
+        for collection in [self.document_player_stats(), self.document_global_stats()] {
+            let mut cursor = collection.find(None, None).await?;
+            while let Some(document) = cursor.try_next().await? {
+                let update = widen_int_stats_update(&document);
+                if !update.is_empty() {
+                    collection.update_one(doc! {"_id": document.get("_id").unwrap()}, doc! {"$set": update}, None).await?;
+                    widened += 1;
+                }
+            }
+        }
+        log::info!("Widened int stats in {} documents", widened);
+        Ok(())
+    }
+
     fn database(&self) -> Database {
-        self.client.database(&*self.config.database_name)
+        self.client.database(&self.config.database_name)
     }
 
     fn player_profiles(&self) -> Collection<PlayerProfile> {
@@ -44,277 +204,2298 @@ impl MongoDatabaseHandler {
         self.database().collection("player-stats")
     }
 
+    fn player_season_stats(&self) -> Collection<PlayerGameStats> {
+        self.database().collection("player-season-stats")
+    }
+
     fn global_stats(&self) -> Collection<GlobalGameStats> {
         self.database().collection("global-stats")
     }
 
+    fn global_stats_rollups(&self) -> Collection<GlobalStatsRollup> {
+        self.database().collection("global-stats-rollups")
+    }
+
     // Used for error handling
     fn document_player_stats(&self) -> Collection<Document> {
         self.database().collection("player-stats")
     }
 
+    fn document_player_season_stats(&self) -> Collection<Document> {
+        self.database().collection("player-season-stats")
+    }
+
     fn document_global_stats(&self) -> Collection<Document> {
         self.database().collection("global-stats")
     }
 
+    fn meta(&self) -> Collection<Document> {
+        self.database().collection("meta")
+    }
+
+    fn seasons(&self) -> Collection<StoredSeason> {
+        self.database().collection("meta")
+    }
+
+    fn rebuilt_player_stats(&self) -> Collection<Document> {
+        self.database().collection("rebuilt-player-stats")
+    }
+
+    fn rebuilt_global_stats(&self) -> Collection<Document> {
+        self.database().collection("rebuilt-global-stats")
+    }
+
+    fn bundle_log(&self) -> Collection<BundleLogEntry> {
+        self.database().collection("bundle-log")
+    }
+
+    /// Whether the bundle log of each namespace covers every change to its stats, keyed by namespace.
+    fn bundle_log_coverage(&self) -> Collection<Document> {
+        self.database().collection("bundle-log-coverage")
+    }
+
+    fn stat_history(&self) -> Collection<StatSnapshot> {
+        self.database().collection("stat-history")
+    }
+
+    fn leaderboard_snapshots(&self) -> Collection<StoredLeaderboardSnapshot> {
+        self.database().collection("leaderboard-snapshots")
+    }
+
+    fn games(&self) -> Collection<Game> {
+        self.database().collection("games")
+    }
+
+    fn stat_info(&self) -> Collection<StoredStatInfo> {
+        self.database().collection("stat-metadata")
+    }
+
+    fn job_runs(&self) -> Collection<JobRun> {
+        self.database().collection("job-runs")
+    }
+
+    fn leases(&self) -> Collection<Document> {
+        self.database().collection("leases")
+    }
+
     fn corrupt_stats(&self) -> Collection<Document> {
         self.database().collection("corrupt_stats")
     }
 
-    async fn get_player_profile(&self, uuid: &Uuid) -> Result<Option<PlayerProfile>> {
-        let options = FindOptions::builder().limit(1).build();
-        let profile = self.player_profiles()
-            .find(doc! {"uuid": uuid_to_bson(uuid)?}, options).await?
-            .try_next().await?;
-        Ok(profile)
+    fn suspicious_uploads(&self) -> Collection<SuspiciousUpload> {
+        self.database().collection("suspicious-uploads")
     }
 
-    async fn update_player_profile(&self, uuid: &Uuid, username: Option<String>) -> Result<PlayerProfile> {
-        match self.get_player_profile(uuid).await? {
-            Some(profile) => {
-                if let Some(username) = username {
-                    if let Some(profile_username) = profile.username.clone() {
-                        if username != profile_username {
-                            log::debug!("Player {} updated username to {}", uuid, &username);
-                            self.player_profiles().update_one(
-                                doc! {"uuid": uuid_to_bson(uuid)?},
-                                doc! {"$set": {
-                                    "username": username.clone(),
-                                }},
-                                None,
-                            ).await?;
-
-                            let mut profile = profile.clone();
-                            profile.username = Some(username.clone());
-                            return Ok(profile);
-                        }
-                    }
-                }
-                Ok(profile.clone())
-            }
-            None => {
-                let profile = PlayerProfile {
-                    uuid: *uuid,
-                    username: username.clone(),
-                };
-                self.player_profiles().insert_one(&profile, None).await?;
-                Ok(profile)
+    fn achievement_info(&self) -> Collection<StoredAchievementInfo> {
+        self.database().collection("achievement-metadata")
+    }
+
+    fn achievements(&self) -> Collection<StoredAchievement> {
+        self.database().collection("achievements")
+    }
+
+    fn ratings(&self) -> Collection<StoredRating> {
+        self.database().collection("ratings")
+    }
+
+    fn document_ratings(&self) -> Collection<Document> {
+        self.database().collection("ratings")
+    }
+
+    fn playtime(&self) -> Collection<StoredPlaytime> {
+        self.database().collection("playtime")
+    }
+
+    fn server_stats(&self) -> Collection<ServerStats> {
+        self.database().collection("server-stats")
+    }
+
+    fn upload_log(&self) -> Collection<UploadLogEntry> {
+        self.database().collection("upload-log")
+    }
+
+    fn applied_bundles(&self) -> Collection<Document> {
+        self.database().collection("applied-bundles")
+    }
+
+    /// Records that the bundle with the given ID is being applied. Returns `false` if it already has been, and fails
+    /// with [BundlePending] if another upload of it hasn't finished, or failed without releasing its claim.
+    async fn claim_bundle_id(&self, bundle_id: &Uuid) -> Result<bool> {
+        let id = uuid_to_bson(bundle_id)?;
+        let claim = doc! {"_id": id.clone(), "applied_at": bson::DateTime::now(), "pending": true};
+        match self.applied_bundles().insert_one(claim, None).await {
+            Ok(_) => Ok(true),
+            Err(e) if is_duplicate_key_error(&e) => match self.applied_bundles().find_one(doc! {"_id": id}, None).await? {
+                Some(claim) if !claim.get_bool("pending").unwrap_or(false) => Ok(false),
+                _ => Err(BundlePending(*bundle_id).into()),
+            },
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Marks the claim on a bundle ID as finished, so that further uploads of the bundle are answered as duplicates.
+    /// Uploads of the bundle are refused until its ID is pruned if this fails, which is only logged since the bundle
+    /// can't be applied again either way.
+    async fn finish_bundle_claim(&self, bundle_id: &Uuid) {
+        let res = match uuid_to_bson(bundle_id) {
+            Ok(id) => self.applied_bundles().update_one(doc! {"_id": id}, doc! {"$unset": {"pending": ""}}, None).await
+                .map(|_| ()).map_err(anyhow::Error::from),
+            Err(e) => Err(e.into()),
+        };
+        if let Err(e) = res {
+            log::warn!("failed to mark bundle id {} as applied: {}", bundle_id, e);
+        }
+    }
+
+    /// Locks a namespace against uploads by this instance, for operations that read its stats and then write them.
+    async fn lock_namespace(&self, namespace: &str) -> tokio::sync::OwnedMutexGuard<()> {
+        let lock = self.upload_locks.lock().unwrap().entry(namespace.to_string()).or_default().clone();
+        lock.lock_owned().await
+    }
+
+    /// Starts tracking whether the bundle log covers every change to the stats of a namespace, before its first bundle
+    /// is logged. It only does if the namespace had no stats before then, and stops when its stats are changed in a
+    /// way that isn't logged, see [Self::mark_bundle_log_incomplete].
+    async fn track_bundle_log_coverage(&self, namespace: &str) -> Result<()> {
+        if self.covered_namespaces.lock().unwrap().contains(namespace) {
+            return Ok(());
+        }
+        if self.bundle_log_coverage().find_one(doc! {"_id": namespace}, None).await?.is_none() {
+            let mut complete = true;
+            for collection in [self.document_player_stats(), self.document_player_season_stats(), self.document_global_stats()] {
+                complete &= collection.find_one(doc! {"namespace": namespace}, None).await?.is_none();
             }
+            let options = UpdateOptions::builder().upsert(true).build();
+            self.bundle_log_coverage().update_one(doc! {"_id": namespace}, doc! {
+                "$setOnInsert": {"complete": complete, "since": bson::DateTime::now()},
+            }, options).await?;
         }
+        self.covered_namespaces.lock().unwrap().insert(namespace.to_string());
+        Ok(())
     }
 
-    async fn get_player_stats(&self, uuid: &Uuid, namespace: &Option<String>) -> Result<Option<PlayerStatsResponse>> {
-        if self.get_player_profile(uuid).await?.is_none() { // player not found.
-            return Ok(None);
+    /// Records that the stats of namespaces were changed in a way that the bundle log doesn't cover, so that their
+    /// aggregates can no longer be replaced by a rebuild from the log.
+    async fn mark_bundle_log_incomplete(&self, namespaces: &[&str]) -> Result<()> {
+        let options = UpdateOptions::builder().upsert(true).build();
+        for namespace in namespaces {
+            self.bundle_log_coverage().update_one(doc! {"_id": namespace}, doc! {
+                "$set": {"complete": false},
+            }, options.clone()).await?;
         }
+        Ok(())
+    }
 
-        let options = FindOptions::builder().build();
-        let mut stats = self.player_stats().find(match namespace {
-            Some(namespace) => doc! {
-                "uuid": uuid_to_bson(uuid)?,
-                "namespace": namespace.clone(),
-            },
-            None => doc! {
-                "uuid": uuid_to_bson(uuid)?,
-            },
-        }, options).await?;
+    /// Whether the bundle log covers every change to the stats of a namespace.
+    async fn is_bundle_log_complete(&self, namespace: &str) -> Result<bool> {
+        let coverage = self.bundle_log_coverage().find_one(doc! {"_id": namespace}, None).await?;
+        Ok(coverage.is_some_and(|coverage| coverage.get_bool("complete").unwrap_or(false)))
+    }
 
-        let mut final_stats: HashMap<String, HashMap<String, f64>> = HashMap::new();
-        while let Some(stats) = stats.try_next().await? {
-            let mut s = HashMap::new();
-            for (name, stat) in stats.stats {
-                s.insert(name, stat.into());
+    /// Logs a bundle, if the bundle log is enabled, and applies it. Fails with [PartlyApplied] once any of its stats
+    /// may have been written.
+    async fn log_and_apply_stats_bundle(&self, bundle: &GameStatsBundle) -> Result<()> {
+        let season = self.prepare_stats_bundle(bundle).await?;
+        if self.config.bundle_log.enabled {
+            self.track_bundle_log_coverage(&bundle.namespace).await?;
+            // Logged before it is applied, so that every bundle that has affected the aggregates is in the log.
+            let entry = BundleLogEntry::new(bundle);
+            let id = entry.id;
+            self.bundle_log().insert_one(entry, None).instrument(tracing::info_span!("log_bundle")).await?;
+            if let Err(e) = self.apply_stats_bundle(bundle, season).await {
+                // Rebuilds skip bundles that weren't applied, so the log no longer matches what was.
+                self.mark_bundle_log_incomplete(&[&bundle.namespace]).await?;
+                return Err(PartlyApplied(e).into());
             }
-            final_stats.insert(stats.namespace, s);
+            // The bundle has been applied, so failing the upload here would only lead to it being applied again.
+            if let Err(e) = self.bundle_log().update_one(doc! {"_id": id}, doc! {"$set": {"applied": true}}, None)
+                .instrument(tracing::info_span!("mark_bundle_applied"))
+                .await {
+                log::warn!("failed to mark bundle {} as applied in the bundle log: {}", id, e);
+                self.mark_bundle_log_incomplete(&[&bundle.namespace]).await?;
+            }
+        } else {
+            self.apply_stats_bundle(bundle, season).await.map_err(PartlyApplied)?;
         }
+        Ok(())
+    }
 
-        Ok(Some(final_stats))
+    /// Counts a bundle towards the stats of the server that uploaded it.
+    #[tracing::instrument(skip_all, fields(server_name = %bundle.server_name))]
+    async fn record_server_stats(&self, bundle: &GameStatsBundle) -> Result<()> {
+        let mut increments = doc! {"bundles": 1_i64, "player_uploads": bundle.stats.players.len() as i64};
+        for (stat, count) in ServerStats::stat_counts(bundle) {
+            increments.insert(format!("stats.{}", stat), count);
+        }
+        let options = UpdateOptions::builder().upsert(true).build();
+        self.server_stats().update_one(
+            doc! {"server_name": &bundle.server_name, "namespace": &bundle.namespace},
+            doc! {"$inc": increments, "$set": {"last_upload_at": bson::DateTime::now()}},
+            options,
+        ).await?;
+        Ok(())
     }
 
-    async fn ensure_player_stats_document(&self, uuid: &Uuid, namespace: &str) -> Result<()> {
-        self.update_player_profile(uuid, None).await?; // Ensure that the player is tracked in the database.
+    /// Records the values that the uploaded stats of each player in a bundle have after it was applied.
+    #[tracing::instrument(skip_all)]
+    async fn record_stat_history(&self, bundle: &GameStatsBundle) -> Result<()> {
+        if bundle.stats.players.is_empty() {
+            return Ok(());
+        }
 
-        let options = FindOptions::builder().limit(1).build();
-        let mut res = self.player_stats().find(doc! {
-            "uuid": uuid_to_bson(uuid)?,
-            "namespace": namespace,
+        let uuids = bundle.stats.players.keys().map(uuid_to_bson).collect::<bson::ser::Result<Vec<_>>>()?;
+        let mut projection = doc! {"uuid": 1, "namespace": 1};
+        for stat_name in bundle.stats.players.values().flat_map(HashMap::keys) {
+            projection.insert(format!("stats.{}", stat_name), 1);
+        }
+        let options = FindOptions::builder().projection(projection).build();
+        let mut documents = self.player_stats().find(doc! {
+            "uuid": {"$in": uuids},
+            "namespace": &bundle.namespace,
         }, options).await?;
-        let stats = res.try_next().await;
 
-        let needs_new_document = match stats {
-            Ok(stats) => stats.is_none(),
-            Err(e) => {
-                self.handle_broken_player_stats_document(&e.into(), uuid, namespace).await?;
-                true
+        let recorded_at = bson::DateTime::now();
+        let mut snapshots = Vec::new();
+        while let Some(stats) = documents.try_next().await? {
+            snapshots.push(StatSnapshot {
+                id: ObjectId::new(),
+                uuid: stats.uuid,
+                namespace: stats.namespace,
+                recorded_at,
+                stats: stats.stats.into_iter().map(|(name, stat)| (name, stat.into())).collect(),
+            });
+        }
+        if !snapshots.is_empty() {
+            self.stat_history().insert_many(snapshots, None).await?;
+        }
+        Ok(())
+    }
+
+    /// Checks that no stat in a bundle would be stored as a different type than it already is, as the increments of
+    /// one type can't be applied to the stored value of another without leaving a document that can't be read.
+    #[tracing::instrument(skip_all)]
+    async fn check_stat_types(&self, bundle: &GameStatsBundle) -> Result<()> {
+        let namespace = &bundle.namespace;
+        if !bundle.stats.players.is_empty() {
+            let uuids = bundle.stats.players.keys().map(uuid_to_bson).collect::<bson::ser::Result<Vec<_>>>()?;
+            let mut projection = doc! {"uuid": 1};
+            for stat_name in bundle.stats.players.values().flat_map(HashMap::keys) {
+                projection.insert(format!("stats.{}.type", stat_name), 1);
+            }
+            let filter = doc! {"uuid": {"$in": uuids}, "namespace": namespace};
+            let mut queries = vec![(self.document_player_stats(), filter.clone())];
+            if self.config.seasonal_namespaces.contains(namespace) {
+                let mut filter = filter;
+                filter.insert("season", self.get_current_season().await?.season);
+                queries.push((self.document_player_season_stats(), filter));
+            }
+
+            for (collection, filter) in queries {
+                let options = FindOptions::builder().projection(projection.clone()).build();
+                let mut documents = collection.find(filter, options).await?;
+                while let Some(document) = documents.try_next().await? {
+                    let player = match document.get("uuid").cloned().map(uuid_from_bson) {
+                        Some(Ok(player)) => player,
+                        _ => continue,
+                    };
+                    if let Some(stats) = bundle.stats.players.get(&player) {
+                        self.check_document_stat_types(namespace, Some(player), &document, stats)?;
+                    }
+                }
+            }
+        }
+
+        if let Some(global) = &bundle.stats.global {
+            if let Some(document) = self.document_global_stats().find_one(doc! {"namespace": namespace}, None).await? {
+                self.check_document_stat_types(namespace, None, &document, global)?;
             }
+        }
+        Ok(())
+    }
+
+    fn check_document_stat_types(&self, namespace: &str, player: Option<Uuid>, document: &Document, stats: &HashMap<String, UploadStat>) -> Result<()> {
+        let stored_stats = match document.get_document("stats") {
+            Ok(stored_stats) => stored_stats,
+            Err(_) => return Ok(()),
         };
+        for (stat_name, stat) in stats {
+            // Stats with an unknown type are left for quarantine, as the document can't be read anyway.
+            let stored = stored_stats.get_document(stat_name).ok()
+                .and_then(|stored| stored.get("type").cloned())
+                .and_then(|stored| bson::from_bson::<StatType>(stored).ok());
+            let stored = match stored {
+                Some(stored) => stored,
+                None => continue,
+            };
+
+            let uploaded = stored_type(stat, self.stat_storage(namespace, stat_name, stat));
+            if !can_store_as(stored, uploaded) {
+                return Err(StatTypeMismatch {
+                    namespace: namespace.to_string(),
+                    player,
+                    stat: stat_name.clone(),
+                    stored,
+                    uploaded,
+                }.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Prepares the documents that a bundle is applied to, without changing any stats, so that it can be retried
+    /// safely. Returns the current season if the bundle's stats are also added to it.
+    #[tracing::instrument(skip_all)]
+    async fn prepare_stats_bundle(&self, bundle: &GameStatsBundle) -> Result<Option<u32>> {
+        let namespace = &bundle.namespace;
+        let mut season = None;
+        if !bundle.stats.players.is_empty() {
+            let uuids = bundle.stats.players.keys().map(uuid_to_bson).collect::<bson::ser::Result<Vec<_>>>()?;
+            self.track_players(&uuids).await?;
+            self.quarantine_broken_stats::<PlayerGameStats>(self.document_player_stats(), doc! {
+                "uuid": {"$in": &uuids},
+                "namespace": namespace,
+            }, namespace, false).await?;
+            if self.config.seasonal_namespaces.contains(namespace) {
+                season = Some(self.get_current_season().await?.season);
+            }
+        }
+
+        if bundle.stats.global.is_some() {
+            self.quarantine_broken_stats::<GlobalGameStats>(self.document_global_stats(), doc! {
+                "namespace": namespace,
+            }, namespace, true).await?;
+        }
+        Ok(season)
+    }
 
-        if needs_new_document {
-            self.player_stats().insert_one(PlayerGameStats {
-                uuid: *uuid,
-                namespace: namespace.to_string(),
-                stats: HashMap::new(),
-            }, None).await?;
+    /// Adds the stats of a [prepared](Self::prepare_stats_bundle) bundle onto the aggregates.
+    ///
+    /// Each collection is written with a single batch of upserts, so the number of round trips doesn't grow with the
+    /// number of players in the bundle.
+    #[tracing::instrument(skip_all)]
+    async fn apply_stats_bundle(&self, bundle: &GameStatsBundle, season: Option<u32>) -> Result<()> {
+        let namespace = &bundle.namespace;
+        if !bundle.stats.players.is_empty() {
+            let mut updates = Vec::new();
+            for (player, stats) in &bundle.stats.players {
+                let filter = doc! {"uuid": uuid_to_bson(player)?, "namespace": namespace};
+                updates.extend(self.create_increment_upserts(filter, namespace, stats));
+            }
+            self.apply_updates(self.player_stats().name(), updates).await?;
+
+            if let Some(season) = season {
+                let mut updates = Vec::new();
+                for (player, stats) in &bundle.stats.players {
+                    let filter = doc! {"uuid": uuid_to_bson(player)?, "namespace": namespace, "season": season};
+                    updates.extend(self.create_increment_upserts(filter, namespace, stats));
+                }
+                self.apply_updates(self.player_season_stats().name(), updates).await?;
+            }
+        }
+
+        if let Some(global) = &bundle.stats.global {
+            let updates = self.create_increment_upserts(doc! {"namespace": namespace}, namespace, global);
+            self.apply_updates(self.global_stats().name(), updates).await?;
+            self.update_global_stats_rollup(namespace, global).await?;
         }
 
         Ok(())
     }
 
-    async fn ensure_global_stats_document(&self, namespace: &str) -> Result<()> {
-        let options = FindOptions::builder().limit(1).build();
-        let mut res = self.global_stats().find(doc! {
+    /// Creates a profile for each player that doesn't have one yet, so that every player who uploads is tracked, and
+    /// records that they were seen.
+    #[tracing::instrument(skip_all)]
+    async fn track_players(&self, uuids: &[Bson]) -> Result<()> {
+        let now = bson::DateTime::now();
+        let updates = uuids.iter()
+            .map(|uuid| doc! {
+                "q": {"uuid": uuid},
+                "u": {
+                    "$setOnInsert": {"username": Bson::Null, "private": false},
+                    "$min": {"first_seen": now},
+                    "$set": {"last_seen": now},
+                },
+                "upsert": true,
+            })
+            .collect();
+        self.apply_updates(self.player_profiles().name(), updates).await
+    }
+
+    /// Creates the update statements that add stats onto the document matching `filter`, creating it if it is missing.
+    ///
+    /// Native increments are combined into one statement, and decimal increments into one pipeline.
+    fn create_increment_upserts(&self, filter: Document, namespace: &str, stats: &HashMap<String, UploadStat>) -> Vec<Document> {
+        let mut operations = Vec::new();
+        let mut pipeline = Vec::new();
+        for (stat_name, stat) in stats {
+            match self.stat_storage(namespace, stat_name, stat) {
+                StatStorage::Native => operations.push(stat.create_increment_operation(stat_name, &self.config.histogram_buckets(namespace, stat_name))),
+                StatStorage::Decimal128 => pipeline.extend(stat.create_decimal_increment_pipeline(stat_name)),
+            }
+        }
+
+        let mut updates = Vec::new();
+        if !operations.is_empty() {
+            updates.push(doc! {"q": filter.clone(), "u": combine_updates(operations.into_iter()), "upsert": true});
+        }
+        if !pipeline.is_empty() {
+            updates.push(doc! {"q": filter, "u": pipeline, "upsert": true});
+        }
+        updates
+    }
+
+    /// Records the increments of an upload in the hourly rollup used to compute deltas of global stats.
+    #[tracing::instrument(skip_all)]
+    async fn update_global_stats_rollup(&self, namespace: &str, stats: &HashMap<String, UploadStat>) -> Result<()> {
+        let update = combine_updates(stats.iter().map(|(name, stat)| stat.create_increment_operation(name, &self.config.histogram_buckets(namespace, name))));
+        if update.is_empty() {
+            return Ok(());
+        }
+
+        let now = bson::DateTime::now().timestamp_millis();
+        let hour = bson::DateTime::from_millis(now - now.rem_euclid(HOUR_MILLIS));
+        let options = UpdateOptions::builder().upsert(true).build();
+        self.global_stats_rollups().update_one(doc! {
             "namespace": namespace,
-        }, options).await?;
+            "hour": hour,
+        }, update, options).await?;
+        Ok(())
+    }
 
-        let stats = res.try_next().await;
+    /// How an upload of a stat is stored. Only totals and averages accumulate floating point error, so other stats
+    /// are always stored natively.
+    fn stat_storage(&self, namespace: &str, stat_name: &str, stat: &UploadStat) -> StatStorage {
+        if !matches!(stat.aggregation(), StatAggregation::Total | StatAggregation::Average) {
+            return StatStorage::Native;
+        }
+        self.config.stat_metadata(namespace, stat_name)
+            .map(|metadata| metadata.storage)
+            .unwrap_or_default()
+    }
 
-        let needs_new_document = match stats {
-            Ok(stats) => stats.is_none(),
-            Err(e) => {
-                self.handle_broken_global_stats_document(&e.into(), &namespace).await?;
-                true
-            }
-        };
+    fn create_increment_update(&self, namespace: &str, stat_name: &str, stat: &UploadStat) -> UpdateModifications {
+        match self.stat_storage(namespace, stat_name, stat) {
+            StatStorage::Native => UpdateModifications::Document(stat.create_increment_operation(stat_name, &self.config.histogram_buckets(namespace, stat_name))),
+            StatStorage::Decimal128 => UpdateModifications::Pipeline(stat.create_decimal_increment_pipeline(stat_name)),
+        }
+    }
 
-        if needs_new_document {
-            self.global_stats().insert_one(GlobalGameStats {
-                namespace: namespace.to_string(),
-                stats: HashMap::new(),
-            }, None).await?;
+    async fn merge_stats_document(&self, source: &Document, stats: &HashMap<String, GameStat>, target: Option<HashMap<String, GameStat>>,
+                                  target_query: Document, collection: &Collection<Document>) -> Result<()> {
+        if let Some(target) = &target {
+            check_mergeable(stats, target)?;
         }
+        let id = source.get("_id").unwrap();
+        merge_stats(stats, id, target_query.clone(), collection).await?;
+        collection.delete_one(doc! {"_id": id}, None).await?;
+        finish_merge(id, target_query, collection).await
+    }
 
+    /// Adds the logged bundles matching `filter` onto the rebuilt aggregates, in the order that they were received.
+    async fn replay_bundle_log(&self, filter: Document, report: &mut AggregateRebuildReport) -> Result<()> {
+        let options = FindOptions::builder().sort(doc! {"received_at": 1}).batch_size(ADMIN_BATCH_SIZE).build();
+        let mut cursor = self.bundle_log().find(filter, options).await?;
+        while let Some(entry) = cursor.try_next().await? {
+            if !entry.applied {
+                report.skipped += 1;
+                continue;
+            }
+            let result = match entry.to_bundle() {
+                Ok(bundle) => self.apply_rebuilt_bundle(bundle).await,
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok(()) => report.replayed += 1,
+                Err(e) => report.failed.push(DocumentFailure {
+                    document: entry.id.to_hex(),
+                    error: e.to_string(),
+                }),
+            }
+        }
         Ok(())
     }
 
-    async fn upload_stats_bundle(&self, bundle: GameStatsBundle) -> Result<()> {
+    /// Adds the stats of a logged bundle onto the rebuilt aggregates.
+    async fn apply_rebuilt_bundle(&self, bundle: GameStatsBundle) -> Result<()> {
+        let options = UpdateOptions::builder().upsert(true).build();
         for (player, stats) in bundle.stats.players {
-            // Ensure that there is a document to upload stats to.
-            self.ensure_player_stats_document(&player, &bundle.namespace).await?;
+            let query = doc! {
+                "uuid": uuid_to_bson(&player)?,
+                "namespace": &bundle.namespace,
+            };
             for (stat_name, stat) in stats {
-                self.player_stats().update_one(doc! {
-                    "uuid": uuid_to_bson(&player)?,
-                    "namespace": &bundle.namespace,
-                }, stat.create_increment_operation(&stat_name), None).await?;
+                let update = self.create_increment_update(&bundle.namespace, &stat_name, &stat);
+                self.rebuilt_player_stats().update_one(query.clone(), update, options.clone()).await?;
             }
         }
 
         if let Some(global) = bundle.stats.global {
-            self.ensure_global_stats_document(&bundle.namespace).await?;
-            for (stat_name, stat) in global {
-                self.global_stats().update_one(doc! {
-                    "namespace": &bundle.namespace,
-                }, stat.create_increment_operation(&stat_name), None).await?;
+            for (stat_name, stat) in &global {
+                let update = self.create_increment_update(&bundle.namespace, stat_name, stat);
+                self.rebuilt_global_stats().update_one(doc! {"namespace": &bundle.namespace}, update, options.clone()).await?;
             }
         }
-
         Ok(())
     }
 
-    async fn handle_broken_player_stats_document(&self, e: &anyhow::Error, uuid: &Uuid, namespace: &str) -> Result<()> {
-        let doc = self.document_player_stats().find_one(doc! {
-            "uuid": uuid_to_bson(uuid)?,
-            "namespace": namespace,
-        }, None).await?;
+    /// Records the stats that differ between the live and rebuilt aggregates in `scope`.
+    async fn compare_rebuilt_aggregates(&self, scope: &Document, report: &mut AggregateRebuildReport) -> Result<()> {
+        // Every document is checked from both sides, so that documents that exist on only one side are found.
+        let pairs = [
+            (self.rebuilt_player_stats(), self.document_player_stats(), true),
+            (self.document_player_stats(), self.rebuilt_player_stats(), false),
+            (self.rebuilt_global_stats(), self.document_global_stats(), true),
+            (self.document_global_stats(), self.rebuilt_global_stats(), false),
+        ];
+        for (source, other, source_is_rebuilt) in pairs {
+            let options = FindOptions::builder().batch_size(ADMIN_BATCH_SIZE).build();
+            let mut cursor = source.find(scope.clone(), options).await?;
+            while let Some(document) = cursor.try_next().await? {
+                let mut query = doc! {"namespace": document.get_str("namespace")?};
+                if let Some(uuid) = document.get("uuid") {
+                    query.insert("uuid", uuid.clone());
+                }
+                let counterpart = other.find_one(query, None).await?;
+                // Documents found from the live side that also exist in the rebuild have already been compared.
+                if !source_is_rebuilt && counterpart.is_some() {
+                    continue;
+                }
 
-        if let Some(doc) = doc {
-            self.handle_broken_document(e, &doc, namespace, false).await?;
-            self.document_player_stats().delete_one(doc! {
-                "_id": doc.get("_id").unwrap(),
-            }, None).await?;
-        } else {
-            // This should never happen
-            log::warn!("Missing corrupt document that was there before!? (player: {}, namespace: {})", uuid, namespace);
+                let (rebuilt, live) = if source_is_rebuilt {
+                    (Some(&document), counterpart.as_ref())
+                } else {
+                    (None, Some(&document))
+                };
+                if let Err(e) = compare_stats_documents(live, rebuilt, report) {
+                    report.failed.push(DocumentFailure {
+                        document: document.get("_id").cloned().unwrap_or(Bson::Null).to_string(),
+                        error: e.to_string(),
+                    });
+                }
+            }
         }
-
         Ok(())
     }
 
-    async fn handle_broken_global_stats_document(&self, e: &anyhow::Error, namespace: &str) -> Result<()> {
-        let doc = self.document_global_stats().find_one(doc! {
-            "namespace": namespace,
+    /// Sends a batch of update statements to a collection in a single round trip.
+    #[tracing::instrument(skip_all, fields(collection = %collection, updates = updates.len()))]
+    async fn apply_updates(&self, collection: &str, updates: Vec<Document>) -> Result<()> {
+        if updates.is_empty() {
+            return Ok(());
+        }
+        let response = self.database().run_command(doc! {
+            "update": collection,
+            "updates": updates,
+            "ordered": false,
         }, None).await?;
 
-        if let Some(doc) = doc {
-            self.handle_broken_document(e, &doc, namespace, true).await?;
-            self.document_global_stats().delete_one(doc! {
-                "_id": doc.get("_id").unwrap(),
-            }, None).await?;
-        } else {
-            // This should never happen
-            log::warn!("Missing corrupt document that was there before!? (global; namespace: {})", namespace);
+        if let Ok(errors) = response.get_array("writeErrors") {
+            anyhow::bail!("{} updates to {} failed: {:?}", errors.len(), collection, errors);
+        }
+        Ok(())
+    }
+
+    /// Quarantines the stats documents matching `filter` that can't be read as `T`, so that uploads start afresh.
+    #[tracing::instrument(skip_all, fields(collection = collection.name()))]
+    async fn quarantine_broken_stats<T: DeserializeOwned>(&self, collection: Collection<Document>, filter: Document, namespace: &str, global: bool) -> Result<()> {
+        let mut documents = collection.find(filter, None).await?;
+        while let Some(document) = documents.try_next().await? {
+            if let Err(e) = bson::from_document::<T>(document.clone()) {
+                self.handle_broken_document(&e.into(), &document, namespace, global).await?;
+                collection.delete_one(doc! {
+                    "_id": document.get("_id").unwrap(),
+                }, None).await?;
+            }
         }
 
         Ok(())
     }
 
+    #[tracing::instrument(skip_all)]
     async fn handle_broken_document(&self, e: &anyhow::Error, document: &Document, namespace: &str, global: bool) -> Result<()> {
-        let mut corrupt_document = document.clone();
-        corrupt_document.remove("_id"); // remove the ID so the driver generates a new one when it is re-inserted
-        let corrupt_id = self.corrupt_stats().insert_one(document, None).await?.inserted_id;
+        let corrupt = CorruptDocument {
+            id: ObjectId::new(),
+            collection: if global { "global-stats" } else { "player-stats" }.to_string(),
+            namespace: Some(namespace.to_string()),
+            error: Some(e.to_string()),
+            quarantined_at: Some(bson::DateTime::now()),
+            document: document.clone(),
+        };
+        let corrupt_id = corrupt.id;
+        self.corrupt_stats().insert_one(bson::to_document(&corrupt)?, None).await?;
 
-        // TODO: Error reporting (discord webhook probably)
-        log::warn!("Corrupt stats document (not our fault, probably a minigame's)!\nError: {}\nDocument: {}\nNamespace: {}, global: {}", e, document, namespace, global);
+        log::warn!("Corrupt stats document (not our fault, probably a minigame's)!\nError: {}\nDocument: {}\nNamespace: {}, global: {}, quarantined as: {}", e, document, namespace, global, corrupt_id);
+        let player = match (global, document.get("uuid")) {
+            (false, Some(uuid)) => uuid_from_bson(uuid.clone()).ok(),
+            _ => None,
+        };
+        self.reporter.report(Alert {
+            title: format!("Quarantined corrupt {} stats document as {}", if global { "global" } else { "player" }, corrupt_id),
+            namespace: Some(namespace.to_string()),
+            player,
+            error: e.to_string(),
+        });
 
         Ok(())
     }
 }
 
-impl Actor for MongoDatabaseHandler {}
-
-pub struct GetPlayerProfile(pub Uuid);
-impl Message for GetPlayerProfile {
-    type Result = Result<Option<PlayerProfile>>;
-}
-
 #[async_trait]
-impl Handler<GetPlayerProfile> for MongoDatabaseHandler {
-    async fn handle(&mut self, message: GetPlayerProfile, _ctx: &mut Context<Self>) -> <GetPlayerProfile as Message>::Result {
-        self.get_player_profile(&message.0).await
+impl Migrations for MongoDatabaseHandler {
+    fn migrations(&self) -> Vec<&'static str> {
+        MIGRATIONS.to_vec()
     }
-}
 
-pub struct UpdatePlayerProfile {
-    pub uuid: Uuid,
-    pub username: String,
-}
+    async fn schema_version(&self) -> Result<usize> {
+        if let Some(schema) = self.meta().find_one(doc! {"_id": SCHEMA_ID}, None).await? {
+            return Ok(schema.get_i64("version")? as usize);
+        }
+        // Int stats were widened before there were schema versions, which was recorded with a marker instead.
+        let widened = self.meta().find_one(doc! {"_id": "int_stats_widened"}, None).await?.is_some();
+        Ok(if widened { 1 } else { 0 })
+    }
 
-impl Message for UpdatePlayerProfile {
-    type Result = Result<()>;
+    async fn apply_migration(&self, index: usize) -> Result<()> {
+        match MIGRATIONS[index] {
+            "widen_int_stats" => self.widen_int_stats().await?,
+            name => anyhow::bail!("unknown migration {}", name),
+        }
+        self.meta().update_one(
+            doc! {"_id": SCHEMA_ID},
+            doc! {"$set": {"version": index as i64 + 1, "migrated_at": bson::DateTime::now()}},
+            UpdateOptions::builder().upsert(true).build(),
+        ).await?;
+        Ok(())
+    }
 }
 
 #[async_trait]
-impl Handler<UpdatePlayerProfile> for MongoDatabaseHandler {
-    async fn handle(&mut self, message: UpdatePlayerProfile, _ctx: &mut Context<Self>) -> <UpdatePlayerProfile as Message>::Result {
-        self.update_player_profile(&message.uuid, Some(message.username)).await?;
+impl StatsStore for MongoDatabaseHandler {
+    #[tracing::instrument(skip_all)]
+    async fn ping(&self) -> Result<()> {
+        self.client.database("admin")
+            .run_command(doc! {"ping": 1}, None)
+            .await?;
         Ok(())
     }
-}
 
-pub struct GetPlayerStats {
-    pub uuid: Uuid,
-    pub namespace: Option<String>,
-}
-
-impl Message for GetPlayerStats {
-    type Result = Result<Option<PlayerStatsResponse>>;
-}
+    fn is_transient_error(&self, error: &anyhow::Error) -> bool {
+        error.downcast_ref::<mongodb::error::Error>().is_some_and(is_transient_error)
+    }
 
-#[async_trait]
-impl Handler<GetPlayerStats> for MongoDatabaseHandler {
-    async fn handle(&mut self, message: GetPlayerStats, _ctx: &mut Context<Self>) -> <GetPlayerStats as Message>::Result {
-        self.get_player_stats(&message.uuid, &message.namespace).await
+    #[tracing::instrument(skip_all)]
+    async fn get_player_profile(&self, uuid: &Uuid) -> Result<Option<PlayerProfile>> {
+        let options = FindOptions::builder().limit(1).build();
+        let profile = self.player_profiles()
+            .find(doc! {"uuid": uuid_to_bson(uuid)?}, options).await?
+            .try_next().await?;
+        Ok(profile)
     }
-}
 
-pub struct UploadStatsBundle(pub GameStatsBundle);
+    #[tracing::instrument(skip_all)]
+    async fn get_player_profile_by_name(&self, username: &str) -> Result<Option<PlayerProfile>> {
+        let collation = Collation::builder().locale("en".to_string()).strength(CollationStrength::Secondary).build();
+        let options = FindOptions::builder()
+            .collation(collation)
+            .sort(doc! {"username_updated_at": -1})
+            .limit(1)
+            .build();
+        let profile = self.player_profiles()
+            .find(doc! {"username": username}, options).await?
+            .try_next().await?;
+        Ok(profile)
+    }
 
-impl Message for UploadStatsBundle {
-    type Result = Result<()>;
-}
+    #[tracing::instrument(skip_all)]
+    async fn get_players(&self, message: GetPlayers) -> Result<Vec<PlayerProfile>> {
+        let (filter, sort) = match (message.order, message.after) {
+            (PlayerOrder::Uuid, None) => (doc! {}, doc! {"uuid": 1}),
+            (PlayerOrder::Uuid, Some(after)) => (doc! {"uuid": {"$gt": uuid_to_bson(&after.uuid)?}}, doc! {"uuid": 1}),
+            (PlayerOrder::LastSeen, after) => {
+                // Players who were never seen sort as null, and so after everyone else.
+                let filter = match after {
+                    None => doc! {},
+                    Some(PlayerCursor { uuid, last_seen: Some(last_seen) }) => doc! {"$or": [
+                        {"last_seen": {"$lt": last_seen}},
+                        {"last_seen": last_seen, "uuid": {"$gt": uuid_to_bson(&uuid)?}},
+                        {"last_seen": null},
+                    ]},
+                    Some(PlayerCursor { uuid, last_seen: None }) => doc! {"last_seen": null, "uuid": {"$gt": uuid_to_bson(&uuid)?}},
+                };
+                (filter, doc! {"last_seen": -1, "uuid": 1})
+            }
+        };
+        let options = FindOptions::builder().sort(sort).limit(message.limit).build();
+        Ok(self.player_profiles().find(filter, options).await?.try_collect().await?)
+    }
 
-#[async_trait]
-impl Handler<UploadStatsBundle> for MongoDatabaseHandler {
-    async fn handle(&mut self, message: UploadStatsBundle, _ctx: &mut Context<Self>) -> <UploadStatsBundle as Message>::Result {
-        self.upload_stats_bundle(message.0).await
+    #[tracing::instrument(skip_all)]
+    async fn search_players(&self, message: SearchPlayers) -> Result<Vec<PlayerProfile>> {
+        // A range under the case-insensitive collation of the username index matches the prefix in any case, as
+        // U+FFFF sorts after every other character.
+        let mut filter = doc! {"username": {"$gte": &message.prefix, "$lt": format!("{}\u{ffff}", message.prefix)}};
+        if !message.include_private {
+            filter.insert("private", doc! {"$ne": true});
+        }
+        let collation = Collation::builder().locale("en".to_string()).strength(CollationStrength::Secondary).build();
+        let options = FindOptions::builder()
+            .collation(collation)
+            .sort(doc! {"username": 1})
+            .limit(message.limit)
+            .build();
+        Ok(self.player_profiles().find(filter, options).await?.try_collect().await?)
     }
+
+    #[tracing::instrument(skip_all)]
+    async fn update_player_profile(&self, uuid: &Uuid, username: Option<String>) -> Result<PlayerProfile> {
+        let now = bson::DateTime::now();
+        match self.get_player_profile(uuid).await? {
+            Some(mut profile) => {
+                let mut set = doc! {"last_seen": now};
+                let mut update = doc! {"$min": {"first_seen": now}};
+                // Profiles created by uploads have no username until one is set.
+                if let Some(username) = username.filter(|username| profile.username.as_ref() != Some(username)) {
+                    log::debug!("Player {} updated username to {}", uuid, &username);
+                    set.insert("username", username.clone());
+                    set.insert("username_updated_at", now);
+                    if let Some(previous) = &profile.username {
+                        let previous = PreviousUsername {
+                            username: previous.clone(),
+                            used_from: profile.username_updated_at,
+                            used_until: now,
+                        };
+                        update.insert("$push", doc! {"username_history": bson::to_bson(&previous)?});
+                    }
+                    profile.username = Some(username);
+                    profile.username_updated_at = Some(now);
+                }
+                update.insert("$set", set);
+                self.player_profiles().update_one(doc! {"uuid": uuid_to_bson(uuid)?}, update, None).await?;
+
+                profile.first_seen.get_or_insert(now);
+                profile.last_seen = Some(now);
+                Ok(profile)
+            }
+            None => {
+                let profile = PlayerProfile {
+                    uuid: *uuid,
+                    username_updated_at: username.as_ref().map(|_| now),
+                    username: username.clone(),
+                    private: false,
+                    first_seen: Some(now),
+                    last_seen: Some(now),
+                    anonymized: false,
+                };
+                self.player_profiles().insert_one(&profile, None).await?;
+                Ok(profile)
+            }
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn set_player_private(&self, uuid: &Uuid, private: bool) -> Result<()> {
+        self.player_profiles().update_one(
+            doc! {"uuid": uuid_to_bson(uuid)?},
+            doc! {"$set": {"private": private}},
+            None,
+        ).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_username_history(&self, uuid: &Uuid) -> Result<Vec<PreviousUsername>> {
+        let options = FindOneOptions::builder().projection(doc! {"username_history": 1}).build();
+        let profile = self.database().collection::<Document>("players")
+            .find_one(doc! {"uuid": uuid_to_bson(uuid)?}, options).await?;
+        match profile.and_then(|mut profile| profile.remove("username_history")) {
+            Some(history) => Ok(bson::from_bson(history)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn add_playtime(&self, uuid: &Uuid, namespace: &str, seconds: u64) -> Result<()> {
+        let uuid = uuid_to_bson(uuid)?;
+        self.playtime().update_one(
+            doc! {"uuid": &uuid, "namespace": namespace},
+            doc! {
+                "$inc": {"seconds": seconds as i64, "sessions": 1_i64},
+                "$max": {"last_played": bson::DateTime::now()},
+            },
+            UpdateOptions::builder().upsert(true).build(),
+        ).await?;
+        self.track_players(&[uuid]).await
+    }
+
+    async fn get_playtime(&self, uuid: &Uuid) -> Result<Vec<StoredPlaytime>> {
+        let cursor = self.playtime().find(doc! {"uuid": uuid_to_bson(uuid)?}, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn delete_player_data(&self, uuid: &Uuid) -> Result<PlayerDeletionReport> {
+        let uuid = uuid_to_bson(uuid)?;
+        let query = doc! {"uuid": &uuid};
+
+        let mut namespaces = BTreeSet::new();
+        for collection in [self.document_player_stats(), self.document_player_season_stats()] {
+            for namespace in collection.distinct("namespace", query.clone(), None).await? {
+                if let Bson::String(namespace) = namespace {
+                    namespaces.insert(namespace);
+                }
+            }
+        }
+
+        let mut report = PlayerDeletionReport {
+            namespaces: namespaces.into_iter().collect(),
+            ..Default::default()
+        };
+        for collection in [self.document_player_stats(), self.document_player_season_stats()] {
+            report.stats_documents += collection.delete_many(query.clone(), None).await?.deleted_count;
+        }
+        report.history_entries = self.stat_history().delete_many(query.clone(), None).await?.deleted_count;
+        // Their stats are removed from the bundle log too, so that rebuilding the aggregates can't bring them back.
+        let logged_stats = format!("players.{}", uuid_from_bson(uuid.clone())?.to_hyphenated());
+        self.bundle_log().update_many(doc! {&logged_stats: {"$exists": true}}, doc! {"$unset": {&logged_stats: ""}}, None).await?;
+        self.playtime().delete_many(query.clone(), None).await?;
+        self.achievements().delete_many(query.clone(), None).await?;
+        self.ratings().delete_many(query.clone(), None).await?;
+        // Older quarantine records are the broken document by itself, rather than a copy in `document`.
+        report.corrupt_documents = self.corrupt_stats().delete_many(doc! {
+            "$or": [{"uuid": &uuid}, {"document.uuid": &uuid}],
+        }, None).await?.deleted_count;
+        report.profile = self.player_profiles().delete_one(query, None).await?.deleted_count > 0;
+        Ok(report)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn anonymize_player(&self, uuid: &Uuid) -> Result<PlayerAnonymizationReport> {
+        let previous_usernames = self.get_username_history(uuid).await?.len() as u64;
+        let uuid_bson = uuid_to_bson(uuid)?;
+        let query = doc! {"uuid": &uuid_bson};
+        let res = self.player_profiles().update_one(query.clone(), doc! {
+            "$set": {"anonymized": true},
+            "$unset": {"username": "", "username_updated_at": "", "username_history": ""},
+        }, None).await?;
+
+        let mut namespaces = BTreeSet::new();
+        for collection in [self.document_player_stats(), self.document_player_season_stats()] {
+            for namespace in collection.distinct("namespace", query.clone(), None).await? {
+                if let Bson::String(namespace) = namespace {
+                    namespaces.insert(namespace);
+                }
+            }
+        }
+
+        // Leaderboard entries are stored the way their UUID serializes, rather than as a UUID binary.
+        let entry_uuid = bson::to_bson(uuid)?;
+        let options = UpdateOptions::builder().array_filters(vec![doc! {"entry.uuid": &entry_uuid}]).build();
+        let snapshots = self.leaderboard_snapshots().update_many(
+            doc! {"entries.uuid": &entry_uuid},
+            doc! {"$set": {"entries.$[entry].username": Bson::Null}},
+            options,
+        ).await?;
+
+        Ok(PlayerAnonymizationReport {
+            profile: res.matched_count > 0,
+            namespaces: namespaces.into_iter().collect(),
+            previous_usernames,
+            leaderboard_snapshots: snapshots.modified_count,
+        })
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_player_stats(&self, uuid: &Uuid, namespace: &Option<String>, season: Option<u32>) -> Result<Option<PlayerStatsResponse>> {
+        if self.get_player_profile(uuid).await?.is_none() { // player not found.
+            return Ok(None);
+        }
+
+        let mut filter = doc! {"uuid": uuid_to_bson(uuid)?};
+        if let Some(namespace) = namespace {
+            filter.insert("namespace", namespace.clone());
+        }
+        let collection = match season {
+            Some(season) => {
+                filter.insert("season", season);
+                self.player_season_stats()
+            }
+            None => self.player_stats(),
+        };
+
+        let options = FindOptions::builder().build();
+        let mut stats = collection.find(filter, options).await?;
+
+        let mut final_stats: HashMap<String, HashMap<String, f64>> = HashMap::new();
+        while let Some(stats) = stats.try_next().await? {
+            let s = stat_values(&self.config, &stats.namespace, stats.stats);
+            final_stats.insert(stats.namespace, s);
+        }
+
+        Ok(Some(final_stats))
+    }
+
+    #[tracing::instrument(skip_all, fields(namespace = %bundle.namespace))]
+    async fn upload_stats_bundle(&self, bundle: GameStatsBundle) -> Result<BundleOutcome> {
+        let _guard = self.lock_namespace(&bundle.namespace).await;
+        self.check_stat_types(&bundle).await?;
+        if let Some(bundle_id) = &bundle.bundle_id {
+            if !self.claim_bundle_id(bundle_id).await? {
+                return Ok(BundleOutcome::Duplicate);
+            }
+        }
+        if let Err(e) = self.log_and_apply_stats_bundle(&bundle).await {
+            // A partly applied bundle keeps its claim, since applying it again would count the stats that were written
+            // twice. Otherwise nothing was changed, and releasing the claim lets the upload be retried. A claim that
+            // can't be released stays pending, so that retries are refused rather than answered as duplicates.
+            if let Some(bundle_id) = &bundle.bundle_id {
+                if e.is::<PartlyApplied>() {
+                    self.finish_bundle_claim(bundle_id).await;
+                } else if let Err(e) = self.applied_bundles().delete_one(doc! {"_id": uuid_to_bson(bundle_id)?}, None).await {
+                    log::warn!("failed to release bundle id {}: {}", bundle_id, e);
+                }
+            }
+            return Err(e);
+        }
+        if let Some(bundle_id) = &bundle.bundle_id {
+            self.finish_bundle_claim(bundle_id).await;
+        }
+
+        // The bundle has already been applied, so failing the upload here would only lead to it being applied again.
+        if self.config.stat_history.enabled {
+            if let Err(e) = self.record_stat_history(&bundle).await {
+                log::warn!("failed to record stat history of bundle for {}: {}", bundle.namespace, e);
+            }
+        }
+        if let Err(e) = self.record_server_stats(&bundle).await {
+            log::warn!("failed to record bundle for {} in the stats of server '{}': {}", bundle.namespace, bundle.server_name, e);
+        }
+        Ok(BundleOutcome::Applied)
+    }
+
+    #[tracing::instrument(skip_all, fields(namespace = %bundle.namespace))]
+    async fn check_stats_bundle(&self, bundle: &GameStatsBundle) -> Result<BundleOutcome> {
+        self.check_stat_types(bundle).await?;
+        if let Some(bundle_id) = &bundle.bundle_id {
+            if let Some(claim) = self.applied_bundles().find_one(doc! {"_id": uuid_to_bson(bundle_id)?}, None).await? {
+                if claim.get_bool("pending").unwrap_or(false) {
+                    return Err(BundlePending(*bundle_id).into());
+                }
+                return Ok(BundleOutcome::Duplicate);
+            }
+        }
+        Ok(BundleOutcome::Applied)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn log_upload(&self, entry: UploadLogEntry) -> Result<()> {
+        // The collection is capped, so MongoDB drops the oldest entries itself.
+        self.upload_log().insert_one(entry, None).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_upload_log(&self, message: GetUploadLog) -> Result<Vec<UploadLogEntry>> {
+        let mut filter = doc! {};
+        if let Some(server_name) = &message.server_name {
+            filter.insert("server_name", server_name);
+        }
+        if let Some(namespace) = &message.namespace {
+            filter.insert("namespace", namespace);
+        }
+        if let Some(token) = &message.token {
+            filter.insert("token", token);
+        }
+        // Capped collections keep their documents in insertion order.
+        let options = FindOptions::builder().sort(doc! {"$natural": -1}).limit(message.limit).build();
+        Ok(self.upload_log().find(filter, options).await?.try_collect().await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_server_stats(&self, server_name: &str) -> Result<Vec<ServerStats>> {
+        let cursor = self.server_stats().find(doc! {"server_name": server_name}, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn convert_stat(&self, message: ConvertStat) -> Result<StatConversionReport> {
+        let stat_key = format!("stats.{}", message.stat);
+        let mut report = StatConversionReport {
+            dry_run: message.dry_run,
+            ..Default::default()
+        };
+        // Stats are read and then written back, so uploads to the namespace are held back in between.
+        let _guard = match message.dry_run {
+            true => None,
+            false => Some(self.lock_namespace(&message.namespace).await),
+        };
+        if !message.dry_run {
+            self.mark_bundle_log_incomplete(&[&message.namespace]).await?;
+        }
+
+        for collection in [self.document_player_stats(), self.document_player_season_stats(), self.document_global_stats()] {
+            let options = FindOptions::builder().batch_size(ADMIN_BATCH_SIZE).build();
+            let mut cursor = collection.find(doc! {
+                "namespace": &message.namespace,
+                &stat_key: {"$exists": true},
+            }, options).await?;
+
+            let mut updates = Vec::new();
+            while let Some(document) = cursor.try_next().await? {
+                report.matched += 1;
+                let id = document.get("_id").cloned().unwrap_or(Bson::Null);
+
+                let converted = document.get_document("stats").ok()
+                    .and_then(|stats| stats.get(&message.stat))
+                    .ok_or_else(|| anyhow::anyhow!("stat is missing"))
+                    .and_then(|stat| Ok(bson::from_bson::<GameStat>(stat.clone())?))
+                    .and_then(|stat| Ok(stat.convert(message.to, message.count)?));
+
+                match converted {
+                    Ok(converted) => {
+                        report.converted += 1;
+                        updates.push(doc! {
+                            "q": {"_id": id},
+                            "u": {"$set": {&stat_key: bson::to_bson(&converted)?}},
+                        });
+                    }
+                    Err(e) => report.failed.push(DocumentFailure {
+                        document: id.to_string(),
+                        error: e.to_string(),
+                    }),
+                }
+
+                if updates.len() >= ADMIN_BATCH_SIZE as usize && !message.dry_run {
+                    self.apply_updates(collection.name(), std::mem::take(&mut updates)).await?;
+                }
+            }
+
+            if !updates.is_empty() && !message.dry_run {
+                self.apply_updates(collection.name(), updates).await?;
+            }
+        }
+
+        log::info!("{} stat '{}' in {} to {:?}: {} matched, {} converted, {} failed",
+            if message.dry_run { "Dry run converting" } else { "Converted" },
+            message.stat, message.namespace, message.to, report.matched, report.converted, report.failed.len());
+
+        Ok(report)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn rename_stats(&self, namespace: &str, stats: &HashMap<String, String>) -> Result<StatRenameReport> {
+        let _guard = self.lock_namespace(namespace).await;
+        self.mark_bundle_log_incomplete(&[namespace]).await?;
+        let mut report = StatRenameReport::default();
+        for (from, to) in stats {
+            let (from_key, to_key) = (format!("stats.{}", from), format!("stats.{}", to));
+            let renamable = doc! {"namespace": namespace, &from_key: {"$exists": true}, &to_key: {"$exists": false}};
+            let conflicting = doc! {"namespace": namespace, &from_key: {"$exists": true}, &to_key: {"$exists": true}};
+            let update = doc! {"$rename": {&from_key: &to_key}};
+
+            for collection in [self.document_player_stats(), self.document_player_season_stats(), self.document_global_stats()] {
+                let options = FindOptions::builder().projection(doc! {"_id": 1}).build();
+                let mut cursor = collection.find(conflicting.clone(), options).await?;
+                while let Some(document) = cursor.try_next().await? {
+                    report.failed.push(DocumentFailure {
+                        document: document.get("_id").cloned().unwrap_or(Bson::Null).to_string(),
+                        error: format!("stat '{}' already exists", to),
+                    });
+                }
+                report.renamed += collection.update_many(renamable.clone(), update.clone(), None).await?.modified_count;
+            }
+            self.global_stats_rollups().update_many(renamable.clone(), update.clone(), None).await?;
+            self.stat_history().update_many(renamable.clone(), update.clone(), None).await?;
+            self.server_stats().update_many(renamable, update, None).await?;
+
+            self.leaderboard_snapshots().update_many(
+                doc! {"namespace": namespace, "stat": from},
+                doc! {"$set": {"stat": to}},
+                None,
+            ).await?;
+            if self.stat_info().find_one(doc! {"namespace": namespace, "stat": to}, None).await?.is_none() {
+                self.stat_info().update_one(doc! {"namespace": namespace, "stat": from}, doc! {"$set": {"stat": to}}, None).await?;
+            }
+        }
+
+        log::info!("Renamed {} stats in {}: {} renamed, {} failed", stats.len(), namespace, report.renamed, report.failed.len());
+        Ok(report)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn merge_namespace(&self, from: &str, into: &str) -> Result<NamespaceMergeReport> {
+        // Locked in the same order by every merge, so that two merges between the same namespaces can't deadlock.
+        let (first, second) = if from < into { (from, into) } else { (into, from) };
+        let _first = self.lock_namespace(first).await;
+        let _second = match first == second {
+            true => None,
+            false => Some(self.lock_namespace(second).await),
+        };
+        self.mark_bundle_log_incomplete(&[from, into]).await?;
+        let mut report = NamespaceMergeReport::default();
+
+        let mut cursor = self.document_player_stats().find(doc! {"namespace": from}, None).await?;
+        while let Some(document) = cursor.try_next().await? {
+            let result = match bson::from_document::<PlayerGameStats>(document.clone()) {
+                Ok(source) => {
+                    let target = self.player_stats().find_one(doc! {
+                        "uuid": uuid_to_bson(&source.uuid)?,
+                        "namespace": into,
+                    }, None).await?;
+                    self.merge_stats_document(&document, &source.stats, target.map(|target| target.stats), doc! {
+                        "uuid": uuid_to_bson(&source.uuid)?,
+                        "namespace": into,
+                    }, &self.document_player_stats()).await
+                }
+                Err(e) => Err(e.into()),
+            };
+            record_merge(&mut report, &document, result);
+        }
+
+        let mut cursor = self.document_player_season_stats().find(doc! {"namespace": from}, None).await?;
+        while let Some(document) = cursor.try_next().await? {
+            let result = match (bson::from_document::<PlayerGameStats>(document.clone()), document.get("season")) {
+                (Ok(source), Some(season)) => {
+                    let target_query = doc! {
+                        "uuid": uuid_to_bson(&source.uuid)?,
+                        "namespace": into,
+                        "season": season.clone(),
+                    };
+                    let target = self.player_season_stats().find_one(target_query.clone(), None).await?;
+                    self.merge_stats_document(&document, &source.stats, target.map(|target| target.stats), target_query,
+                        &self.document_player_season_stats()).await
+                }
+                (Ok(_), None) => Err(anyhow::anyhow!("season is missing")),
+                (Err(e), _) => Err(e.into()),
+            };
+            record_merge(&mut report, &document, result);
+        }
+
+        let mut cursor = self.document_global_stats().find(doc! {"namespace": from}, None).await?;
+        while let Some(document) = cursor.try_next().await? {
+            let result = match bson::from_document::<GlobalGameStats>(document.clone()) {
+                Ok(source) => {
+                    let target = self.global_stats().find_one(doc! {"namespace": into}, None).await?;
+                    self.merge_stats_document(&document, &source.stats, target.map(|target| target.stats), doc! {
+                        "namespace": into,
+                    }, &self.document_global_stats()).await
+                }
+                Err(e) => Err(e.into()),
+            };
+            record_merge(&mut report, &document, result);
+        }
+
+        log::info!("Merged namespace {} into {}: {} documents merged, {} failed", from, into, report.merged, report.failed.len());
+        Ok(report)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn delete_stat(&self, namespace: &str, stat: &str) -> Result<StatDeletionReport> {
+        let stat_key = format!("stats.{}", stat);
+        let query = doc! {"namespace": namespace, &stat_key: {"$exists": true}};
+        let update = doc! {"$unset": {&stat_key: ""}};
+        let _guard = self.lock_namespace(namespace).await;
+        self.mark_bundle_log_incomplete(&[namespace]).await?;
+
+        let mut report = StatDeletionReport::default();
+        for collection in [self.document_player_stats(), self.document_player_season_stats()] {
+            report.stats_documents += collection.update_many(query.clone(), update.clone(), None).await?.modified_count;
+        }
+        report.global = self.document_global_stats().update_many(query.clone(), update.clone(), None).await?.modified_count > 0;
+        self.global_stats_rollups().update_many(query.clone(), update.clone(), None).await?;
+        report.history_entries = self.stat_history().update_many(query.clone(), update.clone(), None).await?.modified_count;
+        // Entries that only recorded this stat would otherwise be left with nothing in them.
+        self.stat_history().delete_many(doc! {"namespace": namespace, "stats": {}}, None).await?;
+        self.server_stats().update_many(query, update, None).await?;
+
+        let query = doc! {"namespace": namespace, "stat": stat};
+        report.leaderboard_snapshots = self.leaderboard_snapshots().delete_many(query.clone(), None).await?.deleted_count;
+        self.stat_info().delete_many(query, None).await?;
+
+        log::info!("Deleted stat '{}' in {}: {} stats documents, {} history entries, {} leaderboard snapshots",
+            stat, namespace, report.stats_documents, report.history_entries, report.leaderboard_snapshots);
+        Ok(report)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn delete_namespace(&self, namespace: &str) -> Result<NamespaceDeletionReport> {
+        let query = doc! {"namespace": namespace};
+        let _guard = self.lock_namespace(namespace).await;
+        // The namespace's logged bundles are kept, but no longer have anything to do with its stats.
+        self.mark_bundle_log_incomplete(&[namespace]).await?;
+
+        let mut report = NamespaceDeletionReport::default();
+        for collection in [self.document_player_stats(), self.document_player_season_stats()] {
+            report.stats_documents += collection.delete_many(query.clone(), None).await?.deleted_count;
+        }
+        report.global = self.document_global_stats().delete_many(query.clone(), None).await?.deleted_count > 0;
+        self.global_stats_rollups().delete_many(query.clone(), None).await?;
+        report.history_entries = self.stat_history().delete_many(query.clone(), None).await?.deleted_count;
+        report.leaderboard_snapshots = self.leaderboard_snapshots().delete_many(query.clone(), None).await?.deleted_count;
+        report.games = self.games().delete_many(query.clone(), None).await?.deleted_count;
+        report.achievements = self.achievements().delete_many(query.clone(), None).await?.deleted_count;
+        report.ratings = self.ratings().delete_many(query.clone(), None).await?.deleted_count;
+        report.playtime = self.playtime().delete_many(query.clone(), None).await?.deleted_count;
+        self.stat_info().delete_many(query.clone(), None).await?;
+        self.achievement_info().delete_many(query.clone(), None).await?;
+        self.server_stats().delete_many(query, None).await?;
+
+        log::info!("Deleted namespace {}: {} stats documents, {} games", namespace, report.stats_documents, report.games);
+        Ok(report)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_corrupt_documents(&self, limit: i64) -> Result<Vec<CorruptDocument>> {
+        let options = FindOptions::builder().sort(doc! {"_id": -1}).limit(limit).build();
+        let records: Vec<Document> = self.corrupt_stats().find(None, options).await?.try_collect().await?;
+        Ok(records.into_iter().filter_map(CorruptDocument::from_record).collect())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_corrupt_document(&self, id: ObjectId) -> Result<Option<CorruptDocument>> {
+        let record = self.corrupt_stats().find_one(doc! {"_id": id}, None).await?;
+        Ok(record.and_then(CorruptDocument::from_record))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn restore_corrupt_document(&self, id: ObjectId, repaired: Document) -> Result<RestoreOutcome> {
+        let corrupt = match self.get_corrupt_document(id).await? {
+            Some(corrupt) => corrupt,
+            None => return Ok(RestoreOutcome::NotFound),
+        };
+
+        // The target is checked before the repaired stats are merged into it, so uploads to its namespace wait.
+        let (stats, target, target_query, collection, _guard) = if corrupt.collection == "global-stats" {
+            let repaired = match bson::from_document::<GlobalGameStats>(repaired) {
+                Ok(repaired) => repaired,
+                Err(e) => return Ok(RestoreOutcome::Invalid(e.to_string())),
+            };
+            let guard = self.lock_namespace(&repaired.namespace).await;
+            let target_query = doc! {"namespace": &repaired.namespace};
+            let target = self.global_stats().find_one(target_query.clone(), None).await?.map(|target| target.stats);
+            (repaired.stats, target, target_query, self.document_global_stats(), guard)
+        } else {
+            let repaired = match bson::from_document::<PlayerGameStats>(repaired) {
+                Ok(repaired) => repaired,
+                Err(e) => return Ok(RestoreOutcome::Invalid(e.to_string())),
+            };
+            let guard = self.lock_namespace(&repaired.namespace).await;
+            let target_query = doc! {"uuid": uuid_to_bson(&repaired.uuid)?, "namespace": &repaired.namespace};
+            let target = self.player_stats().find_one(target_query.clone(), None).await?.map(|target| target.stats);
+            (repaired.stats, target, target_query, self.document_player_stats(), guard)
+        };
+
+        if let Some(target) = &target {
+            if let Err(e) = check_mergeable(&stats, target) {
+                return Ok(RestoreOutcome::Invalid(e.to_string()));
+            }
+        }
+        self.mark_bundle_log_incomplete(&[target_query.get_str("namespace")?]).await?;
+        merge_stats(&stats, &Bson::ObjectId(id), target_query.clone(), &collection).await?;
+        self.corrupt_stats().delete_one(doc! {"_id": id}, None).await?;
+        finish_merge(&Bson::ObjectId(id), target_query, &collection).await?;
+        log::info!("Restored quarantined document {} to {}", id, corrupt.collection);
+        Ok(RestoreOutcome::Restored)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn quarantine_upload(&self, upload: SuspiciousUpload) -> Result<()> {
+        self.suspicious_uploads().insert_one(upload, None).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_suspicious_uploads(&self, limit: i64) -> Result<Vec<SuspiciousUpload>> {
+        let options = FindOptions::builder().sort(doc! {"_id": -1}).limit(limit).build();
+        Ok(self.suspicious_uploads().find(None, options).await?.try_collect().await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn delete_suspicious_upload(&self, id: ObjectId) -> Result<Option<SuspiciousUpload>> {
+        Ok(self.suspicious_uploads().find_one_and_delete(doc! {"_id": id}, None).await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_stat_info(&self, namespace: &str) -> Result<HashMap<String, StatInfo>> {
+        let mut stats = self.stat_info().find(doc! {"namespace": namespace}, None).await?;
+        let mut info = HashMap::new();
+        while let Some(stat) = stats.try_next().await? {
+            info.insert(stat.stat, stat.info);
+        }
+        Ok(info)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn update_stat_info(&self, namespace: String, stats: HashMap<String, StatInfo>) -> Result<()> {
+        let updated_at = bson::DateTime::now();
+        let mut updates = Vec::new();
+        for (stat, info) in stats {
+            let filter = doc! {"namespace": &namespace, "stat": &stat};
+            let replacement = bson::to_document(&StoredStatInfo {
+                namespace: namespace.clone(),
+                stat,
+                info,
+                updated_at,
+            })?;
+            updates.push(doc! {"q": filter, "u": replacement, "upsert": true});
+        }
+        self.apply_updates(self.stat_info().name(), updates).await
+    }
+
+    async fn get_achievement_info(&self, namespace: &str) -> Result<HashMap<String, AchievementInfo>> {
+        let mut achievements = self.achievement_info().find(doc! {"namespace": namespace}, None).await?;
+        let mut info = HashMap::new();
+        while let Some(achievement) = achievements.try_next().await? {
+            info.insert(achievement.achievement, achievement.info);
+        }
+        Ok(info)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn update_achievement_info(&self, namespace: String, achievements: HashMap<String, AchievementInfo>) -> Result<()> {
+        let updated_at = bson::DateTime::now();
+        let mut updates = Vec::new();
+        for (achievement, info) in achievements {
+            let filter = doc! {"namespace": &namespace, "achievement": &achievement};
+            let replacement = bson::to_document(&StoredAchievementInfo {
+                namespace: namespace.clone(),
+                achievement,
+                info,
+                updated_at,
+            })?;
+            updates.push(doc! {"q": filter, "u": replacement, "upsert": true});
+        }
+        self.apply_updates(self.achievement_info().name(), updates).await
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn grant_achievements(&self, uuid: &Uuid, namespace: &str, achievements: &[String]) -> Result<Vec<String>> {
+        let uuid = uuid_to_bson(uuid)?;
+        let unlocked_at = bson::DateTime::now();
+        let mut granted = Vec::new();
+        for achievement in achievements {
+            let result = self.achievements().update_one(
+                doc! {"uuid": &uuid, "namespace": namespace, "achievement": achievement},
+                doc! {"$setOnInsert": {"unlocked_at": unlocked_at}},
+                UpdateOptions::builder().upsert(true).build(),
+            ).await;
+            match result {
+                Ok(result) if result.upserted_id.is_some() => granted.push(achievement.clone()),
+                Ok(_) => {}
+                // Another request granted the same achievement at the same time.
+                Err(e) if is_duplicate_key_error(&e) => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.track_players(&[uuid]).await?;
+        Ok(granted)
+    }
+
+    async fn get_player_achievements(&self, uuid: &Uuid, namespace: Option<&str>) -> Result<Vec<StoredAchievement>> {
+        let mut filter = doc! {"uuid": uuid_to_bson(uuid)?};
+        if let Some(namespace) = namespace {
+            filter.insert("namespace", namespace);
+        }
+        let options = FindOptions::builder().sort(doc! {"unlocked_at": -1}).build();
+        let cursor = self.achievements().find(filter, options).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_achievement_unlocks(&self, namespace: &str) -> Result<AchievementUnlocks> {
+        let mut unlocks = AchievementUnlocks::default();
+        let mut cursor = self.achievements().aggregate(vec![
+            doc! {"$match": {"namespace": namespace}},
+            doc! {"$group": {"_id": "$achievement", "players": {"$sum": 1}}},
+        ], None).await?;
+        while let Some(document) = cursor.try_next().await? {
+            unlocks.unlocks.insert(document.get_str("_id")?.to_string(), document.get_i32("players")? as u64);
+        }
+
+        // Players who were granted an achievement without uploading any stats are counted too.
+        let mut cursor = self.document_player_stats().aggregate(vec![
+            doc! {"$match": {"namespace": namespace}},
+            doc! {"$project": {"uuid": 1}},
+            doc! {"$unionWith": {"coll": self.achievements().name(), "pipeline": [
+                {"$match": {"namespace": namespace}},
+                {"$project": {"uuid": 1}},
+            ]}},
+            doc! {"$group": {"_id": "$uuid"}},
+            doc! {"$count": "players"},
+        ], None).await?;
+        if let Some(document) = cursor.try_next().await? {
+            unlocks.players = document.get_i32("players")? as u64;
+        }
+        Ok(unlocks)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn record_match(&self, namespace: &str, teams: &[MatchTeam]) -> Result<HashMap<Uuid, RatingChange>> {
+        let uuids = teams.iter().flat_map(|team| &team.players).map(uuid_to_bson).collect::<bson::ser::Result<Vec<_>>>()?;
+        let mut cursor = self.document_ratings().find(doc! {"namespace": namespace, "uuid": {"$in": &uuids}}, None).await?;
+        let mut ratings = HashMap::new();
+        while let Some(document) = cursor.try_next().await? {
+            let uuid = uuid_from_bson(document.get("uuid").cloned().unwrap_or(Bson::Null))?;
+            ratings.insert(uuid, document.get_f64("rating")?);
+        }
+
+        // Changes are added onto the stored ratings rather than replacing them, so that matches recorded at the same
+        // time as this one aren't lost.
+        let changes = crate::ratings::rate_match(&self.config.ratings, &ratings, teams);
+        let updated_at = bson::DateTime::now();
+        let updates = changes.iter()
+            .map(|(uuid, change)| Ok(doc! {
+                "q": {"uuid": uuid_to_bson(uuid)?, "namespace": namespace},
+                "u": [{"$set": {
+                    "rating": {"$add": [{"$ifNull": ["$rating", self.config.ratings.initial_rating]}, change.change]},
+                    "matches": {"$add": [{"$ifNull": ["$matches", 0_i64]}, 1_i64]},
+                    "updated_at": updated_at,
+                }}],
+                "upsert": true,
+            }))
+            .collect::<Result<Vec<_>>>()?;
+        self.apply_updates(self.ratings().name(), updates).await?;
+        self.track_players(&uuids).await?;
+        Ok(changes)
+    }
+
+    async fn get_player_ratings(&self, uuid: &Uuid) -> Result<Vec<StoredRating>> {
+        let cursor = self.ratings().find(doc! {"uuid": uuid_to_bson(uuid)?}, None).await?;
+        Ok(cursor.try_collect().await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_rating_leaderboard(&self, message: GetRatingLeaderboard) -> Result<Vec<LeaderboardEntry>> {
+        let mut pipeline = vec![
+            doc! {"$match": {"namespace": &message.namespace}},
+            doc! {"$sort": {"rating": -1, "uuid": 1}},
+        ];
+        // Private players are filtered out before limiting, so that hiding them doesn't shorten the leaderboard.
+        if message.include_private {
+            pipeline.push(doc! {"$limit": message.limit});
+        }
+        pipeline.push(doc! {"$lookup": {
+            "from": "players",
+            "localField": "uuid",
+            "foreignField": "uuid",
+            "as": "player",
+        }});
+        if !message.include_private {
+            pipeline.push(doc! {"$match": {"player.private": {"$ne": true}}});
+            pipeline.push(doc! {"$limit": message.limit});
+        }
+        pipeline.push(doc! {"$project": {
+            "uuid": 1,
+            "rating": 1,
+            "username": {"$arrayElemAt": ["$player.username", 0]},
+        }});
+
+        let mut cursor = self.document_ratings().aggregate(pipeline, None).await?;
+        let mut entries = Vec::new();
+        while let Some(document) = cursor.try_next().await? {
+            let uuid = document.get("uuid").cloned().unwrap_or(Bson::Null);
+            entries.push(LeaderboardEntry {
+                rank: entries.len() as u32 + 1,
+                uuid: uuid_from_bson(uuid)?,
+                username: document.get_str("username").ok().map(str::to_string),
+                value: document.get_f64("rating")?,
+            });
+        }
+        Ok(entries)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_namespaces(&self) -> Result<Vec<String>> {
+        let mut namespaces = BTreeSet::new();
+        for collection in [self.document_player_stats(), self.document_global_stats()] {
+            for namespace in collection.distinct("namespace", None, None).await? {
+                if let Bson::String(namespace) = namespace {
+                    namespaces.insert(namespace);
+                }
+            }
+        }
+        Ok(namespaces.into_iter().collect())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_global_stats(&self, namespace: &str) -> Result<Option<HashMap<String, f64>>> {
+        let stats = self.global_stats().find_one(doc! {"namespace": namespace}, None).await?;
+        Ok(stats.map(|stats| stat_values(&self.config, namespace, stats.stats)))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_global_stats_delta(&self, namespace: &str, window_hours: u32) -> Result<HashMap<String, f64>> {
+        let now = bson::DateTime::now().timestamp_millis();
+        let start = now - (window_hours as i64 * HOUR_MILLIS);
+        let start = bson::DateTime::from_millis(start - start.rem_euclid(HOUR_MILLIS));
+
+        // Rollups are read oldest first, so that the latest values of the window are those of its last hour.
+        let options = FindOptions::builder().sort(doc! {"hour": 1}).build();
+        let rollups: Vec<GlobalStatsRollup> = self.global_stats_rollups().find(doc! {
+            "namespace": namespace,
+            "hour": {"$gte": start},
+        }, options).await?.try_collect().await?;
+        Ok(sum_rollups(rollups.into_iter().map(|rollup| rollup.stats)))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn insert_game(&self, game: Game) -> Result<()> {
+        self.games().insert_one(game, None).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_games(&self, message: GetGames) -> Result<Vec<Game>> {
+        let mut filter = doc! {};
+        if let Some(player) = &message.player {
+            filter.insert("participants.uuid", uuid_to_bson(player)?);
+        }
+        match &message.namespace {
+            Some(namespace) => filter.insert("namespace", namespace),
+            None => filter.insert("namespace", doc! {"$nin": &message.hidden_namespaces}),
+        };
+
+        let options = FindOptions::builder().sort(doc! {"ended_at": -1}).limit(message.limit).build();
+        let mut games: Vec<Game> = self.games().find(filter, options).await?.try_collect().await?;
+
+        if !message.include_private {
+            let mut participants: Vec<Bson> = Vec::new();
+            for game in &games {
+                for participant in &game.participants {
+                    participants.push(uuid_to_bson(&participant.uuid)?);
+                }
+            }
+            let private: Vec<PlayerProfile> = self.player_profiles()
+                .find(doc! {"uuid": {"$in": participants}, "private": true}, None).await?
+                .try_collect().await?;
+            for game in &mut games {
+                game.participants.retain(|participant| !private.iter().any(|profile| profile.uuid == participant.uuid));
+            }
+        }
+        Ok(games)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_leaderboard(&self, message: GetLeaderboard) -> Result<Vec<LeaderboardEntry>> {
+        let direction = match message.order {
+            LeaderboardOrder::Descending => -1,
+            LeaderboardOrder::Ascending => 1,
+        };
+
+        let mut filter = doc! {
+            "namespace": &message.namespace,
+            format!("stats.{}", message.stat): {"$exists": true},
+        };
+        let collection = match message.season {
+            Some(season) => {
+                filter.insert("season", season);
+                self.document_player_season_stats()
+            }
+            None => self.document_player_stats(),
+        };
+
+        let mut pipeline = vec![
+            doc! {"$match": filter},
+            doc! {"$project": {"uuid": 1, "value": ranked_value(&message.stat)}},
+            doc! {"$sort": {"value": direction, "uuid": 1}},
+        ];
+        // Private players are filtered out before limiting, so that hiding them doesn't shorten the leaderboard.
+        if message.include_private {
+            pipeline.push(doc! {"$limit": message.limit});
+        }
+        pipeline.push(doc! {"$lookup": {
+            "from": "players",
+            "localField": "uuid",
+            "foreignField": "uuid",
+            "as": "player",
+        }});
+        if !message.include_private {
+            pipeline.push(doc! {"$match": {"player.private": {"$ne": true}}});
+            pipeline.push(doc! {"$limit": message.limit});
+        }
+        pipeline.push(doc! {"$project": {
+            "uuid": 1,
+            "value": 1,
+            "username": {"$arrayElemAt": ["$player.username", 0]},
+        }});
+
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        let mut entries = Vec::new();
+        while let Some(document) = cursor.try_next().await? {
+            let uuid = document.get("uuid").cloned().unwrap_or(Bson::Null);
+            entries.push(LeaderboardEntry {
+                rank: entries.len() as u32 + 1,
+                uuid: uuid_from_bson(uuid)?,
+                username: document.get_str("username").ok().map(str::to_string),
+                value: document.get_f64("value")?,
+            });
+        }
+        Ok(entries)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_stat_rank(&self, message: GetStatRank) -> Result<Option<StatRank>> {
+        let mut filter = doc! {
+            "namespace": &message.namespace,
+            format!("stats.{}", message.stat): {"$exists": true},
+        };
+        let collection = match message.season {
+            Some(season) => {
+                filter.insert("season", season);
+                self.document_player_season_stats()
+            }
+            None => self.document_player_stats(),
+        };
+
+        let mut player_filter = filter.clone();
+        player_filter.insert("uuid", uuid_to_bson(&message.uuid)?);
+        let mut cursor = collection.aggregate(vec![
+            doc! {"$match": player_filter},
+            doc! {"$project": {"value": ranked_value(&message.stat)}},
+        ], None).await?;
+        let value = match cursor.try_next().await? {
+            Some(document) => document.get_f64("value")?,
+            None => return Ok(None),
+        };
+
+        let better = match message.order {
+            LeaderboardOrder::Descending => "$gt",
+            LeaderboardOrder::Ascending => "$lt",
+        };
+        let mut pipeline = vec![
+            doc! {"$match": filter},
+            doc! {"$project": {"uuid": 1, "value": ranked_value(&message.stat)}},
+        ];
+        if !message.include_private {
+            pipeline.push(doc! {"$lookup": {
+                "from": "players",
+                "localField": "uuid",
+                "foreignField": "uuid",
+                "as": "player",
+            }});
+            pipeline.push(doc! {"$match": {"player.private": {"$ne": true}}});
+        }
+        pipeline.push(doc! {"$facet": {
+            "ahead": [{"$match": {"value": {better: value}}}, {"$count": "count"}],
+            "players": [{"$count": "count"}],
+        }});
+
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        let counts = cursor.try_next().await?.unwrap_or_default();
+        // `$count` outputs nothing rather than zero when there is nothing to count.
+        let count = |facet: &str| -> u64 {
+            counts.get_array(facet).ok()
+                .and_then(|counts| counts.first())
+                .and_then(Bson::as_document)
+                .and_then(|count| count.get("count"))
+                .and_then(|count| count.as_i32().map(i64::from).or_else(|| count.as_i64()))
+                .map_or(0, |count| count as u64)
+        };
+        Ok(Some(StatRank {
+            value,
+            ahead: count("ahead"),
+            players: count("players"),
+        }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_stat_summary(&self, message: GetStatSummary) -> Result<Option<StatSummaryResponse>> {
+        let mut filter = doc! {
+            "namespace": &message.namespace,
+            format!("stats.{}", message.stat): {"$exists": true},
+        };
+        let collection = match message.season {
+            Some(season) => {
+                filter.insert("season", season);
+                self.document_player_season_stats()
+            }
+            None => self.document_player_stats(),
+        };
+        let values = vec![
+            doc! {"$match": filter},
+            doc! {"$project": {"value": ranked_value(&message.stat)}},
+            doc! {"$match": {"value": {"$ne": null}}},
+        ];
+
+        let mut pipeline = values.clone();
+        pipeline.push(doc! {"$group": {
+            "_id": null,
+            "players": {"$sum": 1},
+            "sum": {"$sum": "$value"},
+            "mean": {"$avg": "$value"},
+            "stddev": {"$stdDevPop": "$value"},
+        }});
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+        let summary = match cursor.try_next().await? {
+            Some(summary) => summary,
+            None => return Ok(None),
+        };
+        let players = summary.get_i32("players")? as u64;
+
+        // The median is the middle value, or the mean of the two middle values if there is an even number of them.
+        let mut pipeline = values;
+        pipeline.push(doc! {"$sort": {"value": 1}});
+        pipeline.push(doc! {"$skip": ((players - 1) / 2) as i64});
+        pipeline.push(doc! {"$limit": if players.is_multiple_of(2) { 2 } else { 1 }});
+        let middle: Vec<Document> = collection.aggregate(pipeline, None).await?.try_collect().await?;
+        let middle = middle.iter().map(|document| document.get_f64("value")).collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Some(StatSummaryResponse {
+            players,
+            sum: summary.get_f64("sum")?,
+            mean: summary.get_f64("mean")?,
+            median: middle.iter().sum::<f64>() / middle.len() as f64,
+            stddev: summary.get_f64("stddev")?,
+        }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_stat_history(&self, message: GetStatHistory) -> Result<Vec<StatSnapshot>> {
+        let mut recorded_at = doc! {};
+        if let Some(from) = message.from {
+            recorded_at.insert("$gte", from);
+        }
+        if let Some(until) = message.until {
+            recorded_at.insert("$lt", until);
+        }
+        let stat_key = format!("stats.{}", message.stat);
+        let mut filter = doc! {
+            "uuid": uuid_to_bson(&message.uuid)?,
+            "namespace": &message.namespace,
+            &stat_key: {"$exists": true},
+        };
+        if !recorded_at.is_empty() {
+            filter.insert("recorded_at", recorded_at);
+        }
+
+        let options = FindOptions::builder()
+            .projection(doc! {"uuid": 1, "namespace": 1, "recorded_at": 1, &stat_key: 1})
+            .sort(doc! {"recorded_at": 1})
+            .limit(message.limit)
+            .build();
+        let snapshots = self.stat_history().find(filter, options).await?;
+        Ok(snapshots.try_collect().await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_stat_histogram(&self, message: GetStatHistogram) -> Result<Option<Vec<HistogramBucket>>> {
+        let (collection, filter) = match &message.uuid {
+            Some(uuid) => (self.document_player_stats(), doc! {"uuid": uuid_to_bson(uuid)?, "namespace": &message.namespace}),
+            None => (self.document_global_stats(), doc! {"namespace": &message.namespace}),
+        };
+        let options = FindOneOptions::builder().projection(doc! {format!("stats.{}", message.stat): 1}).build();
+        let stat = collection.find_one(filter, options).await?
+            .and_then(|document| document.get_document("stats").ok()?.get_document(&message.stat).ok().cloned());
+        match stat {
+            Some(stat) => Ok(bson::from_document::<GameStat>(stat)?.histogram()),
+            None => Ok(None),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_current_season(&self) -> Result<StoredSeason> {
+        let season = self.seasons().find_one(doc! {"_id": SEASON_ID}, None).await?;
+        Ok(season.unwrap_or_else(|| StoredSeason {
+            id: SEASON_ID.to_string(),
+            season: 1,
+            started_at: None,
+        }))
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn start_season(&self) -> Result<StoredSeason> {
+        let update = vec![doc! {"$set": {
+            "season": {"$add": [{"$ifNull": ["$season", 1]}, 1]},
+            "started_at": "$$NOW",
+        }}];
+        let options = FindOneAndUpdateOptions::builder()
+            .upsert(true)
+            .return_document(ReturnDocument::After)
+            .build();
+        let season = self.seasons().find_one_and_update(doc! {"_id": SEASON_ID}, UpdateModifications::Pipeline(update), options).await?;
+        let season = season.ok_or_else(|| anyhow::anyhow!("season document was not upserted"))?;
+        log::info!("Started season {}", season.season);
+        Ok(season)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn prune_global_stats_rollups(&self) -> Result<u64> {
+        let now = bson::DateTime::now().timestamp_millis();
+        let cutoff = bson::DateTime::from_millis(now - self.config.global_stats_rollup_retention_hours as i64 * HOUR_MILLIS);
+        let res = self.global_stats_rollups().delete_many(doc! {
+            "hour": {"$lt": cutoff},
+        }, None).await?;
+        log::debug!("Pruned {} global stats rollups", res.deleted_count);
+        Ok(res.deleted_count)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn prune_bundle_log(&self) -> Result<u64> {
+        let retention_days = match self.config.bundle_log.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let now = bson::DateTime::now().timestamp_millis();
+        let cutoff = bson::DateTime::from_millis(now - retention_days as i64 * 24 * HOUR_MILLIS);
+        let pruned = doc! {"received_at": {"$lt": cutoff}};
+        let namespaces = self.bundle_log().distinct("namespace", pruned.clone(), None).await?;
+        let namespaces: Vec<&str> = namespaces.iter().filter_map(Bson::as_str).collect();
+        self.mark_bundle_log_incomplete(&namespaces).await?;
+        let res = self.bundle_log().delete_many(pruned, None).await?;
+        log::debug!("Pruned {} logged bundles", res.deleted_count);
+        Ok(res.deleted_count)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn prune_stat_history(&self) -> Result<u64> {
+        let retention_days = match self.config.stat_history.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let now = bson::DateTime::now().timestamp_millis();
+        let cutoff = bson::DateTime::from_millis(now - retention_days as i64 * 24 * HOUR_MILLIS);
+        let res = self.stat_history().delete_many(doc! {
+            "recorded_at": {"$lt": cutoff},
+        }, None).await?;
+        log::debug!("Pruned {} stat history snapshots", res.deleted_count);
+        Ok(res.deleted_count)
+    }
+
+    async fn save_leaderboard_snapshot(&self, snapshot: StoredLeaderboardSnapshot) -> Result<()> {
+        self.leaderboard_snapshots().insert_one(snapshot, None).await?;
+        Ok(())
+    }
+
+    async fn get_leaderboard_snapshot(&self, message: GetLeaderboardSnapshot) -> Result<Option<StoredLeaderboardSnapshot>> {
+        let filter = doc! {
+            "namespace": &message.namespace,
+            "stat": &message.stat,
+            "order": bson::to_bson(&message.order)?,
+            "taken_at": {"$lte": message.at},
+        };
+        let options = FindOneOptions::builder().sort(doc! {"taken_at": -1}).build();
+        Ok(self.leaderboard_snapshots().find_one(filter, options).await?)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn prune_leaderboard_snapshots(&self) -> Result<u64> {
+        let retention_days = match self.config.leaderboard_snapshots.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let now = bson::DateTime::now().timestamp_millis();
+        let cutoff = bson::DateTime::from_millis(now - retention_days as i64 * 24 * HOUR_MILLIS);
+        let res = self.leaderboard_snapshots().delete_many(doc! {
+            "taken_at": {"$lt": cutoff},
+        }, None).await?;
+        log::debug!("Pruned {} leaderboard snapshots", res.deleted_count);
+        Ok(res.deleted_count)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn prune_bundle_ids(&self) -> Result<u64> {
+        let now = bson::DateTime::now().timestamp_millis();
+        let cutoff = bson::DateTime::from_millis(now - self.config.bundle_id_retention_hours as i64 * HOUR_MILLIS);
+        let res = self.applied_bundles().delete_many(doc! {
+            "applied_at": {"$lt": cutoff},
+        }, None).await?;
+        log::debug!("Pruned {} applied bundle ids", res.deleted_count);
+        Ok(res.deleted_count)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn rebuild_aggregates(&self, message: RebuildAggregates) -> Result<AggregateRebuildReport> {
+        let mut report = AggregateRebuildReport {
+            mode: message.mode,
+            replayed: 0,
+            skipped: 0,
+            failed: Vec::new(),
+            mismatched: 0,
+            mismatches: Vec::new(),
+            replaced: 0,
+        };
+        let scope = match &message.namespace {
+            Some(namespace) => doc! {"namespace": namespace},
+            None => doc! {},
+        };
+        // Anything that the log doesn't cover would be lost by replacing the aggregates with what it adds up to.
+        let replaced = match (message.mode, &message.namespace) {
+            (RebuildMode::Replace, Some(namespace)) if self.is_bundle_log_complete(namespace).await? => Some(namespace),
+            (RebuildMode::Replace, Some(namespace)) => return Err(IncompleteBundleLog(namespace.clone()).into()),
+            (RebuildMode::Replace, None) => anyhow::bail!("only the aggregates of a single namespace can be replaced"),
+            (RebuildMode::Verify, _) => None,
+        };
+
+        self.rebuilt_player_stats().drop(None).await?;
+        self.rebuilt_global_stats().drop(None).await?;
+
+        let mut filter = scope.clone();
+        let mut received_at = Document::new();
+        if let Some(from) = message.from {
+            received_at.insert("$gte", from);
+        }
+        if let Some(until) = message.until {
+            received_at.insert("$lt", until);
+        }
+        // Bundles logged after a replacement starts are replayed while uploads to the namespace are held back, just
+        // before the aggregates are replaced. Taking the lock first means that none are still being logged.
+        let started = match replaced {
+            Some(namespace) => {
+                let _guard = self.lock_namespace(namespace).await;
+                let started = bson::DateTime::now();
+                received_at.insert("$lt", started);
+                Some(started)
+            }
+            None => None,
+        };
+        if !received_at.is_empty() {
+            filter.insert("received_at", received_at);
+        }
+        self.replay_bundle_log(filter, &mut report).await?;
+
+        let _guard = match (replaced, started) {
+            (Some(namespace), Some(started)) => {
+                let guard = self.lock_namespace(namespace).await;
+                let mut filter = scope.clone();
+                filter.insert("received_at", doc! {"$gte": started});
+                self.replay_bundle_log(filter, &mut report).await?;
+                Some(guard)
+            }
+            _ => None,
+        };
+
+        self.compare_rebuilt_aggregates(&scope, &mut report).await?;
+
+        if let Some(namespace) = replaced {
+            // The log may have stopped covering the namespace since the rebuild started, such as if it was pruned.
+            if !self.is_bundle_log_complete(namespace).await? {
+                return Err(IncompleteBundleLog(namespace.clone()).into());
+            }
+            if !report.failed.is_empty() {
+                anyhow::bail!("{} logged bundles of {} couldn't be replayed, so its aggregates weren't replaced", report.failed.len(), namespace);
+            }
+
+            for (live, rebuilt) in [
+                (self.document_player_stats(), self.rebuilt_player_stats()),
+                (self.document_global_stats(), self.rebuilt_global_stats()),
+            ] {
+                live.delete_many(scope.clone(), None).await?;
+                let options = FindOptions::builder().batch_size(ADMIN_BATCH_SIZE).build();
+                let mut cursor = rebuilt.find(scope.clone(), options).await?;
+                let mut batch = Vec::new();
+                while let Some(document) = cursor.try_next().await? {
+                    batch.push(document);
+                    if batch.len() >= ADMIN_BATCH_SIZE as usize {
+                        report.replaced += batch.len() as u64;
+                        live.insert_many(std::mem::take(&mut batch), None).await?;
+                    }
+                }
+                if !batch.is_empty() {
+                    report.replaced += batch.len() as u64;
+                    live.insert_many(batch, None).await?;
+                }
+            }
+        }
+
+        log::info!("Rebuilt aggregates from {} logged bundles ({} skipped, {} failed): {} stats differed, {} documents replaced",
+                report.replayed, report.skipped, report.failed.len(), report.mismatched, report.replaced);
+        Ok(report)
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn acquire_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<bool> {
+        let now = bson::DateTime::now();
+        let expires = bson::DateTime::from_millis(now.timestamp_millis() + ttl.as_millis() as i64);
+        let options = UpdateOptions::builder().upsert(true).build();
+        let res = self.leases().update_one(doc! {
+            "_id": name,
+            "$or": [{"holder": holder}, {"expires": {"$lte": now}}],
+        }, doc! {
+            "$set": {"holder": holder, "expires": expires},
+        }, options).await;
+
+        match res {
+            Ok(_) => Ok(true),
+            // The lease exists but didn't match the filter, so the upsert tried to insert a second one.
+            Err(e) if is_duplicate_key_error(&e) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn release_lease(&self, name: &str, holder: &str) -> Result<()> {
+        self.leases().delete_one(doc! {"_id": name, "holder": holder}, None).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn start_job_run(&self, run: &JobRun) -> Result<()> {
+        self.job_runs().insert_one(run, None).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn finish_job_run(&self, id: ObjectId, result: &std::result::Result<u64, String>) -> Result<()> {
+        let update = match result {
+            Ok(items_processed) => doc! {
+                "finished_at": bson::DateTime::now(),
+                "outcome": "succeeded",
+                "items_processed": *items_processed as i64,
+            },
+            Err(error) => doc! {
+                "finished_at": bson::DateTime::now(),
+                "outcome": "failed",
+                "error": error,
+            },
+        };
+        self.job_runs().update_one(doc! {"_id": id}, doc! {"$set": update}, None).await?;
+        Ok(())
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn get_job_runs(&self, job: Option<&str>, limit: i64) -> Result<Vec<JobRun>> {
+        let filter = job.map(|job| doc! {"job": job});
+        let options = FindOptions::builder()
+            .sort(doc! {"started_at": -1})
+            .limit(limit)
+            .build();
+        Ok(self.job_runs().find(filter, options).await?.try_collect().await?)
+    }
+
+    async fn backup_manifest(&self) -> Result<BackupManifest> {
+        Ok(BackupManifest {
+            schema_version: Migrations::schema_version(self).await?,
+            collections: BACKUP_COLLECTIONS.iter().map(|collection| collection.to_string()).collect(),
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(collection = %collection))]
+    async fn export_documents(&self, collection: &str, after: Option<serde_json::Value>, limit: i64) -> Result<BackupBatch> {
+        let filter = match after {
+            Some(after) => doc! {"_id": {"$gt": Bson::try_from(after)?}},
+            None => doc! {},
+        };
+        let options = FindOptions::builder().sort(doc! {"_id": 1}).limit(limit).build();
+        let documents: Vec<Document> = self.backup_collection(collection)?.find(filter, options).await?.try_collect().await?;
+
+        let next = match documents.last() {
+            Some(last) if documents.len() as i64 >= limit => last.get("_id").cloned().map(Bson::into_canonical_extjson),
+            _ => None,
+        };
+        Ok(BackupBatch {
+            documents: documents.into_iter().map(|document| Bson::Document(document).into_canonical_extjson()).collect(),
+            next,
+        })
+    }
+
+    #[tracing::instrument(skip_all, fields(collection = %collection))]
+    async fn import_documents(&self, collection: &str, documents: Vec<serde_json::Value>) -> Result<u64> {
+        let documents = documents.into_iter()
+            .map(|document| match Bson::try_from(document)? {
+                Bson::Document(document) => Ok(document),
+                other => anyhow::bail!("expected a document, but got {}", other),
+            })
+            .collect::<Result<Vec<Document>>>()?;
+        if documents.is_empty() {
+            return Ok(0);
+        }
+
+        // Restored stats aren't in the bundle log of this database, even if it was restored too.
+        if ["player-stats", "player-season-stats", "global-stats"].contains(&collection) {
+            let namespaces: BTreeSet<&str> = documents.iter().filter_map(|document| document.get_str("namespace").ok()).collect();
+            self.mark_bundle_log_incomplete(&namespaces.into_iter().collect::<Vec<_>>()).await?;
+        }
+
+        let count = documents.len() as u64;
+        let options = InsertManyOptions::builder().ordered(false).build();
+        match self.backup_collection(collection)?.insert_many(documents, options).await {
+            Ok(res) => Ok(res.inserted_ids.len() as u64),
+            // Every document that doesn't already exist is still inserted, as the inserts aren't ordered.
+            Err(e) => match &*e.kind {
+                ErrorKind::BulkWrite(BulkWriteFailure { write_errors: Some(errors), write_concern_error: None, .. })
+                    if errors.iter().all(|error| error.code == 11000) => Ok(count - errors.len() as u64),
+                _ => Err(e.into()),
+            },
+        }
+    }
+}
+
+impl MongoDatabaseHandler {
+    fn backup_collection(&self, collection: &str) -> Result<Collection<Document>> {
+        if !BACKUP_COLLECTIONS.contains(&collection) {
+            anyhow::bail!("'{}' isn't a collection that is backed up", collection);
+        }
+        Ok(self.database().collection(collection))
+    }
+}
+
+/// Adds the stats of the document `source` onto the document matching `target_query`, unless they were already added
+/// by a merge that was interrupted before `source` was removed. Once it has been removed, [finish_merge] must be called.
+async fn merge_stats(stats: &HashMap<String, GameStat>, source: &Bson, target_query: Document, collection: &Collection<Document>) -> Result<()> {
+    let mut update = combine_updates(stats.iter().map(|(name, stat)| stat.create_merge_operation(name)));
+    if update.is_empty() {
+        return Ok(());
+    }
+
+    let mut applied_query = target_query.clone();
+    applied_query.insert(MERGED_FROM, source.clone());
+    if collection.count_documents(applied_query, None).await? > 0 {
+        return Ok(());
+    }
+
+    update.insert("$addToSet", doc! {MERGED_FROM: source.clone()});
+    let options = UpdateOptions::builder().upsert(true).build();
+    collection.update_one(target_query, update, options).await?;
+    Ok(())
+}
+
+/// Forgets that the stats of `source` were merged onto the document matching `target_query`, after it was removed.
+async fn finish_merge(source: &Bson, target_query: Document, collection: &Collection<Document>) -> Result<()> {
+    collection.update_one(target_query, doc! {"$pull": {MERGED_FROM: source.clone()}}, None).await?;
+    Ok(())
+}
+
+/// An error applying a bundle after some of its stats may have been written. It isn't [transient](is_transient_error),
+/// so the bundle isn't applied again.
+#[derive(Debug)]
+struct PartlyApplied(anyhow::Error);
+
+impl std::fmt::Display for PartlyApplied {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the bundle may have been partly applied: {:#}", self.0)
+    }
+}
+
+impl std::error::Error for PartlyApplied {}
+
+/// Whether an error means that an operation never reached the database, so that retrying it can't apply it twice.
+/// The driver already retries other errors where that is safe.
+fn is_transient_error(error: &mongodb::error::Error) -> bool {
+    matches!(&*error.kind, ErrorKind::ServerSelection { .. } | ErrorKind::ConnectionPoolCleared { .. })
+}
+
+fn is_namespace_exists_error(error: &mongodb::error::Error) -> bool {
+    matches!(&*error.kind, ErrorKind::Command(e) if e.code == 48)
+}
+
+fn is_duplicate_key_error(error: &mongodb::error::Error) -> bool {
+    match &*error.kind {
+        ErrorKind::Write(WriteFailure::WriteError(e)) => e.code == 11000,
+        ErrorKind::Command(e) => e.code == 11000,
+        _ => false,
+    }
+}
+
+/// The expression that leaderboards rank players by: the value of a stat, or the average of a rolling average.
+fn ranked_value(stat: &str) -> Document {
+    let value_key = format!("$stats.{}.value", stat);
+    doc! {"$toDouble": {"$cond": [
+        {"$eq": [{"$type": &value_key}, "object"]},
+        {"$divide": [format!("{}.total", value_key), format!("{}.count", value_key)]},
+        &value_key,
+    ]}}
+}
+
+/// Combines several update documents into one, merging the fields of each update operator.
+fn combine_updates(updates: impl Iterator<Item = Document>) -> Document {
+    let mut combined = Document::new();
+    for update in updates {
+        for (operator, fields) in update {
+            if let Bson::Document(fields) = fields {
+                match combined.get_document_mut(&operator) {
+                    Ok(existing) => existing.extend(fields),
+                    Err(_) => {
+                        combined.insert(operator, fields);
+                    }
+                }
+            }
+        }
+    }
+    combined
+}
+
+fn record_merge(report: &mut NamespaceMergeReport, document: &Document, result: Result<()>) {
+    match result {
+        Ok(()) => report.merged += 1,
+        Err(e) => report.failed.push(DocumentFailure {
+            document: document.get("_id").cloned().unwrap_or(Bson::Null).to_string(),
+            error: e.to_string(),
+        }),
+    }
+}
+
+/// Records each stat that differs between a live stats document and its rebuilt counterpart, either of which may be
+/// missing.
+fn compare_stats_documents(live: Option<&Document>, rebuilt: Option<&Document>, report: &mut AggregateRebuildReport) -> Result<()> {
+    let document = live.or(rebuilt).expect("at least one document is compared");
+    let namespace = document.get_str("namespace")?.to_string();
+    let player = match document.get("uuid") {
+        Some(uuid) => Some(uuid_from_bson(uuid.clone())?),
+        None => None,
+    };
+
+    let stats = |document: Option<&Document>| -> Result<HashMap<String, f64>> {
+        let stats: HashMap<String, GameStat> = match document {
+            Some(document) => bson::from_document(document.get_document("stats").cloned().unwrap_or_default())?,
+            None => HashMap::new(),
+        };
+        Ok(stats.into_iter().map(|(name, stat)| (name, stat.into())).collect())
+    };
+    let live = stats(live)?;
+    let rebuilt = stats(rebuilt)?;
+
+    let mut names: Vec<&String> = live.keys().chain(rebuilt.keys()).collect();
+    names.sort();
+    names.dedup();
+    for name in names {
+        let live = live.get(name).copied();
+        let rebuilt = rebuilt.get(name).copied();
+        let matches = match (live, rebuilt) {
+            (Some(live), Some(rebuilt)) => (live - rebuilt).abs() <= 1e-9 * live.abs().max(rebuilt.abs()).max(1.0),
+            _ => false,
+        };
+        if !matches {
+            report.mismatched += 1;
+            if report.mismatches.len() < MAX_REPORTED_MISMATCHES {
+                report.mismatches.push(StatMismatch {
+                    namespace: namespace.clone(),
+                    player,
+                    stat: name.clone(),
+                    live,
+                    rebuilt,
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Builds a `$set` document converting any int32 values of int stats in the given stats document to int64.
+fn widen_int_stats_update(document: &Document) -> Document {
+    let mut update = Document::new();
+    let stats = match document.get_document("stats") {
+        Ok(stats) => stats,
+        Err(_) => return update,
+    };
+
+    for (name, stat) in stats {
+        let stat = match stat.as_document() {
+            Some(stat) => stat,
+            None => continue,
+        };
+        match stat.get_str("type") {
+            Ok("int_total") => {
+                if let Ok(value) = stat.get_i32("value") {
+                    update.insert(format!("stats.{}.value", name), value as i64);
+                }
+            }
+            Ok("int_rolling_average") => {
+                if let Ok(total) = stat.get_document("value").and_then(|value| value.get_i32("total")) {
+                    update.insert(format!("stats.{}.value.total", name), total as i64);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    update
 }
@@ -0,0 +1,259 @@
+//! The GraphQL API served at `POST /graphql`, which lets a page fetch a player's profile, stats and games in a single
+//! request. It reads through the same store messages as the REST API, and hides the same things from the public view.
+
+use async_graphql::{ComplexObject, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use tokio::time::Instant;
+use uuid::Uuid;
+use xtra::{Address, Handler};
+
+use crate::config::Config;
+use crate::model::{GameResponse, PlayerProfile, StoredRating};
+use crate::mojang::UsernameResolver;
+use crate::store::{DeadlineExceeded, DeadlineMessage, GetGames, GetGlobalStats, GetPlayerProfile, GetPlayerProfileByName, GetPlayerRatings, GetPlayerStats, StoreHandler, WithDeadline};
+use crate::web::{self, View};
+
+/// How deeply queries may nest fields, which bounds the work a single request can ask for.
+const MAX_DEPTH: usize = 8;
+/// The most fields a query may select, counting each field of every object once.
+const MAX_COMPLEXITY: usize = 500;
+
+pub type GraphQlSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+/// Builds the schema of the API. Each request must be given its [View] and its deadline as data.
+pub fn schema(config: &Config, database: Address<StoreHandler>, usernames: UsernameResolver) -> GraphQlSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(config.clone())
+        .data(database)
+        .data(usernames)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY)
+        .finish()
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Looks up a player by UUID.
+    async fn player(&self, ctx: &Context<'_>, uuid: String) -> async_graphql::Result<Option<Player>> {
+        let uuid = Uuid::parse_str(&uuid).map_err(|_| format!("invalid player UUID '{}'", uuid))?;
+        let profile = send(ctx, GetPlayerProfile(uuid)).await?;
+        match profile {
+            Some(mut profile) => {
+                let (usernames, view, deadline) = (ctx.data_unchecked::<UsernameResolver>(), *ctx.data_unchecked::<View>(), *ctx.data_unchecked::<Instant>());
+                web::resolve_username(usernames, &mut profile, view, deadline).await;
+                Ok(Some(Player::load(ctx, profile).await?))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up a player by their current username.
+    async fn player_by_name(&self, ctx: &Context<'_>, username: String) -> async_graphql::Result<Option<Player>> {
+        match send(ctx, GetPlayerProfileByName(username)).await? {
+            // Resolving the name of a private player would reveal the username that their profile hides.
+            Some(profile) if *ctx.data_unchecked::<View>() == View::Public && profile.private => Ok(None),
+            Some(profile) => Ok(Some(Player::load(ctx, profile).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// The global stats of a namespace, or `null` if it has none.
+    async fn global_stats(&self, ctx: &Context<'_>, namespace: String) -> async_graphql::Result<Option<NamespaceStats>> {
+        let (config, view) = (ctx.data_unchecked::<Config>(), *ctx.data_unchecked::<View>());
+        let namespace = config.canonical_namespace(&namespace).to_string();
+        if !view.can_see_namespace(config, &namespace) {
+            return Ok(None);
+        }
+        let stats = send(ctx, GetGlobalStats(namespace.clone())).await?;
+        Ok(stats.map(|stats| NamespaceStats::new(namespace, stats)))
+    }
+
+    /// The most recent games, optionally only those of one namespace.
+    async fn games(&self, ctx: &Context<'_>, namespace: Option<String>, #[graphql(default = 20)] limit: i64) -> async_graphql::Result<Vec<Game>> {
+        games(ctx, None, namespace, limit).await
+    }
+}
+
+/// A player's profile. Stats and games are only fetched if they are selected.
+#[derive(SimpleObject)]
+#[graphql(complex)]
+pub struct Player {
+    uuid: String,
+    username: Option<String>,
+    /// Whether the player's stats are hidden from the public API. Only given to tokens with the `read_private` scope.
+    private: Option<bool>,
+    /// When the player was first seen, in RFC 3339 format.
+    first_seen: Option<String>,
+    /// When the player was last seen, in RFC 3339 format.
+    last_seen: Option<String>,
+    ratings: Vec<Rating>,
+    /// Whether the player was anonymized, so that they have no username.
+    anonymized: bool,
+    #[graphql(skip)]
+    id: Uuid,
+    /// Whether the player is private and the request can't see private players.
+    #[graphql(skip)]
+    hidden: bool,
+}
+
+impl Player {
+    async fn load(ctx: &Context<'_>, profile: PlayerProfile) -> async_graphql::Result<Player> {
+        let (config, view) = (ctx.data_unchecked::<Config>(), *ctx.data_unchecked::<View>());
+        let ratings: Vec<StoredRating> = send(ctx, GetPlayerRatings(profile.uuid)).await?;
+        let hidden = view == View::Public && profile.private;
+        let response = view.filter_profile(config, profile, ratings);
+
+        let mut ratings: Vec<Rating> = response.ratings.into_iter()
+            .map(|(namespace, rating)| Rating { namespace, rating: rating.rating, matches: rating.matches })
+            .collect();
+        ratings.sort_by(|a, b| a.namespace.cmp(&b.namespace));
+        Ok(Player {
+            uuid: response.uuid.to_string(),
+            username: response.username,
+            private: response.private,
+            first_seen: response.first_seen,
+            last_seen: response.last_seen,
+            ratings,
+            anonymized: response.anonymized,
+            id: response.uuid,
+            hidden,
+        })
+    }
+}
+
+#[ComplexObject]
+impl Player {
+    /// The player's all-time stats in each namespace, or only in `namespaces` if given. `null` for private players.
+    async fn stats(&self, ctx: &Context<'_>, namespaces: Option<Vec<String>>) -> async_graphql::Result<Option<Vec<NamespaceStats>>> {
+        if self.hidden {
+            return Ok(None);
+        }
+        let (config, view) = (ctx.data_unchecked::<Config>(), *ctx.data_unchecked::<View>());
+        let namespaces = namespaces.map(|namespaces| namespaces.iter()
+            .map(|namespace| config.canonical_namespace(namespace).to_string())
+            .collect::<Vec<_>>());
+
+        // A single namespace is looked up by itself, and several are picked out of the player's stats in every namespace.
+        let namespace = match namespaces.as_deref() {
+            Some([namespace]) => Some(namespace.clone()),
+            _ => None,
+        };
+        let stats = send(ctx, GetPlayerStats { uuid: self.id, namespace, season: None }).await?.unwrap_or_default();
+        let mut stats: Vec<NamespaceStats> = view.filter_stats(config, stats).into_iter()
+            .filter(|(namespace, _)| namespaces.as_ref().is_none_or(|namespaces| namespaces.contains(namespace)))
+            .map(|(namespace, stats)| NamespaceStats::new(namespace, stats))
+            .collect();
+        stats.sort_by(|a, b| a.namespace.cmp(&b.namespace));
+        Ok(Some(stats))
+    }
+
+    /// The player's most recent games, optionally only those of one namespace. `null` for private players.
+    async fn games(&self, ctx: &Context<'_>, namespace: Option<String>, #[graphql(default = 20)] limit: i64) -> async_graphql::Result<Option<Vec<Game>>> {
+        if self.hidden {
+            return Ok(None);
+        }
+        Ok(Some(games(ctx, Some(self.id), namespace, limit).await?))
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Rating {
+    namespace: String,
+    rating: f64,
+    /// Number of rated matches played.
+    matches: u64,
+}
+
+/// The stats of a player or of a whole namespace, sorted by name.
+#[derive(SimpleObject)]
+pub struct NamespaceStats {
+    namespace: String,
+    stats: Vec<Stat>,
+}
+
+impl NamespaceStats {
+    fn new(namespace: String, stats: impl IntoIterator<Item = (String, f64)>) -> Self {
+        let mut stats: Vec<Stat> = stats.into_iter().map(|(name, value)| Stat { name, value }).collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        NamespaceStats { namespace, stats }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Stat {
+    name: String,
+    value: f64,
+}
+
+#[derive(SimpleObject)]
+pub struct Game {
+    id: String,
+    server_name: String,
+    namespace: String,
+    /// When the game started, in RFC 3339 format.
+    started_at: String,
+    /// When the game ended, in RFC 3339 format.
+    ended_at: String,
+    participants: Vec<GameParticipant>,
+}
+
+impl From<GameResponse> for Game {
+    fn from(game: GameResponse) -> Self {
+        Game {
+            id: game.id,
+            server_name: game.server_name,
+            namespace: game.namespace,
+            started_at: game.started_at,
+            ended_at: game.ended_at,
+            participants: game.participants.into_iter()
+                .map(|participant| GameParticipant { uuid: participant.uuid.to_string(), winner: participant.winner, score: participant.score })
+                .collect(),
+        }
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct GameParticipant {
+    uuid: String,
+    /// Whether the player won, or was on the winning team.
+    winner: bool,
+    /// The player's final score, for games that keep one.
+    score: Option<f64>,
+}
+
+async fn games(ctx: &Context<'_>, player: Option<Uuid>, namespace: Option<String>, limit: i64) -> async_graphql::Result<Vec<Game>> {
+    let (config, view) = (ctx.data_unchecked::<Config>(), *ctx.data_unchecked::<View>());
+    if !(1..=100).contains(&limit) {
+        return Err("limit must be between 1 and 100".into());
+    }
+    let namespace = namespace.map(|namespace| config.canonical_namespace(&namespace).to_string());
+    if namespace.as_ref().is_some_and(|namespace| !view.can_see_namespace(config, namespace)) {
+        return Ok(Vec::new());
+    }
+
+    let games = send(ctx, GetGames {
+        player,
+        namespace,
+        hidden_namespaces: if view == View::Public { config.internal_namespaces.clone() } else { Vec::new() },
+        limit,
+        include_private: view == View::Full,
+    }).await?;
+    Ok(games.into_iter().map(|game| Game::from(GameResponse::from(game))).collect())
+}
+
+/// Sends a message to the database within the request's deadline, turning errors into GraphQL errors that don't
+/// reveal any details.
+async fn send<M, T>(ctx: &Context<'_>, message: M) -> async_graphql::Result<T>
+    where M: DeadlineMessage<Result = anyhow::Result<T>>, T: Send + 'static, StoreHandler: Handler<WithDeadline<M>> {
+    let database = ctx.data_unchecked::<Address<StoreHandler>>();
+    let deadline = *ctx.data_unchecked::<Instant>();
+    web::send(database, message, deadline).await.map_err(|e| {
+        if e.is::<DeadlineExceeded>() {
+            log::debug!("request exceeded its deadline");
+            return "the request exceeded its deadline".into();
+        }
+        log::warn!("error handling request: {}", e);
+        "internal server error".into()
+    })
+}
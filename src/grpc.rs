@@ -0,0 +1,340 @@
+//! The gRPC service that game servers can upload through instead of the HTTP API, described by
+//! `proto/persistence.proto`. Uploads go through the same [Ingest] as `POST /stats/upload`, so they are validated,
+//! processed, queued and spooled in the same way.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::time::Duration;
+
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status};
+use tracing::Instrument;
+use uuid::Uuid;
+use warp::http::StatusCode;
+
+use crate::config::{Config, TokenScope};
+use crate::model::{GameStatsBundle, StatsBundle, UploadStat, Violation};
+use crate::shutdown;
+use crate::store::{DeadlineExceeded, UpdatePlayerProfile};
+use crate::web::{self, Ingest, UploadResult};
+
+/// The messages of the service. They are written out by hand instead of being generated, so that building doesn't
+/// need `protoc`, and must be kept in sync with `proto/persistence.proto`.
+pub mod proto {
+    use std::collections::HashMap;
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct StatsBundle {
+        #[prost(string, tag = "1")]
+        pub server_name: String,
+        #[prost(string, tag = "2")]
+        pub namespace: String,
+        #[prost(string, tag = "3")]
+        pub bundle_id: String,
+        #[prost(map = "string, message", tag = "4")]
+        pub global: HashMap<String, Stat>,
+        #[prost(map = "string, message", tag = "5")]
+        pub players: HashMap<String, PlayerStats>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PlayerStats {
+        #[prost(map = "string, message", tag = "1")]
+        pub stats: HashMap<String, Stat>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Stat {
+        #[prost(oneof = "stat::Value", tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15")]
+        pub value: Option<stat::Value>,
+    }
+
+    pub mod stat {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Value {
+            #[prost(int32, tag = "1")]
+            IntTotal(i32),
+            #[prost(int32, tag = "2")]
+            IntRollingAverage(i32),
+            #[prost(message, tag = "3")]
+            IntRollingAverageBatch(super::IntBatch),
+            #[prost(int64, tag = "4")]
+            LongTotal(i64),
+            #[prost(int64, tag = "5")]
+            LongRollingAverage(i64),
+            #[prost(message, tag = "6")]
+            LongRollingAverageBatch(super::LongBatch),
+            #[prost(double, tag = "7")]
+            FloatTotal(f64),
+            #[prost(double, tag = "8")]
+            FloatRollingAverage(f64),
+            #[prost(message, tag = "9")]
+            FloatRollingAverageBatch(super::FloatBatch),
+            #[prost(int32, tag = "10")]
+            IntMin(i32),
+            #[prost(int32, tag = "11")]
+            IntMax(i32),
+            #[prost(double, tag = "12")]
+            FloatMin(f64),
+            #[prost(double, tag = "13")]
+            FloatMax(f64),
+            #[prost(double, tag = "14")]
+            Latest(f64),
+            #[prost(message, tag = "15")]
+            Histogram(super::Histogram),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct IntBatch {
+        #[prost(int32, tag = "1")]
+        pub total: i32,
+        #[prost(uint32, tag = "2")]
+        pub count: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct LongBatch {
+        #[prost(int64, tag = "1")]
+        pub total: i64,
+        #[prost(uint32, tag = "2")]
+        pub count: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FloatBatch {
+        #[prost(double, tag = "1")]
+        pub total: f64,
+        #[prost(uint32, tag = "2")]
+        pub count: u32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Histogram {
+        #[prost(double, repeated, tag = "1")]
+        pub values: Vec<f64>,
+    }
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+    #[repr(i32)]
+    pub enum UploadOutcome {
+        Unspecified = 0,
+        Applied = 1,
+        Duplicate = 2,
+        Queued = 3,
+        Spooled = 4,
+        Quarantined = 5,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UploadStatsResponse {
+        #[prost(enumeration = "UploadOutcome", tag = "1")]
+        pub outcome: i32,
+        #[prost(string, tag = "2")]
+        pub upload_id: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UpdatePlayerProfileRequest {
+        #[prost(string, tag = "1")]
+        pub uuid: String,
+        #[prost(string, tag = "2")]
+        pub username: String,
+        #[prost(bool, optional, tag = "3")]
+        pub private: Option<bool>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UpdatePlayerProfileResponse {}
+
+    include!(concat!(env!("OUT_DIR"), "/nucleoid.persistence.v1.Persistence.rs"));
+}
+
+pub use proto::persistence_server::{Persistence, PersistenceServer};
+
+/// Binds the gRPC service on `bind_address` and the configured port, returning a future that serves it until the
+/// process is asked to shut down. It is served with TLS if `tls_cert_path` and `tls_key_path` are set, but unlike the
+/// HTTP API, the certificate is not reloaded on `SIGHUP`.
+pub fn serve(config: &Config, ingest: Ingest) -> anyhow::Result<impl Future<Output = anyhow::Result<()>>> {
+    let address = (config.bind_address, config.grpc.port).into();
+    let keepalive = config.http.tcp_keepalive_secs.map(Duration::from_secs);
+    let incoming = TcpIncoming::new(address, true, keepalive)
+        .map_err(|e| anyhow::anyhow!("failed to bind gRPC service to {}: {}", address, e))?;
+
+    let mut server = Server::builder();
+    if let (Some(cert_path), Some(key_path)) = (&config.tls_cert_path, &config.tls_key_path) {
+        let identity = Identity::from_pem(std::fs::read(cert_path)?, std::fs::read(key_path)?);
+        server = server.tls_config(ServerTlsConfig::new().identity(identity))?;
+    }
+
+    log::info!("Serving gRPC on {}", address);
+    let router = server.add_service(PersistenceServer::new(GrpcService::new(ingest)));
+    Ok(async move {
+        router.serve_with_incoming_shutdown(incoming, shutdown::signal()).await?;
+        Ok(())
+    })
+}
+
+/// Implements the RPCs of the service on top of an [Ingest].
+pub struct GrpcService {
+    ingest: Ingest,
+}
+
+impl GrpcService {
+    pub fn new(ingest: Ingest) -> Self {
+        Self { ingest }
+    }
+}
+
+#[tonic::async_trait]
+impl Persistence for GrpcService {
+    async fn upload_stats_bundle(&self, request: Request<proto::StatsBundle>) -> Result<Response<proto::UploadStatsResponse>, Status> {
+        let config = &self.ingest.config;
+        if config.read_only {
+            return Err(Status::unavailable("the backend is read-only"));
+        }
+        let authorization = authorization(&request).ok_or_else(|| Status::unauthenticated("no valid authorization metadata was sent"))?;
+        let bundle = bundle_from_proto(request.into_inner()).map_err(Status::invalid_argument)?;
+
+        let route = "upload_stats";
+        let result = self.ingest.upload(authorization, bundle)
+            .instrument(tracing::info_span!("grpc", route, otel.name = route))
+            .await;
+        let (outcome, upload_id) = match result {
+            UploadResult::Applied => (proto::UploadOutcome::Applied, None),
+            UploadResult::Duplicate => (proto::UploadOutcome::Duplicate, None),
+            UploadResult::Queued(id) => (proto::UploadOutcome::Queued, Some(id.to_string())),
+            UploadResult::Spooled => (proto::UploadOutcome::Spooled, None),
+            UploadResult::Quarantined(id) => (proto::UploadOutcome::Quarantined, Some(id.to_hex())),
+            UploadResult::Unauthorized(status) => return Err(unauthorized(status)),
+            UploadResult::Invalid(violations) => return Err(invalid(&violations)),
+            UploadResult::Rejected(error) => return Err(Status::invalid_argument(error)),
+            UploadResult::TypeMismatch(error) => return Err(Status::failed_precondition(error)),
+            UploadResult::Unavailable => return Err(Status::unavailable("the upload could not be accepted, try again later")),
+            UploadResult::Failed(e) => return Err(server_error(&e)),
+        };
+
+        Ok(Response::new(proto::UploadStatsResponse {
+            outcome: outcome as i32,
+            upload_id: upload_id.unwrap_or_default(),
+        }))
+    }
+
+    async fn update_player_profile(&self, request: Request<proto::UpdatePlayerProfileRequest>) -> Result<Response<proto::UpdatePlayerProfileResponse>, Status> {
+        let Ingest { config, database, limits, .. } = &self.ingest;
+        if config.read_only {
+            return Err(Status::unavailable("the backend is read-only"));
+        }
+        let authorization = authorization(&request).ok_or_else(|| Status::unauthenticated("no valid authorization metadata was sent"))?;
+        if let Some(status) = web::missing_scope(config, &authorization, TokenScope::UpdateProfiles) {
+            return Err(unauthorized(status));
+        }
+        let request = request.into_inner();
+        let uuid = Uuid::parse_str(&request.uuid)
+            .map_err(|_| Status::invalid_argument(format!("invalid player UUID '{}'", request.uuid)))?;
+
+        let route = "update_player_profile";
+        let _permit = match limits.acquire(route).await {
+            Some(permit) => permit,
+            None => return Err(Status::unavailable("too many requests are being handled, try again later")),
+        };
+        let message = UpdatePlayerProfile { uuid, username: request.username, private: request.private };
+        web::send(database, message, limits.deadline(route))
+            .instrument(tracing::info_span!("grpc", route, otel.name = route))
+            .await
+            .map_err(|e| server_error(&e))?;
+        Ok(Response::new(proto::UpdatePlayerProfileResponse {}))
+    }
+}
+
+/// Reads the API token from the `authorization` metadata of a request.
+fn authorization<T>(request: &Request<T>) -> Option<String> {
+    let token = request.metadata().get("authorization")?.to_str().ok()?;
+    Some(token.to_string())
+}
+
+fn unauthorized(status: StatusCode) -> Status {
+    if status == StatusCode::FORBIDDEN {
+        Status::permission_denied("the token is missing a scope required by this method")
+    } else {
+        Status::unauthenticated("the token is not valid")
+    }
+}
+
+fn invalid(violations: &[Violation]) -> Status {
+    let violations = violations.iter()
+        .map(|violation| format!("{}: {}", violation.field, violation.message))
+        .collect::<Vec<_>>();
+    Status::invalid_argument(format!("the request breaks {} limits of the backend: {}", violations.len(), violations.join("; ")))
+}
+
+fn server_error(e: &anyhow::Error) -> Status {
+    if e.is::<DeadlineExceeded>() {
+        log::debug!("request exceeded its deadline");
+        return Status::deadline_exceeded("the request exceeded its deadline");
+    }
+    log::warn!("error handling request: {}", e);
+    Status::internal("internal server error")
+}
+
+fn bundle_from_proto(bundle: proto::StatsBundle) -> Result<GameStatsBundle, String> {
+    let bundle_id = if bundle.bundle_id.is_empty() {
+        None
+    } else {
+        let id = Uuid::parse_str(&bundle.bundle_id)
+            .map_err(|_| format!("invalid bundle ID '{}'", bundle.bundle_id))?;
+        Some(id)
+    };
+
+    let global = if bundle.global.is_empty() {
+        None
+    } else {
+        Some(stats_from_proto(bundle.global)?)
+    };
+    let mut players = HashMap::with_capacity(bundle.players.len());
+    for (player, stats) in bundle.players {
+        let uuid = Uuid::parse_str(&player)
+            .map_err(|_| format!("invalid player UUID '{}'", player))?;
+        players.insert(uuid, stats_from_proto(stats.stats)?);
+    }
+
+    Ok(GameStatsBundle {
+        server_name: bundle.server_name,
+        namespace: bundle.namespace,
+        stats: StatsBundle { global, players },
+        bundle_id,
+    })
+}
+
+fn stats_from_proto(stats: HashMap<String, proto::Stat>) -> Result<HashMap<String, UploadStat>, String> {
+    stats.into_iter()
+        .map(|(name, stat)| {
+            let stat = stat.value
+                .map(stat_from_proto)
+                .ok_or_else(|| format!("stat '{}' has no value", name))?;
+            Ok((name, stat))
+        })
+        .collect()
+}
+
+fn stat_from_proto(value: proto::stat::Value) -> UploadStat {
+    use proto::stat::Value;
+    match value {
+        Value::IntTotal(value) => UploadStat::IntTotal(value),
+        Value::IntRollingAverage(value) => UploadStat::IntRollingAverage(value),
+        Value::IntRollingAverageBatch(batch) => UploadStat::IntRollingAverageBatch { total: batch.total, count: batch.count },
+        Value::LongTotal(value) => UploadStat::LongTotal(value),
+        Value::LongRollingAverage(value) => UploadStat::LongRollingAverage(value),
+        Value::LongRollingAverageBatch(batch) => UploadStat::LongRollingAverageBatch { total: batch.total, count: batch.count },
+        Value::FloatTotal(value) => UploadStat::FloatTotal(value),
+        Value::FloatRollingAverage(value) => UploadStat::FloatRollingAverage(value),
+        Value::FloatRollingAverageBatch(batch) => UploadStat::FloatRollingAverageBatch { total: batch.total, count: batch.count },
+        Value::IntMin(value) => UploadStat::IntMin(value),
+        Value::IntMax(value) => UploadStat::IntMax(value),
+        Value::FloatMin(value) => UploadStat::FloatMin(value),
+        Value::FloatMax(value) => UploadStat::FloatMax(value),
+        Value::Latest(value) => UploadStat::Latest(value),
+        Value::Histogram(histogram) => UploadStat::Histogram(histogram.values),
+    }
+}
@@ -0,0 +1,128 @@
+use async_trait::async_trait;
+use xtra::Address;
+
+use bson::oid::ObjectId;
+
+use crate::config::{Config, LeaderboardSnapshotConfig};
+use crate::metrics::Metrics;
+use crate::model::StoredLeaderboardSnapshot;
+use crate::store::{StoreHandler, GetLeaderboard, PruneBundleIds, PruneBundleLog, PruneGlobalStatsRollups, PruneLeaderboardSnapshots, PruneStatHistory, SaveLeaderboardSnapshot};
+use crate::scheduler::{Job, Scheduler};
+use crate::spool::Spool;
+
+/// Registers the built-in background jobs.
+pub fn register(scheduler: &mut Scheduler, config: &Config, database: &Address<StoreHandler>, metrics: &Metrics) -> anyhow::Result<()> {
+    scheduler.register("prune_global_stats_rollups", "0 0 * * * *", PruneGlobalStatsRollupsJob(database.clone()))?;
+    scheduler.register("prune_bundle_log", "0 30 * * * *", PruneBundleLogJob(database.clone()))?;
+    scheduler.register("prune_stat_history", "0 45 * * * *", PruneStatHistoryJob(database.clone()))?;
+    scheduler.register("prune_bundle_ids", "0 15 * * * *", PruneBundleIdsJob(database.clone()))?;
+    // Each instance has its own spool, so every instance replays it.
+    if let Some(spool_dir) = &config.spool_dir {
+        scheduler.register_local("replay_spool", "0 */5 * * * *", ReplaySpoolJob {
+            spool: Spool::new(spool_dir),
+            database: database.clone(),
+            metrics: metrics.clone(),
+        })?;
+    }
+
+    // Aliases are resolved up front, so that snapshots are found under the names that reads resolve them to.
+    let mut snapshots = config.leaderboard_snapshots.clone();
+    for leaderboard in &mut snapshots.leaderboards {
+        let namespace = config.canonical_namespace(&leaderboard.namespace).to_string();
+        leaderboard.stat = config.canonical_stat_name(&namespace, &leaderboard.stat).to_string();
+        leaderboard.namespace = namespace;
+    }
+    scheduler.register("snapshot_leaderboards", "0 0 0 * * *", SnapshotLeaderboardsJob {
+        config: snapshots,
+        database: database.clone(),
+    })?;
+    Ok(())
+}
+
+/// Deletes global stats rollups that are older than the retention period.
+struct PruneGlobalStatsRollupsJob(Address<StoreHandler>);
+
+#[async_trait]
+impl Job for PruneGlobalStatsRollupsJob {
+    async fn run(&self) -> anyhow::Result<u64> {
+        self.0.send(PruneGlobalStatsRollups).await?
+    }
+}
+
+/// Deletes logged bundles that are older than the retention period.
+struct PruneBundleLogJob(Address<StoreHandler>);
+
+#[async_trait]
+impl Job for PruneBundleLogJob {
+    async fn run(&self) -> anyhow::Result<u64> {
+        self.0.send(PruneBundleLog).await?
+    }
+}
+
+/// Deletes stat history snapshots that are older than the retention period.
+struct PruneStatHistoryJob(Address<StoreHandler>);
+
+#[async_trait]
+impl Job for PruneStatHistoryJob {
+    async fn run(&self) -> anyhow::Result<u64> {
+        self.0.send(PruneStatHistory).await?
+    }
+}
+
+/// Forgets the IDs of applied bundles that are older than the retention period.
+struct PruneBundleIdsJob(Address<StoreHandler>);
+
+#[async_trait]
+impl Job for PruneBundleIdsJob {
+    async fn run(&self) -> anyhow::Result<u64> {
+        self.0.send(PruneBundleIds).await?
+    }
+}
+
+/// Writes bundles that were spooled while the server was running, e.g. because no upload slot was free.
+struct ReplaySpoolJob {
+    spool: Spool,
+    database: Address<StoreHandler>,
+    metrics: Metrics,
+}
+
+#[async_trait]
+impl Job for ReplaySpoolJob {
+    async fn run(&self) -> anyhow::Result<u64> {
+        self.spool.replay(&self.database, &self.metrics).await
+    }
+}
+
+/// Saves the configured leaderboards as they stand now, then deletes snapshots that are older than the retention
+/// period.
+struct SnapshotLeaderboardsJob {
+    config: LeaderboardSnapshotConfig,
+    database: Address<StoreHandler>,
+}
+
+#[async_trait]
+impl Job for SnapshotLeaderboardsJob {
+    async fn run(&self) -> anyhow::Result<u64> {
+        let taken_at = bson::DateTime::now();
+        for leaderboard in &self.config.leaderboards {
+            let entries = self.database.send(GetLeaderboard {
+                namespace: leaderboard.namespace.clone(),
+                stat: leaderboard.stat.clone(),
+                limit: self.config.size,
+                order: leaderboard.order,
+                season: None,
+                include_private: false,
+            }).await??;
+            self.database.send(SaveLeaderboardSnapshot(StoredLeaderboardSnapshot {
+                id: ObjectId::new(),
+                namespace: leaderboard.namespace.clone(),
+                stat: leaderboard.stat.clone(),
+                order: leaderboard.order,
+                taken_at,
+                entries,
+            })).await??;
+        }
+        self.database.send(PruneLeaderboardSnapshots).await??;
+        Ok(self.config.leaderboards.len() as u64)
+    }
+}
@@ -0,0 +1,89 @@
+use std::future::Future;
+use std::time::Duration;
+
+use rand::Rng;
+use rand::distributions::Alphanumeric;
+use xtra::Address;
+
+use crate::store::{AcquireLease, StoreHandler, ReleaseLease};
+
+/// Coordinates work between instances of the backend that share a database, using lease documents that are held by
+/// one instance at a time until they expire.
+#[derive(Clone)]
+pub struct Leases {
+    database: Address<StoreHandler>,
+    holder: String,
+}
+
+impl Leases {
+    /// Creates a lease holder with an identifier that is unique to this instance.
+    pub fn new(database: Address<StoreHandler>) -> Self {
+        Self { database, holder: random_id() }
+    }
+
+    /// Takes the named lease for this instance for `ttl`, returning `false` if another instance holds it.
+    pub async fn try_acquire(&self, name: &str, ttl: Duration) -> anyhow::Result<bool> {
+        self.acquire_as(name, &self.holder, ttl).await
+    }
+
+    async fn acquire_as(&self, name: &str, holder: &str, ttl: Duration) -> anyhow::Result<bool> {
+        self.database.send(AcquireLease {
+            name: name.to_string(),
+            holder: holder.to_string(),
+            ttl,
+        }).await?
+    }
+
+    async fn release_as(&self, name: &str, holder: &str) -> anyhow::Result<()> {
+        self.database.send(ReleaseLease {
+            name: name.to_string(),
+            holder: holder.to_string(),
+        }).await?
+    }
+
+    /// Runs `task` while holding all of the named leases, returning `None` without running it if any are held by
+    /// other task, on this instance or another. Leases are taken in sorted order and released once the task finishes.
+    pub async fn run_exclusive<T>(&self, names: &[String], ttl: Duration, task: impl Future<Output = anyhow::Result<T>>) -> anyhow::Result<Option<T>> {
+        let mut names = names.to_vec();
+        names.sort();
+        names.dedup();
+
+        // Each task holds its leases under its own name, so that tasks on the same instance also exclude each other.
+        let holder = format!("{}/{}", self.holder, random_id());
+
+        let mut acquired = Vec::new();
+        for name in &names {
+            match self.acquire_as(name, &holder, ttl).await {
+                Ok(true) => acquired.push(name),
+                Ok(false) => {
+                    self.release_all(&acquired, &holder).await;
+                    return Ok(None);
+                }
+                Err(e) => {
+                    self.release_all(&acquired, &holder).await;
+                    return Err(e);
+                }
+            }
+        }
+
+        let res = task.await;
+        self.release_all(&acquired, &holder).await;
+        res.map(Some)
+    }
+
+    async fn release_all(&self, names: &[&String], holder: &str) {
+        for name in names {
+            if let Err(e) = self.release_as(name, holder).await {
+                log::warn!("failed to release lease '{}', it will be held until it expires: {}", name, e);
+            }
+        }
+    }
+}
+
+pub(crate) fn random_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(16)
+        .map(char::from)
+        .collect()
+}
@@ -0,0 +1,45 @@
+//! The Nucleoid persistence backend, which stores player profiles and game statistics.
+//!
+//! The service is normally run by the `nucleoid-persistence-backend` binary, but other services can embed the API
+//! with [web::routes], or use the storage layer directly through the [store::StoreHandler] actor, which is backed by a
+//! [store::StatsStore] such as [database::MongoDatabaseHandler], [postgres::PostgresDatabaseHandler] or
+//! [memory::MemoryDatabaseHandler].
+
+pub mod backup;
+pub mod compression;
+pub mod config;
+pub mod database;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod graphql;
+pub mod jobs;
+pub mod lease;
+pub mod limit;
+pub mod live;
+pub mod logging;
+pub mod memory;
+#[cfg(any(feature = "amqp", feature = "nats"))]
+pub mod message_queue;
+pub mod metrics;
+pub mod migration;
+pub mod model;
+pub mod mojang;
+pub mod openapi;
+pub mod postgres;
+pub mod processor;
+pub mod ratings;
+pub mod reporting;
+pub mod result_cache;
+pub mod scheduler;
+pub mod script;
+pub mod server;
+pub mod shutdown;
+pub mod signature;
+pub mod spool;
+pub mod store;
+pub mod tls;
+pub mod upload_queue;
+pub mod validation;
+pub mod wasm;
+pub mod web;
+mod util;
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+use crate::config::{ConcurrencyConfig, Config, DeadlineConfig, RateLimitKey};
+use crate::metrics::Metrics;
+
+/// Limits on how requests are handled: how many are handled at once, both across all routes and for individual
+/// routes, and how long each may take.
+#[derive(Clone)]
+pub struct RouteLimits {
+    global: Option<Arc<Semaphore>>,
+    routes: Arc<HashMap<String, Arc<Semaphore>>>,
+    queue_timeout: Duration,
+    deadlines: DeadlineConfig,
+    metrics: Metrics,
+}
+
+/// Holds a request's slots in the concurrency limits until it is dropped.
+pub struct ConcurrencyPermit {
+    _route: Option<OwnedSemaphorePermit>,
+    _global: Option<OwnedSemaphorePermit>,
+}
+
+impl RouteLimits {
+    pub fn new(config: &ConcurrencyConfig, deadlines: &DeadlineConfig, metrics: Metrics) -> Self {
+        Self {
+            global: config.global.map(|limit| Arc::new(Semaphore::new(limit))),
+            routes: Arc::new(config.routes.iter()
+                .map(|(route, limit)| (route.clone(), Arc::new(Semaphore::new(*limit))))
+                .collect()),
+            queue_timeout: Duration::from_millis(config.queue_timeout_ms),
+            deadlines: deadlines.clone(),
+            metrics,
+        }
+    }
+
+    /// The deadline of a request to the given route that starts now.
+    pub fn deadline(&self, route: &str) -> Instant {
+        let millis = self.deadlines.routes.get(route).copied().unwrap_or(self.deadlines.default_ms);
+        Instant::now() + Duration::from_millis(millis)
+    }
+
+    /// Waits for a slot for the given route, returning `None` if none became free within the queue timeout.
+    pub async fn acquire(&self, route: &str) -> Option<ConcurrencyPermit> {
+        let acquire = async {
+            // The route's slot is acquired first so that queued requests don't hold up other routes.
+            let route_permit = match self.routes.get(route) {
+                Some(semaphore) => Some(semaphore.clone().acquire_owned().await.ok()?),
+                None => None,
+            };
+            let global_permit = match &self.global {
+                Some(semaphore) => Some(semaphore.clone().acquire_owned().await.ok()?),
+                None => None,
+            };
+            Some(ConcurrencyPermit {
+                _route: route_permit,
+                _global: global_permit,
+            })
+        };
+
+        let permit = tokio::time::timeout(self.queue_timeout, acquire).await.ok().flatten();
+        if permit.is_none() {
+            log::debug!("rejecting request to {} as the concurrency limit was reached", route);
+            self.metrics.increment("nucleoid_concurrency_rejections_total", &[("route", route)]);
+        }
+        permit
+    }
+}
+
+/// How many buckets are tracked before full ones are first dropped.
+const MIN_PRUNE_BUCKETS: usize = 1024;
+
+/// Limits how many requests each token or client address may make to a route. Each client has a bucket holding up
+/// to the route's `requests`, which refills steadily over its window and is drawn from by every request.
+#[derive(Clone)]
+pub struct RateLimiter {
+    /// The config that limits are read from on each request, so that they can be reloaded.
+    config: Config,
+    buckets: Arc<Mutex<Buckets>>,
+    metrics: Metrics,
+}
+
+struct Buckets {
+    buckets: HashMap<(&'static str, String), Bucket>,
+    /// How many buckets are tracked before full ones are next dropped.
+    prune_at: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(config: &Config, metrics: Metrics) -> Self {
+        Self {
+            config: config.clone(),
+            buckets: Arc::new(Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                prune_at: MIN_PRUNE_BUCKETS,
+            })),
+            metrics,
+        }
+    }
+
+    /// What requests to the given route are counted by, or `None` if the route isn't rate limited.
+    pub fn key(&self, route: &str) -> Option<RateLimitKey> {
+        self.config.rate_limit(route).map(|limit| limit.per)
+    }
+
+    /// Counts a request from `client` to the given route, returning how long it should wait before retrying if it
+    /// has used up its limit.
+    pub fn check(&self, route: &'static str, client: &str) -> Result<(), Duration> {
+        let limit = match self.config.rate_limit(route) {
+            Some(limit) => limit,
+            None => return Ok(()),
+        };
+        let window = Duration::from_secs(limit.window_secs.max(1));
+        if limit.requests == 0 {
+            return self.reject(route, window);
+        }
+
+        let capacity = limit.requests as f64;
+        let per_sec = capacity / window.as_secs_f64();
+        let now = Instant::now();
+
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.buckets.entry((route, client.to_string()))
+            .or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_sec).min(capacity);
+        bucket.updated = now;
+
+        let result = if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+        };
+
+        if buckets.buckets.len() >= buckets.prune_at {
+            self.prune(&mut buckets, now);
+        }
+        drop(buckets);
+
+        match result {
+            Ok(()) => Ok(()),
+            Err(retry_after) => self.reject(route, retry_after),
+        }
+    }
+
+    /// Drops the buckets that have refilled, which behave the same as new ones, and those of routes that are no
+    /// longer limited.
+    fn prune(&self, buckets: &mut Buckets, now: Instant) {
+        buckets.buckets.retain(|(route, _), bucket| match self.config.rate_limit(route) {
+            Some(limit) => {
+                let per_sec = limit.requests as f64 / limit.window_secs.max(1) as f64;
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_sec < limit.requests as f64
+            }
+            None => false,
+        });
+        buckets.prune_at = (buckets.buckets.len() * 2).max(MIN_PRUNE_BUCKETS);
+    }
+
+    fn reject(&self, route: &str, retry_after: Duration) -> Result<(), Duration> {
+        log::debug!("rejecting request to {} as its rate limit was reached", route);
+        self.metrics.increment("nucleoid_rate_limit_rejections_total", &[("route", route)]);
+        Err(retry_after)
+    }
+}
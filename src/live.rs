@@ -0,0 +1,73 @@
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use tokio::sync::broadcast;
+use warp::ws::{Message, WebSocket};
+
+use crate::model::{GameStatsBundle, LiveBundleEvent};
+use crate::processor::StatProcessor;
+
+/// How many events are buffered for each client before a slow one starts missing them.
+const BUFFERED_EVENTS: usize = 256;
+
+/// Broadcasts each applied bundle to the clients connected to the live feed.
+#[derive(Clone)]
+pub struct LiveFeed {
+    sender: broadcast::Sender<LiveBundleEvent>,
+}
+
+impl Default for LiveFeed {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(BUFFERED_EVENTS);
+        Self { sender }
+    }
+}
+
+impl LiveFeed {
+    /// Sends events to a client until it disconnects, skipping those for any of `hidden_namespaces`.
+    pub async fn serve(&self, socket: WebSocket, hidden_namespaces: Vec<String>) {
+        let mut events = self.sender.subscribe();
+        let (mut outgoing, mut incoming) = socket.split();
+        loop {
+            tokio::select! {
+                event = events.recv() => {
+                    let event = match event {
+                        Ok(event) => event,
+                        // A slow client misses the events it fell behind on, rather than holding up the feed.
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    };
+                    if hidden_namespaces.contains(&event.namespace) {
+                        continue;
+                    }
+                    let message = match serde_json::to_string(&event) {
+                        Ok(message) => message,
+                        Err(e) => {
+                            log::warn!("failed to serialize live event: {}", e);
+                            continue;
+                        }
+                    };
+                    if outgoing.send(Message::text(message)).await.is_err() {
+                        break;
+                    }
+                }
+                message = incoming.next() => match message {
+                    // The feed only goes one way, so anything else that clients send is ignored.
+                    Some(Ok(message)) if !message.is_close() => (),
+                    _ => break,
+                },
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl StatProcessor for LiveFeed {
+    async fn bundle_applied(&self, bundle: &GameStatsBundle) {
+        // Sending only fails when no clients are connected.
+        let _ = self.sender.send(LiveBundleEvent {
+            namespace: bundle.namespace.clone(),
+            server_name: bundle.server_name.clone(),
+            players: bundle.stats.players.len(),
+        });
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt::{self, Display};
+
+use hyper::header::HeaderValue;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::{self, Sampler, Tracer};
+use opentelemetry_sdk::{runtime, Resource};
+use rand::Rng;
+use tracing::Level;
+use tracing_opentelemetry::OpenTelemetryLayer;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::TelemetryConfig;
+
+/// Header that a request's ID is read from and returned in.
+pub const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Longest request ID that is accepted from a client, so that log lines can't be bloated with huge IDs.
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// Installs the global logger, which also receives everything logged through the `log` crate. Levels are filtered by
+/// `RUST_LOG`, and `NUCLEOID_LOG_FORMAT=json` writes one JSON object per line instead of plain text.
+///
+/// This is configured by environment variables rather than the config file so that loading the config can be logged.
+/// Trace export is configured by the config file, so it is started afterwards with [Logging::export_traces].
+pub fn init() -> Logging {
+    let (telemetry, telemetry_handle) = reload::Layer::new(None);
+    let telemetry = telemetry.with_filter(Targets::new().with_target("nucleoid_persistence", Level::INFO));
+
+    let format = match std::env::var("NUCLEOID_LOG_FORMAT").as_deref() {
+        Ok("json") => tracing_subscriber::fmt::layer().json().flatten_event(true).boxed(),
+        Ok("text") | Err(_) => tracing_subscriber::fmt::layer().boxed(),
+        Ok(format) => panic!("invalid value for NUCLEOID_LOG_FORMAT: {} (expected text or json)", format),
+    };
+
+    tracing_subscriber::registry()
+        .with(telemetry)
+        .with(format.with_filter(EnvFilter::from_default_env()))
+        .init();
+    Logging { telemetry: telemetry_handle }
+}
+
+type TelemetryLayer = Option<OpenTelemetryLayer<Registry, Tracer>>;
+
+/// Handle to the global logger installed by [init].
+pub struct Logging {
+    telemetry: reload::Handle<TelemetryLayer, Registry>,
+}
+
+impl Logging {
+    /// Starts exporting the spans of requests and their database work to an OpenTelemetry collector, if one is
+    /// configured. Spans are exported regardless of `RUST_LOG`.
+    pub fn export_traces(&self, config: &TelemetryConfig) -> anyhow::Result<()> {
+        let endpoint = match &config.otlp_endpoint {
+            Some(endpoint) => endpoint,
+            None => return Ok(()),
+        };
+
+        let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sample_ratio)));
+        let resource = Resource::new([KeyValue::new("service.name", config.service_name.clone())]);
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().http().with_endpoint(endpoint))
+            .with_trace_config(trace::config().with_sampler(sampler).with_resource(resource))
+            .install_batch(runtime::Tokio)?;
+        self.telemetry.reload(Some(tracing_opentelemetry::layer().with_tracer(tracer)))?;
+
+        log::info!("Exporting traces to {}", endpoint);
+        Ok(())
+    }
+
+    /// Exports any spans that are still buffered. Should be called before exiting.
+    pub async fn shutdown(self) {
+        // Flushing blocks until the batch exporter, which runs on the runtime, has sent everything.
+        let _ = tokio::task::spawn_blocking(opentelemetry::global::shutdown_tracer_provider).await;
+    }
+}
+
+/// Identifies an HTTP request in logs, including the logs of the database work done for it. Added to the extensions
+/// of every request served by [crate::server::serve].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RequestId(String);
+
+impl RequestId {
+    /// Uses the ID sent by the client, e.g. by a reverse proxy that already assigned one, or generates a new one.
+    pub fn from_header(header: Option<&HeaderValue>) -> Self {
+        header
+            .and_then(|value| value.to_str().ok())
+            .filter(|id| !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN)
+            .map(|id| RequestId(id.to_string()))
+            .unwrap_or_else(Self::generate)
+    }
+
+    pub fn generate() -> Self {
+        RequestId(format!("{:016x}", rand::thread_rng().gen::<u64>()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
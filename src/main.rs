@@ -1,22 +1,54 @@
-use xtra::Actor;
-use xtra::spawn::Tokio;
+use anyhow::Context;
 
-mod database;
-mod config;
-mod web;
-mod model;
-mod util;
+use nucleoid_persistence::{config, jobs, logging, metrics, scheduler, spool, store, web};
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    env_logger::init();
+    let logging = logging::init();
 
-    let config = config::load();
-    let database = database::MongoDatabaseHandler::connect(&config).await?
-        .create(None)
-        .spawn(&mut Tokio::Global);
+    let mut read_only = false;
+    let mut config_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--read-only" {
+            read_only = true;
+        } else if arg == "--config" {
+            config_path = Some(args.next().context("--config needs the path of a config file")?.into());
+        } else if let Some(path) = arg.strip_prefix("--config=") {
+            config_path = Some(path.into());
+        }
+    }
 
-    web::run(&config, database.clone()).await;
+    let config_path = config::locate(config_path)?;
+    let mut config = config::load(&config_path)?;
+    logging.export_traces(&config.telemetry)?;
+    if read_only {
+        config.read_only = true;
+    }
+    if config.read_only {
+        log::info!("Starting in read-only mode");
+    }
+    config.reload_on_sighup(config_path)?;
+    let database = store::StoreHandler::connect(&config).await?;
 
+    let metrics = metrics::Metrics::default();
+
+    // Bundles left over from the last run are written before the server starts taking new uploads.
+    if let (Some(spool_dir), false) = (&config.spool_dir, config.read_only) {
+        let spool = spool::Spool::new(spool_dir);
+        spool.remove_partial_files().await?;
+        spool.replay(&database, &metrics).await?;
+    }
+
+    // Every job writes to the database, so none are run in read-only mode.
+    let mut scheduler = scheduler::Scheduler::new(&config, database.clone());
+    if !config.read_only {
+        jobs::register(&mut scheduler, &config, &database, &metrics)?;
+    }
+    let jobs = scheduler.start();
+
+    web::run(&config, database.clone(), metrics, jobs).await?;
+
+    logging.shutdown().await;
     Ok(())
 }
@@ -0,0 +1,1093 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::Document;
+use bson::oid::ObjectId;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::model::{AchievementInfo, AchievementUnlocks, AggregateRebuildReport, CorruptDocument, DocumentFailure, Game, GameStat, GameStatsBundle, HistogramBucket, JobOutcome, JobRun, LeaderboardEntry, LeaderboardOrder, MatchTeam, StoredLeaderboardSnapshot, NamespaceDeletionReport, NamespaceMergeReport, PlayerAnonymizationReport, PlayerDeletionReport, PlayerProfile, PlayerStatsResponse, PreviousUsername, RatingChange, ServerStats, StatConversionReport, StatDeletionReport, StatRenameReport, StatRank, StatSummaryResponse, UploadLogEntry, StatInfo, StatSnapshot, StoredAchievement, StoredPlaytime, StoredRating, StoredSeason, SuspiciousUpload};
+use crate::store::{BackupBatch, BackupManifest, add_stat, add_upload, BundleOutcome, check_mergeable, stat_values, sum_rollups, uploaded_stat, ConvertStat, GetGames, GetLeaderboard, GetLeaderboardSnapshot, GetPlayers, GetRatingLeaderboard, GetStatHistogram, GetStatRank, GetStatSummary, GetStatHistory, GetUploadLog, PlayerOrder, RebuildAggregates, RestoreOutcome, SearchPlayers, StatsStore, Unsupported};
+
+const HOUR_MILLIS: i64 = 60 * 60 * 1000;
+const DAY_MILLIS: i64 = 24 * HOUR_MILLIS;
+
+/// The season that the all-time stats of players are stored under.
+const ALL_TIME: u32 = 0;
+
+/// Keeps everything in memory, so that the service can run without a database. Nothing survives a restart, which
+/// makes it suited to tests and local development.
+pub struct MemoryDatabaseHandler {
+    config: Config,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    players: HashMap<Uuid, PlayerProfile>,
+    /// Usernames that players changed away from, oldest first.
+    username_history: HashMap<Uuid, Vec<PreviousUsername>>,
+    /// Playtime of players by uuid and namespace.
+    playtime: HashMap<(Uuid, String), StoredPlaytime>,
+    /// Stats of players by uuid, namespace and season.
+    player_stats: HashMap<(Uuid, String, u32), HashMap<String, GameStat>>,
+    global_stats: HashMap<String, HashMap<String, GameStat>>,
+    /// Hourly rollups of global stats by namespace and the start of their hour, in milliseconds.
+    rollups: BTreeMap<(String, i64), HashMap<String, GameStat>>,
+    games: Vec<Game>,
+    stat_info: HashMap<String, HashMap<String, StatInfo>>,
+    achievement_info: HashMap<String, HashMap<String, AchievementInfo>>,
+    /// Unlocked achievements by uuid, namespace and achievement id.
+    achievements: HashMap<(Uuid, String, String), bson::DateTime>,
+    /// Ratings of players by uuid and namespace.
+    ratings: HashMap<(Uuid, String), StoredRating>,
+    stat_history: Vec<StatSnapshot>,
+    /// Saved leaderboards, oldest first.
+    leaderboard_snapshots: Vec<StoredLeaderboardSnapshot>,
+    /// When each logged bundle was received. The bundles themselves are never read back.
+    bundle_log: Vec<bson::DateTime>,
+    /// What each server has uploaded, by server name and namespace.
+    server_stats: HashMap<(String, String), ServerStats>,
+    /// Logged uploads, oldest first.
+    upload_log: VecDeque<UploadLogEntry>,
+    /// Quarantined uploads, oldest first.
+    suspicious_uploads: Vec<SuspiciousUpload>,
+    /// When each bundle with an ID was applied, by its ID.
+    applied_bundles: HashMap<Uuid, bson::DateTime>,
+    season: Option<StoredSeason>,
+    job_runs: Vec<JobRun>,
+    leases: HashMap<String, (String, Instant)>,
+}
+
+impl MemoryDatabaseHandler {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            config: config.clone(),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        // Nothing panics while holding the lock, but a test that does shouldn't take every other request with it.
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl State {
+    fn current_season(&self) -> StoredSeason {
+        self.season.clone().unwrap_or(StoredSeason {
+            id: "season".to_string(),
+            season: 1,
+            started_at: None,
+        })
+    }
+}
+
+#[async_trait]
+impl StatsStore for MemoryDatabaseHandler {
+    async fn ping(&self) -> Result<()> {
+        Ok(())
+    }
+
+    async fn get_player_profile(&self, uuid: &Uuid) -> Result<Option<PlayerProfile>> {
+        Ok(self.state().players.get(uuid).cloned())
+    }
+
+    async fn get_player_profile_by_name(&self, username: &str) -> Result<Option<PlayerProfile>> {
+        let username = username.to_lowercase();
+        Ok(self.state().players.values()
+            .filter(|profile| profile.username.as_ref().is_some_and(|name| name.to_lowercase() == username))
+            .max_by_key(|profile| profile.username_updated_at)
+            .cloned())
+    }
+
+    async fn get_players(&self, message: GetPlayers) -> Result<Vec<PlayerProfile>> {
+        // Players who were never seen have no `last_seen`, and so come after everyone else.
+        let key = |uuid: Uuid, last_seen: Option<bson::DateTime>| match message.order {
+            PlayerOrder::Uuid => (Reverse(None), uuid),
+            PlayerOrder::LastSeen => (Reverse(last_seen), uuid),
+        };
+        let state = self.state();
+        let mut players: Vec<&PlayerProfile> = state.players.values()
+            .filter(|profile| message.after.is_none_or(|after| key(profile.uuid, profile.last_seen) > key(after.uuid, after.last_seen)))
+            .collect();
+        players.sort_by_key(|profile| key(profile.uuid, profile.last_seen));
+        Ok(players.into_iter().take(message.limit as usize).cloned().collect())
+    }
+
+    async fn search_players(&self, message: SearchPlayers) -> Result<Vec<PlayerProfile>> {
+        let prefix = message.prefix.to_lowercase();
+        let state = self.state();
+        let mut players: Vec<(String, &PlayerProfile)> = state.players.values()
+            .filter(|profile| message.include_private || !profile.private)
+            .filter_map(|profile| Some((profile.username.as_ref()?.to_lowercase(), profile)))
+            .filter(|(username, _)| username.starts_with(&prefix))
+            .collect();
+        players.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(players.into_iter().take(message.limit as usize).map(|(_, profile)| profile.clone()).collect())
+    }
+
+    async fn update_player_profile(&self, uuid: &Uuid, username: Option<String>) -> Result<PlayerProfile> {
+        let mut state = self.state();
+        let state = &mut *state;
+        let now = bson::DateTime::now();
+        let profile = state.players.entry(*uuid).or_insert_with(|| new_profile(*uuid));
+        profile.first_seen.get_or_insert(now);
+        profile.last_seen = Some(now);
+        // Profiles created by uploads have no username until one is set.
+        if let Some(username) = username {
+            if profile.username.as_ref() != Some(&username) {
+                if let Some(previous) = profile.username.replace(username) {
+                    state.username_history.entry(*uuid).or_default().push(PreviousUsername {
+                        username: previous,
+                        used_from: profile.username_updated_at,
+                        used_until: now,
+                    });
+                }
+                profile.username_updated_at = Some(now);
+            }
+        }
+        Ok(profile.clone())
+    }
+
+    async fn set_player_private(&self, uuid: &Uuid, private: bool) -> Result<()> {
+        if let Some(profile) = self.state().players.get_mut(uuid) {
+            profile.private = private;
+        }
+        Ok(())
+    }
+
+    async fn get_username_history(&self, uuid: &Uuid) -> Result<Vec<PreviousUsername>> {
+        Ok(self.state().username_history.get(uuid).cloned().unwrap_or_default())
+    }
+
+    async fn add_playtime(&self, uuid: &Uuid, namespace: &str, seconds: u64) -> Result<()> {
+        let mut state = self.state();
+        let now = bson::DateTime::now();
+        let playtime = state.playtime.entry((*uuid, namespace.to_string())).or_insert_with(|| StoredPlaytime {
+            namespace: namespace.to_string(),
+            seconds: 0,
+            sessions: 0,
+            last_played: now,
+        });
+        playtime.seconds += seconds as i64;
+        playtime.sessions += 1;
+        playtime.last_played = now;
+
+        let profile = state.players.entry(*uuid).or_insert_with(|| new_profile(*uuid));
+        profile.first_seen.get_or_insert(now);
+        profile.last_seen = Some(now);
+        Ok(())
+    }
+
+    async fn get_playtime(&self, uuid: &Uuid) -> Result<Vec<StoredPlaytime>> {
+        Ok(self.state().playtime.iter()
+            .filter(|((player, _), _)| player == uuid)
+            .map(|(_, playtime)| playtime.clone())
+            .collect())
+    }
+
+    async fn delete_player_data(&self, uuid: &Uuid) -> Result<PlayerDeletionReport> {
+        let mut state = self.state();
+        state.username_history.remove(uuid);
+        state.playtime.retain(|(player, _), _| player != uuid);
+        state.achievements.retain(|(player, _, _), _| player != uuid);
+        state.ratings.retain(|(player, _), _| player != uuid);
+        let mut report = PlayerDeletionReport {
+            profile: state.players.remove(uuid).is_some(),
+            ..Default::default()
+        };
+
+        let mut namespaces = BTreeSet::new();
+        state.player_stats.retain(|(player, namespace, _), _| {
+            if player != uuid {
+                return true;
+            }
+            namespaces.insert(namespace.clone());
+            report.stats_documents += 1;
+            false
+        });
+        report.namespaces = namespaces.into_iter().collect();
+
+        let history_len = state.stat_history.len();
+        state.stat_history.retain(|snapshot| snapshot.uuid != *uuid);
+        report.history_entries = (history_len - state.stat_history.len()) as u64;
+        Ok(report)
+    }
+
+    async fn anonymize_player(&self, uuid: &Uuid) -> Result<PlayerAnonymizationReport> {
+        let mut state = self.state();
+        let mut report = PlayerAnonymizationReport {
+            previous_usernames: state.username_history.remove(uuid).map_or(0, |history| history.len() as u64),
+            ..Default::default()
+        };
+        if let Some(profile) = state.players.get_mut(uuid) {
+            profile.username = None;
+            profile.username_updated_at = None;
+            profile.anonymized = true;
+            report.profile = true;
+        }
+        for snapshot in &mut state.leaderboard_snapshots {
+            let mut anonymized = false;
+            for entry in snapshot.entries.iter_mut().filter(|entry| entry.uuid == *uuid) {
+                entry.username = None;
+                anonymized = true;
+            }
+            report.leaderboard_snapshots += anonymized as u64;
+        }
+        let namespaces: BTreeSet<String> = state.player_stats.keys()
+            .filter(|(player, _, _)| player == uuid)
+            .map(|(_, namespace, _)| namespace.clone())
+            .collect();
+        report.namespaces = namespaces.into_iter().collect();
+        Ok(report)
+    }
+
+    async fn get_player_stats(&self, uuid: &Uuid, namespace: &Option<String>, season: Option<u32>) -> Result<Option<PlayerStatsResponse>> {
+        let state = self.state();
+        if !state.players.contains_key(uuid) { // player not found.
+            return Ok(None);
+        }
+
+        let season = season.unwrap_or(ALL_TIME);
+        let mut final_stats = HashMap::new();
+        for ((player, stats_namespace, stats_season), stats) in &state.player_stats {
+            if player == uuid && *stats_season == season && namespace.as_ref().is_none_or(|namespace| namespace == stats_namespace) {
+                final_stats.insert(stats_namespace.clone(), stat_values(&self.config, stats_namespace, stats.clone()));
+            }
+        }
+        Ok(Some(final_stats))
+    }
+
+    async fn upload_stats_bundle(&self, bundle: GameStatsBundle) -> Result<BundleOutcome> {
+        let namespace = &bundle.namespace;
+        let mut state = self.state();
+        if bundle.bundle_id.is_some_and(|id| state.applied_bundles.contains_key(&id)) {
+            return Ok(BundleOutcome::Duplicate);
+        }
+
+        // Everything is added up before anything is stored, so that a bundle with a mismatched stat changes nothing.
+        let mut seasons = vec![ALL_TIME];
+        if self.config.seasonal_namespaces.contains(namespace) {
+            seasons.push(state.current_season().season);
+        }
+        let mut player_stats = Vec::new();
+        for (player, stats) in &bundle.stats.players {
+            for &season in &seasons {
+                let key = (*player, namespace.clone(), season);
+                let stored = state.player_stats.get(&key).cloned().unwrap_or_default();
+                player_stats.push((key, add_upload(&self.config, namespace, Some(*player), stored, stats)?));
+            }
+        }
+
+        let mut global_stats = None;
+        if let Some(global) = &bundle.stats.global {
+            let stored = state.global_stats.get(namespace).cloned().unwrap_or_default();
+            let updated = add_upload(&self.config, namespace, None, stored, global)?;
+
+            let now = bson::DateTime::now().timestamp_millis();
+            let hour = (namespace.clone(), now - now.rem_euclid(HOUR_MILLIS));
+            let mut rollup = state.rollups.get(&hour).cloned().unwrap_or_default();
+            for (name, stat) in global {
+                let added = add_stat(rollup.get(name), uploaded_stat(stat, &self.config.histogram_buckets(namespace, name)))?;
+                rollup.insert(name.clone(), added);
+            }
+            global_stats = Some((updated, hour, rollup));
+        }
+
+        if self.config.bundle_log.enabled {
+            state.bundle_log.push(bson::DateTime::now());
+        }
+        if let Some(bundle_id) = bundle.bundle_id {
+            state.applied_bundles.insert(bundle_id, bson::DateTime::now());
+        }
+        let recorded_at = bson::DateTime::now();
+        for player in bundle.stats.players.keys() {
+            let profile = state.players.entry(*player).or_insert_with(|| new_profile(*player));
+            profile.first_seen.get_or_insert(recorded_at);
+            profile.last_seen = Some(recorded_at);
+        }
+        for (key, stats) in player_stats {
+            if self.config.stat_history.enabled && key.2 == ALL_TIME {
+                let uploaded = &bundle.stats.players[&key.0];
+                state.stat_history.push(StatSnapshot {
+                    id: ObjectId::new(),
+                    uuid: key.0,
+                    namespace: namespace.clone(),
+                    recorded_at,
+                    stats: stats.iter()
+                        .filter(|(name, _)| uploaded.contains_key(*name))
+                        .map(|(name, stat)| (name.clone(), stat.clone().into()))
+                        .collect(),
+                });
+            }
+            state.player_stats.insert(key, stats);
+        }
+        if let Some((updated, hour, rollup)) = global_stats {
+            state.global_stats.insert(namespace.clone(), updated);
+            state.rollups.insert(hour, rollup);
+        }
+
+        let server_stats = state.server_stats.entry((bundle.server_name.clone(), namespace.clone()))
+            .or_insert_with(|| ServerStats {
+                server_name: bundle.server_name.clone(),
+                namespace: namespace.clone(),
+                bundles: 0,
+                player_uploads: 0,
+                last_upload_at: recorded_at,
+                stats: HashMap::new(),
+            });
+        server_stats.bundles += 1;
+        server_stats.player_uploads += bundle.stats.players.len() as i64;
+        server_stats.last_upload_at = recorded_at;
+        for (stat, count) in ServerStats::stat_counts(&bundle) {
+            *server_stats.stats.entry(stat).or_insert(0) += count;
+        }
+        Ok(BundleOutcome::Applied)
+    }
+
+    async fn check_stats_bundle(&self, bundle: &GameStatsBundle) -> Result<BundleOutcome> {
+        let namespace = &bundle.namespace;
+        let state = self.state();
+        if bundle.bundle_id.is_some_and(|id| state.applied_bundles.contains_key(&id)) {
+            return Ok(BundleOutcome::Duplicate);
+        }
+
+        let mut seasons = vec![ALL_TIME];
+        if self.config.seasonal_namespaces.contains(namespace) {
+            seasons.push(state.current_season().season);
+        }
+        for (player, stats) in &bundle.stats.players {
+            for &season in &seasons {
+                let stored = state.player_stats.get(&(*player, namespace.clone(), season)).cloned().unwrap_or_default();
+                add_upload(&self.config, namespace, Some(*player), stored, stats)?;
+            }
+        }
+        if let Some(global) = &bundle.stats.global {
+            let stored = state.global_stats.get(namespace).cloned().unwrap_or_default();
+            add_upload(&self.config, namespace, None, stored, global)?;
+        }
+        Ok(BundleOutcome::Applied)
+    }
+
+    async fn log_upload(&self, entry: UploadLogEntry) -> Result<()> {
+        let mut state = self.state();
+        state.upload_log.push_back(entry);
+        while state.upload_log.len() as u64 > self.config.upload_log.max_entries {
+            state.upload_log.pop_front();
+        }
+        Ok(())
+    }
+
+    async fn get_upload_log(&self, message: GetUploadLog) -> Result<Vec<UploadLogEntry>> {
+        Ok(self.state().upload_log.iter().rev()
+            .filter(|entry| message.server_name.as_ref().is_none_or(|server_name| entry.server_name == *server_name))
+            .filter(|entry| message.namespace.as_ref().is_none_or(|namespace| entry.namespace == *namespace))
+            .filter(|entry| message.token.as_ref().is_none_or(|token| entry.token == *token))
+            .take(message.limit as usize)
+            .cloned()
+            .collect())
+    }
+
+    async fn get_server_stats(&self, server_name: &str) -> Result<Vec<ServerStats>> {
+        Ok(self.state().server_stats.values()
+            .filter(|stats| stats.server_name == server_name)
+            .cloned()
+            .collect())
+    }
+
+    async fn convert_stat(&self, message: ConvertStat) -> Result<StatConversionReport> {
+        let mut report = StatConversionReport {
+            dry_run: message.dry_run,
+            ..Default::default()
+        };
+
+        let mut state = self.state();
+        let state = &mut *state;
+        let players = state.player_stats.iter_mut()
+            .filter(|((_, namespace, _), _)| *namespace == message.namespace)
+            .map(|((uuid, namespace, season), stats)| (document_key(namespace, Some(uuid), *season), stats));
+        let global = state.global_stats.get_mut(&message.namespace)
+            .map(|stats| (document_key(&message.namespace, None, ALL_TIME), stats));
+
+        for (document, stats) in players.chain(global) {
+            let stat = match stats.get(&message.stat) {
+                Some(stat) => stat,
+                None => continue,
+            };
+            report.matched += 1;
+            match stat.convert(message.to, message.count) {
+                Ok(converted) => {
+                    report.converted += 1;
+                    if !message.dry_run {
+                        stats.insert(message.stat.clone(), converted);
+                    }
+                }
+                Err(e) => report.failed.push(DocumentFailure { document, error: e.to_string() }),
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn rename_stats(&self, namespace: &str, stats: &HashMap<String, String>) -> Result<StatRenameReport> {
+        let mut report = StatRenameReport::default();
+        let mut state = self.state();
+        let state = &mut *state;
+
+        for (from, to) in stats {
+            let players = state.player_stats.iter_mut()
+                .filter(|((_, stats_namespace, _), _)| stats_namespace == namespace)
+                .map(|((uuid, _, season), stats)| (document_key(namespace, Some(uuid), *season), stats));
+            let global = state.global_stats.get_mut(namespace)
+                .map(|stats| (document_key(namespace, None, ALL_TIME), stats));
+            for (document, stats) in players.chain(global) {
+                if !stats.contains_key(from) {
+                    continue;
+                }
+                if stats.contains_key(to) {
+                    report.failed.push(DocumentFailure { document, error: format!("stat '{}' already exists", to) });
+                } else if let Some(stat) = stats.remove(from) {
+                    stats.insert(to.clone(), stat);
+                    report.renamed += 1;
+                }
+            }
+
+            let rollups = state.rollups.iter_mut()
+                .filter(|((rollup_namespace, _), _)| rollup_namespace == namespace)
+                .map(|(_, stats)| stats);
+            for stats in rollups {
+                rename_key(stats, from, to);
+            }
+            for snapshot in state.stat_history.iter_mut().filter(|snapshot| snapshot.namespace == namespace) {
+                rename_key(&mut snapshot.stats, from, to);
+            }
+            for ((_, server_namespace), server) in state.server_stats.iter_mut() {
+                if server_namespace == namespace {
+                    rename_key(&mut server.stats, from, to);
+                }
+            }
+
+            for snapshot in state.leaderboard_snapshots.iter_mut() {
+                if snapshot.namespace == namespace && snapshot.stat == *from {
+                    snapshot.stat = to.clone();
+                }
+            }
+            if let Some(info) = state.stat_info.get_mut(namespace) {
+                rename_key(info, from, to);
+            }
+        }
+        Ok(report)
+    }
+
+    async fn merge_namespace(&self, from: &str, into: &str) -> Result<NamespaceMergeReport> {
+        let mut report = NamespaceMergeReport::default();
+        let mut state = self.state();
+
+        let keys: Vec<_> = state.player_stats.keys()
+            .filter(|(_, namespace, _)| namespace == from)
+            .cloned()
+            .collect();
+        for key in keys {
+            let (uuid, _, season) = key.clone();
+            let target = state.player_stats.get(&(uuid, into.to_string(), season)).cloned().unwrap_or_default();
+            match merge_stats(&state.player_stats[&key], target) {
+                Ok(merged) => {
+                    state.player_stats.remove(&key);
+                    state.player_stats.insert((uuid, into.to_string(), season), merged);
+                    report.merged += 1;
+                }
+                Err(e) => report.failed.push(DocumentFailure { document: document_key(from, Some(&uuid), season), error: e.to_string() }),
+            }
+        }
+
+        if let Some(stats) = state.global_stats.get(from) {
+            let target = state.global_stats.get(into).cloned().unwrap_or_default();
+            match merge_stats(stats, target) {
+                Ok(merged) => {
+                    state.global_stats.remove(from);
+                    state.global_stats.insert(into.to_string(), merged);
+                    report.merged += 1;
+                }
+                Err(e) => report.failed.push(DocumentFailure { document: document_key(from, None, ALL_TIME), error: e.to_string() }),
+            }
+        }
+
+        Ok(report)
+    }
+
+    async fn delete_stat(&self, namespace: &str, stat: &str) -> Result<StatDeletionReport> {
+        let mut report = StatDeletionReport::default();
+        let mut state = self.state();
+        let state = &mut *state;
+
+        for ((_, stats_namespace, _), stats) in state.player_stats.iter_mut() {
+            if stats_namespace == namespace && stats.remove(stat).is_some() {
+                report.stats_documents += 1;
+            }
+        }
+        report.global = state.global_stats.get_mut(namespace).is_some_and(|stats| stats.remove(stat).is_some());
+        for ((rollup_namespace, _), stats) in state.rollups.iter_mut() {
+            if rollup_namespace == namespace {
+                stats.remove(stat);
+            }
+        }
+        for snapshot in state.stat_history.iter_mut().filter(|snapshot| snapshot.namespace == namespace) {
+            if snapshot.stats.remove(stat).is_some() {
+                report.history_entries += 1;
+            }
+        }
+        state.stat_history.retain(|snapshot| snapshot.namespace != namespace || !snapshot.stats.is_empty());
+        for ((_, server_namespace), server) in state.server_stats.iter_mut() {
+            if server_namespace == namespace {
+                server.stats.remove(stat);
+            }
+        }
+
+        let snapshots = state.leaderboard_snapshots.len();
+        state.leaderboard_snapshots.retain(|snapshot| snapshot.namespace != namespace || snapshot.stat != stat);
+        report.leaderboard_snapshots = (snapshots - state.leaderboard_snapshots.len()) as u64;
+        if let Some(info) = state.stat_info.get_mut(namespace) {
+            info.remove(stat);
+        }
+        Ok(report)
+    }
+
+    async fn delete_namespace(&self, namespace: &str) -> Result<NamespaceDeletionReport> {
+        let mut report = NamespaceDeletionReport::default();
+        let mut state = self.state();
+
+        let stats = state.player_stats.len();
+        state.player_stats.retain(|(_, stats_namespace, _), _| stats_namespace != namespace);
+        report.stats_documents = (stats - state.player_stats.len()) as u64;
+        report.global = state.global_stats.remove(namespace).is_some();
+        state.rollups.retain(|(rollup_namespace, _), _| rollup_namespace != namespace);
+        let history = state.stat_history.len();
+        state.stat_history.retain(|snapshot| snapshot.namespace != namespace);
+        report.history_entries = (history - state.stat_history.len()) as u64;
+        let snapshots = state.leaderboard_snapshots.len();
+        state.leaderboard_snapshots.retain(|snapshot| snapshot.namespace != namespace);
+        report.leaderboard_snapshots = (snapshots - state.leaderboard_snapshots.len()) as u64;
+        let games = state.games.len();
+        state.games.retain(|game| game.namespace != namespace);
+        report.games = (games - state.games.len()) as u64;
+        let achievements = state.achievements.len();
+        state.achievements.retain(|(_, achievement_namespace, _), _| achievement_namespace != namespace);
+        report.achievements = (achievements - state.achievements.len()) as u64;
+        let ratings = state.ratings.len();
+        state.ratings.retain(|(_, rating_namespace), _| rating_namespace != namespace);
+        report.ratings = (ratings - state.ratings.len()) as u64;
+        let playtime = state.playtime.len();
+        state.playtime.retain(|(_, playtime_namespace), _| playtime_namespace != namespace);
+        report.playtime = (playtime - state.playtime.len()) as u64;
+        state.stat_info.remove(namespace);
+        state.achievement_info.remove(namespace);
+        state.server_stats.retain(|(_, server_namespace), _| server_namespace != namespace);
+        Ok(report)
+    }
+
+    // Stats are never serialized, so they can't become corrupt.
+    async fn get_corrupt_documents(&self, _limit: i64) -> Result<Vec<CorruptDocument>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_corrupt_document(&self, _id: ObjectId) -> Result<Option<CorruptDocument>> {
+        Ok(None)
+    }
+
+    async fn restore_corrupt_document(&self, _id: ObjectId, _repaired: Document) -> Result<RestoreOutcome> {
+        Ok(RestoreOutcome::NotFound)
+    }
+
+    async fn quarantine_upload(&self, upload: SuspiciousUpload) -> Result<()> {
+        // Kept in the order they were received, even when an upload that failed to be released is put back.
+        let mut state = self.state();
+        let index = state.suspicious_uploads.partition_point(|other| other.id < upload.id);
+        state.suspicious_uploads.insert(index, upload);
+        Ok(())
+    }
+
+    async fn get_suspicious_uploads(&self, limit: i64) -> Result<Vec<SuspiciousUpload>> {
+        Ok(self.state().suspicious_uploads.iter().rev().take(limit.max(0) as usize).cloned().collect())
+    }
+
+    async fn delete_suspicious_upload(&self, id: ObjectId) -> Result<Option<SuspiciousUpload>> {
+        let mut state = self.state();
+        let index = state.suspicious_uploads.iter().position(|upload| upload.id == id);
+        Ok(index.map(|index| state.suspicious_uploads.remove(index)))
+    }
+
+    async fn get_stat_info(&self, namespace: &str) -> Result<HashMap<String, StatInfo>> {
+        Ok(self.state().stat_info.get(namespace).cloned().unwrap_or_default())
+    }
+
+    async fn update_stat_info(&self, namespace: String, stats: HashMap<String, StatInfo>) -> Result<()> {
+        self.state().stat_info.entry(namespace).or_default().extend(stats);
+        Ok(())
+    }
+
+    async fn get_achievement_info(&self, namespace: &str) -> Result<HashMap<String, AchievementInfo>> {
+        Ok(self.state().achievement_info.get(namespace).cloned().unwrap_or_default())
+    }
+
+    async fn update_achievement_info(&self, namespace: String, achievements: HashMap<String, AchievementInfo>) -> Result<()> {
+        self.state().achievement_info.entry(namespace).or_default().extend(achievements);
+        Ok(())
+    }
+
+    async fn grant_achievements(&self, uuid: &Uuid, namespace: &str, achievements: &[String]) -> Result<Vec<String>> {
+        let mut state = self.state();
+        let now = bson::DateTime::now();
+        let mut granted = Vec::new();
+        for achievement in achievements {
+            if let Entry::Vacant(entry) = state.achievements.entry((*uuid, namespace.to_string(), achievement.clone())) {
+                entry.insert(now);
+                granted.push(achievement.clone());
+            }
+        }
+
+        let profile = state.players.entry(*uuid).or_insert_with(|| new_profile(*uuid));
+        profile.first_seen.get_or_insert(now);
+        profile.last_seen = Some(now);
+        Ok(granted)
+    }
+
+    async fn get_player_achievements(&self, uuid: &Uuid, namespace: Option<&str>) -> Result<Vec<StoredAchievement>> {
+        let mut achievements: Vec<StoredAchievement> = self.state().achievements.iter()
+            .filter(|((player, achievement_namespace, _), _)| player == uuid && namespace.is_none_or(|namespace| namespace == achievement_namespace))
+            .map(|((_, namespace, achievement), unlocked_at)| StoredAchievement {
+                namespace: namespace.clone(),
+                achievement: achievement.clone(),
+                unlocked_at: *unlocked_at,
+            })
+            .collect();
+        achievements.sort_by_key(|achievement| std::cmp::Reverse(achievement.unlocked_at));
+        Ok(achievements)
+    }
+
+    async fn get_achievement_unlocks(&self, namespace: &str) -> Result<AchievementUnlocks> {
+        let state = self.state();
+        let mut unlocks = AchievementUnlocks::default();
+        let mut players = HashSet::new();
+        for (uuid, achievement_namespace, achievement) in state.achievements.keys() {
+            if achievement_namespace == namespace {
+                *unlocks.unlocks.entry(achievement.clone()).or_insert(0) += 1;
+                players.insert(*uuid);
+            }
+        }
+        // Players who were granted an achievement without uploading any stats are counted too.
+        players.extend(state.player_stats.keys()
+            .filter(|(_, stats_namespace, season)| stats_namespace == namespace && *season == ALL_TIME)
+            .map(|(uuid, _, _)| *uuid));
+        unlocks.players = players.len() as u64;
+        Ok(unlocks)
+    }
+
+    async fn record_match(&self, namespace: &str, teams: &[MatchTeam]) -> Result<HashMap<Uuid, RatingChange>> {
+        let mut state = self.state();
+        let ratings = state.ratings.iter()
+            .filter(|((_, rating_namespace), _)| rating_namespace == namespace)
+            .map(|((uuid, _), rating)| (*uuid, rating.rating))
+            .collect();
+        let changes = crate::ratings::rate_match(&self.config.ratings, &ratings, teams);
+
+        let now = bson::DateTime::now();
+        for (uuid, change) in &changes {
+            let rating = state.ratings.entry((*uuid, namespace.to_string())).or_insert_with(|| StoredRating {
+                namespace: namespace.to_string(),
+                rating: self.config.ratings.initial_rating,
+                matches: 0,
+                updated_at: now,
+            });
+            rating.rating = change.rating;
+            rating.matches += 1;
+            rating.updated_at = now;
+
+            let profile = state.players.entry(*uuid).or_insert_with(|| new_profile(*uuid));
+            profile.first_seen.get_or_insert(now);
+            profile.last_seen = Some(now);
+        }
+        Ok(changes)
+    }
+
+    async fn get_player_ratings(&self, uuid: &Uuid) -> Result<Vec<StoredRating>> {
+        Ok(self.state().ratings.iter()
+            .filter(|((player, _), _)| player == uuid)
+            .map(|(_, rating)| rating.clone())
+            .collect())
+    }
+
+    async fn get_rating_leaderboard(&self, message: GetRatingLeaderboard) -> Result<Vec<LeaderboardEntry>> {
+        let state = self.state();
+        let mut values: Vec<(Uuid, f64)> = state.ratings.iter()
+            .filter(|((_, namespace), _)| *namespace == message.namespace)
+            .filter(|((uuid, _), _)| message.include_private || !state.players.get(uuid).is_some_and(|profile| profile.private))
+            .map(|((uuid, _), rating)| (*uuid, rating.rating))
+            .collect();
+        values.sort_by(|(a_uuid, a), (b_uuid, b)| b.total_cmp(a).then_with(|| a_uuid.cmp(b_uuid)));
+
+        Ok(values.into_iter()
+            .take(message.limit.max(0) as usize)
+            .enumerate()
+            .map(|(index, (uuid, value))| LeaderboardEntry {
+                rank: index as u32 + 1,
+                uuid,
+                username: state.players.get(&uuid).and_then(|profile| profile.username.clone()),
+                value,
+            })
+            .collect())
+    }
+
+    async fn get_namespaces(&self) -> Result<Vec<String>> {
+        let state = self.state();
+        let mut namespaces: Vec<String> = state.player_stats.keys()
+            .map(|(_, namespace, _)| namespace.clone())
+            .chain(state.global_stats.keys().cloned())
+            .collect();
+        namespaces.sort();
+        namespaces.dedup();
+        Ok(namespaces)
+    }
+
+    async fn get_global_stats(&self, namespace: &str) -> Result<Option<HashMap<String, f64>>> {
+        let stats = self.state().global_stats.get(namespace).cloned();
+        Ok(stats.map(|stats| stat_values(&self.config, namespace, stats)))
+    }
+
+    async fn get_global_stats_delta(&self, namespace: &str, window_hours: u32) -> Result<HashMap<String, f64>> {
+        let start = bson::DateTime::now().timestamp_millis() - window_hours as i64 * HOUR_MILLIS;
+        let start = start - start.rem_euclid(HOUR_MILLIS);
+        let state = self.state();
+        let rollups = state.rollups.range((namespace.to_string(), start)..)
+            .take_while(|((rollup_namespace, _), _)| rollup_namespace == namespace)
+            .map(|(_, stats)| stats.clone());
+        Ok(sum_rollups(rollups))
+    }
+
+    async fn insert_game(&self, game: Game) -> Result<()> {
+        self.state().games.push(game);
+        Ok(())
+    }
+
+    async fn get_games(&self, message: GetGames) -> Result<Vec<Game>> {
+        let state = self.state();
+        let mut games: Vec<Game> = state.games.iter()
+            .filter(|game| match &message.namespace {
+                Some(namespace) => game.namespace == *namespace,
+                None => !message.hidden_namespaces.contains(&game.namespace),
+            })
+            .filter(|game| message.player.is_none_or(|player| game.participants.iter().any(|participant| participant.uuid == player)))
+            .cloned()
+            .collect();
+        games.sort_by_key(|game| std::cmp::Reverse(game.ended_at));
+        games.truncate(message.limit.max(0) as usize);
+
+        if !message.include_private {
+            for game in &mut games {
+                game.participants.retain(|participant| !state.players.get(&participant.uuid).is_some_and(|profile| profile.private));
+            }
+        }
+        Ok(games)
+    }
+
+    async fn get_leaderboard(&self, message: GetLeaderboard) -> Result<Vec<LeaderboardEntry>> {
+        let state = self.state();
+        let season = message.season.unwrap_or(ALL_TIME);
+        let mut values: Vec<(Uuid, f64)> = state.player_stats.iter()
+            .filter(|((_, namespace, stats_season), _)| *namespace == message.namespace && *stats_season == season)
+            .filter(|((uuid, _, _), _)| message.include_private || !state.players.get(uuid).is_some_and(|profile| profile.private))
+            .filter_map(|((uuid, _, _), stats)| Some((*uuid, stats.get(&message.stat)?.clone().into())))
+            .collect();
+        values.sort_by(|(a_uuid, a), (b_uuid, b)| {
+            let order = match message.order {
+                LeaderboardOrder::Descending => b.total_cmp(a),
+                LeaderboardOrder::Ascending => a.total_cmp(b),
+            };
+            order.then_with(|| a_uuid.cmp(b_uuid))
+        });
+
+        Ok(values.into_iter()
+            .take(message.limit.max(0) as usize)
+            .enumerate()
+            .map(|(index, (uuid, value))| LeaderboardEntry {
+                rank: index as u32 + 1,
+                uuid,
+                username: state.players.get(&uuid).and_then(|profile| profile.username.clone()),
+                value,
+            })
+            .collect())
+    }
+
+    async fn get_stat_rank(&self, message: GetStatRank) -> Result<Option<StatRank>> {
+        let state = self.state();
+        let season = message.season.unwrap_or(ALL_TIME);
+        let value: f64 = match state.player_stats.get(&(message.uuid, message.namespace.clone(), season)).and_then(|stats| stats.get(&message.stat)) {
+            Some(stat) => stat.clone().into(),
+            None => return Ok(None),
+        };
+
+        let values: Vec<f64> = state.player_stats.iter()
+            .filter(|((_, namespace, stats_season), _)| *namespace == message.namespace && *stats_season == season)
+            .filter(|((uuid, _, _), _)| message.include_private || !state.players.get(uuid).is_some_and(|profile| profile.private))
+            .filter_map(|(_, stats)| Some(stats.get(&message.stat)?.clone().into()))
+            .collect();
+        let ahead = values.iter()
+            .filter(|other| match message.order {
+                LeaderboardOrder::Descending => **other > value,
+                LeaderboardOrder::Ascending => **other < value,
+            })
+            .count();
+        Ok(Some(StatRank {
+            value,
+            ahead: ahead as u64,
+            players: values.len() as u64,
+        }))
+    }
+
+    async fn get_stat_summary(&self, message: GetStatSummary) -> Result<Option<StatSummaryResponse>> {
+        let state = self.state();
+        let season = message.season.unwrap_or(ALL_TIME);
+        let mut values: Vec<f64> = state.player_stats.iter()
+            .filter(|((_, namespace, stats_season), _)| *namespace == message.namespace && *stats_season == season)
+            .filter_map(|(_, stats)| Some(stats.get(&message.stat)?.clone().into()))
+            .collect();
+        if values.is_empty() {
+            return Ok(None);
+        }
+        values.sort_by(f64::total_cmp);
+
+        let players = values.len();
+        let sum: f64 = values.iter().sum();
+        let mean = sum / players as f64;
+        let median = if players.is_multiple_of(2) {
+            (values[players / 2 - 1] + values[players / 2]) / 2.0
+        } else {
+            values[players / 2]
+        };
+        let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / players as f64;
+        Ok(Some(StatSummaryResponse {
+            players: players as u64,
+            sum,
+            mean,
+            median,
+            stddev: variance.sqrt(),
+        }))
+    }
+
+    async fn get_stat_history(&self, message: GetStatHistory) -> Result<Vec<StatSnapshot>> {
+        let state = self.state();
+        let mut snapshots: Vec<StatSnapshot> = state.stat_history.iter()
+            .filter(|snapshot| snapshot.uuid == message.uuid && snapshot.namespace == message.namespace)
+            .filter(|snapshot| snapshot.stats.contains_key(&message.stat))
+            .filter(|snapshot| message.from.is_none_or(|from| snapshot.recorded_at >= from))
+            .filter(|snapshot| message.until.is_none_or(|until| snapshot.recorded_at < until))
+            .cloned()
+            .collect();
+        snapshots.sort_by_key(|snapshot| snapshot.recorded_at);
+        snapshots.truncate(message.limit.max(0) as usize);
+        for snapshot in &mut snapshots {
+            snapshot.stats.retain(|name, _| *name == message.stat);
+        }
+        Ok(snapshots)
+    }
+
+    async fn get_stat_histogram(&self, message: GetStatHistogram) -> Result<Option<Vec<HistogramBucket>>> {
+        let state = self.state();
+        let stats = match message.uuid {
+            Some(uuid) => state.player_stats.get(&(uuid, message.namespace.clone(), ALL_TIME)),
+            None => state.global_stats.get(&message.namespace),
+        };
+        Ok(stats.and_then(|stats| stats.get(&message.stat)).and_then(GameStat::histogram))
+    }
+
+    async fn get_current_season(&self) -> Result<StoredSeason> {
+        Ok(self.state().current_season())
+    }
+
+    async fn start_season(&self) -> Result<StoredSeason> {
+        let mut state = self.state();
+        let mut season = state.current_season();
+        season.season += 1;
+        season.started_at = Some(bson::DateTime::now());
+        state.season = Some(season.clone());
+        Ok(season)
+    }
+
+    async fn prune_global_stats_rollups(&self) -> Result<u64> {
+        let cutoff = bson::DateTime::now().timestamp_millis() - self.config.global_stats_rollup_retention_hours as i64 * HOUR_MILLIS;
+        let mut state = self.state();
+        let before = state.rollups.len();
+        state.rollups.retain(|(_, hour), _| *hour >= cutoff);
+        Ok((before - state.rollups.len()) as u64)
+    }
+
+    async fn prune_bundle_log(&self) -> Result<u64> {
+        let retention_days = match self.config.bundle_log.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let cutoff = bson::DateTime::now().timestamp_millis() - retention_days as i64 * DAY_MILLIS;
+        let mut state = self.state();
+        let before = state.bundle_log.len();
+        state.bundle_log.retain(|received_at| received_at.timestamp_millis() >= cutoff);
+        Ok((before - state.bundle_log.len()) as u64)
+    }
+
+    async fn prune_stat_history(&self) -> Result<u64> {
+        let retention_days = match self.config.stat_history.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let cutoff = bson::DateTime::now().timestamp_millis() - retention_days as i64 * DAY_MILLIS;
+        let mut state = self.state();
+        let before = state.stat_history.len();
+        state.stat_history.retain(|snapshot| snapshot.recorded_at.timestamp_millis() >= cutoff);
+        Ok((before - state.stat_history.len()) as u64)
+    }
+
+    async fn save_leaderboard_snapshot(&self, snapshot: StoredLeaderboardSnapshot) -> Result<()> {
+        self.state().leaderboard_snapshots.push(snapshot);
+        Ok(())
+    }
+
+    async fn get_leaderboard_snapshot(&self, message: GetLeaderboardSnapshot) -> Result<Option<StoredLeaderboardSnapshot>> {
+        Ok(self.state().leaderboard_snapshots.iter()
+            .filter(|snapshot| snapshot.namespace == message.namespace && snapshot.stat == message.stat && snapshot.order == message.order)
+            .filter(|snapshot| snapshot.taken_at <= message.at)
+            .max_by_key(|snapshot| snapshot.taken_at)
+            .cloned())
+    }
+
+    async fn prune_leaderboard_snapshots(&self) -> Result<u64> {
+        let retention_days = match self.config.leaderboard_snapshots.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let cutoff = bson::DateTime::now().timestamp_millis() - retention_days as i64 * DAY_MILLIS;
+        let mut state = self.state();
+        let before = state.leaderboard_snapshots.len();
+        state.leaderboard_snapshots.retain(|snapshot| snapshot.taken_at.timestamp_millis() >= cutoff);
+        Ok((before - state.leaderboard_snapshots.len()) as u64)
+    }
+
+    async fn prune_bundle_ids(&self) -> Result<u64> {
+        let cutoff = bson::DateTime::now().timestamp_millis() - self.config.bundle_id_retention_hours as i64 * HOUR_MILLIS;
+        let mut state = self.state();
+        let before = state.applied_bundles.len();
+        state.applied_bundles.retain(|_, applied_at| applied_at.timestamp_millis() >= cutoff);
+        Ok((before - state.applied_bundles.len()) as u64)
+    }
+
+    async fn rebuild_aggregates(&self, _message: RebuildAggregates) -> Result<AggregateRebuildReport> {
+        Err(Unsupported("rebuilding aggregates", "in-memory").into())
+    }
+
+    async fn acquire_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<bool> {
+        let mut state = self.state();
+        let now = Instant::now();
+        let available = state.leases.get(name).is_none_or(|(current, expires)| current == holder || *expires <= now);
+        if available {
+            state.leases.insert(name.to_string(), (holder.to_string(), now + ttl));
+        }
+        Ok(available)
+    }
+
+    async fn release_lease(&self, name: &str, holder: &str) -> Result<()> {
+        let mut state = self.state();
+        if state.leases.get(name).is_some_and(|(current, _)| current == holder) {
+            state.leases.remove(name);
+        }
+        Ok(())
+    }
+
+    async fn start_job_run(&self, run: &JobRun) -> Result<()> {
+        self.state().job_runs.push(run.clone());
+        Ok(())
+    }
+
+    async fn finish_job_run(&self, id: ObjectId, result: &std::result::Result<u64, String>) -> Result<()> {
+        let mut state = self.state();
+        if let Some(run) = state.job_runs.iter_mut().find(|run| run.id == id) {
+            run.finished_at = Some(bson::DateTime::now());
+            match result {
+                Ok(items_processed) => {
+                    run.outcome = JobOutcome::Succeeded;
+                    run.items_processed = Some(*items_processed as i64);
+                }
+                Err(error) => {
+                    run.outcome = JobOutcome::Failed;
+                    run.error = Some(error.clone());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn get_job_runs(&self, job: Option<&str>, limit: i64) -> Result<Vec<JobRun>> {
+        let state = self.state();
+        let mut runs: Vec<JobRun> = state.job_runs.iter()
+            .filter(|run| job.is_none_or(|job| run.job == job))
+            .cloned()
+            .collect();
+        runs.sort_by_key(|run| std::cmp::Reverse(run.started_at));
+        runs.truncate(limit.max(0) as usize);
+        Ok(runs)
+    }
+
+    // Nothing survives a restart anyway, so there is nothing worth backing up.
+    async fn backup_manifest(&self) -> Result<BackupManifest> {
+        Err(Unsupported("backing up and restoring", "in-memory").into())
+    }
+
+    async fn export_documents(&self, _collection: &str, _after: Option<serde_json::Value>, _limit: i64) -> Result<BackupBatch> {
+        Err(Unsupported("backing up and restoring", "in-memory").into())
+    }
+
+    async fn import_documents(&self, _collection: &str, _documents: Vec<serde_json::Value>) -> Result<u64> {
+        Err(Unsupported("backing up and restoring", "in-memory").into())
+    }
+}
+
+/// Adds stats onto the stats they are being merged into, failing if they can't be merged.
+fn merge_stats(stats: &HashMap<String, GameStat>, mut target: HashMap<String, GameStat>) -> Result<HashMap<String, GameStat>> {
+    check_mergeable(stats, &target)?;
+    for (name, stat) in stats {
+        let merged = add_stat(target.get(name), stat.clone())?;
+        target.insert(name.clone(), merged);
+    }
+    Ok(target)
+}
+
+/// Identifies a player's or a namespace's stats in admin reports.
+/// Moves the value under one key to another, unless there already is a value under the new key.
+fn rename_key<V>(map: &mut HashMap<String, V>, from: &str, to: &str) {
+    if !map.contains_key(to) {
+        if let Some(value) = map.remove(from) {
+            map.insert(to.to_string(), value);
+        }
+    }
+}
+
+fn document_key(namespace: &str, uuid: Option<&Uuid>, season: u32) -> String {
+    match (uuid, season) {
+        (Some(uuid), ALL_TIME) => format!("{}/{}", namespace, uuid),
+        (Some(uuid), season) => format!("{}/{}/season {}", namespace, uuid, season),
+        (None, _) => format!("{}/global", namespace),
+    }
+}
+
+fn new_profile(uuid: Uuid) -> PlayerProfile {
+    PlayerProfile {
+        uuid,
+        username: None,
+        private: false,
+        username_updated_at: None,
+        first_seen: None,
+        last_seen: None,
+        anonymized: false,
+    }
+}
@@ -0,0 +1,225 @@
+//! Consumes stats bundles from a message queue, so that game servers can publish them without waiting for the API.
+//! Bundles go through the same [Ingest] as `POST /stats/upload`, and are only acknowledged once they have been
+//! accepted, so the broker keeps them while the backend is down. Only available with the `amqp` or `nats` feature.
+
+use std::time::Duration;
+
+use tokio::sync::watch;
+
+use crate::config::{Config, MessageQueueConfig, MessageQueueKind, TokenScope};
+use crate::model::GameStatsBundle;
+use crate::shutdown;
+use crate::web::{Ingest, UploadResult};
+
+/// What is done with a consumed message.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Disposition {
+    /// The bundle was accepted, so the message is removed from the queue.
+    Ack,
+    /// The bundle can never be accepted, so the message is dropped, or dead-lettered if the broker is set up to.
+    Reject,
+    /// The bundle couldn't be written right now, so the message is delivered again later.
+    Requeue,
+}
+
+impl Disposition {
+    fn label(self) -> &'static str {
+        match self {
+            Disposition::Ack => "acked",
+            Disposition::Reject => "rejected",
+            Disposition::Requeue => "requeued",
+        }
+    }
+}
+
+/// Starts consuming bundles in the background until the process is asked to shut down, reconnecting to the broker
+/// whenever the connection is lost. Fails straight away if the consumer is misconfigured.
+pub fn spawn(config: &Config, ingest: Ingest) -> anyhow::Result<()> {
+    let settings = config.message_queue.clone();
+    match config.token_by_name(&settings.token) {
+        Some(token) if token.has_scope(TokenScope::UploadStats) => {}
+        Some(_) => anyhow::bail!("message_queue.token '{}' doesn't have the upload_stats scope", settings.token),
+        None => anyhow::bail!("message_queue.token '{}' isn't one of the configured tokens", settings.token),
+    }
+    let (feature, built) = match settings.kind {
+        MessageQueueKind::Amqp => ("amqp", cfg!(feature = "amqp")),
+        MessageQueueKind::Nats => ("nats", cfg!(feature = "nats")),
+    };
+    if !built {
+        anyhow::bail!("message_queue.kind is {}, but the backend was built without the {} feature", feature, feature);
+    }
+
+    let (stop, stopping) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown::signal().await;
+        let _ = stop.send(true);
+    });
+
+    tokio::spawn(async move {
+        let consumer = Consumer { settings, ingest };
+        consumer.run(stopping).await;
+    });
+    Ok(())
+}
+
+struct Consumer {
+    settings: MessageQueueConfig,
+    ingest: Ingest,
+}
+
+impl Consumer {
+    async fn run(&self, mut stopping: watch::Receiver<bool>) {
+        let retry_delay = Duration::from_secs(self.settings.retry_delay_secs);
+        loop {
+            match self.consume(&mut stopping).await {
+                Ok(()) => {
+                    log::info!("Stopped consuming stats bundles from the message queue");
+                    return;
+                }
+                Err(e) => log::warn!("stopped consuming from the message queue, reconnecting in {}s: {}", retry_delay.as_secs(), e),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(retry_delay) => {}
+                _ = stopping.changed() => return,
+            }
+        }
+    }
+
+    /// Consumes messages until shutdown, returning an error if the connection to the broker is lost.
+    async fn consume(&self, stopping: &mut watch::Receiver<bool>) -> anyhow::Result<()> {
+        match self.settings.kind {
+            #[cfg(feature = "amqp")]
+            MessageQueueKind::Amqp => self.consume_amqp(stopping).await,
+            #[cfg(feature = "nats")]
+            MessageQueueKind::Nats => self.consume_nats(stopping).await,
+            #[allow(unreachable_patterns)]
+            kind => anyhow::bail!("the backend was built without support for {:?} message queues", kind),
+        }
+    }
+
+    /// Uploads the bundle in a message, deciding what to do with the message from the outcome.
+    async fn handle(&self, payload: &[u8]) -> Disposition {
+        let disposition = match serde_json::from_slice::<GameStatsBundle>(payload) {
+            Ok(bundle) => self.upload(bundle).await,
+            Err(e) => {
+                log::warn!("dropping message from the message queue that isn't a stats bundle: {}", e);
+                Disposition::Reject
+            }
+        };
+        if disposition == Disposition::Requeue {
+            // Waiting before handing the message back stops it from being redelivered straight away.
+            tokio::time::sleep(Duration::from_secs(self.settings.retry_delay_secs)).await;
+        }
+        self.ingest.metrics.increment("nucleoid_message_queue_messages_total", &[("outcome", disposition.label())]);
+        disposition
+    }
+
+    async fn upload(&self, bundle: GameStatsBundle) -> Disposition {
+        let (server_name, namespace) = (bundle.server_name.clone(), bundle.namespace.clone());
+        // The token is looked up by name for each bundle, so that it can be rotated by reloading the config.
+        let token = match self.ingest.config.token_by_name(&self.settings.token) {
+            Some(token) => token.token,
+            None => {
+                log::error!("requeueing bundle for {} from '{}' from the message queue: message_queue.token '{}' is no longer configured",
+                    namespace, server_name, self.settings.token);
+                return Disposition::Requeue;
+            }
+        };
+        match self.ingest.upload(token, bundle).await {
+            UploadResult::Applied | UploadResult::Duplicate | UploadResult::Queued(_) | UploadResult::Spooled | UploadResult::Quarantined(_) => Disposition::Ack,
+            UploadResult::Unauthorized(status) => {
+                log::error!("dropping bundle for {} from '{}' from the message queue: the token was refused with {}", namespace, server_name, status);
+                Disposition::Reject
+            }
+            UploadResult::Invalid(violations) => {
+                log::warn!("dropping bundle for {} from '{}' from the message queue: {} limits broken", namespace, server_name, violations.len());
+                Disposition::Reject
+            }
+            UploadResult::Rejected(error) | UploadResult::TypeMismatch(error) => {
+                log::warn!("dropping bundle for {} from '{}' from the message queue: {}", namespace, server_name, error);
+                Disposition::Reject
+            }
+            UploadResult::Unavailable => Disposition::Requeue,
+            UploadResult::Failed(e) => {
+                log::warn!("failed to write bundle for {} from '{}' from the message queue, requeueing it: {}", namespace, server_name, e);
+                Disposition::Requeue
+            }
+        }
+    }
+
+    /// Consumes the configured queue, which is declared as durable if it doesn't exist, until shutdown.
+    #[cfg(feature = "amqp")]
+    async fn consume_amqp(&self, stopping: &mut watch::Receiver<bool>) -> anyhow::Result<()> {
+        use futures::StreamExt;
+        use lapin::options::{BasicAckOptions, BasicConsumeOptions, BasicNackOptions, BasicQosOptions, BasicRejectOptions, QueueDeclareOptions};
+        use lapin::types::FieldTable;
+        use lapin::{Connection, ConnectionProperties};
+
+        let connection = Connection::connect(&self.settings.url, ConnectionProperties::default()).await?;
+        let channel = connection.create_channel().await?;
+        channel.basic_qos(self.settings.prefetch, BasicQosOptions::default()).await?;
+        let queue = &self.settings.subject;
+        channel.queue_declare(queue, QueueDeclareOptions { durable: true, ..Default::default() }, FieldTable::default()).await?;
+        let mut deliveries = channel.basic_consume(queue, "nucleoid-persistence", BasicConsumeOptions::default(), FieldTable::default()).await?;
+        log::info!("Consuming stats bundles from AMQP queue {}", queue);
+
+        loop {
+            let delivery = tokio::select! {
+                delivery = deliveries.next() => delivery,
+                _ = stopping.changed() => break,
+            };
+            let delivery = match delivery {
+                Some(delivery) => delivery?,
+                None => anyhow::bail!("the broker cancelled the consumer"),
+            };
+            match self.handle(&delivery.data).await {
+                Disposition::Ack => delivery.ack(BasicAckOptions::default()).await?,
+                Disposition::Reject => delivery.reject(BasicRejectOptions { requeue: false }).await?,
+                Disposition::Requeue => delivery.nack(BasicNackOptions { requeue: true, ..Default::default() }).await?,
+            }
+        }
+        connection.close(0, "shutting down").await?;
+        Ok(())
+    }
+
+    /// Consumes the configured subject through a durable JetStream consumer, creating its stream if it doesn't exist,
+    /// until shutdown.
+    #[cfg(feature = "nats")]
+    async fn consume_nats(&self, stopping: &mut watch::Receiver<bool>) -> anyhow::Result<()> {
+        use async_nats::jetstream::{self, consumer::{pull, PullConsumer}, stream, AckKind};
+        use futures::StreamExt;
+
+        let client = async_nats::connect(&self.settings.url).await?;
+        let context = jetstream::new(client);
+        let stream = context.get_or_create_stream(stream::Config {
+            name: self.settings.stream.clone(),
+            subjects: vec![self.settings.subject.clone()],
+            ..Default::default()
+        }).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+        let consumer: PullConsumer = stream.get_or_create_consumer("nucleoid-persistence", pull::Config {
+            durable_name: Some("nucleoid-persistence".to_string()),
+            filter_subject: self.settings.subject.clone(),
+            max_ack_pending: self.settings.prefetch.into(),
+            ..Default::default()
+        }).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+        let mut messages = consumer.messages().await?;
+        log::info!("Consuming stats bundles from NATS subject {}", self.settings.subject);
+
+        loop {
+            let message = tokio::select! {
+                message = messages.next() => message,
+                _ = stopping.changed() => return Ok(()),
+            };
+            let message = match message {
+                Some(message) => message.map_err(|e| anyhow::anyhow!("{}", e))?,
+                None => anyhow::bail!("the server closed the consumer"),
+            };
+            let ack = match self.handle(&message.payload).await {
+                Disposition::Ack => AckKind::Ack,
+                Disposition::Reject => AckKind::Term,
+                Disposition::Requeue => AckKind::Nak(None),
+            };
+            message.ack_with(ack).await.map_err(|e| anyhow::anyhow!("{}", e))?;
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+
+type Labels = Vec<(String, String)>;
+
+/// Counters exposed in the Prometheus text format at `/metrics`.
+#[derive(Clone, Default)]
+pub struct Metrics {
+    counters: Arc<Mutex<BTreeMap<&'static str, BTreeMap<Labels, u64>>>>,
+}
+
+impl Metrics {
+    pub fn increment(&self, name: &'static str, labels: &[(&str, &str)]) {
+        self.add(name, labels, 1);
+    }
+
+    pub fn add(&self, name: &'static str, labels: &[(&str, &str)], value: u64) {
+        let labels = labels.iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+
+        let mut counters = self.counters.lock().unwrap();
+        *counters.entry(name).or_default().entry(labels).or_default() += value;
+    }
+
+    pub fn render(&self) -> String {
+        let counters = self.counters.lock().unwrap();
+        let mut out = String::new();
+        for (name, series) in counters.iter() {
+            writeln!(out, "# TYPE {} counter", name).unwrap();
+            for (labels, value) in series {
+                if labels.is_empty() {
+                    writeln!(out, "{} {}", name, value).unwrap();
+                } else {
+                    let labels = labels.iter()
+                        .map(|(key, value)| format!("{}=\"{}\"", key, escape_label_value(value)))
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    writeln!(out, "{}{{{}}} {}", name, labels, value).unwrap();
+                }
+            }
+        }
+        out
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
@@ -0,0 +1,85 @@
+//! Versioned migrations, which bring the documents and tables of an existing database up to date when the backend
+//! starts. Each backend lists its own migrations, and records how many of them have been applied as the schema
+//! version in its `meta` collection.
+
+use std::time::Duration;
+
+use anyhow::Result;
+use async_trait::async_trait;
+
+use crate::config::Config;
+use crate::lease;
+use crate::store::StatsStore;
+
+/// The lease held while migrating, so that instances starting at the same time don't migrate concurrently.
+const LEASE: &str = "schema_migrations";
+
+/// How long to wait before checking again whether another instance has finished migrating.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+
+#[async_trait]
+pub trait Migrations: StatsStore {
+    /// Names of the migrations, in the order that they are applied. Migrations are only ever appended, as the schema
+    /// version is the number of them that have been applied.
+    fn migrations(&self) -> Vec<&'static str>;
+
+    async fn schema_version(&self) -> Result<usize>;
+
+    /// Applies the migration at `index` and records it, so that the schema version becomes `index + 1`. Migrations
+    /// are left partly applied if they fail, so they have to be safe to run again.
+    async fn apply_migration(&self, index: usize) -> Result<()>;
+}
+
+/// Applies every migration that the database hasn't had yet, waiting for any other instance that is already
+/// migrating it. Fails if the database was migrated by a newer version of the backend.
+pub async fn migrate(store: &impl Migrations, config: &Config) -> Result<()> {
+    let holder = lease::random_id();
+    let ttl = Duration::from_secs(config.admin_lock_ttl_secs);
+    loop {
+        if check_version(store).await? == store.migrations().len() {
+            return Ok(());
+        }
+        if store.acquire_lease(LEASE, &holder, ttl).await? {
+            break;
+        }
+        log::info!("Waiting for another instance to migrate the database...");
+        tokio::time::sleep(RETRY_DELAY).await;
+    }
+
+    let res = apply_pending(store).await;
+    if let Err(e) = store.release_lease(LEASE, &holder).await {
+        log::warn!("failed to release lease '{}', it will be held until it expires: {}", LEASE, e);
+    }
+    res
+}
+
+/// Checks that a database which isn't migrated by this instance can be used, warning if it is out of date.
+pub async fn check(store: &impl Migrations) -> Result<()> {
+    let version = check_version(store).await?;
+    if version < store.migrations().len() {
+        log::warn!("The database is at schema version {} rather than {}, and needs to be migrated by an instance that isn't read-only",
+            version, store.migrations().len());
+    }
+    Ok(())
+}
+
+async fn apply_pending(store: &impl Migrations) -> Result<()> {
+    // Another instance may have migrated the database between checking its version and taking the lease.
+    let version = check_version(store).await?;
+    for (index, name) in store.migrations().into_iter().enumerate().skip(version) {
+        log::info!("Applying migration {} ({})...", index + 1, name);
+        store.apply_migration(index).await
+            .map_err(|e| anyhow::anyhow!("migration {} ({}) failed: {}", index + 1, name, e))?;
+    }
+    log::info!("Migrated the database to schema version {}", store.migrations().len());
+    Ok(())
+}
+
+async fn check_version(store: &impl Migrations) -> Result<usize> {
+    let version = store.schema_version().await?;
+    if version > store.migrations().len() {
+        anyhow::bail!("the database is at schema version {}, which is newer than the {} that this version of the backend supports",
+            version, store.migrations().len());
+    }
+    Ok(version)
+}
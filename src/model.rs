@@ -1,20 +1,77 @@
 use serde::{Serialize, Deserialize};
 use uuid::Uuid;
-use bson::{Document, doc};
+use bson::{Bson, Document, doc};
+use bson::Decimal128;
+use bson::oid::ObjectId;
 use std::collections::HashMap;
 
+use crate::util::uuid_from_bson;
+
+pub use nucleoid_persistence_api::{
+    AchievementInfo, AchievementSummary, AchievementsResponse, AggregateRebuildReport, BackupUploadResponse, ConvertStatRequest, CorruptDocumentResponse, CorruptDocumentSummary, DocumentFailure,
+    ErrorResponse, GameParticipant, GameResponse, GameStatsBundle, GameUploadRequest, GameUploadResponse,
+    GlobalStatsDeltaResponse, GrantAchievementsRequest, GrantAchievementsResponse, GlobalStatsResponse, HistogramBucket, JobOutcome, JobResponse, JobRunResponse,
+    JobTrigger, LeaderboardEntry, LeaderboardOrder, LeaderboardResponse, LeaderboardSnapshotResponse, MatchResultRequest, MatchResultResponse, MatchTeam,
+    StatRankResponse, StatSummaryResponse, LiveBundleEvent, MergeNamespaceRequest,
+    NamespaceDeletionReport, NamespaceMergeReport, NamespacePlaytime, PlayerAnonymizationReport, PlayerDeletionReport, PlayerListResponse, PlayerProfileResponse, PlayerStatsBundle, PlayerStatsResponse, PlayerRating, PlaytimeReport, PlaytimeResponse, RatingChange, RebuildAggregatesRequest,
+    RebuildMode, RenameStatsRequest, RestoreCorruptDocumentRequest, RestoreReport, RunJobResponse, SeasonResponse, ServerNamespaceSummary,
+    ServerStatsSummary, StatAggregation,
+    StatConversionReport, StatDeletionReport, StatHistoryPoint, StatInfo, StatInfoResponse, StatMismatch, StatRenameReport, StatType, StatsBundle, SuspiciousUploadResponse,
+    UpdatePlayerProfileRequest, UploadDryRunOutcome, UploadDryRunResponse, UploadLogResponse, UploadQueuedResponse, UploadStat, UploadState, UploadStatusResponse,
+    UnlockedAchievement, UsernameHistoryEntry, ValidationErrorResponse, Violation,
+};
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PlayerProfile {
     #[serde(with = "bson::serde_helpers::uuid_as_binary")]
     pub uuid: Uuid,
     pub username: Option<String>,
+    /// Private players have their username and stats hidden from unauthenticated requests.
+    #[serde(default)]
+    pub private: bool,
+    /// When the username was last changed, so that the current holder of a name can be found.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username_updated_at: Option<bson::DateTime>,
+    /// When the player was first uploaded or had their profile updated. Unknown for players who were last seen
+    /// before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<bson::DateTime>,
+    /// When the player was last uploaded or had their profile updated.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<bson::DateTime>,
+    /// Anonymized players have no username, and never get one again, but keep their stats.
+    #[serde(default)]
+    pub anonymized: bool,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct PlayerProfileResponse {
-    pub uuid: Uuid,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub username: Option<String>,
+/// A username that a player changed away from, kept in the player's `username_history`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PreviousUsername {
+    pub username: String,
+    /// When the player took the name, which isn't known for names set before they were recorded.
+    pub used_from: Option<bson::DateTime>,
+    pub used_until: bson::DateTime,
+}
+
+impl From<PreviousUsername> for UsernameHistoryEntry {
+    fn from(name: PreviousUsername) -> Self {
+        Self {
+            username: name.username,
+            used_from: name.used_from.map(to_rfc3339),
+            used_until: Some(to_rfc3339(name.used_until)),
+        }
+    }
+}
+
+impl PlayerProfile {
+    /// The player's current username as an entry of their username history.
+    pub fn current_username(&self) -> Option<UsernameHistoryEntry> {
+        Some(UsernameHistoryEntry {
+            username: self.username.clone()?,
+            used_from: self.username_updated_at.map(to_rfc3339),
+            used_until: None,
+        })
+    }
 }
 
 impl From<PlayerProfile> for PlayerProfileResponse {
@@ -22,6 +79,11 @@ impl From<PlayerProfile> for PlayerProfileResponse {
         Self {
             uuid: p.uuid,
             username: p.username,
+            private: Some(p.private),
+            first_seen: p.first_seen.map(to_rfc3339),
+            last_seen: p.last_seen.map(to_rfc3339),
+            ratings: HashMap::new(),
+            anonymized: p.anonymized,
         }
     }
 }
@@ -40,80 +102,806 @@ pub struct GlobalGameStats {
     pub stats: HashMap<String, GameStat>,
 }
 
+/// The increments made to a namespace's global stats within one hour.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GlobalStatsRollup {
+    pub namespace: String,
+    /// The start of the hour that this rollup covers.
+    pub hour: bson::DateTime,
+    pub stats: HashMap<String, GameStat>,
+}
+
+/// The registered info of one stat, stored in the `stat-metadata` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredStatInfo {
+    pub namespace: String,
+    pub stat: String,
+    #[serde(flatten)]
+    pub info: StatInfo,
+    pub updated_at: bson::DateTime,
+}
+
+/// The registered info of an achievement, stored in the `achievement-metadata` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredAchievementInfo {
+    pub namespace: String,
+    pub achievement: String,
+    #[serde(flatten)]
+    pub info: AchievementInfo,
+    pub updated_at: bson::DateTime,
+}
+
+/// An achievement that a player has unlocked, stored in the `achievements` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredAchievement {
+    pub namespace: String,
+    pub achievement: String,
+    pub unlocked_at: bson::DateTime,
+}
+
+impl From<StoredAchievement> for UnlockedAchievement {
+    fn from(achievement: StoredAchievement) -> Self {
+        Self {
+            namespace: achievement.namespace,
+            achievement: achievement.achievement,
+            unlocked_at: to_rfc3339(achievement.unlocked_at),
+        }
+    }
+}
+
+/// The top of a leaderboard at a point in time, stored in the `leaderboard-snapshots` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredLeaderboardSnapshot {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub namespace: String,
+    pub stat: String,
+    pub order: LeaderboardOrder,
+    pub taken_at: bson::DateTime,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
+impl From<StoredLeaderboardSnapshot> for LeaderboardSnapshotResponse {
+    fn from(snapshot: StoredLeaderboardSnapshot) -> Self {
+        Self {
+            taken_at: to_rfc3339(snapshot.taken_at),
+            order: snapshot.order,
+            entries: snapshot.entries,
+        }
+    }
+}
+
+/// A player's rating in a namespace, stored in the `ratings` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredRating {
+    pub namespace: String,
+    pub rating: f64,
+    pub matches: i64,
+    pub updated_at: bson::DateTime,
+}
+
+impl From<StoredRating> for PlayerRating {
+    fn from(rating: StoredRating) -> Self {
+        Self {
+            rating: rating.rating,
+            matches: rating.matches as u64,
+        }
+    }
+}
+
+/// How many players have unlocked each achievement of a namespace.
+#[derive(Debug, Clone, Default)]
+pub struct AchievementUnlocks {
+    /// Number of players with stats or achievements in the namespace.
+    pub players: u64,
+    /// Number of players who have unlocked each achievement, keyed by its id.
+    pub unlocks: HashMap<String, u64>,
+}
+
+/// A completed match, stored in the `games` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Game {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub server_name: String,
+    pub namespace: String,
+    pub started_at: bson::DateTime,
+    pub ended_at: bson::DateTime,
+    pub participants: Vec<StoredGameParticipant>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredGameParticipant {
+    #[serde(with = "bson::serde_helpers::uuid_as_binary")]
+    pub uuid: Uuid,
+    pub winner: bool,
+    pub score: Option<f64>,
+}
+
+impl From<Game> for GameResponse {
+    fn from(game: Game) -> Self {
+        Self {
+            id: game.id.to_hex(),
+            server_name: game.server_name,
+            namespace: game.namespace,
+            started_at: to_rfc3339(game.started_at),
+            ended_at: to_rfc3339(game.ended_at),
+            participants: game.participants.into_iter()
+                .map(|participant| GameParticipant {
+                    uuid: participant.uuid,
+                    winner: participant.winner,
+                    score: participant.score,
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A record of one execution of a background job, stored in the `job-runs` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct JobRun {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub job: String,
+    pub trigger: JobTrigger,
+    pub started_at: bson::DateTime,
+    pub finished_at: Option<bson::DateTime>,
+    pub outcome: JobOutcome,
+    pub items_processed: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// The values of a player's stats after a bundle was applied, stored in the `stat-history` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StatSnapshot {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    #[serde(with = "bson::serde_helpers::uuid_as_binary")]
+    pub uuid: Uuid,
+    pub namespace: String,
+    pub recorded_at: bson::DateTime,
+    /// The values of the stats that were in the bundle, as they are returned by the API.
+    pub stats: HashMap<String, f64>,
+}
+
+impl StatSnapshot {
+    /// The recorded value of a stat, if it was in the snapshot.
+    pub fn point(&self, stat: &str) -> Option<StatHistoryPoint> {
+        Some(StatHistoryPoint {
+            recorded_at: to_rfc3339(self.recorded_at),
+            value: *self.stats.get(stat)?,
+        })
+    }
+}
+
+/// The current season, stored in the `meta` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredSeason {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub season: u32,
+    pub started_at: Option<bson::DateTime>,
+}
+
+impl From<StoredSeason> for SeasonResponse {
+    fn from(season: StoredSeason) -> Self {
+        Self {
+            season: season.season,
+            started_at: season.started_at.map(to_rfc3339),
+        }
+    }
+}
+
+/// An accepted bundle as it was written, stored in the append-only `bundle-log` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BundleLogEntry {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub received_at: bson::DateTime,
+    /// Whether all of the bundle's stats were added to the aggregates. Bundles that failed part way through may have
+    /// been partly applied.
+    pub applied: bool,
+    pub server_name: String,
+    pub namespace: String,
+    pub global: Option<HashMap<String, UploadStat>>,
+    /// Player stats, keyed by the hyphenated UUID of the player.
+    pub players: HashMap<String, HashMap<String, UploadStat>>,
+}
+
+impl BundleLogEntry {
+    pub fn new(bundle: &GameStatsBundle) -> Self {
+        Self {
+            id: ObjectId::new(),
+            received_at: bson::DateTime::now(),
+            applied: false,
+            server_name: bundle.server_name.clone(),
+            namespace: bundle.namespace.clone(),
+            global: bundle.stats.global.clone(),
+            players: bundle.stats.players.iter()
+                .map(|(player, stats)| (player.to_hyphenated().to_string(), stats.clone()))
+                .collect(),
+        }
+    }
+
+    /// Recreates the bundle that this entry was logged from.
+    pub fn to_bundle(&self) -> Result<GameStatsBundle, uuid::Error> {
+        let players = self.players.iter()
+            .map(|(player, stats)| Ok((Uuid::parse_str(player)?, stats.clone())))
+            .collect::<Result<_, uuid::Error>>()?;
+        // Replayed bundles are applied again on purpose, so they aren't checked against the IDs of applied bundles.
+        Ok(GameStatsBundle {
+            server_name: self.server_name.clone(),
+            namespace: self.namespace.clone(),
+            stats: StatsBundle {
+                global: self.global.clone(),
+                players,
+            },
+            bundle_id: None,
+        })
+    }
+}
+
+/// An upload that added more to a stat than its `max_increment`, kept in the `suspicious-uploads` collection instead of
+/// being applied until it is released or discarded.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SuspiciousUpload {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub received_at: bson::DateTime,
+    /// The name of the token that the upload was made with.
+    pub token: String,
+    pub violations: Vec<Violation>,
+    pub server_name: String,
+    pub namespace: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_id: Option<Uuid>,
+    pub global: Option<HashMap<String, UploadStat>>,
+    /// Player stats, keyed by the hyphenated UUID of the player.
+    pub players: HashMap<String, HashMap<String, UploadStat>>,
+}
+
+impl SuspiciousUpload {
+    pub fn new(token: &str, bundle: &GameStatsBundle, violations: Vec<Violation>) -> Self {
+        let entry = BundleLogEntry::new(bundle);
+        Self {
+            id: entry.id,
+            received_at: entry.received_at,
+            token: token.to_string(),
+            violations,
+            server_name: entry.server_name,
+            namespace: entry.namespace,
+            bundle_id: bundle.bundle_id,
+            global: entry.global,
+            players: entry.players,
+        }
+    }
+
+    /// Recreates the bundle that was uploaded.
+    pub fn to_bundle(&self) -> Result<GameStatsBundle, uuid::Error> {
+        let players = self.players.iter()
+            .map(|(player, stats)| Ok((Uuid::parse_str(player)?, stats.clone())))
+            .collect::<Result<_, uuid::Error>>()?;
+        Ok(GameStatsBundle {
+            server_name: self.server_name.clone(),
+            namespace: self.namespace.clone(),
+            stats: StatsBundle {
+                global: self.global.clone(),
+                players,
+            },
+            bundle_id: self.bundle_id,
+        })
+    }
+
+    pub fn to_response(&self) -> Result<SuspiciousUploadResponse, uuid::Error> {
+        Ok(SuspiciousUploadResponse {
+            id: self.id.to_hex(),
+            received_at: to_rfc3339(self.received_at),
+            token: self.token.clone(),
+            violations: self.violations.clone(),
+            bundle: self.to_bundle()?,
+        })
+    }
+}
+
+/// An accepted upload, stored in the capped `upload-log` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct UploadLogEntry {
+    pub received_at: bson::DateTime,
+    /// The name of the token that the upload was made with.
+    pub token: String,
+    pub server_name: String,
+    pub namespace: String,
+    pub players: i64,
+    pub stats: i64,
+    pub spooled: bool,
+}
+
+impl UploadLogEntry {
+    pub fn new(token: &str, bundle: &GameStatsBundle, spooled: bool) -> Self {
+        let player_stats = bundle.stats.players.values().map(HashMap::len).sum::<usize>();
+        Self {
+            received_at: bson::DateTime::now(),
+            token: token.to_string(),
+            server_name: bundle.server_name.clone(),
+            namespace: bundle.namespace.clone(),
+            players: bundle.stats.players.len() as i64,
+            stats: (player_stats + bundle.stats.global.as_ref().map_or(0, HashMap::len)) as i64,
+            spooled,
+        }
+    }
+}
+
+impl From<UploadLogEntry> for UploadLogResponse {
+    fn from(entry: UploadLogEntry) -> Self {
+        Self {
+            received_at: to_rfc3339(entry.received_at),
+            token: entry.token,
+            server_name: entry.server_name,
+            namespace: entry.namespace,
+            players: entry.players as u64,
+            stats: entry.stats as u64,
+            spooled: entry.spooled,
+        }
+    }
+}
+
+/// Where a player ranks by a stat.
+#[derive(Debug, Clone, Copy)]
+pub struct StatRank {
+    pub value: f64,
+    /// The number of players with a better value.
+    pub ahead: u64,
+    pub players: u64,
+}
+
+impl From<StatRank> for StatRankResponse {
+    fn from(rank: StatRank) -> Self {
+        let below = rank.players.saturating_sub(rank.ahead + 1);
+        Self {
+            value: rank.value,
+            rank: rank.ahead + 1,
+            players: rank.players,
+            percentile: below as f64 * 100.0 / rank.players.max(1) as f64,
+        }
+    }
+}
+
+/// Counts of what a game server has uploaded to a namespace, stored in the `server-stats` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ServerStats {
+    pub server_name: String,
+    pub namespace: String,
+    pub bundles: i64,
+    pub player_uploads: i64,
+    pub last_upload_at: bson::DateTime,
+    /// Number of values uploaded for each stat.
+    pub stats: HashMap<String, i64>,
+}
+
+impl ServerStats {
+    /// Counts the values that a bundle uploads for each stat.
+    pub fn stat_counts(bundle: &GameStatsBundle) -> HashMap<String, i64> {
+        let mut counts = HashMap::new();
+        for stat in bundle.stats.global.iter().chain(bundle.stats.players.values()).flat_map(HashMap::keys) {
+            *counts.entry(stat.clone()).or_insert(0) += 1;
+        }
+        counts
+    }
+}
+
+/// A player's playtime in a namespace, stored in the `playtime` collection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StoredPlaytime {
+    pub namespace: String,
+    pub seconds: i64,
+    pub sessions: i64,
+    pub last_played: bson::DateTime,
+}
+
+impl From<StoredPlaytime> for NamespacePlaytime {
+    fn from(playtime: StoredPlaytime) -> Self {
+        Self {
+            seconds: playtime.seconds as u64,
+            sessions: playtime.sessions as u64,
+            last_played: to_rfc3339(playtime.last_played),
+        }
+    }
+}
+
+impl From<ServerStats> for ServerNamespaceSummary {
+    fn from(stats: ServerStats) -> Self {
+        Self {
+            bundles: stats.bundles as u64,
+            player_uploads: stats.player_uploads as u64,
+            last_upload_at: to_rfc3339(stats.last_upload_at),
+            stats: stats.stats.into_iter().map(|(stat, count)| (stat, count as u64)).collect(),
+        }
+    }
+}
+
+impl From<JobRun> for JobRunResponse {
+    fn from(run: JobRun) -> Self {
+        Self {
+            id: run.id.to_hex(),
+            job: run.job,
+            trigger: run.trigger,
+            started_at: to_rfc3339(run.started_at),
+            finished_at: run.finished_at.map(to_rfc3339),
+            outcome: run.outcome,
+            items_processed: run.items_processed,
+            error: run.error,
+        }
+    }
+}
+
+/// A stats document that couldn't be read, kept in the `corrupt_stats` collection until it is repaired.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CorruptDocument {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub collection: String,
+    pub namespace: Option<String>,
+    pub error: Option<String>,
+    pub quarantined_at: Option<bson::DateTime>,
+    pub document: Document,
+}
+
+impl CorruptDocument {
+    /// Reads a record of the `corrupt_stats` collection. Older versions stored the broken document by itself, so the
+    /// details of those are worked out from the document.
+    pub fn from_record(record: Document) -> Option<Self> {
+        if record.contains_key("document") {
+            return bson::from_document(record).ok();
+        }
+
+        let collection = if record.contains_key("uuid") { "player-stats" } else { "global-stats" };
+        Some(Self {
+            id: record.get_object_id("_id").ok()?,
+            collection: collection.to_string(),
+            namespace: record.get_str("namespace").ok().map(str::to_string),
+            error: None,
+            quarantined_at: None,
+            document: record,
+        })
+    }
+
+    pub fn summary(&self) -> CorruptDocumentSummary {
+        CorruptDocumentSummary {
+            id: self.id.to_hex(),
+            collection: self.collection.clone(),
+            namespace: self.namespace.clone(),
+            player: self.document.get("uuid").and_then(|uuid| uuid_from_bson(uuid.clone()).ok()),
+            error: self.error.clone(),
+            quarantined_at: self.quarantined_at.map(to_rfc3339),
+        }
+    }
+}
+
+impl From<CorruptDocument> for CorruptDocumentResponse {
+    fn from(document: CorruptDocument) -> Self {
+        Self {
+            summary: document.summary(),
+            document: Bson::Document(document.document).into_canonical_extjson(),
+        }
+    }
+}
+
+fn to_rfc3339(date_time: bson::DateTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(date_time.to_system_time()).to_rfc3339()
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type", content = "value", rename_all = "snake_case")]
 pub enum GameStat {
-    IntTotal(i32),
+    // Int totals are stored as 64-bit values so that they can't overflow, see create_increment_operation.
+    IntTotal(i64),
+    #[serde(rename = "int_rolling_average")]
     IntAverage {
-        total: i32,
+        total: i64,
+        count: i32,
+    },
+    LongTotal(i64),
+    #[serde(rename = "long_rolling_average")]
+    LongAverage {
+        total: i64,
         count: i32,
     },
     FloatTotal(f64),
+    #[serde(rename = "float_rolling_average")]
     FloatAverage {
         total: f64,
         count: i32,
     },
+    DecimalTotal(Decimal128),
+    #[serde(rename = "decimal_rolling_average")]
+    DecimalAverage {
+        total: Decimal128,
+        count: i32,
+    },
+    // Stored as 64-bit values like int totals, so that all int stats have the same representation.
+    IntMin(i64),
+    IntMax(i64),
+    FloatMin(f64),
+    FloatMax(f64),
+    Latest(f64),
+    /// Counts keyed by the index of their bucket, whose upper bounds are `bounds`. Buckets that have never counted a
+    /// value are missing.
+    Histogram {
+        bounds: Vec<f64>,
+        counts: HashMap<String, i64>,
+    },
 }
 
-impl Into<f64> for GameStat {
-    fn into(self) -> f64 {
-        match self {
+impl From<GameStat> for f64 {
+    fn from(stat: GameStat) -> Self {
+        match stat {
             GameStat::IntTotal(v) => v as f64,
             GameStat::IntAverage { total, count } => (total as f64) / (count as f64),
+            GameStat::LongTotal(v) => v as f64,
+            GameStat::LongAverage { total, count } => (total as f64) / (count as f64),
             GameStat::FloatTotal(v) => v,
             GameStat::FloatAverage { total, count } => total / (count as f64),
+            GameStat::DecimalTotal(v) => decimal_to_f64(&v),
+            GameStat::DecimalAverage { total, count } => decimal_to_f64(&total) / (count as f64),
+            GameStat::IntMin(v) | GameStat::IntMax(v) => v as f64,
+            GameStat::FloatMin(v) | GameStat::FloatMax(v) | GameStat::Latest(v) => v,
+            GameStat::Histogram { counts, .. } => counts.values().sum::<i64>() as f64,
         }
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct GlobalStatsBundle {
-    pub namespace: String,
-    pub stats: HashMap<String, GameStat>,
+fn decimal_to_f64(decimal: &Decimal128) -> f64 {
+    decimal.to_string().parse().unwrap_or(f64::NAN)
 }
 
-pub type PlayerStatsResponse = HashMap<String, HashMap<String, f64>>;
-pub type PlayerStatsBundle = HashMap<Uuid, HashMap<String, UploadStat>>;
+#[derive(thiserror::Error, Debug)]
+pub enum StatConversionError {
+    #[error("a count must be supplied to convert a total into an average")]
+    MissingCount,
+    #[error("value cannot be represented as {0:?}")]
+    OutOfRange(StatType),
+    #[error("histograms cannot be converted to or from other types")]
+    Histogram,
+}
 
-#[derive(Serialize, Deserialize)]
-pub struct GameStatsBundle {
-    pub server_name: String,
-    pub namespace: String,
-    pub stats: StatsBundle,
+/// A stat total kept in its stored representation, so that conversions only lose precision when they must.
+enum StatNumber {
+    Integer(i64),
+    Float(f64),
+    Decimal(Decimal128),
 }
 
-#[derive(Serialize, Deserialize)]
-pub struct StatsBundle {
-    pub global: Option<HashMap<String, UploadStat>>,
-    pub players: PlayerStatsBundle,
+impl StatNumber {
+    fn to_i64(&self) -> Option<i64> {
+        let value = match self {
+            StatNumber::Integer(value) => return Some(*value),
+            StatNumber::Float(value) => *value,
+            StatNumber::Decimal(value) => decimal_to_f64(value),
+        };
+        if value.is_finite() && value >= i64::MIN as f64 && value < i64::MAX as f64 {
+            Some(value.round() as i64)
+        } else {
+            None
+        }
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        let value = match self {
+            StatNumber::Integer(value) => *value as f64,
+            StatNumber::Float(value) => *value,
+            StatNumber::Decimal(value) => decimal_to_f64(value),
+        };
+        Some(value).filter(|value| value.is_finite())
+    }
+
+    fn to_decimal(&self) -> Option<Decimal128> {
+        match self {
+            StatNumber::Integer(value) => Some(Decimal128::from_str(&value.to_string())),
+            StatNumber::Float(value) if value.is_finite() => Some(Decimal128::from_str(&format!("{:e}", value))),
+            StatNumber::Float(_) => None,
+            StatNumber::Decimal(value) => Some(value.clone()),
+        }
+    }
 }
 
-#[derive(Serialize, Deserialize)]
-#[serde(rename_all = "snake_case", tag = "type", content = "value")]
-pub enum UploadStat {
-    IntTotal(i32),
-    IntRollingAverage(i32),
-    FloatTotal(f64),
-    FloatRollingAverage(f64),
+impl GameStat {
+    pub fn stat_type(&self) -> StatType {
+        match self {
+            GameStat::IntTotal(_) => StatType::IntTotal,
+            GameStat::IntAverage { .. } => StatType::IntRollingAverage,
+            GameStat::LongTotal(_) => StatType::LongTotal,
+            GameStat::LongAverage { .. } => StatType::LongRollingAverage,
+            GameStat::FloatTotal(_) => StatType::FloatTotal,
+            GameStat::FloatAverage { .. } => StatType::FloatRollingAverage,
+            GameStat::DecimalTotal(_) => StatType::DecimalTotal,
+            GameStat::DecimalAverage { .. } => StatType::DecimalRollingAverage,
+            GameStat::IntMin(_) => StatType::IntMin,
+            GameStat::IntMax(_) => StatType::IntMax,
+            GameStat::FloatMin(_) => StatType::FloatMin,
+            GameStat::FloatMax(_) => StatType::FloatMax,
+            GameStat::Latest(_) => StatType::Latest,
+            GameStat::Histogram { .. } => StatType::Histogram,
+        }
+    }
+
+    /// The total of this stat, the accumulated total if it is an average, or the value kept by other stats.
+    pub fn total(&self) -> f64 {
+        match self.total_and_count().0 {
+            StatNumber::Integer(v) => v as f64,
+            StatNumber::Float(v) => v,
+            StatNumber::Decimal(v) => decimal_to_f64(&v),
+        }
+    }
+
+    /// The number of values that make up this stat, if it is an average.
+    pub fn count(&self) -> Option<i32> {
+        self.total_and_count().1
+    }
+
+    fn total_and_count(&self) -> (StatNumber, Option<i32>) {
+        match self {
+            GameStat::IntTotal(v) | GameStat::LongTotal(v) => (StatNumber::Integer(*v), None),
+            GameStat::IntAverage { total, count } | GameStat::LongAverage { total, count } => (StatNumber::Integer(*total), Some(*count)),
+            GameStat::FloatTotal(v) => (StatNumber::Float(*v), None),
+            GameStat::FloatAverage { total, count } => (StatNumber::Float(*total), Some(*count)),
+            GameStat::DecimalTotal(v) => (StatNumber::Decimal(v.clone()), None),
+            GameStat::DecimalAverage { total, count } => (StatNumber::Decimal(total.clone()), Some(*count)),
+            GameStat::IntMin(v) | GameStat::IntMax(v) => (StatNumber::Integer(*v), None),
+            GameStat::FloatMin(v) | GameStat::FloatMax(v) | GameStat::Latest(v) => (StatNumber::Float(*v), None),
+            GameStat::Histogram { counts, .. } => (StatNumber::Integer(counts.values().sum()), None),
+        }
+    }
+
+    /// The buckets of this stat, if it is a histogram.
+    pub fn histogram(&self) -> Option<Vec<HistogramBucket>> {
+        let (bounds, counts) = match self {
+            GameStat::Histogram { bounds, counts } => (bounds, counts),
+            _ => return None,
+        };
+        Some((0..=bounds.len())
+            .map(|index| HistogramBucket {
+                min: index.checked_sub(1).map(|index| bounds[index]),
+                max: bounds.get(index).copied(),
+                count: counts.get(&index.to_string()).copied().unwrap_or_default(),
+            })
+            .collect())
+    }
+
+    /// Generate a BSON document for adding this stat onto the stat `id` of another document. Minimums and maximums
+    /// keep the lower or higher of the two values, latest values replace the other document's value, and histograms
+    /// add their counts onto the buckets with the same index.
+    pub fn create_merge_operation(&self, id: &str) -> Document {
+        let value_key = format!("stats.{}.value", id);
+        let type_key = format!("stats.{}.type", id);
+        let total_key = format!("{}.total", value_key);
+        let count_key = format!("{}.count", value_key);
+        let stat_type = bson::to_bson(&self.stat_type()).expect("stat types always serialize");
+
+        let inc = match self {
+            GameStat::IntMin(v) => return doc! { "$min": { value_key: v }, "$set": { type_key: stat_type } },
+            GameStat::IntMax(v) => return doc! { "$max": { value_key: v }, "$set": { type_key: stat_type } },
+            GameStat::FloatMin(v) => return doc! { "$min": { value_key: v }, "$set": { type_key: stat_type } },
+            GameStat::FloatMax(v) => return doc! { "$max": { value_key: v }, "$set": { type_key: stat_type } },
+            GameStat::Latest(v) => return doc! { "$set": { value_key: v, type_key: stat_type } },
+            GameStat::Histogram { bounds, counts } => {
+                let inc: Document = counts.iter()
+                    .map(|(index, count)| (format!("{}.counts.{}", value_key, index), Bson::Int64(*count)))
+                    .collect();
+                let mut update = doc! { "$set": { format!("{}.bounds", value_key): bounds, type_key: stat_type } };
+                if !inc.is_empty() {
+                    update.insert("$inc", inc);
+                }
+                return update;
+            }
+            GameStat::IntTotal(v) | GameStat::LongTotal(v) => doc! { value_key: v },
+            GameStat::FloatTotal(v) => doc! { value_key: v },
+            GameStat::DecimalTotal(v) => doc! { value_key: Bson::Decimal128(v.clone()) },
+            GameStat::IntAverage { total, count } | GameStat::LongAverage { total, count } => doc! { total_key: total, count_key: count },
+            GameStat::FloatAverage { total, count } => doc! { total_key: total, count_key: count },
+            GameStat::DecimalAverage { total, count } => doc! { total_key: Bson::Decimal128(total.clone()), count_key: count },
+        };
+
+        doc! {
+            "$inc": inc,
+            "$set": { type_key: stat_type },
+        }
+    }
+
+    /// Converts this stat into another stored type.
+    ///
+    /// Averages keep their existing count, other stats converted into averages use the supplied `count`, and averages
+    /// converted into any other type keep their accumulated total.
+    pub fn convert(&self, to: StatType, count: Option<i32>) -> Result<GameStat, StatConversionError> {
+        if self.stat_type() == StatType::Histogram || to == StatType::Histogram {
+            return Err(StatConversionError::Histogram);
+        }
+
+        let (total, existing_count) = self.total_and_count();
+        let count = existing_count.or(count);
+        let count = || count.ok_or(StatConversionError::MissingCount);
+        let out_of_range = || StatConversionError::OutOfRange(to);
+
+        Ok(match to {
+            StatType::IntTotal => GameStat::IntTotal(total.to_i64().ok_or_else(out_of_range)?),
+            StatType::IntRollingAverage => GameStat::IntAverage { total: total.to_i64().ok_or_else(out_of_range)?, count: count()? },
+            StatType::LongTotal => GameStat::LongTotal(total.to_i64().ok_or_else(out_of_range)?),
+            StatType::LongRollingAverage => GameStat::LongAverage { total: total.to_i64().ok_or_else(out_of_range)?, count: count()? },
+            StatType::FloatTotal => GameStat::FloatTotal(total.to_f64().ok_or_else(out_of_range)?),
+            StatType::FloatRollingAverage => GameStat::FloatAverage { total: total.to_f64().ok_or_else(out_of_range)?, count: count()? },
+            StatType::DecimalTotal => GameStat::DecimalTotal(total.to_decimal().ok_or_else(out_of_range)?),
+            StatType::DecimalRollingAverage => GameStat::DecimalAverage { total: total.to_decimal().ok_or_else(out_of_range)?, count: count()? },
+            StatType::IntMin => GameStat::IntMin(total.to_i64().ok_or_else(out_of_range)?),
+            StatType::IntMax => GameStat::IntMax(total.to_i64().ok_or_else(out_of_range)?),
+            StatType::FloatMin => GameStat::FloatMin(total.to_f64().ok_or_else(out_of_range)?),
+            StatType::FloatMax => GameStat::FloatMax(total.to_f64().ok_or_else(out_of_range)?),
+            StatType::Latest => GameStat::Latest(total.to_f64().ok_or_else(out_of_range)?),
+            StatType::Histogram => return Err(StatConversionError::Histogram),
+        })
+    }
 }
 
-impl UploadStat {
-    /// Generate a BSON document for increasing this value.
-    pub fn create_increment_operation(&self, id: &str) -> Document {
+/// Database operations for uploaded stats.
+pub trait UploadStatExt {
+    /// Generate a BSON document for increasing this value. Histogram values are counted in the buckets with the upper
+    /// bounds `buckets`, which must be in ascending order.
+    fn create_increment_operation(&self, id: &str, buckets: &[f64]) -> Document;
+
+    /// Generate an update pipeline for increasing this value, storing the total as a Decimal128.
+    ///
+    /// The value is converted from its decimal string form on the server so no binary floating point error is
+    /// introduced, and any existing non-decimal total is promoted by `$add`.
+    fn create_decimal_increment_pipeline(&self, id: &str) -> Vec<Document>;
+}
+
+/// The exact decimal representation of a value, as understood by `$toDecimal`.
+fn decimal_string(stat: &UploadStat) -> String {
+    match stat {
+        UploadStat::IntTotal(value) | UploadStat::IntRollingAverage(value)
+            | UploadStat::IntRollingAverageBatch { total: value, .. } => value.to_string(),
+        UploadStat::LongTotal(value) | UploadStat::LongRollingAverage(value)
+            | UploadStat::LongRollingAverageBatch { total: value, .. } => value.to_string(),
+        UploadStat::FloatTotal(value) | UploadStat::FloatRollingAverage(value)
+            | UploadStat::FloatRollingAverageBatch { total: value, .. } => format!("{:e}", value),
+        UploadStat::IntMin(value) | UploadStat::IntMax(value) => value.to_string(),
+        UploadStat::FloatMin(value) | UploadStat::FloatMax(value) | UploadStat::Latest(value) => format!("{:e}", value),
+        UploadStat::Histogram(_) => format!("{:e}", stat.value()),
+    }
+}
+
+impl UploadStatExt for UploadStat {
+    fn create_increment_operation(&self, id: &str, buckets: &[f64]) -> Document {
         let value_key = format!("stats.{}.value", id);
         let type_key = format!("stats.{}.type", id);
         let total_key = format!("{}.total", value_key);
         let count_key = format!("{}.count", value_key);
 
         match self {
+            // Int stats are incremented as Int64 so that totals nearing i32::MAX are widened instead of overflowing.
             UploadStat::IntTotal(value) => doc! {
-                "$inc": { value_key: value },
+                "$inc": { value_key: Bson::Int64(*value as i64) },
                 "$set": { type_key: "int_total" }
             },
             UploadStat::IntRollingAverage(value) => doc! {
-                "$inc": { total_key: value, count_key: 1 },
+                "$inc": { total_key: Bson::Int64(*value as i64), count_key: 1 },
                 "$set": { type_key: "int_rolling_average" }
             },
+            UploadStat::IntRollingAverageBatch { total, count } => doc! {
+                "$inc": { total_key: Bson::Int64(*total as i64), count_key: *count as i32 },
+                "$set": { type_key: "int_rolling_average" }
+            },
+            // Explicitly Int64 so that the server widens the stored field rather than keeping it an int32.
+            UploadStat::LongTotal(value) => doc! {
+                "$inc": { value_key: Bson::Int64(*value) },
+                "$set": { type_key: "long_total" }
+            },
+            UploadStat::LongRollingAverage(value) => doc! {
+                "$inc": { total_key: Bson::Int64(*value), count_key: 1 },
+                "$set": { type_key: "long_rolling_average" }
+            },
+            UploadStat::LongRollingAverageBatch { total, count } => doc! {
+                "$inc": { total_key: Bson::Int64(*total), count_key: *count as i32 },
+                "$set": { type_key: "long_rolling_average" }
+            },
             UploadStat::FloatTotal(value) => doc! {
                 "$inc": { value_key: value },
                 "$set": { type_key: "float_total" }
@@ -122,6 +910,67 @@ impl UploadStat {
                 "$inc": { total_key: value, count_key: 1 },
                 "$set": { type_key: "float_rolling_average" }
             },
+            UploadStat::FloatRollingAverageBatch { total, count } => doc! {
+                "$inc": { total_key: total, count_key: *count as i32 },
+                "$set": { type_key: "float_rolling_average" }
+            },
+            UploadStat::IntMin(value) => doc! {
+                "$min": { value_key: Bson::Int64(*value as i64) },
+                "$set": { type_key: "int_min" }
+            },
+            UploadStat::IntMax(value) => doc! {
+                "$max": { value_key: Bson::Int64(*value as i64) },
+                "$set": { type_key: "int_max" }
+            },
+            UploadStat::FloatMin(value) => doc! {
+                "$min": { value_key: value },
+                "$set": { type_key: "float_min" }
+            },
+            UploadStat::FloatMax(value) => doc! {
+                "$max": { value_key: value },
+                "$set": { type_key: "float_max" }
+            },
+            UploadStat::Latest(value) => doc! {
+                "$set": { value_key: value, type_key: "latest" }
+            },
+            UploadStat::Histogram(values) => {
+                let mut counts: HashMap<usize, i64> = HashMap::new();
+                for value in values {
+                    *counts.entry(buckets.partition_point(|bound| bound <= value)).or_default() += 1;
+                }
+                let inc: Document = counts.into_iter()
+                    .map(|(index, count)| (format!("{}.counts.{}", value_key, index), Bson::Int64(count)))
+                    .collect();
+                // The bounds are stored with the counts so that they can be read without the config.
+                let mut update = doc! { "$set": { format!("{}.bounds", value_key): buckets, type_key: "histogram" } };
+                if !inc.is_empty() {
+                    update.insert("$inc", inc);
+                }
+                update
+            }
         }
     }
+
+    fn create_decimal_increment_pipeline(&self, id: &str) -> Vec<Document> {
+        let value_key = format!("stats.{}.value", id);
+        let type_key = format!("stats.{}.type", id);
+        let total_key = format!("{}.total", value_key);
+        let count_key = format!("{}.count", value_key);
+        let value = doc! { "$toDecimal": decimal_string(self) };
+
+        let set = if self.is_average() {
+            doc! {
+                &total_key: { "$add": [{ "$ifNull": [format!("${}", total_key), { "$toDecimal": 0 }] }, value] },
+                &count_key: { "$add": [{ "$ifNull": [format!("${}", count_key), 0] }, self.count() as i32] },
+                type_key: "decimal_rolling_average",
+            }
+        } else {
+            doc! {
+                &value_key: { "$add": [{ "$ifNull": [format!("${}", value_key), { "$toDecimal": 0 }] }, value] },
+                type_key: "decimal_total",
+            }
+        };
+
+        vec![doc! { "$set": set }]
+    }
 }
@@ -0,0 +1,149 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Deserialize;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::config::MojangConfig;
+use crate::metrics::Metrics;
+
+/// How many cached lookups are kept before expired ones are first dropped.
+const MIN_PRUNE_ENTRIES: usize = 1024;
+
+/// Looks up the usernames of players whose profiles don't have one, such as those only created by stats uploads,
+/// from the Mojang session API. Lookups are cached, including for players that Mojang doesn't know, and limited to
+/// the configured rate so that the backend's address isn't blocked.
+#[derive(Clone)]
+pub struct UsernameResolver {
+    inner: Option<Arc<Resolver>>,
+}
+
+struct Resolver {
+    config: MojangConfig,
+    http: reqwest::Client,
+    state: Mutex<State>,
+    metrics: Metrics,
+}
+
+struct State {
+    cache: HashMap<Uuid, CachedLookup>,
+    /// How many cached lookups are kept before expired ones are next dropped.
+    prune_at: usize,
+    /// Lookups that can be made right now, refilling steadily up to `requests_per_minute`.
+    tokens: f64,
+    updated: Instant,
+}
+
+struct CachedLookup {
+    username: Option<String>,
+    expires: Instant,
+}
+
+#[derive(Deserialize)]
+struct SessionProfile {
+    name: String,
+}
+
+impl UsernameResolver {
+    /// Creates a resolver, which never finds a username unless lookups are enabled.
+    pub fn new(config: &MojangConfig, metrics: Metrics) -> Self {
+        if !config.enabled {
+            return Self { inner: None };
+        }
+        let http = reqwest::Client::builder()
+            .timeout(Duration::from_millis(config.timeout_ms))
+            .build()
+            .expect("failed to create HTTP client");
+        Self {
+            inner: Some(Arc::new(Resolver {
+                config: config.clone(),
+                http,
+                state: Mutex::new(State {
+                    cache: HashMap::new(),
+                    prune_at: MIN_PRUNE_ENTRIES,
+                    tokens: config.requests_per_minute as f64,
+                    updated: Instant::now(),
+                }),
+                metrics,
+            })),
+        }
+    }
+
+    /// Finds the current username of a player, or `None` if Mojang doesn't know them, or they couldn't be looked up
+    /// right now.
+    pub async fn resolve(&self, uuid: Uuid) -> Option<String> {
+        let resolver = self.inner.as_ref()?;
+        if let Some(cached) = resolver.cached(uuid) {
+            return cached;
+        }
+        if !resolver.take_token() {
+            log::debug!("not looking up the username of {} as the Mojang rate limit was reached", uuid);
+            resolver.metrics.increment("nucleoid_mojang_lookups_total", &[("outcome", "rate_limited")]);
+            return None;
+        }
+
+        let url = format!("{}/{}", resolver.config.url.trim_end_matches('/'), uuid.to_simple());
+        let (username, outcome) = match resolver.lookup(&url).await {
+            Ok(Some(username)) => (Some(username), "found"),
+            Ok(None) => (None, "not_found"),
+            Err(e) => {
+                // Failed lookups aren't cached, so they are tried again by a later request.
+                log::warn!("failed to look up the username of {} from Mojang: {}", uuid, e);
+                resolver.metrics.increment("nucleoid_mojang_lookups_total", &[("outcome", "failed")]);
+                return None;
+            }
+        };
+        resolver.metrics.increment("nucleoid_mojang_lookups_total", &[("outcome", outcome)]);
+        resolver.cache(uuid, username.clone());
+        username
+    }
+}
+
+impl Resolver {
+    fn cached(&self, uuid: Uuid) -> Option<Option<String>> {
+        let state = self.state.lock().unwrap();
+        state.cache.get(&uuid)
+            .filter(|cached| cached.expires > Instant::now())
+            .map(|cached| cached.username.clone())
+    }
+
+    fn take_token(&self) -> bool {
+        let capacity = self.config.requests_per_minute as f64;
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.tokens = (state.tokens + now.duration_since(state.updated).as_secs_f64() * capacity / 60.0).min(capacity);
+        state.updated = now;
+        if state.tokens >= 1.0 {
+            state.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Fetches a player's profile, which the session API responds to with a `204 No Content` or `404 Not Found` if
+    /// there is no such player.
+    async fn lookup(&self, url: &str) -> reqwest::Result<Option<String>> {
+        let response = self.http.get(url).send().await?;
+        if matches!(response.status(), reqwest::StatusCode::NO_CONTENT | reqwest::StatusCode::NOT_FOUND) {
+            return Ok(None);
+        }
+        let profile: SessionProfile = response.error_for_status()?.json().await?;
+        Ok(Some(profile.name))
+    }
+
+    fn cache(&self, uuid: Uuid, username: Option<String>) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        state.cache.insert(uuid, CachedLookup {
+            username,
+            expires: now + Duration::from_secs(self.config.cache_ttl_secs),
+        });
+        if state.cache.len() >= state.prune_at {
+            state.cache.retain(|_, cached| cached.expires > now);
+            state.prune_at = (state.cache.len() * 2).max(MIN_PRUNE_ENTRIES);
+        }
+    }
+}
@@ -0,0 +1,545 @@
+// The functions below only carry the `#[utoipa::path]` documentation of each route. The routes themselves are built
+// with warp in `web`, whose handlers take the backend's state rather than the request's parameters.
+#![allow(dead_code)]
+
+use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
+use utoipa::{Modify, OpenApi};
+
+use crate::model::{
+    AchievementInfo, AchievementSummary, AchievementsResponse, ErrorResponse, GameParticipant, GameResponse, GameStatsBundle,
+    GameUploadRequest, GameUploadResponse, GrantAchievementsRequest, GrantAchievementsResponse, HistogramBucket, LeaderboardEntry, LeaderboardOrder, LeaderboardSnapshotResponse, MatchResultRequest, MatchResultResponse, MatchTeam, NamespacePlaytime, PlayerProfileResponse, PlayerRating, PlaytimeReport, PlaytimeResponse, RatingChange, SeasonResponse,
+    ServerNamespaceSummary, ServerStatsSummary, StatHistoryPoint, StatInfo, StatRankResponse, StatSummaryResponse, StatsBundle, UnlockedAchievement, UpdatePlayerProfileRequest,
+    UploadDryRunOutcome, UploadDryRunResponse, UploadQueuedResponse, UploadStat, UploadState, UploadStatusResponse, UsernameHistoryEntry, ValidationErrorResponse, Violation,
+};
+
+/// The OpenAPI document served at `/openapi.json`, covering the routes used by game servers and other clients.
+/// Admin routes are left out, as they are documented in the README for the operators who use them.
+#[derive(OpenApi)]
+#[openapi(
+    info(
+        title = "Nucleoid persistence backend",
+        description = "Player profiles and per-minigame statistics for Nucleoid servers.",
+    ),
+    paths(
+        get_player_profile, get_player_by_name, search_players, get_username_history, get_playtime, report_playtime, get_player_achievements, grant_achievements,
+        update_player_profile, get_all_player_stats, get_player_stats,
+        get_stat_history, get_stat_histogram, get_stat_rank, get_player_games, upload_stats, get_upload_status, get_namespaces, get_global_stats,
+        get_stat_summary, get_global_stats_delta, get_global_stat_histogram, get_server_stats, get_stat_metadata, update_stat_metadata, get_leaderboard, get_leaderboard_snapshot,
+        get_current_season, upload_game, get_recent_games, get_achievements, update_achievements, record_match, get_rating_leaderboard,
+    ),
+    components(schemas(
+        AchievementInfo, AchievementSummary, AchievementsResponse, ErrorResponse, GameParticipant, GameResponse, GameStatsBundle,
+        GameUploadRequest, GameUploadResponse, GrantAchievementsRequest, GrantAchievementsResponse, HistogramBucket, LeaderboardEntry, LeaderboardOrder, LeaderboardSnapshotResponse, MatchResultRequest, MatchResultResponse, MatchTeam, NamespacePlaytime, PlayerProfileResponse, PlayerRating, PlaytimeReport, PlaytimeResponse, RatingChange, SeasonResponse,
+        ServerNamespaceSummary, ServerStatsSummary, StatHistoryPoint, StatInfo, StatRankResponse, StatSummaryResponse, StatsBundle, UnlockedAchievement, UpdatePlayerProfileRequest,
+        UploadDryRunOutcome, UploadDryRunResponse, UploadQueuedResponse, UploadStat, UploadState, UploadStatusResponse, UsernameHistoryEntry, ValidationErrorResponse, Violation,
+    )),
+    modifiers(&TokenAuth),
+    tags(
+        (name = "players", description = "Player profiles and their stats"),
+        (name = "stats", description = "Uploading stats, and global stats and leaderboards"),
+        (name = "games", description = "Completed matches"),
+        (name = "achievements", description = "Achievements of each namespace, and the players who unlocked them"),
+        (name = "ratings", description = "Elo ratings of players, maintained from the results of matches"),
+    ),
+)]
+pub struct ApiDoc;
+
+/// Declares the token that is sent in the `Authorization` header, and the signature that uploads can send instead.
+struct TokenAuth;
+
+impl Modify for TokenAuth {
+    fn modify(&self, openapi: &mut utoipa::openapi::OpenApi) {
+        if let Some(components) = openapi.components.as_mut() {
+            components.add_security_scheme("token", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "Authorization",
+                "A token from the `tokens` option of the backend's config. Tokens with the `read_private` scope see \
+                 private players and internal namespaces on read routes.",
+            ))));
+            components.add_security_scheme("signature", SecurityScheme::ApiKey(ApiKey::Header(ApiKeyValue::with_description(
+                "X-Signature",
+                "The hex-encoded HMAC-SHA256, keyed by a token, of `{timestamp}\\n{nonce}\\n{body}`. The token is named by \
+                 `X-Signature-Key`, and the timestamp and nonce are sent in `X-Signature-Timestamp` and \
+                 `X-Signature-Nonce`.",
+            ))));
+        }
+    }
+}
+
+/// Gets the profile of a player.
+#[utoipa::path(
+    get, path = "/player/{uuid}", tag = "players",
+    params(("uuid" = String, Path, description = "The UUID of the player")),
+    responses(
+        (status = 200, body = PlayerProfileResponse),
+        (status = 404, description = "The player is unknown"),
+    ),
+)]
+fn get_player_profile() {}
+
+/// Gets the profile of the player who most recently had a username, ignoring case.
+#[utoipa::path(
+    get, path = "/player/by-name/{username}", tag = "players",
+    params(("username" = String, Path, description = "The username, in any case")),
+    responses(
+        (status = 200, body = PlayerProfileResponse),
+        (status = 404, description = "No player has had the username, or they are private"),
+    ),
+)]
+fn get_player_by_name() {}
+
+/// Finds the players whose current username starts with a prefix, ignoring case, ordered by username.
+#[utoipa::path(
+    get, path = "/players/search", tag = "players",
+    params(
+        ("q" = String, Query, description = "The start of the username, from 1 to 16 characters"),
+        ("limit" = Option<i64>, Query, description = "From 1 to 50, defaulting to 10"),
+    ),
+    responses(
+        (status = 200, body = Vec<PlayerProfileResponse>, description = "The matching players, without their ratings. Private players are only found with the `read_private` scope"),
+        (status = 400, description = "`q` is empty or too long, or `limit` is out of range"),
+    ),
+)]
+fn search_players() {}
+
+/// Lists the usernames that a player has used, newest first and starting with their current username.
+#[utoipa::path(
+    get, path = "/player/{uuid}/names", tag = "players",
+    params(("uuid" = String, Path, description = "The UUID of the player")),
+    responses(
+        (status = 200, body = Vec<UsernameHistoryEntry>),
+        (status = 401, description = "The token is unknown"),
+        (status = 403, description = "The token doesn't have the `read_private` scope"),
+        (status = 404, description = "The player is unknown"),
+    ),
+    security(("token" = [])),
+)]
+fn get_username_history() {}
+
+/// Gets how long a player has played in total and in each namespace.
+#[utoipa::path(
+    get, path = "/player/{uuid}/playtime", tag = "players",
+    params(("uuid" = String, Path, description = "The UUID of the player")),
+    responses(
+        (status = 200, body = PlaytimeResponse),
+        (status = 404, description = "The player is unknown"),
+    ),
+)]
+fn get_playtime() {}
+
+/// Adds a play session onto a player's playtime in a namespace.
+#[utoipa::path(
+    post, path = "/player/{uuid}/playtime", tag = "players",
+    params(("uuid" = String, Path, description = "The UUID of the player")),
+    request_body = PlaytimeReport,
+    responses(
+        (status = 204, description = "The session was added"),
+        (status = 400, description = "The session is shorter than a second or longer than a day"),
+        (status = 401, body = ErrorResponse, description = "The token is unknown, or the signature is invalid or was already used"),
+        (status = 403, description = "The token doesn't have the `upload_stats` scope"),
+    ),
+    security(("token" = []), ("signature" = [])),
+)]
+fn report_playtime() {}
+
+/// Lists the achievements that a player has unlocked, most recently unlocked first.
+#[utoipa::path(
+    get, path = "/player/{uuid}/achievements", tag = "achievements",
+    params(
+        ("uuid" = String, Path, description = "The UUID of the player"),
+        ("namespace" = Option<String>, Query, description = "Only list achievements of this namespace"),
+    ),
+    responses(
+        (status = 200, body = Vec<UnlockedAchievement>),
+        (status = 404, description = "The player is unknown, or the namespace is internal"),
+    ),
+)]
+fn get_player_achievements() {}
+
+/// Unlocks registered achievements for a player.
+#[utoipa::path(
+    post, path = "/player/{uuid}/achievements", tag = "achievements",
+    params(("uuid" = String, Path, description = "The UUID of the player")),
+    request_body = GrantAchievementsRequest,
+    responses(
+        (status = 200, body = GrantAchievementsResponse),
+        (status = 400, body = ValidationErrorResponse, description = "An achievement isn't registered for the namespace"),
+        (status = 401, body = ErrorResponse, description = "The token is unknown, or the signature is invalid or was already used"),
+        (status = 403, description = "The token doesn't have the `upload_stats` scope"),
+    ),
+    security(("token" = []), ("signature" = [])),
+)]
+fn grant_achievements() {}
+
+/// Updates the username of a player, and whether they are private.
+#[utoipa::path(
+    put, path = "/player/{uuid}", tag = "players",
+    params(("uuid" = String, Path, description = "The UUID of the player")),
+    request_body = UpdatePlayerProfileRequest,
+    responses(
+        (status = 204, description = "The profile was updated"),
+        (status = 401, description = "The token is unknown"),
+        (status = 403, description = "The token doesn't have the `update_profiles` scope"),
+    ),
+    security(("token" = [])),
+)]
+fn update_player_profile() {}
+
+/// Gets a player's stats in every namespace, or in the namespaces listed in `namespaces`, keyed by namespace and then
+/// by stat name.
+#[utoipa::path(
+    get, path = "/player/{uuid}/stats", tag = "players",
+    params(
+        ("uuid" = String, Path, description = "The UUID of the player"),
+        ("season" = Option<String>, Query, description = "A season number, `current`, or `all-time` (the default)"),
+        ("namespaces" = Option<String>, Query, description = "Comma-separated namespaces to limit the stats to"),
+    ),
+    responses(
+        (status = 200, body = HashMap<String, HashMap<String, f64>>),
+        (status = 400, description = "`namespaces` is empty"),
+        (status = 404, description = "The player is unknown or private, or one of `namespaces` is internal"),
+    ),
+)]
+fn get_all_player_stats() {}
+
+/// Gets a player's stats in a namespace, keyed by namespace and then by stat name.
+#[utoipa::path(
+    get, path = "/player/{uuid}/stats/{namespace}", tag = "players",
+    params(
+        ("uuid" = String, Path, description = "The UUID of the player"),
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("season" = Option<String>, Query, description = "A season number, `current`, or `all-time` (the default)"),
+    ),
+    responses(
+        (status = 200, body = HashMap<String, HashMap<String, f64>>),
+        (status = 404, description = "The player is unknown or private, or the namespace is internal"),
+    ),
+)]
+fn get_player_stats() {}
+
+/// Gets the values that a player's stat had after each of the bundles that changed it, oldest first.
+#[utoipa::path(
+    get, path = "/player/{uuid}/stats/{namespace}/history", tag = "players",
+    params(
+        ("uuid" = String, Path, description = "The UUID of the player"),
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("stat" = String, Query, description = "The stat to get the history of"),
+        ("from" = Option<String>, Query, description = "Only include values recorded at or after this RFC 3339 time"),
+        ("until" = Option<String>, Query, description = "Only include values recorded before this RFC 3339 time"),
+        ("limit" = Option<i64>, Query, description = "From 1 to 5000, defaulting to 500"),
+    ),
+    responses(
+        (status = 200, body = Vec<StatHistoryPoint>),
+        (status = 400, description = "A parameter is invalid"),
+        (status = 404, description = "The player is private, or the namespace is internal"),
+    ),
+)]
+fn get_stat_history() {}
+
+/// Gets the buckets of a player's histogram stat, from the lowest values to the highest.
+#[utoipa::path(
+    get, path = "/player/{uuid}/stats/{namespace}/histogram", tag = "players",
+    params(
+        ("uuid" = String, Path, description = "The UUID of the player"),
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("stat" = String, Query, description = "The histogram stat to get"),
+    ),
+    responses(
+        (status = 200, body = Vec<HistogramBucket>),
+        (status = 400, description = "The stat name is invalid"),
+        (status = 404, description = "The stat isn't a histogram, the player is private, or the namespace is internal"),
+    ),
+)]
+fn get_stat_histogram() {}
+
+/// Gets the games that a player most recently took part in, newest first.
+#[utoipa::path(
+    get, path = "/player/{uuid}/games", tag = "players",
+    params(
+        ("uuid" = String, Path, description = "The UUID of the player"),
+        ("namespace" = Option<String>, Query, description = "Only include games in this namespace"),
+        ("limit" = Option<i64>, Query, description = "From 1 to 100, defaulting to 20"),
+    ),
+    responses(
+        (status = 200, body = Vec<GameResponse>),
+        (status = 404, description = "The player is private, or the namespace is internal"),
+    ),
+)]
+fn get_player_games() {}
+
+/// Adds a bundle of stats onto the stored totals and averages.
+#[utoipa::path(
+    post, path = "/stats/upload", tag = "stats",
+    request_body = GameStatsBundle,
+    params(("dry_run" = Option<bool>, Query, description = "If `true`, the bundle is only checked against the stored stats and nothing is written")),
+    responses(
+        (status = 200, body = UploadDryRunResponse, description = "What uploading the bundle would have done, for a dry run"),
+        (status = 204, description = "The bundle was applied, or was already applied if the response has an `X-Duplicate-Bundle: true` header"),
+        (status = 202, body = UploadQueuedResponse, description = "The bundle was queued and will be applied in the background, or, without a body, was saved to the spool and will be applied later, or was quarantined as suspicious if the response has an `X-Quarantined-Upload` header"),
+        (status = 400, body = ValidationErrorResponse, description = "A name or the size of the bundle breaks the backend's limits, or a stat was rejected, e.g. for being out of its bounds"),
+        (status = 401, body = ErrorResponse, description = "The token is unknown, or the signature is invalid or was already used"),
+        (status = 403, description = "The token doesn't have the `upload_stats` scope"),
+        (status = 422, body = ErrorResponse, description = "A stat was uploaded as a different type than it is stored as"),
+        (status = 503, description = "The backend can't take the upload right now, and it wasn't applied"),
+    ),
+    security(("token" = []), ("signature" = [])),
+)]
+fn upload_stats() {}
+
+/// Gets the progress of an upload that was queued to be applied in the background.
+#[utoipa::path(
+    get, path = "/stats/upload/{id}/status", tag = "stats",
+    params(("id" = String, Path, description = "The `id` that the upload was accepted with")),
+    responses(
+        (status = 200, body = UploadStatusResponse),
+        (status = 401, description = "The token is unknown"),
+        (status = 403, description = "The token doesn't have the `upload_stats` scope"),
+        (status = 404, description = "The upload is unknown to this instance of the backend, or finished longer ago than statuses are kept for"),
+    ),
+    security(("token" = [])),
+)]
+fn get_upload_status() {}
+
+/// Lists the namespaces that have player or global stats.
+#[utoipa::path(
+    get, path = "/stats/namespaces", tag = "stats",
+    responses((status = 200, body = Vec<String>)),
+)]
+fn get_namespaces() {}
+
+/// Gets the global stats of a namespace, keyed by stat name.
+#[utoipa::path(
+    get, path = "/stats/global/{namespace}", tag = "stats",
+    params(("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`")),
+    responses(
+        (status = 200, body = HashMap<String, f64>),
+        (status = 404, description = "The namespace has no global stats, or is internal"),
+    ),
+)]
+fn get_global_stats() {}
+
+/// Summarizes one of the player stats of a namespace across every player that has it.
+#[utoipa::path(
+    get, path = "/stats/{namespace}/summary", tag = "stats",
+    params(
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("stat" = String, Query, description = "The player stat to summarize"),
+        ("season" = Option<String>, Query, description = "A season number, `current`, or `all-time` (the default)"),
+    ),
+    responses(
+        (status = 200, body = StatSummaryResponse),
+        (status = 400, description = "A parameter is invalid"),
+        (status = 404, description = "No player has the stat, or the namespace is internal"),
+    ),
+)]
+fn get_stat_summary() {}
+
+/// Gets how much the global stats of a namespace changed over a window, keyed by stat name.
+#[utoipa::path(
+    get, path = "/stats/global/{namespace}/delta", tag = "stats",
+    params(
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("window" = Option<String>, Query, description = "A number of hours or days, e.g. `6h` or `7d`, defaulting to `24h`"),
+    ),
+    responses(
+        (status = 200, body = HashMap<String, f64>),
+        (status = 400, description = "The window is invalid or longer than rollups are kept for"),
+        (status = 404, description = "The namespace is internal"),
+    ),
+)]
+fn get_global_stats_delta() {}
+
+/// Gets the buckets of a global histogram stat, from the lowest values to the highest.
+#[utoipa::path(
+    get, path = "/stats/global/{namespace}/histogram", tag = "stats",
+    params(
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("stat" = String, Query, description = "The histogram stat to get"),
+    ),
+    responses(
+        (status = 200, body = Vec<HistogramBucket>),
+        (status = 400, description = "The stat name is invalid"),
+        (status = 404, description = "The stat isn't a histogram, or the namespace is internal"),
+    ),
+)]
+fn get_global_stat_histogram() {}
+
+/// Summarises what a game server has uploaded to each namespace, by the `server_name` of its bundles.
+#[utoipa::path(
+    get, path = "/stats/servers/{server}/summary", tag = "stats",
+    params(("server" = String, Path, description = "The `server_name` that the server uploads bundles with")),
+    responses(
+        (status = 200, body = ServerStatsSummary),
+        (status = 404, description = "The server hasn't uploaded any bundles, or only to internal namespaces"),
+    ),
+)]
+fn get_server_stats() {}
+
+/// Gets the registered display names, units and descriptions of a namespace's stats, keyed by stat name.
+#[utoipa::path(
+    get, path = "/stats/{namespace}/metadata", tag = "stats",
+    params(("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`")),
+    responses(
+        (status = 200, body = HashMap<String, StatInfo>),
+        (status = 404, description = "The namespace is internal"),
+    ),
+)]
+fn get_stat_metadata() {}
+
+/// Registers the display names, units and descriptions of a namespace's stats, keyed by stat name.
+#[utoipa::path(
+    put, path = "/stats/{namespace}/metadata", tag = "stats",
+    params(("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`")),
+    request_body = HashMap<String, StatInfo>,
+    responses(
+        (status = 204, description = "The metadata was registered"),
+        (status = 400, body = ValidationErrorResponse, description = "A stat name is invalid"),
+        (status = 401, description = "The token is unknown"),
+        (status = 403, description = "The token doesn't have the `update_stat_metadata` scope"),
+    ),
+    security(("token" = [])),
+)]
+fn update_stat_metadata() {}
+
+/// Gets the registered achievements of a namespace, keyed by id, and how many players have unlocked each.
+#[utoipa::path(
+    get, path = "/achievements/{namespace}", tag = "achievements",
+    params(("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`")),
+    responses(
+        (status = 200, body = AchievementsResponse),
+        (status = 404, description = "The namespace is internal"),
+    ),
+)]
+fn get_achievements() {}
+
+/// Registers the display names and descriptions of a namespace's achievements, keyed by id.
+#[utoipa::path(
+    put, path = "/achievements/{namespace}", tag = "achievements",
+    params(("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`")),
+    request_body = HashMap<String, AchievementInfo>,
+    responses(
+        (status = 204, description = "The achievements were registered"),
+        (status = 400, body = ValidationErrorResponse, description = "An achievement id is invalid"),
+        (status = 401, description = "The token is unknown"),
+        (status = 403, description = "The token doesn't have the `update_achievements` scope"),
+    ),
+    security(("token" = [])),
+)]
+fn update_achievements() {}
+
+/// Records the result of a match, updating the Elo ratings of its players in the namespace.
+#[utoipa::path(
+    post, path = "/ratings/{namespace}/match", tag = "ratings",
+    params(("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`")),
+    request_body = MatchResultRequest,
+    responses(
+        (status = 200, body = MatchResultResponse),
+        (status = 400, body = ValidationErrorResponse, description = "The match doesn't have valid teams"),
+        (status = 401, body = ErrorResponse, description = "The token is unknown, or the signature is invalid or was already used"),
+        (status = 403, description = "The token doesn't have the `upload_games` scope"),
+    ),
+    security(("token" = []), ("signature" = [])),
+)]
+fn record_match() {}
+
+/// Ranks the players of a namespace by their rating.
+#[utoipa::path(
+    get, path = "/ratings/{namespace}/leaderboard", tag = "ratings",
+    params(
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("limit" = Option<i64>, Query, description = "From 1 to 100, defaulting to 10"),
+    ),
+    responses(
+        (status = 200, body = Vec<LeaderboardEntry>),
+        (status = 400, description = "The limit is invalid"),
+        (status = 404, description = "The namespace is internal"),
+    ),
+)]
+fn get_rating_leaderboard() {}
+
+/// Ranks the players of a namespace by one of their stats.
+#[utoipa::path(
+    get, path = "/leaderboard/{namespace}/{stat}", tag = "stats",
+    params(
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("stat" = String, Path, description = "The stat to rank players by"),
+        ("limit" = Option<i64>, Query, description = "From 1 to 100, defaulting to 10"),
+        ("order" = Option<LeaderboardOrder>, Query, description = "Which end of the leaderboard is ranked first"),
+        ("season" = Option<String>, Query, description = "A season number, `current`, or `all-time` (the default)"),
+    ),
+    responses(
+        (status = 200, body = Vec<LeaderboardEntry>),
+        (status = 400, description = "A parameter is invalid"),
+        (status = 404, description = "The namespace is internal"),
+    ),
+)]
+fn get_leaderboard() {}
+
+/// Gets a leaderboard as it stood when the `snapshot_leaderboards` job last saved it, or at an earlier time.
+#[utoipa::path(
+    get, path = "/leaderboard/{namespace}/{stat}/snapshot", tag = "stats",
+    params(
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("stat" = String, Path, description = "The stat that players are ranked by"),
+        ("order" = Option<LeaderboardOrder>, Query, description = "Which end of the leaderboard is ranked first"),
+        ("at" = Option<String>, Query, description = "Get the latest snapshot taken at or before this time, in RFC 3339 format. Defaults to now"),
+    ),
+    responses(
+        (status = 200, body = LeaderboardSnapshotResponse),
+        (status = 400, description = "A parameter is invalid"),
+        (status = 404, description = "No snapshot of the leaderboard was taken by then, or the namespace is internal"),
+    ),
+)]
+fn get_leaderboard_snapshot() {}
+
+/// Finds where a player ranks among the players of a namespace by one of their stats.
+#[utoipa::path(
+    get, path = "/player/{uuid}/stats/{namespace}/{stat}/rank", tag = "players",
+    params(
+        ("uuid" = String, Path, description = "The UUID of the player"),
+        ("namespace" = String, Path, description = "The namespace of the game, e.g. `bedwars`"),
+        ("stat" = String, Path, description = "The stat to rank the player by"),
+        ("order" = Option<LeaderboardOrder>, Query, description = "Whether higher (`desc`, the default) or lower values rank first"),
+        ("season" = Option<String>, Query, description = "A season number, `current`, or `all-time` (the default)"),
+    ),
+    responses(
+        (status = 200, body = StatRankResponse),
+        (status = 400, description = "A parameter is invalid"),
+        (status = 404, description = "The player doesn't have the stat, the player is private, or the namespace is internal"),
+    ),
+)]
+fn get_stat_rank() {}
+
+/// Gets the season that the stats of seasonal namespaces are currently added to.
+#[utoipa::path(
+    get, path = "/seasons/current", tag = "stats",
+    responses((status = 200, body = SeasonResponse)),
+)]
+fn get_current_season() {}
+
+/// Records a completed match.
+#[utoipa::path(
+    post, path = "/games/upload", tag = "games",
+    request_body = GameUploadRequest,
+    responses(
+        (status = 201, body = GameUploadResponse),
+        (status = 400, description = "The times are invalid, or a score isn't finite"),
+        (status = 401, body = ErrorResponse, description = "The token is unknown, or the signature is invalid or was already used"),
+        (status = 403, description = "The token doesn't have the `upload_games` scope"),
+    ),
+    security(("token" = []), ("signature" = [])),
+)]
+fn upload_game() {}
+
+/// Lists the most recently finished games, newest first.
+#[utoipa::path(
+    get, path = "/games/recent", tag = "games",
+    params(
+        ("namespace" = Option<String>, Query, description = "Only include games in this namespace"),
+        ("limit" = Option<i64>, Query, description = "From 1 to 100, defaulting to 20"),
+    ),
+    responses(
+        (status = 200, body = Vec<GameResponse>),
+        (status = 404, description = "The namespace is internal"),
+    ),
+)]
+fn get_recent_games() {}
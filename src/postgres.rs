@@ -0,0 +1,1730 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::Document;
+use bson::oid::ObjectId;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use sqlx::postgres::{PgConnectOptions, PgPool, PgPoolOptions, PgRow};
+use sqlx::types::Json;
+use sqlx::{Postgres, QueryBuilder, Row, Transaction};
+use std::collections::HashMap;
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::config::Config;
+use crate::migration::{self, Migrations};
+use crate::model::{AchievementInfo, AchievementUnlocks, AggregateRebuildReport, CorruptDocument, DocumentFailure, Game, GameStat, GameStatsBundle, HistogramBucket, JobRun, LeaderboardEntry, LeaderboardOrder, MatchTeam, StoredLeaderboardSnapshot, NamespaceDeletionReport, NamespaceMergeReport, PlayerAnonymizationReport, PlayerDeletionReport, PlayerProfile, PlayerStatsBundle, PreviousUsername, RatingChange, PlayerStatsResponse, ServerStats, StatConversionReport, StatDeletionReport, StatRenameReport, StatRank, StatSummaryResponse, UploadLogEntry, StatInfo, StatSnapshot, StoredAchievement, StoredGameParticipant, StoredPlaytime, StoredRating, StoredSeason, SuspiciousUpload, UploadStat, Violation};
+use crate::store::{BackupBatch, BackupManifest, add_stat, add_upload, BundleOutcome, check_mergeable, stat_values, sum_rollups, uploaded_stat, ConvertStat, GetGames, GetLeaderboard, GetLeaderboardSnapshot, GetPlayers, GetRatingLeaderboard, GetStatHistogram, GetStatRank, GetStatSummary, GetStatHistory, GetUploadLog, PlayerOrder, RebuildAggregates, RestoreOutcome, SearchPlayers, StatsStore, Unsupported};
+
+/// The `id` of the row in the `seasons` table that holds the current season.
+const SEASON_ID: &str = "season";
+
+/// The season that the all-time stats of players are stored under.
+const ALL_TIME: i32 = 0;
+
+/// Statements that create the tables and indexes that queries rely on. Each does nothing if it has already been run.
+const SCHEMA: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS players (
+        uuid UUID PRIMARY KEY,
+        username TEXT,
+        username_updated_at TIMESTAMPTZ,
+        private BOOLEAN NOT NULL DEFAULT FALSE
+    )",
+    "CREATE INDEX IF NOT EXISTS players_username ON players (lower(username), username_updated_at DESC)",
+    // Prefix searches can only use an index with pattern ops under collations other than C.
+    "CREATE INDEX IF NOT EXISTS players_username_prefix ON players (lower(username) text_pattern_ops)",
+    "CREATE TABLE IF NOT EXISTS username_history (
+        uuid UUID NOT NULL,
+        username TEXT NOT NULL,
+        used_from TIMESTAMPTZ,
+        used_until TIMESTAMPTZ NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS username_history_uuid ON username_history (uuid, used_until)",
+    "CREATE TABLE IF NOT EXISTS player_stats (
+        uuid UUID NOT NULL,
+        namespace TEXT NOT NULL,
+        season INTEGER NOT NULL,
+        stats JSONB NOT NULL DEFAULT '{}',
+        PRIMARY KEY (uuid, namespace, season)
+    )",
+    "CREATE INDEX IF NOT EXISTS player_stats_namespace ON player_stats (namespace, season)",
+    "CREATE TABLE IF NOT EXISTS global_stats (
+        namespace TEXT PRIMARY KEY,
+        stats JSONB NOT NULL DEFAULT '{}'
+    )",
+    "CREATE TABLE IF NOT EXISTS global_stats_rollups (
+        namespace TEXT NOT NULL,
+        hour TIMESTAMPTZ NOT NULL,
+        stats JSONB NOT NULL DEFAULT '{}',
+        PRIMARY KEY (namespace, hour)
+    )",
+    "CREATE INDEX IF NOT EXISTS global_stats_rollups_hour ON global_stats_rollups (hour)",
+    "CREATE TABLE IF NOT EXISTS games (
+        id TEXT PRIMARY KEY,
+        server_name TEXT NOT NULL,
+        namespace TEXT NOT NULL,
+        started_at TIMESTAMPTZ NOT NULL,
+        ended_at TIMESTAMPTZ NOT NULL,
+        participants JSONB NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS games_ended_at ON games (ended_at DESC)",
+    "CREATE INDEX IF NOT EXISTS games_namespace_ended_at ON games (namespace, ended_at DESC)",
+    "CREATE INDEX IF NOT EXISTS games_participants ON games USING GIN (participants jsonb_path_ops)",
+    "CREATE TABLE IF NOT EXISTS stat_metadata (
+        namespace TEXT NOT NULL,
+        stat TEXT NOT NULL,
+        info JSONB NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (namespace, stat)
+    )",
+    "CREATE TABLE IF NOT EXISTS achievement_metadata (
+        namespace TEXT NOT NULL,
+        achievement TEXT NOT NULL,
+        info JSONB NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (namespace, achievement)
+    )",
+    "CREATE TABLE IF NOT EXISTS achievements (
+        uuid UUID NOT NULL,
+        namespace TEXT NOT NULL,
+        achievement TEXT NOT NULL,
+        unlocked_at TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (uuid, namespace, achievement)
+    )",
+    "CREATE INDEX IF NOT EXISTS achievements_namespace ON achievements (namespace, achievement)",
+    "CREATE TABLE IF NOT EXISTS ratings (
+        uuid UUID NOT NULL,
+        namespace TEXT NOT NULL,
+        rating DOUBLE PRECISION NOT NULL,
+        matches BIGINT NOT NULL,
+        updated_at TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (uuid, namespace)
+    )",
+    "CREATE INDEX IF NOT EXISTS ratings_namespace_rating ON ratings (namespace, rating DESC)",
+    "CREATE TABLE IF NOT EXISTS stat_history (
+        id TEXT PRIMARY KEY,
+        uuid UUID NOT NULL,
+        namespace TEXT NOT NULL,
+        recorded_at TIMESTAMPTZ NOT NULL,
+        stats JSONB NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS stat_history_uuid_namespace_recorded_at ON stat_history (uuid, namespace, recorded_at)",
+    "CREATE TABLE IF NOT EXISTS leaderboard_snapshots (
+        id TEXT PRIMARY KEY,
+        namespace TEXT NOT NULL,
+        stat TEXT NOT NULL,
+        ascending BOOLEAN NOT NULL,
+        taken_at TIMESTAMPTZ NOT NULL,
+        entries JSONB NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS leaderboard_snapshots_namespace_stat_taken_at ON leaderboard_snapshots (namespace, stat, ascending, taken_at)",
+    "CREATE TABLE IF NOT EXISTS suspicious_uploads (
+        id TEXT PRIMARY KEY,
+        received_at TIMESTAMPTZ NOT NULL,
+        token TEXT NOT NULL,
+        violations JSONB NOT NULL,
+        bundle JSONB NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS bundle_log (
+        id TEXT PRIMARY KEY,
+        received_at TIMESTAMPTZ NOT NULL,
+        bundle JSONB NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS server_stats (
+        server_name TEXT NOT NULL,
+        namespace TEXT NOT NULL,
+        bundles BIGINT NOT NULL,
+        player_uploads BIGINT NOT NULL,
+        last_upload_at TIMESTAMPTZ NOT NULL,
+        stats JSONB NOT NULL DEFAULT '{}',
+        PRIMARY KEY (server_name, namespace)
+    )",
+    "CREATE TABLE IF NOT EXISTS playtime (
+        uuid UUID NOT NULL,
+        namespace TEXT NOT NULL,
+        seconds BIGINT NOT NULL,
+        sessions BIGINT NOT NULL,
+        last_played TIMESTAMPTZ NOT NULL,
+        PRIMARY KEY (uuid, namespace)
+    )",
+    "CREATE TABLE IF NOT EXISTS upload_log (
+        seq BIGSERIAL PRIMARY KEY,
+        received_at TIMESTAMPTZ NOT NULL,
+        token TEXT NOT NULL,
+        server_name TEXT NOT NULL,
+        namespace TEXT NOT NULL,
+        players BIGINT NOT NULL,
+        stats BIGINT NOT NULL,
+        spooled BOOLEAN NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS applied_bundles (
+        id UUID PRIMARY KEY,
+        applied_at TIMESTAMPTZ NOT NULL
+    )",
+    "CREATE INDEX IF NOT EXISTS applied_bundles_applied_at ON applied_bundles (applied_at)",
+    "CREATE TABLE IF NOT EXISTS seasons (
+        id TEXT PRIMARY KEY,
+        season INTEGER NOT NULL,
+        started_at TIMESTAMPTZ
+    )",
+    "CREATE TABLE IF NOT EXISTS job_runs (
+        id TEXT PRIMARY KEY,
+        job TEXT NOT NULL,
+        trigger TEXT NOT NULL,
+        started_at TIMESTAMPTZ NOT NULL,
+        finished_at TIMESTAMPTZ,
+        outcome TEXT NOT NULL,
+        items_processed BIGINT,
+        error TEXT
+    )",
+    "CREATE INDEX IF NOT EXISTS job_runs_job_started_at ON job_runs (job, started_at DESC)",
+    "CREATE TABLE IF NOT EXISTS leases (
+        name TEXT PRIMARY KEY,
+        holder TEXT NOT NULL,
+        expires TIMESTAMPTZ NOT NULL
+    )",
+    "CREATE TABLE IF NOT EXISTS meta (
+        id TEXT PRIMARY KEY,
+        version BIGINT NOT NULL,
+        migrated_at TIMESTAMPTZ NOT NULL
+    )",
+];
+
+/// The tables that backups are made of, with the columns that identify each row. Leases only matter while the
+/// instance that took them is running.
+const BACKUP_TABLES: &[(&str, &str)] = &[
+    ("meta", "id"),
+    ("seasons", "id"),
+    ("players", "uuid"),
+    ("username_history", "uuid, used_until, username"),
+    ("player_stats", "uuid, namespace, season"),
+    ("global_stats", "namespace"),
+    ("global_stats_rollups", "namespace, hour"),
+    ("games", "id"),
+    ("stat_metadata", "namespace, stat"),
+    ("achievement_metadata", "namespace, achievement"),
+    ("achievements", "uuid, namespace, achievement"),
+    ("ratings", "uuid, namespace"),
+    ("playtime", "uuid, namespace"),
+    ("stat_history", "id"),
+    ("leaderboard_snapshots", "id"),
+    ("server_stats", "server_name, namespace"),
+    ("bundle_log", "id"),
+    ("upload_log", "seq"),
+    ("applied_bundles", "id"),
+    ("suspicious_uploads", "id"),
+    ("job_runs", "id"),
+];
+
+/// The `id` of the row in the `meta` table that holds the schema version.
+const SCHEMA_ID: &str = "schema";
+
+/// Migrations of the database, in the order that they are applied, each with the statements that it runs in a
+/// single transaction. See [Migrations].
+const MIGRATIONS: &[(&str, &[&str])] = &[
+    ("add_player_activity", &[
+        "ALTER TABLE players ADD COLUMN IF NOT EXISTS first_seen TIMESTAMPTZ",
+        "ALTER TABLE players ADD COLUMN IF NOT EXISTS last_seen TIMESTAMPTZ",
+        "ALTER TABLE players ADD COLUMN IF NOT EXISTS anonymized BOOLEAN NOT NULL DEFAULT FALSE",
+    ]),
+];
+
+/// Stores stats in PostgreSQL. Stats are kept as JSONB maps of stat name to stored stat, and uploads are added onto
+/// them in transactions that lock the rows they change.
+pub struct PostgresDatabaseHandler {
+    pool: PgPool,
+    config: Config,
+}
+
+impl PostgresDatabaseHandler {
+    pub async fn connect(config: &Config) -> Result<Self> {
+        // Creating the tables that already exist raises a notice for each, which sqlx would log.
+        let options = config.database_url.parse::<PgConnectOptions>()?
+            .options([("client_min_messages", "warning")]);
+        let pool = PgPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(config.database_retry.server_selection_timeout_ms))
+            .connect_with(options).await?;
+        let handler = Self {
+            pool,
+            config: config.clone(),
+        };
+
+        // A read-only instance may be pointed at a replica, so it leaves migrations to a writable one.
+        if config.read_only {
+            migration::check(&handler).await?;
+        } else {
+            handler.create_tables().await?;
+            migration::migrate(&handler, config).await?;
+        }
+
+        Ok(handler)
+    }
+
+    async fn create_tables(&self) -> Result<()> {
+        for statement in SCHEMA {
+            sqlx::query(statement).execute(&self.pool).await?;
+        }
+        Ok(())
+    }
+
+    /// Adds uploaded stats onto the stats of players in one season, locking their rows until the transaction ends.
+    /// Returns the resulting stats of each player.
+    async fn add_player_stats(&self, tx: &mut Transaction<'_, Postgres>, namespace: &str, season: i32, players: &PlayerStatsBundle) -> Result<HashMap<Uuid, HashMap<String, GameStat>>> {
+        let mut uuids: Vec<String> = players.keys().map(Uuid::to_string).collect();
+        uuids.sort();
+
+        // Rows are created before they are locked, so that concurrent uploads for a new player can't both insert one.
+        sqlx::query("INSERT INTO player_stats (uuid, namespace, season) SELECT unnest($1::uuid[]), $2, $3 ON CONFLICT DO NOTHING")
+            .bind(&uuids).bind(namespace).bind(season)
+            .execute(&mut **tx).await?;
+        let rows = sqlx::query("SELECT uuid::text AS uuid, stats FROM player_stats
+                WHERE uuid = ANY($1::uuid[]) AND namespace = $2 AND season = $3 ORDER BY uuid FOR UPDATE")
+            .bind(&uuids).bind(namespace).bind(season)
+            .fetch_all(&mut **tx).await?;
+
+        let mut updated = HashMap::new();
+        for row in rows {
+            let player = row_uuid(&row)?;
+            let stored = row.try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0;
+            if let Some(stats) = players.get(&player) {
+                updated.insert(player, add_upload(&self.config, namespace, Some(player), stored, stats)?);
+            }
+        }
+
+        let (uuids, stats): (Vec<String>, Vec<String>) = updated.iter()
+            .map(|(player, stats)| Ok((player.to_string(), serde_json::to_string(stats)?)))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter().unzip();
+        sqlx::query("UPDATE player_stats SET stats = updated.stats::jsonb
+                FROM unnest($1::uuid[], $2::text[]) AS updated (uuid, stats)
+                WHERE player_stats.uuid = updated.uuid AND namespace = $3 AND season = $4")
+            .bind(&uuids).bind(&stats).bind(namespace).bind(season)
+            .execute(&mut **tx).await?;
+        Ok(updated)
+    }
+
+    /// Adds uploaded stats onto a namespace's global stats and the rollup of the current hour.
+    async fn add_global_stats(&self, tx: &mut Transaction<'_, Postgres>, namespace: &str, stats: &HashMap<String, UploadStat>) -> Result<()> {
+        sqlx::query("INSERT INTO global_stats (namespace) VALUES ($1) ON CONFLICT DO NOTHING")
+            .bind(namespace)
+            .execute(&mut **tx).await?;
+        let stored = sqlx::query("SELECT stats FROM global_stats WHERE namespace = $1 FOR UPDATE")
+            .bind(namespace)
+            .fetch_one(&mut **tx).await?
+            .try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0;
+        let updated = add_upload(&self.config, namespace, None, stored, stats)?;
+        sqlx::query("UPDATE global_stats SET stats = $2 WHERE namespace = $1")
+            .bind(namespace).bind(Json(updated))
+            .execute(&mut **tx).await?;
+
+        // The rollup only holds what was uploaded this hour, so it can't have a stored type to check against.
+        sqlx::query("INSERT INTO global_stats_rollups (namespace, hour) VALUES ($1, date_trunc('hour', now())) ON CONFLICT DO NOTHING")
+            .bind(namespace)
+            .execute(&mut **tx).await?;
+        let mut rollup = sqlx::query("SELECT stats FROM global_stats_rollups WHERE namespace = $1 AND hour = date_trunc('hour', now()) FOR UPDATE")
+            .bind(namespace)
+            .fetch_one(&mut **tx).await?
+            .try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0;
+        for (name, stat) in stats {
+            let uploaded = uploaded_stat(stat, &self.config.histogram_buckets(namespace, name));
+            let added = add_stat(rollup.get(name), uploaded)?;
+            rollup.insert(name.clone(), added);
+        }
+        sqlx::query("UPDATE global_stats_rollups SET stats = $2 WHERE namespace = $1 AND hour = date_trunc('hour', now())")
+            .bind(namespace).bind(Json(rollup))
+            .execute(&mut **tx).await?;
+        Ok(())
+    }
+
+    /// Records the values that the uploaded stats of each player in a bundle have after it was applied.
+    async fn record_stat_history(&self, namespace: &str, players: &PlayerStatsBundle, updated: &HashMap<Uuid, HashMap<String, GameStat>>) -> Result<()> {
+        if updated.is_empty() {
+            return Ok(());
+        }
+
+        let mut insert = QueryBuilder::<Postgres>::new("INSERT INTO stat_history (id, uuid, namespace, recorded_at, stats) ");
+        insert.push_values(updated, |mut values, (player, stats)| {
+            let uploaded = players.get(player);
+            let snapshot: HashMap<&String, f64> = stats.iter()
+                .filter(|(name, _)| uploaded.is_some_and(|uploaded| uploaded.contains_key(*name)))
+                .map(|(name, stat)| (name, stat.clone().into()))
+                .collect();
+            values.push_bind(ObjectId::new().to_hex())
+                .push_bind(player.to_string()).push_unseparated("::uuid")
+                .push_bind(namespace)
+                .push("now()")
+                .push_bind(Json(snapshot));
+        });
+        insert.build().execute(&self.pool).await?;
+        Ok(())
+    }
+
+    /// Merges a row of stats into the row with the same key in another namespace, and deletes it. Nothing is changed if
+    /// the stats can't be merged.
+    async fn merge_stats_row(&self, from: &str, into: &str, player: Option<(Uuid, i32)>, stats: HashMap<String, GameStat>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        let target = match player {
+            Some((uuid, season)) => {
+                sqlx::query("INSERT INTO player_stats (uuid, namespace, season) VALUES ($1::uuid, $2, $3) ON CONFLICT DO NOTHING")
+                    .bind(uuid.to_string()).bind(into).bind(season)
+                    .execute(&mut *tx).await?;
+                sqlx::query("SELECT stats FROM player_stats WHERE uuid = $1::uuid AND namespace = $2 AND season = $3 FOR UPDATE")
+                    .bind(uuid.to_string()).bind(into).bind(season)
+                    .fetch_one(&mut *tx).await?
+            }
+            None => {
+                sqlx::query("INSERT INTO global_stats (namespace) VALUES ($1) ON CONFLICT DO NOTHING")
+                    .bind(into)
+                    .execute(&mut *tx).await?;
+                sqlx::query("SELECT stats FROM global_stats WHERE namespace = $1 FOR UPDATE")
+                    .bind(into)
+                    .fetch_one(&mut *tx).await?
+            }
+        };
+        let mut target = target.try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0;
+        check_mergeable(&stats, &target)?;
+        for (name, stat) in stats {
+            let merged = add_stat(target.get(&name), stat)?;
+            target.insert(name, merged);
+        }
+
+        match player {
+            Some((uuid, season)) => {
+                sqlx::query("UPDATE player_stats SET stats = $4 WHERE uuid = $1::uuid AND namespace = $2 AND season = $3")
+                    .bind(uuid.to_string()).bind(into).bind(season).bind(Json(target))
+                    .execute(&mut *tx).await?;
+                sqlx::query("DELETE FROM player_stats WHERE uuid = $1::uuid AND namespace = $2 AND season = $3")
+                    .bind(uuid.to_string()).bind(from).bind(season)
+                    .execute(&mut *tx).await?;
+            }
+            None => {
+                sqlx::query("UPDATE global_stats SET stats = $2 WHERE namespace = $1")
+                    .bind(into).bind(Json(target))
+                    .execute(&mut *tx).await?;
+                sqlx::query("DELETE FROM global_stats WHERE namespace = $1")
+                    .bind(from)
+                    .execute(&mut *tx).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Migrations for PostgresDatabaseHandler {
+    fn migrations(&self) -> Vec<&'static str> {
+        MIGRATIONS.iter().map(|(name, _)| *name).collect()
+    }
+
+    async fn schema_version(&self) -> Result<usize> {
+        let row = sqlx::query("SELECT version FROM meta WHERE id = $1")
+            .bind(SCHEMA_ID)
+            .fetch_optional(&self.pool).await?;
+        Ok(row.map(|row| row.try_get::<i64, _>("version")).transpose()?.unwrap_or(0) as usize)
+    }
+
+    async fn apply_migration(&self, index: usize) -> Result<()> {
+        let (_, statements) = MIGRATIONS[index];
+        let mut tx = self.pool.begin().await?;
+        for statement in statements {
+            sqlx::query(statement).execute(&mut *tx).await?;
+        }
+        sqlx::query("INSERT INTO meta (id, version, migrated_at) VALUES ($1, $2, now())
+                ON CONFLICT (id) DO UPDATE SET version = EXCLUDED.version, migrated_at = EXCLUDED.migrated_at")
+            .bind(SCHEMA_ID).bind(index as i64 + 1)
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl StatsStore for PostgresDatabaseHandler {
+    async fn ping(&self) -> Result<()> {
+        sqlx::query("SELECT 1").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    fn is_transient_error(&self, error: &anyhow::Error) -> bool {
+        // A connection is only taken from the pool once one is available, so a query that timed out waiting for one
+        // was never sent.
+        matches!(error.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::PoolTimedOut))
+    }
+
+    async fn get_player_profile(&self, uuid: &Uuid) -> Result<Option<PlayerProfile>> {
+        let row = sqlx::query("SELECT uuid::text AS uuid, username, username_updated_at, private, first_seen, last_seen, anonymized FROM players WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .fetch_optional(&self.pool).await?;
+        row.as_ref().map(profile_from_row).transpose()
+    }
+
+    async fn get_player_profile_by_name(&self, username: &str) -> Result<Option<PlayerProfile>> {
+        let row = sqlx::query("SELECT uuid::text AS uuid, username, username_updated_at, private, first_seen, last_seen, anonymized FROM players
+                WHERE lower(username) = lower($1) ORDER BY username_updated_at DESC NULLS LAST LIMIT 1")
+            .bind(username)
+            .fetch_optional(&self.pool).await?;
+        row.as_ref().map(profile_from_row).transpose()
+    }
+
+    async fn get_players(&self, message: GetPlayers) -> Result<Vec<PlayerProfile>> {
+        let after_uuid = message.after.map(|after| after.uuid.to_string());
+        let rows = match message.order {
+            PlayerOrder::Uuid => sqlx::query("SELECT uuid::text AS uuid, username, username_updated_at, private, first_seen, last_seen, anonymized FROM players
+                    WHERE $1::uuid IS NULL OR uuid > $1::uuid ORDER BY uuid LIMIT $2")
+                .bind(after_uuid).bind(message.limit)
+                .fetch_all(&self.pool).await?,
+            // Compared at the millisecond precision of the cursor, which would otherwise skip players seen within the
+            // same millisecond.
+            PlayerOrder::LastSeen => {
+                let after_seen = message.after.and_then(|after| after.last_seen).map(|last_seen| last_seen.to_chrono());
+                sqlx::query("SELECT uuid::text AS uuid, username, username_updated_at, private, first_seen, last_seen, anonymized FROM players
+                        WHERE $1::uuid IS NULL
+                            OR ($2::timestamptz IS NOT NULL AND (date_trunc('milliseconds', last_seen) < $2 OR last_seen IS NULL
+                                OR (date_trunc('milliseconds', last_seen) = $2 AND uuid > $1::uuid)))
+                            OR ($2::timestamptz IS NULL AND last_seen IS NULL AND uuid > $1::uuid)
+                        ORDER BY date_trunc('milliseconds', last_seen) DESC NULLS LAST, uuid LIMIT $3")
+                    .bind(after_uuid).bind(after_seen).bind(message.limit)
+                    .fetch_all(&self.pool).await?
+            }
+        };
+        rows.iter().map(profile_from_row).collect()
+    }
+
+    async fn search_players(&self, message: SearchPlayers) -> Result<Vec<PlayerProfile>> {
+        let pattern = format!("{}%", message.prefix.to_lowercase().replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"));
+        let rows = sqlx::query("SELECT uuid::text AS uuid, username, username_updated_at, private, first_seen, last_seen, anonymized FROM players
+                WHERE lower(username) LIKE $1 AND (NOT private OR $2) ORDER BY lower(username) LIMIT $3")
+            .bind(pattern).bind(message.include_private).bind(message.limit)
+            .fetch_all(&self.pool).await?;
+        rows.iter().map(profile_from_row).collect()
+    }
+
+    async fn update_player_profile(&self, uuid: &Uuid, username: Option<String>) -> Result<PlayerProfile> {
+        let mut tx = self.pool.begin().await?;
+        // Locks the profile, so that each of several concurrent renames records the name that it replaced.
+        let previous = sqlx::query("SELECT username, username_updated_at FROM players WHERE uuid = $1::uuid FOR UPDATE")
+            .bind(uuid.to_string())
+            .fetch_optional(&mut *tx).await?;
+
+        // Profiles created by uploads have no username until one is set.
+        let row = sqlx::query("INSERT INTO players (uuid, username, username_updated_at, first_seen, last_seen)
+                VALUES ($1::uuid, $2::text, CASE WHEN $2::text IS NULL THEN NULL ELSE now() END, now(), now())
+                ON CONFLICT (uuid) DO UPDATE SET
+                    username = COALESCE(EXCLUDED.username, players.username),
+                    username_updated_at = CASE WHEN EXCLUDED.username IS NOT NULL AND players.username IS DISTINCT FROM EXCLUDED.username
+                        THEN EXCLUDED.username_updated_at ELSE players.username_updated_at END,
+                    first_seen = COALESCE(players.first_seen, EXCLUDED.first_seen),
+                    last_seen = EXCLUDED.last_seen
+                RETURNING uuid::text AS uuid, username, username_updated_at, private, first_seen, last_seen, anonymized")
+            .bind(uuid.to_string()).bind(&username)
+            .fetch_one(&mut *tx).await?;
+        let profile = profile_from_row(&row)?;
+        if let Some(previous) = previous {
+            let previous_username = previous.try_get::<Option<String>, _>("username")?;
+            if let Some(previous_username) = previous_username.filter(|previous| profile.username.as_ref() != Some(previous)) {
+                sqlx::query("INSERT INTO username_history (uuid, username, used_from, used_until) VALUES ($1::uuid, $2, $3, $4)")
+                    .bind(uuid.to_string()).bind(previous_username)
+                    .bind(previous.try_get::<Option<DateTime<Utc>>, _>("username_updated_at")?)
+                    .bind(profile.username_updated_at.map(bson::DateTime::to_chrono))
+                    .execute(&mut *tx).await?;
+            }
+        }
+        tx.commit().await?;
+        Ok(profile)
+    }
+
+    async fn set_player_private(&self, uuid: &Uuid, private: bool) -> Result<()> {
+        sqlx::query("UPDATE players SET private = $2 WHERE uuid = $1::uuid")
+            .bind(uuid.to_string()).bind(private)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_username_history(&self, uuid: &Uuid) -> Result<Vec<PreviousUsername>> {
+        let rows = sqlx::query("SELECT username, used_from, used_until FROM username_history WHERE uuid = $1::uuid ORDER BY used_until")
+            .bind(uuid.to_string())
+            .fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok(PreviousUsername {
+                username: row.try_get("username")?,
+                used_from: row.try_get::<Option<DateTime<Utc>>, _>("used_from")?.map(bson::DateTime::from_chrono),
+                used_until: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("used_until")?),
+            }))
+            .collect()
+    }
+
+    async fn add_playtime(&self, uuid: &Uuid, namespace: &str, seconds: u64) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO players (uuid, first_seen, last_seen) VALUES ($1::uuid, now(), now())
+                ON CONFLICT (uuid) DO UPDATE SET first_seen = COALESCE(players.first_seen, EXCLUDED.first_seen), last_seen = EXCLUDED.last_seen")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO playtime (uuid, namespace, seconds, sessions, last_played) VALUES ($1::uuid, $2, $3, 1, now())
+                ON CONFLICT (uuid, namespace) DO UPDATE SET seconds = playtime.seconds + EXCLUDED.seconds,
+                    sessions = playtime.sessions + 1, last_played = GREATEST(playtime.last_played, EXCLUDED.last_played)")
+            .bind(uuid.to_string()).bind(namespace).bind(seconds as i64)
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_playtime(&self, uuid: &Uuid) -> Result<Vec<StoredPlaytime>> {
+        let rows = sqlx::query("SELECT namespace, seconds, sessions, last_played FROM playtime WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok(StoredPlaytime {
+                namespace: row.try_get("namespace")?,
+                seconds: row.try_get("seconds")?,
+                sessions: row.try_get("sessions")?,
+                last_played: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("last_played")?),
+            }))
+            .collect()
+    }
+
+    async fn delete_player_data(&self, uuid: &Uuid) -> Result<PlayerDeletionReport> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("DELETE FROM playtime WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM achievements WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM ratings WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM username_history WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        let namespaces = sqlx::query("DELETE FROM player_stats WHERE uuid = $1::uuid RETURNING namespace")
+            .bind(uuid.to_string())
+            .fetch_all(&mut *tx).await?;
+        let history = sqlx::query("DELETE FROM stat_history WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        let profile = sqlx::query("DELETE FROM players WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        let mut report = PlayerDeletionReport {
+            profile: profile.rows_affected() > 0,
+            stats_documents: namespaces.len() as u64,
+            history_entries: history.rows_affected(),
+            ..Default::default()
+        };
+        for row in namespaces {
+            report.namespaces.push(row.try_get("namespace")?);
+        }
+        report.namespaces.sort();
+        report.namespaces.dedup();
+        Ok(report)
+    }
+
+    async fn anonymize_player(&self, uuid: &Uuid) -> Result<PlayerAnonymizationReport> {
+        let mut tx = self.pool.begin().await?;
+        let profile = sqlx::query("UPDATE players SET username = NULL, username_updated_at = NULL, anonymized = TRUE WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        let history = sqlx::query("DELETE FROM username_history WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        let snapshots = sqlx::query("UPDATE leaderboard_snapshots SET entries = (
+                    SELECT jsonb_agg(CASE WHEN entry->>'uuid' = $1 THEN jsonb_set(entry, '{username}', 'null') ELSE entry END ORDER BY position)
+                    FROM jsonb_array_elements(entries) WITH ORDINALITY AS entries(entry, position)
+                )
+                WHERE entries @> jsonb_build_array(jsonb_build_object('uuid', $1::text))")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        let namespaces = sqlx::query("SELECT DISTINCT namespace FROM player_stats WHERE uuid = $1::uuid ORDER BY namespace")
+            .bind(uuid.to_string())
+            .fetch_all(&mut *tx).await?;
+        tx.commit().await?;
+
+        Ok(PlayerAnonymizationReport {
+            profile: profile.rows_affected() > 0,
+            namespaces: namespaces.iter().map(|row| row.try_get("namespace")).collect::<Result<_, _>>()?,
+            previous_usernames: history.rows_affected(),
+            leaderboard_snapshots: snapshots.rows_affected(),
+        })
+    }
+
+    async fn get_player_stats(&self, uuid: &Uuid, namespace: &Option<String>, season: Option<u32>) -> Result<Option<PlayerStatsResponse>> {
+        if self.get_player_profile(uuid).await?.is_none() { // player not found.
+            return Ok(None);
+        }
+
+        let rows = sqlx::query("SELECT namespace, stats FROM player_stats
+                WHERE uuid = $1::uuid AND season = $2 AND ($3::text IS NULL OR namespace = $3)")
+            .bind(uuid.to_string()).bind(season.map_or(ALL_TIME, |season| season as i32)).bind(namespace)
+            .fetch_all(&self.pool).await?;
+
+        let mut final_stats = HashMap::new();
+        for row in rows {
+            let namespace: String = row.try_get("namespace")?;
+            let stats = row.try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0;
+            final_stats.insert(namespace.clone(), stat_values(&self.config, &namespace, stats));
+        }
+        Ok(Some(final_stats))
+    }
+
+    async fn upload_stats_bundle(&self, bundle: GameStatsBundle) -> Result<BundleOutcome> {
+        let namespace = &bundle.namespace;
+        let mut tx = self.pool.begin().await?;
+
+        // Claimed in the same transaction, so a bundle that fails to apply can be uploaded again. A concurrent upload
+        // of the same bundle waits here until this transaction has finished.
+        if let Some(bundle_id) = &bundle.bundle_id {
+            let claimed = sqlx::query("INSERT INTO applied_bundles (id, applied_at) VALUES ($1::uuid, now()) ON CONFLICT DO NOTHING")
+                .bind(bundle_id.to_string())
+                .execute(&mut *tx).await?
+                .rows_affected() > 0;
+            if !claimed {
+                return Ok(BundleOutcome::Duplicate);
+            }
+        }
+
+        // Logged in the same transaction that applies it, so the log holds exactly the bundles that were applied.
+        if self.config.bundle_log.enabled {
+            sqlx::query("INSERT INTO bundle_log (id, received_at, bundle) VALUES ($1, now(), $2)")
+                .bind(ObjectId::new().to_hex()).bind(Json(&bundle))
+                .execute(&mut *tx).await?;
+        }
+
+        let mut updated = HashMap::new();
+        if !bundle.stats.players.is_empty() {
+            // Sorted so that uploads sharing players lock their profiles in the same order, rather than deadlocking.
+            let mut uuids: Vec<String> = bundle.stats.players.keys().map(Uuid::to_string).collect();
+            uuids.sort();
+            sqlx::query("INSERT INTO players (uuid, first_seen, last_seen) SELECT unnest($1::uuid[]), now(), now()
+                    ON CONFLICT (uuid) DO UPDATE SET first_seen = COALESCE(players.first_seen, EXCLUDED.first_seen), last_seen = EXCLUDED.last_seen")
+                .bind(&uuids)
+                .execute(&mut *tx).await?;
+
+            updated = self.add_player_stats(&mut tx, namespace, ALL_TIME, &bundle.stats.players).await?;
+            if self.config.seasonal_namespaces.contains(namespace) {
+                let season = self.get_current_season().await?.season;
+                self.add_player_stats(&mut tx, namespace, season as i32, &bundle.stats.players).await?;
+            }
+        }
+
+        if let Some(global) = &bundle.stats.global {
+            self.add_global_stats(&mut tx, namespace, global).await?;
+        }
+
+        // Each stat's count is added onto the stored one, or stored as it is if the server never uploaded the stat.
+        sqlx::query("INSERT INTO server_stats (server_name, namespace, bundles, player_uploads, last_upload_at, stats)
+                VALUES ($1, $2, 1, $3, now(), $4)
+                ON CONFLICT (server_name, namespace) DO UPDATE SET
+                    bundles = server_stats.bundles + 1,
+                    player_uploads = server_stats.player_uploads + EXCLUDED.player_uploads,
+                    last_upload_at = EXCLUDED.last_upload_at,
+                    stats = server_stats.stats || COALESCE((
+                        SELECT jsonb_object_agg(key, value::bigint + COALESCE((server_stats.stats ->> key)::bigint, 0))
+                        FROM jsonb_each_text(EXCLUDED.stats)
+                    ), '{}')")
+            .bind(&bundle.server_name).bind(namespace).bind(bundle.stats.players.len() as i64)
+            .bind(Json(ServerStats::stat_counts(&bundle)))
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        // The bundle has already been applied, so failing the upload here would only lead to it being applied again.
+        if self.config.stat_history.enabled {
+            if let Err(e) = self.record_stat_history(namespace, &bundle.stats.players, &updated).await {
+                log::warn!("failed to record stat history of bundle for {}: {}", namespace, e);
+            }
+        }
+        Ok(BundleOutcome::Applied)
+    }
+
+    async fn check_stats_bundle(&self, bundle: &GameStatsBundle) -> Result<BundleOutcome> {
+        let namespace = &bundle.namespace;
+        if let Some(bundle_id) = &bundle.bundle_id {
+            let applied = sqlx::query("SELECT 1 FROM applied_bundles WHERE id = $1::uuid")
+                .bind(bundle_id.to_string())
+                .fetch_optional(&self.pool).await?
+                .is_some();
+            if applied {
+                return Ok(BundleOutcome::Duplicate);
+            }
+        }
+
+        // The stored stats are added up as an upload would add them, without locking or writing them back.
+        if !bundle.stats.players.is_empty() {
+            let mut seasons = vec![ALL_TIME];
+            if self.config.seasonal_namespaces.contains(namespace) {
+                seasons.push(self.get_current_season().await?.season as i32);
+            }
+            let uuids: Vec<String> = bundle.stats.players.keys().map(Uuid::to_string).collect();
+            let rows = sqlx::query("SELECT uuid::text AS uuid, stats FROM player_stats
+                    WHERE uuid = ANY($1::uuid[]) AND namespace = $2 AND season = ANY($3)")
+                .bind(&uuids).bind(namespace).bind(&seasons)
+                .fetch_all(&self.pool).await?;
+            for row in rows {
+                let player = row_uuid(&row)?;
+                let stored = row.try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0;
+                if let Some(stats) = bundle.stats.players.get(&player) {
+                    add_upload(&self.config, namespace, Some(player), stored, stats)?;
+                }
+            }
+        }
+
+        if let Some(global) = &bundle.stats.global {
+            let stored = sqlx::query("SELECT stats FROM global_stats WHERE namespace = $1")
+                .bind(namespace)
+                .fetch_optional(&self.pool).await?;
+            if let Some(row) = stored {
+                let stored = row.try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0;
+                add_upload(&self.config, namespace, None, stored, global)?;
+            }
+        }
+        Ok(BundleOutcome::Applied)
+    }
+
+    async fn log_upload(&self, entry: UploadLogEntry) -> Result<()> {
+        let seq: i64 = sqlx::query_scalar("INSERT INTO upload_log (received_at, token, server_name, namespace, players, stats, spooled)
+                VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING seq")
+            .bind(entry.received_at.to_chrono()).bind(&entry.token).bind(&entry.server_name).bind(&entry.namespace)
+            .bind(entry.players).bind(entry.stats).bind(entry.spooled)
+            .fetch_one(&self.pool).await?;
+        // Sequence numbers are never reused, so everything more than `max_entries` behind the new entry is dropped.
+        sqlx::query("DELETE FROM upload_log WHERE seq <= $1")
+            .bind(seq - self.config.upload_log.max_entries as i64)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_upload_log(&self, message: GetUploadLog) -> Result<Vec<UploadLogEntry>> {
+        let rows = sqlx::query("SELECT received_at, token, server_name, namespace, players, stats, spooled FROM upload_log
+                WHERE ($1::text IS NULL OR server_name = $1) AND ($2::text IS NULL OR namespace = $2) AND ($3::text IS NULL OR token = $3)
+                ORDER BY seq DESC LIMIT $4")
+            .bind(&message.server_name).bind(&message.namespace).bind(&message.token).bind(message.limit)
+            .fetch_all(&self.pool).await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(UploadLogEntry {
+                received_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("received_at")?),
+                token: row.try_get("token")?,
+                server_name: row.try_get("server_name")?,
+                namespace: row.try_get("namespace")?,
+                players: row.try_get("players")?,
+                stats: row.try_get("stats")?,
+                spooled: row.try_get("spooled")?,
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn get_server_stats(&self, server_name: &str) -> Result<Vec<ServerStats>> {
+        let rows = sqlx::query("SELECT namespace, bundles, player_uploads, last_upload_at, stats FROM server_stats WHERE server_name = $1")
+            .bind(server_name)
+            .fetch_all(&self.pool).await?;
+
+        let mut stats = Vec::new();
+        for row in rows {
+            stats.push(ServerStats {
+                server_name: server_name.to_string(),
+                namespace: row.try_get("namespace")?,
+                bundles: row.try_get("bundles")?,
+                player_uploads: row.try_get("player_uploads")?,
+                last_upload_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("last_upload_at")?),
+                stats: row.try_get::<Json<HashMap<String, i64>>, _>("stats")?.0,
+            });
+        }
+        Ok(stats)
+    }
+
+    async fn convert_stat(&self, message: ConvertStat) -> Result<StatConversionReport> {
+        let mut report = StatConversionReport {
+            dry_run: message.dry_run,
+            ..Default::default()
+        };
+
+        let mut tx = self.pool.begin().await?;
+        let players = sqlx::query("SELECT uuid::text AS uuid, season, stats -> $2 AS stat FROM player_stats
+                WHERE namespace = $1 AND stats ? $2 FOR UPDATE")
+            .bind(&message.namespace).bind(&message.stat)
+            .fetch_all(&mut *tx).await?;
+        let global = sqlx::query("SELECT NULL::text AS uuid, NULL::integer AS season, stats -> $2 AS stat FROM global_stats
+                WHERE namespace = $1 AND stats ? $2 FOR UPDATE")
+            .bind(&message.namespace).bind(&message.stat)
+            .fetch_all(&mut *tx).await?;
+
+        for row in players.iter().chain(&global) {
+            report.matched += 1;
+            let uuid: Option<String> = row.try_get("uuid")?;
+            let season: Option<i32> = row.try_get("season")?;
+            let document = row_key(&message.namespace, uuid.as_deref(), season);
+
+            let converted = row.try_get::<Json<GameStat>, _>("stat")
+                .map_err(anyhow::Error::from)
+                .and_then(|stat| Ok(stat.0.convert(message.to, message.count)?));
+            let converted = match converted {
+                Ok(converted) => converted,
+                Err(e) => {
+                    report.failed.push(DocumentFailure { document, error: e.to_string() });
+                    continue;
+                }
+            };
+            report.converted += 1;
+
+            let update = match (uuid, season) {
+                (Some(uuid), Some(season)) => sqlx::query("UPDATE player_stats SET stats = jsonb_set(stats, ARRAY[$2], $3)
+                        WHERE namespace = $1 AND uuid = $4::uuid AND season = $5")
+                    .bind(&message.namespace).bind(&message.stat).bind(Json(converted)).bind(uuid).bind(season),
+                _ => sqlx::query("UPDATE global_stats SET stats = jsonb_set(stats, ARRAY[$2], $3) WHERE namespace = $1")
+                    .bind(&message.namespace).bind(&message.stat).bind(Json(converted)),
+            };
+            update.execute(&mut *tx).await?;
+        }
+
+        if message.dry_run {
+            tx.rollback().await?;
+        } else {
+            tx.commit().await?;
+        }
+
+        log::info!("{} stat '{}' in {} to {:?}: {} matched, {} converted, {} failed",
+            if message.dry_run { "Dry run converting" } else { "Converted" },
+            message.stat, message.namespace, message.to, report.matched, report.converted, report.failed.len());
+
+        Ok(report)
+    }
+
+    async fn rename_stats(&self, namespace: &str, stats: &HashMap<String, String>) -> Result<StatRenameReport> {
+        let mut report = StatRenameReport::default();
+        let mut tx = self.pool.begin().await?;
+        for (from, to) in stats {
+            let conflicts = sqlx::query("SELECT uuid::text AS uuid, season FROM player_stats WHERE namespace = $1 AND stats ? $2 AND stats ? $3")
+                .bind(namespace).bind(from).bind(to)
+                .fetch_all(&mut *tx).await?;
+            for row in conflicts {
+                let uuid: String = row.try_get("uuid")?;
+                report.failed.push(DocumentFailure {
+                    document: row_key(namespace, Some(&uuid), Some(row.try_get("season")?)),
+                    error: format!("stat '{}' already exists", to),
+                });
+            }
+            let conflict = sqlx::query("SELECT 1 FROM global_stats WHERE namespace = $1 AND stats ? $2 AND stats ? $3")
+                .bind(namespace).bind(from).bind(to)
+                .fetch_optional(&mut *tx).await?;
+            if conflict.is_some() {
+                report.failed.push(DocumentFailure {
+                    document: row_key(namespace, None, None),
+                    error: format!("stat '{}' already exists", to),
+                });
+            }
+
+            for table in ["player_stats", "global_stats", "global_stats_rollups", "stat_history", "server_stats"] {
+                let res = sqlx::query(&format!("UPDATE {} SET stats = (stats - $2) || jsonb_build_object($3::text, stats -> $2)
+                        WHERE namespace = $1 AND stats ? $2 AND NOT stats ? $3", table))
+                    .bind(namespace).bind(from).bind(to)
+                    .execute(&mut *tx).await?;
+                if matches!(table, "player_stats" | "global_stats") {
+                    report.renamed += res.rows_affected();
+                }
+            }
+            sqlx::query("UPDATE leaderboard_snapshots SET stat = $3 WHERE namespace = $1 AND stat = $2")
+                .bind(namespace).bind(from).bind(to)
+                .execute(&mut *tx).await?;
+            sqlx::query("UPDATE stat_metadata SET stat = $3 WHERE namespace = $1 AND stat = $2
+                    AND NOT EXISTS (SELECT 1 FROM stat_metadata WHERE namespace = $1 AND stat = $3)")
+                .bind(namespace).bind(from).bind(to)
+                .execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        log::info!("Renamed {} stats in {}: {} renamed, {} failed", stats.len(), namespace, report.renamed, report.failed.len());
+        Ok(report)
+    }
+
+    async fn merge_namespace(&self, from: &str, into: &str) -> Result<NamespaceMergeReport> {
+        let mut report = NamespaceMergeReport::default();
+
+        let players = sqlx::query("SELECT uuid::text AS uuid, season, stats FROM player_stats WHERE namespace = $1")
+            .bind(from)
+            .fetch_all(&self.pool).await?;
+        for row in players {
+            let uuid: String = row.try_get("uuid")?;
+            let season: i32 = row.try_get("season")?;
+            let result = match row.try_get::<Json<HashMap<String, GameStat>>, _>("stats") {
+                Ok(stats) => self.merge_stats_row(from, into, Some((Uuid::parse_str(&uuid)?, season)), stats.0).await,
+                Err(e) => Err(e.into()),
+            };
+            record_merge(&mut report, row_key(from, Some(&uuid), Some(season)), result);
+        }
+
+        let global = sqlx::query("SELECT stats FROM global_stats WHERE namespace = $1")
+            .bind(from)
+            .fetch_optional(&self.pool).await?;
+        if let Some(row) = global {
+            let result = match row.try_get::<Json<HashMap<String, GameStat>>, _>("stats") {
+                Ok(stats) => self.merge_stats_row(from, into, None, stats.0).await,
+                Err(e) => Err(e.into()),
+            };
+            record_merge(&mut report, row_key(from, None, None), result);
+        }
+
+        log::info!("Merged namespace {} into {}: {} documents merged, {} failed", from, into, report.merged, report.failed.len());
+        Ok(report)
+    }
+
+    async fn delete_stat(&self, namespace: &str, stat: &str) -> Result<StatDeletionReport> {
+        let mut tx = self.pool.begin().await?;
+        let mut removed = Vec::new();
+        for table in ["player_stats", "global_stats", "global_stats_rollups", "stat_history", "server_stats"] {
+            let res = sqlx::query(&format!("UPDATE {} SET stats = stats - $2 WHERE namespace = $1 AND stats ? $2", table))
+                .bind(namespace).bind(stat)
+                .execute(&mut *tx).await?;
+            removed.push(res.rows_affected());
+        }
+        // Entries that only recorded this stat would otherwise be left with nothing in them.
+        sqlx::query("DELETE FROM stat_history WHERE namespace = $1 AND stats = '{}'::jsonb")
+            .bind(namespace)
+            .execute(&mut *tx).await?;
+        let snapshots = sqlx::query("DELETE FROM leaderboard_snapshots WHERE namespace = $1 AND stat = $2")
+            .bind(namespace).bind(stat)
+            .execute(&mut *tx).await?;
+        sqlx::query("DELETE FROM stat_metadata WHERE namespace = $1 AND stat = $2")
+            .bind(namespace).bind(stat)
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        let report = StatDeletionReport {
+            stats_documents: removed[0],
+            global: removed[1] > 0,
+            history_entries: removed[3],
+            leaderboard_snapshots: snapshots.rows_affected(),
+        };
+        log::info!("Deleted stat '{}' in {}: {} stats documents, {} history entries, {} leaderboard snapshots",
+            stat, namespace, report.stats_documents, report.history_entries, report.leaderboard_snapshots);
+        Ok(report)
+    }
+
+    async fn delete_namespace(&self, namespace: &str) -> Result<NamespaceDeletionReport> {
+        let tables = [
+            "player_stats", "global_stats", "stat_history", "leaderboard_snapshots", "games", "achievements", "ratings", "playtime",
+            "global_stats_rollups", "stat_metadata", "achievement_metadata", "server_stats",
+        ];
+        let mut tx = self.pool.begin().await?;
+        let mut deleted = Vec::new();
+        for table in tables {
+            let res = sqlx::query(&format!("DELETE FROM {} WHERE namespace = $1", table))
+                .bind(namespace)
+                .execute(&mut *tx).await?;
+            deleted.push(res.rows_affected());
+        }
+        tx.commit().await?;
+
+        let report = NamespaceDeletionReport {
+            stats_documents: deleted[0],
+            global: deleted[1] > 0,
+            history_entries: deleted[2],
+            leaderboard_snapshots: deleted[3],
+            games: deleted[4],
+            achievements: deleted[5],
+            ratings: deleted[6],
+            playtime: deleted[7],
+        };
+        log::info!("Deleted namespace {}: {} stats documents, {} games", namespace, report.stats_documents, report.games);
+        Ok(report)
+    }
+
+    // Rows are always valid JSON, and stats that can't be read fail the operation that reads them instead of being
+    // quarantined, so there is never anything to restore.
+    async fn get_corrupt_documents(&self, _limit: i64) -> Result<Vec<CorruptDocument>> {
+        Ok(Vec::new())
+    }
+
+    async fn get_corrupt_document(&self, _id: ObjectId) -> Result<Option<CorruptDocument>> {
+        Ok(None)
+    }
+
+    async fn restore_corrupt_document(&self, _id: ObjectId, _repaired: Document) -> Result<RestoreOutcome> {
+        Ok(RestoreOutcome::NotFound)
+    }
+
+    async fn quarantine_upload(&self, upload: SuspiciousUpload) -> Result<()> {
+        sqlx::query("INSERT INTO suspicious_uploads (id, received_at, token, violations, bundle) VALUES ($1, $2, $3, $4, $5)")
+            .bind(upload.id.to_hex()).bind(upload.received_at.to_chrono()).bind(&upload.token)
+            .bind(Json(&upload.violations)).bind(Json(&upload.to_bundle()?))
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_suspicious_uploads(&self, limit: i64) -> Result<Vec<SuspiciousUpload>> {
+        let rows = sqlx::query("SELECT id, received_at, token, violations, bundle FROM suspicious_uploads ORDER BY received_at DESC LIMIT $1")
+            .bind(limit)
+            .fetch_all(&self.pool).await?;
+        rows.iter().map(suspicious_upload_from_row).collect()
+    }
+
+    async fn delete_suspicious_upload(&self, id: ObjectId) -> Result<Option<SuspiciousUpload>> {
+        let row = sqlx::query("DELETE FROM suspicious_uploads WHERE id = $1 RETURNING id, received_at, token, violations, bundle")
+            .bind(id.to_hex())
+            .fetch_optional(&self.pool).await?;
+        row.as_ref().map(suspicious_upload_from_row).transpose()
+    }
+
+    async fn get_stat_info(&self, namespace: &str) -> Result<HashMap<String, StatInfo>> {
+        let rows = sqlx::query("SELECT stat, info FROM stat_metadata WHERE namespace = $1")
+            .bind(namespace)
+            .fetch_all(&self.pool).await?;
+        let mut info = HashMap::new();
+        for row in rows {
+            info.insert(row.try_get("stat")?, row.try_get::<Json<StatInfo>, _>("info")?.0);
+        }
+        Ok(info)
+    }
+
+    async fn update_stat_info(&self, namespace: String, stats: HashMap<String, StatInfo>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (stat, info) in stats {
+            sqlx::query("INSERT INTO stat_metadata (namespace, stat, info, updated_at) VALUES ($1, $2, $3, now())
+                    ON CONFLICT (namespace, stat) DO UPDATE SET info = EXCLUDED.info, updated_at = EXCLUDED.updated_at")
+                .bind(&namespace).bind(stat).bind(Json(info))
+                .execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_achievement_info(&self, namespace: &str) -> Result<HashMap<String, AchievementInfo>> {
+        let rows = sqlx::query("SELECT achievement, info FROM achievement_metadata WHERE namespace = $1")
+            .bind(namespace)
+            .fetch_all(&self.pool).await?;
+        let mut info = HashMap::new();
+        for row in rows {
+            info.insert(row.try_get("achievement")?, row.try_get::<Json<AchievementInfo>, _>("info")?.0);
+        }
+        Ok(info)
+    }
+
+    async fn update_achievement_info(&self, namespace: String, achievements: HashMap<String, AchievementInfo>) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for (achievement, info) in achievements {
+            sqlx::query("INSERT INTO achievement_metadata (namespace, achievement, info, updated_at) VALUES ($1, $2, $3, now())
+                    ON CONFLICT (namespace, achievement) DO UPDATE SET info = EXCLUDED.info, updated_at = EXCLUDED.updated_at")
+                .bind(&namespace).bind(achievement).bind(Json(info))
+                .execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn grant_achievements(&self, uuid: &Uuid, namespace: &str, achievements: &[String]) -> Result<Vec<String>> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO players (uuid, first_seen, last_seen) VALUES ($1::uuid, now(), now())
+                ON CONFLICT (uuid) DO UPDATE SET first_seen = COALESCE(players.first_seen, EXCLUDED.first_seen), last_seen = EXCLUDED.last_seen")
+            .bind(uuid.to_string())
+            .execute(&mut *tx).await?;
+        let rows = sqlx::query("INSERT INTO achievements (uuid, namespace, achievement, unlocked_at)
+                SELECT $1::uuid, $2, unnest($3::text[]), now()
+                ON CONFLICT DO NOTHING RETURNING achievement")
+            .bind(uuid.to_string()).bind(namespace).bind(achievements)
+            .fetch_all(&mut *tx).await?;
+        tx.commit().await?;
+        Ok(rows.iter().map(|row| row.try_get("achievement")).collect::<Result<_, _>>()?)
+    }
+
+    async fn get_player_achievements(&self, uuid: &Uuid, namespace: Option<&str>) -> Result<Vec<StoredAchievement>> {
+        let rows = sqlx::query("SELECT namespace, achievement, unlocked_at FROM achievements
+                WHERE uuid = $1::uuid AND ($2::text IS NULL OR namespace = $2) ORDER BY unlocked_at DESC")
+            .bind(uuid.to_string()).bind(namespace)
+            .fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok(StoredAchievement {
+                namespace: row.try_get("namespace")?,
+                achievement: row.try_get("achievement")?,
+                unlocked_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("unlocked_at")?),
+            }))
+            .collect()
+    }
+
+    async fn get_achievement_unlocks(&self, namespace: &str) -> Result<AchievementUnlocks> {
+        let rows = sqlx::query("SELECT achievement, count(*) AS players FROM achievements WHERE namespace = $1 GROUP BY achievement")
+            .bind(namespace)
+            .fetch_all(&self.pool).await?;
+        let mut unlocks = AchievementUnlocks::default();
+        for row in rows {
+            unlocks.unlocks.insert(row.try_get("achievement")?, row.try_get::<i64, _>("players")? as u64);
+        }
+
+        // Players who were granted an achievement without uploading any stats are counted too.
+        let players: i64 = sqlx::query_scalar("SELECT count(*) FROM (
+                SELECT uuid FROM player_stats WHERE namespace = $1 AND season = $2
+                UNION SELECT uuid FROM achievements WHERE namespace = $1
+            ) AS players")
+            .bind(namespace).bind(ALL_TIME)
+            .fetch_one(&self.pool).await?;
+        unlocks.players = players as u64;
+        Ok(unlocks)
+    }
+
+    async fn record_match(&self, namespace: &str, teams: &[MatchTeam]) -> Result<HashMap<Uuid, RatingChange>> {
+        // Sorted, so that concurrent matches with the same players lock their rows in the same order.
+        let mut uuids: Vec<String> = teams.iter().flat_map(|team| &team.players).map(Uuid::to_string).collect();
+        uuids.sort();
+
+        let mut tx = self.pool.begin().await?;
+        sqlx::query("INSERT INTO players (uuid, first_seen, last_seen) SELECT unnest($1::text[])::uuid, now(), now()
+                ON CONFLICT (uuid) DO UPDATE SET first_seen = COALESCE(players.first_seen, EXCLUDED.first_seen), last_seen = EXCLUDED.last_seen")
+            .bind(&uuids)
+            .execute(&mut *tx).await?;
+        sqlx::query("INSERT INTO ratings (uuid, namespace, rating, matches, updated_at)
+                SELECT unnest($1::text[])::uuid, $2, $3, 0, now()
+                ON CONFLICT DO NOTHING")
+            .bind(&uuids).bind(namespace).bind(self.config.ratings.initial_rating)
+            .execute(&mut *tx).await?;
+        let rows = sqlx::query("SELECT uuid::text AS uuid, rating FROM ratings
+                WHERE namespace = $1 AND uuid = ANY($2::text[]::uuid[]) ORDER BY uuid FOR UPDATE")
+            .bind(namespace).bind(&uuids)
+            .fetch_all(&mut *tx).await?;
+        let mut ratings = HashMap::new();
+        for row in rows {
+            ratings.insert(row_uuid(&row)?, row.try_get::<f64, _>("rating")?);
+        }
+
+        let changes = crate::ratings::rate_match(&self.config.ratings, &ratings, teams);
+        for (uuid, change) in &changes {
+            sqlx::query("UPDATE ratings SET rating = $3, matches = matches + 1, updated_at = now()
+                    WHERE uuid = $1::uuid AND namespace = $2")
+                .bind(uuid.to_string()).bind(namespace).bind(change.rating)
+                .execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(changes)
+    }
+
+    async fn get_player_ratings(&self, uuid: &Uuid) -> Result<Vec<StoredRating>> {
+        let rows = sqlx::query("SELECT namespace, rating, matches, updated_at FROM ratings WHERE uuid = $1::uuid")
+            .bind(uuid.to_string())
+            .fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok(StoredRating {
+                namespace: row.try_get("namespace")?,
+                rating: row.try_get("rating")?,
+                matches: row.try_get("matches")?,
+                updated_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("updated_at")?),
+            }))
+            .collect()
+    }
+
+    async fn get_rating_leaderboard(&self, message: GetRatingLeaderboard) -> Result<Vec<LeaderboardEntry>> {
+        let rows = sqlx::query("SELECT ratings.uuid::text AS uuid, players.username, ratings.rating
+                FROM ratings LEFT JOIN players ON players.uuid = ratings.uuid
+                WHERE ratings.namespace = $1 AND ($2 OR NOT COALESCE(players.private, FALSE))
+                ORDER BY ratings.rating DESC, ratings.uuid LIMIT $3")
+            .bind(&message.namespace).bind(message.include_private).bind(message.limit)
+            .fetch_all(&self.pool).await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(LeaderboardEntry {
+                rank: entries.len() as u32 + 1,
+                uuid: row_uuid(&row)?,
+                username: row.try_get("username")?,
+                value: row.try_get("rating")?,
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn get_namespaces(&self) -> Result<Vec<String>> {
+        let rows = sqlx::query("SELECT namespace FROM player_stats UNION SELECT namespace FROM global_stats ORDER BY namespace")
+            .fetch_all(&self.pool).await?;
+        Ok(rows.iter().map(|row| row.try_get("namespace")).collect::<Result<_, _>>()?)
+    }
+
+    async fn get_global_stats(&self, namespace: &str) -> Result<Option<HashMap<String, f64>>> {
+        let row = sqlx::query("SELECT stats FROM global_stats WHERE namespace = $1")
+            .bind(namespace)
+            .fetch_optional(&self.pool).await?;
+        match row {
+            Some(row) => Ok(Some(stat_values(&self.config, namespace, row.try_get::<Json<_>, _>("stats")?.0))),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_global_stats_delta(&self, namespace: &str, window_hours: u32) -> Result<HashMap<String, f64>> {
+        // Rollups are read oldest first, so that the latest values of the window are those of its last hour.
+        let rows = sqlx::query("SELECT stats FROM global_stats_rollups
+                WHERE namespace = $1 AND hour >= date_trunc('hour', now() - make_interval(hours => $2)) ORDER BY hour")
+            .bind(namespace).bind(window_hours as i32)
+            .fetch_all(&self.pool).await?;
+        let rollups = rows.iter()
+            .map(|row| Ok(row.try_get::<Json<HashMap<String, GameStat>>, _>("stats")?.0))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(sum_rollups(rollups))
+    }
+
+    async fn insert_game(&self, game: Game) -> Result<()> {
+        let participants: Vec<_> = game.participants.iter()
+            .map(|participant| json!({
+                "uuid": participant.uuid.to_string(),
+                "winner": participant.winner,
+                "score": participant.score,
+            }))
+            .collect();
+        sqlx::query("INSERT INTO games (id, server_name, namespace, started_at, ended_at, participants) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(game.id.to_hex()).bind(&game.server_name).bind(&game.namespace)
+            .bind(game.started_at.to_chrono()).bind(game.ended_at.to_chrono()).bind(Json(participants))
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_games(&self, message: GetGames) -> Result<Vec<Game>> {
+        let mut query = QueryBuilder::<Postgres>::new("SELECT id, server_name, namespace, started_at, ended_at, participants FROM games WHERE ");
+        match &message.namespace {
+            Some(namespace) => query.push("namespace = ").push_bind(namespace),
+            None => query.push("NOT (namespace = ANY(").push_bind(&message.hidden_namespaces).push("))"),
+        };
+        if let Some(player) = &message.player {
+            query.push(" AND participants @> ").push_bind(Json(json!([{"uuid": player.to_string()}])));
+        }
+        query.push(" ORDER BY ended_at DESC LIMIT ").push_bind(message.limit);
+        let rows = query.build().fetch_all(&self.pool).await?;
+
+        let mut games = rows.iter().map(game_from_row).collect::<Result<Vec<_>>>()?;
+        if !message.include_private {
+            let participants: Vec<String> = games.iter()
+                .flat_map(|game| &game.participants)
+                .map(|participant| participant.uuid.to_string())
+                .collect();
+            let private = sqlx::query("SELECT uuid::text AS uuid FROM players WHERE private AND uuid = ANY($1::uuid[])")
+                .bind(&participants)
+                .fetch_all(&self.pool).await?
+                .iter().map(row_uuid).collect::<Result<Vec<_>>>()?;
+            for game in &mut games {
+                game.participants.retain(|participant| !private.contains(&participant.uuid));
+            }
+        }
+        Ok(games)
+    }
+
+    async fn get_leaderboard(&self, message: GetLeaderboard) -> Result<Vec<LeaderboardEntry>> {
+        let direction = match message.order {
+            LeaderboardOrder::Descending => "DESC",
+            LeaderboardOrder::Ascending => "ASC",
+        };
+        let rows = sqlx::query(&format!("SELECT uuid, username, value FROM (
+                SELECT stats.uuid::text AS uuid, players.username, COALESCE(players.private, FALSE) AS private,
+                    CASE WHEN jsonb_typeof(stats.stats -> $2 -> 'value') = 'object'
+                        THEN (stats.stats -> $2 -> 'value' ->> 'total')::float8 / (stats.stats -> $2 -> 'value' ->> 'count')::float8
+                        ELSE (stats.stats -> $2 ->> 'value')::float8
+                    END AS value
+                FROM player_stats stats LEFT JOIN players ON players.uuid = stats.uuid
+                WHERE stats.namespace = $1 AND stats.season = $3 AND stats.stats ? $2
+            ) ranked
+            WHERE value IS NOT NULL AND ($4 OR NOT private)
+            ORDER BY value {}, uuid LIMIT $5", direction))
+            .bind(&message.namespace).bind(&message.stat)
+            .bind(message.season.map_or(ALL_TIME, |season| season as i32))
+            .bind(message.include_private).bind(message.limit)
+            .fetch_all(&self.pool).await?;
+
+        let mut entries = Vec::new();
+        for row in rows {
+            entries.push(LeaderboardEntry {
+                rank: entries.len() as u32 + 1,
+                uuid: row_uuid(&row)?,
+                username: row.try_get("username")?,
+                value: row.try_get("value")?,
+            });
+        }
+        Ok(entries)
+    }
+
+    async fn get_stat_rank(&self, message: GetStatRank) -> Result<Option<StatRank>> {
+        let better = match message.order {
+            LeaderboardOrder::Descending => ">",
+            LeaderboardOrder::Ascending => "<",
+        };
+        let row = sqlx::query(&format!("WITH ranked AS (
+                SELECT stats.uuid, COALESCE(players.private, FALSE) AS private,
+                    CASE WHEN jsonb_typeof(stats.stats -> $2 -> 'value') = 'object'
+                        THEN (stats.stats -> $2 -> 'value' ->> 'total')::float8 / (stats.stats -> $2 -> 'value' ->> 'count')::float8
+                        ELSE (stats.stats -> $2 ->> 'value')::float8
+                    END AS value
+                FROM player_stats stats LEFT JOIN players ON players.uuid = stats.uuid
+                WHERE stats.namespace = $1 AND stats.season = $3 AND stats.stats ? $2
+            ), player AS (
+                SELECT value FROM ranked WHERE uuid = $4::uuid AND value IS NOT NULL
+            )
+            SELECT player.value,
+                (SELECT count(*) FROM ranked WHERE ranked.value {} player.value AND ($5 OR NOT private)) AS ahead,
+                (SELECT count(*) FROM ranked WHERE ranked.value IS NOT NULL AND ($5 OR NOT private)) AS players
+            FROM player", better))
+            .bind(&message.namespace).bind(&message.stat)
+            .bind(message.season.map_or(ALL_TIME, |season| season as i32))
+            .bind(message.uuid.to_string()).bind(message.include_private)
+            .fetch_optional(&self.pool).await?;
+
+        row.map(|row| Ok(StatRank {
+            value: row.try_get("value")?,
+            ahead: row.try_get::<i64, _>("ahead")? as u64,
+            players: row.try_get::<i64, _>("players")? as u64,
+        })).transpose()
+    }
+
+    async fn get_stat_summary(&self, message: GetStatSummary) -> Result<Option<StatSummaryResponse>> {
+        let row = sqlx::query("WITH summarized AS (
+                SELECT CASE WHEN jsonb_typeof(stats -> $2 -> 'value') = 'object'
+                        THEN (stats -> $2 -> 'value' ->> 'total')::float8 / (stats -> $2 -> 'value' ->> 'count')::float8
+                        ELSE (stats -> $2 ->> 'value')::float8
+                    END AS value
+                FROM player_stats
+                WHERE namespace = $1 AND season = $3 AND stats ? $2
+            )
+            SELECT count(value) AS players, sum(value) AS sum, avg(value) AS mean,
+                percentile_cont(0.5) WITHIN GROUP (ORDER BY value) AS median, stddev_pop(value) AS stddev
+            FROM summarized")
+            .bind(&message.namespace).bind(&message.stat)
+            .bind(message.season.map_or(ALL_TIME, |season| season as i32))
+            .fetch_one(&self.pool).await?;
+
+        let players = row.try_get::<i64, _>("players")? as u64;
+        if players == 0 {
+            return Ok(None);
+        }
+        Ok(Some(StatSummaryResponse {
+            players,
+            sum: row.try_get("sum")?,
+            mean: row.try_get("mean")?,
+            median: row.try_get("median")?,
+            stddev: row.try_get("stddev")?,
+        }))
+    }
+
+    async fn get_stat_history(&self, message: GetStatHistory) -> Result<Vec<StatSnapshot>> {
+        let rows = sqlx::query("SELECT id, uuid::text AS uuid, namespace, recorded_at, stats FROM stat_history
+                WHERE uuid = $1::uuid AND namespace = $2 AND stats ? $3
+                    AND ($4::timestamptz IS NULL OR recorded_at >= $4) AND ($5::timestamptz IS NULL OR recorded_at < $5)
+                ORDER BY recorded_at LIMIT $6")
+            .bind(message.uuid.to_string()).bind(&message.namespace).bind(&message.stat)
+            .bind(message.from.map(bson::DateTime::to_chrono)).bind(message.until.map(bson::DateTime::to_chrono))
+            .bind(message.limit)
+            .fetch_all(&self.pool).await?;
+
+        rows.iter()
+            .map(|row| {
+                let mut stats = row.try_get::<Json<HashMap<String, f64>>, _>("stats")?.0;
+                stats.retain(|name, _| *name == message.stat);
+                Ok(StatSnapshot {
+                    id: ObjectId::parse_str(row.try_get::<String, _>("id")?)?,
+                    uuid: row_uuid(row)?,
+                    namespace: row.try_get("namespace")?,
+                    recorded_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("recorded_at")?),
+                    stats,
+                })
+            })
+            .collect()
+    }
+
+    async fn get_stat_histogram(&self, message: GetStatHistogram) -> Result<Option<Vec<HistogramBucket>>> {
+        let row = match &message.uuid {
+            Some(uuid) => sqlx::query("SELECT stats -> $3 AS stat FROM player_stats WHERE uuid = $1::uuid AND namespace = $2 AND season = $4")
+                .bind(uuid.to_string()).bind(&message.namespace).bind(&message.stat).bind(ALL_TIME)
+                .fetch_optional(&self.pool).await?,
+            None => sqlx::query("SELECT stats -> $2 AS stat FROM global_stats WHERE namespace = $1")
+                .bind(&message.namespace).bind(&message.stat)
+                .fetch_optional(&self.pool).await?,
+        };
+        let stat = match row {
+            Some(row) => row.try_get::<Option<Json<GameStat>>, _>("stat")?,
+            None => None,
+        };
+        Ok(stat.and_then(|stat| stat.0.histogram()))
+    }
+
+    async fn get_current_season(&self) -> Result<StoredSeason> {
+        let row = sqlx::query("SELECT season, started_at FROM seasons WHERE id = $1")
+            .bind(SEASON_ID)
+            .fetch_optional(&self.pool).await?;
+        match row {
+            Some(row) => season_from_row(&row),
+            None => Ok(StoredSeason {
+                id: SEASON_ID.to_string(),
+                season: 1,
+                started_at: None,
+            }),
+        }
+    }
+
+    async fn start_season(&self) -> Result<StoredSeason> {
+        let row = sqlx::query("INSERT INTO seasons (id, season, started_at) VALUES ($1, 2, now())
+                ON CONFLICT (id) DO UPDATE SET season = seasons.season + 1, started_at = now()
+                RETURNING season, started_at")
+            .bind(SEASON_ID)
+            .fetch_one(&self.pool).await?;
+        let season = season_from_row(&row)?;
+        log::info!("Started season {}", season.season);
+        Ok(season)
+    }
+
+    async fn prune_global_stats_rollups(&self) -> Result<u64> {
+        let res = sqlx::query("DELETE FROM global_stats_rollups WHERE hour < now() - make_interval(hours => $1)")
+            .bind(self.config.global_stats_rollup_retention_hours as i32)
+            .execute(&self.pool).await?;
+        log::debug!("Pruned {} global stats rollups", res.rows_affected());
+        Ok(res.rows_affected())
+    }
+
+    async fn prune_bundle_log(&self) -> Result<u64> {
+        let retention_days = match self.config.bundle_log.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let res = sqlx::query("DELETE FROM bundle_log WHERE received_at < now() - make_interval(days => $1)")
+            .bind(retention_days as i32)
+            .execute(&self.pool).await?;
+        log::debug!("Pruned {} logged bundles", res.rows_affected());
+        Ok(res.rows_affected())
+    }
+
+    async fn prune_stat_history(&self) -> Result<u64> {
+        let retention_days = match self.config.stat_history.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let res = sqlx::query("DELETE FROM stat_history WHERE recorded_at < now() - make_interval(days => $1)")
+            .bind(retention_days as i32)
+            .execute(&self.pool).await?;
+        log::debug!("Pruned {} stat history snapshots", res.rows_affected());
+        Ok(res.rows_affected())
+    }
+
+    async fn save_leaderboard_snapshot(&self, snapshot: StoredLeaderboardSnapshot) -> Result<()> {
+        sqlx::query("INSERT INTO leaderboard_snapshots (id, namespace, stat, ascending, taken_at, entries) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(snapshot.id.to_hex()).bind(&snapshot.namespace).bind(&snapshot.stat)
+            .bind(snapshot.order == LeaderboardOrder::Ascending).bind(snapshot.taken_at.to_chrono()).bind(Json(&snapshot.entries))
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_leaderboard_snapshot(&self, message: GetLeaderboardSnapshot) -> Result<Option<StoredLeaderboardSnapshot>> {
+        let row = sqlx::query("SELECT id, taken_at, entries FROM leaderboard_snapshots
+                WHERE namespace = $1 AND stat = $2 AND ascending = $3 AND taken_at <= $4
+                ORDER BY taken_at DESC LIMIT 1")
+            .bind(&message.namespace).bind(&message.stat)
+            .bind(message.order == LeaderboardOrder::Ascending).bind(message.at.to_chrono())
+            .fetch_optional(&self.pool).await?;
+        row.map(|row| Ok(StoredLeaderboardSnapshot {
+            id: ObjectId::parse_str(row.try_get::<String, _>("id")?)?,
+            namespace: message.namespace,
+            stat: message.stat,
+            order: message.order,
+            taken_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("taken_at")?),
+            entries: row.try_get::<Json<Vec<LeaderboardEntry>>, _>("entries")?.0,
+        })).transpose()
+    }
+
+    async fn prune_leaderboard_snapshots(&self) -> Result<u64> {
+        let retention_days = match self.config.leaderboard_snapshots.retention_days {
+            Some(days) => days,
+            None => return Ok(0),
+        };
+        let res = sqlx::query("DELETE FROM leaderboard_snapshots WHERE taken_at < now() - make_interval(days => $1)")
+            .bind(retention_days as i32)
+            .execute(&self.pool).await?;
+        log::debug!("Pruned {} leaderboard snapshots", res.rows_affected());
+        Ok(res.rows_affected())
+    }
+
+    async fn prune_bundle_ids(&self) -> Result<u64> {
+        let res = sqlx::query("DELETE FROM applied_bundles WHERE applied_at < now() - make_interval(hours => $1)")
+            .bind(self.config.bundle_id_retention_hours as i32)
+            .execute(&self.pool).await?;
+        log::debug!("Pruned {} applied bundle ids", res.rows_affected());
+        Ok(res.rows_affected())
+    }
+
+    async fn rebuild_aggregates(&self, _message: RebuildAggregates) -> Result<AggregateRebuildReport> {
+        Err(Unsupported("rebuilding aggregates", "PostgreSQL").into())
+    }
+
+    async fn acquire_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<bool> {
+        let res = sqlx::query("INSERT INTO leases (name, holder, expires) VALUES ($1, $2, now() + make_interval(secs => $3))
+                ON CONFLICT (name) DO UPDATE SET holder = EXCLUDED.holder, expires = EXCLUDED.expires
+                WHERE leases.holder = EXCLUDED.holder OR leases.expires <= now()")
+            .bind(name).bind(holder).bind(ttl.as_secs_f64())
+            .execute(&self.pool).await?;
+        Ok(res.rows_affected() > 0)
+    }
+
+    async fn release_lease(&self, name: &str, holder: &str) -> Result<()> {
+        sqlx::query("DELETE FROM leases WHERE name = $1 AND holder = $2")
+            .bind(name).bind(holder)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn start_job_run(&self, run: &JobRun) -> Result<()> {
+        sqlx::query("INSERT INTO job_runs (id, job, trigger, started_at, finished_at, outcome, items_processed, error)
+                VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
+            .bind(run.id.to_hex()).bind(&run.job).bind(to_name(run.trigger)?)
+            .bind(run.started_at.to_chrono()).bind(run.finished_at.map(bson::DateTime::to_chrono))
+            .bind(to_name(run.outcome)?).bind(run.items_processed).bind(&run.error)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn finish_job_run(&self, id: ObjectId, result: &std::result::Result<u64, String>) -> Result<()> {
+        let (outcome, items_processed, error) = match result {
+            Ok(items_processed) => ("succeeded", Some(*items_processed as i64), None),
+            Err(error) => ("failed", None, Some(error)),
+        };
+        sqlx::query("UPDATE job_runs SET finished_at = now(), outcome = $2, items_processed = $3, error = $4 WHERE id = $1")
+            .bind(id.to_hex()).bind(outcome).bind(items_processed).bind(error)
+            .execute(&self.pool).await?;
+        Ok(())
+    }
+
+    async fn get_job_runs(&self, job: Option<&str>, limit: i64) -> Result<Vec<JobRun>> {
+        let rows = sqlx::query("SELECT id, job, trigger, started_at, finished_at, outcome, items_processed, error FROM job_runs
+                WHERE $1::text IS NULL OR job = $1 ORDER BY started_at DESC LIMIT $2")
+            .bind(job).bind(limit)
+            .fetch_all(&self.pool).await?;
+        rows.iter()
+            .map(|row| Ok(JobRun {
+                id: ObjectId::parse_str(row.try_get::<String, _>("id")?)?,
+                job: row.try_get("job")?,
+                trigger: from_name(row.try_get("trigger")?)?,
+                started_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("started_at")?),
+                finished_at: row.try_get::<Option<DateTime<Utc>>, _>("finished_at")?.map(bson::DateTime::from_chrono),
+                outcome: from_name(row.try_get("outcome")?)?,
+                items_processed: row.try_get("items_processed")?,
+                error: row.try_get("error")?,
+            }))
+            .collect()
+    }
+
+    async fn backup_manifest(&self) -> Result<BackupManifest> {
+        Ok(BackupManifest {
+            schema_version: Migrations::schema_version(self).await?,
+            collections: BACKUP_TABLES.iter().map(|(table, _)| table.to_string()).collect(),
+        })
+    }
+
+    async fn export_documents(&self, collection: &str, after: Option<serde_json::Value>, limit: i64) -> Result<BackupBatch> {
+        let key = backup_table_key(collection)?;
+        let offset = after.as_ref().and_then(serde_json::Value::as_i64).unwrap_or(0);
+        let rows = sqlx::query(&format!("SELECT to_jsonb(t) AS document FROM {} t ORDER BY {} LIMIT $1 OFFSET $2", collection, key))
+            .bind(limit).bind(offset)
+            .fetch_all(&self.pool).await?;
+        let documents = rows.iter()
+            .map(|row| Ok(row.try_get::<Json<serde_json::Value>, _>("document")?.0))
+            .collect::<Result<Vec<_>>>()?;
+        let next = (documents.len() as i64 >= limit).then(|| serde_json::Value::from(offset + limit));
+        Ok(BackupBatch { documents, next })
+    }
+
+    async fn import_documents(&self, collection: &str, documents: Vec<serde_json::Value>) -> Result<u64> {
+        // Rows are matched on their key as well as on any unique constraint, as some tables don't have one.
+        let key = backup_table_key(collection)?;
+        let restored_key = key.split(", ").map(|column| format!("restored.{}", column)).collect::<Vec<_>>().join(", ");
+        let mut tx = self.pool.begin().await?;
+        let res = sqlx::query(&format!("INSERT INTO {table} SELECT restored.* FROM jsonb_populate_recordset(NULL::{table}, $1) AS restored
+                WHERE NOT EXISTS (SELECT 1 FROM {table} WHERE ({key}) = ({restored_key})) ON CONFLICT DO NOTHING",
+                table = collection, key = key, restored_key = restored_key))
+            .bind(Json(&documents))
+            .execute(&mut *tx).await?;
+        if collection == "upload_log" {
+            // The sequence would otherwise hand out the numbers of restored entries again.
+            sqlx::query("SELECT setval(pg_get_serial_sequence('upload_log', 'seq'), GREATEST((SELECT max(seq) FROM upload_log), 1))")
+                .execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+        Ok(res.rows_affected())
+    }
+}
+
+/// The columns that a table that is backed up is ordered by, checking that it is one of the [BACKUP_TABLES].
+fn backup_table_key(table: &str) -> Result<&'static str> {
+    BACKUP_TABLES.iter()
+        .find(|(name, _)| *name == table)
+        .map(|(_, key)| *key)
+        .ok_or_else(|| anyhow::anyhow!("'{}' isn't a table that is backed up", table))
+}
+
+fn record_merge(report: &mut NamespaceMergeReport, document: String, result: Result<()>) {
+    match result {
+        Ok(()) => report.merged += 1,
+        Err(e) => report.failed.push(DocumentFailure { document, error: e.to_string() }),
+    }
+}
+
+/// Identifies a row of player or global stats in admin reports.
+fn row_key(namespace: &str, uuid: Option<&str>, season: Option<i32>) -> String {
+    match (uuid, season) {
+        (Some(uuid), Some(ALL_TIME) | None) => format!("{}/{}", namespace, uuid),
+        (Some(uuid), Some(season)) => format!("{}/{}/season {}", namespace, uuid, season),
+        (None, _) => format!("{}/global", namespace),
+    }
+}
+
+fn row_uuid(row: &PgRow) -> Result<Uuid> {
+    Ok(Uuid::parse_str(&row.try_get::<String, _>("uuid")?)?)
+}
+
+fn profile_from_row(row: &PgRow) -> Result<PlayerProfile> {
+    Ok(PlayerProfile {
+        uuid: row_uuid(row)?,
+        username: row.try_get("username")?,
+        private: row.try_get("private")?,
+        username_updated_at: row.try_get::<Option<DateTime<Utc>>, _>("username_updated_at")?.map(bson::DateTime::from_chrono),
+        first_seen: row.try_get::<Option<DateTime<Utc>>, _>("first_seen")?.map(bson::DateTime::from_chrono),
+        last_seen: row.try_get::<Option<DateTime<Utc>>, _>("last_seen")?.map(bson::DateTime::from_chrono),
+        anonymized: row.try_get("anonymized")?,
+    })
+}
+
+fn suspicious_upload_from_row(row: &PgRow) -> Result<SuspiciousUpload> {
+    let bundle: GameStatsBundle = row.try_get::<Json<GameStatsBundle>, _>("bundle")?.0;
+    let mut upload = SuspiciousUpload::new(&row.try_get::<String, _>("token")?, &bundle, row.try_get::<Json<Vec<Violation>>, _>("violations")?.0);
+    upload.id = ObjectId::parse_str(row.try_get::<String, _>("id")?)?;
+    upload.received_at = bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("received_at")?);
+    Ok(upload)
+}
+
+fn season_from_row(row: &PgRow) -> Result<StoredSeason> {
+    Ok(StoredSeason {
+        id: SEASON_ID.to_string(),
+        season: row.try_get::<i32, _>("season")? as u32,
+        started_at: row.try_get::<Option<DateTime<Utc>>, _>("started_at")?.map(bson::DateTime::from_chrono),
+    })
+}
+
+fn game_from_row(row: &PgRow) -> Result<Game> {
+    let participants = row.try_get::<Json<Vec<serde_json::Value>>, _>("participants")?.0;
+    Ok(Game {
+        id: ObjectId::parse_str(row.try_get::<String, _>("id")?)?,
+        server_name: row.try_get("server_name")?,
+        namespace: row.try_get("namespace")?,
+        started_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("started_at")?),
+        ended_at: bson::DateTime::from_chrono(row.try_get::<DateTime<Utc>, _>("ended_at")?),
+        participants: participants.iter()
+            .map(|participant| Ok(StoredGameParticipant {
+                uuid: Uuid::parse_str(participant["uuid"].as_str().unwrap_or_default())?,
+                winner: participant["winner"].as_bool().unwrap_or_default(),
+                score: participant["score"].as_f64(),
+            }))
+            .collect::<Result<_>>()?,
+    })
+}
+
+/// The name that a unit enum variant is serialized as, e.g. `succeeded` for [crate::model::JobOutcome::Succeeded].
+fn to_name<T: Serialize>(value: T) -> Result<String> {
+    match serde_json::to_value(value)? {
+        serde_json::Value::String(name) => Ok(name),
+        value => anyhow::bail!("{} is not a unit variant", value),
+    }
+}
+
+fn from_name<T: DeserializeOwned>(name: String) -> Result<T> {
+    Ok(serde_json::from_value(serde_json::Value::String(name))?)
+}
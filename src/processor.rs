@@ -0,0 +1,234 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use thiserror::Error;
+use uuid::Uuid;
+use xtra::Address;
+
+use crate::config::Config;
+use crate::store::{GetPlayerStats, StoreHandler};
+use crate::live::LiveFeed;
+use crate::metrics::Metrics;
+use crate::model::{GameStatsBundle, StatAggregation, UploadStat};
+use crate::result_cache::ResultCache;
+use crate::script::Scripts;
+use crate::wasm::WasmPlugin;
+
+/// Where an uploaded stat came from.
+pub struct StatContext<'a> {
+    pub server_name: &'a str,
+    pub namespace: &'a str,
+    /// The player the stat belongs to, or `None` for global stats.
+    pub player: Option<&'a Uuid>,
+    pub name: &'a str,
+}
+
+#[derive(Error, Debug)]
+#[error("bundle for {namespace} was rejected: {reason}")]
+pub struct BundleRejection {
+    pub namespace: String,
+    pub reason: String,
+}
+
+/// Logic that runs on every uploaded bundle, without changes to how bundles are written.
+#[async_trait]
+pub trait StatProcessor: Send + Sync {
+    /// Called with each uploaded bundle before its stats are processed, to change its stats or reject it.
+    fn transform(&self, _bundle: &mut GameStatsBundle) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called for each uploaded stat before the bundle is written. Rejecting any stat rejects the whole bundle.
+    fn process_stat(&self, _context: &StatContext<'_>, _stat: &UploadStat) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// Called once a bundle has been written to the database.
+    async fn bundle_applied(&self, _bundle: &GameStatsBundle) {}
+}
+
+/// The processors that are run on uploads, in the order they were registered.
+#[derive(Clone)]
+pub struct Processors {
+    processors: Arc<Vec<Box<dyn StatProcessor>>>,
+}
+
+impl Processors {
+    /// Creates the configured WASM plugins and scripts and the built-in processors, followed by any from
+    /// [extensions].
+    pub fn new(config: &Config, database: Address<StoreHandler>, metrics: Metrics, live: LiveFeed, cache: ResultCache) -> anyhow::Result<Self> {
+        let mut processors: Vec<Box<dyn StatProcessor>> = Vec::new();
+        // Plugins and scripts run first so that the stats they produce are validated like any other.
+        for path in &config.wasm_plugins.paths {
+            processors.push(Box::new(WasmPlugin::load(path, config.wasm_plugins.fuel, config.wasm_plugins.memory_bytes)?));
+            log::info!("Loaded WASM plugin {}", path.display());
+        }
+        if !config.scripts.is_empty() {
+            processors.push(Box::new(Scripts::load(&config.scripts)?));
+        }
+        processors.push(Box::new(Validation { config: config.clone() }));
+        processors.push(Box::new(AnomalyDetection { config: config.clone(), metrics: metrics.clone() }));
+        processors.push(Box::new(Milestones { config: config.clone(), database, metrics }));
+        processors.push(Box::new(cache));
+        processors.push(Box::new(live));
+        processors.extend(extensions(config));
+        Ok(Self { processors: Arc::new(processors) })
+    }
+
+    /// Runs every processor on the bundle and then on every stat in it, stopping at the first rejection.
+    pub fn process(&self, bundle: &mut GameStatsBundle) -> Result<(), BundleRejection> {
+        for processor in self.processors.iter() {
+            processor.transform(bundle).map_err(|reason| BundleRejection {
+                namespace: bundle.namespace.clone(),
+                reason,
+            })?;
+        }
+
+        let global = bundle.stats.global.iter()
+            .flatten()
+            .map(|(name, stat)| (None, name, stat));
+        let players = bundle.stats.players.iter()
+            .flat_map(|(player, stats)| stats.iter().map(move |(name, stat)| (Some(player), name, stat)));
+
+        for (player, name, stat) in global.chain(players) {
+            let context = StatContext {
+                server_name: &bundle.server_name,
+                namespace: &bundle.namespace,
+                player,
+                name,
+            };
+            for processor in self.processors.iter() {
+                processor.process_stat(&context, stat).map_err(|reason| BundleRejection {
+                    namespace: bundle.namespace.clone(),
+                    reason: format!("stat '{}': {}", name, reason),
+                })?;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn bundle_applied(&self, bundle: &GameStatsBundle) {
+        for processor in self.processors.iter() {
+            processor.bundle_applied(bundle).await;
+        }
+    }
+}
+
+/// Processors compiled into this build for network-specific logic. Add them here rather than changing how uploads
+/// are written.
+fn extensions(_config: &Config) -> Vec<Box<dyn StatProcessor>> {
+    Vec::new()
+}
+
+/// Rejects stats whose values can't be stored or are outside of their configured bounds. Their names are checked
+/// beforehand by [crate::validation].
+struct Validation {
+    config: Config,
+}
+
+impl StatProcessor for Validation {
+    fn process_stat(&self, context: &StatContext<'_>, stat: &UploadStat) -> Result<(), String> {
+        // Counts are stored as 32-bit values, like those of rolling averages uploaded one value at a time.
+        if stat.count() == 0 || stat.count() > i32::MAX as u32 {
+            return Err(format!("count must be between 1 and {}", i32::MAX));
+        }
+
+        let metadata = self.config.stat_metadata(context.namespace, context.name);
+        if stat.aggregation() == StatAggregation::Histogram && metadata.is_none_or(|metadata| metadata.buckets.is_empty()) {
+            return Err("histogram stats need buckets in their stat_metadata".to_string());
+        }
+
+        for value in stat.values() {
+            // serde accepts NaN and infinities, and a single one would poison every future average.
+            if !value.is_finite() {
+                return Err("value is not finite".to_string());
+            }
+
+            if let Some(metadata) = metadata {
+                let below_min = metadata.min.is_some_and(|min| value < min);
+                let above_max = metadata.max.is_some_and(|max| value > max);
+                if below_min || above_max {
+                    return Err(format!("value {} is out of bounds", value));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reports uploads that are accepted but unusually large, which may point to a buggy or compromised server.
+struct AnomalyDetection {
+    config: Config,
+    metrics: Metrics,
+}
+
+impl StatProcessor for AnomalyDetection {
+    fn process_stat(&self, context: &StatContext<'_>, stat: &UploadStat) -> Result<(), String> {
+        let threshold = self.config.stat_metadata(context.namespace, context.name)
+            .and_then(|metadata| metadata.anomaly_above);
+        if let Some(threshold) = threshold {
+            if let Some(value) = stat.values().into_iter().find(|value| *value > threshold) {
+                let owner = context.player.map(|player| format!("player {}", player)).unwrap_or_else(|| "global stats".to_string());
+                log::warn!("server '{}' uploaded anomalous value {} for stat '{}' of {} in {}",
+                        context.server_name, value, context.name, owner, context.namespace);
+                self.metrics.increment("nucleoid_stat_anomalies_total", &[("namespace", context.namespace), ("stat", context.name)]);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reports players whose totals pass one of the configured milestones of a stat.
+struct Milestones {
+    config: Config,
+    database: Address<StoreHandler>,
+    metrics: Metrics,
+}
+
+#[async_trait]
+impl StatProcessor for Milestones {
+    async fn bundle_applied(&self, bundle: &GameStatsBundle) {
+        let namespace_metadata = match self.config.stat_metadata.get(&bundle.namespace) {
+            Some(metadata) => metadata,
+            None => return,
+        };
+
+        for (player, stats) in &bundle.stats.players {
+            let candidates: Vec<_> = stats.iter()
+                .filter(|(_, stat)| stat.aggregation() == StatAggregation::Total)
+                .filter_map(|(name, stat)| {
+                    let milestones = &namespace_metadata.get(name)?.milestones;
+                    (!milestones.is_empty()).then(|| (name, stat.value(), milestones))
+                })
+                .collect();
+            if candidates.is_empty() {
+                continue;
+            }
+
+            let totals = match self.database.send(GetPlayerStats { uuid: *player, namespace: Some(bundle.namespace.clone()), season: None }).await {
+                Ok(Ok(Some(mut totals))) => totals.remove(&bundle.namespace).unwrap_or_default(),
+                Ok(Ok(None)) => continue,
+                Ok(Err(e)) => {
+                    log::warn!("failed to check milestones for {}: {}", player, e);
+                    continue;
+                }
+                Err(e) => {
+                    log::warn!("failed to check milestones for {}: {}", player, e);
+                    continue;
+                }
+            };
+
+            for (name, increment, milestones) in candidates {
+                let total = match totals.get(name) {
+                    Some(total) => *total,
+                    None => continue,
+                };
+                let previous = total - increment;
+                for milestone in milestones.iter().filter(|&&milestone| previous < milestone && milestone <= total) {
+                    log::info!("player {} reached {} of stat '{}' in {}", player, milestone, name, bundle.namespace);
+                    self.metrics.increment("nucleoid_stat_milestones_total", &[("namespace", &bundle.namespace), ("stat", name)]);
+                }
+            }
+        }
+    }
+}
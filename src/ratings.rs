@@ -0,0 +1,44 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use uuid::Uuid;
+
+use crate::config::RatingsConfig;
+use crate::model::{MatchTeam, RatingChange};
+
+/// Works out how a match changes the Elo ratings of its players, given their ratings before it. Players without a
+/// rating start at the configured initial rating.
+///
+/// Each team is rated by the mean rating of its players, and scored against every other team as a win, draw or loss
+/// by placement. A team's change is summed over those pairings and divided by the number of opponents, so that no
+/// match changes a rating by more than the K-factor, and every player of the team gets the same change.
+pub fn rate_match(config: &RatingsConfig, ratings: &HashMap<Uuid, f64>, teams: &[MatchTeam]) -> HashMap<Uuid, RatingChange> {
+    let rating = |uuid: &Uuid| ratings.get(uuid).copied().unwrap_or(config.initial_rating);
+    let team_ratings: Vec<f64> = teams.iter()
+        .map(|team| team.players.iter().map(rating).sum::<f64>() / team.players.len().max(1) as f64)
+        .collect();
+    let opponents = teams.len().saturating_sub(1).max(1) as f64;
+
+    let mut changes = HashMap::new();
+    for (i, team) in teams.iter().enumerate() {
+        let mut score = 0.0;
+        for (j, opponent) in teams.iter().enumerate() {
+            if i == j {
+                continue;
+            }
+            let expected = 1.0 / (1.0 + 10f64.powf((team_ratings[j] - team_ratings[i]) / 400.0));
+            let actual = match team.placement.cmp(&opponent.placement) {
+                Ordering::Less => 1.0,
+                Ordering::Equal => 0.5,
+                Ordering::Greater => 0.0,
+            };
+            score += actual - expected;
+        }
+
+        let change = config.k_factor * score / opponents;
+        for player in &team.players {
+            changes.insert(*player, RatingChange { rating: rating(player) + change, change });
+        }
+    }
+    changes
+}
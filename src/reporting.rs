@@ -0,0 +1,94 @@
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::config::Config;
+
+/// Longest error message included in an alert. Discord rejects embeds with longer descriptions.
+const MAX_ERROR_CHARS: usize = 1800;
+
+/// Red, as the sidebar colour of alert embeds.
+const ALERT_COLOR: u32 = 0xE74C3C;
+
+/// Posts alerts about problems that need someone's attention to a Discord webhook.
+#[derive(Clone)]
+pub struct Reporter {
+    http: reqwest::Client,
+    /// The config that the webhook is read from for each alert, so that it can be reloaded.
+    config: Config,
+}
+
+/// A problem to alert about.
+pub struct Alert {
+    pub title: String,
+    pub namespace: Option<String>,
+    pub player: Option<Uuid>,
+    pub error: String,
+}
+
+impl Reporter {
+    /// Creates a reporter for the configured webhook, which does nothing if there is none.
+    pub fn new(config: &Config) -> Self {
+        Self {
+            http: reqwest::Client::new(),
+            config: config.clone(),
+        }
+    }
+
+    /// Sends an alert in the background. Failures are logged rather than returned, so that reporting a problem never
+    /// affects the operation that ran into it.
+    pub fn report(&self, alert: Alert) {
+        let webhook_url = match self.config.webhook_url() {
+            Some(webhook_url) => webhook_url,
+            None => return,
+        };
+
+        let http = self.http.clone();
+        tokio::spawn(async move {
+            let result = http.post(&webhook_url)
+                .json(&alert.to_message())
+                .send().await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                log::warn!("failed to send alert '{}' to webhook: {}", alert.title, e);
+            }
+        });
+    }
+
+    /// Reports an error from handling a request, if server errors are configured to be reported.
+    pub fn report_server_error(&self, operation: &str, e: &anyhow::Error) {
+        if self.config.webhook_server_errors() {
+            self.report(Alert {
+                title: format!("Server error in {}", operation),
+                namespace: None,
+                player: None,
+                error: format!("{:#}", e),
+            });
+        }
+    }
+}
+
+impl Alert {
+    fn to_message(&self) -> serde_json::Value {
+        let mut fields = Vec::new();
+        if let Some(namespace) = &self.namespace {
+            fields.push(json!({"name": "Namespace", "value": namespace, "inline": true}));
+        }
+        if let Some(player) = &self.player {
+            fields.push(json!({"name": "Player", "value": player.to_string(), "inline": true}));
+        }
+
+        let mut error: String = self.error.chars().take(MAX_ERROR_CHARS).collect();
+        if error.len() < self.error.len() {
+            error.push_str("...");
+        }
+
+        json!({
+            "embeds": [{
+                "title": self.title,
+                "description": format!("```\n{}\n```", error),
+                "color": ALERT_COLOR,
+                "fields": fields,
+            }],
+        })
+    }
+}
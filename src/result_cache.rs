@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use async_trait::async_trait;
+use serde::Serialize;
+use tokio::time::Instant;
+use xtra::{Actor, Address, Context, Handler, Message};
+use xtra::spawn::Tokio;
+
+use crate::config::ResultCacheConfig;
+use crate::metrics::Metrics;
+use crate::model::{GameStatsBundle, LeaderboardOrder};
+use crate::processor::StatProcessor;
+
+/// An aggregation whose result can be cached.
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum CacheKey {
+    Leaderboard {
+        namespace: String,
+        stat: String,
+        limit: i64,
+        order: LeaderboardOrder,
+        season: Option<u32>,
+        include_private: bool,
+    },
+    GlobalStats(String),
+    GlobalStatsDelta {
+        namespace: String,
+        window_hours: u32,
+    },
+    StatSummary {
+        namespace: String,
+        stat: String,
+        season: Option<u32>,
+    },
+}
+
+impl CacheKey {
+    fn namespace(&self) -> &str {
+        match self {
+            CacheKey::Leaderboard { namespace, .. } => namespace,
+            CacheKey::GlobalStats(namespace) => namespace,
+            CacheKey::GlobalStatsDelta { namespace, .. } => namespace,
+            CacheKey::StatSummary { namespace, .. } => namespace,
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            CacheKey::Leaderboard { .. } => "leaderboard",
+            CacheKey::GlobalStats(_) => "global_stats",
+            CacheKey::GlobalStatsDelta { .. } => "global_stats_delta",
+            CacheKey::StatSummary { .. } => "stat_summary",
+        }
+    }
+
+    /// Whether the result is dropped when a bundle is applied to its namespace, rather than only when it expires.
+    fn invalidated_by_uploads(&self) -> bool {
+        !matches!(self, CacheKey::StatSummary { .. })
+    }
+}
+
+/// Memoizes the serialized results of expensive aggregations, such as leaderboards and global stats, until they
+/// expire or, except for stat summaries, a bundle is applied to their namespace.
+#[derive(Clone)]
+pub struct ResultCache {
+    /// The actor holding the entries, or `None` if caching is disabled.
+    address: Option<Address<CacheStore>>,
+    ttl: Option<Duration>,
+    stat_summary_ttl: Option<Duration>,
+    metrics: Metrics,
+}
+
+impl ResultCache {
+    /// Starts the actor that holds cached results, unless no TTL is configured.
+    pub fn spawn(config: &ResultCacheConfig, metrics: Metrics) -> Self {
+        let ttl = config.ttl_secs.map(Duration::from_secs);
+        let stat_summary_ttl = config.stat_summary_ttl_secs.map(Duration::from_secs);
+        let address = (ttl.is_some() || stat_summary_ttl.is_some()).then(|| {
+            CacheStore {
+                max_entries: config.max_entries,
+                entries: HashMap::new(),
+                generations: HashMap::new(),
+            }.create(None).spawn(&mut Tokio::Global)
+        });
+        Self { address, ttl, stat_summary_ttl, metrics }
+    }
+
+    fn ttl(&self, key: &CacheKey) -> Option<Duration> {
+        match key {
+            CacheKey::StatSummary { .. } => self.stat_summary_ttl,
+            _ => self.ttl,
+        }
+    }
+
+    /// Returns the cached result for `key`, or runs `fetch` and caches its result. Results of `None` are passed on
+    /// without being cached.
+    pub async fn get_or_fetch<T, F>(&self, key: CacheKey, fetch: F) -> anyhow::Result<Option<Arc<serde_json::Value>>>
+        where T: Serialize, F: Future<Output = anyhow::Result<Option<T>>> {
+        let (address, ttl) = match (&self.address, self.ttl(&key)) {
+            (Some(address), Some(ttl)) => (address, ttl),
+            _ => return Ok(fetch.await?.map(serde_json::to_value).transpose()?.map(Arc::new)),
+        };
+
+        // If the actor has stopped, results are fetched every time rather than failing requests.
+        let generation = match address.send(GetCached(key.clone())).await {
+            Ok(CacheLookup::Hit(value)) => {
+                self.metrics.increment("nucleoid_result_cache_requests_total", &[("kind", key.kind()), ("result", "hit")]);
+                return Ok(Some(value));
+            }
+            Ok(CacheLookup::Miss { generation }) => Some(generation),
+            Err(_) => None,
+        };
+        self.metrics.increment("nucleoid_result_cache_requests_total", &[("kind", key.kind()), ("result", "miss")]);
+
+        let value = match fetch.await? {
+            Some(value) => Arc::new(serde_json::to_value(value)?),
+            None => return Ok(None),
+        };
+        if let Some(generation) = generation {
+            let _ = address.send(StoreCached { key, generation, ttl, value: value.clone() }).await;
+        }
+        Ok(Some(value))
+    }
+
+    /// Drops every cached result for a namespace, including any that are still being fetched.
+    pub async fn invalidate(&self, namespace: &str) {
+        self.send_invalidation(namespace, false).await;
+    }
+
+    async fn send_invalidation(&self, namespace: &str, upload: bool) {
+        if let Some(address) = &self.address {
+            let _ = address.send(InvalidateNamespace { namespace: namespace.to_string(), upload }).await;
+        }
+    }
+}
+
+#[async_trait]
+impl StatProcessor for ResultCache {
+    async fn bundle_applied(&self, bundle: &GameStatsBundle) {
+        self.send_invalidation(&bundle.namespace, true).await;
+    }
+}
+
+struct CacheEntry {
+    value: Arc<serde_json::Value>,
+    expires_at: Instant,
+}
+
+struct CacheStore {
+    max_entries: usize,
+    entries: HashMap<CacheKey, CacheEntry>,
+    /// How many times each namespace has been invalidated, so that results fetched before an invalidation are not
+    /// stored after it.
+    generations: HashMap<String, Generation>,
+}
+
+impl CacheStore {
+    fn generation(&self, namespace: &str) -> Generation {
+        self.generations.get(namespace).copied().unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+struct Generation {
+    /// Invalidations by anything, including uploads.
+    all: u64,
+    /// Invalidations by changes other than uploads, which also drop the results that uploads don't.
+    edits: u64,
+}
+
+impl Actor for CacheStore {}
+
+enum CacheLookup {
+    Hit(Arc<serde_json::Value>),
+    /// The result isn't cached, and can be stored if the namespace is still at `generation` once it is fetched.
+    Miss { generation: Generation },
+}
+
+struct GetCached(CacheKey);
+
+impl Message for GetCached {
+    type Result = CacheLookup;
+}
+
+#[async_trait]
+impl Handler<GetCached> for CacheStore {
+    async fn handle(&mut self, message: GetCached, _ctx: &mut Context<Self>) -> CacheLookup {
+        match self.entries.get(&message.0) {
+            Some(entry) if entry.expires_at > Instant::now() => return CacheLookup::Hit(entry.value.clone()),
+            Some(_) => {
+                self.entries.remove(&message.0);
+            }
+            None => (),
+        }
+        CacheLookup::Miss { generation: self.generation(message.0.namespace()) }
+    }
+}
+
+struct StoreCached {
+    key: CacheKey,
+    generation: Generation,
+    ttl: Duration,
+    value: Arc<serde_json::Value>,
+}
+
+impl Message for StoreCached {
+    type Result = ();
+}
+
+#[async_trait]
+impl Handler<StoreCached> for CacheStore {
+    async fn handle(&mut self, message: StoreCached, _ctx: &mut Context<Self>) {
+        let current = self.generation(message.key.namespace());
+        let stale = match message.key.invalidated_by_uploads() {
+            true => message.generation != current,
+            false => message.generation.edits != current.edits,
+        };
+        if stale {
+            return;
+        }
+
+        let now = Instant::now();
+        if self.entries.len() >= self.max_entries {
+            self.entries.retain(|_, entry| entry.expires_at > now);
+            // Once full of live entries, new results aren't cached until some of them expire.
+            if self.entries.len() >= self.max_entries {
+                return;
+            }
+        }
+        self.entries.insert(message.key, CacheEntry { value: message.value, expires_at: now + message.ttl });
+    }
+}
+
+struct InvalidateNamespace {
+    namespace: String,
+    /// Whether a bundle was applied, which leaves the results that aren't [invalidated by
+    /// uploads](CacheKey::invalidated_by_uploads).
+    upload: bool,
+}
+
+impl Message for InvalidateNamespace {
+    type Result = ();
+}
+
+#[async_trait]
+impl Handler<InvalidateNamespace> for CacheStore {
+    async fn handle(&mut self, message: InvalidateNamespace, _ctx: &mut Context<Self>) {
+        let InvalidateNamespace { namespace, upload } = message;
+        self.entries.retain(|key, _| key.namespace() != namespace || (upload && !key.invalidated_by_uploads()));
+        let generation = self.generations.entry(namespace).or_default();
+        generation.all += 1;
+        if !upload {
+            generation.edits += 1;
+        }
+    }
+}
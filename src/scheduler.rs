@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::Context;
+use bson::oid::ObjectId;
+use async_trait::async_trait;
+use chrono::Utc;
+use cron::Schedule;
+use rand::Rng;
+use thiserror::Error;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+use xtra::Address;
+
+use crate::config::Config;
+use crate::store::{FinishJobRun, StoreHandler, StartJobRun};
+use crate::lease::Leases;
+use crate::model::{JobOutcome, JobRun, JobTrigger};
+
+/// A periodic background task.
+#[async_trait]
+pub trait Job: Send + Sync {
+    /// Runs the job, returning the number of items it processed.
+    async fn run(&self) -> anyhow::Result<u64>;
+}
+
+/// Runs registered jobs on their cron schedules.
+///
+/// Every instance schedules every job, but each run only happens on the instance that takes the job's lease, which is
+/// held until the next scheduled run so that instances that wake up slightly later skip it. Jobs registered with
+/// [Scheduler::register_local] work on the instance's own state, so they run on every instance without a lease.
+pub struct Scheduler {
+    config: Config,
+    database: Address<StoreHandler>,
+    jobs: Vec<ScheduledJob>,
+}
+
+struct ScheduledJob {
+    name: &'static str,
+    expression: String,
+    schedule: Schedule,
+    jitter: Duration,
+    job: Arc<dyn Job>,
+    /// Held while the job runs, so that a run is skipped if the previous one hasn't finished.
+    running: Arc<Mutex<()>>,
+    /// Whether the job runs on every instance, rather than on the one that takes its lease.
+    local: bool,
+}
+
+impl Scheduler {
+    pub fn new(config: &Config, database: Address<StoreHandler>) -> Self {
+        Self {
+            config: config.clone(),
+            database,
+            jobs: Vec::new(),
+        }
+    }
+
+    /// Registers a job to run on `default_schedule`, unless the config overrides it or disables the job.
+    pub fn register(&mut self, name: &'static str, default_schedule: &str, job: impl Job + 'static) -> anyhow::Result<()> {
+        self.add(name, default_schedule, job, false)
+    }
+
+    /// Registers a job like [Self::register], but to run on every instance rather than on one of them.
+    pub fn register_local(&mut self, name: &'static str, default_schedule: &str, job: impl Job + 'static) -> anyhow::Result<()> {
+        self.add(name, default_schedule, job, true)
+    }
+
+    fn add(&mut self, name: &'static str, default_schedule: &str, job: impl Job + 'static, local: bool) -> anyhow::Result<()> {
+        let config = self.config.jobs.get(name);
+        if config.is_some_and(|config| !config.enabled) {
+            log::info!("Job '{}' is disabled", name);
+            return Ok(());
+        }
+
+        let expression = config.and_then(|config| config.schedule.as_deref()).unwrap_or(default_schedule);
+        let schedule = Schedule::from_str(expression)
+            .map_err(|e| anyhow::anyhow!("{}", e))
+            .with_context(|| format!("invalid schedule '{}' for job '{}'", expression, name))?;
+
+        self.jobs.push(ScheduledJob {
+            name,
+            expression: expression.to_string(),
+            schedule,
+            jitter: Duration::from_secs(config.map(|config| config.jitter_secs).unwrap_or(0)),
+            job: Arc::new(job),
+            running: Arc::new(Mutex::new(())),
+            local,
+        });
+        Ok(())
+    }
+
+    /// Starts running every registered job on its schedule, returning a handle to run them on demand.
+    pub fn start(self) -> Jobs {
+        let leases = Leases::new(self.database.clone());
+        let mut jobs = HashMap::new();
+        for job in self.jobs {
+            let job = Arc::new(job);
+            tokio::spawn(run_schedule(job.clone(), leases.clone(), self.database.clone()));
+            jobs.insert(job.name, job);
+        }
+        Jobs {
+            jobs: Arc::new(jobs),
+            database: self.database,
+        }
+    }
+}
+
+/// The registered jobs, for running them outside of their schedules.
+#[derive(Clone)]
+pub struct Jobs {
+    jobs: Arc<HashMap<&'static str, Arc<ScheduledJob>>>,
+    database: Address<StoreHandler>,
+}
+
+/// A registered job, as shown by the admin API.
+#[derive(Debug)]
+pub struct JobInfo {
+    pub name: &'static str,
+    pub schedule: String,
+    /// Whether the job is running on this instance.
+    pub running: bool,
+}
+
+#[derive(Error, Debug)]
+pub enum RunJobError {
+    #[error("no job is registered with that name")]
+    NotFound,
+    #[error("the job is already running")]
+    AlreadyRunning,
+}
+
+impl Jobs {
+    /// Starts a run of the named job straight away, returning the id of the run.
+    ///
+    /// Unlike scheduled runs this doesn't take the job's lease, so it runs even if another instance ran the job
+    /// recently.
+    pub fn run_now(&self, name: &str) -> Result<String, RunJobError> {
+        let job = self.jobs.get(name).ok_or(RunJobError::NotFound)?;
+        let running = job.running.clone().try_lock_owned().map_err(|_| RunJobError::AlreadyRunning)?;
+        Ok(spawn_run(job, running, JobTrigger::Manual, self.database.clone()))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.jobs.contains_key(name)
+    }
+
+    /// Lists the registered jobs, sorted by name.
+    pub fn list(&self) -> Vec<JobInfo> {
+        let mut jobs: Vec<_> = self.jobs.values()
+            .map(|job| JobInfo {
+                name: job.name,
+                schedule: job.expression.clone(),
+                running: job.running.try_lock().is_err(),
+            })
+            .collect();
+        jobs.sort_by_key(|job| job.name);
+        jobs
+    }
+}
+
+/// Runs a job in the background and records the run in the database, returning the id of the run.
+fn spawn_run(job: &ScheduledJob, running: OwnedMutexGuard<()>, trigger: JobTrigger, database: Address<StoreHandler>) -> String {
+    let id = ObjectId::new();
+    let name = job.name;
+    let task = job.job.clone();
+    tokio::spawn(async move {
+        let _running = running;
+        log::debug!("running job '{}' ({})", name, id);
+
+        let run = JobRun {
+            id,
+            job: name.to_string(),
+            trigger,
+            started_at: bson::DateTime::now(),
+            finished_at: None,
+            outcome: JobOutcome::Running,
+            items_processed: None,
+            error: None,
+        };
+        // A job still runs if its run can't be recorded, as the database may be why it needs to run.
+        if let Err(e) = database.send(StartJobRun(run)).await.map_err(anyhow::Error::from).and_then(|res| res) {
+            log::warn!("failed to record start of job '{}' ({}): {}", name, id, e);
+        }
+
+        let result = task.run().await.map_err(|e| e.to_string());
+        match &result {
+            Ok(items_processed) => log::debug!("job '{}' ({}) processed {} items", name, id, items_processed),
+            Err(e) => log::warn!("job '{}' ({}) failed: {}", name, id, e),
+        }
+
+        if let Err(e) = database.send(FinishJobRun { id, result }).await.map_err(anyhow::Error::from).and_then(|res| res) {
+            log::warn!("failed to record end of job '{}' ({}): {}", name, id, e);
+        }
+    });
+    id.to_hex()
+}
+
+async fn run_schedule(job: Arc<ScheduledJob>, leases: Leases, database: Address<StoreHandler>) {
+    // The next run is found from the current time on each iteration, so runs missed while the process was busy are
+    // skipped rather than run back to back.
+    while let Some(next) = job.schedule.upcoming(Utc).next() {
+        let delay = (next - Utc::now()).to_std().unwrap_or_default();
+        let jitter = Duration::from_millis(rand::thread_rng().gen_range(0..=job.jitter.as_millis() as u64));
+        tokio::time::sleep(delay + jitter).await;
+
+        // The lease lasts until the following run, so that this run happens on only one instance.
+        if !job.local && !acquire_lease(&job, &leases, &next).await {
+            continue;
+        }
+
+        let running = match job.running.clone().try_lock_owned() {
+            Ok(running) => running,
+            Err(_) => {
+                log::warn!("skipping job '{}' as its previous run hasn't finished", job.name);
+                continue;
+            }
+        };
+        spawn_run(&job, running, JobTrigger::Schedule, database.clone());
+    }
+}
+
+/// Takes the lease for a scheduled run of a job, returning whether this instance should run it.
+async fn acquire_lease(job: &ScheduledJob, leases: &Leases, next: &chrono::DateTime<Utc>) -> bool {
+    let until_next = job.schedule.after(next).next()
+        .and_then(|following| (following - Utc::now()).to_std().ok())
+        .unwrap_or_default()
+        .max(Duration::from_secs(1));
+    match leases.try_acquire(&format!("job:{}", job.name), until_next).await {
+        Ok(true) => true,
+        Ok(false) => {
+            log::debug!("skipping job '{}' as another instance is running it", job.name);
+            false
+        }
+        Err(e) => {
+            log::warn!("failed to acquire lease for job '{}': {}", job.name, e);
+            false
+        }
+    }
+}
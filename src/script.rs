@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use anyhow::Context;
+use rhai::{Dynamic, Engine, Scope, AST};
+
+use crate::model::{GameStatsBundle, UploadStat};
+use crate::processor::StatProcessor;
+
+/// Largest number of operations that a single script call may perform.
+const MAX_OPERATIONS: u64 = 100_000;
+
+/// Rhai scripts configured per namespace, which can validate uploaded stats and derive new ones from them.
+///
+/// A script can define either or both of:
+/// - `validate(name, value)`: called for each uploaded stat. Returning `false` or a string rejects the upload.
+/// - `derive(stats)`: called with a map of each player's uploaded stats, and the global stats. Returns a map of stats
+///   to add, in the same format. Stats that were uploaded are never replaced.
+///
+/// Scripts are reloaded when their file changes. If a changed script fails to compile, the previous version is kept.
+pub struct Scripts {
+    engine: Engine,
+    scripts: HashMap<String, Script>,
+}
+
+struct Script {
+    path: PathBuf,
+    loaded: RwLock<LoadedScript>,
+}
+
+struct LoadedScript {
+    modified: SystemTime,
+    ast: Arc<AST>,
+}
+
+impl Scripts {
+    pub fn load(paths: &HashMap<String, PathBuf>) -> anyhow::Result<Self> {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        engine.set_max_call_levels(32);
+        engine.set_max_string_size(4096);
+        engine.set_max_array_size(4096);
+        engine.set_max_map_size(4096);
+
+        let mut scripts = HashMap::new();
+        for (namespace, path) in paths {
+            let loaded = compile(&engine, path)?;
+            log::info!("Loaded script {} for {}", path.display(), namespace);
+            scripts.insert(namespace.clone(), Script {
+                path: path.clone(),
+                loaded: RwLock::new(loaded),
+            });
+        }
+        Ok(Self { engine, scripts })
+    }
+
+    /// Gets the current version of a namespace's script, reloading it first if its file has changed.
+    fn script(&self, namespace: &str) -> Option<Arc<AST>> {
+        let script = self.scripts.get(namespace)?;
+        let modified = std::fs::metadata(&script.path).and_then(|metadata| metadata.modified()).ok();
+
+        let loaded = script.loaded.read().unwrap();
+        if modified.is_none_or(|modified| modified == loaded.modified) {
+            return Some(loaded.ast.clone());
+        }
+        drop(loaded);
+
+        let mut loaded = script.loaded.write().unwrap();
+        match compile(&self.engine, &script.path) {
+            Ok(reloaded) => {
+                log::info!("Reloaded script {} for {}", script.path.display(), namespace);
+                *loaded = reloaded;
+            }
+            Err(e) => {
+                log::warn!("failed to reload script for {}, keeping the previous version: {:#}", namespace, e);
+                // Not retried until the file changes again.
+                loaded.modified = modified.unwrap();
+            }
+        }
+        Some(loaded.ast.clone())
+    }
+
+    fn validate(&self, ast: &AST, stats: &HashMap<String, UploadStat>) -> Result<(), String> {
+        // Each value of a histogram is validated on its own.
+        for (name, value) in stats.iter().flat_map(|(name, stat)| stat.values().into_iter().map(move |value| (name, value))) {
+            let result = self.engine.call_fn::<Dynamic>(&mut Scope::new(), ast, "validate", (name.clone(), value))
+                .map_err(|e| format!("validation script failed for stat '{}': {}", name, e))?;
+            if let Some(reason) = result.clone().try_cast::<String>() {
+                return Err(format!("stat '{}': {}", name, reason));
+            }
+            if result.as_bool() == Ok(false) {
+                return Err(format!("stat '{}' failed validation", name));
+            }
+        }
+        Ok(())
+    }
+
+    fn derive(&self, ast: &AST, stats: &mut HashMap<String, UploadStat>) -> Result<(), String> {
+        let input = rhai::serde::to_dynamic(&*stats).map_err(|e| e.to_string())?;
+        let output = self.engine.call_fn::<Dynamic>(&mut Scope::new(), ast, "derive", (input,))
+            .map_err(|e| format!("derivation script failed: {}", e))?;
+        let derived: HashMap<String, UploadStat> = rhai::serde::from_dynamic(&output)
+            .map_err(|e| format!("derivation script returned invalid stats: {}", e))?;
+
+        for (name, stat) in derived {
+            stats.entry(name).or_insert(stat);
+        }
+        Ok(())
+    }
+}
+
+impl StatProcessor for Scripts {
+    fn transform(&self, bundle: &mut GameStatsBundle) -> Result<(), String> {
+        let ast = match self.script(&bundle.namespace) {
+            Some(ast) => ast,
+            None => return Ok(()),
+        };
+        let has_validate = has_function(&ast, "validate");
+        let has_derive = has_function(&ast, "derive");
+
+        let global = bundle.stats.global.iter_mut();
+        let players = bundle.stats.players.values_mut();
+        for stats in global.chain(players) {
+            if has_validate {
+                self.validate(&ast, stats)?;
+            }
+            if has_derive {
+                self.derive(&ast, stats)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn compile(engine: &Engine, path: &Path) -> anyhow::Result<LoadedScript> {
+    let modified = std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("failed to read script {}", path.display()))?;
+    let ast = engine.compile_file(path.to_path_buf())
+        .map_err(|e| anyhow::anyhow!("failed to compile script {}: {}", path.display(), e))?;
+    Ok(LoadedScript { modified, ast: Arc::new(ast) })
+}
+
+fn has_function(ast: &AST, name: &str) -> bool {
+    ast.iter_functions().any(|function| function.name == name)
+}
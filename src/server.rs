@@ -0,0 +1,257 @@
+use std::convert::Infallible;
+use std::future::Future;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures::ready;
+use futures::stream::{FuturesUnordered, StreamExt};
+use hyper::header::HeaderValue;
+use hyper::server::accept::Accept;
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use hyper::service::{make_service_fn, service_fn, Service};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{AcquireError, OwnedSemaphorePermit, Semaphore};
+use tokio_rustls::server::TlsStream;
+use tracing::Instrument;
+use warp::filters::BoxedFilter;
+use warp::Reply;
+
+use crate::config::HttpConfig;
+use crate::logging::{RequestId, REQUEST_ID_HEADER};
+use crate::tls::TlsCertificate;
+
+/// How long a client has to complete the TLS handshake before its connection is closed.
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Serves the given routes with a hyper server configured by the `http` section of the config, until `shutdown`
+/// completes and all open connections are closed. Connections are accepted over HTTPS if `tls` is given.
+pub async fn serve(config: &HttpConfig, addr: SocketAddr, tls: Option<TlsCertificate>, routes: BoxedFilter<(Box<dyn Reply>,)>, shutdown: impl Future<Output = ()>) -> anyhow::Result<()> {
+    let mut incoming = AddrIncoming::bind(&addr)?;
+    incoming.set_keepalive(config.tcp_keepalive_secs.map(Duration::from_secs));
+    incoming.set_nodelay(true);
+    let https = tls.is_some();
+    let incoming = ServerIncoming {
+        inner: LimitedIncoming::new(incoming, config.max_connections),
+        tls,
+        handshakes: FuturesUnordered::new(),
+    };
+
+    let service = warp::service(routes);
+    let make_service = make_service_fn(move |connection: &Connection| {
+        let service = service.clone();
+        let remote_addr = RemoteAddr(connection.remote_addr());
+        async move {
+            Ok::<_, Infallible>(service_fn(move |mut request: hyper::Request<hyper::Body>| {
+                let request_id = RequestId::from_header(request.headers().get(REQUEST_ID_HEADER));
+                let span = tracing::info_span!("request", request_id = %request_id, method = %request.method(), path = %request.uri().path());
+                let header = HeaderValue::from_str(request_id.as_str()).expect("request IDs are valid header values");
+                request.extensions_mut().insert(remote_addr);
+                request.extensions_mut().insert(request_id);
+
+                let response = span.in_scope(|| service.clone().call(request));
+                async move {
+                    let mut response = response.await?;
+                    response.headers_mut().insert(REQUEST_ID_HEADER, header);
+                    Ok::<_, Infallible>(response)
+                }.instrument(span)
+            }))
+        }
+    });
+
+    let mut builder = hyper::Server::builder(incoming)
+        .http1_keepalive(config.keep_alive)
+        .http1_only(!config.http2);
+    if config.http2 {
+        builder = builder
+            .http2_keep_alive_interval(config.http2_keepalive_interval_secs.map(Duration::from_secs))
+            .http2_keep_alive_timeout(Duration::from_secs(config.http2_keepalive_timeout_secs));
+    }
+
+    log::info!("Listening on {} over {}", addr, if https { "HTTPS" } else { "HTTP" });
+    builder.serve(make_service).with_graceful_shutdown(shutdown).await?;
+    Ok(())
+}
+
+/// The address of the client that sent a request, added to the extensions of every request served by [serve]. Routes
+/// embedded in another server don't have it.
+#[derive(Clone, Copy, Debug)]
+pub struct RemoteAddr(pub SocketAddr);
+
+type HandshakeFuture = Pin<Box<dyn Future<Output = io::Result<Connection>> + Send>>;
+
+/// Accepts connections from a [LimitedIncoming], completing the TLS handshake of each first if HTTPS is enabled.
+/// Handshakes happen alongside each other, so that a slow client doesn't hold up the connections after it.
+struct ServerIncoming {
+    inner: LimitedIncoming,
+    tls: Option<TlsCertificate>,
+    handshakes: FuturesUnordered<HandshakeFuture>,
+}
+
+impl Accept for ServerIncoming {
+    type Conn = Connection;
+    type Error = io::Error;
+
+    fn poll_accept(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = &mut *self;
+        let mut closed = false;
+        loop {
+            match Pin::new(&mut this.inner).poll_accept(cx) {
+                Poll::Ready(Some(Ok(connection))) => match &this.tls {
+                    Some(tls) => {
+                        let handshake = tls.acceptor().accept(connection);
+                        this.handshakes.push(Box::pin(async move {
+                            match tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, handshake).await {
+                                Ok(stream) => Ok(Connection::Tls(Box::new(stream?))),
+                                Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")),
+                            }
+                        }));
+                    }
+                    None => return Poll::Ready(Some(Ok(Connection::Plain(connection)))),
+                },
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    closed = true;
+                    break;
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        loop {
+            match this.handshakes.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(connection))) => return Poll::Ready(Some(Ok(connection))),
+                // A failed handshake only affects its own client, so it isn't passed on to hyper as an accept error.
+                Poll::Ready(Some(Err(e))) => log::debug!("TLS handshake failed: {}", e),
+                Poll::Ready(None) if closed => return Poll::Ready(None),
+                Poll::Ready(None) | Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// A connection accepted by [serve], over TLS if HTTPS is enabled.
+enum Connection {
+    Plain(LimitedConnection),
+    Tls(Box<TlsStream<LimitedConnection>>),
+}
+
+impl Connection {
+    fn remote_addr(&self) -> SocketAddr {
+        match self {
+            Connection::Plain(connection) => connection.stream.remote_addr(),
+            Connection::Tls(stream) => stream.get_ref().0.stream.remote_addr(),
+        }
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        match &mut *self {
+            Connection::Plain(connection) => Pin::new(connection).poll_read(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        match &mut *self {
+            Connection::Plain(connection) => Pin::new(connection).poll_write(cx, buf),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut *self {
+            Connection::Plain(connection) => Pin::new(connection).poll_flush(cx),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut *self {
+            Connection::Plain(connection) => Pin::new(connection).poll_shutdown(cx),
+            Connection::Tls(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+}
+
+type AcquireFuture = Pin<Box<dyn Future<Output = Result<OwnedSemaphorePermit, AcquireError>> + Send>>;
+
+/// Accepts connections from an [AddrIncoming], waiting for an open connection to close once the limit is reached.
+struct LimitedIncoming {
+    inner: AddrIncoming,
+    semaphore: Option<Arc<Semaphore>>,
+    acquire: Option<AcquireFuture>,
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl LimitedIncoming {
+    fn new(inner: AddrIncoming, max_connections: Option<usize>) -> Self {
+        Self {
+            inner,
+            semaphore: max_connections.map(|max| Arc::new(Semaphore::new(max))),
+            acquire: None,
+            permit: None,
+        }
+    }
+}
+
+impl Accept for LimitedIncoming {
+    type Conn = LimitedConnection;
+    type Error = io::Error;
+
+    fn poll_accept(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = &mut *self;
+
+        if let (Some(semaphore), None) = (&this.semaphore, &this.permit) {
+            let acquire = this.acquire.get_or_insert_with(|| Box::pin(semaphore.clone().acquire_owned()));
+            match acquire.as_mut().poll(cx) {
+                Poll::Ready(permit) => {
+                    this.acquire = None;
+                    this.permit = Some(permit.expect("connection semaphore is never closed"));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        Poll::Ready(match ready!(Pin::new(&mut this.inner).poll_accept(cx)) {
+            Some(Ok(stream)) => Some(Ok(LimitedConnection {
+                stream,
+                _permit: this.permit.take(),
+            })),
+            Some(Err(e)) => Some(Err(e)),
+            None => None,
+        })
+    }
+}
+
+/// A connection that releases its slot in the connection limit when it is closed.
+struct LimitedConnection {
+    stream: AddrStream,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+impl AsyncRead for LimitedConnection {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for LimitedConnection {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::Notify;
+use tokio::time::Instant;
+
+use crate::model::GameStatsBundle;
+
+/// Waits for the process to be asked to shut down, with either Ctrl+C or `SIGTERM`.
+pub async fn signal() {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+            .expect("failed to listen for SIGTERM");
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => (),
+            _ = terminate.recv() => (),
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+/// Keeps track of stats uploads that are being written to the database, so that shutdown can wait for them.
+#[derive(Clone, Default)]
+pub struct UploadTracker {
+    state: Arc<Mutex<TrackerState>>,
+    idle: Arc<Notify>,
+}
+
+#[derive(Default)]
+struct TrackerState {
+    closed: bool,
+    next_id: u64,
+    in_flight: HashMap<u64, AbandonedUpload>,
+}
+
+/// An upload that hadn't been written when shutdown stopped waiting for it.
+#[derive(Clone)]
+pub struct AbandonedUpload {
+    pub bundle: GameStatsBundle,
+    /// Whether the upload had started being written, so that some of its stats may have been.
+    pub started: bool,
+}
+
+/// An upload that is being written, which is removed from its [UploadTracker] when dropped.
+pub struct TrackedUpload {
+    id: u64,
+    tracker: UploadTracker,
+}
+
+impl UploadTracker {
+    /// Starts tracking an upload that will be written later, returning `None` if uploads are no longer accepted. It
+    /// must be [started](TrackedUpload::start) before it is written.
+    pub fn begin(&self, bundle: GameStatsBundle) -> Option<TrackedUpload> {
+        self.track(bundle, false)
+    }
+
+    /// Starts tracking an upload that is being written straight away, returning `None` if uploads are no longer
+    /// accepted.
+    pub fn begin_started(&self, bundle: GameStatsBundle) -> Option<TrackedUpload> {
+        self.track(bundle, true)
+    }
+
+    fn track(&self, bundle: GameStatsBundle, started: bool) -> Option<TrackedUpload> {
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return None;
+        }
+        let id = state.next_id;
+        state.next_id += 1;
+        state.in_flight.insert(id, AbandonedUpload { bundle, started });
+        Some(TrackedUpload { id, tracker: self.clone() })
+    }
+
+    /// Stops accepting new uploads.
+    pub fn close(&self) {
+        self.state.lock().unwrap().closed = true;
+    }
+
+    /// Waits until all uploads have been written or the deadline passes, returning any that were not.
+    pub async fn drain(&self, deadline: Instant) -> Vec<AbandonedUpload> {
+        loop {
+            let idle = self.idle.notified();
+            {
+                let state = self.state.lock().unwrap();
+                if state.in_flight.is_empty() {
+                    return Vec::new();
+                }
+            }
+            if tokio::time::timeout_at(deadline, idle).await.is_err() {
+                // Taken out of the tracker so that uploads that haven't started, such as queued ones, can't be started
+                // once they have been handed over to be spooled.
+                return self.state.lock().unwrap().in_flight.drain().map(|(_, upload)| upload).collect();
+            }
+        }
+    }
+}
+
+impl TrackedUpload {
+    /// Marks the upload as being written to the database, after which it may be partly applied if it is abandoned.
+    /// Returns `false` if it was already abandoned by shutdown, in which case it mustn't be written.
+    #[must_use]
+    pub fn start(&self) -> bool {
+        match self.tracker.state.lock().unwrap().in_flight.get_mut(&self.id) {
+            Some(upload) => {
+                upload.started = true;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl Drop for TrackedUpload {
+    fn drop(&mut self) {
+        let mut state = self.tracker.state.lock().unwrap();
+        state.in_flight.remove(&self.id);
+        if state.in_flight.is_empty() {
+            self.tracker.idle.notify_waiters();
+        }
+    }
+}
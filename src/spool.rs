@@ -0,0 +1,112 @@
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use xtra::Address;
+
+use crate::store::{Ping, StoreHandler, UploadStatsBundle};
+use crate::metrics::Metrics;
+use crate::model::GameStatsBundle;
+
+/// A directory of accepted stats bundles that have not been written to the database yet, each stored as a JSON file.
+#[derive(Clone)]
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+
+    /// Writes a bundle to the spool, returning the path of its file.
+    pub async fn write(&self, bundle: &GameStatsBundle) -> anyhow::Result<PathBuf> {
+        tokio::fs::create_dir_all(&self.dir).await?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let name = format!("{}-{:016x}", timestamp, rand::random::<u64>());
+        let path = self.dir.join(format!("{}.json", name));
+        let partial = self.dir.join(format!("{}.partial", name));
+
+        // Written under another name first so that a crash never leaves a truncated bundle behind.
+        tokio::fs::write(&partial, serde_json::to_vec(bundle)?).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(path)
+    }
+
+    /// Removes bundles that were still being written when the process stopped. Only safe before anything else is
+    /// writing to the spool, i.e. on startup.
+    pub async fn remove_partial_files(&self) -> anyhow::Result<()> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some("partial") {
+                log::warn!("removing partially written spool file {}", path.display());
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes every spooled bundle to the database, oldest first, returning how many were written. Bundles that fail
+    /// are renamed with a `.failed` extension so that they are kept for inspection but not replayed again, unless the
+    /// database can't be reached, in which case they are left for the next replay.
+    pub async fn replay(&self, database: &Address<StoreHandler>, metrics: &Metrics) -> anyhow::Result<u64> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut bundles = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some("json") {
+                bundles.push(path);
+            }
+        }
+        if bundles.is_empty() {
+            return Ok(0);
+        }
+        bundles.sort();
+
+        log::info!("Replaying {} spooled bundles from {}", bundles.len(), self.dir.display());
+        let mut replayed = 0;
+        let mut failed = 0;
+        for path in &bundles {
+            match replay_bundle(database, path).await {
+                Ok(()) => {
+                    tokio::fs::remove_file(path).await?;
+                    metrics.increment("nucleoid_spool_replayed_total", &[]);
+                    replayed += 1;
+                }
+                Err(e) if !reachable(database).await => {
+                    log::warn!("stopped replaying spooled bundles as the database can't be reached: {}", e);
+                    break;
+                }
+                Err(e) => {
+                    log::error!("failed to replay spooled bundle {}: {}", path.display(), e);
+                    tokio::fs::rename(path, path.with_extension("failed")).await?;
+                    metrics.increment("nucleoid_spool_replay_failures_total", &[]);
+                    failed += 1;
+                }
+            }
+        }
+        log::info!("Replayed {} spooled bundles, {} failed", replayed, failed);
+        Ok(replayed)
+    }
+}
+
+async fn replay_bundle(database: &Address<StoreHandler>, path: &Path) -> anyhow::Result<()> {
+    let bundle: GameStatsBundle = serde_json::from_slice(&tokio::fs::read(path).await?)?;
+    // A bundle that was applied before it was spooled is only skipped here, as there is nothing left to do with it.
+    database.send(UploadStatsBundle(bundle)).await??;
+    Ok(())
+}
+
+async fn reachable(database: &Address<StoreHandler>) -> bool {
+    matches!(database.send(Ping).await, Ok(Ok(())))
+}
@@ -0,0 +1,1884 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use bson::Document;
+use bson::oid::ObjectId;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+use tracing::Instrument;
+use uuid::Uuid;
+use xtra::{Actor, Address, Context, Handler, Message};
+
+use crate::config::{Config, DatabaseRetryConfig, DatabaseType, StatStorage};
+use crate::database::MongoDatabaseHandler;
+use crate::memory::MemoryDatabaseHandler;
+use crate::model::{AchievementInfo, AchievementUnlocks, AggregateRebuildReport, MatchTeam, RatingChange, StoredLeaderboardSnapshot, StoredRating, CorruptDocument, Game, GameStat, GameStatsBundle, HistogramBucket, JobRun, LeaderboardEntry, LeaderboardOrder, NamespaceDeletionReport, NamespaceMergeReport, PlayerAnonymizationReport, PlayerDeletionReport, PlayerProfile, PlayerStatsResponse, PreviousUsername, RebuildMode, StoredAchievement, StoredPlaytime, ServerStats, StatRank, StatSummaryResponse, StatConversionReport, StatDeletionReport, StatRenameReport, UploadLogEntry, StatInfo, StatAggregation, StatSnapshot, StatType, StoredSeason, SuspiciousUpload, UploadStat};
+use crate::postgres::PostgresDatabaseHandler;
+use crate::reporting::Reporter;
+
+/// A backend that player profiles and stats are stored in.
+///
+/// The rest of the service talks to a store through the [StoreHandler] actor, which applies deadlines and retries to
+/// every operation, so implementations only need to perform them.
+#[async_trait]
+pub trait StatsStore: Send + Sync + 'static {
+    /// Checks that the backend can be reached.
+    async fn ping(&self) -> Result<()>;
+
+    /// Whether an error means that an operation never reached the backend, so that retrying it can't apply it twice.
+    fn is_transient_error(&self, _error: &anyhow::Error) -> bool {
+        false
+    }
+
+    async fn get_player_profile(&self, uuid: &Uuid) -> Result<Option<PlayerProfile>>;
+
+    /// Finds the player who most recently took a username, ignoring case.
+    async fn get_player_profile_by_name(&self, username: &str) -> Result<Option<PlayerProfile>>;
+
+    /// Lists a page of player profiles, starting after the `after` cursor.
+    async fn get_players(&self, message: GetPlayers) -> Result<Vec<PlayerProfile>>;
+
+    /// Finds the players whose current username starts with a prefix, ignoring case, ordered by username.
+    async fn search_players(&self, message: SearchPlayers) -> Result<Vec<PlayerProfile>>;
+
+    /// Creates a player's profile if it doesn't exist yet, and sets their username if one is given. A username that
+    /// is replaced is added to the player's username history.
+    async fn update_player_profile(&self, uuid: &Uuid, username: Option<String>) -> Result<PlayerProfile>;
+
+    async fn set_player_private(&self, uuid: &Uuid, private: bool) -> Result<()>;
+
+    /// Lists the usernames that a player changed away from, oldest first.
+    async fn get_username_history(&self, uuid: &Uuid) -> Result<Vec<PreviousUsername>>;
+
+    /// Adds a play session onto a player's playtime in a namespace, and records that the player was seen, creating
+    /// their profile if they don't have one yet.
+    async fn add_playtime(&self, uuid: &Uuid, namespace: &str, seconds: u64) -> Result<()>;
+
+    /// Gets a player's playtime in each namespace that they have played.
+    async fn get_playtime(&self, uuid: &Uuid) -> Result<Vec<StoredPlaytime>>;
+
+    /// Deletes a player's profile, stats, stat history and playtime and quarantined stats documents.
+    async fn delete_player_data(&self, uuid: &Uuid) -> Result<PlayerDeletionReport>;
+
+    /// Removes a player's username from their profile, their username history and saved leaderboards, and marks the
+    /// profile as anonymized, keeping their stats under their UUID only.
+    async fn anonymize_player(&self, uuid: &Uuid) -> Result<PlayerAnonymizationReport>;
+
+    /// Gets a player's stats in one or every namespace, from one season or, if `season` is `None`, of all time.
+    async fn get_player_stats(&self, uuid: &Uuid, namespace: &Option<String>, season: Option<u32>) -> Result<Option<PlayerStatsResponse>>;
+
+    /// Adds a bundle of stats onto the stored player and global stats, and counts it towards the stats of the server
+    /// that uploaded it. Fails with a [StatTypeMismatch] without changing anything if a stat would be stored as a
+    /// different type than it already is.
+    ///
+    /// A bundle with a `bundle_id` that has already been applied changes nothing, and is reported as a
+    /// [BundleOutcome::Duplicate].
+    async fn upload_stats_bundle(&self, bundle: GameStatsBundle) -> Result<BundleOutcome>;
+
+    /// Checks what uploading a bundle would do without writing anything, failing with a [StatTypeMismatch] if it
+    /// would be rejected.
+    async fn check_stats_bundle(&self, bundle: &GameStatsBundle) -> Result<BundleOutcome>;
+
+    /// Gets what a game server has uploaded to each namespace.
+    async fn get_server_stats(&self, server_name: &str) -> Result<Vec<ServerStats>>;
+
+    /// Records an accepted upload, dropping the oldest recorded uploads once there are more than the upload log keeps.
+    async fn log_upload(&self, entry: UploadLogEntry) -> Result<()>;
+
+    /// Gets the most recently accepted uploads, newest first.
+    async fn get_upload_log(&self, message: GetUploadLog) -> Result<Vec<UploadLogEntry>>;
+
+    /// Changes the type that a stat is stored as in every document of a namespace.
+    async fn convert_stat(&self, message: ConvertStat) -> Result<StatConversionReport>;
+
+    /// Renames stats in every document of a namespace, given a map of their current keys to their new keys.
+    ///
+    /// Stats are left under their old key in documents that already have a stat under the new key.
+    async fn rename_stats(&self, namespace: &str, stats: &HashMap<String, String>) -> Result<StatRenameReport>;
+
+    /// Moves all player and global stats from one namespace into another, combining them with any existing stats.
+    ///
+    /// Documents with a stat that is an average in one namespace but a total in the other are left untouched.
+    async fn merge_namespace(&self, from: &str, into: &str) -> Result<NamespaceMergeReport>;
+
+    /// Removes a stat from a namespace: from every player's stats in each season, the global stats and their rollups,
+    /// stat history and server stats, along with its leaderboard snapshots and registered info.
+    async fn delete_stat(&self, namespace: &str, stat: &str) -> Result<StatDeletionReport>;
+
+    /// Deletes everything stored for a namespace, apart from the bundle and upload logs.
+    async fn delete_namespace(&self, namespace: &str) -> Result<NamespaceDeletionReport>;
+
+    /// Lists quarantined documents, most recently quarantined first.
+    async fn get_corrupt_documents(&self, limit: i64) -> Result<Vec<CorruptDocument>>;
+
+    async fn get_corrupt_document(&self, id: ObjectId) -> Result<Option<CorruptDocument>>;
+
+    /// Merges a repaired version of a quarantined document into the collection it was taken from, adding its stats
+    /// onto any document that uploads have created since, and then removes it from quarantine.
+    async fn restore_corrupt_document(&self, id: ObjectId, repaired: Document) -> Result<RestoreOutcome>;
+
+    /// Keeps an upload that broke a stat's `max_increment` aside, without applying it.
+    async fn quarantine_upload(&self, upload: SuspiciousUpload) -> Result<()>;
+
+    /// Lists quarantined uploads, most recently received first.
+    async fn get_suspicious_uploads(&self, limit: i64) -> Result<Vec<SuspiciousUpload>>;
+
+    /// Removes an upload from quarantine, returning it if it was there.
+    async fn delete_suspicious_upload(&self, id: ObjectId) -> Result<Option<SuspiciousUpload>>;
+
+    async fn get_stat_info(&self, namespace: &str) -> Result<HashMap<String, StatInfo>>;
+
+    /// Registers the info of stats, replacing anything registered for them before.
+    async fn update_stat_info(&self, namespace: String, stats: HashMap<String, StatInfo>) -> Result<()>;
+
+    async fn get_achievement_info(&self, namespace: &str) -> Result<HashMap<String, AchievementInfo>>;
+
+    /// Registers the info of achievements, replacing anything registered for them before.
+    async fn update_achievement_info(&self, namespace: String, achievements: HashMap<String, AchievementInfo>) -> Result<()>;
+
+    /// Unlocks achievements for a player, returning those that they didn't have before. The player is recorded as
+    /// seen, and gets a profile if they don't have one yet.
+    async fn grant_achievements(&self, uuid: &Uuid, namespace: &str, achievements: &[String]) -> Result<Vec<String>>;
+
+    /// Lists the achievements that a player has unlocked in one or every namespace, most recently unlocked first.
+    async fn get_player_achievements(&self, uuid: &Uuid, namespace: Option<&str>) -> Result<Vec<StoredAchievement>>;
+
+    /// Counts the players who have unlocked each achievement of a namespace, and the players of the namespace.
+    async fn get_achievement_unlocks(&self, namespace: &str) -> Result<AchievementUnlocks>;
+
+    /// Updates the ratings of a match's players from its result, returning their new ratings. The players are
+    /// recorded as seen, and get a profile if they don't have one yet.
+    async fn record_match(&self, namespace: &str, teams: &[MatchTeam]) -> Result<HashMap<Uuid, RatingChange>>;
+
+    /// Gets a player's rating in each namespace that they have played rated matches in.
+    async fn get_player_ratings(&self, uuid: &Uuid) -> Result<Vec<StoredRating>>;
+
+    /// Ranks the players of a namespace by their rating, highest first.
+    async fn get_rating_leaderboard(&self, message: GetRatingLeaderboard) -> Result<Vec<LeaderboardEntry>>;
+
+    /// Lists every namespace that has player or global stats, in order.
+    async fn get_namespaces(&self) -> Result<Vec<String>>;
+
+    async fn get_global_stats(&self, namespace: &str) -> Result<Option<HashMap<String, f64>>>;
+
+    /// Sums the hourly rollups of a namespace's global stats over the given window.
+    ///
+    /// Rollups have a resolution of one hour, so the hour that the window starts in is always included.
+    async fn get_global_stats_delta(&self, namespace: &str, window_hours: u32) -> Result<HashMap<String, f64>>;
+
+    async fn insert_game(&self, game: Game) -> Result<()>;
+
+    async fn get_games(&self, message: GetGames) -> Result<Vec<Game>>;
+
+    /// Ranks the players of a namespace by the value of a stat, using the average for rolling averages.
+    async fn get_leaderboard(&self, message: GetLeaderboard) -> Result<Vec<LeaderboardEntry>>;
+
+    /// Finds where a player ranks among the players of a namespace by the value of a stat, counted the same way as
+    /// leaderboards. Returns `None` if the player doesn't have the stat.
+    async fn get_stat_rank(&self, message: GetStatRank) -> Result<Option<StatRank>>;
+
+    /// Summarizes the values of a stat across every player of a namespace, counted the same way as leaderboards.
+    /// Returns `None` if no player has the stat.
+    async fn get_stat_summary(&self, message: GetStatSummary) -> Result<Option<StatSummaryResponse>>;
+
+    /// Lists the recorded values of a player's stat, oldest first.
+    async fn get_stat_history(&self, message: GetStatHistory) -> Result<Vec<StatSnapshot>>;
+
+    /// Gets the buckets of a player's stat, or of a global stat if `uuid` is `None`, returning `None` if the stat
+    /// doesn't exist or isn't a histogram.
+    async fn get_stat_histogram(&self, message: GetStatHistogram) -> Result<Option<Vec<HistogramBucket>>>;
+
+    /// Gets the season that the stats of seasonal namespaces are added to. Until a season is started, it is season 1.
+    async fn get_current_season(&self) -> Result<StoredSeason>;
+
+    /// Ends the current season, so that the stats of seasonal namespaces are added to the next one from now on.
+    async fn start_season(&self) -> Result<StoredSeason>;
+
+    async fn prune_global_stats_rollups(&self) -> Result<u64>;
+
+    async fn prune_bundle_log(&self) -> Result<u64>;
+
+    async fn prune_stat_history(&self) -> Result<u64>;
+
+    async fn save_leaderboard_snapshot(&self, snapshot: StoredLeaderboardSnapshot) -> Result<()>;
+
+    /// Gets the latest snapshot of a leaderboard that was taken at or before `at`.
+    async fn get_leaderboard_snapshot(&self, message: GetLeaderboardSnapshot) -> Result<Option<StoredLeaderboardSnapshot>>;
+
+    async fn prune_leaderboard_snapshots(&self) -> Result<u64>;
+
+    /// Forgets the IDs of bundles that were applied before the retention period.
+    async fn prune_bundle_ids(&self) -> Result<u64>;
+
+    /// Replays logged bundles into rebuilt aggregates, then compares them with the live aggregates and, if asked to,
+    /// replaces the live aggregates with them.
+    async fn rebuild_aggregates(&self, message: RebuildAggregates) -> Result<AggregateRebuildReport>;
+
+    /// Takes the named lease for `holder`, or extends it if they already hold it. Returns `false` if another holder
+    /// has a lease that hasn't expired yet.
+    async fn acquire_lease(&self, name: &str, holder: &str, ttl: Duration) -> Result<bool>;
+
+    async fn release_lease(&self, name: &str, holder: &str) -> Result<()>;
+
+    async fn start_job_run(&self, run: &JobRun) -> Result<()>;
+
+    async fn finish_job_run(&self, id: ObjectId, result: &std::result::Result<u64, String>) -> Result<()>;
+
+    /// Gets the most recent runs of a job, or of every job, newest first.
+    async fn get_job_runs(&self, job: Option<&str>, limit: i64) -> Result<Vec<JobRun>>;
+
+    /// Describes what backups of the store are made of.
+    async fn backup_manifest(&self) -> Result<BackupManifest>;
+
+    /// Reads up to `limit` documents of a collection for a backup, continuing after `after`, which is the `next` of
+    /// the previous batch.
+    async fn export_documents(&self, collection: &str, after: Option<serde_json::Value>, limit: i64) -> Result<BackupBatch>;
+
+    /// Inserts documents from a backup into a collection, leaving out those that already exist. Returns how many
+    /// were inserted.
+    async fn import_documents(&self, collection: &str, documents: Vec<serde_json::Value>) -> Result<u64>;
+}
+
+/// The actor that handles messages for a [StatsStore]. Several handlers share the store and take messages from the
+/// same address, so that a slow operation only holds up the handler that is running it.
+pub struct StoreHandler {
+    store: Arc<dyn StatsStore>,
+    retry: DatabaseRetryConfig,
+    reporter: Reporter,
+    /// Whether the last operation reached the store, shared by every handler so that losing and regaining it is
+    /// logged once.
+    connected: Arc<AtomicBool>,
+}
+
+impl StoreHandler {
+    /// Connects to the store selected by `database_type` and starts the actors that handle messages for it.
+    pub async fn connect(config: &Config) -> Result<Address<Self>> {
+        Ok(match config.database_type {
+            DatabaseType::Mongodb => Self::spawn(MongoDatabaseHandler::connect(config).await?, config),
+            DatabaseType::Postgres => Self::spawn(PostgresDatabaseHandler::connect(config).await?, config),
+            DatabaseType::Memory => Self::spawn(MemoryDatabaseHandler::new(config), config),
+        })
+    }
+
+    /// Spawns `database_actors` handlers for a store, all taking messages from the returned address.
+    pub fn spawn(store: impl StatsStore, config: &Config) -> Address<Self> {
+        let store: Arc<dyn StatsStore> = Arc::new(store);
+        let connected = Arc::new(AtomicBool::new(true));
+        let handler = || Self {
+            store: store.clone(),
+            retry: config.database_retry.clone(),
+            reporter: Reporter::new(config),
+            connected: connected.clone(),
+        };
+
+        let (address, mut ctx) = Context::new(None);
+        for _ in 1..config.database_actors.max(1) {
+            tokio::spawn(ctx.attach(handler()));
+        }
+        tokio::spawn(ctx.run(handler()));
+        address
+    }
+
+    /// Handles a message, retrying it with backoff after errors that mean it never reached the store.
+    async fn handle_with_retries<M, T>(&mut self, message: M, ctx: &mut Context<Self>) -> Result<T>
+        where M: Message<Result = Result<T>> + Clone, T: Send + 'static, Self: Handler<M> {
+        let mut backoff = Duration::from_millis(self.retry.initial_backoff_ms);
+        let mut attempt = 1;
+        loop {
+            let result = <Self as Handler<M>>::handle(self, message.clone(), ctx).await;
+            let transient = match &result {
+                Err(e) => self.store.is_transient_error(e),
+                Ok(_) => false,
+            };
+            self.set_connected(!transient);
+
+            if !transient || attempt >= self.retry.max_attempts {
+                return result;
+            }
+            log::debug!("retrying database operation in {:?} after a transient error", backoff);
+            tokio::time::sleep(backoff).await;
+            backoff = (backoff * 2).min(Duration::from_millis(self.retry.max_backoff_ms));
+            attempt += 1;
+        }
+    }
+
+    fn set_connected(&self, connected: bool) {
+        if self.connected.swap(connected, Ordering::Relaxed) != connected {
+            if connected {
+                log::info!("reconnected to the database");
+            } else {
+                log::warn!("lost connection to the database, retrying operations");
+            }
+        }
+    }
+}
+
+impl Actor for StoreHandler {}
+
+/// Converts stored stats into the values returned by the API, applying stat aliases if configured to.
+pub(crate) fn stat_values(config: &Config, namespace: &str, stats: HashMap<String, GameStat>) -> HashMap<String, f64> {
+    let mut values = HashMap::new();
+    for (name, stat) in stats {
+        if config.stat_aliases_on_read {
+            let canonical = config.canonical_stat_name(namespace, &name);
+            if canonical != name {
+                // Values stored under the current key take precedence over any left under an old one.
+                values.entry(canonical.to_string()).or_insert_with(|| stat.into());
+                continue;
+            }
+        }
+        values.insert(name, stat.into());
+    }
+    values
+}
+
+/// Checks that stats can be merged onto a document's existing stats, which must have the same types: a merge can't
+/// change how a stat is aggregated, such as between totals and averages, or how its value is stored, such as between
+/// ints and floats.
+pub(crate) fn check_mergeable(stats: &HashMap<String, GameStat>, target: &HashMap<String, GameStat>) -> Result<()> {
+    for (name, stat) in stats {
+        if let Some(existing) = target.get(name) {
+            if existing.stat_type() != stat.stat_type() {
+                anyhow::bail!("stat '{}' is a {:?} but the target has a {:?}", name, stat.stat_type(), existing.stat_type());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// The type that an uploaded stat is stored as.
+pub(crate) fn stored_type(stat: &UploadStat, storage: StatStorage) -> StatType {
+    match (storage, stat) {
+        (_, UploadStat::IntMin(_)) => StatType::IntMin,
+        (_, UploadStat::IntMax(_)) => StatType::IntMax,
+        (_, UploadStat::FloatMin(_)) => StatType::FloatMin,
+        (_, UploadStat::FloatMax(_)) => StatType::FloatMax,
+        (_, UploadStat::Latest(_)) => StatType::Latest,
+        (_, UploadStat::Histogram(_)) => StatType::Histogram,
+        (StatStorage::Decimal128, stat) if stat.is_average() => StatType::DecimalRollingAverage,
+        (StatStorage::Decimal128, _) => StatType::DecimalTotal,
+        (StatStorage::Native, UploadStat::IntTotal(_)) => StatType::IntTotal,
+        (StatStorage::Native, UploadStat::IntRollingAverage(_) | UploadStat::IntRollingAverageBatch { .. }) => StatType::IntRollingAverage,
+        (StatStorage::Native, UploadStat::LongTotal(_)) => StatType::LongTotal,
+        (StatStorage::Native, UploadStat::LongRollingAverage(_) | UploadStat::LongRollingAverageBatch { .. }) => StatType::LongRollingAverage,
+        (StatStorage::Native, UploadStat::FloatTotal(_)) => StatType::FloatTotal,
+        (StatStorage::Native, UploadStat::FloatRollingAverage(_) | UploadStat::FloatRollingAverageBatch { .. }) => StatType::FloatRollingAverage,
+    }
+}
+
+/// Whether a stat stored as one type can take an upload stored as another. Decimal increments promote native values
+/// of the same shape, so that a stat can be moved to decimal storage without converting it first.
+pub(crate) fn can_store_as(stored: StatType, uploaded: StatType) -> bool {
+    let uploaded_decimal = matches!(uploaded, StatType::DecimalTotal | StatType::DecimalRollingAverage);
+    stored == uploaded || (uploaded_decimal && stored.aggregation() == uploaded.aggregation())
+}
+
+/// Combines the stats of hourly rollups, oldest first, into the change of each stat over their window. Minimums and
+/// maximums keep the lowest or highest value, latest values the last one, and other stats are summed, with averages
+/// divided by their summed count.
+pub(crate) fn sum_rollups(rollups: impl IntoIterator<Item = HashMap<String, GameStat>>) -> HashMap<String, f64> {
+    let mut sums: HashMap<String, (Option<f64>, Option<i64>)> = HashMap::new();
+    for stats in rollups {
+        for (name, stat) in stats {
+            let (total, count) = sums.entry(name).or_insert((None, None));
+            let value = stat.total();
+            *total = Some(match (stat.stat_type().aggregation(), *total) {
+                (_, None) | (StatAggregation::Latest, _) => value,
+                (StatAggregation::Min, Some(total)) => total.min(value),
+                (StatAggregation::Max, Some(total)) => total.max(value),
+                (StatAggregation::Total | StatAggregation::Average | StatAggregation::Histogram, Some(total)) => total + value,
+            });
+            if let Some(stat_count) = stat.count() {
+                *count = Some(count.unwrap_or(0) + stat_count as i64);
+            }
+        }
+    }
+
+    sums.into_iter()
+        .map(|(name, (total, count))| {
+            let total = total.unwrap_or_default();
+            match count {
+                Some(count) => (name, total / count as f64),
+                None => (name, total),
+            }
+        })
+        .collect()
+}
+
+/// Adds uploaded stats onto stored stats in memory, failing with a [StatTypeMismatch] if one would change the type that
+/// a stat is stored as. For backends without a decimal type, so stats are always stored natively.
+pub(crate) fn add_upload(config: &Config, namespace: &str, player: Option<Uuid>, mut stored: HashMap<String, GameStat>, stats: &HashMap<String, UploadStat>) -> Result<HashMap<String, GameStat>> {
+    for (name, stat) in stats {
+        let uploaded = stored_type(stat, StatStorage::Native);
+        if let Some(existing) = stored.get(name) {
+            if !can_store_as(existing.stat_type(), uploaded) {
+                return Err(StatTypeMismatch {
+                    namespace: namespace.to_string(),
+                    player,
+                    stat: name.clone(),
+                    stored: existing.stat_type(),
+                    uploaded,
+                }.into());
+            }
+        }
+        let added = add_stat(stored.get(name), uploaded_stat(stat, &config.histogram_buckets(namespace, name)))?;
+        stored.insert(name.clone(), added);
+    }
+    Ok(stored)
+}
+
+/// The stored stat that an upload adds, counting histogram values in the buckets with the upper bounds `buckets`.
+pub(crate) fn uploaded_stat(stat: &UploadStat, buckets: &[f64]) -> GameStat {
+    match stat {
+        UploadStat::IntTotal(value) => GameStat::IntTotal(*value as i64),
+        UploadStat::IntRollingAverage(value) => GameStat::IntAverage { total: *value as i64, count: 1 },
+        UploadStat::IntRollingAverageBatch { total, count } => GameStat::IntAverage { total: *total as i64, count: *count as i32 },
+        UploadStat::LongTotal(value) => GameStat::LongTotal(*value),
+        UploadStat::LongRollingAverage(value) => GameStat::LongAverage { total: *value, count: 1 },
+        UploadStat::LongRollingAverageBatch { total, count } => GameStat::LongAverage { total: *total, count: *count as i32 },
+        UploadStat::FloatTotal(value) => GameStat::FloatTotal(*value),
+        UploadStat::FloatRollingAverage(value) => GameStat::FloatAverage { total: *value, count: 1 },
+        UploadStat::FloatRollingAverageBatch { total, count } => GameStat::FloatAverage { total: *total, count: *count as i32 },
+        UploadStat::IntMin(value) => GameStat::IntMin(*value as i64),
+        UploadStat::IntMax(value) => GameStat::IntMax(*value as i64),
+        UploadStat::FloatMin(value) => GameStat::FloatMin(*value),
+        UploadStat::FloatMax(value) => GameStat::FloatMax(*value),
+        UploadStat::Latest(value) => GameStat::Latest(*value),
+        UploadStat::Histogram(values) => {
+            let mut counts: HashMap<String, i64> = HashMap::new();
+            for value in values {
+                *counts.entry(buckets.partition_point(|bound| bound <= value).to_string()).or_default() += 1;
+            }
+            GameStat::Histogram { bounds: buckets.to_vec(), counts }
+        }
+    }
+}
+
+/// Adds a stat onto a stored stat, the way [GameStat::create_merge_operation] does in MongoDB. A stat of another type
+/// with the same aggregation is converted into the stored type first.
+pub(crate) fn add_stat(stored: Option<&GameStat>, stat: GameStat) -> Result<GameStat> {
+    let stored = match stored {
+        Some(stored) => stored,
+        None => return Ok(stat),
+    };
+    let stat = if stat.stat_type() == stored.stat_type() { stat } else { stat.convert(stored.stat_type(), None)? };
+    let overflow = || anyhow::anyhow!("adding onto a {} would overflow it", stored.stat_type().name());
+
+    Ok(match (stored, stat) {
+        (GameStat::IntTotal(a), GameStat::IntTotal(b)) => GameStat::IntTotal(a.checked_add(b).ok_or_else(overflow)?),
+        (GameStat::LongTotal(a), GameStat::LongTotal(b)) => GameStat::LongTotal(a.checked_add(b).ok_or_else(overflow)?),
+        (GameStat::FloatTotal(a), GameStat::FloatTotal(b)) => GameStat::FloatTotal(a + b),
+        (GameStat::IntAverage { total: a, count: c }, GameStat::IntAverage { total: b, count: d }) => GameStat::IntAverage {
+            total: a.checked_add(b).ok_or_else(overflow)?,
+            count: c.checked_add(d).ok_or_else(overflow)?,
+        },
+        (GameStat::LongAverage { total: a, count: c }, GameStat::LongAverage { total: b, count: d }) => GameStat::LongAverage {
+            total: a.checked_add(b).ok_or_else(overflow)?,
+            count: c.checked_add(d).ok_or_else(overflow)?,
+        },
+        (GameStat::FloatAverage { total: a, count: c }, GameStat::FloatAverage { total: b, count: d }) => GameStat::FloatAverage {
+            total: a + b,
+            count: c.checked_add(d).ok_or_else(overflow)?,
+        },
+        (GameStat::IntMin(a), GameStat::IntMin(b)) => GameStat::IntMin(b.min(*a)),
+        (GameStat::IntMax(a), GameStat::IntMax(b)) => GameStat::IntMax(b.max(*a)),
+        (GameStat::FloatMin(a), GameStat::FloatMin(b)) => GameStat::FloatMin(b.min(*a)),
+        (GameStat::FloatMax(a), GameStat::FloatMax(b)) => GameStat::FloatMax(b.max(*a)),
+        (GameStat::Latest(_), GameStat::Latest(b)) => GameStat::Latest(b),
+        (GameStat::Histogram { counts: stored_counts, .. }, GameStat::Histogram { bounds, counts }) => {
+            let mut stored_counts = stored_counts.clone();
+            for (index, count) in counts {
+                *stored_counts.entry(index).or_default() += count;
+            }
+            GameStat::Histogram { bounds, counts: stored_counts }
+        }
+        (stored, stat) => anyhow::bail!("a {} can't be added onto a {}", stat.stat_type().name(), stored.stat_type().name()),
+    })
+}
+
+#[derive(thiserror::Error, Debug)]
+#[error("deadline exceeded")]
+pub struct DeadlineExceeded;
+
+/// A rebuild that would replace the aggregates of a namespace, although the bundle log doesn't cover every change to
+/// its stats.
+#[derive(thiserror::Error, Debug)]
+#[error("the bundle log doesn't cover every change to the stats of {0}, so replacing its aggregates would lose stats")]
+pub struct IncompleteBundleLog(pub String);
+
+/// An operation that the configured backend can't perform.
+#[derive(thiserror::Error, Debug)]
+#[error("{0} is not supported by the {1} backend")]
+pub struct Unsupported(pub &'static str, pub &'static str);
+
+/// An upload of a bundle whose ID is claimed by another upload that hasn't finished, or that failed without releasing
+/// its claim. The upload can be retried later.
+#[derive(thiserror::Error, Debug)]
+#[error("another upload of bundle {0} hasn't finished")]
+pub struct BundlePending(pub Uuid);
+
+/// An upload that would change the type that a stat is stored as.
+#[derive(Debug)]
+pub struct StatTypeMismatch {
+    pub namespace: String,
+    /// The player the stat belongs to, or `None` for global stats.
+    pub player: Option<Uuid>,
+    pub stat: String,
+    pub stored: StatType,
+    pub uploaded: StatType,
+}
+
+impl std::fmt::Display for StatTypeMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.player {
+            Some(player) => write!(f, "stat '{}' of player {}", self.stat, player)?,
+            None => write!(f, "global stat '{}'", self.stat)?,
+        }
+        write!(f, " in {} is stored as {} but was uploaded as {}; convert the stat before uploading it as a different type",
+                self.namespace, self.stored.name(), self.uploaded.name())
+    }
+}
+
+impl std::error::Error for StatTypeMismatch {}
+
+/// Wraps a message with the deadline of the request that sent it. Handling is abandoned once the deadline passes,
+/// whether the message is still queued or its database work is in progress, except that
+/// [writes](DeadlineMessage::WRITES) are only abandoned while they are queued.
+pub struct WithDeadline<M> {
+    pub message: M,
+    pub deadline: Instant,
+    /// Span that the database work is logged in, so that it can be matched up with the request's ID.
+    pub span: tracing::Span,
+}
+
+/// A message that can be sent [with a deadline](WithDeadline).
+pub trait DeadlineMessage: Message {
+    /// Whether the message writes to the store. A write that was stopped part-way through may already have been
+    /// partly applied, and would be applied again when the request is retried, so once started it is always finished.
+    const WRITES: bool = false;
+}
+
+impl<M, T> Message for WithDeadline<M>
+    where M: Message<Result = Result<T>>, T: Send + 'static {
+    type Result = Result<T>;
+}
+
+#[async_trait]
+impl<M, T> Handler<WithDeadline<M>> for StoreHandler
+    where M: DeadlineMessage<Result = Result<T>> + Clone, T: Send + 'static, StoreHandler: Handler<M> {
+    async fn handle(&mut self, message: WithDeadline<M>, ctx: &mut Context<Self>) -> Result<T> {
+        if Instant::now() >= message.deadline {
+            return Err(DeadlineExceeded.into());
+        }
+        let handle = self.handle_with_retries(message.message, ctx).instrument(message.span);
+        let res = if M::WRITES {
+            Ok(handle.await)
+        } else {
+            tokio::time::timeout_at(message.deadline, handle).await
+        };
+        match res {
+            Ok(Err(e)) if !e.is::<StatTypeMismatch>() && !e.is::<IncompleteBundleLog>() && !e.is::<BundlePending>() && !e.is::<Unsupported>() => {
+                let operation = std::any::type_name::<M>().rsplit("::").next().unwrap_or_default();
+                self.reporter.report_server_error(operation, &e);
+                Err(e)
+            }
+            Ok(res) => res,
+            Err(_) => Err(DeadlineExceeded.into()),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct GetPlayerProfile(pub Uuid);
+impl Message for GetPlayerProfile {
+    type Result = Result<Option<PlayerProfile>>;
+}
+
+impl DeadlineMessage for GetPlayerProfile {}
+
+#[async_trait]
+impl Handler<GetPlayerProfile> for StoreHandler {
+    async fn handle(&mut self, message: GetPlayerProfile, _ctx: &mut Context<Self>) -> <GetPlayerProfile as Message>::Result {
+        self.store.get_player_profile(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetPlayerProfileByName(pub String);
+
+impl Message for GetPlayerProfileByName {
+    type Result = Result<Option<PlayerProfile>>;
+}
+
+impl DeadlineMessage for GetPlayerProfileByName {}
+
+#[async_trait]
+impl Handler<GetPlayerProfileByName> for StoreHandler {
+    async fn handle(&mut self, message: GetPlayerProfileByName, _ctx: &mut Context<Self>) -> <GetPlayerProfileByName as Message>::Result {
+        self.store.get_player_profile_by_name(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetPlayers {
+    pub order: PlayerOrder,
+    /// The last player of the previous page.
+    pub after: Option<PlayerCursor>,
+    pub limit: i64,
+}
+
+impl Message for GetPlayers {
+    type Result = Result<Vec<PlayerProfile>>;
+}
+
+impl DeadlineMessage for GetPlayers {}
+
+/// The order that players are listed in.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum PlayerOrder {
+    Uuid,
+    /// Most recently seen first, then by UUID, with players who were never seen last.
+    LastSeen,
+}
+
+/// Where a page of players listed in a [PlayerOrder] ends, so that the next page can start after it.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct PlayerCursor {
+    pub uuid: Uuid,
+    /// When the player was last seen, only used when listing by [PlayerOrder::LastSeen].
+    pub last_seen: Option<bson::DateTime>,
+}
+
+#[async_trait]
+impl Handler<GetPlayers> for StoreHandler {
+    async fn handle(&mut self, message: GetPlayers, _ctx: &mut Context<Self>) -> <GetPlayers as Message>::Result {
+        self.store.get_players(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchPlayers {
+    pub prefix: String,
+    pub include_private: bool,
+    pub limit: i64,
+}
+
+impl Message for SearchPlayers {
+    type Result = Result<Vec<PlayerProfile>>;
+}
+
+impl DeadlineMessage for SearchPlayers {}
+
+#[async_trait]
+impl Handler<SearchPlayers> for StoreHandler {
+    async fn handle(&mut self, message: SearchPlayers, _ctx: &mut Context<Self>) -> <SearchPlayers as Message>::Result {
+        self.store.search_players(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct UpdatePlayerProfile {
+    pub uuid: Uuid,
+    pub username: String,
+    pub private: Option<bool>,
+}
+
+impl Message for UpdatePlayerProfile {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for UpdatePlayerProfile {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<UpdatePlayerProfile> for StoreHandler {
+    async fn handle(&mut self, message: UpdatePlayerProfile, _ctx: &mut Context<Self>) -> <UpdatePlayerProfile as Message>::Result {
+        // Anonymized players keep no username, even once their game server reports it again.
+        let anonymized = self.store.get_player_profile(&message.uuid).await?.is_some_and(|profile| profile.anonymized);
+        let username = Some(message.username).filter(|_| !anonymized);
+        self.store.update_player_profile(&message.uuid, username).await?;
+        if let Some(private) = message.private {
+            self.store.set_player_private(&message.uuid, private).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct GetUsernameHistory(pub Uuid);
+
+impl Message for GetUsernameHistory {
+    type Result = Result<Vec<PreviousUsername>>;
+}
+
+impl DeadlineMessage for GetUsernameHistory {}
+
+#[async_trait]
+impl Handler<GetUsernameHistory> for StoreHandler {
+    async fn handle(&mut self, message: GetUsernameHistory, _ctx: &mut Context<Self>) -> <GetUsernameHistory as Message>::Result {
+        self.store.get_username_history(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct AddPlaytime {
+    pub uuid: Uuid,
+    pub namespace: String,
+    pub seconds: u64,
+}
+
+impl Message for AddPlaytime {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for AddPlaytime {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<AddPlaytime> for StoreHandler {
+    async fn handle(&mut self, message: AddPlaytime, _ctx: &mut Context<Self>) -> <AddPlaytime as Message>::Result {
+        self.store.add_playtime(&message.uuid, &message.namespace, message.seconds).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetPlaytime(pub Uuid);
+
+impl Message for GetPlaytime {
+    type Result = Result<Vec<StoredPlaytime>>;
+}
+
+impl DeadlineMessage for GetPlaytime {}
+
+#[async_trait]
+impl Handler<GetPlaytime> for StoreHandler {
+    async fn handle(&mut self, message: GetPlaytime, _ctx: &mut Context<Self>) -> <GetPlaytime as Message>::Result {
+        self.store.get_playtime(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetPlayerStats {
+    pub uuid: Uuid,
+    pub namespace: Option<String>,
+    /// The season to get stats from, or `None` for all-time stats.
+    pub season: Option<u32>,
+}
+
+impl Message for GetPlayerStats {
+    type Result = Result<Option<PlayerStatsResponse>>;
+}
+
+impl DeadlineMessage for GetPlayerStats {}
+
+#[async_trait]
+impl Handler<GetPlayerStats> for StoreHandler {
+    async fn handle(&mut self, message: GetPlayerStats, _ctx: &mut Context<Self>) -> <GetPlayerStats as Message>::Result {
+        self.store.get_player_stats(&message.uuid, &message.namespace, message.season).await
+    }
+}
+
+#[derive(Clone)]
+pub struct UploadStatsBundle(pub GameStatsBundle);
+
+impl Message for UploadStatsBundle {
+    type Result = Result<BundleOutcome>;
+}
+
+impl DeadlineMessage for UploadStatsBundle {
+    const WRITES: bool = true;
+}
+
+/// What uploading a bundle did.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum BundleOutcome {
+    Applied,
+    /// A bundle with the same `bundle_id` was already applied, so nothing was changed.
+    Duplicate,
+}
+
+#[async_trait]
+impl Handler<UploadStatsBundle> for StoreHandler {
+    async fn handle(&mut self, message: UploadStatsBundle, _ctx: &mut Context<Self>) -> <UploadStatsBundle as Message>::Result {
+        // Uploaded in a task of its own, so that a bundle that has been claimed by its `bundle_id` is always either
+        // applied or released, even if handling the message is abandoned, such as at shutdown.
+        let store = self.store.clone();
+        tokio::spawn(async move { store.upload_stats_bundle(message.0).await }.in_current_span()).await?
+    }
+}
+
+/// Checks a bundle against the stored stats, as for a dry run of an upload.
+#[derive(Clone)]
+pub struct CheckStatsBundle(pub GameStatsBundle);
+
+impl Message for CheckStatsBundle {
+    type Result = Result<BundleOutcome>;
+}
+
+impl DeadlineMessage for CheckStatsBundle {}
+
+#[async_trait]
+impl Handler<CheckStatsBundle> for StoreHandler {
+    async fn handle(&mut self, message: CheckStatsBundle, _ctx: &mut Context<Self>) -> <CheckStatsBundle as Message>::Result {
+        self.store.check_stats_bundle(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct ConvertStat {
+    pub namespace: String,
+    pub stat: String,
+    pub to: StatType,
+    pub count: Option<i32>,
+    pub dry_run: bool,
+}
+
+impl Message for ConvertStat {
+    type Result = Result<StatConversionReport>;
+}
+
+impl DeadlineMessage for ConvertStat {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<ConvertStat> for StoreHandler {
+    async fn handle(&mut self, message: ConvertStat, _ctx: &mut Context<Self>) -> <ConvertStat as Message>::Result {
+        self.store.convert_stat(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct RenameStats {
+    pub namespace: String,
+    pub stats: HashMap<String, String>,
+}
+
+impl Message for RenameStats {
+    type Result = Result<StatRenameReport>;
+}
+
+impl DeadlineMessage for RenameStats {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<RenameStats> for StoreHandler {
+    async fn handle(&mut self, message: RenameStats, _ctx: &mut Context<Self>) -> <RenameStats as Message>::Result {
+        self.store.rename_stats(&message.namespace, &message.stats).await
+    }
+}
+
+#[derive(Clone)]
+pub struct DeleteStat {
+    pub namespace: String,
+    pub stat: String,
+}
+
+impl Message for DeleteStat {
+    type Result = Result<StatDeletionReport>;
+}
+
+impl DeadlineMessage for DeleteStat {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<DeleteStat> for StoreHandler {
+    async fn handle(&mut self, message: DeleteStat, _ctx: &mut Context<Self>) -> <DeleteStat as Message>::Result {
+        self.store.delete_stat(&message.namespace, &message.stat).await
+    }
+}
+
+#[derive(Clone)]
+pub struct DeleteNamespace(pub String);
+
+impl Message for DeleteNamespace {
+    type Result = Result<NamespaceDeletionReport>;
+}
+
+impl DeadlineMessage for DeleteNamespace {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<DeleteNamespace> for StoreHandler {
+    async fn handle(&mut self, message: DeleteNamespace, _ctx: &mut Context<Self>) -> <DeleteNamespace as Message>::Result {
+        self.store.delete_namespace(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct DeletePlayerData(pub Uuid);
+impl Message for DeletePlayerData {
+    type Result = Result<PlayerDeletionReport>;
+}
+
+impl DeadlineMessage for DeletePlayerData {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<DeletePlayerData> for StoreHandler {
+    async fn handle(&mut self, message: DeletePlayerData, _ctx: &mut Context<Self>) -> <DeletePlayerData as Message>::Result {
+        self.store.delete_player_data(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct AnonymizePlayer(pub Uuid);
+impl Message for AnonymizePlayer {
+    type Result = Result<PlayerAnonymizationReport>;
+}
+
+impl DeadlineMessage for AnonymizePlayer {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<AnonymizePlayer> for StoreHandler {
+    async fn handle(&mut self, message: AnonymizePlayer, _ctx: &mut Context<Self>) -> <AnonymizePlayer as Message>::Result {
+        self.store.anonymize_player(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct MergeNamespace {
+    pub from: String,
+    pub into: String,
+}
+
+impl Message for MergeNamespace {
+    type Result = Result<NamespaceMergeReport>;
+}
+
+impl DeadlineMessage for MergeNamespace {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<MergeNamespace> for StoreHandler {
+    async fn handle(&mut self, message: MergeNamespace, _ctx: &mut Context<Self>) -> <MergeNamespace as Message>::Result {
+        self.store.merge_namespace(&message.from, &message.into).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetCorruptDocuments {
+    pub limit: i64,
+}
+
+impl Message for GetCorruptDocuments {
+    type Result = Result<Vec<CorruptDocument>>;
+}
+
+impl DeadlineMessage for GetCorruptDocuments {}
+
+#[async_trait]
+impl Handler<GetCorruptDocuments> for StoreHandler {
+    async fn handle(&mut self, message: GetCorruptDocuments, _ctx: &mut Context<Self>) -> <GetCorruptDocuments as Message>::Result {
+        self.store.get_corrupt_documents(message.limit).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetCorruptDocument(pub ObjectId);
+
+impl Message for GetCorruptDocument {
+    type Result = Result<Option<CorruptDocument>>;
+}
+
+impl DeadlineMessage for GetCorruptDocument {}
+
+#[async_trait]
+impl Handler<GetCorruptDocument> for StoreHandler {
+    async fn handle(&mut self, message: GetCorruptDocument, _ctx: &mut Context<Self>) -> <GetCorruptDocument as Message>::Result {
+        self.store.get_corrupt_document(message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct RestoreCorruptDocument {
+    pub id: ObjectId,
+    pub document: Document,
+}
+
+pub enum RestoreOutcome {
+    Restored,
+    NotFound,
+    /// The repaired document can't be read, or can't be merged into the existing document.
+    Invalid(String),
+}
+
+impl Message for RestoreCorruptDocument {
+    type Result = Result<RestoreOutcome>;
+}
+
+impl DeadlineMessage for RestoreCorruptDocument {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<RestoreCorruptDocument> for StoreHandler {
+    async fn handle(&mut self, message: RestoreCorruptDocument, _ctx: &mut Context<Self>) -> <RestoreCorruptDocument as Message>::Result {
+        self.store.restore_corrupt_document(message.id, message.document).await
+    }
+}
+
+#[derive(Clone)]
+pub struct QuarantineUpload(pub SuspiciousUpload);
+
+impl Message for QuarantineUpload {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for QuarantineUpload {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<QuarantineUpload> for StoreHandler {
+    async fn handle(&mut self, message: QuarantineUpload, _ctx: &mut Context<Self>) -> <QuarantineUpload as Message>::Result {
+        self.store.quarantine_upload(message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetSuspiciousUploads {
+    pub limit: i64,
+}
+
+impl Message for GetSuspiciousUploads {
+    type Result = Result<Vec<SuspiciousUpload>>;
+}
+
+impl DeadlineMessage for GetSuspiciousUploads {}
+
+#[async_trait]
+impl Handler<GetSuspiciousUploads> for StoreHandler {
+    async fn handle(&mut self, message: GetSuspiciousUploads, _ctx: &mut Context<Self>) -> <GetSuspiciousUploads as Message>::Result {
+        self.store.get_suspicious_uploads(message.limit).await
+    }
+}
+
+#[derive(Clone)]
+pub struct DeleteSuspiciousUpload(pub ObjectId);
+
+impl Message for DeleteSuspiciousUpload {
+    type Result = Result<Option<SuspiciousUpload>>;
+}
+
+impl DeadlineMessage for DeleteSuspiciousUpload {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<DeleteSuspiciousUpload> for StoreHandler {
+    async fn handle(&mut self, message: DeleteSuspiciousUpload, _ctx: &mut Context<Self>) -> <DeleteSuspiciousUpload as Message>::Result {
+        self.store.delete_suspicious_upload(message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetStatInfo(pub String);
+
+impl Message for GetStatInfo {
+    type Result = Result<HashMap<String, StatInfo>>;
+}
+
+impl DeadlineMessage for GetStatInfo {}
+
+#[async_trait]
+impl Handler<GetStatInfo> for StoreHandler {
+    async fn handle(&mut self, message: GetStatInfo, _ctx: &mut Context<Self>) -> <GetStatInfo as Message>::Result {
+        self.store.get_stat_info(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct UpdateStatInfo {
+    pub namespace: String,
+    pub stats: HashMap<String, StatInfo>,
+}
+
+impl Message for UpdateStatInfo {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for UpdateStatInfo {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<UpdateStatInfo> for StoreHandler {
+    async fn handle(&mut self, message: UpdateStatInfo, _ctx: &mut Context<Self>) -> <UpdateStatInfo as Message>::Result {
+        self.store.update_stat_info(message.namespace, message.stats).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetAchievementInfo(pub String);
+
+impl Message for GetAchievementInfo {
+    type Result = Result<HashMap<String, AchievementInfo>>;
+}
+
+impl DeadlineMessage for GetAchievementInfo {}
+
+#[async_trait]
+impl Handler<GetAchievementInfo> for StoreHandler {
+    async fn handle(&mut self, message: GetAchievementInfo, _ctx: &mut Context<Self>) -> <GetAchievementInfo as Message>::Result {
+        self.store.get_achievement_info(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct UpdateAchievementInfo {
+    pub namespace: String,
+    pub achievements: HashMap<String, AchievementInfo>,
+}
+
+impl Message for UpdateAchievementInfo {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for UpdateAchievementInfo {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<UpdateAchievementInfo> for StoreHandler {
+    async fn handle(&mut self, message: UpdateAchievementInfo, _ctx: &mut Context<Self>) -> <UpdateAchievementInfo as Message>::Result {
+        self.store.update_achievement_info(message.namespace, message.achievements).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GrantAchievements {
+    pub uuid: Uuid,
+    pub namespace: String,
+    pub achievements: Vec<String>,
+}
+
+impl Message for GrantAchievements {
+    type Result = Result<Vec<String>>;
+}
+
+impl DeadlineMessage for GrantAchievements {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<GrantAchievements> for StoreHandler {
+    async fn handle(&mut self, message: GrantAchievements, _ctx: &mut Context<Self>) -> <GrantAchievements as Message>::Result {
+        self.store.grant_achievements(&message.uuid, &message.namespace, &message.achievements).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetPlayerAchievements {
+    pub uuid: Uuid,
+    pub namespace: Option<String>,
+}
+
+impl Message for GetPlayerAchievements {
+    type Result = Result<Vec<StoredAchievement>>;
+}
+
+impl DeadlineMessage for GetPlayerAchievements {}
+
+#[async_trait]
+impl Handler<GetPlayerAchievements> for StoreHandler {
+    async fn handle(&mut self, message: GetPlayerAchievements, _ctx: &mut Context<Self>) -> <GetPlayerAchievements as Message>::Result {
+        self.store.get_player_achievements(&message.uuid, message.namespace.as_deref()).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetAchievementUnlocks(pub String);
+
+impl Message for GetAchievementUnlocks {
+    type Result = Result<AchievementUnlocks>;
+}
+
+impl DeadlineMessage for GetAchievementUnlocks {}
+
+#[async_trait]
+impl Handler<GetAchievementUnlocks> for StoreHandler {
+    async fn handle(&mut self, message: GetAchievementUnlocks, _ctx: &mut Context<Self>) -> <GetAchievementUnlocks as Message>::Result {
+        self.store.get_achievement_unlocks(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct RecordMatch {
+    pub namespace: String,
+    pub teams: Vec<MatchTeam>,
+}
+
+impl Message for RecordMatch {
+    type Result = Result<HashMap<Uuid, RatingChange>>;
+}
+
+impl DeadlineMessage for RecordMatch {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<RecordMatch> for StoreHandler {
+    async fn handle(&mut self, message: RecordMatch, _ctx: &mut Context<Self>) -> <RecordMatch as Message>::Result {
+        self.store.record_match(&message.namespace, &message.teams).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetPlayerRatings(pub Uuid);
+
+impl Message for GetPlayerRatings {
+    type Result = Result<Vec<StoredRating>>;
+}
+
+impl DeadlineMessage for GetPlayerRatings {}
+
+#[async_trait]
+impl Handler<GetPlayerRatings> for StoreHandler {
+    async fn handle(&mut self, message: GetPlayerRatings, _ctx: &mut Context<Self>) -> <GetPlayerRatings as Message>::Result {
+        self.store.get_player_ratings(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetRatingLeaderboard {
+    pub namespace: String,
+    pub limit: i64,
+    /// Whether private players are ranked.
+    pub include_private: bool,
+}
+
+impl Message for GetRatingLeaderboard {
+    type Result = Result<Vec<LeaderboardEntry>>;
+}
+
+impl DeadlineMessage for GetRatingLeaderboard {}
+
+#[async_trait]
+impl Handler<GetRatingLeaderboard> for StoreHandler {
+    async fn handle(&mut self, message: GetRatingLeaderboard, _ctx: &mut Context<Self>) -> <GetRatingLeaderboard as Message>::Result {
+        self.store.get_rating_leaderboard(message).await
+    }
+}
+
+/// Checks that the database can be reached.
+#[derive(Clone)]
+pub struct Ping;
+
+impl Message for Ping {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for Ping {}
+
+#[async_trait]
+impl Handler<Ping> for StoreHandler {
+    async fn handle(&mut self, _message: Ping, _ctx: &mut Context<Self>) -> <Ping as Message>::Result {
+        self.store.ping().await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetNamespaces;
+
+impl Message for GetNamespaces {
+    type Result = Result<Vec<String>>;
+}
+
+impl DeadlineMessage for GetNamespaces {}
+
+#[async_trait]
+impl Handler<GetNamespaces> for StoreHandler {
+    async fn handle(&mut self, _message: GetNamespaces, _ctx: &mut Context<Self>) -> <GetNamespaces as Message>::Result {
+        self.store.get_namespaces().await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetServerStats(pub String);
+
+impl Message for GetServerStats {
+    type Result = Result<Vec<ServerStats>>;
+}
+
+impl DeadlineMessage for GetServerStats {}
+
+#[async_trait]
+impl Handler<GetServerStats> for StoreHandler {
+    async fn handle(&mut self, message: GetServerStats, _ctx: &mut Context<Self>) -> <GetServerStats as Message>::Result {
+        self.store.get_server_stats(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetGlobalStats(pub String);
+
+impl Message for GetGlobalStats {
+    type Result = Result<Option<HashMap<String, f64>>>;
+}
+
+impl DeadlineMessage for GetGlobalStats {}
+
+#[async_trait]
+impl Handler<GetGlobalStats> for StoreHandler {
+    async fn handle(&mut self, message: GetGlobalStats, _ctx: &mut Context<Self>) -> <GetGlobalStats as Message>::Result {
+        self.store.get_global_stats(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetGlobalStatsDelta {
+    pub namespace: String,
+    pub window_hours: u32,
+}
+
+impl Message for GetGlobalStatsDelta {
+    type Result = Result<HashMap<String, f64>>;
+}
+
+impl DeadlineMessage for GetGlobalStatsDelta {}
+
+#[async_trait]
+impl Handler<GetGlobalStatsDelta> for StoreHandler {
+    async fn handle(&mut self, message: GetGlobalStatsDelta, _ctx: &mut Context<Self>) -> <GetGlobalStatsDelta as Message>::Result {
+        self.store.get_global_stats_delta(&message.namespace, message.window_hours).await
+    }
+}
+
+#[derive(Clone)]
+pub struct InsertGame(pub Game);
+
+impl Message for InsertGame {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for InsertGame {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<InsertGame> for StoreHandler {
+    async fn handle(&mut self, message: InsertGame, _ctx: &mut Context<Self>) -> <InsertGame as Message>::Result {
+        self.store.insert_game(message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct LogUpload(pub UploadLogEntry);
+
+impl Message for LogUpload {
+    type Result = Result<()>;
+}
+
+impl DeadlineMessage for LogUpload {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<LogUpload> for StoreHandler {
+    async fn handle(&mut self, message: LogUpload, _ctx: &mut Context<Self>) -> <LogUpload as Message>::Result {
+        self.store.log_upload(message.0).await
+    }
+}
+
+/// Gets the most recently accepted uploads, newest first.
+#[derive(Clone)]
+pub struct GetUploadLog {
+    /// Only include uploads from servers with this name.
+    pub server_name: Option<String>,
+    /// Only include uploads to this namespace.
+    pub namespace: Option<String>,
+    /// Only include uploads made with the token of this name.
+    pub token: Option<String>,
+    pub limit: i64,
+}
+
+impl Message for GetUploadLog {
+    type Result = Result<Vec<UploadLogEntry>>;
+}
+
+impl DeadlineMessage for GetUploadLog {}
+
+#[async_trait]
+impl Handler<GetUploadLog> for StoreHandler {
+    async fn handle(&mut self, message: GetUploadLog, _ctx: &mut Context<Self>) -> <GetUploadLog as Message>::Result {
+        self.store.get_upload_log(message).await
+    }
+}
+
+/// Gets the most recently finished games, newest first.
+#[derive(Clone)]
+pub struct GetGames {
+    /// Only include games that this player took part in.
+    pub player: Option<Uuid>,
+    /// Only include games in this namespace.
+    pub namespace: Option<String>,
+    /// Namespaces whose games are left out when no namespace is given.
+    pub hidden_namespaces: Vec<String>,
+    pub limit: i64,
+    /// Whether private players are included in the participants of games.
+    pub include_private: bool,
+}
+
+impl Message for GetGames {
+    type Result = Result<Vec<Game>>;
+}
+
+impl DeadlineMessage for GetGames {}
+
+#[async_trait]
+impl Handler<GetGames> for StoreHandler {
+    async fn handle(&mut self, message: GetGames, _ctx: &mut Context<Self>) -> <GetGames as Message>::Result {
+        self.store.get_games(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetLeaderboard {
+    pub namespace: String,
+    pub stat: String,
+    pub limit: i64,
+    pub order: LeaderboardOrder,
+    /// The season to rank players in, or `None` to rank them by their all-time stats.
+    pub season: Option<u32>,
+    /// Whether private players are included.
+    pub include_private: bool,
+}
+
+impl Message for GetLeaderboard {
+    type Result = Result<Vec<LeaderboardEntry>>;
+}
+
+impl DeadlineMessage for GetLeaderboard {}
+
+#[async_trait]
+impl Handler<GetLeaderboard> for StoreHandler {
+    async fn handle(&mut self, message: GetLeaderboard, _ctx: &mut Context<Self>) -> <GetLeaderboard as Message>::Result {
+        self.store.get_leaderboard(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetStatRank {
+    pub uuid: Uuid,
+    pub namespace: String,
+    pub stat: String,
+    pub order: LeaderboardOrder,
+    /// The season to rank the player in, or `None` to rank them by their all-time stats.
+    pub season: Option<u32>,
+    /// Whether private players are counted.
+    pub include_private: bool,
+}
+
+impl Message for GetStatRank {
+    type Result = Result<Option<StatRank>>;
+}
+
+impl DeadlineMessage for GetStatRank {}
+
+#[async_trait]
+impl Handler<GetStatRank> for StoreHandler {
+    async fn handle(&mut self, message: GetStatRank, _ctx: &mut Context<Self>) -> <GetStatRank as Message>::Result {
+        self.store.get_stat_rank(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetStatSummary {
+    pub namespace: String,
+    pub stat: String,
+    /// The season to summarize, or `None` to summarize all-time stats.
+    pub season: Option<u32>,
+}
+
+impl Message for GetStatSummary {
+    type Result = Result<Option<StatSummaryResponse>>;
+}
+
+impl DeadlineMessage for GetStatSummary {}
+
+#[async_trait]
+impl Handler<GetStatSummary> for StoreHandler {
+    async fn handle(&mut self, message: GetStatSummary, _ctx: &mut Context<Self>) -> <GetStatSummary as Message>::Result {
+        self.store.get_stat_summary(message).await
+    }
+}
+
+/// Gets the recorded values of one of a player's stats.
+#[derive(Clone)]
+pub struct GetStatHistory {
+    pub uuid: Uuid,
+    pub namespace: String,
+    pub stat: String,
+    /// Only include values recorded at or after this time.
+    pub from: Option<bson::DateTime>,
+    /// Only include values recorded before this time.
+    pub until: Option<bson::DateTime>,
+    pub limit: i64,
+}
+
+impl Message for GetStatHistory {
+    type Result = Result<Vec<StatSnapshot>>;
+}
+
+impl DeadlineMessage for GetStatHistory {}
+
+#[async_trait]
+impl Handler<GetStatHistory> for StoreHandler {
+    async fn handle(&mut self, message: GetStatHistory, _ctx: &mut Context<Self>) -> <GetStatHistory as Message>::Result {
+        self.store.get_stat_history(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetStatHistogram {
+    /// The player whose stat to get, or `None` for the global stat.
+    pub uuid: Option<Uuid>,
+    pub namespace: String,
+    pub stat: String,
+}
+
+impl Message for GetStatHistogram {
+    type Result = Result<Option<Vec<HistogramBucket>>>;
+}
+
+impl DeadlineMessage for GetStatHistogram {}
+
+#[async_trait]
+impl Handler<GetStatHistogram> for StoreHandler {
+    async fn handle(&mut self, message: GetStatHistogram, _ctx: &mut Context<Self>) -> <GetStatHistogram as Message>::Result {
+        self.store.get_stat_histogram(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetCurrentSeason;
+
+impl Message for GetCurrentSeason {
+    type Result = Result<StoredSeason>;
+}
+
+impl DeadlineMessage for GetCurrentSeason {}
+
+#[async_trait]
+impl Handler<GetCurrentSeason> for StoreHandler {
+    async fn handle(&mut self, _message: GetCurrentSeason, _ctx: &mut Context<Self>) -> <GetCurrentSeason as Message>::Result {
+        self.store.get_current_season().await
+    }
+}
+
+#[derive(Clone)]
+pub struct StartSeason;
+
+impl Message for StartSeason {
+    type Result = Result<StoredSeason>;
+}
+
+impl DeadlineMessage for StartSeason {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<StartSeason> for StoreHandler {
+    async fn handle(&mut self, _message: StartSeason, _ctx: &mut Context<Self>) -> <StartSeason as Message>::Result {
+        self.store.start_season().await
+    }
+}
+
+#[derive(Clone)]
+pub struct PruneGlobalStatsRollups;
+
+impl Message for PruneGlobalStatsRollups {
+    type Result = Result<u64>;
+}
+
+#[async_trait]
+impl Handler<PruneGlobalStatsRollups> for StoreHandler {
+    async fn handle(&mut self, _message: PruneGlobalStatsRollups, _ctx: &mut Context<Self>) -> <PruneGlobalStatsRollups as Message>::Result {
+        self.store.prune_global_stats_rollups().await
+    }
+}
+
+#[derive(Clone)]
+pub struct PruneBundleLog;
+
+impl Message for PruneBundleLog {
+    type Result = Result<u64>;
+}
+
+#[async_trait]
+impl Handler<PruneBundleLog> for StoreHandler {
+    async fn handle(&mut self, _message: PruneBundleLog, _ctx: &mut Context<Self>) -> <PruneBundleLog as Message>::Result {
+        self.store.prune_bundle_log().await
+    }
+}
+
+#[derive(Clone)]
+pub struct PruneStatHistory;
+
+impl Message for PruneStatHistory {
+    type Result = Result<u64>;
+}
+
+#[async_trait]
+impl Handler<PruneStatHistory> for StoreHandler {
+    async fn handle(&mut self, _message: PruneStatHistory, _ctx: &mut Context<Self>) -> <PruneStatHistory as Message>::Result {
+        self.store.prune_stat_history().await
+    }
+}
+
+#[derive(Clone)]
+pub struct SaveLeaderboardSnapshot(pub StoredLeaderboardSnapshot);
+
+impl Message for SaveLeaderboardSnapshot {
+    type Result = Result<()>;
+}
+
+#[async_trait]
+impl Handler<SaveLeaderboardSnapshot> for StoreHandler {
+    async fn handle(&mut self, message: SaveLeaderboardSnapshot, _ctx: &mut Context<Self>) -> <SaveLeaderboardSnapshot as Message>::Result {
+        self.store.save_leaderboard_snapshot(message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetLeaderboardSnapshot {
+    pub namespace: String,
+    pub stat: String,
+    pub order: LeaderboardOrder,
+    pub at: bson::DateTime,
+}
+
+impl Message for GetLeaderboardSnapshot {
+    type Result = Result<Option<StoredLeaderboardSnapshot>>;
+}
+
+impl DeadlineMessage for GetLeaderboardSnapshot {}
+
+#[async_trait]
+impl Handler<GetLeaderboardSnapshot> for StoreHandler {
+    async fn handle(&mut self, message: GetLeaderboardSnapshot, _ctx: &mut Context<Self>) -> <GetLeaderboardSnapshot as Message>::Result {
+        self.store.get_leaderboard_snapshot(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct PruneLeaderboardSnapshots;
+
+impl Message for PruneLeaderboardSnapshots {
+    type Result = Result<u64>;
+}
+
+#[async_trait]
+impl Handler<PruneLeaderboardSnapshots> for StoreHandler {
+    async fn handle(&mut self, _message: PruneLeaderboardSnapshots, _ctx: &mut Context<Self>) -> <PruneLeaderboardSnapshots as Message>::Result {
+        self.store.prune_leaderboard_snapshots().await
+    }
+}
+
+#[derive(Clone)]
+pub struct PruneBundleIds;
+
+impl Message for PruneBundleIds {
+    type Result = Result<u64>;
+}
+
+#[async_trait]
+impl Handler<PruneBundleIds> for StoreHandler {
+    async fn handle(&mut self, _message: PruneBundleIds, _ctx: &mut Context<Self>) -> <PruneBundleIds as Message>::Result {
+        self.store.prune_bundle_ids().await
+    }
+}
+
+#[derive(Clone)]
+pub struct RebuildAggregates {
+    pub namespace: Option<String>,
+    pub from: Option<bson::DateTime>,
+    pub until: Option<bson::DateTime>,
+    pub mode: RebuildMode,
+}
+
+impl Message for RebuildAggregates {
+    type Result = Result<AggregateRebuildReport>;
+}
+
+impl DeadlineMessage for RebuildAggregates {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<RebuildAggregates> for StoreHandler {
+    async fn handle(&mut self, message: RebuildAggregates, _ctx: &mut Context<Self>) -> <RebuildAggregates as Message>::Result {
+        self.store.rebuild_aggregates(message).await
+    }
+}
+
+#[derive(Clone)]
+pub struct AcquireLease {
+    pub name: String,
+    pub holder: String,
+    pub ttl: Duration,
+}
+
+impl Message for AcquireLease {
+    type Result = Result<bool>;
+}
+
+#[async_trait]
+impl Handler<AcquireLease> for StoreHandler {
+    async fn handle(&mut self, message: AcquireLease, _ctx: &mut Context<Self>) -> <AcquireLease as Message>::Result {
+        self.store.acquire_lease(&message.name, &message.holder, message.ttl).await
+    }
+}
+
+/// What backups of a store are made of.
+pub struct BackupManifest {
+    /// The schema version of the database. Backups can only be restored into a database at the same version.
+    pub schema_version: usize,
+    /// Names of the collections, or tables, that are backed up, in the order that they are exported.
+    pub collections: Vec<String>,
+}
+
+/// Documents read from a collection for a backup.
+pub struct BackupBatch {
+    /// MongoDB documents as canonical extended JSON, or PostgreSQL rows as JSON objects.
+    pub documents: Vec<serde_json::Value>,
+    /// Where the next batch starts, or `None` if there are no more documents.
+    pub next: Option<serde_json::Value>,
+}
+
+#[derive(Clone)]
+pub struct GetBackupManifest;
+
+impl Message for GetBackupManifest {
+    type Result = Result<BackupManifest>;
+}
+
+impl DeadlineMessage for GetBackupManifest {}
+
+#[async_trait]
+impl Handler<GetBackupManifest> for StoreHandler {
+    async fn handle(&mut self, _message: GetBackupManifest, _ctx: &mut Context<Self>) -> <GetBackupManifest as Message>::Result {
+        self.store.backup_manifest().await
+    }
+}
+
+#[derive(Clone)]
+pub struct ExportDocuments {
+    pub collection: String,
+    pub after: Option<serde_json::Value>,
+    pub limit: i64,
+}
+
+impl Message for ExportDocuments {
+    type Result = Result<BackupBatch>;
+}
+
+impl DeadlineMessage for ExportDocuments {}
+
+#[async_trait]
+impl Handler<ExportDocuments> for StoreHandler {
+    async fn handle(&mut self, message: ExportDocuments, _ctx: &mut Context<Self>) -> <ExportDocuments as Message>::Result {
+        self.store.export_documents(&message.collection, message.after, message.limit).await
+    }
+}
+
+#[derive(Clone)]
+pub struct ImportDocuments {
+    pub collection: String,
+    pub documents: Vec<serde_json::Value>,
+}
+
+impl Message for ImportDocuments {
+    type Result = Result<u64>;
+}
+
+impl DeadlineMessage for ImportDocuments {
+    const WRITES: bool = true;
+}
+
+#[async_trait]
+impl Handler<ImportDocuments> for StoreHandler {
+    async fn handle(&mut self, message: ImportDocuments, _ctx: &mut Context<Self>) -> <ImportDocuments as Message>::Result {
+        self.store.import_documents(&message.collection, message.documents).await
+    }
+}
+
+#[derive(Clone)]
+pub struct ReleaseLease {
+    pub name: String,
+    pub holder: String,
+}
+
+impl Message for ReleaseLease {
+    type Result = Result<()>;
+}
+
+#[async_trait]
+impl Handler<ReleaseLease> for StoreHandler {
+    async fn handle(&mut self, message: ReleaseLease, _ctx: &mut Context<Self>) -> <ReleaseLease as Message>::Result {
+        self.store.release_lease(&message.name, &message.holder).await
+    }
+}
+
+#[derive(Clone)]
+pub struct StartJobRun(pub JobRun);
+
+impl Message for StartJobRun {
+    type Result = Result<()>;
+}
+
+#[async_trait]
+impl Handler<StartJobRun> for StoreHandler {
+    async fn handle(&mut self, message: StartJobRun, _ctx: &mut Context<Self>) -> <StartJobRun as Message>::Result {
+        self.store.start_job_run(&message.0).await
+    }
+}
+
+#[derive(Clone)]
+pub struct FinishJobRun {
+    pub id: ObjectId,
+    /// The number of items processed, or the error that the job failed with.
+    pub result: std::result::Result<u64, String>,
+}
+
+impl Message for FinishJobRun {
+    type Result = Result<()>;
+}
+
+#[async_trait]
+impl Handler<FinishJobRun> for StoreHandler {
+    async fn handle(&mut self, message: FinishJobRun, _ctx: &mut Context<Self>) -> <FinishJobRun as Message>::Result {
+        self.store.finish_job_run(message.id, &message.result).await
+    }
+}
+
+#[derive(Clone)]
+pub struct GetJobRuns {
+    pub job: Option<String>,
+    pub limit: i64,
+}
+
+impl Message for GetJobRuns {
+    type Result = Result<Vec<JobRun>>;
+}
+
+impl DeadlineMessage for GetJobRuns {}
+
+#[async_trait]
+impl Handler<GetJobRuns> for StoreHandler {
+    async fn handle(&mut self, message: GetJobRuns, _ctx: &mut Context<Self>) -> <GetJobRuns as Message>::Result {
+        self.store.get_job_runs(message.job.as_deref(), message.limit).await
+    }
+}
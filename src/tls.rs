@@ -0,0 +1,88 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use anyhow::Context;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig};
+use tokio_rustls::TlsAcceptor;
+
+/// The certificate and key that HTTPS connections are accepted with, which can be reloaded from their files without
+/// restarting, e.g. after the certificate is renewed.
+#[derive(Clone)]
+pub struct TlsCertificate {
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    http2: bool,
+    current: Arc<RwLock<Arc<ServerConfig>>>,
+}
+
+impl TlsCertificate {
+    /// Loads a PEM certificate chain and private key, offering HTTP/2 to clients through ALPN if `http2` is set.
+    pub fn load(cert_path: &Path, key_path: &Path, http2: bool) -> anyhow::Result<Self> {
+        let config = server_config(cert_path, key_path, http2)?;
+        Ok(Self {
+            cert_path: cert_path.to_path_buf(),
+            key_path: key_path.to_path_buf(),
+            http2,
+            current: Arc::new(RwLock::new(Arc::new(config))),
+        })
+    }
+
+    /// Loads the certificate and key again, which new connections are then accepted with. The previous certificate
+    /// is kept if they can't be loaded.
+    pub fn reload(&self) -> anyhow::Result<()> {
+        let config = server_config(&self.cert_path, &self.key_path, self.http2)?;
+        *self.current.write().unwrap() = Arc::new(config);
+        Ok(())
+    }
+
+    /// Reloads the certificate whenever the process receives `SIGHUP`.
+    pub fn reload_on_sighup(&self) -> anyhow::Result<()> {
+        #[cfg(unix)]
+        {
+            let mut hangup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+            let certificate = self.clone();
+            tokio::spawn(async move {
+                while hangup.recv().await.is_some() {
+                    match certificate.reload() {
+                        Ok(()) => log::info!("Reloaded TLS certificate from {}", certificate.cert_path.display()),
+                        Err(e) => log::error!("Failed to reload TLS certificate, still using the previous one: {:#}", e),
+                    }
+                }
+            });
+        }
+        Ok(())
+    }
+
+    pub(crate) fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+}
+
+fn server_config(cert_path: &Path, key_path: &Path, http2: bool) -> anyhow::Result<ServerConfig> {
+    let mut reader = BufReader::new(File::open(cert_path).with_context(|| format!("failed to open {}", cert_path.display()))?);
+    let certs: Vec<Certificate> = rustls_pemfile::certs(&mut reader)?.into_iter().map(Certificate).collect();
+    if certs.is_empty() {
+        anyhow::bail!("no certificates found in {}", cert_path.display());
+    }
+
+    let mut reader = BufReader::new(File::open(key_path).with_context(|| format!("failed to open {}", key_path.display()))?);
+    let key = rustls_pemfile::read_all(&mut reader)?.into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::RSAKey(key) | rustls_pemfile::Item::PKCS8Key(key) | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .with_context(|| format!("no private key found in {}", key_path.display()))?;
+
+    let mut config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+    config.alpn_protocols = if http2 {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    } else {
+        vec![b"http/1.1".to_vec()]
+    };
+    Ok(config)
+}
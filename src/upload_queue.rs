@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use chrono::Utc;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::config::UploadQueueConfig;
+use crate::model::{GameStatsBundle, UploadState, UploadStatusResponse};
+use crate::shutdown::TrackedUpload;
+
+/// A bundle waiting in an [UploadQueue].
+pub struct QueuedUpload {
+    pub authorization: String,
+    pub bundle: GameStatsBundle,
+    /// Keeps the upload tracked until it has been applied, so that shutdown waits for queued bundles too.
+    pub tracked: TrackedUpload,
+}
+
+/// The queue was full, so the upload was handed back.
+pub struct QueueFull(pub Box<QueuedUpload>);
+
+/// A bounded queue of uploads that a fixed number of workers apply in the background, remembering how each went so
+/// that game servers can check on their uploads.
+///
+/// Statuses are only kept by this process, so an upload's status can't be checked through another replica of the
+/// backend.
+#[derive(Clone)]
+pub struct UploadQueue {
+    sender: mpsc::Sender<(Uuid, QueuedUpload)>,
+    statuses: Arc<Mutex<HashMap<Uuid, UploadStatus>>>,
+    retention: Duration,
+}
+
+struct UploadStatus {
+    response: UploadStatusResponse,
+    finished: Option<Instant>,
+}
+
+impl UploadQueue {
+    /// Starts the workers, which apply each queued upload with `apply` and record the state and error it returns.
+    pub fn spawn<F, Fut>(config: &UploadQueueConfig, apply: F) -> Self
+    where
+        F: Fn(QueuedUpload) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = (UploadState, Option<String>)> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(config.capacity.max(1));
+        let queue = Self {
+            sender,
+            statuses: Arc::default(),
+            retention: Duration::from_secs(config.status_retention_secs),
+        };
+
+        let receiver = Arc::new(tokio::sync::Mutex::new(receiver));
+        let apply = Arc::new(apply);
+        for _ in 0..config.workers.max(1) {
+            let receiver = receiver.clone();
+            let apply = apply.clone();
+            let queue = queue.clone();
+            tokio::spawn(async move {
+                loop {
+                    let next = receiver.lock().await.recv().await;
+                    let (id, upload) = match next {
+                        Some(next) => next,
+                        None => break,
+                    };
+                    queue.set_state(id, UploadState::Processing, None);
+                    // Applied in its own task, so that a panic fails the upload rather than stopping the worker.
+                    let (state, error) = match tokio::spawn(apply(upload)).await {
+                        Ok(result) => result,
+                        Err(e) => (UploadState::Failed, Some(e.to_string())),
+                    };
+                    queue.set_state(id, state, error);
+                }
+            });
+        }
+        queue
+    }
+
+    /// Adds an upload to the queue, returning its ID.
+    pub fn enqueue(&self, upload: QueuedUpload) -> Result<Uuid, QueueFull> {
+        let id = Uuid::new_v4();
+        let mut statuses = self.statuses.lock().unwrap();
+        let retention = self.retention;
+        statuses.retain(|_, status| status.finished.is_none_or(|finished| finished.elapsed() < retention));
+
+        // Recorded before it is sent, so that a worker never finishes an upload that has no status yet.
+        statuses.insert(id, UploadStatus {
+            response: UploadStatusResponse {
+                id,
+                state: UploadState::Queued,
+                error: None,
+                received_at: Utc::now().to_rfc3339(),
+                finished_at: None,
+            },
+            finished: None,
+        });
+        match self.sender.try_send((id, upload)) {
+            Ok(()) => Ok(id),
+            Err(e) => {
+                statuses.remove(&id);
+                let (mpsc::error::TrySendError::Full((_, upload)) | mpsc::error::TrySendError::Closed((_, upload))) = e;
+                Err(QueueFull(Box::new(upload)))
+            }
+        }
+    }
+
+    /// Gets the status of a queued upload, unless it finished longer ago than statuses are kept for.
+    pub fn status(&self, id: &Uuid) -> Option<UploadStatusResponse> {
+        let statuses = self.statuses.lock().unwrap();
+        statuses.get(id)
+            .filter(|status| status.finished.is_none_or(|finished| finished.elapsed() < self.retention))
+            .map(|status| status.response.clone())
+    }
+
+    fn set_state(&self, id: Uuid, state: UploadState, error: Option<String>) {
+        let mut statuses = self.statuses.lock().unwrap();
+        if let Some(status) = statuses.get_mut(&id) {
+            status.response.state = state;
+            status.response.error = error;
+            if state != UploadState::Queued && state != UploadState::Processing {
+                status.response.finished_at = Some(Utc::now().to_rfc3339());
+                status.finished = Some(Instant::now());
+            }
+        }
+    }
+}
@@ -5,3 +5,7 @@ pub fn uuid_to_bson(uuid: &Uuid) -> bson::ser::Result<Bson> {
     let serializer = bson::ser::Serializer::new();
     bson::serde_helpers::uuid_as_binary::serialize(uuid, serializer)
 }
+
+pub fn uuid_from_bson(bson: Bson) -> bson::de::Result<Uuid> {
+    bson::serde_helpers::uuid_as_binary::deserialize(bson::Deserializer::new(bson))
+}
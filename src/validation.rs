@@ -0,0 +1,143 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::config::{Config, ValidationConfig};
+use crate::model::{GameStatsBundle, MatchTeam, StatAggregation, Violation};
+
+/// Checks the namespace and stat names of an uploaded bundle, and its size, returning every limit that it breaks.
+///
+/// Names are checked after aliases are resolved, so a server that still uploads an old name can be kept working with
+/// an alias to a valid one.
+pub fn validate_bundle(config: &ValidationConfig, bundle: &GameStatsBundle) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    check_name(config, "namespace".to_string(), &bundle.namespace, &mut violations);
+
+    let players = bundle.stats.players.len();
+    if players > config.max_players_per_bundle {
+        violations.push(Violation {
+            field: "stats.players".to_string(),
+            message: format!("has {} players, more than the limit of {}", players, config.max_players_per_bundle),
+        });
+    }
+
+    let player_stats = bundle.stats.players.values().map(HashMap::len).sum::<usize>();
+    let stats = player_stats + bundle.stats.global.as_ref().map_or(0, HashMap::len);
+    if stats > config.max_stats_per_bundle {
+        violations.push(Violation {
+            field: "stats".to_string(),
+            message: format!("has {} stats, more than the limit of {}", stats, config.max_stats_per_bundle),
+        });
+    }
+
+    violations.extend(validate_stat_names(config, bundle.stats.global.iter().flat_map(HashMap::keys)
+        .chain(bundle.stats.players.values().flat_map(HashMap::keys))));
+    violations
+}
+
+/// Checks that a bundle doesn't add more to any total than its stat's `max_increment`, returning each increment that
+/// is too large. Such bundles are quarantined rather than rejected, since a server that uploads them is either buggy
+/// or up to no good.
+pub fn check_increments(config: &Config, bundle: &GameStatsBundle) -> Vec<Violation> {
+    let metadata = match config.stat_metadata.get(&bundle.namespace) {
+        Some(metadata) => metadata,
+        None => return Vec::new(),
+    };
+
+    let global = bundle.stats.global.iter().flatten()
+        .map(|(name, stat)| (format!("stats.global.{}", name), name, stat));
+    let players = bundle.stats.players.iter()
+        .flat_map(|(player, stats)| stats.iter().map(move |(name, stat)| (format!("stats.players.{}.{}", player, name), name, stat)));
+    let mut violations: Vec<Violation> = global.chain(players)
+        .filter(|(_, _, stat)| stat.aggregation() == StatAggregation::Total)
+        .filter_map(|(field, name, stat)| {
+            let limit = metadata.get(name)?.max_increment?;
+            (stat.value() > limit).then(|| Violation {
+                field,
+                message: format!("adds {}, more than the limit of {}", stat.value(), limit),
+            })
+        })
+        .collect();
+    violations.sort_by(|a, b| a.field.cmp(&b.field));
+    violations
+}
+
+/// Checks the teams of a submitted match: there must be at least two, none of them empty, and no player may be on
+/// more than one team or appear twice.
+pub fn validate_match(config: &ValidationConfig, namespace: &str, teams: &[MatchTeam]) -> Vec<Violation> {
+    let mut violations = Vec::new();
+    check_name(config, "namespace".to_string(), namespace, &mut violations);
+
+    if teams.len() < 2 {
+        violations.push(Violation {
+            field: "teams".to_string(),
+            message: "a match needs at least 2 teams".to_string(),
+        });
+    }
+    for (i, team) in teams.iter().enumerate() {
+        if team.players.is_empty() {
+            violations.push(Violation {
+                field: format!("teams.{}.players", i),
+                message: "a team needs at least 1 player".to_string(),
+            });
+        }
+    }
+
+    let players = teams.iter().map(|team| team.players.len()).sum::<usize>();
+    if players > config.max_players_per_bundle {
+        violations.push(Violation {
+            field: "teams".to_string(),
+            message: format!("has {} players, more than the limit of {}", players, config.max_players_per_bundle),
+        });
+    }
+    let mut seen = HashSet::new();
+    let repeated: BTreeSet<_> = teams.iter().flat_map(|team| &team.players).filter(|uuid| !seen.insert(**uuid)).collect();
+    for uuid in repeated {
+        violations.push(Violation {
+            field: "teams".to_string(),
+            message: format!("{} appears more than once", uuid),
+        });
+    }
+    violations
+}
+
+/// Checks stat names, reporting each invalid name once however often it appears.
+pub fn validate_stat_names<'a>(config: &ValidationConfig, names: impl Iterator<Item = &'a String>) -> Vec<Violation> {
+    validate_names(config, "stats", names)
+}
+
+/// Checks achievement ids in the same way as stat names.
+pub fn validate_achievement_names<'a>(config: &ValidationConfig, names: impl Iterator<Item = &'a String>) -> Vec<Violation> {
+    validate_names(config, "achievements", names)
+}
+
+fn validate_names<'a>(config: &ValidationConfig, field: &str, names: impl Iterator<Item = &'a String>) -> Vec<Violation> {
+    let names: BTreeSet<&str> = names.map(String::as_str).collect();
+    let mut violations = Vec::new();
+    for name in names {
+        check_name(config, format!("{}.{}", field, truncate(name, config.max_name_length)), name, &mut violations);
+    }
+    violations
+}
+
+/// Checks that a name is made of lowercase letters, digits and underscores, which is also what keeps it from being
+/// read as a path or an operator by MongoDB.
+fn check_name(config: &ValidationConfig, field: String, name: &str, violations: &mut Vec<Violation>) {
+    if name.len() > config.max_name_length {
+        violations.push(Violation {
+            field,
+            message: format!("'{}...' is longer than the limit of {} characters", truncate(name, config.max_name_length), config.max_name_length),
+        });
+    } else if name.is_empty() || !name.bytes().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'_') {
+        violations.push(Violation {
+            field,
+            message: format!("'{}' must only contain a-z, 0-9 and _", name),
+        });
+    }
+}
+
+/// Shortens a name that is too long to be echoed back in full.
+fn truncate(name: &str, max_len: usize) -> &str {
+    match name.char_indices().nth(max_len) {
+        Some((end, _)) => &name[..end],
+        None => name,
+    }
+}
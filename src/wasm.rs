@@ -0,0 +1,106 @@
+use std::convert::TryFrom;
+use std::path::Path;
+
+use anyhow::Context;
+use serde::Deserialize;
+use wasmi::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder};
+
+use crate::model::GameStatsBundle;
+use crate::processor::StatProcessor;
+
+/// A stat processor loaded from a WASM module, which can change or reject bundles before they are written.
+///
+/// Modules can't import anything, so they have no access to the host. They must export:
+/// - `memory`: their linear memory.
+/// - `alloc(len: i32) -> i32`: allocates `len` bytes, returning a pointer to them.
+/// - `transform(ptr: i32, len: i32) -> i64`: takes a bundle as JSON, and returns a pointer to JSON output in the
+///   upper 32 bits and its length in the lower 32 bits. The output is either `{"bundle": ...}` with the bundle to
+///   write, or `{"reject": "reason"}`.
+///
+/// Every call runs in a fresh instance, with limits on the instructions executed and memory used.
+pub struct WasmPlugin {
+    name: String,
+    engine: Engine,
+    module: Module,
+    fuel: u64,
+    memory_bytes: usize,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransformOutput {
+    Bundle(GameStatsBundle),
+    Reject(String),
+}
+
+impl WasmPlugin {
+    pub fn load(path: &Path, fuel: u64, memory_bytes: usize) -> anyhow::Result<Self> {
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config);
+
+        let file = std::fs::File::open(path).with_context(|| format!("failed to open WASM plugin {}", path.display()))?;
+        let module = Module::new(&engine, file).with_context(|| format!("failed to load WASM plugin {}", path.display()))?;
+
+        Ok(Self {
+            name: path.display().to_string(),
+            engine,
+            module,
+            fuel,
+            memory_bytes,
+        })
+    }
+
+    fn call_transform(&self, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(self.memory_bytes)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&self.engine, limits);
+        store.limiter(|limits: &mut StoreLimits| limits);
+        store.add_fuel(self.fuel).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let instance = Linker::new(&self.engine)
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        let memory = instance.get_memory(&store, "memory").context("plugin does not export its memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let transform = instance.get_typed_func::<(i32, i32), i64>(&store, "transform")?;
+
+        let len = i32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as u32 as usize, input).map_err(|e| anyhow::anyhow!("{}", e))?;
+
+        let output = transform.call(&mut store, (ptr, len))?;
+        let output_ptr = (output >> 32) as u32 as usize;
+        let output_len = output as u32 as usize;
+        let mut buffer = vec![0; output_len];
+        memory.read(&store, output_ptr, &mut buffer).map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(buffer)
+    }
+}
+
+impl StatProcessor for WasmPlugin {
+    fn transform(&self, bundle: &mut GameStatsBundle) -> Result<(), String> {
+        let input = serde_json::to_vec(&*bundle).map_err(|e| e.to_string())?;
+        let output = self.call_transform(&input).map_err(|e| {
+            log::warn!("WASM plugin {} failed: {:#}", self.name, e);
+            format!("plugin {} failed", self.name)
+        })?;
+
+        match serde_json::from_slice(&output) {
+            Ok(TransformOutput::Bundle(mut transformed)) => {
+                // Plugins can change stats, but not where they are stored or who they came from.
+                transformed.namespace = std::mem::take(&mut bundle.namespace);
+                transformed.server_name = std::mem::take(&mut bundle.server_name);
+                *bundle = transformed;
+                Ok(())
+            }
+            Ok(TransformOutput::Reject(reason)) => Err(reason),
+            Err(e) => {
+                log::warn!("WASM plugin {} returned invalid output: {}", self.name, e);
+                Err(format!("plugin {} failed", self.name))
+            }
+        }
+    }
+}
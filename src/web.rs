@@ -1,184 +1,3563 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::convert::TryFrom;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
+use bson::oid::ObjectId;
 use serde::{Deserialize, Serialize};
+use serde::de::DeserializeOwned;
+use futures::Stream;
 use uuid::Uuid;
+use utoipa::OpenApi;
 use warp::Filter;
+use warp::filters::BoxedFilter;
 use warp::http::StatusCode;
-use xtra::Address;
+use warp::http::header::{HeaderValue, CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE, EXPIRES, RETRY_AFTER, VARY};
+use warp::hyper::body::Buf;
+use warp::Reply;
+use tokio::sync::Notify;
+use tokio::time::Instant;
+use tracing::Instrument;
+use xtra::{Address, Handler};
 
-use crate::config::Config;
-use crate::database::{GetPlayerProfile, MongoDatabaseHandler, UpdatePlayerProfile, GetPlayerStats, UploadStatsBundle};
-use crate::model::{PlayerProfileResponse, GameStatsBundle};
+use crate::backup::{self, RestoreError};
+use crate::compression::{self, BodyError};
+use crate::config::{Config, CorsConfig, RateLimitKey, TokenScope};
+use crate::graphql::{self, GraphQlSchema};
+use crate::lease::Leases;
+use crate::limit::{RateLimiter, RouteLimits};
+use crate::live::LiveFeed;
+use crate::metrics::Metrics;
+use crate::mojang::UsernameResolver;
+use crate::openapi::ApiDoc;
+use crate::processor::Processors;
+use crate::reporting::{Alert, Reporter};
+use crate::result_cache::{CacheKey, ResultCache};
+use crate::scheduler::{Jobs, RunJobError};
+use crate::server;
+use crate::shutdown::{self, AbandonedUpload, UploadTracker};
+use crate::signature::{self, SignatureError, SignatureVerifier, SignedRequest};
+use crate::spool::Spool;
+use crate::tls::TlsCertificate;
+use crate::upload_queue::{QueueFull, QueuedUpload, UploadQueue};
+use crate::validation;
+use crate::store::{AddPlaytime, GetPlayers, SearchPlayers, PlayerCursor, PlayerOrder, GetAchievementInfo, GetAchievementUnlocks, GetPlayerAchievements, GrantAchievements, UpdateAchievementInfo, GetPlayerProfile, GetPlayerProfileByName, GetPlaytime, GetPlayerRatings, GetRatingLeaderboard, GetUsernameHistory, RecordMatch, StoreHandler, UpdatePlayerProfile, DeletePlayerData, AnonymizePlayer, GetPlayerStats, GetGlobalStats, GetNamespaces, GetStatInfo, UpdateStatInfo, GetLeaderboard, GetLeaderboardSnapshot, GetStatRank, GetStatSummary, GetGames, InsertGame, UploadStatsBundle, CheckStatsBundle, BundleOutcome, BundlePending, ConvertStat, RenameStats, MergeNamespace, DeleteStat, DeleteNamespace, GetGlobalStatsDelta, GetJobRuns, RebuildAggregates, IncompleteBundleLog, GetBackupManifest, WithDeadline, DeadlineMessage, DeadlineExceeded, StatTypeMismatch, GetCorruptDocuments, GetCorruptDocument, RestoreCorruptDocument, RestoreOutcome, QuarantineUpload, GetSuspiciousUploads, DeleteSuspiciousUpload, GetStatHistory, GetCurrentSeason, StartSeason, GetStatHistogram, GetServerStats, GetUploadLog, LogUpload, Ping, Unsupported};
+use crate::model::{AchievementInfo, AchievementSummary, AchievementsResponse, GrantAchievementsRequest, GrantAchievementsResponse, MatchResultRequest, MatchResultResponse, MatchTeam, StoredRating, UnlockedAchievement, PlayerListResponse, PlayerProfile, PlayerProfileResponse, PlayerStatsResponse, GameStatsBundle, UploadStat, JobRunResponse, UpdatePlayerProfileRequest, PlaytimeReport, PlaytimeResponse, ConvertStatRequest, RenameStatsRequest, MergeNamespaceRequest, RunJobResponse, JobResponse, ErrorResponse, RebuildAggregatesRequest, RebuildMode, LeaderboardOrder, LeaderboardSnapshotResponse, StatRankResponse, Game, StoredGameParticipant, GameUploadRequest, GameUploadResponse, GameResponse, StatInfoResponse, CorruptDocument, CorruptDocumentSummary, CorruptDocumentResponse, RestoreCorruptDocumentRequest, StatHistoryPoint, SeasonResponse, SuspiciousUpload, SuspiciousUploadResponse, ServerNamespaceSummary, ServerStatsSummary, UploadLogEntry, UploadDryRunOutcome, UploadDryRunResponse, UploadLogResponse, UploadQueuedResponse, UploadState, UsernameHistoryEntry, ValidationErrorResponse, Violation};
 
-#[derive(Serialize, Deserialize)]
-pub struct PlayerStats(HashMap<String, i32>);
+/// Serves the API until the process is asked to shut down, then waits for in-flight uploads to be written.
+pub async fn run(config: &Config, database: Address<StoreHandler>, metrics: Metrics, jobs: Jobs) -> anyhow::Result<()> {
+    let uploads = UploadTracker::default();
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    let (routes, ingest) = api(config, database, metrics, jobs, uploads.clone())?;
+
+    let tls = match (&config.tls_cert_path, &config.tls_key_path) {
+        (Some(cert_path), Some(key_path)) => {
+            let certificate = TlsCertificate::load(cert_path, key_path, config.http.http2)?;
+            certificate.reload_on_sighup()?;
+            Some(certificate)
+        }
+        (None, None) => None,
+        _ => anyhow::bail!("tls_cert_path and tls_key_path must be set together"),
+    };
+
+    // A read-only instance would only reject every message, so they are left on the queue for a writable one.
+    if config.message_queue.enabled && config.read_only {
+        log::info!("Not consuming the message queue in read-only mode");
+    } else if config.message_queue.enabled {
+        consume_message_queue(config, ingest.clone())?;
+    }
+    if config.grpc.enabled {
+        serve_grpc(config, ingest)?;
+    }
+
+    let shutting_down = Arc::new(Notify::new());
+    let server = server::serve(&config.http, (config.bind_address, config.api_port).into(), tls, routes, {
+        let uploads = uploads.clone();
+        let shutting_down = shutting_down.clone();
+        async move {
+            shutdown::signal().await;
+            log::info!("Shutting down, no longer accepting uploads");
+            uploads.close();
+            shutting_down.notify_one();
+        }
+    });
+    tokio::pin!(server);
+
+    // Once shutdown starts, open requests and in-flight uploads share a single grace period.
+    let deadline = tokio::select! {
+        res = &mut server => {
+            res?;
+            Instant::now() + Duration::from_secs(config.shutdown_timeout_secs)
+        }
+        _ = shutting_down.notified() => {
+            let deadline = Instant::now() + Duration::from_secs(config.shutdown_timeout_secs);
+            match tokio::time::timeout_at(deadline, &mut server).await {
+                Ok(res) => res?,
+                Err(_) => log::warn!("Requests were still open after {}s, closing them", config.shutdown_timeout_secs),
+            }
+            deadline
+        }
+    };
+
+    let abandoned = uploads.drain(deadline).await;
+    for AbandonedUpload { bundle, started } in &abandoned {
+        // Replaying an upload that may have been partly applied would count its stats twice, unless its bundle ID
+        // shows that it was already applied.
+        if *started && bundle.bundle_id.is_none() {
+            log::error!("Abandoned upload of bundle for {} from '{}' during shutdown, and didn't spool it as it may have been partly written",
+                    bundle.namespace, bundle.server_name);
+            continue;
+        }
+        let saved = match &spool {
+            Some(spool) => spool.write(bundle).await,
+            None => Err(anyhow::anyhow!("no spool directory is configured")),
+        };
+        match saved {
+            Ok(path) => log::warn!("Upload of bundle for {} from '{}' was not written before shutdown, saved it to {}",
+                    bundle.namespace, bundle.server_name, path.display()),
+            Err(e) => log::error!("Abandoned upload of bundle for {} from '{}' during shutdown: {}",
+                    bundle.namespace, bundle.server_name, e),
+        }
+    }
+    Ok(())
+}
+
+/// Starts serving the gRPC service alongside the HTTP API. Its uploads are tracked with the same [UploadTracker], so
+/// shutdown waits for them too.
+#[cfg(feature = "grpc")]
+fn serve_grpc(config: &Config, ingest: Ingest) -> anyhow::Result<()> {
+    let server = crate::grpc::serve(config, ingest)?;
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            log::error!("gRPC service stopped: {}", e);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "grpc"))]
+fn serve_grpc(_config: &Config, _ingest: Ingest) -> anyhow::Result<()> {
+    anyhow::bail!("grpc.enabled is set, but the backend was built without the grpc feature")
+}
+
+#[cfg(any(feature = "amqp", feature = "nats"))]
+fn consume_message_queue(config: &Config, ingest: Ingest) -> anyhow::Result<()> {
+    crate::message_queue::spawn(config, ingest)
+}
 
-pub async fn run(config: &Config, database: Address<MongoDatabaseHandler>) {
-    let cors = warp::cors()
-        .allow_any_origin();
+#[cfg(not(any(feature = "amqp", feature = "nats")))]
+fn consume_message_queue(_config: &Config, _ingest: Ingest) -> anyhow::Result<()> {
+    anyhow::bail!("message_queue.enabled is set, but the backend was built without the amqp or nats feature")
+}
+
+/// Builds the filter tree of the API, for serving it or embedding it in another warp server. Uploads are tracked
+/// with `uploads`, so that they can be waited for before shutting down.
+pub fn routes(config: &Config, database: Address<StoreHandler>, metrics: Metrics, jobs: Jobs, uploads: UploadTracker) -> anyhow::Result<Routes> {
+    Ok(api(config, database, metrics, jobs, uploads)?.0)
+}
+
+/// Builds the filter tree of the API along with the [Ingest] that its uploads go through, so that other transports
+/// can accept uploads in the same way.
+pub fn api(config: &Config, database: Address<StoreHandler>, metrics: Metrics, jobs: Jobs, uploads: UploadTracker) -> anyhow::Result<(Routes, Ingest)> {
+    let cors = cors(&config.cors)?;
+
+    let limits = RouteLimits::new(&config.concurrency, &config.deadlines, metrics.clone());
+    let usernames = UsernameResolver::new(&config.mojang, metrics.clone());
+    let rate_limits = RateLimiter::new(config, metrics.clone());
+    let spool = config.spool_dir.as_ref().map(Spool::new);
+    let leases = Leases::new(database.clone());
+    let live = LiveFeed::default();
+    let signatures = SignatureVerifier::default();
+    let cache = ResultCache::spawn(&config.result_cache, metrics.clone());
+    let processors = Processors::new(config, database.clone(), metrics.clone(), live.clone(), cache.clone())?;
+    let queue = config.upload_queue.enabled.then(|| UploadQueue::spawn(&config.upload_queue, {
+        let config = config.clone();
+        let database = database.clone();
+        let processors = processors.clone();
+        let limits = limits.clone();
+        move |upload: QueuedUpload| {
+            let deadline = limits.deadline("upload_stats");
+            apply_queued_upload(config.clone(), database.clone(), processors.clone(), upload, deadline)
+        }
+    }));
+    let ingest = Ingest {
+        config: config.clone(),
+        database: database.clone(),
+        metrics: metrics.clone(),
+        processors: processors.clone(),
+        limits: limits.clone(),
+        uploads: uploads.clone(),
+        spool: spool.clone(),
+        queue: queue.clone(),
+        reporter: Reporter::new(config),
+    };
 
     let player_profile = warp::path("player")
         .and(warp::path::param::<Uuid>())
         .and(warp::filters::method::get())
         .and(warp::filters::path::end())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "player_profile"))
         .and_then({
             let database = database.clone();
-            move |uuid| get_player_profile(database.clone(), uuid)
+            let config = config.clone();
+            let limits = limits.clone();
+            let usernames = usernames.clone();
+            move |uuid, view| limited(limits.clone(), "player_profile", get_player_profile(config.clone(), database.clone(), usernames.clone(), uuid, view, limits.deadline("player_profile")))
         });
 
-    let update_player_profile = warp::path("player")
+    let player_by_name = warp::path("player")
+        .and(warp::path("by-name"))
+        .and(warp::path::param::<String>())
+        .and(warp::filters::method::get())
+        .and(warp::filters::path::end())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "player_by_name"))
+        .and_then({
+            let database = database.clone();
+            let config = config.clone();
+            let limits = limits.clone();
+            move |username, view| limited(limits.clone(), "player_by_name", get_player_by_name(config.clone(), database.clone(), username, view, limits.deadline("player_by_name")))
+        });
+
+    let search_players = warp::path("players")
+        .and(warp::path("search"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<PlayerSearchQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "search_players"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |query, view| limited(limits.clone(), "search_players", search_players(config.clone(), database.clone(), query, view, limits.deadline("search_players")))
+        });
+
+    let username_history = warp::path("player")
         .and(warp::path::param::<Uuid>())
+        .and(warp::path("names"))
         .and(warp::filters::path::end())
-        .and(warp::filters::method::put())
+        .and(warp::filters::method::get())
         .and(warp::header("authorization"))
-        .and(warp::filters::body::json())
+        .and(rate_limited(config, &rate_limits, "username_history"))
         .and_then({
+            let database = database.clone();
             let config = config.clone();
+            let limits = limits.clone();
+            move |uuid, authorization| limited(limits.clone(), "username_history", get_username_history(config.clone(), database.clone(), uuid, authorization, limits.deadline("username_history")))
+        });
+
+    let playtime = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("playtime"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "playtime"))
+        .and_then({
             let database = database.clone();
-            move |uuid, authorization, body: UpdatePlayerProfileRequest| update_player_profile(config.clone(), database.clone(), uuid, authorization, body.username)
+            let config = config.clone();
+            let limits = limits.clone();
+            move |uuid, view| limited(limits.clone(), "playtime", get_playtime(config.clone(), database.clone(), uuid, view, limits.deadline("playtime")))
         });
 
-    let player_game_stats = warp::path("player")
+    let report_playtime = warp::path("player")
         .and(warp::path::param::<Uuid>())
-        .and(warp::path("stats"))
-        .and(warp::path::param::<String>())
+        .and(warp::path("playtime"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(rate_limited(config, &rate_limits, "report_playtime"))
+        .and(authorized_json_body(config, &signatures))
         .and_then({
+            let config = config.clone();
             let database = database.clone();
-            move |uuid, namespace| get_player_stats(database.clone(), uuid, Some(namespace))
+            let limits = limits.clone();
+            move |uuid, authorization, report: PlaytimeReport|
+                limited(limits.clone(), "report_playtime", report_playtime(config.clone(), database.clone(), uuid, authorization, report, limits.deadline("report_playtime")))
         });
 
-    let all_player_game_stats = warp::path("player")
+    let player_achievements = warp::path("player")
         .and(warp::path::param::<Uuid>())
-        .and(warp::path("stats"))
+        .and(warp::path("achievements"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<PlayerAchievementsQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "player_achievements"))
         .and_then({
             let database = database.clone();
-            move |uuid| get_player_stats(database.clone(), uuid, None)
+            let config = config.clone();
+            let limits = limits.clone();
+            move |uuid, query, view|
+                limited(limits.clone(), "player_achievements", get_player_achievements(config.clone(), database.clone(), uuid, query, view, limits.deadline("player_achievements")))
         });
 
-    let upload_game_stats = warp::path("stats")
-        .and(warp::path("upload"))
+    let grant_achievements = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("achievements"))
+        .and(warp::filters::path::end())
         .and(warp::filters::method::post())
-        .and(warp::header("Authorization"))
-        .and(warp::filters::body::json())
+        .and(writable(config))
+        .and(rate_limited(config, &rate_limits, "grant_achievements"))
+        .and(authorized_json_body(config, &signatures))
         .and_then({
             let config = config.clone();
             let database = database.clone();
-            move |authorization, game_stats: GameStatsBundle|
-                upload_game_stats(config.clone(), database.clone(), authorization, game_stats)
+            let limits = limits.clone();
+            move |uuid, authorization, request: GrantAchievementsRequest|
+                limited(limits.clone(), "grant_achievements", grant_achievements(config.clone(), database.clone(), uuid, authorization, request, limits.deadline("grant_achievements")))
         });
 
-    let combined = player_profile
-        // Management
-        .or(update_player_profile)
-        // Stats
-        .or(player_game_stats)
-        .or(all_player_game_stats)
-        .or(upload_game_stats);
+    let update_player_profile = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::put())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "update_player_profile"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |uuid, authorization, body: UpdatePlayerProfileRequest|
+                limited(limits.clone(), "update_player_profile", update_player_profile(config.clone(), database.clone(), uuid, authorization, body, limits.deadline("update_player_profile")))
+        });
 
-    warp::serve(combined.with(cors))
-        .run(([127, 0, 0, 1], config.api_port))
-        .await;
-}
+    let delete_player = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::delete())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "delete_player"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            move |uuid, authorization|
+                limited(limits.clone(), "delete_player", delete_player(config.clone(), database.clone(), cache.clone(), uuid, authorization, limits.deadline("delete_player")))
+        });
 
-type ApiResult = Result<Box<dyn warp::Reply>, warp::Rejection>;
+    let anonymize_player = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("anonymize"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "anonymize_player"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            move |uuid, authorization|
+                limited(limits.clone(), "anonymize_player", anonymize_player(config.clone(), database.clone(), cache.clone(), uuid, authorization, limits.deadline("anonymize_player")))
+        });
 
-async fn get_player_stats(database: Address<MongoDatabaseHandler>, uuid: Uuid, namespace: Option<String>) -> ApiResult {
-    let res = database.send(GetPlayerStats {
-        uuid,
-        namespace
-    }).await.unwrap();
-    return match res {
-        Ok(stats) => {
-            Ok(if let Some(stats) = stats {
-                Box::new(warp::reply::json(&stats))
-            } else {
-                send_http_status(StatusCode::NOT_FOUND)
-            })
-        },
-        Err(e) => {
-            Ok(handle_server_error(&e))
-        }
-    }
-}
+    let list_players = warp::path("players")
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(warp::query::<PlayersQuery>())
+        .and(rate_limited(config, &rate_limits, "list_players"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |authorization, query: PlayersQuery|
+                limited(limits.clone(), "list_players", list_players(config.clone(), database.clone(), authorization, query, limits.deadline("list_players")))
+        });
 
-async fn get_player_profile(database: Address<MongoDatabaseHandler>, uuid: Uuid) -> ApiResult {
-    let res = database.send(GetPlayerProfile(uuid)).await.unwrap();
-    return match res {
-        Ok(profile) => {
-            Ok(if let Some(profile) = profile {
-                Box::new(warp::reply::json(&PlayerProfileResponse::from(profile)))
-            } else {
-                send_http_status(StatusCode::NOT_FOUND)
-            })
-        },
-        Err(e) => {
-            Ok(handle_server_error(&e))
-        }
-    }
-}
+    let stat_history = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("history"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<StatHistoryQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "stat_history"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |uuid, namespace: String, query: StatHistoryQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "stat_history", get_stat_history(config.clone(), database.clone(), uuid, namespace, query, view, limits.deadline("stat_history")))
+            }
+        });
 
-#[derive(Serialize, Deserialize)]
-struct UpdatePlayerProfileRequest {
-    username: String,
-}
+    let player_stat_histogram = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("histogram"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<StatHistogramQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "stat_histogram"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |uuid, namespace: String, query: StatHistogramQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "stat_histogram", get_stat_histogram(config.clone(), database.clone(), Some(uuid), namespace, query, view, limits.deadline("stat_histogram")))
+            }
+        });
 
-async fn update_player_profile(config: Config, database: Address<MongoDatabaseHandler>, uuid: Uuid, authorization: String, username: String) -> ApiResult {
-    if !config.server_tokens.contains(&authorization) {
-        return Ok(send_http_status(StatusCode::UNAUTHORIZED))
-    }
+    let player_stat_rank = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<String>())
+        .and(warp::path("rank"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<StatRankQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "player_stat_rank"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |uuid, namespace: String, stat: String, query: StatRankQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                let stat = config.canonical_stat_name(&namespace, &stat).to_string();
+                limited(limits.clone(), "player_stat_rank", get_stat_rank(config.clone(), database.clone(), uuid, namespace, stat, query, view, limits.deadline("player_stat_rank")))
+            }
+        });
 
-    let res = database.send(UpdatePlayerProfile {
-        uuid, username
-    }).await.unwrap();
+    let player_game_stats = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::query::<SeasonQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "player_stats"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |uuid, namespace: String, query: SeasonQuery, view| {
+                let namespaces = vec![config.canonical_namespace(&namespace).to_string()];
+                limited(limits.clone(), "player_stats", get_player_stats(config.clone(), database.clone(), uuid, Some(namespaces), query.season, view, limits.deadline("player_stats")))
+            }
+        });
 
-    match res {
-        Ok(_) => Ok(Box::new(warp::reply::with_status("", StatusCode::NO_CONTENT))),
-        Err(e) => Ok(handle_server_error(&e))
-    }
-}
+    let all_player_game_stats = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("stats"))
+        .and(warp::query::<AllPlayerStatsQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "player_stats"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |uuid, query: AllPlayerStatsQuery, view| {
+                let namespaces = query.namespaces.map(|namespaces| {
+                    namespaces.split(',')
+                        .map(str::trim)
+                        .filter(|namespace| !namespace.is_empty())
+                        .map(|namespace| config.canonical_namespace(namespace).to_string())
+                        .collect()
+                });
+                limited(limits.clone(), "player_stats", get_player_stats(config.clone(), database.clone(), uuid, namespaces, query.season, view, limits.deadline("player_stats")))
+            }
+        });
 
-#[derive(Serialize, Deserialize)]
-struct UpdatedResponse {
-    updated: bool,
-}
+    let upload_game_stats = warp::path("stats")
+        .and(warp::path("upload"))
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(rate_limited(config, &rate_limits, "upload_stats"))
+        .and(warp::query::<UploadQuery>())
+        .and(authorized_json_body(config, &signatures))
+        .and_then({
+            let ingest = ingest.clone();
+            // Uploads acquire their concurrency slot after validation, so that they can be spooled if none is free.
+            move |query: UploadQuery, authorization, game_stats: GameStatsBundle| upload_game_stats(ingest.clone(), authorization, game_stats, query.dry_run)
+        });
 
-async fn upload_game_stats(config: Config, database: Address<MongoDatabaseHandler>, authorization: String, game_stats: GameStatsBundle) -> ApiResult {
-    if !config.server_tokens.contains(&authorization) {
-        return Ok(send_http_status(StatusCode::UNAUTHORIZED))
-    }
+    let upload_status = warp::path("stats")
+        .and(warp::path("upload"))
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("status"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "upload_status"))
+        .and_then({
+            let config = config.clone();
+            let queue = queue.clone();
+            move |id, authorization| get_upload_status(config.clone(), queue.clone(), authorization, id)
+        });
 
-    if let Some(global) = &game_stats.stats.global {
-        log::debug!("server '{}' uploaded {} player statistics and {} global statistics in statistics bundle for {}",
-                game_stats.server_name, game_stats.stats.players.len(), global.len(), game_stats.namespace);
+    let leaderboard = warp::path("leaderboard")
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<LeaderboardQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "leaderboard"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            move |namespace: String, stat: String, query: LeaderboardQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                let stat = config.canonical_stat_name(&namespace, &stat).to_string();
+                limited(limits.clone(), "leaderboard", get_leaderboard(config.clone(), database.clone(), cache.clone(), namespace, stat, query, view, limits.deadline("leaderboard")))
+            }
+        });
 
-        for name in global.keys() {
-            if name.contains('.') {
-                return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    let leaderboard_snapshot = warp::path("leaderboard")
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<String>())
+        .and(warp::path("snapshot"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<LeaderboardSnapshotQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "leaderboard_snapshot"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, stat: String, query: LeaderboardSnapshotQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                let stat = config.canonical_stat_name(&namespace, &stat).to_string();
+                limited(limits.clone(), "leaderboard_snapshot", get_leaderboard_snapshot(config.clone(), database.clone(), namespace, stat, query, view, limits.deadline("leaderboard_snapshot")))
             }
-        }
-    } else {
-        log::debug!("server '{}' uploaded {} player statistics in statistics bundle for {}",
-                game_stats.server_name, game_stats.stats.players.len(), game_stats.namespace);
-    }
+        });
+
+    let current_season = warp::path("seasons")
+        .and(warp::path("current"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(rate_limited(config, &rate_limits, "current_season"))
+        .and_then({
+            let database = database.clone();
+            let limits = limits.clone();
+            move || limited(limits.clone(), "current_season", get_current_season(database.clone(), limits.deadline("current_season")))
+        });
 
-    for stats in game_stats.stats.players.values() {
-        for name in stats.keys() {
-            if name.contains('.') {
-                return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    let live_feed = warp::path("stats")
+        .and(warp::path("live"))
+        .and(warp::filters::path::end())
+        .and(warp::ws())
+        .and(with_view(config))
+        .map({
+            let config = config.clone();
+            move |ws: warp::ws::Ws, view: View| {
+                let live = live.clone();
+                let hidden_namespaces = match view {
+                    View::Public => config.internal_namespaces.clone(),
+                    View::Full => Vec::new(),
+                };
+                Box::new(ws.on_upgrade(move |socket| async move { live.serve(socket, hidden_namespaces).await })) as Box<dyn Reply>
             }
-        }
-    }
+        });
 
-    let res = database.send(UploadStatsBundle(game_stats)).await.unwrap();
-    match res {
-        Ok(()) => Ok(Box::new(warp::reply::with_status("", StatusCode::NO_CONTENT))),
-        Err(e) => Ok(handle_server_error(&e)),
-    }
-}
+    let namespaces = warp::path("stats")
+        .and(warp::path("namespaces"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "namespaces"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |view| limited(limits.clone(), "namespaces", get_namespaces(config.clone(), database.clone(), view, limits.deadline("namespaces")))
+        });
 
-fn handle_server_error(e: &anyhow::Error) -> Box<dyn warp::Reply> {
-    log::warn!("error handling request: {}", e);
-    send_http_status(StatusCode::INTERNAL_SERVER_ERROR)
+    let stat_metadata = warp::path("stats")
+        .and(warp::path::param::<String>())
+        .and(warp::path("metadata"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "stat_metadata"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "stat_metadata", get_stat_metadata(config.clone(), database.clone(), namespace, view, limits.deadline("stat_metadata")))
+            }
+        });
+
+    let update_stat_metadata = warp::path("stats")
+        .and(warp::path::param::<String>())
+        .and(warp::path("metadata"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::put())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "update_stat_metadata"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, authorization, body: StatInfoResponse| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "update_stat_metadata", update_stat_metadata(config.clone(), database.clone(), namespace, authorization, body, limits.deadline("update_stat_metadata")))
+            }
+        });
+
+    let achievements = warp::path("achievements")
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "achievements"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "achievements", get_achievements(config.clone(), database.clone(), namespace, view, limits.deadline("achievements")))
+            }
+        });
+
+    let update_achievements = warp::path("achievements")
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::put())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "update_achievements"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, authorization, body: HashMap<String, AchievementInfo>| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "update_achievements", update_achievements(config.clone(), database.clone(), namespace, authorization, body, limits.deadline("update_achievements")))
+            }
+        });
+
+    let record_match = warp::path("ratings")
+        .and(warp::path::param::<String>())
+        .and(warp::path("match"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(rate_limited(config, &rate_limits, "record_match"))
+        .and(authorized_json_body(config, &signatures))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, authorization, request: MatchResultRequest| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "record_match", record_match(config.clone(), database.clone(), namespace, authorization, request, limits.deadline("record_match")))
+            }
+        });
+
+    let rating_leaderboard = warp::path("ratings")
+        .and(warp::path::param::<String>())
+        .and(warp::path("leaderboard"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<RatingLeaderboardQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "rating_leaderboard"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, query: RatingLeaderboardQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "rating_leaderboard", get_rating_leaderboard(config.clone(), database.clone(), namespace, query, view, limits.deadline("rating_leaderboard")))
+            }
+        });
+
+    let stat_summary = warp::path("stats")
+        .and(warp::path::param::<String>())
+        .and(warp::path("summary"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<StatSummaryQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "stat_summary"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            move |namespace: String, query: StatSummaryQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "stat_summary", get_stat_summary(config.clone(), database.clone(), cache.clone(), namespace, query, view, limits.deadline("stat_summary")))
+            }
+        });
+
+    let global_stats = warp::path("stats")
+        .and(warp::path("global"))
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "global_stats"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            move |namespace: String, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "global_stats", get_global_stats(config.clone(), database.clone(), cache.clone(), namespace, view, limits.deadline("global_stats")))
+            }
+        });
+
+    let server_stats = warp::path("stats")
+        .and(warp::path("servers"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("summary"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "server_stats"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |server_name: String, view| {
+                // Server names are free text, so unlike namespaces they may need to be percent-encoded in the path.
+                let server_name = percent_encoding::percent_decode_str(&server_name).decode_utf8_lossy().into_owned();
+                limited(limits.clone(), "server_stats", get_server_stats(config.clone(), database.clone(), server_name, view, limits.deadline("server_stats")))
+            }
+        });
+
+    let global_stat_histogram = warp::path("stats")
+        .and(warp::path("global"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("histogram"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<StatHistogramQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "stat_histogram"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |namespace: String, query: StatHistogramQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "stat_histogram", get_stat_histogram(config.clone(), database.clone(), None, namespace, query, view, limits.deadline("stat_histogram")))
+            }
+        });
+
+    let upload_game = warp::path("games")
+        .and(warp::path("upload"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(rate_limited(config, &rate_limits, "upload_game"))
+        .and(authorized_json_body(config, &signatures))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |authorization, body: GameUploadRequest|
+                limited(limits.clone(), "upload_game", upload_game(config.clone(), database.clone(), authorization, body, limits.deadline("upload_game")))
+        });
+
+    let recent_games = warp::path("games")
+        .and(warp::path("recent"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<GamesQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "games"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |query: GamesQuery, view|
+                limited(limits.clone(), "games", get_games(config.clone(), database.clone(), None, query, view, limits.deadline("games")))
+        });
+
+    let player_games = warp::path("player")
+        .and(warp::path::param::<Uuid>())
+        .and(warp::path("games"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<GamesQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "games"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |uuid, query: GamesQuery, view|
+                limited(limits.clone(), "games", get_games(config.clone(), database.clone(), Some(uuid), query, view, limits.deadline("games")))
+        });
+
+    let global_stats_delta = warp::path("stats")
+        .and(warp::path("global"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("delta"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::query::<GlobalStatsDeltaQuery>())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "global_stats_delta"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            move |namespace: String, query: GlobalStatsDeltaQuery, view| {
+                let namespace = config.canonical_namespace(&namespace).to_string();
+                limited(limits.clone(), "global_stats_delta", get_global_stats_delta(config.clone(), database.clone(), cache.clone(), namespace, query.window, view, limits.deadline("global_stats_delta")))
+            }
+        });
+
+    // Health checks are neither rate nor concurrency limited, so that a busy server isn't restarted for being busy.
+    let healthz = warp::path("healthz")
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .map(|| "ok");
+
+    let readyz = warp::path("readyz")
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and_then({
+            let database = database.clone();
+            let limits = limits.clone();
+            move || get_readiness(database.clone(), limits.deadline("readyz"))
+        });
+
+    let metrics_route = warp::path("metrics")
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .map({
+            let metrics = metrics.clone();
+            move || metrics.render()
+        });
+
+    // The document never changes while the server runs, so it is only serialized once.
+    let openapi = serde_json::to_value(ApiDoc::openapi())?;
+    let openapi_route = warp::path("openapi.json")
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .map(move || warp::reply::json(&openapi));
+
+    let convert_stat = warp::path("admin")
+        .and(warp::path("stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("convert"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "convert_stat"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            let leases = leases.clone();
+            move |namespace, authorization, body: ConvertStatRequest|
+                limited(limits.clone(), "convert_stat", convert_stat(config.clone(), database.clone(), cache.clone(), leases.clone(), namespace, authorization, body, limits.deadline("convert_stat")))
+        });
+
+    let rename_stats = warp::path("admin")
+        .and(warp::path("stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("rename"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "rename_stats"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            let leases = leases.clone();
+            move |namespace, authorization, body: RenameStatsRequest|
+                limited(limits.clone(), "rename_stats", rename_stats(config.clone(), database.clone(), cache.clone(), leases.clone(), namespace, authorization, body, limits.deadline("rename_stats")))
+        });
+
+    let merge_namespace = warp::path("admin")
+        .and(warp::path("namespaces"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("merge"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "merge_namespace"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            let leases = leases.clone();
+            move |namespace, authorization, body: MergeNamespaceRequest|
+                limited(limits.clone(), "merge_namespace", merge_namespace(config.clone(), database.clone(), cache.clone(), leases.clone(), namespace, authorization, body.from, limits.deadline("merge_namespace")))
+        });
+
+    let delete_stat = warp::path("admin")
+        .and(warp::path("stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::delete())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "delete_stat"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            let leases = leases.clone();
+            move |namespace, stat, authorization|
+                limited(limits.clone(), "delete_stat", delete_stat(config.clone(), database.clone(), cache.clone(), leases.clone(), namespace, stat, authorization, limits.deadline("delete_stat")))
+        });
+
+    let delete_namespace = warp::path("admin")
+        .and(warp::path("namespaces"))
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::delete())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "delete_namespace"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            let leases = leases.clone();
+            move |namespace, authorization|
+                limited(limits.clone(), "delete_namespace", delete_namespace(config.clone(), database.clone(), cache.clone(), leases.clone(), namespace, authorization, limits.deadline("delete_namespace")))
+        });
+
+    let rebuild_aggregates = warp::path("admin")
+        .and(warp::path("aggregates"))
+        .and(warp::path("rebuild"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "rebuild_aggregates"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            let leases = leases.clone();
+            move |authorization, body: RebuildAggregatesRequest|
+                limited(limits.clone(), "rebuild_aggregates", rebuild_aggregates(config.clone(), database.clone(), cache.clone(), leases.clone(), authorization, body, limits.deadline("rebuild_aggregates")))
+        });
+
+    let backup = warp::path("admin")
+        .and(warp::path("backup"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "backup"))
+        .and(warp::query::<BackupQuery>())
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |authorization, query: BackupQuery|
+                limited(limits.clone(), "backup", backup(config.clone(), database.clone(), limits.clone(), authorization, query.s3))
+        });
+
+    let restore = warp::path("admin")
+        .and(warp::path("restore"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "restore"))
+        .and(warp::body::stream())
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let cache = cache.clone();
+            let limits = limits.clone();
+            move |authorization, body|
+                limited(limits.clone(), "restore", restore(config.clone(), database.clone(), cache.clone(), limits.clone(), authorization, body))
+        });
+
+    let start_season = warp::path("admin")
+        .and(warp::path("seasons"))
+        .and(warp::path("start"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "start_season"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |authorization| limited(limits.clone(), "start_season", start_season(config.clone(), database.clone(), authorization, limits.deadline("start_season")))
+        });
+
+    let run_job = warp::path("admin")
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("run"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and_then({
+            let config = config.clone();
+            let jobs = jobs.clone();
+            move |name, authorization| run_job(config.clone(), jobs.clone(), name, authorization)
+        });
+
+    let list_jobs = warp::path("admin")
+        .and(warp::path("jobs"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "list_jobs"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            let jobs = jobs.clone();
+            move |authorization|
+                limited(limits.clone(), "list_jobs", list_jobs(config.clone(), database.clone(), jobs.clone(), authorization, limits.deadline("list_jobs")))
+        });
+
+    let job_runs = warp::path("admin")
+        .and(warp::path("jobs"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("runs"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(warp::query::<JobRunsQuery>())
+        .and(rate_limited(config, &rate_limits, "job_runs"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |name, authorization, query: JobRunsQuery|
+                limited(limits.clone(), "job_runs", get_job_runs(config.clone(), database.clone(), jobs.clone(), name, authorization, query.limit, limits.deadline("job_runs")))
+        });
+
+    let upload_log = warp::path("admin")
+        .and(warp::path("uploads"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(warp::query::<UploadLogQuery>())
+        .and(rate_limited(config, &rate_limits, "upload_log"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |authorization, query: UploadLogQuery|
+                limited(limits.clone(), "upload_log", get_upload_log(config.clone(), database.clone(), authorization, query, limits.deadline("upload_log")))
+        });
+
+    let corrupt_documents = warp::path("admin")
+        .and(warp::path("corrupt-stats"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(warp::query::<CorruptDocumentsQuery>())
+        .and(rate_limited(config, &rate_limits, "corrupt_stats"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |authorization, query: CorruptDocumentsQuery|
+                limited(limits.clone(), "corrupt_stats", get_corrupt_documents(config.clone(), database.clone(), authorization, query.limit, limits.deadline("corrupt_stats")))
+        });
+
+    let corrupt_document = warp::path("admin")
+        .and(warp::path("corrupt-stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "corrupt_stats"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |id, authorization|
+                limited(limits.clone(), "corrupt_stats", get_corrupt_document(config.clone(), database.clone(), id, authorization, limits.deadline("corrupt_stats")))
+        });
+
+    let restore_corrupt_document = warp::path("admin")
+        .and(warp::path("corrupt-stats"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("restore"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "restore_corrupt_stats"))
+        .and(json_body(config))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            let leases = leases.clone();
+            move |id, authorization, body: RestoreCorruptDocumentRequest|
+                limited(limits.clone(), "restore_corrupt_stats", restore_corrupt_document(config.clone(), database.clone(), leases.clone(), id, authorization, body, limits.deadline("restore_corrupt_stats")))
+        });
+
+    let suspicious_uploads = warp::path("admin")
+        .and(warp::path("suspicious-uploads"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::get())
+        .and(warp::header("authorization"))
+        .and(warp::query::<SuspiciousUploadsQuery>())
+        .and(rate_limited(config, &rate_limits, "suspicious_uploads"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |authorization, query: SuspiciousUploadsQuery|
+                limited(limits.clone(), "suspicious_uploads", get_suspicious_uploads(config.clone(), database.clone(), authorization, query.limit, limits.deadline("suspicious_uploads")))
+        });
+
+    let release_suspicious_upload = warp::path("admin")
+        .and(warp::path("suspicious-uploads"))
+        .and(warp::path::param::<String>())
+        .and(warp::path("release"))
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "release_suspicious_upload"))
+        .and_then({
+            let ingest = ingest.clone();
+            let limits = limits.clone();
+            move |id, authorization|
+                limited(limits.clone(), "release_suspicious_upload", release_suspicious_upload(ingest.clone(), id, authorization, limits.deadline("release_suspicious_upload")))
+        });
+
+    let discard_suspicious_upload = warp::path("admin")
+        .and(warp::path("suspicious-uploads"))
+        .and(warp::path::param::<String>())
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::delete())
+        .and(writable(config))
+        .and(warp::header("authorization"))
+        .and(rate_limited(config, &rate_limits, "discard_suspicious_upload"))
+        .and_then({
+            let config = config.clone();
+            let database = database.clone();
+            let limits = limits.clone();
+            move |id, authorization|
+                limited(limits.clone(), "discard_suspicious_upload", discard_suspicious_upload(config.clone(), database.clone(), id, authorization, limits.deadline("discard_suspicious_upload")))
+        });
+
+    let graphql_schema = graphql::schema(config, database.clone(), usernames.clone());
+    let graphql = warp::path("graphql")
+        .and(warp::filters::path::end())
+        .and(warp::filters::method::post())
+        .and(with_view(config))
+        .and(rate_limited(config, &rate_limits, "graphql"))
+        .and(json_body(config))
+        .and_then({
+            let limits = limits.clone();
+            move |view, request: async_graphql::Request|
+                limited(limits.clone(), "graphql", execute_graphql(graphql_schema.clone(), request, view, limits.deadline("graphql")))
+        });
+
+    // Routes are boxed so that a request's future doesn't hold every route's inline, which overflows the stack of
+    // unoptimized builds. Groups of them are boxed again, so that the type of the combined filter doesn't get too deep
+    // for the compiler.
+    let players = player_by_name.boxed()
+        .or(player_profile.boxed())
+        .or(username_history.boxed())
+        .or(playtime.boxed())
+        .or(report_playtime.boxed())
+        .or(player_achievements.boxed())
+        .or(grant_achievements.boxed())
+        // Management
+        .or(update_player_profile.boxed())
+        .or(delete_player.boxed())
+        .or(anonymize_player.boxed())
+        .or(list_players.boxed())
+        .or(search_players.boxed())
+        .map(boxed_reply)
+        .boxed();
+    // The stat history route goes before the stats of a namespace, whose route also matches longer paths.
+    let stats = stat_history.boxed()
+        .or(player_stat_histogram.boxed())
+        .or(player_stat_rank.boxed())
+        .or(player_game_stats.boxed())
+        .or(all_player_game_stats.boxed())
+        .or(upload_game_stats.boxed())
+        .or(upload_status.boxed())
+        .or(leaderboard.boxed())
+        .or(leaderboard_snapshot.boxed())
+        .or(namespaces.boxed())
+        .or(current_season.boxed())
+        .or(live_feed.boxed())
+        .or(global_stats.boxed())
+        .or(stat_summary.boxed())
+        .or(global_stats_delta.boxed())
+        .or(global_stat_histogram.boxed())
+        .or(server_stats.boxed())
+        .or(stat_metadata.boxed())
+        .or(update_stat_metadata.boxed())
+        .or(achievements.boxed())
+        .or(update_achievements.boxed())
+        .or(record_match.boxed())
+        .or(rating_leaderboard.boxed())
+        .map(boxed_reply)
+        .boxed();
+    let games = upload_game.boxed()
+        .or(recent_games.boxed())
+        .or(player_games.boxed())
+        .or(graphql.boxed())
+        .or(healthz.boxed())
+        .or(readyz.boxed())
+        .or(metrics_route.boxed())
+        .or(openapi_route.boxed())
+        .map(boxed_reply)
+        .boxed();
+    let admin = convert_stat.boxed()
+        .or(rename_stats.boxed())
+        .or(merge_namespace.boxed())
+        .or(delete_stat.boxed())
+        .or(delete_namespace.boxed())
+        .or(rebuild_aggregates.boxed())
+        .or(backup.boxed())
+        .or(restore.boxed())
+        .or(start_season.boxed())
+        .or(run_job.boxed())
+        .or(list_jobs.boxed())
+        .or(job_runs.boxed())
+        .or(upload_log.boxed())
+        .or(corrupt_documents.boxed())
+        .or(corrupt_document.boxed())
+        .or(restore_corrupt_document.boxed())
+        .or(suspicious_uploads.boxed())
+        .or(release_suspicious_upload.boxed())
+        .or(discard_suspicious_upload.boxed())
+        .map(boxed_reply)
+        .boxed();
+
+    let routes = players
+        .or(stats)
+        .or(games)
+        .or(admin)
+        .recover({
+            let max_body_bytes = config.max_body_bytes;
+            move |rejection| handle_rejection(rejection, max_body_bytes)
+        })
+        .with(cors)
+        .map(|reply| Box::new(reply) as Box<dyn warp::Reply>)
+        .boxed();
+
+    Ok((routes, ingest))
+}
+
+type ApiResult = Result<Box<dyn warp::Reply>, warp::Rejection>;
+
+/// The filter tree of the API, as built by [routes].
+pub type Routes = BoxedFilter<(Box<dyn Reply>,)>;
+
+fn boxed_reply(reply: impl warp::Reply + 'static) -> Box<dyn warp::Reply> {
+    Box::new(reply)
+}
+
+/// Builds the CORS filter from its configuration, checking each entry up front as warp panics on invalid ones.
+fn cors(config: &CorsConfig) -> anyhow::Result<warp::cors::Builder> {
+    let mut cors = warp::cors();
+    for origin in &config.allowed_origins {
+        let valid = match origin.split_once("://") {
+            Some((scheme, host)) => !scheme.is_empty() && !host.is_empty() && !host.contains('/') && HeaderValue::from_str(origin).is_ok(),
+            None => false,
+        };
+        if origin != "*" && !valid {
+            anyhow::bail!("invalid CORS origin '{}', expected e.g. 'https://nucleoid.xyz' or '*'", origin);
+        }
+    }
+    // Allowing specific origins after any origin would go back to only allowing those.
+    if config.allowed_origins.iter().any(|origin| origin == "*") {
+        cors = cors.allow_any_origin();
+    } else {
+        cors = cors.allow_origins(config.allowed_origins.iter().map(String::as_str));
+    }
+    for method in &config.allowed_methods {
+        let method = warp::http::Method::from_bytes(method.as_bytes())
+            .map_err(|_| anyhow::anyhow!("invalid CORS method '{}'", method))?;
+        cors = cors.allow_method(method);
+    }
+    for header in &config.allowed_headers {
+        let header = warp::http::header::HeaderName::from_bytes(header.as_bytes())
+            .map_err(|_| anyhow::anyhow!("invalid CORS header '{}'", header))?;
+        cors = cors.allow_header(header);
+    }
+    Ok(cors)
+}
+
+/// Runs a handler once a slot in the concurrency limits of its route is free, or responds with a 503 if none frees
+/// up in time. Handlers are given the deadline of their request, which they pass on to the database with
+/// [WithDeadline].
+async fn limited(limits: RouteLimits, route: &'static str, handler: impl Future<Output = ApiResult>) -> ApiResult {
+    let _permit = match limits.acquire(route).await {
+        Some(permit) => permit,
+        None => return Ok(send_http_status(StatusCode::SERVICE_UNAVAILABLE)),
+    };
+    handler.instrument(tracing::info_span!("route", route, otel.name = route)).await
+}
+
+/// Responds with a 503 unless the database can be reached through its actor.
+async fn get_readiness(database: Address<StoreHandler>, deadline: Instant) -> ApiResult {
+    match send(&database, Ping, deadline).await {
+        Ok(()) => Ok(Box::new("ok")),
+        Err(e) => {
+            log::warn!("readiness check failed: {}", e);
+            Ok(Box::new(warp::reply::with_status("database unavailable", StatusCode::SERVICE_UNAVAILABLE)))
+        }
+    }
+}
+
+/// How much detail a read request is allowed to see.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub(crate) enum View {
+    /// Unauthenticated requests: private players and internal namespaces are hidden.
+    Public,
+    /// Requests with a token that has the `read_private` scope.
+    Full,
+}
+
+impl View {
+    fn from_authorization(config: &Config, authorization: Option<String>) -> View {
+        match authorization {
+            Some(token) if config.token(&token).is_some_and(|token| token.has_scope(TokenScope::ReadPrivate)) => View::Full,
+            _ => View::Public,
+        }
+    }
+
+    pub(crate) fn can_see_namespace(self, config: &Config, namespace: &str) -> bool {
+        self == View::Full || !config.internal_namespaces.iter().any(|internal| internal == namespace)
+    }
+
+    pub(crate) fn filter_profile(self, config: &Config, profile: PlayerProfile, ratings: Vec<StoredRating>) -> PlayerProfileResponse {
+        let mut response = PlayerProfileResponse::from(profile);
+        response.ratings = ratings.into_iter()
+            .filter(|rating| self.can_see_namespace(config, &rating.namespace))
+            .map(|rating| (rating.namespace.clone(), rating.into()))
+            .collect();
+        if self == View::Public {
+            if response.private == Some(true) {
+                response.username = None;
+                response.first_seen = None;
+                response.last_seen = None;
+                response.ratings.clear();
+            }
+            response.private = None;
+        }
+        response
+    }
+
+    pub(crate) fn filter_stats(self, config: &Config, mut stats: PlayerStatsResponse) -> PlayerStatsResponse {
+        stats.retain(|namespace, _| self.can_see_namespace(config, namespace));
+        stats
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CacheClass {
+    Profiles,
+    Stats,
+    Leaderboards,
+    GlobalStats,
+}
+
+impl CacheClass {
+    fn max_age(self, config: &Config) -> Option<u64> {
+        match self {
+            CacheClass::Profiles => config.cache.profiles,
+            CacheClass::Stats => config.cache.stats,
+            CacheClass::Leaderboards => config.cache.leaderboards,
+            CacheClass::GlobalStats => config.cache.global_stats,
+        }
+    }
+}
+
+/// Adds `Cache-Control` and `Expires` headers to a successful response, as configured for its class of route.
+///
+/// Responses with the full view are only cacheable by the client, so a CDN never serves them to the public.
+fn with_cache_headers(config: &Config, class: CacheClass, view: View, reply: Box<dyn warp::Reply>) -> Box<dyn warp::Reply> {
+    let max_age = match class.max_age(config) {
+        Some(max_age) => max_age,
+        None => return reply,
+    };
+
+    let mut response = reply.into_response();
+    if response.status().is_success() {
+        let visibility = if view == View::Public { "public" } else { "private" };
+        let expires = SystemTime::now() + Duration::from_secs(max_age);
+        let headers = response.headers_mut();
+        headers.insert(CACHE_CONTROL, HeaderValue::from_str(&format!("{}, max-age={}", visibility, max_age)).unwrap());
+        headers.insert(EXPIRES, HeaderValue::from_str(&httpdate::fmt_http_date(expires)).unwrap());
+        headers.insert(VARY, HeaderValue::from_static("Authorization"));
+    }
+    Box::new(response)
+}
+
+/// Rejects requests with [ReadOnly] when the service is in read-only mode. Added to every route that changes the
+/// database.
+fn writable(config: &Config) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    let read_only = config.read_only;
+    warp::any()
+        .and_then(move || async move {
+            if read_only {
+                Err(warp::reject::custom(ReadOnly))
+            } else {
+                Ok(())
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Debug)]
+struct ReadOnly;
+
+impl warp::reject::Reject for ReadOnly {}
+
+/// Rejects requests with [RateLimited] once the token or client address that sent them has used up its limit for
+/// the route. Requests whose client address isn't known, such as to routes embedded in another server, aren't
+/// limited.
+fn rate_limited(config: &Config, rate_limits: &RateLimiter, route: &'static str) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    let config = config.clone();
+    let rate_limits = rate_limits.clone();
+    warp::header::optional::<String>("authorization")
+        .and(warp::header::optional::<String>("x-forwarded-for"))
+        .and(warp::ext::optional::<server::RemoteAddr>())
+        .and_then(move |authorization: Option<String>, forwarded_for: Option<String>, remote_addr: Option<server::RemoteAddr>| {
+            let config = config.clone();
+            let rate_limits = rate_limits.clone();
+            async move {
+                let key = match rate_limits.key(route) {
+                    Some(key) => key,
+                    None => return Ok(()),
+                };
+
+                let token = authorization.as_deref().and_then(|token| config.token(token));
+                let client = match (key, token) {
+                    (RateLimitKey::Token, Some(token)) => format!("token:{}", token.name),
+                    _ => {
+                        let forwarded_for = forwarded_for.filter(|_| config.trust_forwarded_for())
+                            .and_then(|forwarded_for| forwarded_for.split(',').next().map(|addr| addr.trim().to_string()))
+                            .filter(|addr| !addr.is_empty());
+                        match forwarded_for.or_else(|| remote_addr.map(|addr| addr.0.ip().to_string())) {
+                            Some(addr) => format!("ip:{}", addr),
+                            None => return Ok(()),
+                        }
+                    }
+                };
+
+                rate_limits.check(route, &client)
+                    .map_err(|retry_after| warp::reject::custom(RateLimited { retry_after }))
+            }
+        })
+        .untuple_one()
+}
+
+#[derive(Debug)]
+struct RateLimited {
+    retry_after: Duration,
+}
+
+impl warp::reject::Reject for RateLimited {}
+
+/// Determines the [View] of a request from its optional `Authorization` header.
+fn with_view(config: &Config) -> impl Filter<Extract = (View,), Error = warp::Rejection> + Clone {
+    let config = config.clone();
+    warp::header::optional::<String>("authorization")
+        .map(move |authorization| View::from_authorization(&config, authorization))
+}
+
+/// Gets a player's stats in the given namespaces, or in every namespace if `namespaces` is `None`.
+async fn get_player_stats(config: Config, database: Address<StoreHandler>, uuid: Uuid, namespaces: Option<Vec<String>>, season: Option<String>, view: View, deadline: Instant) -> ApiResult {
+    if let Some(namespaces) = &namespaces {
+        if namespaces.is_empty() {
+            return Ok(send_http_status(StatusCode::BAD_REQUEST));
+        }
+        if !namespaces.iter().all(|namespace| view.can_see_namespace(&config, namespace)) {
+            return Ok(send_http_status(StatusCode::NOT_FOUND));
+        }
+    }
+
+    if view == View::Public {
+        match send(&database, GetPlayerProfile(uuid), deadline).await {
+            Ok(Some(profile)) if profile.private => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+            Ok(_) => {}
+            Err(e) => return Ok(handle_server_error(&e)),
+        }
+    }
+
+    let season = match resolve_season(&database, season.as_deref(), deadline).await {
+        Ok(season) => season,
+        Err(reply) => return Ok(reply),
+    };
+
+    // A single namespace is looked up by itself, and several are picked out of the player's stats in every namespace.
+    let namespace = match namespaces.as_deref() {
+        Some([namespace]) => Some(namespace.clone()),
+        _ => None,
+    };
+    let res = send(&database, GetPlayerStats {
+        uuid,
+        namespace,
+        season,
+    }, deadline).await;
+    match res {
+        Ok(stats) => {
+            Ok(if let Some(mut stats) = stats {
+                if let Some(namespaces) = &namespaces {
+                    stats.retain(|namespace, _| namespaces.contains(namespace));
+                }
+                let reply = Box::new(warp::reply::json(&view.filter_stats(&config, stats)));
+                with_cache_headers(&config, CacheClass::Stats, view, reply)
+            } else {
+                send_http_status(StatusCode::NOT_FOUND)
+            })
+        },
+        Err(e) => {
+            Ok(handle_server_error(&e))
+        }
+    }
+}
+
+async fn get_player_profile(config: Config, database: Address<StoreHandler>, usernames: UsernameResolver, uuid: Uuid, view: View, deadline: Instant) -> ApiResult {
+    let res = send(&database, GetPlayerProfile(uuid), deadline).await;
+    match res {
+        Ok(profile) => {
+            Ok(if let Some(mut profile) = profile {
+                resolve_username(&usernames, &mut profile, view, deadline).await;
+                let ratings = match send(&database, GetPlayerRatings(uuid), deadline).await {
+                    Ok(ratings) => ratings,
+                    Err(e) => return Ok(handle_server_error(&e)),
+                };
+                let reply = Box::new(warp::reply::json(&view.filter_profile(&config, profile, ratings)));
+                with_cache_headers(&config, CacheClass::Profiles, view, reply)
+            } else {
+                send_http_status(StatusCode::NOT_FOUND)
+            })
+        },
+        Err(e) => {
+            Ok(handle_server_error(&e))
+        }
+    }
+}
+
+/// Fills in the username of a player whose profile has none from Mojang, if lookups are enabled. Private players are
+/// skipped for the public view, which hides their username anyway, and anonymized players are never looked up.
+pub(crate) async fn resolve_username(usernames: &UsernameResolver, profile: &mut PlayerProfile, view: View, deadline: Instant) {
+    if profile.username.is_some() || profile.anonymized || (view == View::Public && profile.private) {
+        return;
+    }
+    profile.username = tokio::time::timeout_at(deadline, usernames.resolve(profile.uuid)).await.ok().flatten();
+}
+
+#[derive(Deserialize)]
+struct SeasonQuery {
+    season: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AllPlayerStatsQuery {
+    season: Option<String>,
+    /// Comma-separated namespaces to limit the stats to.
+    namespaces: Option<String>,
+}
+
+/// Resolves a `season` query parameter to a season number, or `None` for all-time stats. Returns the reply to send
+/// instead if the parameter is invalid or the current season can't be read.
+async fn resolve_season(database: &Address<StoreHandler>, season: Option<&str>, deadline: Instant) -> Result<Option<u32>, Box<dyn Reply>> {
+    match season {
+        None | Some("all-time") => Ok(None),
+        Some("current") => match send(database, GetCurrentSeason, deadline).await {
+            Ok(current) => Ok(Some(current.season)),
+            Err(e) => Err(handle_server_error(&e)),
+        },
+        Some(season) => match season.parse() {
+            Ok(season) if season > 0 => Ok(Some(season)),
+            _ => Err(send_http_status(StatusCode::BAD_REQUEST)),
+        },
+    }
+}
+
+async fn get_current_season(database: Address<StoreHandler>, deadline: Instant) -> ApiResult {
+    match send(&database, GetCurrentSeason, deadline).await {
+        Ok(season) => Ok(Box::new(warp::reply::json(&SeasonResponse::from(season)))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn start_season(config: Config, database: Address<StoreHandler>, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    match send(&database, StartSeason, deadline).await {
+        Ok(season) => Ok(Box::new(warp::reply::json(&SeasonResponse::from(season)))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct StatHistoryQuery {
+    stat: String,
+    from: Option<String>,
+    until: Option<String>,
+    #[serde(default = "default_stat_history_limit")]
+    limit: i64,
+}
+
+fn default_stat_history_limit() -> i64 {
+    500
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_stat_history(config: Config, database: Address<StoreHandler>, uuid: Uuid, namespace: String, query: StatHistoryQuery, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    if !(1..=5000).contains(&query.limit) || query.stat.is_empty() || query.stat.contains('.') || query.stat.starts_with('$') {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+    let (from, until) = match (parse_time(query.from.as_deref()), parse_time(query.until.as_deref())) {
+        (Ok(from), Ok(until)) => (from, until),
+        _ => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+
+    if view == View::Public {
+        match send(&database, GetPlayerProfile(uuid), deadline).await {
+            Ok(Some(profile)) if profile.private => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+            Ok(_) => {}
+            Err(e) => return Ok(handle_server_error(&e)),
+        }
+    }
+
+    let stat = config.canonical_stat_name(&namespace, &query.stat).to_string();
+    let res = send(&database, GetStatHistory {
+        uuid,
+        namespace,
+        stat: stat.clone(),
+        from,
+        until,
+        limit: query.limit,
+    }, deadline).await;
+
+    match res {
+        Ok(snapshots) => {
+            let history: Vec<StatHistoryPoint> = snapshots.into_iter()
+                .filter_map(|snapshot| snapshot.point(&stat))
+                .collect();
+            Ok(with_cache_headers(&config, CacheClass::Stats, view, Box::new(warp::reply::json(&history))))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct StatHistogramQuery {
+    stat: String,
+}
+
+/// Gets the buckets of a histogram stat of a player, or of the global stats if `uuid` is `None`.
+#[allow(clippy::too_many_arguments)]
+async fn get_stat_histogram(config: Config, database: Address<StoreHandler>, uuid: Option<Uuid>, namespace: String, query: StatHistogramQuery, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    if query.stat.is_empty() || query.stat.contains('.') || query.stat.starts_with('$') {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    if let (View::Public, Some(uuid)) = (view, uuid) {
+        match send(&database, GetPlayerProfile(uuid), deadline).await {
+            Ok(Some(profile)) if profile.private => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+            Ok(_) => {}
+            Err(e) => return Ok(handle_server_error(&e)),
+        }
+    }
+
+    let class = if uuid.is_some() { CacheClass::Stats } else { CacheClass::GlobalStats };
+    let stat = config.canonical_stat_name(&namespace, &query.stat).to_string();
+    match send(&database, GetStatHistogram { uuid, namespace, stat }, deadline).await {
+        Ok(Some(buckets)) => Ok(with_cache_headers(&config, class, view, Box::new(warp::reply::json(&buckets)))),
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaderboardQuery {
+    #[serde(default = "default_leaderboard_limit")]
+    limit: i64,
+    #[serde(default)]
+    order: LeaderboardOrder,
+    season: Option<String>,
+}
+
+fn default_leaderboard_limit() -> i64 {
+    10
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_leaderboard(config: Config, database: Address<StoreHandler>, cache: ResultCache, namespace: String, stat: String, query: LeaderboardQuery, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    if !(1..=100).contains(&query.limit) || stat.contains('.') || stat.starts_with('$') {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let season = match resolve_season(&database, query.season.as_deref(), deadline).await {
+        Ok(season) => season,
+        Err(reply) => return Ok(reply),
+    };
+
+    let key = CacheKey::Leaderboard {
+        namespace: namespace.clone(),
+        stat: stat.clone(),
+        limit: query.limit,
+        order: query.order,
+        season,
+        include_private: view == View::Full,
+    };
+    let res = cache.get_or_fetch(key, async {
+        send(&database, GetLeaderboard {
+            namespace,
+            stat,
+            limit: query.limit,
+            order: query.order,
+            season,
+            include_private: view == View::Full,
+        }, deadline).await.map(Some)
+    }).await;
+
+    match res {
+        Ok(Some(leaderboard)) => Ok(with_cache_headers(&config, CacheClass::Leaderboards, view, Box::new(warp::reply::json(&*leaderboard)))),
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct LeaderboardSnapshotQuery {
+    #[serde(default)]
+    order: LeaderboardOrder,
+    at: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_leaderboard_snapshot(config: Config, database: Address<StoreHandler>, namespace: String, stat: String, query: LeaderboardSnapshotQuery, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    let at = match parse_time(query.at.as_deref()) {
+        Ok(at) => at.unwrap_or_else(bson::DateTime::now),
+        Err(_) => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+
+    match send(&database, GetLeaderboardSnapshot { namespace, stat, order: query.order, at }, deadline).await {
+        Ok(Some(snapshot)) => {
+            let reply = Box::new(warp::reply::json(&LeaderboardSnapshotResponse::from(snapshot)));
+            Ok(with_cache_headers(&config, CacheClass::Leaderboards, view, reply))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct StatRankQuery {
+    #[serde(default)]
+    order: LeaderboardOrder,
+    season: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_stat_rank(config: Config, database: Address<StoreHandler>, uuid: Uuid, namespace: String, stat: String, query: StatRankQuery, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    if stat.contains('.') || stat.starts_with('$') {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    if view == View::Public {
+        match send(&database, GetPlayerProfile(uuid), deadline).await {
+            Ok(Some(profile)) if profile.private => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+            Ok(_) => {}
+            Err(e) => return Ok(handle_server_error(&e)),
+        }
+    }
+
+    let season = match resolve_season(&database, query.season.as_deref(), deadline).await {
+        Ok(season) => season,
+        Err(reply) => return Ok(reply),
+    };
+
+    let res = send(&database, GetStatRank {
+        uuid,
+        namespace,
+        stat,
+        order: query.order,
+        season,
+        include_private: view == View::Full,
+    }, deadline).await;
+    match res {
+        Ok(Some(rank)) => {
+            let reply = Box::new(warp::reply::json(&StatRankResponse::from(rank)));
+            Ok(with_cache_headers(&config, CacheClass::Leaderboards, view, reply))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_namespaces(config: Config, database: Address<StoreHandler>, view: View, deadline: Instant) -> ApiResult {
+    match send(&database, GetNamespaces, deadline).await {
+        Ok(namespaces) => {
+            let namespaces: Vec<String> = namespaces.into_iter()
+                .filter(|namespace| view.can_see_namespace(&config, namespace))
+                .collect();
+            Ok(with_cache_headers(&config, CacheClass::GlobalStats, view, Box::new(warp::reply::json(&namespaces))))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_stat_metadata(config: Config, database: Address<StoreHandler>, namespace: String, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+
+    match send(&database, GetStatInfo(namespace), deadline).await {
+        Ok(info) => Ok(with_cache_headers(&config, CacheClass::Stats, view, Box::new(warp::reply::json(&info)))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn update_stat_metadata(config: Config, database: Address<StoreHandler>, namespace: String, authorization: String, stats: StatInfoResponse, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_namespace_scope(&config, &authorization, TokenScope::UpdateStatMetadata, &namespace) {
+        return Ok(send_http_status(status));
+    }
+    let violations = validation::validate_stat_names(&config.validation, stats.keys());
+    if !violations.is_empty() {
+        return Ok(send_violations(violations));
+    }
+
+    match send(&database, UpdateStatInfo { namespace, stats }, deadline).await {
+        Ok(()) => Ok(Box::new(warp::reply::with_status("", StatusCode::NO_CONTENT))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_achievements(config: Config, database: Address<StoreHandler>, namespace: String, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+
+    let info = match send(&database, GetAchievementInfo(namespace.clone()), deadline).await {
+        Ok(info) => info,
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+    let unlocks = match send(&database, GetAchievementUnlocks(namespace), deadline).await {
+        Ok(unlocks) => unlocks,
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+
+    let achievements = info.into_iter()
+        .map(|(achievement, info)| {
+            let unlocked_by = unlocks.unlocks.get(&achievement).copied().unwrap_or(0);
+            let unlock_percent = if unlocks.players == 0 { 0.0 } else { unlocked_by as f64 * 100.0 / unlocks.players as f64 };
+            (achievement, AchievementSummary {
+                display_name: info.display_name,
+                description: info.description,
+                unlocked_by,
+                unlock_percent,
+            })
+        })
+        .collect();
+    let response = AchievementsResponse { players: unlocks.players, achievements };
+    Ok(with_cache_headers(&config, CacheClass::GlobalStats, view, Box::new(warp::reply::json(&response))))
+}
+
+async fn update_achievements(config: Config, database: Address<StoreHandler>, namespace: String, authorization: String, achievements: HashMap<String, AchievementInfo>, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_namespace_scope(&config, &authorization, TokenScope::UpdateAchievements, &namespace) {
+        return Ok(send_http_status(status));
+    }
+    let violations = validation::validate_achievement_names(&config.validation, achievements.keys());
+    if !violations.is_empty() {
+        return Ok(send_violations(violations));
+    }
+
+    match send(&database, UpdateAchievementInfo { namespace, achievements }, deadline).await {
+        Ok(()) => Ok(Box::new(warp::reply::with_status("", StatusCode::NO_CONTENT))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_global_stats(config: Config, database: Address<StoreHandler>, cache: ResultCache, namespace: String, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+
+    let key = CacheKey::GlobalStats(namespace.clone());
+    match cache.get_or_fetch(key, send(&database, GetGlobalStats(namespace), deadline)).await {
+        Ok(Some(stats)) => Ok(with_cache_headers(&config, CacheClass::GlobalStats, view, Box::new(warp::reply::json(&*stats)))),
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct StatSummaryQuery {
+    stat: String,
+    season: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn get_stat_summary(config: Config, database: Address<StoreHandler>, cache: ResultCache, namespace: String, query: StatSummaryQuery, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    let stat = config.canonical_stat_name(&namespace, &query.stat).to_string();
+    if stat.contains('.') || stat.starts_with('$') {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let season = match resolve_season(&database, query.season.as_deref(), deadline).await {
+        Ok(season) => season,
+        Err(reply) => return Ok(reply),
+    };
+
+    let key = CacheKey::StatSummary { namespace: namespace.clone(), stat: stat.clone(), season };
+    let res = cache.get_or_fetch(key, send(&database, GetStatSummary {
+        namespace,
+        stat,
+        season,
+    }, deadline)).await;
+    match res {
+        Ok(Some(summary)) => Ok(with_cache_headers(&config, CacheClass::GlobalStats, view, Box::new(warp::reply::json(&*summary)))),
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_server_stats(config: Config, database: Address<StoreHandler>, server_name: String, view: View, deadline: Instant) -> ApiResult {
+    let stats = match send(&database, GetServerStats(server_name.clone()), deadline).await {
+        Ok(stats) => stats,
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+    let namespaces: HashMap<_, _> = stats.into_iter()
+        .filter(|stats| view.can_see_namespace(&config, &stats.namespace))
+        .map(|stats| (stats.namespace.clone(), ServerNamespaceSummary::from(stats)))
+        .collect();
+    if namespaces.is_empty() {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    Ok(Box::new(warp::reply::json(&ServerStatsSummary { server_name, namespaces })))
+}
+
+async fn get_username_history(config: Config, database: Address<StoreHandler>, uuid: Uuid, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::ReadPrivate) {
+        return Ok(send_http_status(status));
+    }
+    let profile = match send(&database, GetPlayerProfile(uuid), deadline).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+    let history = match send(&database, GetUsernameHistory(uuid), deadline).await {
+        Ok(history) => history,
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+
+    // Newest first, starting with the player's current name.
+    let names: Vec<UsernameHistoryEntry> = profile.current_username().into_iter()
+        .chain(history.into_iter().rev().map(UsernameHistoryEntry::from))
+        .collect();
+    Ok(Box::new(warp::reply::json(&names)))
+}
+
+/// Longest play session that can be reported, so that a broken server can't add implausible amounts of playtime.
+const MAX_SESSION_SECS: u64 = 24 * 60 * 60;
+
+async fn report_playtime(config: Config, database: Address<StoreHandler>, uuid: Uuid, authorization: String, report: PlaytimeReport, deadline: Instant) -> ApiResult {
+    let namespace = config.canonical_namespace(&report.namespace).to_string();
+    if let Some(status) = missing_namespace_scope(&config, &authorization, TokenScope::UploadStats, &namespace) {
+        return Ok(send_http_status(status));
+    }
+    if report.seconds == 0 || report.seconds > MAX_SESSION_SECS {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let res = send(&database, AddPlaytime {
+        uuid,
+        namespace,
+        seconds: report.seconds,
+    }, deadline).await;
+    match res {
+        Ok(()) => Ok(Box::new(warp::reply::with_status("", StatusCode::NO_CONTENT))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_playtime(config: Config, database: Address<StoreHandler>, uuid: Uuid, view: View, deadline: Instant) -> ApiResult {
+    match send(&database, GetPlayerProfile(uuid), deadline).await {
+        Ok(Some(profile)) if !profile.private || view == View::Full => {}
+        Ok(_) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => return Ok(handle_server_error(&e)),
+    }
+    let playtime = match send(&database, GetPlaytime(uuid), deadline).await {
+        Ok(playtime) => playtime,
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+
+    let mut response = PlaytimeResponse::default();
+    for playtime in playtime.into_iter().filter(|playtime| view.can_see_namespace(&config, &playtime.namespace)) {
+        response.total_seconds += playtime.seconds as u64;
+        response.namespaces.insert(playtime.namespace.clone(), playtime.into());
+    }
+    Ok(with_cache_headers(&config, CacheClass::Profiles, view, Box::new(warp::reply::json(&response))))
+}
+
+async fn grant_achievements(config: Config, database: Address<StoreHandler>, uuid: Uuid, authorization: String, request: GrantAchievementsRequest, deadline: Instant) -> ApiResult {
+    let namespace = config.canonical_namespace(&request.namespace).to_string();
+    if let Some(status) = missing_namespace_scope(&config, &authorization, TokenScope::UploadStats, &namespace) {
+        return Ok(send_http_status(status));
+    }
+    let registered = match send(&database, GetAchievementInfo(namespace.clone()), deadline).await {
+        Ok(registered) => registered,
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+    let mut achievements = request.achievements;
+    achievements.sort();
+    achievements.dedup();
+    let violations: Vec<Violation> = achievements.iter()
+        .filter(|achievement| !registered.contains_key(*achievement))
+        .map(|achievement| Violation {
+            field: "achievements".to_string(),
+            message: format!("'{}' is not registered for {}", achievement, namespace),
+        })
+        .collect();
+    if !violations.is_empty() {
+        return Ok(send_violations(violations));
+    }
+
+    match send(&database, GrantAchievements { uuid, namespace, achievements }, deadline).await {
+        Ok(granted) => Ok(Box::new(warp::reply::json(&GrantAchievementsResponse { granted }))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn record_match(config: Config, database: Address<StoreHandler>, namespace: String, authorization: String, request: MatchResultRequest, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_namespace_scope(&config, &authorization, TokenScope::UploadGames, &namespace) {
+        return Ok(send_http_status(status));
+    }
+
+    let teams = match request {
+        MatchResultRequest { winner: Some(winner), loser: Some(loser), teams } if teams.is_empty() => vec![
+            MatchTeam { players: vec![winner], placement: 1 },
+            MatchTeam { players: vec![loser], placement: 2 },
+        ],
+        MatchResultRequest { winner: None, loser: None, teams } => teams,
+        _ => return Ok(send_violations(vec![Violation {
+            field: "teams".to_string(),
+            message: "give either a winner and a loser, or teams".to_string(),
+        }])),
+    };
+    let violations = validation::validate_match(&config.validation, &namespace, &teams);
+    if !violations.is_empty() {
+        return Ok(send_violations(violations));
+    }
+
+    match send(&database, RecordMatch { namespace, teams }, deadline).await {
+        Ok(ratings) => Ok(Box::new(warp::reply::json(&MatchResultResponse { ratings }))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct RatingLeaderboardQuery {
+    #[serde(default = "default_leaderboard_limit")]
+    limit: i64,
+}
+
+async fn get_rating_leaderboard(config: Config, database: Address<StoreHandler>, namespace: String, query: RatingLeaderboardQuery, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    if !(1..=100).contains(&query.limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let res = send(&database, GetRatingLeaderboard {
+        namespace,
+        limit: query.limit,
+        include_private: view == View::Full,
+    }, deadline).await;
+    match res {
+        Ok(leaderboard) => Ok(with_cache_headers(&config, CacheClass::Leaderboards, view, Box::new(warp::reply::json(&leaderboard)))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct PlayerAchievementsQuery {
+    namespace: Option<String>,
+}
+
+async fn get_player_achievements(config: Config, database: Address<StoreHandler>, uuid: Uuid, query: PlayerAchievementsQuery, view: View, deadline: Instant) -> ApiResult {
+    let namespace = query.namespace.map(|namespace| config.canonical_namespace(&namespace).to_string());
+    if namespace.as_ref().is_some_and(|namespace| !view.can_see_namespace(&config, namespace)) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    match send(&database, GetPlayerProfile(uuid), deadline).await {
+        Ok(Some(profile)) if !profile.private || view == View::Full => {}
+        Ok(_) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => return Ok(handle_server_error(&e)),
+    }
+
+    match send(&database, GetPlayerAchievements { uuid, namespace }, deadline).await {
+        Ok(achievements) => {
+            let achievements: Vec<UnlockedAchievement> = achievements.into_iter()
+                .filter(|achievement| view.can_see_namespace(&config, &achievement.namespace))
+                .map(UnlockedAchievement::from)
+                .collect();
+            Ok(with_cache_headers(&config, CacheClass::Stats, view, Box::new(warp::reply::json(&achievements))))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_player_by_name(config: Config, database: Address<StoreHandler>, username: String, view: View, deadline: Instant) -> ApiResult {
+    match send(&database, GetPlayerProfileByName(username), deadline).await {
+        // Resolving the name of a private player would reveal the username that their profile hides.
+        Ok(Some(profile)) if view == View::Public && profile.private => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Ok(Some(profile)) => {
+            let ratings = match send(&database, GetPlayerRatings(profile.uuid), deadline).await {
+                Ok(ratings) => ratings,
+                Err(e) => return Ok(handle_server_error(&e)),
+            };
+            let reply = Box::new(warp::reply::json(&view.filter_profile(&config, profile, ratings)));
+            Ok(with_cache_headers(&config, CacheClass::Profiles, view, reply))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct PlayerSearchQuery {
+    q: String,
+    #[serde(default = "default_player_search_limit")]
+    limit: i64,
+}
+
+fn default_player_search_limit() -> i64 {
+    10
+}
+
+/// Longest username prefix that can be searched for, as no Minecraft username is longer.
+const MAX_SEARCH_PREFIX_LENGTH: usize = 16;
+
+async fn search_players(config: Config, database: Address<StoreHandler>, query: PlayerSearchQuery, view: View, deadline: Instant) -> ApiResult {
+    let prefix = query.q.trim();
+    if prefix.is_empty() || prefix.chars().count() > MAX_SEARCH_PREFIX_LENGTH || !(1..=50).contains(&query.limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    // Private players are left out of public searches, as their usernames are hidden.
+    let search = SearchPlayers { prefix: prefix.to_string(), include_private: view != View::Public, limit: query.limit };
+    match send(&database, search, deadline).await {
+        Ok(players) => {
+            let players: Vec<PlayerProfileResponse> = players.into_iter()
+                .map(|profile| view.filter_profile(&config, profile, Vec::new()))
+                .collect();
+            Ok(with_cache_headers(&config, CacheClass::Profiles, view, Box::new(warp::reply::json(&players))))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct GlobalStatsDeltaQuery {
+    #[serde(default = "default_delta_window")]
+    window: String,
+}
+
+fn default_delta_window() -> String {
+    "24h".to_string()
+}
+
+async fn get_global_stats_delta(config: Config, database: Address<StoreHandler>, cache: ResultCache, namespace: String, window: String, view: View, deadline: Instant) -> ApiResult {
+    if !view.can_see_namespace(&config, &namespace) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+
+    let window_hours = match parse_window_hours(&window) {
+        Some(hours) if hours > 0 && hours <= config.global_stats_rollup_retention_hours => hours,
+        _ => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+
+    let key = CacheKey::GlobalStatsDelta { namespace: namespace.clone(), window_hours };
+    let res = cache.get_or_fetch(key, async {
+        send(&database, GetGlobalStatsDelta {
+            namespace,
+            window_hours,
+        }, deadline).await.map(Some)
+    }).await;
+
+    match res {
+        Ok(Some(delta)) => Ok(with_cache_headers(&config, CacheClass::GlobalStats, view, Box::new(warp::reply::json(&*delta)))),
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+/// Parses a window such as `1h` or `7d` into a number of hours.
+fn parse_window_hours(window: &str) -> Option<u32> {
+    if let Some(hours) = window.strip_suffix('h') {
+        hours.parse().ok()
+    } else if let Some(days) = window.strip_suffix('d') {
+        days.parse::<u32>().ok()?.checked_mul(24)
+    } else {
+        None
+    }
+}
+
+async fn upload_game(config: Config, database: Address<StoreHandler>, authorization: String, request: GameUploadRequest, deadline: Instant) -> ApiResult {
+    let namespace = config.canonical_namespace(&request.namespace).to_string();
+    if let Some(status) = missing_namespace_scope(&config, &authorization, TokenScope::UploadGames, &namespace) {
+        return Ok(send_http_status(status));
+    }
+
+    let (started_at, ended_at) = match (parse_time(Some(&request.started_at)), parse_time(Some(&request.ended_at))) {
+        (Ok(Some(started_at)), Ok(Some(ended_at))) if started_at <= ended_at => (started_at, ended_at),
+        _ => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+    if request.participants.iter().any(|participant| participant.score.is_some_and(|score| !score.is_finite())) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let game = Game {
+        id: ObjectId::new(),
+        server_name: request.server_name,
+        namespace,
+        started_at,
+        ended_at,
+        participants: request.participants.into_iter()
+            .map(|participant| StoredGameParticipant {
+                uuid: participant.uuid,
+                winner: participant.winner,
+                score: participant.score,
+            })
+            .collect(),
+    };
+    let id = game.id.to_hex();
+
+    match send(&database, InsertGame(game), deadline).await {
+        Ok(()) => Ok(Box::new(warp::reply::with_status(warp::reply::json(&GameUploadResponse { id }), StatusCode::CREATED))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct GamesQuery {
+    namespace: Option<String>,
+    #[serde(default = "default_games_limit")]
+    limit: i64,
+}
+
+fn default_games_limit() -> i64 {
+    20
+}
+
+/// Lists recent games, or the recent games of one player.
+async fn get_games(config: Config, database: Address<StoreHandler>, player: Option<Uuid>, query: GamesQuery, view: View, deadline: Instant) -> ApiResult {
+    if !(1..=100).contains(&query.limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+    let namespace = query.namespace.map(|namespace| config.canonical_namespace(&namespace).to_string());
+    if namespace.as_ref().is_some_and(|namespace| !view.can_see_namespace(&config, namespace)) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+
+    if let (Some(player), View::Public) = (player, view) {
+        match send(&database, GetPlayerProfile(player), deadline).await {
+            Ok(Some(profile)) if profile.private => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+            Ok(_) => {}
+            Err(e) => return Ok(handle_server_error(&e)),
+        }
+    }
+
+    let res = send(&database, GetGames {
+        player,
+        namespace,
+        hidden_namespaces: if view == View::Public { config.internal_namespaces.clone() } else { Vec::new() },
+        limit: query.limit,
+        include_private: view == View::Full,
+    }, deadline).await;
+
+    match res {
+        Ok(games) => {
+            let games: Vec<GameResponse> = games.into_iter().map(GameResponse::from).collect();
+            Ok(with_cache_headers(&config, CacheClass::Stats, view, Box::new(warp::reply::json(&games))))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+/// Runs a GraphQL request. Errors in resolving fields are reported in the response body, which is always sent with a
+/// 200.
+async fn execute_graphql(schema: GraphQlSchema, request: async_graphql::Request, view: View, deadline: Instant) -> ApiResult {
+    let response = schema.execute(request.data(view).data(deadline)).await;
+    Ok(Box::new(warp::reply::json(&response)))
+}
+
+async fn update_player_profile(config: Config, database: Address<StoreHandler>, uuid: Uuid, authorization: String, request: UpdatePlayerProfileRequest, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::UpdateProfiles) {
+        return Ok(send_http_status(status));
+    }
+
+    let res = send(&database, UpdatePlayerProfile {
+        uuid,
+        username: request.username,
+        private: request.private,
+    }, deadline).await;
+
+    match res {
+        Ok(_) => Ok(Box::new(warp::reply::with_status("", StatusCode::NO_CONTENT))),
+        Err(e) => Ok(handle_server_error(&e))
+    }
+}
+
+async fn delete_player(config: Config, database: Address<StoreHandler>, cache: ResultCache, uuid: Uuid, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    match send(&database, DeletePlayerData(uuid), deadline).await {
+        Ok(report) => {
+            log::info!("Deleted the data of player {} from {} namespaces", uuid, report.namespaces.len());
+            // Cached leaderboards could still list the player.
+            for namespace in &report.namespaces {
+                cache.invalidate(namespace).await;
+            }
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn anonymize_player(config: Config, database: Address<StoreHandler>, cache: ResultCache, uuid: Uuid, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    match send(&database, AnonymizePlayer(uuid), deadline).await {
+        Ok(report) if !report.profile => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Ok(report) => {
+            log::info!("Anonymized player {}, keeping their stats in {} namespaces", uuid, report.namespaces.len());
+            // Cached leaderboards could still show the player's username.
+            for namespace in &report.namespaces {
+                cache.invalidate(namespace).await;
+            }
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct PlayersQuery {
+    #[serde(default = "default_players_limit")]
+    limit: i64,
+    /// The `next` cursor of the previous page.
+    after: Option<String>,
+    /// `uuid` or `last_seen`, defaulting to `uuid`.
+    sort: Option<String>,
+}
+
+fn default_players_limit() -> i64 {
+    100
+}
+
+async fn list_players(config: Config, database: Address<StoreHandler>, authorization: String, query: PlayersQuery, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let order = match query.sort.as_deref() {
+        None | Some("uuid") => PlayerOrder::Uuid,
+        Some("last_seen") => PlayerOrder::LastSeen,
+        Some(_) => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+    let after = match query.after.as_deref().map(parse_player_cursor) {
+        Some(Some(after)) => Some(after),
+        Some(None) => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+        None => None,
+    };
+    if !(1..=1000).contains(&query.limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    // One more player than asked for is fetched to find out whether there is another page.
+    let mut players = match send(&database, GetPlayers { order, after, limit: query.limit + 1 }, deadline).await {
+        Ok(players) => players,
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+    let next = if players.len() as i64 > query.limit {
+        players.truncate(query.limit as usize);
+        players.last().map(|last| player_cursor(order, last))
+    } else {
+        None
+    };
+    Ok(Box::new(warp::reply::json(&PlayerListResponse {
+        players: players.into_iter().map(PlayerProfileResponse::from).collect(),
+        next,
+    })))
+}
+
+/// Encodes where a page of players ends as `{uuid}`, followed by `@{last_seen millis}` when listing by when players
+/// were last seen.
+fn player_cursor(order: PlayerOrder, last: &PlayerProfile) -> String {
+    match (order, last.last_seen) {
+        (PlayerOrder::LastSeen, Some(last_seen)) => format!("{}@{}", last.uuid, last_seen.timestamp_millis()),
+        _ => last.uuid.to_string(),
+    }
+}
+
+fn parse_player_cursor(cursor: &str) -> Option<PlayerCursor> {
+    let (uuid, last_seen) = match cursor.split_once('@') {
+        Some((uuid, last_seen)) => (uuid, Some(bson::DateTime::from_millis(last_seen.parse().ok()?))),
+        None => (cursor, None),
+    };
+    Some(PlayerCursor { uuid: Uuid::parse_str(uuid).ok()?, last_seen })
+}
+
+/// Header set on the response to an upload whose bundle had already been applied.
+const DUPLICATE_BUNDLE_HEADER: &str = "x-duplicate-bundle";
+/// Header set on the response to an upload whose bundle was quarantined, to the ID it was quarantined under.
+const QUARANTINED_UPLOAD_HEADER: &str = "x-quarantined-upload";
+
+#[derive(Deserialize)]
+struct UploadQuery {
+    /// Whether to only check the bundle against the stored stats, without writing anything.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+async fn upload_game_stats(ingest: Ingest, authorization: String, game_stats: GameStatsBundle, dry_run: bool) -> ApiResult {
+    let result = if dry_run {
+        match ingest.dry_run(authorization, game_stats).await {
+            Ok(report) => return Ok(Box::new(warp::reply::json(&report))),
+            Err(result) => result,
+        }
+    } else {
+        ingest.upload(authorization, game_stats).await
+    };
+    Ok(match result {
+        UploadResult::Applied => Box::new(warp::reply::with_status("", StatusCode::NO_CONTENT)),
+        UploadResult::Duplicate => {
+            let reply = warp::reply::with_status("", StatusCode::NO_CONTENT);
+            Box::new(warp::reply::with_header(reply, DUPLICATE_BUNDLE_HEADER, "true"))
+        }
+        UploadResult::Queued(id) => Box::new(warp::reply::with_status(warp::reply::json(&UploadQueuedResponse { id }), StatusCode::ACCEPTED)),
+        UploadResult::Spooled => send_http_status(StatusCode::ACCEPTED),
+        UploadResult::Quarantined(id) => {
+            let reply = warp::reply::with_status("", StatusCode::ACCEPTED);
+            Box::new(warp::reply::with_header(reply, QUARANTINED_UPLOAD_HEADER, id.to_hex()))
+        }
+        UploadResult::Unauthorized(status) => send_http_status(status),
+        UploadResult::Invalid(violations) => send_violations(violations),
+        UploadResult::Rejected(error) => {
+            let error = ValidationErrorResponse { error, violations: Vec::new() };
+            Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::BAD_REQUEST))
+        }
+        UploadResult::TypeMismatch(error) => {
+            Box::new(warp::reply::with_status(warp::reply::json(&ErrorResponse { error }), StatusCode::UNPROCESSABLE_ENTITY))
+        }
+        UploadResult::Unavailable => send_http_status(StatusCode::SERVICE_UNAVAILABLE),
+        UploadResult::Failed(e) => handle_server_error(&e),
+    })
+}
+
+/// Accepts stats uploads from game servers, whichever transport they arrive through.
+#[derive(Clone)]
+pub struct Ingest {
+    pub(crate) config: Config,
+    pub(crate) database: Address<StoreHandler>,
+    pub(crate) metrics: Metrics,
+    processors: Processors,
+    pub(crate) limits: RouteLimits,
+    uploads: UploadTracker,
+    spool: Option<Spool>,
+    queue: Option<UploadQueue>,
+    reporter: Reporter,
+}
+
+/// What became of an uploaded bundle.
+pub(crate) enum UploadResult {
+    Applied,
+    /// The bundle had already been applied, so it was ignored.
+    Duplicate,
+    /// The bundle was added to the upload queue, under this ID.
+    Queued(Uuid),
+    /// The bundle was saved to the spool, to be applied later.
+    Spooled,
+    /// The bundle added more to a stat than its `max_increment`, so it was quarantined under this ID instead of being
+    /// applied.
+    Quarantined(ObjectId),
+    /// The token is unknown, or doesn't have the `upload_stats` scope, as told by the HTTP status.
+    Unauthorized(StatusCode),
+    Invalid(Vec<Violation>),
+    /// A processor rejected the bundle.
+    Rejected(String),
+    /// A stat was uploaded with a different type than it is stored with.
+    TypeMismatch(String),
+    /// The bundle couldn't be written or spooled, e.g. because the backend is shutting down, or another upload of it
+    /// hasn't finished.
+    Unavailable,
+    Failed(anyhow::Error),
+}
+
+impl Ingest {
+    /// Checks, processes and applies an uploaded bundle, or queues or spools it to be applied later.
+    pub(crate) async fn upload(&self, authorization: String, mut game_stats: GameStatsBundle) -> UploadResult {
+        let Ingest { config, database, processors, limits, uploads, spool, queue, .. } = self.clone();
+        let deadline = limits.deadline("upload_stats");
+        let increments = match self.prepare(&authorization, &mut game_stats) {
+            Ok(increments) => increments,
+            Err(result) => return result,
+        };
+        if !increments.is_empty() {
+            return self.quarantine(&authorization, &game_stats, increments, deadline).await;
+        }
+
+        // Queued uploads are limited by the number of queue workers instead of a concurrency slot.
+        if let Some(queue) = queue {
+            let tracked = match uploads.begin(game_stats.clone()) {
+                Some(tracked) => tracked,
+                None => return UploadResult::Unavailable,
+            };
+            return match queue.enqueue(QueuedUpload { authorization, bundle: game_stats, tracked }) {
+                Ok(id) => UploadResult::Queued(id),
+                Err(QueueFull(upload)) => {
+                    log::debug!("upload queue is full, spooling bundle from '{}'", upload.bundle.server_name);
+                    spool_upload(&config, &database, spool.as_ref(), &upload.authorization, &upload.bundle, deadline).await
+                }
+            };
+        }
+
+        let _permit = match limits.acquire("upload_stats").await {
+            Some(permit) => permit,
+            None => return spool_upload(&config, &database, spool.as_ref(), &authorization, &game_stats, deadline).await,
+        };
+
+        let upload = match uploads.begin_started(game_stats.clone()) {
+            Some(upload) => upload,
+            None => return UploadResult::Unavailable,
+        };
+
+        // The write runs in its own task so that it finishes even if the client disconnects.
+        let server_name = game_stats.server_name.clone();
+        let res = tokio::spawn(async move {
+            let _upload = upload;
+            apply_upload(config, database, processors, authorization, game_stats, deadline).await
+        }.in_current_span()).await;
+        match res.map_err(anyhow::Error::from).and_then(|res| res) {
+            Ok(BundleOutcome::Applied) => UploadResult::Applied,
+            Ok(BundleOutcome::Duplicate) => {
+                log::debug!("ignoring bundle from '{}' that was already applied", server_name);
+                UploadResult::Duplicate
+            }
+            Err(e) if e.is::<BundlePending>() => {
+                log::debug!("refusing bundle from '{}' for now: {}", server_name, e);
+                UploadResult::Unavailable
+            }
+            Err(e) => match e.downcast_ref::<StatTypeMismatch>() {
+                Some(mismatch) => {
+                    log::debug!("rejecting bundle from '{}': {}", server_name, mismatch);
+                    UploadResult::TypeMismatch(mismatch.to_string())
+                }
+                None => UploadResult::Failed(e),
+            },
+        }
+    }
+
+    /// Checks what uploading a bundle would do without writing anything, returning the result to reply with instead if
+    /// it would be refused.
+    pub(crate) async fn dry_run(&self, authorization: String, mut game_stats: GameStatsBundle) -> Result<UploadDryRunResponse, UploadResult> {
+        let deadline = self.limits.deadline("upload_stats");
+        let violations = self.prepare(&authorization, &mut game_stats)?;
+        if !violations.is_empty() {
+            return Ok(UploadDryRunResponse { outcome: UploadDryRunOutcome::Quarantined, violations });
+        }
+
+        let server_name = game_stats.server_name.clone();
+        let outcome = match send(&self.database, CheckStatsBundle(game_stats), deadline).await {
+            Ok(BundleOutcome::Applied) => UploadDryRunOutcome::Applied,
+            Ok(BundleOutcome::Duplicate) => UploadDryRunOutcome::Duplicate,
+            Err(e) if e.is::<BundlePending>() => return Err(UploadResult::Unavailable),
+            Err(e) => return Err(match e.downcast_ref::<StatTypeMismatch>() {
+                Some(mismatch) => {
+                    log::debug!("dry run of bundle from '{}' would be rejected: {}", server_name, mismatch);
+                    UploadResult::TypeMismatch(mismatch.to_string())
+                }
+                None => UploadResult::Failed(e),
+            }),
+        };
+        Ok(UploadDryRunResponse { outcome, violations: Vec::new() })
+    }
+
+    /// Resolves the names in a bundle and checks and processes it, returning the `max_increment`s that it breaks, or
+    /// the result to reply with instead if it is refused.
+    fn prepare(&self, authorization: &str, game_stats: &mut GameStatsBundle) -> Result<Vec<Violation>, UploadResult> {
+        let Ingest { config, metrics, processors, .. } = self;
+        game_stats.namespace = config.canonical_namespace(&game_stats.namespace).to_string();
+        if let Some(status) = missing_namespace_scope(config, authorization, TokenScope::UploadStats, &game_stats.namespace) {
+            return Err(UploadResult::Unauthorized(status));
+        }
+
+        if let Some(global) = game_stats.stats.global.take() {
+            game_stats.stats.global = Some(resolve_stat_names(config, metrics, game_stats, global));
+        }
+        let players = std::mem::take(&mut game_stats.stats.players);
+        game_stats.stats.players = players.into_iter()
+            .map(|(player, stats)| (player, resolve_stat_names(config, metrics, game_stats, stats)))
+            .collect();
+
+        if let Some(global) = &game_stats.stats.global {
+            log::debug!("server '{}' uploaded {} player statistics and {} global statistics in statistics bundle for {}",
+                    game_stats.server_name, game_stats.stats.players.len(), global.len(), game_stats.namespace);
+        } else {
+            log::debug!("server '{}' uploaded {} player statistics in statistics bundle for {}",
+                    game_stats.server_name, game_stats.stats.players.len(), game_stats.namespace);
+        }
+
+        let violations = validation::validate_bundle(&config.validation, game_stats);
+        if !violations.is_empty() {
+            log::debug!("rejecting bundle from '{}' for {}: {} limits broken", game_stats.server_name, game_stats.namespace, violations.len());
+            return Err(UploadResult::Invalid(violations));
+        }
+
+        if let Err(e) = processors.process(game_stats) {
+            log::debug!("rejecting bundle from '{}': {}", game_stats.server_name, e);
+            return Err(UploadResult::Rejected(e.to_string()));
+        }
+
+        Ok(validation::check_increments(config, game_stats))
+    }
+
+    /// Keeps a bundle that broke a stat's `max_increment` aside for an admin to look at, and alerts about it.
+    async fn quarantine(&self, authorization: &str, game_stats: &GameStatsBundle, violations: Vec<Violation>, deadline: Instant) -> UploadResult {
+        let token = self.config.token(authorization).map(|token| token.name).unwrap_or_default();
+        let upload = SuspiciousUpload::new(&token, game_stats, violations);
+        let id = upload.id;
+        let details = upload.violations.iter()
+            .map(|violation| format!("{}: {}", violation.field, violation.message))
+            .collect::<Vec<_>>()
+            .join("\n");
+        if let Err(e) = send(&self.database, QuarantineUpload(upload), deadline).await {
+            return UploadResult::Failed(e);
+        }
+
+        log::warn!("quarantined suspicious bundle {} for {} from '{}':\n{}", id, game_stats.namespace, game_stats.server_name, details);
+        self.metrics.increment("nucleoid_suspicious_uploads_total", &[("namespace", &game_stats.namespace)]);
+        self.reporter.report(Alert {
+            title: format!("Quarantined suspicious upload {} from '{}'", id, game_stats.server_name),
+            namespace: Some(game_stats.namespace.clone()),
+            player: None,
+            error: details,
+        });
+        UploadResult::Quarantined(id)
+    }
+}
+
+/// Writes an accepted bundle to the database, then logs the upload and hands the bundle to the processors if it was
+/// applied.
+async fn apply_upload(config: Config, database: Address<StoreHandler>, processors: Processors, authorization: String, game_stats: GameStatsBundle, deadline: Instant) -> anyhow::Result<BundleOutcome> {
+    let outcome = send(&database, UploadStatsBundle(game_stats.clone()), deadline).await?;
+    if outcome == BundleOutcome::Applied {
+        log_upload(&config, &database, &authorization, &game_stats, false, deadline).await;
+        tokio::spawn(async move { processors.bundle_applied(&game_stats).await }.in_current_span());
+    }
+    Ok(outcome)
+}
+
+/// Applies an upload from the upload queue, returning the state and error that its status is left with.
+async fn apply_queued_upload(config: Config, database: Address<StoreHandler>, processors: Processors, upload: QueuedUpload, deadline: Instant) -> (UploadState, Option<String>) {
+    let QueuedUpload { authorization, bundle, tracked } = upload;
+    if !tracked.start() {
+        // Shutdown gave up waiting for the upload and spooled it, so it is applied when the spool is replayed.
+        return (UploadState::Queued, Some("the backend shut down before the upload was applied, it will be applied later".to_string()));
+    }
+    let server_name = bundle.server_name.clone();
+    match apply_upload(config, database, processors, authorization, bundle, deadline).await {
+        Ok(BundleOutcome::Applied) => (UploadState::Applied, None),
+        Ok(BundleOutcome::Duplicate) => (UploadState::Duplicate, None),
+        Err(e) => match e.downcast_ref::<StatTypeMismatch>() {
+            Some(mismatch) => {
+                log::debug!("rejecting queued bundle from '{}': {}", server_name, mismatch);
+                (UploadState::Rejected, Some(mismatch.to_string()))
+            }
+            None => {
+                log::warn!("failed to apply queued bundle from '{}': {:?}", server_name, e);
+                (UploadState::Failed, Some(e.to_string()))
+            }
+        },
+    }
+}
+
+async fn get_upload_status(config: Config, queue: Option<UploadQueue>, authorization: String, id: Uuid) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::UploadStats) {
+        return Ok(send_http_status(status));
+    }
+    match queue.and_then(|queue| queue.status(&id)) {
+        Some(status) => Ok(Box::new(warp::reply::json(&status))),
+        None => Ok(send_http_status(StatusCode::NOT_FOUND)),
+    }
+}
+
+/// Saves an upload to the spool when it can't be written straight away.
+async fn spool_upload(config: &Config, database: &Address<StoreHandler>, spool: Option<&Spool>, authorization: &str, game_stats: &GameStatsBundle, deadline: Instant) -> UploadResult {
+    let spool = match spool {
+        Some(spool) => spool,
+        None => return UploadResult::Unavailable,
+    };
+    match spool.write(game_stats).await {
+        Ok(path) => {
+            log::info!("spooled bundle for {} from '{}' to {}", game_stats.namespace, game_stats.server_name, path.display());
+            log_upload(config, database, authorization, game_stats, true, deadline).await;
+            UploadResult::Spooled
+        }
+        Err(e) => {
+            log::warn!("failed to spool bundle for {} from '{}': {}", game_stats.namespace, game_stats.server_name, e);
+            UploadResult::Unavailable
+        }
+    }
+}
+
+/// Records an accepted upload in the upload log. The upload has already been accepted, so failing to record it is only
+/// logged.
+async fn log_upload(config: &Config, database: &Address<StoreHandler>, authorization: &str, game_stats: &GameStatsBundle, spooled: bool, deadline: Instant) {
+    if !config.upload_log.enabled {
+        return;
+    }
+    let token = config.token(authorization).map(|token| token.name).unwrap_or_default();
+    let entry = UploadLogEntry::new(&token, game_stats, spooled);
+    if let Err(e) = send(database, LogUpload(entry), deadline).await {
+        log::warn!("failed to log upload for {} from '{}': {}", game_stats.namespace, game_stats.server_name, e);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn convert_stat(config: Config, database: Address<StoreHandler>, cache: ResultCache, leases: Leases, namespace: String, authorization: String, request: ConvertStatRequest, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    if request.stat.contains('.') || request.stat.starts_with('$') || request.count.is_some_and(|count| count <= 0) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let namespace = config.canonical_namespace(&namespace).to_string();
+    let dry_run = request.dry_run;
+    let locks = [namespace_lock(&namespace)];
+    let convert = send(&database, ConvertStat {
+        namespace: namespace.clone(),
+        stat: request.stat,
+        to: request.to,
+        count: request.count,
+        dry_run,
+    }, deadline);
+
+    // Dry runs don't change anything, so they don't need to wait for other operations.
+    let res = if dry_run {
+        convert.await.map(Some)
+    } else {
+        leases.run_exclusive(&locks, Duration::from_secs(config.admin_lock_ttl_secs), convert).await
+    };
+
+    match res {
+        Ok(Some(report)) => {
+            if !dry_run {
+                cache.invalidate(&namespace).await;
+            }
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::CONFLICT)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn rename_stats(config: Config, database: Address<StoreHandler>, cache: ResultCache, leases: Leases, namespace: String, authorization: String, request: RenameStatsRequest, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    // Renaming a stat to another one that is itself being renamed would depend on the order that they are renamed in.
+    let invalid = |stat: &String| stat.is_empty() || stat.contains('.') || stat.starts_with('$');
+    let targets: HashSet<&String> = request.stats.values().collect();
+    if request.stats.is_empty()
+        || request.stats.iter().any(|(from, to)| invalid(from) || invalid(to) || from == to || targets.contains(from))
+        || targets.len() < request.stats.len() {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let namespace = config.canonical_namespace(&namespace).to_string();
+    let locks = [namespace_lock(&namespace)];
+    let rename = send(&database, RenameStats { namespace: namespace.clone(), stats: request.stats }, deadline);
+    let res = leases.run_exclusive(&locks, Duration::from_secs(config.admin_lock_ttl_secs), rename).await;
+
+    match res {
+        Ok(Some(report)) => {
+            cache.invalidate(&namespace).await;
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::CONFLICT)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn merge_namespace(config: Config, database: Address<StoreHandler>, cache: ResultCache, leases: Leases, namespace: String, authorization: String, from: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let from = config.canonical_namespace(&from).to_string();
+    let into = config.canonical_namespace(&namespace).to_string();
+    if from == into {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let locks = [namespace_lock(&from), namespace_lock(&into)];
+    let merge = send(&database, MergeNamespace {
+        from: from.clone(),
+        into: into.clone(),
+    }, deadline);
+    let res = leases.run_exclusive(&locks, Duration::from_secs(config.admin_lock_ttl_secs), merge).await;
+
+    match res {
+        Ok(Some(report)) => {
+            cache.invalidate(&from).await;
+            cache.invalidate(&into).await;
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::CONFLICT)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn delete_stat(config: Config, database: Address<StoreHandler>, cache: ResultCache, leases: Leases, namespace: String, stat: String, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    if stat.contains('.') || stat.starts_with('$') {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let namespace = config.canonical_namespace(&namespace).to_string();
+    let locks = [namespace_lock(&namespace)];
+    let delete = send(&database, DeleteStat { namespace: namespace.clone(), stat }, deadline);
+    let res = leases.run_exclusive(&locks, Duration::from_secs(config.admin_lock_ttl_secs), delete).await;
+
+    match res {
+        Ok(Some(report)) => {
+            cache.invalidate(&namespace).await;
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::CONFLICT)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn delete_namespace(config: Config, database: Address<StoreHandler>, cache: ResultCache, leases: Leases, namespace: String, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let namespace = config.canonical_namespace(&namespace).to_string();
+    let locks = [namespace_lock(&namespace)];
+    let delete = send(&database, DeleteNamespace(namespace.clone()), deadline);
+    let res = leases.run_exclusive(&locks, Duration::from_secs(config.admin_lock_ttl_secs), delete).await;
+
+    match res {
+        Ok(Some(report)) => {
+            cache.invalidate(&namespace).await;
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::CONFLICT)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn rebuild_aggregates(config: Config, database: Address<StoreHandler>, cache: ResultCache, leases: Leases, authorization: String, request: RebuildAggregatesRequest, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    // Replacing the aggregates with a partial history would lose every stat outside of it. Replacements are also
+    // limited to one namespace, so that they can be locked like other operations that change its stats.
+    let partial = request.from.is_some() || request.until.is_some();
+    if request.mode == RebuildMode::Replace && (partial || request.namespace.is_none()) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+    let (from, until) = match (parse_time(request.from.as_deref()), parse_time(request.until.as_deref())) {
+        (Ok(from), Ok(until)) => (from, until),
+        _ => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+
+    let namespace = request.namespace.map(|namespace| config.canonical_namespace(&namespace).to_string());
+    // Rebuilds share their working collections, so only one runs at a time.
+    let mut locks = vec![REBUILD_LOCK.to_string()];
+    if let (RebuildMode::Replace, Some(namespace)) = (request.mode, &namespace) {
+        locks.push(namespace_lock(namespace));
+    }
+
+    let rebuild = send(&database, RebuildAggregates {
+        namespace: namespace.clone(),
+        from,
+        until,
+        mode: request.mode,
+    }, deadline);
+    let res = leases.run_exclusive(&locks, Duration::from_secs(config.admin_lock_ttl_secs), rebuild).await;
+
+    match res {
+        Ok(Some(report)) => {
+            if let (RebuildMode::Replace, Some(namespace)) = (request.mode, &namespace) {
+                cache.invalidate(namespace).await;
+            }
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::CONFLICT)),
+        Err(e) if e.is::<IncompleteBundleLog>() => {
+            let error = ErrorResponse { error: e.to_string() };
+            Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::CONFLICT)))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct BackupQuery {
+    /// Whether to upload the backup to the configured S3 bucket rather than respond with it.
+    #[serde(default)]
+    s3: bool,
+}
+
+/// Responds with a backup of the whole database, or uploads it to S3. Backups are read a batch at a time while
+/// uploads continue, so they aren't a consistent snapshot of a database that is being written to.
+async fn backup(config: Config, database: Address<StoreHandler>, limits: RouteLimits, authorization: String, s3: bool) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+    // Checking that the store can be backed up first means that failures are reported with a status, rather than
+    // by cutting off the response.
+    if let Err(e) = send(&database, GetBackupManifest, limits.deadline("backup")).await {
+        return Ok(handle_server_error(&e));
+    }
+
+    if s3 {
+        let s3 = match &config.backup.s3 {
+            Some(s3) => s3,
+            None => {
+                let error = ErrorResponse {
+                    error: "no S3 bucket is configured for backups".to_string(),
+                };
+                return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::BAD_REQUEST)));
+            }
+        };
+        return match backup::upload(s3, database, limits, config.database_type).await {
+            Ok(response) => Ok(Box::new(warp::reply::json(&response))),
+            Err(e) => Ok(handle_server_error(&e)),
+        };
+    }
+
+    let filename = format!("nucleoid-backup-{}.ndjson", chrono::Utc::now().format("%Y%m%dT%H%M%SZ"));
+    let body = warp::hyper::Body::wrap_stream(backup::stream(database, limits, config.database_type));
+    let reply = warp::reply::with_header(warp::reply::Response::new(body), CONTENT_TYPE, "application/x-ndjson");
+    Ok(Box::new(warp::reply::with_header(reply, CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", filename))))
+}
+
+async fn restore<B: Buf>(
+    config: Config,
+    database: Address<StoreHandler>,
+    cache: ResultCache,
+    limits: RouteLimits,
+    authorization: String,
+    body: impl Stream<Item = Result<B, warp::Error>> + Unpin,
+) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    match backup::restore(&database, &limits, config.database_type, config.max_body_bytes, body).await {
+        Ok(report) => {
+            // Restored stats replace none that were cached, but cached results may have been of namespaces that had
+            // none of their stats yet.
+            if let Ok(namespaces) = send(&database, GetNamespaces, limits.deadline("restore")).await {
+                for namespace in namespaces {
+                    cache.invalidate(&namespace).await;
+                }
+            }
+            Ok(Box::new(warp::reply::json(&report)))
+        }
+        Err(RestoreError::Invalid(error)) => {
+            let error = ErrorResponse { error };
+            Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::BAD_REQUEST)))
+        }
+        Err(RestoreError::Failed(e)) => Ok(handle_server_error(&e)),
+    }
+}
+
+/// Parses an optional RFC 3339 time from a request.
+fn parse_time(time: Option<&str>) -> Result<Option<bson::DateTime>, chrono::ParseError> {
+    time.map(|time| {
+        let time = chrono::DateTime::parse_from_rfc3339(time)?;
+        Ok(bson::DateTime::from_millis(time.timestamp_millis()))
+    }).transpose()
+}
+
+async fn run_job(config: Config, jobs: Jobs, name: String, authorization: String) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    match jobs.run_now(&name) {
+        Ok(run_id) => {
+            log::info!("started run {} of job '{}' on request", run_id, name);
+            Ok(Box::new(warp::reply::with_status(warp::reply::json(&RunJobResponse { run_id }), StatusCode::ACCEPTED)))
+        }
+        Err(RunJobError::NotFound) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(RunJobError::AlreadyRunning) => Ok(send_http_status(StatusCode::CONFLICT)),
+    }
+}
+
+async fn list_jobs(config: Config, database: Address<StoreHandler>, jobs: Jobs, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let mut response = Vec::new();
+    for job in jobs.list() {
+        let last_run = match send(&database, GetJobRuns { job: Some(job.name.to_string()), limit: 1 }, deadline).await {
+            Ok(runs) => runs.into_iter().next().map(JobRunResponse::from),
+            Err(e) => return Ok(handle_server_error(&e)),
+        };
+        response.push(JobResponse {
+            name: job.name.to_string(),
+            schedule: job.schedule,
+            running: job.running,
+            last_run,
+        });
+    }
+    Ok(Box::new(warp::reply::json(&response)))
+}
+
+#[derive(Deserialize)]
+struct JobRunsQuery {
+    #[serde(default = "default_job_runs_limit")]
+    limit: i64,
+}
+
+fn default_job_runs_limit() -> i64 {
+    50
+}
+
+async fn get_job_runs(config: Config, database: Address<StoreHandler>, jobs: Jobs, name: String, authorization: String, limit: i64, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    if !jobs.contains(&name) {
+        return Ok(send_http_status(StatusCode::NOT_FOUND));
+    }
+    if !(1..=500).contains(&limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    match send(&database, GetJobRuns { job: Some(name), limit }, deadline).await {
+        Ok(runs) => Ok(Box::new(warp::reply::json(&runs.into_iter().map(JobRunResponse::from).collect::<Vec<_>>()))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct UploadLogQuery {
+    server_name: Option<String>,
+    namespace: Option<String>,
+    token: Option<String>,
+    #[serde(default = "default_upload_log_limit")]
+    limit: i64,
+}
+
+fn default_upload_log_limit() -> i64 {
+    50
+}
+
+async fn get_upload_log(config: Config, database: Address<StoreHandler>, authorization: String, query: UploadLogQuery, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    if !(1..=1000).contains(&query.limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let namespace = query.namespace.map(|namespace| config.canonical_namespace(&namespace).to_string());
+    match send(&database, GetUploadLog { server_name: query.server_name, namespace, token: query.token, limit: query.limit }, deadline).await {
+        Ok(entries) => {
+            let entries: Vec<UploadLogResponse> = entries.into_iter().map(UploadLogResponse::from).collect();
+            Ok(Box::new(warp::reply::json(&entries)))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct CorruptDocumentsQuery {
+    #[serde(default = "default_corrupt_documents_limit")]
+    limit: i64,
+}
+
+fn default_corrupt_documents_limit() -> i64 {
+    50
+}
+
+async fn get_corrupt_documents(config: Config, database: Address<StoreHandler>, authorization: String, limit: i64, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    if !(1..=500).contains(&limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    match send(&database, GetCorruptDocuments { limit }, deadline).await {
+        Ok(documents) => {
+            let summaries: Vec<CorruptDocumentSummary> = documents.iter().map(CorruptDocument::summary).collect();
+            Ok(Box::new(warp::reply::json(&summaries)))
+        }
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn get_corrupt_document(config: Config, database: Address<StoreHandler>, id: String, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let id = match ObjectId::parse_str(&id) {
+        Ok(id) => id,
+        Err(_) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+    };
+    match send(&database, GetCorruptDocument(id), deadline).await {
+        Ok(Some(document)) => Ok(Box::new(warp::reply::json(&CorruptDocumentResponse::from(document)))),
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+async fn restore_corrupt_document(config: Config, database: Address<StoreHandler>, leases: Leases, id: String, authorization: String, request: RestoreCorruptDocumentRequest, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let id = match ObjectId::parse_str(&id) {
+        Ok(id) => id,
+        Err(_) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+    };
+    let document = match bson::Bson::try_from(request.document) {
+        Ok(bson::Bson::Document(document)) => document,
+        _ => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+    let namespace = match document.get_str("namespace") {
+        Ok(namespace) => namespace.to_string(),
+        Err(_) => return Ok(send_http_status(StatusCode::BAD_REQUEST)),
+    };
+
+    // Restoring adds stats onto the namespace, so it waits for other admin operations on it like a merge would.
+    let locks = [namespace_lock(&namespace)];
+    let restore = send(&database, RestoreCorruptDocument { id, document }, deadline);
+    match leases.run_exclusive(&locks, Duration::from_secs(config.admin_lock_ttl_secs), restore).await {
+        Ok(Some(RestoreOutcome::Restored)) => Ok(send_http_status(StatusCode::NO_CONTENT)),
+        Ok(Some(RestoreOutcome::NotFound)) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Ok(Some(RestoreOutcome::Invalid(error))) => {
+            let error = ErrorResponse { error };
+            Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::UNPROCESSABLE_ENTITY)))
+        }
+        Ok(None) => Ok(send_http_status(StatusCode::CONFLICT)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+#[derive(Deserialize)]
+struct SuspiciousUploadsQuery {
+    #[serde(default = "default_suspicious_uploads_limit")]
+    limit: i64,
+}
+
+fn default_suspicious_uploads_limit() -> i64 {
+    50
+}
+
+async fn get_suspicious_uploads(config: Config, database: Address<StoreHandler>, authorization: String, limit: i64, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    if !(1..=500).contains(&limit) {
+        return Ok(send_http_status(StatusCode::BAD_REQUEST));
+    }
+
+    let uploads = send(&database, GetSuspiciousUploads { limit }, deadline).await.and_then(|uploads| {
+        uploads.iter()
+            .map(|upload| Ok(upload.to_response()?))
+            .collect::<anyhow::Result<Vec<SuspiciousUploadResponse>>>()
+    });
+    match uploads {
+        Ok(uploads) => Ok(Box::new(warp::reply::json(&uploads))),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+/// Applies a quarantined upload as it was uploaded, once an admin has decided that it is genuine, and removes it from
+/// quarantine. The upload is logged under the token that it was made with, if that token still exists.
+async fn release_suspicious_upload(ingest: Ingest, id: String, authorization: String, deadline: Instant) -> ApiResult {
+    let Ingest { config, database, processors, uploads, .. } = ingest;
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let id = match ObjectId::parse_str(&id) {
+        Ok(id) => id,
+        Err(_) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+    };
+    // Taken out of quarantine before it is applied, so that releasing it twice at once can't apply it twice. It is
+    // put back if it can't be applied.
+    let upload = match send(&database, DeleteSuspiciousUpload(id), deadline).await {
+        Ok(Some(upload)) => upload,
+        Ok(None) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => return Ok(handle_server_error(&e)),
+    };
+    let res = match upload.to_bundle() {
+        Ok(bundle) => match uploads.begin_started(bundle.clone()) {
+            Some(_tracked) => {
+                let token = config.token_by_name(&upload.token).map(|token| token.token.clone()).unwrap_or_default();
+                apply_upload(config, database.clone(), processors, token, bundle, deadline).await.map(Some)
+            }
+            None => Ok(None),
+        },
+        Err(e) => Err(e.into()),
+    };
+    let error = match res {
+        Ok(Some(_)) => {
+            log::info!("Released quarantined upload {} for {} from '{}'", id, upload.namespace, upload.server_name);
+            return Ok(send_http_status(StatusCode::NO_CONTENT));
+        }
+        Ok(None) => None,
+        Err(e) => Some(e),
+    };
+
+    let (namespace, server_name) = (upload.namespace.clone(), upload.server_name.clone());
+    if let Err(e) = send(&database, QuarantineUpload(upload), deadline).await {
+        log::error!("failed to put quarantined upload {} for {} from '{}' back after failing to release it: {}", id, namespace, server_name, e);
+    }
+    match error {
+        None => Ok(send_http_status(StatusCode::SERVICE_UNAVAILABLE)),
+        Some(e) => match e.downcast_ref::<StatTypeMismatch>() {
+            Some(mismatch) => {
+                let error = ErrorResponse { error: mismatch.to_string() };
+                Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::UNPROCESSABLE_ENTITY)))
+            }
+            None => Ok(handle_server_error(&e)),
+        },
+    }
+}
+
+async fn discard_suspicious_upload(config: Config, database: Address<StoreHandler>, id: String, authorization: String, deadline: Instant) -> ApiResult {
+    if let Some(status) = missing_scope(&config, &authorization, TokenScope::Admin) {
+        return Ok(send_http_status(status));
+    }
+
+    let id = match ObjectId::parse_str(&id) {
+        Ok(id) => id,
+        Err(_) => return Ok(send_http_status(StatusCode::NOT_FOUND)),
+    };
+    match send(&database, DeleteSuspiciousUpload(id), deadline).await {
+        Ok(Some(_)) => Ok(send_http_status(StatusCode::NO_CONTENT)),
+        Ok(None) => Ok(send_http_status(StatusCode::NOT_FOUND)),
+        Err(e) => Ok(handle_server_error(&e)),
+    }
+}
+
+/// The lock held while aggregates are rebuilt from the bundle log.
+const REBUILD_LOCK: &str = "admin:rebuild";
+
+/// The lock held by admin operations that change a namespace's stats, so that they don't run at the same time.
+fn namespace_lock(namespace: &str) -> String {
+    format!("admin:namespace:{}", namespace)
+}
+
+/// Applies stat key aliases and deprecation redirects to uploaded stats.
+fn resolve_stat_names(config: &Config, metrics: &Metrics, bundle: &GameStatsBundle, stats: HashMap<String, UploadStat>) -> HashMap<String, UploadStat> {
+    let mut resolved = HashMap::with_capacity(stats.len());
+    let mut renamed = Vec::new();
+    for (name, stat) in stats {
+        let canonical = resolve_stat_name(config, metrics, bundle, &name);
+        if canonical == name {
+            resolved.insert(name, stat);
+        } else {
+            renamed.push((name, canonical, stat));
+        }
+    }
+
+    for (name, canonical, stat) in renamed {
+        if resolved.contains_key(&canonical) {
+            log::warn!("stat '{}' in {} was uploaded under both its current key and '{}', ignoring the latter", canonical, bundle.namespace, name);
+            continue;
+        }
+        resolved.insert(canonical, stat);
+    }
+    resolved
+}
+
+/// Resolves the key that an uploaded stat should be stored under, reporting uploads to deprecated stats.
+fn resolve_stat_name(config: &Config, metrics: &Metrics, bundle: &GameStatsBundle, name: &str) -> String {
+    let namespace = &bundle.namespace;
+    let name = config.canonical_stat_name(namespace, name);
+    let deprecation = match config.stat_metadata(namespace, name).and_then(|metadata| metadata.deprecated.as_ref()) {
+        Some(deprecation) => deprecation,
+        None => return name.to_string(),
+    };
+
+    metrics.increment("nucleoid_deprecated_stat_uploads_total", &[("namespace", namespace), ("stat", name)]);
+    match &deprecation.successor {
+        Some(successor) if deprecation.redirect => {
+            log::warn!("server '{}' uploaded deprecated stat '{}' in {}, storing it as '{}'", bundle.server_name, name, namespace, successor);
+            successor.clone()
+        }
+        _ => {
+            log::warn!("server '{}' uploaded deprecated stat '{}' in {}", bundle.server_name, name, namespace);
+            name.to_string()
+        }
+    }
+}
+
+/// Deserializes a JSON request body, which may be compressed, rejecting bodies larger than the configured limit
+/// either before or after they are decompressed.
+fn json_body<T: DeserializeOwned + Send>(config: &Config) -> impl Filter<Extract = (T,), Error = warp::Rejection> + Clone {
+    let max_body_bytes = config.max_body_bytes;
+    warp::body::content_length_limit(max_body_bytes)
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and_then(move |encoding: Option<String>, body: warp::hyper::body::Bytes| async move {
+            decode_json(encoding.as_deref(), &body, max_body_bytes)
+        })
+}
+
+/// Like [json_body], but also extracts the token that authorized the request: either the one sent in the
+/// `Authorization` header, or the one that the body was signed with. Signatures cover the body as it was sent, before
+/// it is decompressed.
+fn authorized_json_body<T: DeserializeOwned + Send>(config: &Config, signatures: &SignatureVerifier) -> impl Filter<Extract = (String, T), Error = warp::Rejection> + Clone {
+    let config = config.clone();
+    let signatures = signatures.clone();
+    let max_body_bytes = config.max_body_bytes;
+    warp::header::optional::<String>("authorization")
+        .and(signed_request())
+        .and(warp::body::content_length_limit(max_body_bytes))
+        .and(warp::header::optional::<String>("content-encoding"))
+        .and(warp::body::bytes())
+        .and_then(move |authorization: Option<String>, signed: Option<SignedRequest>, encoding: Option<String>, body: warp::hyper::body::Bytes| {
+            let config = config.clone();
+            let signatures = signatures.clone();
+            async move {
+                let authorization = match (signed, authorization) {
+                    (Some(signed), _) => {
+                        let token = signatures.verify(&config, &signed, &body).map_err(|e| {
+                            log::debug!("rejecting request signed with '{}': {}", signed.key, e);
+                            warp::reject::custom(e)
+                        })?;
+                        token.token
+                    }
+                    (None, Some(authorization)) => authorization,
+                    (None, None) => return Err(warp::reject::custom(MissingAuthorization)),
+                };
+                Ok((authorization, decode_json(encoding.as_deref(), &body, max_body_bytes)?))
+            }
+        })
+        .untuple_one()
+}
+
+/// Reads the signature headers of a request, if it has an `X-Signature` header.
+fn signed_request() -> impl Filter<Extract = (Option<SignedRequest>,), Error = warp::Rejection> + Clone {
+    warp::header::optional::<String>(signature::SIGNATURE_HEADER)
+        .and(warp::header::optional::<String>(signature::KEY_HEADER))
+        .and(warp::header::optional::<i64>(signature::TIMESTAMP_HEADER))
+        .and(warp::header::optional::<String>(signature::NONCE_HEADER))
+        .and_then(|signature: Option<String>, key: Option<String>, timestamp: Option<i64>, nonce: Option<String>| async move {
+            match (signature, key, timestamp, nonce) {
+                (None, ..) => Ok(None),
+                (Some(signature), Some(key), Some(timestamp), Some(nonce)) => Ok(Some(SignedRequest { key, timestamp, nonce, signature })),
+                _ => Err(warp::reject::custom(SignatureError::MissingHeaders)),
+            }
+        })
+}
+
+fn decode_json<T: DeserializeOwned>(encoding: Option<&str>, body: &[u8], max_body_bytes: u64) -> Result<T, warp::Rejection> {
+    let body = compression::decode(encoding, body, max_body_bytes).map_err(warp::reject::custom)?;
+    serde_json::from_slice(&body).map_err(|e| warp::reject::custom(BodyError::Invalid(e.to_string())))
+}
+
+#[derive(Debug)]
+struct MissingAuthorization;
+
+impl warp::reject::Reject for MissingAuthorization {}
+
+async fn handle_rejection(rejection: warp::Rejection, max_body_bytes: u64) -> Result<Box<dyn warp::Reply>, warp::Rejection> {
+    if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        let error = ErrorResponse {
+            error: format!("request body is larger than the limit of {} bytes", max_body_bytes),
+        };
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::PAYLOAD_TOO_LARGE)));
+    }
+    if rejection.find::<ReadOnly>().is_some() {
+        let error = ErrorResponse {
+            error: "the service is in read-only mode".to_string(),
+        };
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::SERVICE_UNAVAILABLE)));
+    }
+    if let Some(e) = rejection.find::<RateLimited>() {
+        let error = ErrorResponse {
+            error: "too many requests, try again later".to_string(),
+        };
+        // Retry-After is in whole seconds, so waits are rounded up rather than retrying too early.
+        let retry_after = e.retry_after.as_secs() + u64::from(e.retry_after.subsec_nanos() > 0);
+        let reply = warp::reply::with_status(warp::reply::json(&error), StatusCode::TOO_MANY_REQUESTS);
+        return Ok(Box::new(warp::reply::with_header(reply, RETRY_AFTER, retry_after.max(1).to_string())));
+    }
+    if rejection.find::<MissingAuthorization>().is_some() {
+        let error = ErrorResponse {
+            error: "missing Authorization or X-Signature header".to_string(),
+        };
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::UNAUTHORIZED)));
+    }
+    if let Some(e) = rejection.find::<SignatureError>() {
+        let error = ErrorResponse { error: e.to_string() };
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::UNAUTHORIZED)));
+    }
+    if let Some(e) = rejection.find::<BodyError>() {
+        let status = match e {
+            BodyError::UnsupportedEncoding(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            BodyError::Invalid(_) => StatusCode::BAD_REQUEST,
+            BodyError::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        };
+        let error = ErrorResponse { error: e.to_string() };
+        return Ok(Box::new(warp::reply::with_status(warp::reply::json(&error), status)));
+    }
+    Err(rejection)
+}
+
+/// Sends a message to the database, giving up with [DeadlineExceeded] once the request's deadline has passed. Writes
+/// that started before the deadline are waited for, so that the response says whether they were applied.
+pub(crate) async fn send<M, T>(database: &Address<StoreHandler>, message: M, deadline: Instant) -> anyhow::Result<T>
+    where M: DeadlineMessage<Result = anyhow::Result<T>>, T: Send + 'static, StoreHandler: Handler<WithDeadline<M>> {
+    let res = database.send(WithDeadline { message, deadline, span: tracing::Span::current() });
+    if M::WRITES {
+        return res.await.unwrap();
+    }
+    match tokio::time::timeout_at(deadline, res).await {
+        Ok(res) => res.unwrap(),
+        Err(_) => Err(DeadlineExceeded.into()),
+    }
+}
+
+fn send_violations(violations: Vec<Violation>) -> Box<dyn warp::Reply> {
+    let error = ValidationErrorResponse {
+        error: format!("the request breaks {} limits of the backend", violations.len()),
+        violations,
+    };
+    Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::BAD_REQUEST))
+}
+
+fn handle_server_error(e: &anyhow::Error) -> Box<dyn warp::Reply> {
+    if e.is::<DeadlineExceeded>() {
+        log::debug!("request exceeded its deadline");
+        return send_http_status(StatusCode::GATEWAY_TIMEOUT);
+    }
+    if let Some(unsupported) = e.downcast_ref::<Unsupported>() {
+        let error = ErrorResponse { error: unsupported.to_string() };
+        return Box::new(warp::reply::with_status(warp::reply::json(&error), StatusCode::NOT_IMPLEMENTED));
+    }
+    log::warn!("error handling request: {}", e);
+    send_http_status(StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+/// Checks that a token grants a scope, returning the status to reject the request with if it doesn't.
+pub(crate) fn missing_scope(config: &Config, authorization: &str, scope: TokenScope) -> Option<StatusCode> {
+    match config.token(authorization) {
+        Some(token) if token.has_scope(scope) => None,
+        Some(token) => {
+            log::debug!("token '{}' is missing the {:?} scope", token.name, scope);
+            Some(StatusCode::FORBIDDEN)
+        }
+        None => Some(StatusCode::UNAUTHORIZED),
+    }
+}
+
+/// Checks that a token grants a scope and may write to a namespace, returning the status to reject the request with if
+/// it doesn't.
+pub(crate) fn missing_namespace_scope(config: &Config, authorization: &str, scope: TokenScope, namespace: &str) -> Option<StatusCode> {
+    if let Some(status) = missing_scope(config, authorization, scope) {
+        return Some(status);
+    }
+    match config.token(authorization) {
+        Some(token) if !token.can_write_namespace(namespace) => {
+            log::debug!("token '{}' may not write to namespace {}", token.name, namespace);
+            Some(StatusCode::FORBIDDEN)
+        }
+        _ => None,
+    }
 }
 
 fn send_http_status(status: StatusCode) -> Box<dyn warp::Reply> {
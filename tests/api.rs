@@ -0,0 +1,1450 @@
+//! Tests of the HTTP API, run against the in-memory backend so that they don't need a database.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use warp::http::StatusCode;
+use warp::http::header::HeaderValue;
+
+use nucleoid_persistence::config::{ApiToken, Config, DatabaseType, RateLimit, RateLimitKey, SnapshotLeaderboard, StatMetadata, TokenScope};
+use nucleoid_persistence::logging::RequestId;
+use nucleoid_persistence::memory::MemoryDatabaseHandler;
+use nucleoid_persistence::metrics::Metrics;
+use nucleoid_persistence::model::{GameStatsBundle, LeaderboardOrder};
+use nucleoid_persistence::scheduler::Scheduler;
+use nucleoid_persistence::shutdown::UploadTracker;
+use nucleoid_persistence::signature;
+use nucleoid_persistence::spool::Spool;
+use nucleoid_persistence::store::StoreHandler;
+use nucleoid_persistence::{config, jobs, web};
+
+const SERVER_TOKEN: &str = "server-token";
+const ADMIN_TOKEN: &str = "admin-token";
+
+const ALICE: &str = "07e92b46-8386-4067-8f72-8ab96e606fb7";
+const BOB: &str = "a3f5c0d2-1b4e-4f6a-9c8d-7e6f5a4b3c2d";
+
+struct Api {
+    routes: web::Routes,
+    #[cfg(feature = "grpc")]
+    grpc: nucleoid_persistence::grpc::GrpcService,
+}
+
+struct Response {
+    status: StatusCode,
+    body: Value,
+}
+
+impl Api {
+    fn new() -> Self {
+        Self::with_config(test_config())
+    }
+
+    fn with_config(config: Config) -> Self {
+        let database = StoreHandler::spawn(MemoryDatabaseHandler::new(&config), &config);
+        let mut scheduler = Scheduler::new(&config, database.clone());
+        jobs::register(&mut scheduler, &config, &database, &Metrics::default()).unwrap();
+        let (routes, _ingest) = web::api(&config, database, Metrics::default(), scheduler.start(), UploadTracker::default()).unwrap();
+        Self {
+            routes,
+            #[cfg(feature = "grpc")]
+            grpc: nucleoid_persistence::grpc::GrpcService::new(_ingest),
+        }
+    }
+
+    async fn request(&self, method: &str, path: &str, token: Option<&str>, body: Option<Value>) -> Response {
+        let mut request = warp::test::request().method(method).path(path);
+        if let Some(token) = token {
+            request = request.header("authorization", token);
+        }
+        if let Some(body) = body {
+            request = request.json(&body);
+        }
+        let res = request.reply(&self.routes).await;
+        Response {
+            status: res.status(),
+            body: serde_json::from_slice(res.body()).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(res.body()).into_owned())),
+        }
+    }
+
+    async fn get(&self, path: &str) -> Response {
+        self.request("GET", path, None, None).await
+    }
+
+    async fn get_as(&self, path: &str, token: &str) -> Response {
+        self.request("GET", path, Some(token), None).await
+    }
+
+    async fn post(&self, path: &str, token: &str, body: Value) -> Response {
+        self.request("POST", path, Some(token), Some(body)).await
+    }
+
+    async fn put(&self, path: &str, token: &str, body: Value) -> Response {
+        self.request("PUT", path, Some(token), Some(body)).await
+    }
+
+    async fn signed_upload(&self, body: &[u8], timestamp: i64, nonce: &str, signature: &str) -> StatusCode {
+        warp::test::request().method("POST").path("/stats/upload")
+            .header("x-signature-key", "server")
+            .header("x-signature-timestamp", timestamp)
+            .header("x-signature-nonce", nonce)
+            .header("x-signature", signature)
+            .body(body)
+            .reply(&self.routes).await
+            .status()
+    }
+
+    async fn upload(&self, namespace: &str, players: Value, global: Option<Value>) -> Response {
+        let mut bundle = bundle(namespace, players);
+        bundle["stats"]["global"] = global.unwrap_or(Value::Null);
+        self.post("/stats/upload", SERVER_TOKEN, bundle).await
+    }
+
+    /// Reads the public stats of a player in one namespace.
+    async fn player_stats(&self, uuid: &str, namespace: &str) -> Response {
+        self.get(&format!("/player/{}/stats/{}", uuid, namespace)).await
+    }
+
+    /// Waits for a queued upload to finish, returning its status.
+    async fn wait_for_upload(&self, id: &Value) -> Value {
+        let path = format!("/stats/upload/{}/status", id.as_str().unwrap());
+        for _ in 0..100 {
+            let res = self.get_as(&path, SERVER_TOKEN).await;
+            assert_eq!(res.status, StatusCode::OK);
+            if res.body["finished_at"] != Value::Null {
+                return res.body;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("upload {} didn't finish", id);
+    }
+
+    async fn set_username(&self, uuid: &str, username: &str) {
+        let res = self.put(&format!("/player/{}", uuid), SERVER_TOKEN, json!({"username": username})).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+    }
+}
+
+fn test_config() -> Config {
+    let mut config = Config {
+        tokens: vec![
+            ApiToken {
+                name: "server".to_string(),
+                token: SERVER_TOKEN.to_string(),
+                scopes: vec![TokenScope::UploadStats, TokenScope::UploadGames, TokenScope::UpdateProfiles, TokenScope::UpdateStatMetadata, TokenScope::UpdateAchievements],
+                namespaces: Vec::new(),
+            },
+            ApiToken {
+                name: "admin".to_string(),
+                token: ADMIN_TOKEN.to_string(),
+                scopes: vec![TokenScope::Admin, TokenScope::ReadPrivate],
+                namespaces: Vec::new(),
+            },
+        ],
+        internal_namespaces: vec!["internal".to_string()],
+        seasonal_namespaces: vec!["seasonal".to_string()],
+        ..Default::default()
+    };
+    config.stat_metadata.insert("bedwars".to_string(), HashMap::from([(
+        "damage".to_string(),
+        StatMetadata { buckets: vec![10.0, 20.0], ..Default::default() },
+    )]));
+    config
+}
+
+/// A test config in which bundles that add more than `limit` to a stat in bedwars are quarantined.
+fn config_with_max_increment(stat: &str, limit: f64) -> Config {
+    let mut config = test_config();
+    config.stat_metadata.insert("bedwars".to_string(), HashMap::from([(
+        stat.to_string(),
+        StatMetadata { max_increment: Some(limit), ..Default::default() },
+    )]));
+    config
+}
+
+/// A bundle from the `play` server with only player stats.
+fn bundle(namespace: &str, players: Value) -> Value {
+    json!({"server_name": "play", "namespace": namespace, "stats": {"players": players, "global": null}})
+}
+
+fn int_total(value: i32) -> Value {
+    json!({"type": "int_total", "value": value})
+}
+
+#[tokio::test]
+async fn player_profiles() {
+    let api = Api::new();
+    assert_eq!(api.get(&format!("/player/{}", ALICE)).await.status, StatusCode::NOT_FOUND);
+
+    api.set_username(ALICE, "Alice").await;
+    let res = api.get(&format!("/player/{}", ALICE)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["uuid"], ALICE);
+    assert_eq!(res.body["username"], "Alice");
+
+    let res = api.get("/player/by-name/aLiCe").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["uuid"], ALICE);
+    assert_eq!(api.get("/player/by-name/nobody").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn reloading_config_rotates_tokens_and_rate_limits() {
+    let config = test_config();
+    let api = Api::with_config(config.clone());
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}}), None).await;
+    assert_eq!(api.get_as(&format!("/player/{}/stats", ALICE), ADMIN_TOKEN).await.status, StatusCode::OK);
+
+    let mut reloaded = test_config();
+    reloaded.tokens[0].token = "rotated".to_string();
+    reloaded.rate_limits.routes.insert("player_stats".to_string(), RateLimit { per: RateLimitKey::Token, requests: 1, window_secs: 60 });
+    config.reload_from(reloaded);
+
+    let profile = json!({"username": "Alice"});
+    assert_eq!(api.put(&format!("/player/{}", ALICE), SERVER_TOKEN, profile.clone()).await.status, StatusCode::UNAUTHORIZED);
+    assert_eq!(api.put(&format!("/player/{}", ALICE), "rotated", profile).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.get_as(&format!("/player/{}/stats", ALICE), ADMIN_TOKEN).await.status, StatusCode::OK);
+    assert_eq!(api.get_as(&format!("/player/{}/stats", ALICE), ADMIN_TOKEN).await.status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    assert!(test_config().validate().is_ok());
+
+    let mut config = test_config();
+    config.tokens.clear();
+    config.database_url = "localhost:27017".to_string();
+    config.api_port = 0;
+    let problems = config.validate().unwrap_err().problems;
+    assert_eq!(problems.len(), 3, "{:?}", problems);
+    assert!(problems[0].starts_with("`tokens` is empty"));
+
+    let mut config = test_config();
+    config.tokens[1].name = "server".to_string();
+    config.database_type = DatabaseType::Postgres;
+    assert_eq!(config.validate().unwrap_err().problems.len(), 2);
+}
+
+#[test]
+fn configs_can_be_toml_or_yaml() {
+    let dir = std::env::temp_dir().join(format!("nucleoid-config-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let files = [
+        ("config.json", r#"{"database_url": "mongodb://localhost", "database_name": "stats", "api_port": 4000, "tokens": [{"name": "server", "token": "secret", "scopes": ["upload_stats"]}]}"#),
+        ("config.toml", "database_url = \"mongodb://localhost\"\ndatabase_name = \"stats\"\napi_port = 4000\n[[tokens]]\nname = \"server\"\ntoken = \"secret\"\nscopes = [\"upload_stats\"]\n"),
+        ("config.yaml", "database_url: mongodb://localhost\ndatabase_name: stats\napi_port: 4000\ntokens:\n  - name: server\n    token: secret\n    scopes: [upload_stats]\n"),
+    ];
+    for (name, text) in &files {
+        let path = dir.join(name);
+        std::fs::write(&path, text).unwrap();
+        let config = config::load(&path).unwrap_or_else(|e| panic!("{}: {:#}", name, e));
+        assert_eq!(config.api_port, 4000);
+        assert_eq!(config.token_by_name("server").unwrap().token, "secret");
+    }
+
+    // A missing file is created with defaults in the format of its extension.
+    let created = dir.join("created.toml");
+    let _ = std::fs::remove_file(&created);
+    let config = config::load(&created).unwrap();
+    assert_eq!(config::load(&created).unwrap().tokens[0].token, config.tokens[0].token);
+
+    assert!(config::load(&dir.join("config.ini")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn first_and_last_seen() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}}), None).await;
+    let first = api.get(&format!("/player/{}", ALICE)).await.body;
+    assert!(first["first_seen"].is_string());
+    assert_eq!(first["first_seen"], first["last_seen"]);
+
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    api.set_username(ALICE, "Alice").await;
+    let res = api.get(&format!("/player/{}", ALICE)).await;
+    assert_eq!(res.body["first_seen"], first["first_seen"]);
+    assert_ne!(res.body["last_seen"], first["last_seen"]);
+}
+
+#[tokio::test]
+async fn nameless_players_are_looked_up_from_mojang() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use warp::{Filter, Reply};
+
+    // A stand-in for the session API, which only knows Alice.
+    let lookups = Arc::new(AtomicUsize::new(0));
+    let session = warp::path!("session" / "minecraft" / "profile" / String).map({
+        let lookups = lookups.clone();
+        move |id: String| {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            if id == ALICE.replace('-', "") {
+                warp::reply::json(&json!({"id": id, "name": "Alice"})).into_response()
+            } else {
+                StatusCode::NO_CONTENT.into_response()
+            }
+        }
+    });
+    let (address, server) = warp::serve(session).bind_ephemeral(([127, 0, 0, 1], 0));
+    tokio::spawn(server);
+
+    let mut config = test_config();
+    config.mojang.enabled = true;
+    config.mojang.url = format!("http://{}/session/minecraft/profile", address);
+    let api = Api::with_config(config);
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}, BOB: {"kills": int_total(1)}}), None).await;
+
+    for _ in 0..2 {
+        assert_eq!(api.get(&format!("/player/{}", ALICE)).await.body["username"], "Alice");
+        assert_eq!(api.get(&format!("/player/{}", BOB)).await.body.get("username"), None);
+    }
+    // Both lookups were cached, including the player that Mojang doesn't know.
+    assert_eq!(lookups.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn username_goes_to_latest_holder() {
+    let api = Api::new();
+    api.set_username(ALICE, "Steve").await;
+    // Names are ordered by when they were taken, which is only precise to the millisecond.
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    api.set_username(BOB, "Steve").await;
+    assert_eq!(api.get("/player/by-name/steve").await.body["uuid"], BOB);
+}
+
+#[tokio::test]
+async fn username_history() {
+    let api = Api::new();
+    let path = format!("/player/{}/names", ALICE);
+    assert_eq!(api.get_as(&path, ADMIN_TOKEN).await.status, StatusCode::NOT_FOUND);
+
+    api.set_username(ALICE, "Alice").await;
+    api.set_username(ALICE, "Alice").await;
+    api.set_username(ALICE, "Alicia").await;
+    let res = api.get_as(&path, ADMIN_TOKEN).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let names: Vec<&str> = res.body.as_array().unwrap().iter().map(|name| name["username"].as_str().unwrap()).collect();
+    assert_eq!(names, ["Alicia", "Alice"]);
+    assert_eq!(res.body[0]["used_until"], Value::Null);
+    assert_eq!(res.body[1]["used_until"], res.body[0]["used_from"]);
+
+    assert_eq!(api.get_as(&path, SERVER_TOKEN).await.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn playtime() {
+    let api = Api::new();
+    let path = format!("/player/{}/playtime", ALICE);
+    assert_eq!(api.get(&path).await.status, StatusCode::NOT_FOUND);
+
+    for (namespace, seconds) in [("bedwars", 600), ("bedwars", 300), ("spleef", 120), ("internal", 60)] {
+        let res = api.post(&path, SERVER_TOKEN, json!({"namespace": namespace, "seconds": seconds})).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+    }
+    assert_eq!(api.post(&path, SERVER_TOKEN, json!({"namespace": "bedwars", "seconds": 0})).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(api.post(&path, ADMIN_TOKEN, json!({"namespace": "bedwars", "seconds": 60})).await.status, StatusCode::FORBIDDEN);
+
+    let res = api.get(&path).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["total_seconds"], 1020);
+    assert_eq!(res.body["namespaces"]["bedwars"]["seconds"], 900);
+    assert_eq!(res.body["namespaces"]["bedwars"]["sessions"], 2);
+    assert!(res.body["namespaces"]["internal"].is_null());
+    assert_eq!(api.get_as(&path, ADMIN_TOKEN).await.body["total_seconds"], 1080);
+    assert!(api.get(&format!("/player/{}", ALICE)).await.body["last_seen"].is_string());
+}
+
+#[tokio::test]
+async fn writes_require_scope() {
+    let api = Api::new();
+    let res = api.request("PUT", &format!("/player/{}", ALICE), None, Some(json!({"username": "Alice"}))).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+
+    let writes = [
+        ("PUT", format!("/player/{}", ALICE), json!({"username": "Alice"})),
+        ("POST", "/stats/upload".to_string(), bundle("bedwars", json!({}))),
+    ];
+    for (method, path, body) in writes {
+        assert_eq!(api.request(method, &path, Some("unknown"), Some(body.clone())).await.status, StatusCode::UNAUTHORIZED);
+        assert_eq!(api.request(method, &path, Some(ADMIN_TOKEN), Some(body)).await.status, StatusCode::FORBIDDEN);
+    }
+}
+
+#[tokio::test]
+async fn private_players_are_hidden() {
+    let api = Api::new();
+    api.set_username(ALICE, "Alice").await;
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(3)}}), None).await;
+    let res = api.put(&format!("/player/{}", ALICE), SERVER_TOKEN, json!({"username": "Alice", "private": true})).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+
+    assert_eq!(api.get(&format!("/player/{}", ALICE)).await.body, json!({"uuid": ALICE}));
+    assert_eq!(api.get("/player/by-name/alice").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.get("/leaderboard/bedwars/kills").await.body, json!([]));
+
+    let res = api.get_as(&format!("/player/{}", ALICE), ADMIN_TOKEN).await;
+    assert_eq!(res.body["username"], "Alice");
+    assert_eq!(res.body["private"], true);
+    assert!(res.body["last_seen"].is_string());
+    assert_eq!(api.get_as("/leaderboard/bedwars/kills", ADMIN_TOKEN).await.body[0]["uuid"], ALICE);
+}
+
+#[tokio::test]
+async fn uploaded_stats_are_added_up() {
+    let api = Api::new();
+    for value in [10, 20] {
+        let res = api.upload("bedwars", json!({ALICE: {
+            "kills": int_total(value),
+            "accuracy": {"type": "float_rolling_average", "value": value as f64 / 40.0},
+            "best": {"type": "int_max", "value": value},
+        }}), None).await;
+        assert_eq!(res.status, StatusCode::NO_CONTENT);
+    }
+    api.upload("spleef", json!({ALICE: {"wins": int_total(1)}}), None).await;
+
+    let res = api.player_stats(ALICE, "bedwars").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"bedwars": {"kills": 30.0, "accuracy": 0.375, "best": 20.0}}));
+
+    let res = api.get(&format!("/player/{}/stats", ALICE)).await;
+    assert_eq!(res.body["spleef"], json!({"wins": 1.0}));
+    assert_eq!(res.body["bedwars"]["kills"], 30.0);
+
+    assert_eq!(api.player_stats(BOB, "bedwars").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn uploads_that_change_stat_types_are_rejected() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(10)}}), None).await;
+
+    let res = api.upload("bedwars", json!({
+        ALICE: {"kills": {"type": "float_total", "value": 1.5}},
+        BOB: {"kills": int_total(5)},
+    }), None).await;
+    assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    // Nothing in the bundle is applied.
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 10.0}}));
+    assert_eq!(api.get(&format!("/player/{}", BOB)).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn queued_uploads() {
+    let mut config = test_config();
+    config.upload_queue.enabled = true;
+    let api = Api::with_config(config);
+    let res = api.upload("bedwars", json!({ALICE: {"kills": int_total(10)}}), None).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let applied = api.wait_for_upload(&res.body["id"]).await;
+    assert_eq!(applied["state"], "applied");
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 10.0}}));
+
+    let res = api.upload("bedwars", json!({ALICE: {"kills": {"type": "float_total", "value": 1.5}}}), None).await;
+    let rejected = api.wait_for_upload(&res.body["id"]).await;
+    assert_eq!(rejected["state"], "rejected");
+    assert!(rejected["error"].as_str().unwrap().contains("is stored as int_total"));
+
+    let path = format!("/stats/upload/{}/status", res.body["id"].as_str().unwrap());
+    assert_eq!(api.get_as(&path, ADMIN_TOKEN).await.status, StatusCode::FORBIDDEN);
+    let path = format!("/stats/upload/{}/status", BOB);
+    assert_eq!(api.get_as(&path, SERVER_TOKEN).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn uploads_with_invalid_names_list_every_violation() {
+    let mut config = test_config();
+    config.validation.max_players_per_bundle = 1;
+    let api = Api::with_config(config);
+
+    let res = api.upload("Bed-Wars", json!({
+        ALICE: {"Kills": int_total(1), "deaths": int_total(1)},
+        BOB: {"Kills": int_total(1)},
+    }), None).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let fields: Vec<_> = res.body["violations"].as_array().unwrap().iter()
+        .map(|violation| violation["field"].as_str().unwrap())
+        .collect();
+    assert_eq!(fields, ["namespace", "stats.players", "stats.Kills"]);
+
+    assert_eq!(api.get("/stats/namespaces").await.body, json!([]));
+}
+
+#[tokio::test]
+async fn reuploaded_bundles_are_ignored() {
+    let api = Api::new();
+    let mut bundle = bundle("bedwars", json!({ALICE: {"kills": int_total(1)}}));
+    bundle["bundle_id"] = json!("5b0e8a52-2f9c-4d1e-8c3a-6f7b9d0e1a2b");
+    assert_eq!(api.post("/stats/upload", SERVER_TOKEN, bundle.clone()).await.status, StatusCode::NO_CONTENT);
+    let res = warp::test::request().method("POST").path("/stats/upload")
+        .header("authorization", SERVER_TOKEN)
+        .json(&bundle)
+        .reply(&api.routes).await;
+    assert_eq!(res.status(), StatusCode::NO_CONTENT);
+    assert_eq!(res.headers().get("x-duplicate-bundle"), Some(&HeaderValue::from_static("true")));
+
+    let res = api.player_stats(ALICE, "bedwars").await;
+    assert_eq!(res.body, json!({"bedwars": {"kills": 1.0}}));
+    assert_eq!(api.get_as("/admin/uploads", ADMIN_TOKEN).await.body.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn suspicious_uploads_are_quarantined() {
+    let api = Api::with_config(config_with_max_increment("wins", 100.0));
+    assert_eq!(api.upload("bedwars", json!({ALICE: {"wins": int_total(100)}}), None).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.upload("bedwars", json!({ALICE: {"wins": int_total(150)}}), None).await.status, StatusCode::ACCEPTED);
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"wins": 100.0}}));
+
+    assert_eq!(api.get_as("/admin/suspicious-uploads", SERVER_TOKEN).await.status, StatusCode::FORBIDDEN);
+    let res = api.get_as("/admin/suspicious-uploads", ADMIN_TOKEN).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body[0]["token"], "server");
+    assert_eq!(res.body[0]["violations"], json!([
+        {"field": format!("stats.players.{}.wins", ALICE), "message": "adds 150, more than the limit of 100"},
+    ]));
+    let id = res.body[0]["id"].as_str().unwrap().to_string();
+
+    let res = api.post(&format!("/admin/suspicious-uploads/{}/release", id), ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"wins": 250.0}}));
+    assert_eq!(api.get_as("/admin/suspicious-uploads", ADMIN_TOKEN).await.body, json!([]));
+    let res = api.post(&format!("/admin/suspicious-uploads/{}/release", id), ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+
+    // An upload that can't be released is kept in quarantine.
+    api.upload("bedwars", json!({BOB: {"wins": int_total(1000)}}), None).await;
+    api.upload("bedwars", json!({BOB: {"wins": {"type": "float_total", "value": 1.0}}}), None).await;
+    let id = api.get_as("/admin/suspicious-uploads", ADMIN_TOKEN).await.body[0]["id"].clone();
+    let path = format!("/admin/suspicious-uploads/{}", id.as_str().unwrap());
+    let res = api.post(&format!("{}/release", path), ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(api.get_as("/admin/suspicious-uploads", ADMIN_TOKEN).await.body[0]["id"], id);
+    assert_eq!(api.request("DELETE", &path, Some(ADMIN_TOKEN), None).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.request("DELETE", &path, Some(ADMIN_TOKEN), None).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.player_stats(BOB, "bedwars").await.body, json!({"bedwars": {"wins": 1.0}}));
+}
+
+#[tokio::test]
+async fn tokens_can_be_limited_to_namespaces() {
+    let mut config = test_config();
+    config.tokens.push(ApiToken {
+        name: "bedwars".to_string(),
+        token: "bedwars-token".to_string(),
+        scopes: vec![TokenScope::UploadStats, TokenScope::UpdateStatMetadata],
+        namespaces: vec!["bedwars".to_string()],
+    });
+    let api = Api::with_config(config);
+    let bundle = |namespace: &str| json!({"server_name": "bedwars", "namespace": namespace, "stats": {"players": {ALICE: {"kills": int_total(1)}}}});
+
+    assert_eq!(api.post("/stats/upload", "bedwars-token", bundle("bedwars")).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.post("/stats/upload", "bedwars-token", bundle("skywars")).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(api.post("/stats/upload", SERVER_TOKEN, bundle("skywars")).await.status, StatusCode::NO_CONTENT);
+    let metadata = json!({"kills": {"display_name": "Kills"}});
+    assert_eq!(api.put("/stats/skywars/metadata", "bedwars-token", metadata).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(api.player_stats(ALICE, "skywars").await.body, json!({"skywars": {"kills": 1.0}}));
+}
+
+#[tokio::test]
+async fn dry_run_uploads_write_nothing() {
+    let api = Api::with_config(config_with_max_increment("wins", 100.0));
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}}), None).await;
+
+    let res = api.post("/stats/upload?dry_run=true", SERVER_TOKEN, bundle("bedwars", json!({ALICE: {"kills": int_total(2)}}))).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"outcome": "applied"}));
+    let res = api.post("/stats/upload?dry_run=true", SERVER_TOKEN, bundle("bedwars", json!({BOB: {"wins": int_total(500)}}))).await;
+    assert_eq!(res.body["outcome"], "quarantined");
+    assert_eq!(res.body["violations"][0]["field"], format!("stats.players.{}.wins", BOB));
+    let res = api.post("/stats/upload?dry_run=true", SERVER_TOKEN, bundle("bedwars", json!({ALICE: {"kills": {"type": "float_total", "value": 1.5}}}))).await;
+    assert_eq!(res.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 1.0}}));
+    assert_eq!(api.player_stats(BOB, "bedwars").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.get_as("/admin/suspicious-uploads", ADMIN_TOKEN).await.body, json!([]));
+}
+
+#[tokio::test]
+async fn stats_are_attributed_to_servers() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}, BOB: {"kills": int_total(2)}}), Some(json!({"games": int_total(1)}))).await;
+    api.upload("bedwars", json!({ALICE: {"deaths": int_total(1)}}), None).await;
+    api.upload("internal", json!({ALICE: {"kills": int_total(1)}}), None).await;
+
+    let res = api.get("/stats/servers/play/summary").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["namespaces"].as_object().unwrap().len(), 1);
+    let bedwars = &res.body["namespaces"]["bedwars"];
+    assert_eq!(bedwars["bundles"], 2);
+    assert_eq!(bedwars["player_uploads"], 3);
+    assert_eq!(bedwars["stats"], json!({"kills": 2, "deaths": 1, "games": 1}));
+
+    let res = api.get_as("/stats/servers/play/summary", ADMIN_TOKEN).await;
+    assert_eq!(res.body["namespaces"]["internal"]["bundles"], 1);
+    assert_eq!(api.get("/stats/servers/lobby/summary").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn accepted_uploads_are_logged() {
+    let mut config = test_config();
+    config.upload_log.max_entries = 2;
+    let api = Api::with_config(config);
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}}), None).await;
+    api.upload("spleef", json!({ALICE: {"wins": int_total(1)}, BOB: {"wins": int_total(1)}}), Some(json!({"games": int_total(1)}))).await;
+    api.upload("spleef", json!({ALICE: {"wins": int_total(1)}}), None).await;
+    // Rejected uploads aren't logged.
+    api.upload("Spleef", json!({}), None).await;
+
+    let res = api.get_as("/admin/uploads", ADMIN_TOKEN).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let entries = res.body.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[1]["token"], "server");
+    assert_eq!(entries[1]["server_name"], "play");
+    assert_eq!(entries[1]["players"], 2);
+    assert_eq!(entries[1]["stats"], 3);
+    assert_eq!(entries[1]["spooled"], false);
+
+    assert_eq!(api.get_as("/admin/uploads?namespace=bedwars", ADMIN_TOKEN).await.body, json!([]));
+    assert_eq!(api.get_as("/admin/uploads", SERVER_TOKEN).await.status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn signed_uploads() {
+    let api = Api::new();
+    let body = serde_json::to_vec(&bundle("bedwars", json!({ALICE: {"kills": int_total(3)}}))).unwrap();
+    let now = chrono::Utc::now().timestamp();
+
+    let status = api.signed_upload(&body, now, "first", &signature::sign(SERVER_TOKEN, now, "first", &body)).await;
+    assert_eq!(status, StatusCode::NO_CONTENT);
+    // The same request can't be replayed, and other tokens or times don't match the signature.
+    let status = api.signed_upload(&body, now, "first", &signature::sign(SERVER_TOKEN, now, "first", &body)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let status = api.signed_upload(&body, now, "second", &signature::sign(ADMIN_TOKEN, now, "second", &body)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let stale = now - 3600;
+    let status = api.signed_upload(&body, stale, "third", &signature::sign(SERVER_TOKEN, stale, "third", &body)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 3.0}}));
+}
+
+#[cfg(feature = "grpc")]
+#[tokio::test]
+async fn grpc_uploads() {
+    use nucleoid_persistence::grpc::{proto, Persistence};
+    use proto::stat::Value as StatValue;
+
+    fn request<T>(message: T, token: &str) -> tonic::Request<T> {
+        let mut request = tonic::Request::new(message);
+        request.metadata_mut().insert("authorization", token.parse().unwrap());
+        request
+    }
+
+    let api = Api::new();
+    let stat = |value| proto::Stat { value: Some(value) };
+    let bundle = proto::StatsBundle {
+        server_name: "play".to_string(),
+        namespace: "bedwars".to_string(),
+        bundle_id: "5f8d0d55-1b2c-4b5e-9f5a-3c2b1a0d9e8f".to_string(),
+        global: HashMap::from([("games".to_string(), stat(StatValue::IntTotal(1)))]),
+        players: HashMap::from([(ALICE.to_string(), proto::PlayerStats {
+            stats: HashMap::from([
+                ("kills".to_string(), stat(StatValue::IntTotal(3))),
+                ("accuracy".to_string(), stat(StatValue::FloatRollingAverageBatch(proto::FloatBatch { total: 1.5, count: 3 }))),
+            ]),
+        })]),
+    };
+
+    let res = api.grpc.upload_stats_bundle(request(bundle.clone(), SERVER_TOKEN)).await.unwrap().into_inner();
+    assert_eq!(res.outcome(), proto::UploadOutcome::Applied);
+    let res = api.grpc.upload_stats_bundle(request(bundle.clone(), SERVER_TOKEN)).await.unwrap().into_inner();
+    assert_eq!(res.outcome(), proto::UploadOutcome::Duplicate);
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 3.0, "accuracy": 0.5}}));
+
+    let status = api.grpc.upload_stats_bundle(tonic::Request::new(bundle.clone())).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unauthenticated);
+    let status = api.grpc.upload_stats_bundle(request(bundle.clone(), ADMIN_TOKEN)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::PermissionDenied);
+    let invalid = proto::StatsBundle { namespace: "Bed-Wars".to_string(), bundle_id: String::new(), ..bundle };
+    let status = api.grpc.upload_stats_bundle(request(invalid, SERVER_TOKEN)).await.unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+
+    let profile = proto::UpdatePlayerProfileRequest { uuid: ALICE.to_string(), username: "alice".to_string(), private: None };
+    api.grpc.update_player_profile(request(profile, SERVER_TOKEN)).await.unwrap();
+    assert_eq!(api.get(&format!("/player/{}", ALICE)).await.body["username"], "alice");
+}
+
+#[tokio::test]
+async fn leaderboards() {
+    let api = Api::new();
+    api.set_username(ALICE, "Alice").await;
+    api.upload("bedwars", json!({
+        ALICE: {"kills": int_total(5)},
+        BOB: {"kills": int_total(8)},
+    }), None).await;
+
+    let res = api.get("/leaderboard/bedwars/kills").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!([
+        {"rank": 1, "uuid": BOB, "username": null, "value": 8.0},
+        {"rank": 2, "uuid": ALICE, "username": "Alice", "value": 5.0},
+    ]));
+
+    let res = api.get("/leaderboard/bedwars/kills?order=asc&limit=1").await;
+    assert_eq!(res.body, json!([{"rank": 1, "uuid": ALICE, "username": "Alice", "value": 5.0}]));
+    assert_eq!(api.get("/leaderboard/bedwars/deaths").await.body, json!([]));
+}
+
+#[tokio::test]
+async fn stat_ranks() {
+    let api = Api::new();
+    api.upload("bedwars", json!({
+        ALICE: {"kills": int_total(5)},
+        BOB: {"kills": int_total(8)},
+    }), None).await;
+
+    let res = api.get(&format!("/player/{}/stats/bedwars/kills/rank", ALICE)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"value": 5.0, "rank": 2, "players": 2, "percentile": 0.0}));
+    let res = api.get(&format!("/player/{}/stats/bedwars/kills/rank?order=asc", ALICE)).await;
+    assert_eq!(res.body, json!({"value": 5.0, "rank": 1, "players": 2, "percentile": 50.0}));
+    assert_eq!(api.get(&format!("/player/{}/stats/bedwars/deaths/rank", ALICE)).await.status, StatusCode::NOT_FOUND);
+
+    // Private players aren't counted, or ranked, without a token that can see them.
+    api.put(&format!("/player/{}", BOB), SERVER_TOKEN, json!({"username": "Bob", "private": true})).await;
+    assert_eq!(api.get(&format!("/player/{}/stats/bedwars/kills/rank", ALICE)).await.body["rank"], 1);
+    assert_eq!(api.get(&format!("/player/{}/stats/bedwars/kills/rank", BOB)).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.get_as(&format!("/player/{}/stats/bedwars/kills/rank", ALICE), ADMIN_TOKEN).await.body["rank"], 2);
+}
+
+#[tokio::test]
+async fn stat_summaries() {
+    let api = Api::new();
+    assert_eq!(api.get("/stats/bedwars/summary?stat=kills").await.status, StatusCode::NOT_FOUND);
+    api.upload("bedwars", json!({
+        ALICE: {"kills": int_total(4)},
+        BOB: {"kills": int_total(8)},
+    }), None).await;
+
+    let res = api.get("/stats/bedwars/summary?stat=kills").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"players": 2, "sum": 12.0, "mean": 6.0, "median": 6.0, "stddev": 2.0}));
+    assert_eq!(api.get("/stats/bedwars/summary?stat=$kills").await.status, StatusCode::BAD_REQUEST);
+
+    // Summaries are kept until they expire, rather than dropped by each upload.
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(4)}}), None).await;
+    assert_eq!(api.get("/stats/bedwars/summary?stat=kills").await.body["sum"], 12.0);
+
+    // Admin operations drop them, though.
+    assert_eq!(api.request("DELETE", "/admin/stats/bedwars/kills", Some(ADMIN_TOKEN), None).await.status, StatusCode::OK);
+    assert_eq!(api.get("/stats/bedwars/summary?stat=kills").await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn namespaces() {
+    let api = Api::new();
+    api.upload("spleef", json!({ALICE: {"wins": int_total(1)}}), None).await;
+    api.upload("bedwars", json!({}), Some(json!({"games": int_total(1)}))).await;
+    api.upload("internal", json!({ALICE: {"wins": int_total(1)}}), None).await;
+
+    assert_eq!(api.get("/stats/namespaces").await.body, json!(["bedwars", "spleef"]));
+    assert_eq!(api.get_as("/stats/namespaces", ADMIN_TOKEN).await.body, json!(["bedwars", "internal", "spleef"]));
+}
+
+#[tokio::test]
+async fn global_stats() {
+    let api = Api::new();
+    assert_eq!(api.get("/stats/global/bedwars").await.status, StatusCode::NOT_FOUND);
+
+    for _ in 0..2 {
+        api.upload("bedwars", json!({}), Some(json!({
+            "games": int_total(1),
+            "length": {"type": "float_rolling_average", "value": 30.0},
+        }))).await;
+    }
+    let res = api.get("/stats/global/bedwars").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"games": 2.0, "length": 30.0}));
+
+    let res = api.get("/stats/global/bedwars/delta?window=1h").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"games": 2.0, "length": 30.0}));
+}
+
+#[tokio::test]
+async fn stats_in_several_namespaces() {
+    let api = Api::new();
+    for namespace in ["bedwars", "spleef", "skywars", "internal"] {
+        api.upload(namespace, json!({ALICE: {"wins": int_total(1)}}), None).await;
+    }
+
+    let res = api.get(&format!("/player/{}/stats?namespaces=bedwars,%20spleef,parkour", ALICE)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"bedwars": {"wins": 1.0}, "spleef": {"wins": 1.0}}));
+
+    let path = format!("/player/{}/stats?namespaces=bedwars,internal", ALICE);
+    assert_eq!(api.get(&path).await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.get_as(&path, ADMIN_TOKEN).await.body.as_object().unwrap().len(), 2);
+    assert_eq!(api.get(&format!("/player/{}/stats?namespaces=,", ALICE)).await.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn internal_namespaces_are_hidden() {
+    let api = Api::new();
+    api.upload("internal", json!({ALICE: {"wins": int_total(1)}}), Some(json!({"games": int_total(1)}))).await;
+
+    assert_eq!(api.get("/stats/global/internal").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.get_as("/stats/global/internal", ADMIN_TOKEN).await.status, StatusCode::OK);
+    assert_eq!(api.get(&format!("/player/{}/stats", ALICE)).await.body, json!({}));
+}
+
+#[tokio::test]
+async fn histograms() {
+    let api = Api::new();
+    let damage = json!({"type": "histogram", "value": [5.0, 15.0, 25.0, 30.0]});
+    api.upload("bedwars", json!({ALICE: {"damage": damage}}), Some(json!({"damage": damage}))).await;
+
+    let buckets = json!([
+        {"min": null, "max": 10.0, "count": 1},
+        {"min": 10.0, "max": 20.0, "count": 1},
+        {"min": 20.0, "max": null, "count": 2},
+    ]);
+    let res = api.get(&format!("/player/{}/stats/bedwars/histogram?stat=damage", ALICE)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, buckets);
+    assert_eq!(api.get("/stats/global/bedwars/histogram?stat=damage").await.body, buckets);
+
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}}), None).await;
+    let res = api.get(&format!("/player/{}/stats/bedwars/histogram?stat=kills", ALICE)).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn stat_history() {
+    let api = Api::new();
+    for value in [3, 4] {
+        api.upload("bedwars", json!({ALICE: {"kills": int_total(value), "deaths": int_total(1)}}), None).await;
+    }
+
+    let res = api.get(&format!("/player/{}/stats/bedwars/history?stat=kills", ALICE)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let values: Vec<&Value> = res.body.as_array().unwrap().iter().map(|point| &point["value"]).collect();
+    assert_eq!(values, [3.0, 7.0]);
+
+    let res = api.get(&format!("/player/{}/stats/bedwars/history?stat=kills&limit=1", ALICE)).await;
+    assert_eq!(res.body.as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn deleting_a_player() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(3)}, BOB: {"kills": int_total(1)}}), None).await;
+    api.upload("seasonal", json!({ALICE: {"wins": int_total(1)}}), None).await;
+
+    let path = format!("/player/{}", ALICE);
+    assert_eq!(api.request("DELETE", &path, Some(SERVER_TOKEN), None).await.status, StatusCode::FORBIDDEN);
+    let res = api.request("DELETE", &path, Some(ADMIN_TOKEN), None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({
+        "profile": true,
+        "namespaces": ["bedwars", "seasonal"],
+        "stats_documents": 3,
+        "history_entries": 2,
+        "corrupt_documents": 0,
+    }));
+
+    assert_eq!(api.get(&path).await.status, StatusCode::NOT_FOUND);
+    let res = api.get(&format!("/player/{}/stats/bedwars/history?stat=kills", ALICE)).await;
+    assert_eq!(res.body, json!([]));
+    let res = api.get("/leaderboard/bedwars/kills").await;
+    let players: Vec<&Value> = res.body.as_array().unwrap().iter().map(|entry| &entry["uuid"]).collect();
+    assert_eq!(players, [BOB]);
+
+    let res = api.request("DELETE", &path, Some(ADMIN_TOKEN), None).await;
+    assert_eq!(res.body["profile"], false);
+}
+
+#[tokio::test]
+async fn players_are_listed_in_pages() {
+    let api = Api::new();
+    api.set_username(BOB, "Bob").await;
+    tokio::time::sleep(Duration::from_millis(5)).await;
+    api.set_username(ALICE, "Alice").await;
+    assert_eq!(api.get_as("/players", SERVER_TOKEN).await.status, StatusCode::FORBIDDEN);
+
+    let res = api.get_as("/players?limit=1", ADMIN_TOKEN).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["players"][0]["uuid"], ALICE);
+    let next = res.body["next"].as_str().unwrap().to_string();
+    let res = api.get_as(&format!("/players?limit=1&after={}", next), ADMIN_TOKEN).await;
+    assert_eq!(res.body["players"][0]["username"], "Bob");
+    assert_eq!(res.body.get("next"), None);
+
+    let res = api.get_as("/players?limit=1&sort=last_seen", ADMIN_TOKEN).await;
+    assert_eq!(res.body["players"][0]["uuid"], ALICE);
+    let next = res.body["next"].as_str().unwrap().to_string();
+    let res = api.get_as(&format!("/players?sort=last_seen&after={}", next), ADMIN_TOKEN).await;
+    let uuids: Vec<&Value> = res.body["players"].as_array().unwrap().iter().map(|player| &player["uuid"]).collect();
+    assert_eq!(uuids, [BOB]);
+
+    assert_eq!(api.get_as("/players?sort=username", ADMIN_TOKEN).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(api.get_as("/players?after=nope", ADMIN_TOKEN).await.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn players_are_searched_by_username_prefix() {
+    let api = Api::new();
+    api.set_username(ALICE, "Notch_Fan").await;
+    api.set_username(BOB, "nothing").await;
+    api.put(&format!("/player/{}", BOB), SERVER_TOKEN, json!({"username": "nothing", "private": true})).await;
+
+    let res = api.get("/players/search?q=not").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!([{"uuid": ALICE, "username": "Notch_Fan", "first_seen": res.body[0]["first_seen"], "last_seen": res.body[0]["last_seen"]}]));
+    let res = api.get_as("/players/search?q=NOT", ADMIN_TOKEN).await;
+    let usernames: Vec<&Value> = res.body.as_array().unwrap().iter().map(|player| &player["username"]).collect();
+    assert_eq!(usernames, ["Notch_Fan", "nothing"]);
+    assert_eq!(api.get("/players/search?q=notch_").await.body.as_array().unwrap().len(), 1);
+    assert_eq!(api.get("/players/search?q=notch%25").await.body, json!([]));
+    assert_eq!(api.get("/players/search?q=").await.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn anonymizing_a_player() {
+    let api = Api::new();
+    api.set_username(ALICE, "Alice").await;
+    api.set_username(ALICE, "Alicia").await;
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(3)}}), None).await;
+
+    let path = format!("/player/{}/anonymize", ALICE);
+    assert_eq!(api.post(&path, SERVER_TOKEN, json!({})).await.status, StatusCode::FORBIDDEN);
+    let res = api.post(&path, ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({
+        "profile": true,
+        "namespaces": ["bedwars"],
+        "previous_usernames": 1,
+        "leaderboard_snapshots": 0,
+    }));
+
+    // The name isn't taken back when the player's game server reports it again.
+    api.set_username(ALICE, "Alicia").await;
+    let res = api.get(&format!("/player/{}", ALICE)).await;
+    assert_eq!(res.body["username"], Value::Null);
+    assert_eq!(res.body["anonymized"], true);
+    assert_eq!(api.get("/player/by-name/alicia").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(api.get_as(&format!("/player/{}/names", ALICE), ADMIN_TOKEN).await.body, json!([]));
+
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 3.0}}));
+    let res = api.get("/leaderboard/bedwars/kills").await;
+    assert_eq!(res.body[0]["uuid"], ALICE);
+    assert_eq!(res.body[0]["username"], Value::Null);
+
+    let res = api.post(&format!("/player/{}/anonymize", BOB), ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn seasons() {
+    let api = Api::new();
+    let res = api.get("/seasons/current").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"season": 1, "started_at": null}));
+
+    api.upload("seasonal", json!({ALICE: {"wins": int_total(2)}}), None).await;
+    assert_eq!(api.post("/admin/seasons/start", SERVER_TOKEN, json!({})).await.status, StatusCode::FORBIDDEN);
+    let res = api.post("/admin/seasons/start", ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["season"], 2);
+    assert_eq!(api.get("/seasons/current").await.body["season"], 2);
+
+    api.upload("seasonal", json!({ALICE: {"wins": int_total(3)}}), None).await;
+    let stats = |season: &str| format!("/player/{}/stats/seasonal?season={}", ALICE, season);
+    assert_eq!(api.get(&stats("all-time")).await.body, json!({"seasonal": {"wins": 5.0}}));
+    assert_eq!(api.get(&stats("1")).await.body, json!({"seasonal": {"wins": 2.0}}));
+    assert_eq!(api.get(&stats("current")).await.body, json!({"seasonal": {"wins": 3.0}}));
+    assert_eq!(api.get("/leaderboard/seasonal/wins?season=1").await.body[0]["value"], 2.0);
+}
+
+#[tokio::test]
+async fn stat_metadata() {
+    let api = Api::new();
+    assert_eq!(api.get("/stats/bedwars/metadata").await.body, json!({}));
+
+    let kills = json!({"display_name": "Kills", "description": "Players killed"});
+    let res = api.put("/stats/bedwars/metadata", SERVER_TOKEN, json!({"kills": kills})).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.put("/stats/bedwars/metadata", ADMIN_TOKEN, json!({})).await.status, StatusCode::FORBIDDEN);
+
+    let res = api.get("/stats/bedwars/metadata").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"kills": kills}));
+}
+
+#[tokio::test]
+async fn achievements() {
+    let api = Api::new();
+    let first_win = json!({"display_name": "First win"});
+    let res = api.put("/achievements/bedwars", SERVER_TOKEN, json!({"first_win": first_win, "ten_kills": {}})).await;
+    assert_eq!(res.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.put("/achievements/bedwars", ADMIN_TOKEN, json!({})).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(api.put("/achievements/bedwars", SERVER_TOKEN, json!({"First Win": {}})).await.status, StatusCode::BAD_REQUEST);
+
+    let path = format!("/player/{}/achievements", ALICE);
+    let res = api.post(&path, SERVER_TOKEN, json!({"namespace": "bedwars", "achievements": ["first_win", "first_win"]})).await;
+    assert_eq!(res.body, json!({"granted": ["first_win"]}));
+    let res = api.post(&path, SERVER_TOKEN, json!({"namespace": "bedwars", "achievements": ["first_win"]})).await;
+    assert_eq!(res.body, json!({"granted": []}));
+    let res = api.post(&path, SERVER_TOKEN, json!({"namespace": "bedwars", "achievements": ["unknown"]})).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    api.upload("bedwars", json!({BOB: {"kills": int_total(1)}}), None).await;
+
+    let res = api.get(&path).await;
+    assert_eq!(res.body[0]["achievement"], "first_win");
+    assert!(res.body[0]["unlocked_at"].is_string());
+    assert_eq!(api.get(&format!("/player/{}/achievements?namespace=spleef", ALICE)).await.body, json!([]));
+
+    let res = api.get("/achievements/bedwars").await;
+    assert_eq!(res.body["players"], 2);
+    assert_eq!(res.body["achievements"]["first_win"]["display_name"], "First win");
+    assert_eq!(res.body["achievements"]["first_win"]["unlocked_by"], 1);
+    assert_eq!(res.body["achievements"]["first_win"]["unlock_percent"], 50.0);
+    assert_eq!(res.body["achievements"]["ten_kills"]["unlocked_by"], 0);
+}
+
+#[tokio::test]
+async fn ratings() {
+    let api = Api::new();
+    let res = api.post("/ratings/bedwars/match", SERVER_TOKEN, json!({"winner": ALICE, "loser": BOB})).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["ratings"][ALICE], json!({"rating": 1016.0, "change": 16.0}));
+    assert_eq!(res.body["ratings"][BOB], json!({"rating": 984.0, "change": -16.0}));
+
+    let teams = json!({"teams": [{"players": [ALICE], "placement": 1}, {"players": [ALICE], "placement": 2}]});
+    assert_eq!(api.post("/ratings/bedwars/match", SERVER_TOKEN, teams).await.status, StatusCode::BAD_REQUEST);
+    let both = json!({"winner": ALICE, "loser": BOB, "teams": [{"players": [ALICE], "placement": 1}]});
+    assert_eq!(api.post("/ratings/bedwars/match", SERVER_TOKEN, both).await.status, StatusCode::BAD_REQUEST);
+    assert_eq!(api.post("/ratings/bedwars/match", ADMIN_TOKEN, json!({"winner": ALICE, "loser": BOB})).await.status, StatusCode::FORBIDDEN);
+
+    let res = api.get("/ratings/bedwars/leaderboard").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body[0]["uuid"], ALICE);
+    assert_eq!(res.body[1]["value"], 984.0);
+
+    let res = api.get(&format!("/player/{}", ALICE)).await;
+    assert_eq!(res.body["ratings"]["bedwars"], json!({"rating": 1016.0, "matches": 1}));
+}
+
+#[tokio::test]
+async fn games() {
+    let api = Api::new();
+    for (namespace, ended_at) in [("bedwars", "2024-01-01T00:10:00Z"), ("spleef", "2024-01-01T00:20:00Z")] {
+        let res = api.post("/games/upload", SERVER_TOKEN, json!({
+            "server_name": "play",
+            "namespace": namespace,
+            "started_at": "2024-01-01T00:00:00Z",
+            "ended_at": ended_at,
+            "participants": [{"uuid": ALICE, "winner": true, "score": 10.0}, {"uuid": BOB}],
+        })).await;
+        assert_eq!(res.status, StatusCode::CREATED);
+        assert!(res.body["id"].is_string());
+    }
+
+    let res = api.get("/games/recent").await;
+    assert_eq!(res.status, StatusCode::OK);
+    let namespaces: Vec<&Value> = res.body.as_array().unwrap().iter().map(|game| &game["namespace"]).collect();
+    assert_eq!(namespaces, ["spleef", "bedwars"]);
+
+    let res = api.get("/games/recent?namespace=bedwars").await;
+    assert_eq!(res.body.as_array().unwrap().len(), 1);
+    assert_eq!(res.body[0]["participants"].as_array().unwrap().len(), 2);
+
+    let res = api.get(&format!("/player/{}/games?limit=1", BOB)).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body[0]["namespace"], "spleef");
+}
+
+#[tokio::test]
+async fn graphql() {
+    let api = Api::new();
+    api.set_username(ALICE, "Alice").await;
+    api.set_username(BOB, "Bob").await;
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(3)}, BOB: {"kills": int_total(1)}}), Some(json!({"games": int_total(1)}))).await;
+    api.upload("internal", json!({ALICE: {"secret": int_total(1)}}), None).await;
+    api.post("/games/upload", SERVER_TOKEN, json!({
+        "server_name": "play",
+        "namespace": "bedwars",
+        "started_at": "2024-01-01T00:00:00Z",
+        "ended_at": "2024-01-01T00:10:00Z",
+        "participants": [{"uuid": ALICE, "winner": true}, {"uuid": BOB}],
+    })).await;
+    api.put(&format!("/player/{}", BOB), SERVER_TOKEN, json!({"username": "Bob", "private": true})).await;
+
+    let query = |query: &str| json!({"query": query});
+    let res = api.request("POST", "/graphql", None, Some(query(r#"{
+        playerByName(username: "alice") { uuid username stats { namespace stats { name value } } games { namespace } }
+        globalStats(namespace: "bedwars") { stats { name value } }
+    }"#))).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["data"], json!({
+        "playerByName": {
+            "uuid": ALICE,
+            "username": "Alice",
+            "stats": [{"namespace": "bedwars", "stats": [{"name": "kills", "value": 3.0}]}],
+            "games": [{"namespace": "bedwars"}],
+        },
+        "globalStats": {"stats": [{"name": "games", "value": 1.0}]},
+    }));
+
+    // Private players are hidden from the public view, as in the REST API.
+    let private = query(&format!(r#"{{ player(uuid: "{}") {{ username stats {{ namespace }} }} playerByName(username: "bob") {{ uuid }} }}"#, BOB));
+    let res = api.request("POST", "/graphql", None, Some(private.clone())).await;
+    assert_eq!(res.body["data"], json!({"player": {"username": null, "stats": null}, "playerByName": null}));
+    let res = api.request("POST", "/graphql", Some(ADMIN_TOKEN), Some(private)).await;
+    assert_eq!(res.body["data"]["player"]["username"], "Bob");
+    assert_eq!(res.body["data"]["playerByName"]["uuid"], BOB);
+
+    let res = api.request("POST", "/graphql", None, Some(query("{ games(limit: 1000) { id } }"))).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.body["errors"][0]["message"].is_string());
+}
+
+#[tokio::test]
+async fn health_checks() {
+    let api = Api::new();
+    assert_eq!(api.get("/healthz").await.status, StatusCode::OK);
+    assert_eq!(api.get("/readyz").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn metrics() {
+    let mut config = test_config();
+    config.result_cache.ttl_secs = Some(60);
+    let api = Api::with_config(config);
+    api.get("/leaderboard/bedwars/kills").await;
+    let res = api.get("/metrics").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.body.as_str().unwrap().contains("nucleoid_result_cache_requests_total"));
+}
+
+#[tokio::test]
+async fn cors() {
+    let mut config = test_config();
+    config.cors.allowed_origins = vec!["https://nucleoid.xyz".to_string()];
+    let api = Api::with_config(config);
+    let preflight = |origin: &str, method: &str, headers: &str| warp::test::request()
+        .method("OPTIONS")
+        .path("/stats/namespaces")
+        .header("origin", origin)
+        .header("access-control-request-method", method)
+        .header("access-control-request-headers", headers)
+        .reply(&api.routes);
+
+    let res = preflight("https://nucleoid.xyz", "GET", "content-type").await;
+    assert_eq!(res.status(), StatusCode::OK);
+    assert_eq!(res.headers()["access-control-allow-origin"], "https://nucleoid.xyz");
+    assert_eq!(preflight("https://example.com", "GET", "content-type").await.status(), StatusCode::FORBIDDEN);
+    // Tokens can't be sent from other origins by default, which keeps the admin API out of reach of pages.
+    assert_eq!(preflight("https://nucleoid.xyz", "POST", "content-type").await.status(), StatusCode::FORBIDDEN);
+    assert_eq!(preflight("https://nucleoid.xyz", "GET", "authorization").await.status(), StatusCode::FORBIDDEN);
+
+    let mut config = test_config();
+    config.cors.allowed_origins = vec!["nucleoid.xyz".to_string()];
+    let database = StoreHandler::spawn(MemoryDatabaseHandler::new(&config), &config);
+    let jobs = Scheduler::new(&config, database.clone()).start();
+    assert!(web::routes(&config, database, Metrics::default(), jobs, UploadTracker::default()).is_err());
+}
+
+#[tokio::test]
+async fn openapi_document() {
+    let api = Api::new();
+    let res = api.get("/openapi.json").await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert!(res.body["paths"]["/stats/upload"].is_object());
+}
+
+#[tokio::test]
+async fn live_feed() {
+    let api = Api::new();
+    let mut client = warp::test::ws().path("/stats/live").handshake(api.routes.clone()).await.unwrap();
+
+    // The feed is subscribed to once the upgrade completes, so uploads are repeated until one is seen.
+    let message = loop {
+        api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}}), None).await;
+        if let Ok(message) = tokio::time::timeout(Duration::from_millis(100), client.recv()).await {
+            break message.unwrap();
+        }
+    };
+    let event: Value = serde_json::from_str(message.to_str().unwrap()).unwrap();
+    assert_eq!(event, json!({"namespace": "bedwars", "server_name": "play", "players": 1}));
+}
+
+#[tokio::test]
+async fn convert_stat() {
+    let mut config = test_config();
+    config.namespace_aliases.insert("bw".to_string(), "bedwars".to_string());
+    let api = Api::with_config(config);
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(4)}, BOB: {"kills": int_total(6)}}), None).await;
+
+    let path = "/admin/stats/bw/convert";
+    assert_eq!(api.post(path, SERVER_TOKEN, json!({"stat": "kills", "to": "float_total"})).await.status, StatusCode::FORBIDDEN);
+    assert_eq!(api.post(path, ADMIN_TOKEN, json!({"stat": "$kills", "to": "float_total"})).await.status, StatusCode::BAD_REQUEST);
+
+    let res = api.post(path, ADMIN_TOKEN, json!({"stat": "kills", "to": "float_total", "dry_run": true})).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"dry_run": true, "matched": 2, "converted": 2, "failed": []}));
+    assert_eq!(api.upload("bedwars", json!({ALICE: {"kills": {"type": "float_total", "value": 0.5}}}), None).await.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let res = api.post(path, ADMIN_TOKEN, json!({"stat": "kills", "to": "float_total"})).await;
+    assert_eq!(res.body["converted"], 2);
+    assert_eq!(api.upload("bedwars", json!({ALICE: {"kills": {"type": "float_total", "value": 0.5}}}), None).await.status, StatusCode::NO_CONTENT);
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 4.5}}));
+}
+
+#[tokio::test]
+async fn convert_stat_with_season_stats() {
+    let api = Api::new();
+    api.upload("seasonal", json!({ALICE: {"wins": int_total(2)}}), None).await;
+
+    let res = api.post("/admin/stats/seasonal/convert", ADMIN_TOKEN, json!({"stat": "wins", "to": "float_total"})).await;
+    assert_eq!(res.body, json!({"dry_run": false, "matched": 2, "converted": 2, "failed": []}));
+    assert_eq!(api.upload("seasonal", json!({ALICE: {"wins": {"type": "float_total", "value": 0.5}}}), None).await.status, StatusCode::NO_CONTENT);
+    let stats = |season: &str| format!("/player/{}/stats/seasonal?season={}", ALICE, season);
+    assert_eq!(api.get(&stats("all-time")).await.body, json!({"seasonal": {"wins": 2.5}}));
+    assert_eq!(api.get(&stats("current")).await.body, json!({"seasonal": {"wins": 2.5}}));
+}
+
+#[tokio::test]
+async fn merge_namespace() {
+    let mut config = test_config();
+    config.namespace_aliases.insert("bw".to_string(), "bedwars".to_string());
+    config.result_cache.ttl_secs = Some(60);
+    let api = Api::with_config(config);
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(4)}}), Some(json!({"games": int_total(1)}))).await;
+    api.upload("bed_wars", json!({ALICE: {"kills": int_total(6)}, BOB: {"kills": int_total(1)}}), Some(json!({"games": int_total(2)}))).await;
+    assert_eq!(api.get("/stats/global/bedwars").await.body, json!({"games": 1.0}));
+
+    let res = api.post("/admin/namespaces/bw/merge", ADMIN_TOKEN, json!({"from": "bedwars"})).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = api.post("/admin/namespaces/bw/merge", ADMIN_TOKEN, json!({"from": "bed_wars"})).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"merged": 3, "failed": []}));
+
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 10.0}}));
+    assert_eq!(api.get("/stats/global/bedwars").await.body, json!({"games": 3.0}));
+    assert_eq!(api.get("/stats/namespaces").await.body, json!(["bedwars"]));
+}
+
+#[tokio::test]
+async fn merging_differently_aggregated_stats_fails() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(4)}}), None).await;
+    api.upload("bed_wars", json!({ALICE: {"kills": {"type": "int_rolling_average", "value": 2}}}), None).await;
+
+    let res = api.post("/admin/namespaces/bedwars/merge", ADMIN_TOKEN, json!({"from": "bed_wars"})).await;
+    assert_eq!(res.body["merged"], 0);
+    assert_eq!(res.body["failed"].as_array().unwrap().len(), 1);
+    assert_eq!(api.player_stats(ALICE, "bed_wars").await.body, json!({"bed_wars": {"kills": 2.0}}));
+}
+
+#[tokio::test]
+async fn merging_differently_stored_stats_fails() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": {"type": "float_total", "value": 0.5}}}), None).await;
+    api.upload("bed_wars", json!({ALICE: {"kills": int_total(2)}}), None).await;
+
+    let res = api.post("/admin/namespaces/bedwars/merge", ADMIN_TOKEN, json!({"from": "bed_wars"})).await;
+    assert_eq!(res.body["merged"], 0);
+    assert_eq!(res.body["failed"].as_array().unwrap().len(), 1);
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"kills": 0.5}}));
+}
+
+#[tokio::test]
+async fn stats_are_renamed() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(4)}, BOB: {"kills": int_total(2), "eliminations": int_total(1)}}), Some(json!({"kills": int_total(6)}))).await;
+
+    let res = api.post("/admin/stats/bedwars/rename", ADMIN_TOKEN, json!({"stats": {"kills": "eliminations", "beds": "eliminations"}})).await;
+    assert_eq!(res.status, StatusCode::BAD_REQUEST);
+    let res = api.post("/admin/stats/bedwars/rename", ADMIN_TOKEN, json!({"stats": {"kills": "eliminations"}})).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["renamed"], 2);
+    assert_eq!(res.body["failed"], json!([{"document": format!("bedwars/{}", BOB), "error": "stat 'eliminations' already exists"}]));
+
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"eliminations": 4.0}}));
+    assert_eq!(api.player_stats(BOB, "bedwars").await.body, json!({"bedwars": {"kills": 2.0, "eliminations": 1.0}}));
+    assert_eq!(api.get("/stats/global/bedwars").await.body, json!({"eliminations": 6.0}));
+}
+
+#[tokio::test]
+async fn stats_and_namespaces_are_deleted() {
+    let api = Api::new();
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(4), "beds": int_total(1)}, BOB: {"beds": int_total(2)}}), Some(json!({"kills": int_total(4)}))).await;
+    api.upload("old_game", json!({ALICE: {"wins": int_total(1)}}), Some(json!({"games": int_total(1)}))).await;
+
+    let res = api.request("DELETE", "/admin/stats/bedwars/kills", Some(SERVER_TOKEN), None).await;
+    assert_eq!(res.status, StatusCode::FORBIDDEN);
+    let res = api.request("DELETE", "/admin/stats/bedwars/kills", Some(ADMIN_TOKEN), None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!({"stats_documents": 1, "global": true, "history_entries": 1, "leaderboard_snapshots": 0}));
+    assert_eq!(api.player_stats(ALICE, "bedwars").await.body, json!({"bedwars": {"beds": 1.0}}));
+    assert_eq!(api.player_stats(BOB, "bedwars").await.body, json!({"bedwars": {"beds": 2.0}}));
+
+    let res = api.request("DELETE", "/admin/namespaces/old_game", Some(ADMIN_TOKEN), None).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body["stats_documents"], 1);
+    assert_eq!(res.body["global"], true);
+    assert_eq!(api.get("/stats/namespaces").await.body, json!(["bedwars"]));
+    assert_eq!(api.get(&format!("/player/{}/stats", ALICE)).await.body, json!({"bedwars": {"beds": 1.0}}));
+}
+
+#[tokio::test]
+async fn rebuilds_and_backups_are_unsupported_in_memory() {
+    let api = Api::new();
+    for (path, body) in [("/admin/aggregates/rebuild", json!({"mode": "verify"})), ("/admin/backup", json!({})), ("/admin/restore", json!({}))] {
+        assert_eq!(api.post(path, ADMIN_TOKEN, body.clone()).await.status, StatusCode::NOT_IMPLEMENTED);
+        assert_eq!(api.post(path, SERVER_TOKEN, body).await.status, StatusCode::FORBIDDEN);
+    }
+}
+
+#[tokio::test]
+async fn leaderboard_snapshots() {
+    let mut config = test_config();
+    config.leaderboard_snapshots.leaderboards = vec![SnapshotLeaderboard {
+        namespace: "bedwars".to_string(),
+        stat: "kills".to_string(),
+        order: LeaderboardOrder::Descending,
+    }];
+    let api = Api::with_config(config);
+    assert_eq!(api.get("/leaderboard/bedwars/kills/snapshot").await.status, StatusCode::NOT_FOUND);
+
+    api.upload("bedwars", json!({ALICE: {"kills": int_total(5)}, BOB: {"kills": int_total(3)}}), None).await;
+    let res = api.post("/admin/jobs/snapshot_leaderboards/run", ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let snapshot = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let res = api.get("/leaderboard/bedwars/kills/snapshot").await;
+            if res.status == StatusCode::OK {
+                return res.body;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+    assert_eq!(snapshot["order"], "desc");
+    assert_eq!(snapshot["entries"][0]["uuid"], ALICE);
+
+    // Later uploads change the live leaderboard but not the snapshot.
+    api.upload("bedwars", json!({BOB: {"kills": int_total(10)}}), None).await;
+    assert_eq!(api.get("/leaderboard/bedwars/kills").await.body[0]["uuid"], BOB);
+    assert_eq!(api.get("/leaderboard/bedwars/kills/snapshot").await.body["entries"][0]["uuid"], ALICE);
+    let res = api.get("/leaderboard/bedwars/kills/snapshot?at=2000-01-01T00:00:00Z").await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn jobs() {
+    let api = Api::new();
+    let res = api.get_as("/admin/jobs", ADMIN_TOKEN).await;
+    assert_eq!(res.status, StatusCode::OK);
+    let names: Vec<&Value> = res.body.as_array().unwrap().iter().map(|job| &job["name"]).collect();
+    assert_eq!(names, ["prune_bundle_ids", "prune_bundle_log", "prune_global_stats_rollups", "prune_stat_history", "replay_spool", "snapshot_leaderboards"]);
+    assert_eq!(api.get_as("/admin/jobs", SERVER_TOKEN).await.status, StatusCode::FORBIDDEN);
+
+    let res = api.post("/admin/jobs/prune_bundle_log/run", ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let run_id = res.body["run_id"].clone();
+    assert_eq!(api.post("/admin/jobs/missing/run", ADMIN_TOKEN, json!({})).await.status, StatusCode::NOT_FOUND);
+
+    // The run happens in the background, so it is waited for.
+    let run = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let res = api.get_as("/admin/jobs/prune_bundle_log/runs", ADMIN_TOKEN).await;
+            assert_eq!(res.status, StatusCode::OK);
+            if !res.body[0]["finished_at"].is_null() {
+                return res.body[0].clone();
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+    assert_eq!(run["id"], run_id);
+    assert_eq!(run["trigger"], "manual");
+    assert_eq!(run["outcome"], "succeeded");
+    assert_eq!(api.get_as("/admin/jobs/missing/runs", ADMIN_TOKEN).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn spooled_bundles_are_replayed_by_a_job() {
+    let dir = std::env::temp_dir().join(format!("nucleoid-spool-{}", std::process::id()));
+    let mut config = test_config();
+    config.spool_dir = Some(dir.clone());
+    let api = Api::with_config(config);
+
+    let bundle = serde_json::from_value(bundle("bedwars", json!({ALICE: {"kills": int_total(4)}}))).unwrap();
+    Spool::new(&dir).write(&bundle).await.unwrap();
+
+    let res = api.post("/admin/jobs/replay_spool/run", ADMIN_TOKEN, json!({})).await;
+    assert_eq!(res.status, StatusCode::ACCEPTED);
+    let stats = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let res = api.player_stats(ALICE, "bedwars").await;
+            if res.status == StatusCode::OK {
+                return res.body;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }).await.unwrap();
+    assert_eq!(stats, json!({"bedwars": {"kills": 4.0}}));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn corrupt_stats() {
+    let api = Api::new();
+    let res = api.get_as("/admin/corrupt-stats", ADMIN_TOKEN).await;
+    assert_eq!(res.status, StatusCode::OK);
+    assert_eq!(res.body, json!([]));
+    assert_eq!(api.get_as("/admin/corrupt-stats", SERVER_TOKEN).await.status, StatusCode::FORBIDDEN);
+
+    let path = "/admin/corrupt-stats/507f1f77bcf86cd799439011";
+    assert_eq!(api.get_as(path, ADMIN_TOKEN).await.status, StatusCode::NOT_FOUND);
+    let res = api.post(&format!("{}/restore", path), ADMIN_TOKEN, json!({"document": {"namespace": "bedwars"}})).await;
+    assert_eq!(res.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn read_only_mode_rejects_writes() {
+    let mut config = test_config();
+    config.read_only = true;
+    let api = Api::with_config(config);
+    let res = api.upload("bedwars", json!({ALICE: {"kills": int_total(1)}}), None).await;
+    assert_eq!(res.status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(api.get("/healthz").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn abandoned_uploads_say_whether_they_were_started() {
+    let uploads = UploadTracker::default();
+    let bundle: GameStatsBundle = serde_json::from_value(bundle("bedwars", json!({}))).unwrap();
+    let queued = uploads.begin(bundle.clone()).unwrap();
+    let _writing = uploads.begin_started(bundle).unwrap();
+    uploads.close();
+
+    let mut started: Vec<bool> = uploads.drain(tokio::time::Instant::now()).await.iter().map(|upload| upload.started).collect();
+    started.sort();
+    assert_eq!(started, [false, true]);
+    // Abandoned uploads are handed over to be spooled, so they can't be started anymore.
+    assert!(!queued.start());
+}
+
+#[test]
+fn request_ids_are_reused_from_clients() {
+    let header = HeaderValue::from_static("proxy-assigned-id");
+    assert_eq!(RequestId::from_header(Some(&header)).as_str(), "proxy-assigned-id");
+
+    let generated = RequestId::from_header(None);
+    assert_eq!(generated.as_str().len(), 16);
+    let too_long = HeaderValue::from_str(&"a".repeat(65)).unwrap();
+    assert_ne!(RequestId::from_header(Some(&too_long)).as_str(), too_long.to_str().unwrap());
+}